@@ -0,0 +1,155 @@
+//! `adb reverse` for testing on Android emulators and USB devices.
+//!
+//! `bridge start --adb-reverse` forwards the bridge's port on every
+//! connected device to the bridge on this machine, so the app reaches it at
+//! `ws://127.0.0.1:<port>` without any network setup. Devices that connect
+//! later are picked up every [`RECHECK_INTERVAL`]; the forwards are removed
+//! again when the [`AdbReverseGuard`] is dropped.
+
+use anyhow::{Context, Result};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::error::BridgeError;
+
+/// How often connected devices are listed again while the bridge runs.
+pub const RECHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A device as listed by `adb devices`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdbDevice {
+    pub serial: String,
+    /// `device` when usable; otherwise e.g. `unauthorized` or `offline`
+    pub state: String,
+}
+
+impl AdbDevice {
+    pub fn is_ready(&self) -> bool {
+        self.state == "device"
+    }
+}
+
+/// Parse the output of `adb devices`.
+pub fn parse_devices(output: &str) -> Vec<AdbDevice> {
+    output
+        .lines()
+        .filter(|line| !line.starts_with("List of devices") && !line.starts_with('*'))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some(AdbDevice { serial: fields.next()?.to_string(), state: fields.next()?.to_string() })
+        })
+        .collect()
+}
+
+/// The devices `adb` sees.
+pub fn devices() -> Result<Vec<AdbDevice>> {
+    let output = Command::new("adb").arg("devices").output().map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => BridgeError::dependency_missing("adb", e).into(),
+        _ => anyhow::Error::new(e).context("Failed to run 'adb devices'"),
+    })?;
+    if !output.status.success() {
+        anyhow::bail!("adb devices failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(parse_devices(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Forward `tcp:<port>` on the device `serial` to `tcp:<port>` here.
+fn reverse(serial: &str, port: u16) -> Result<()> {
+    let tcp = format!("tcp:{}", port);
+    let output = Command::new("adb")
+        .args(["-s", serial, "reverse", &tcp, &tcp])
+        .output()
+        .context("Failed to run 'adb reverse'")?;
+    if !output.status.success() {
+        anyhow::bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+/// Guard that removes the forwards it set up when dropped.
+#[derive(Debug)]
+pub struct AdbReverseGuard {
+    port: u16,
+    serials: Vec<String>,
+}
+
+impl AdbReverseGuard {
+    /// The devices forwarding to the bridge.
+    pub fn serials(&self) -> &[String] {
+        &self.serials
+    }
+
+    /// Forward the port on connected devices that don't forward it yet.
+    /// Returns the newly forwarded devices.
+    pub fn refresh(&mut self) -> Result<Vec<String>> {
+        let mut added = Vec::new();
+        for device in devices()? {
+            if self.serials.contains(&device.serial) {
+                continue;
+            }
+            if !device.is_ready() {
+                warn!("📱 Skipping Android device {} ({}) — allow USB debugging on it", device.serial, device.state);
+                continue;
+            }
+            match reverse(&device.serial, self.port) {
+                Ok(()) => {
+                    info!("📱 {} reaches the bridge at 127.0.0.1:{}", device.serial, self.port);
+                    self.serials.push(device.serial.clone());
+                    added.push(device.serial);
+                }
+                Err(e) => warn!("adb reverse failed on {}: {:#}", device.serial, e),
+            }
+        }
+        Ok(added)
+    }
+}
+
+impl Drop for AdbReverseGuard {
+    fn drop(&mut self) {
+        let tcp = format!("tcp:{}", self.port);
+        for serial in &self.serials {
+            let _ = Command::new("adb")
+                .args(["-s", serial, "reverse", "--remove", &tcp])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
+        }
+    }
+}
+
+/// Forward `port` on every connected device. Fails when `adb` is missing or
+/// no device could be set up.
+pub fn reverse_all(port: u16) -> Result<AdbReverseGuard> {
+    let mut guard = AdbReverseGuard { port, serials: Vec::new() };
+    guard.refresh()?;
+    if guard.serials.is_empty() {
+        anyhow::bail!(
+            "No Android device to forward to — start an emulator or connect a device with USB debugging on, then check `adb devices`"
+        );
+    }
+    Ok(guard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn devices_are_parsed_with_their_state() {
+        let output = "\
+* daemon not running; starting now at tcp:5037
+* daemon started successfully
+List of devices attached
+emulator-5554\tdevice
+R58M123ABC\tunauthorized
+
+";
+        let devices = parse_devices(output);
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0], AdbDevice { serial: "emulator-5554".into(), state: "device".into() });
+        assert!(devices[0].is_ready());
+        assert!(!devices[1].is_ready());
+        assert!(parse_devices("List of devices attached\n\n").is_empty());
+    }
+}
@@ -0,0 +1,523 @@
+//! `/admin/...`: a live snapshot of the running bridge and a few controls,
+//! served on the bridge's own listener for `bridge top` and the web admin UI.
+//!
+//! | Request                        | Answer                                                    |
+//! |--------------------------------|-----------------------------------------------------------|
+//! | `GET /admin/status`            | [`AdminStatus`] as JSON                                   |
+//! | `GET /admin/devices`           | the `[[devices]]` as [`DeviceSummary`]s, without tokens   |
+//! | `GET /admin/transports`        | the enabled transports as [`TransportSummary`]s           |
+//! | `GET /admin/logs`              | the last [`LOG_LINES`] lines of `bridge.log` at INFO+     |
+//! | `POST /admin/agents/<id>/kill` | kill the pooled agent with that id (`404` if none)        |
+//! | `POST /admin/pairing/rotate`   | replace the pairing code; the new [`PairingStatus`]       |
+//! | `GET /admin/pairing/qr`        | [`PairingQr`], renewing a used or expired code first      |
+//! | `POST /admin/token/rotate`     | replace the auth token and restart; `{"authToken": ...}`  |
+//!
+//! Only the admin token (`admin_token` in `common.toml`) is accepted. The auth
+//! token, device and user tokens and session tokens never are: they live on
+//! phones, and leaking one must not grant management access. Without an admin
+//! token the endpoints are disabled.
+//!
+//! With `admin_ui = true` in `common.toml`, `GET /admin/` also serves a
+//! single-page UI for the same endpoints from assets embedded in the binary.
+//! The page itself is public and holds no data; it asks for the admin token
+//! and keeps it in the tab's session storage.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+use subtle::ConstantTimeEq;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::agent_pool::{AgentPool, AgentSummary, PoolStats};
+use crate::auth_failures::AuthFailures;
+use crate::bridge::{create_http_response, request_path};
+use crate::common_config::{DeviceConfig, UserConfig};
+use crate::pairing::PairingManager;
+use crate::rate_limiter::RateLimiter;
+
+const PREFIX: &str = "/admin/";
+
+/// Lines of `bridge.log` returned by `GET /admin/logs`.
+pub const LOG_LINES: usize = 200;
+
+/// INFO and more severe, as in [`crate::log_file::line_level`].
+const LOG_LEVEL: u8 = 3;
+
+/// The web admin UI: path under `/admin/`, content type and body.
+const UI_ASSETS: [(&str, &str, &str); 3] = [
+    ("", "text/html; charset=utf-8", include_str!("admin_ui/index.html")),
+    ("admin.js", "text/javascript; charset=utf-8", include_str!("admin_ui/admin.js")),
+    ("admin.css", "text/css; charset=utf-8", include_str!("admin_ui/admin.css")),
+];
+
+/// Replaces the auth token in `common.toml` and returns the new one; the
+/// bridge then restarts to serve it.
+pub type TokenRotatorFn = Arc<dyn Fn() -> Result<String> + Send + Sync>;
+
+/// Everything `GET /admin/status` reports.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminStatus {
+    pub version: String,
+    pub uptime_secs: u64,
+    /// Open connections per client address, the admin request's included
+    pub connections: Vec<ClientConnections>,
+    /// Whether the memory watchdog is refusing new connections
+    pub shedding: bool,
+    /// `None` without an agent pool
+    pub pool: Option<PoolStats>,
+    pub agents: Vec<AgentSummary>,
+    /// `None` when pairing is disabled
+    pub pairing: Option<PairingStatus>,
+}
+
+/// Open connections from one address.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientConnections {
+    pub ip: String,
+    pub count: usize,
+}
+
+/// The pairing code currently accepted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairingStatus {
+    pub code: String,
+    /// Pairing URL as in the QR code
+    pub url: String,
+    pub seconds_remaining: u64,
+    pub used: bool,
+}
+
+/// A configured device, without its token.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceSummary {
+    pub name: String,
+    /// The `[[users]]` namespace of the device (`None` for the owner's)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    pub scopes: Vec<String>,
+}
+
+impl DeviceSummary {
+    fn of(device: &DeviceConfig, user: Option<&str>) -> Self {
+        Self {
+            name: device.name.clone(),
+            user: user.map(str::to_string),
+            scopes: device.scopes.iter().map(|s| s.as_str().to_string()).collect(),
+        }
+    }
+}
+
+/// An enabled transport.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransportSummary {
+    pub name: String,
+    /// Whether this bridge process serves it
+    pub serving: bool,
+    /// Client URL, for the served transport
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+/// The pairing code with its QR code, for `GET /admin/pairing/qr`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairingQr {
+    #[serde(flatten)]
+    pub pairing: PairingStatus,
+    /// The pairing URL as an SVG QR code
+    pub svg: String,
+}
+
+impl PairingStatus {
+    fn of(manager: &PairingManager) -> Self {
+        Self {
+            code: manager.get_code(),
+            url: manager.pairing_url(),
+            seconds_remaining: manager.seconds_remaining(),
+            used: manager.is_used(),
+        }
+    }
+}
+
+/// What the admin endpoints report on and control.
+pub struct Admin {
+    started_at: Instant,
+    admin_token: Option<String>,
+    rate_limiter: Arc<RateLimiter>,
+    agent_pool: Option<Arc<RwLock<AgentPool>>>,
+    pairing_manager: Option<Arc<PairingManager>>,
+    ui: bool,
+    devices: Vec<DeviceSummary>,
+    transports: Vec<TransportSummary>,
+    log_path: Option<PathBuf>,
+    token_rotator: Option<TokenRotatorFn>,
+}
+
+impl Admin {
+    pub fn new(admin_token: Option<String>, rate_limiter: Arc<RateLimiter>) -> Self {
+        Self {
+            started_at: Instant::now(),
+            admin_token,
+            rate_limiter,
+            agent_pool: None,
+            pairing_manager: None,
+            ui: false,
+            devices: Vec::new(),
+            transports: Vec::new(),
+            log_path: None,
+            token_rotator: None,
+        }
+    }
+
+    pub fn with_agent_pool(mut self, agent_pool: Option<Arc<RwLock<AgentPool>>>) -> Self {
+        self.agent_pool = agent_pool;
+        self
+    }
+
+    pub fn with_pairing(mut self, pairing_manager: Option<Arc<PairingManager>>) -> Self {
+        self.pairing_manager = pairing_manager;
+        self
+    }
+
+    /// Serve the web admin UI at `GET /admin/`.
+    pub fn with_ui(mut self) -> Self {
+        self.ui = true;
+        self
+    }
+
+    /// List the device names and scopes at `GET /admin/devices`.
+    pub fn with_devices(mut self, devices: &[DeviceConfig]) -> Self {
+        self.devices.extend(devices.iter().map(|d| DeviceSummary::of(d, None)));
+        self
+    }
+
+    /// Also list the devices of these users.
+    pub fn with_users(mut self, users: &[UserConfig]) -> Self {
+        for user in users {
+            self.devices.extend(user.devices.iter().map(|d| DeviceSummary::of(d, Some(&user.name))));
+        }
+        self
+    }
+
+    pub fn with_transports(mut self, transports: Vec<TransportSummary>) -> Self {
+        self.transports = transports;
+        self
+    }
+
+    /// Serve the tail of this log file at `GET /admin/logs`.
+    pub fn with_log_file(mut self, path: Option<PathBuf>) -> Self {
+        self.log_path = path;
+        self
+    }
+
+    /// Allow `POST /admin/token/rotate`.
+    pub fn with_token_rotator(mut self, rotator: Option<TokenRotatorFn>) -> Self {
+        self.token_rotator = rotator;
+        self
+    }
+
+    /// A snapshot for `GET /admin/status`.
+    pub async fn status(&self) -> AdminStatus {
+        let (pool, agents) = match self.agent_pool {
+            Some(ref pool) => {
+                let pool = pool.read().await;
+                (Some(pool.stats()), pool.summaries())
+            }
+            None => (None, Vec::new()),
+        };
+        AdminStatus {
+            version: crate::VERSION.to_string(),
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            connections: self
+                .rate_limiter
+                .connections()
+                .await
+                .into_iter()
+                .map(|(ip, count)| ClientConnections { ip: ip.to_string(), count })
+                .collect(),
+            shedding: self.rate_limiter.is_shedding(),
+            pool,
+            agents,
+            pairing: self.pairing_manager.as_deref().map(PairingStatus::of),
+        }
+    }
+
+    /// Kill the pooled agent with summary id `id`; `false` if there is none.
+    pub async fn kill_agent(&self, id: &str) -> bool {
+        let Some(ref pool) = self.agent_pool else {
+            return false;
+        };
+        let mut pool = pool.write().await;
+        let Some(token) = pool.token_for_id(id) else {
+            return false;
+        };
+        pool.remove_agent(&token).await;
+        true
+    }
+
+    /// Replace the pairing code; `None` when pairing is disabled.
+    pub fn rotate_pairing_code(&self) -> Option<PairingStatus> {
+        let manager = self.pairing_manager.as_deref()?;
+        manager.renew();
+        Some(PairingStatus::of(manager))
+    }
+
+    /// The pairing code and its QR code, renewing the code first when it
+    /// can no longer be used; `None` when pairing is disabled.
+    pub fn pairing_qr(&self) -> Option<Result<PairingQr>> {
+        let manager = self.pairing_manager.as_deref()?;
+        if manager.is_used() || manager.is_expired() {
+            manager.renew();
+        }
+        let pairing = PairingStatus::of(manager);
+        Some(crate::qr::render_qr_svg(&pairing.url).map(|svg| PairingQr { pairing, svg }))
+    }
+
+    /// The last [`LOG_LINES`] lines of the log file; empty without one.
+    pub fn recent_logs(&self) -> Vec<String> {
+        self.log_path
+            .as_deref()
+            .and_then(|path| crate::log_file::read_recent(path, LOG_LEVEL, LOG_LINES).ok())
+            .unwrap_or_default()
+    }
+
+    fn ui_asset(&self, path: &str) -> Option<(&'static str, &'static str)> {
+        let name = if path == "/admin" { "" } else { path.strip_prefix(PREFIX)? };
+        UI_ASSETS.iter().find(|(asset, _, _)| *asset == name).map(|(_, content_type, body)| (*content_type, *body))
+    }
+
+    fn accepts(&self, request: &str) -> bool {
+        let Some(ref expected) = self.admin_token else {
+            return false;
+        };
+        let presented = crate::streamable_http::client_token(request).unwrap_or_default();
+        bool::from(presented.as_bytes().ct_eq(expected.as_bytes()))
+    }
+}
+
+/// Whether the request line is for an admin endpoint.
+pub fn matches(first_line: &str) -> bool {
+    let mut parts = first_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("");
+    let path = path.split('?').next().unwrap_or(path);
+    matches!(method, "GET" | "POST") && (path.starts_with(PREFIX) || path == "/admin")
+}
+
+/// A static UI asset, never cached and locked to its own origin.
+fn asset_response(content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Cache-Control: no-store\r\n\
+         Content-Security-Policy: default-src 'self'; img-src 'self' data:; frame-ancestors 'none'\r\n\
+         X-Content-Type-Options: nosniff\r\n\
+         Referrer-Policy: no-referrer\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+/// Serve one admin request.
+pub(crate) async fn handle_request<S>(
+    stream: &mut S,
+    request: &str,
+    admin: &Admin,
+    auth_failures: Option<&AuthFailures>,
+    client_ip: &str,
+) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let path = request_path(request);
+    let method = request.split_whitespace().next().unwrap_or("");
+    let asset = if method == "GET" { admin.ui_asset(path) } else { None };
+    let response = if admin.admin_token.is_none() {
+        create_http_response(403, "Forbidden", r#"{"error":"admin_disabled","message":"The admin endpoints need an admin_token"}"#)
+    } else if let Some((content_type, body)) = asset {
+        if admin.ui {
+            asset_response(content_type, body)
+        } else {
+            create_http_response(404, "Not Found", r#"{"error":"admin_ui_disabled","message":"Set admin_ui = true in common.toml"}"#)
+        }
+    } else if !admin.accepts(request) {
+        warn!("🚫 Admin request {} from {} rejected: invalid or missing token", path, client_ip);
+        if let Some(failures) = auth_failures {
+            failures.record(client_ip, path);
+        }
+        create_http_response(401, "Unauthorized", r#"{"error":"unauthorized"}"#)
+    } else {
+        let route = path.strip_prefix(PREFIX).unwrap_or("");
+        match (method, route.split('/').collect::<Vec<_>>().as_slice()) {
+            ("GET", ["status"]) => {
+                let json = serde_json::to_string(&admin.status().await).unwrap_or_default();
+                create_http_response(200, "OK", &json)
+            }
+            ("GET", ["devices"]) => create_http_response(200, "OK", &serde_json::to_string(&admin.devices).unwrap_or_default()),
+            ("GET", ["transports"]) => create_http_response(200, "OK", &serde_json::to_string(&admin.transports).unwrap_or_default()),
+            ("GET", ["logs"]) => create_http_response(200, "OK", &serde_json::json!({ "lines": admin.recent_logs() }).to_string()),
+            ("POST", ["agents", id, "kill"]) => {
+                if admin.kill_agent(id).await {
+                    info!("🛑 Agent {} killed from the admin endpoint", id);
+                    create_http_response(200, "OK", &serde_json::json!({ "killed": id }).to_string())
+                } else {
+                    create_http_response(404, "Not Found", r#"{"error":"no_such_agent"}"#)
+                }
+            }
+            ("POST", ["pairing", "rotate"]) => match admin.rotate_pairing_code() {
+                Some(pairing) => {
+                    info!("🔗 Pairing code replaced from the admin endpoint");
+                    create_http_response(200, "OK", &serde_json::to_string(&pairing).unwrap_or_default())
+                }
+                None => create_http_response(404, "Not Found", r#"{"error":"pairing_disabled"}"#),
+            },
+            ("GET", ["pairing", "qr"]) => match admin.pairing_qr() {
+                Some(Ok(qr)) => create_http_response(200, "OK", &serde_json::to_string(&qr).unwrap_or_default()),
+                Some(Err(e)) => {
+                    warn!("Failed to render the pairing QR code: {:#}", e);
+                    create_http_response(500, "Internal Server Error", r#"{"error":"qr_failed"}"#)
+                }
+                None => create_http_response(404, "Not Found", r#"{"error":"pairing_disabled"}"#),
+            },
+            ("POST", ["token", "rotate"]) => match admin.token_rotator {
+                Some(ref rotate) => match rotate() {
+                    Ok(token) => {
+                        warn!("🔑 Auth token replaced from the admin endpoint by {} — restarting, pair again", client_ip);
+                        create_http_response(200, "OK", &serde_json::json!({ "authToken": token, "restarting": true }).to_string())
+                    }
+                    Err(e) => {
+                        warn!("Failed to replace the auth token: {:#}", e);
+                        create_http_response(500, "Internal Server Error", r#"{"error":"rotation_failed"}"#)
+                    }
+                },
+                None => create_http_response(404, "Not Found", r#"{"error":"token_rotation_unavailable"}"#),
+            },
+            _ => create_http_response(404, "Not Found", r#"{"error":"not_found"}"#),
+        }
+    };
+    stream.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admin() -> Admin {
+        let pairing = PairingManager::new_with_cf(
+            "agent".into(),
+            "wss://10.0.0.5:8765".into(),
+            "secret".into(),
+            None,
+            None,
+            None,
+            "/tmp".into(),
+        );
+        Admin::new(Some("admin-secret".into()), Arc::new(RateLimiter::new(10, 30)))
+            .with_agent_pool(Some(Arc::new(RwLock::new(AgentPool::new(Default::default())))))
+            .with_pairing(Some(Arc::new(pairing)))
+    }
+
+    async fn answer(admin: &Admin, request_line: &str, token: Option<&str>) -> (u16, String) {
+        let mut request = format!("{} HTTP/1.1\r\nHost: bridge\r\n", request_line);
+        if let Some(token) = token {
+            request.push_str(&format!("X-Bridge-Token: {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        let mut response = Vec::new();
+        handle_request(&mut response, &request, admin, None, "127.0.0.1").await.unwrap();
+        let response = String::from_utf8(response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn only_the_admin_token_gets_in() {
+        let admin = admin();
+        assert_eq!(answer(&admin, "GET /admin/status", None).await.0, 401);
+        assert_eq!(answer(&admin, "GET /admin/status", Some("wrong")).await.0, 401);
+        // The pairing manager's auth token is a client token, not an admin one
+        assert_eq!(answer(&admin, "GET /admin/status", Some("secret")).await.0, 401);
+        let (status, body) = answer(&admin, "GET /admin/status", Some("admin-secret")).await;
+        assert_eq!(status, 200);
+        let report: AdminStatus = serde_json::from_str(&body).unwrap();
+        assert_eq!(report.pool.map(|p| p.total), Some(0));
+        assert!(report.pairing.is_some());
+
+        let disabled = Admin::new(None, Arc::new(RateLimiter::new(10, 30)));
+        assert_eq!(answer(&disabled, "GET /admin/status", None).await.0, 403);
+        assert_eq!(answer(&disabled, "GET /admin/status", Some("admin-secret")).await.0, 403);
+    }
+
+    #[tokio::test]
+    async fn rotates_the_pairing_code_and_kills_agents() {
+        let admin = admin();
+        let before = admin.status().await.pairing.unwrap();
+        let (status, body) = answer(&admin, "POST /admin/pairing/rotate", Some("admin-secret")).await;
+        assert_eq!(status, 200);
+        let after: PairingStatus = serde_json::from_str(&body).unwrap();
+        assert_ne!(after.code, before.code);
+        assert!(after.url.starts_with("https://10.0.0.5:8765/pair/local?code="));
+
+        assert_eq!(answer(&admin, "POST /admin/agents/abc/kill", Some("admin-secret")).await.0, 404);
+        assert_eq!(answer(&admin, "GET /admin/agents/abc/kill", Some("admin-secret")).await.0, 404);
+    }
+
+    #[tokio::test]
+    async fn serves_the_ui_only_when_enabled_and_lists_devices_without_tokens() {
+        let device = DeviceConfig { name: "tablet".into(), token: "tablet-token-0123456789".into(), scopes: vec![crate::device_tokens::Scope::Chat] };
+        let admin = admin().with_devices(std::slice::from_ref(&device));
+        let (status, body) = answer(&admin, "GET /admin/", None).await;
+        assert_eq!(status, 404);
+        assert!(body.contains("admin_ui_disabled"));
+
+        let admin = admin.with_ui();
+        let mut response = Vec::new();
+        handle_request(&mut response, "GET /admin HTTP/1.1\r\n\r\n", &admin, None, "127.0.0.1").await.unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Content-Type: text/html"));
+        assert!(response.contains("Content-Security-Policy: default-src 'self'"));
+        assert_eq!(answer(&admin, "GET /admin/admin.js", None).await.0, 200);
+        assert_eq!(answer(&admin, "GET /admin/devices", None).await.0, 401);
+
+        let (status, body) = answer(&admin, "GET /admin/devices", Some("admin-secret")).await;
+        assert_eq!(status, 200);
+        assert!(!body.contains(&device.token));
+        let devices: Vec<DeviceSummary> = serde_json::from_str(&body).unwrap();
+        assert_eq!(devices, vec![DeviceSummary { name: "tablet".into(), user: None, scopes: vec!["chat".into()] }]);
+    }
+
+    #[tokio::test]
+    async fn qr_renews_a_used_code_and_token_rotation_needs_a_rotator() {
+        let admin = admin();
+        let pairing = admin.pairing_manager.clone().unwrap();
+        pairing.validate(&pairing.get_code()).unwrap();
+        let (status, body) = answer(&admin, "GET /admin/pairing/qr", Some("admin-secret")).await;
+        assert_eq!(status, 200);
+        let qr: PairingQr = serde_json::from_str(&body).unwrap();
+        assert!(!qr.pairing.used);
+        assert!(qr.svg.contains("<svg"));
+
+        assert_eq!(answer(&admin, "POST /admin/token/rotate", Some("admin-secret")).await.0, 404);
+        let admin = admin.with_token_rotator(Some(Arc::new(|| Ok("fresh-token".to_string()))));
+        let (status, body) = answer(&admin, "POST /admin/token/rotate", Some("admin-secret")).await;
+        assert_eq!(status, 200);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&body).unwrap()["authToken"], "fresh-token");
+    }
+}
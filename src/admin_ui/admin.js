@@ -0,0 +1,269 @@
+// Web admin UI: polls the /admin/... JSON endpoints with the admin token the
+// user signs in with. Everything from the bridge is inserted as text.
+"use strict";
+
+const TOKEN_KEY = "bridgeAdminToken";
+const STATUS_INTERVAL_MS = 2000;
+const LOGS_INTERVAL_MS = 5000;
+const RESTART_DELAY_MS = 5000;
+
+const $ = (id) => document.getElementById(id);
+let timers = [];
+
+class Unauthorized extends Error {}
+
+async function api(method, path) {
+  const response = await fetch(path, {
+    method,
+    headers: { "X-Bridge-Token": sessionStorage.getItem(TOKEN_KEY) || "" },
+    cache: "no-store",
+  });
+  if (response.status === 401) {
+    throw new Unauthorized();
+  }
+  const body = await response.json().catch(() => ({}));
+  if (!response.ok) {
+    throw new Error(body.message || body.error || `HTTP ${response.status}`);
+  }
+  return body;
+}
+
+function cell(text, className) {
+  const td = document.createElement("td");
+  td.textContent = text;
+  if (className) {
+    td.className = className;
+  }
+  return td;
+}
+
+function fill(tbody, rows, empty, columns) {
+  tbody.replaceChildren();
+  if (rows.length === 0) {
+    const tr = document.createElement("tr");
+    const td = cell(empty, "muted");
+    td.colSpan = columns;
+    tr.append(td);
+    tbody.append(tr);
+    return;
+  }
+  for (const row of rows) {
+    const tr = document.createElement("tr");
+    tr.append(...row);
+    tbody.append(tr);
+  }
+}
+
+function duration(secs) {
+  if (secs < 60) return `${secs}s`;
+  if (secs < 3600) return `${Math.floor(secs / 60)}m ${secs % 60}s`;
+  if (secs < 86400) return `${Math.floor(secs / 3600)}h ${Math.floor((secs % 3600) / 60)}m`;
+  return `${Math.floor(secs / 86400)}d ${Math.floor((secs % 86400) / 3600)}h`;
+}
+
+function bytes(n) {
+  if (n < 1024) return `${n} B`;
+  if (n < 1024 * 1024) return `${(n / 1024).toFixed(1)} KiB`;
+  return `${(n / 1024 / 1024).toFixed(1)} MiB`;
+}
+
+function notice(text) {
+  $("notice").textContent = text;
+  $("notice").hidden = !text;
+}
+
+function renderStatus(status) {
+  $("summary").textContent =
+    `v${status.version} · up ${duration(status.uptimeSecs)}` + (status.shedding ? " · shedding connections" : "");
+
+  fill(
+    $("sessions"),
+    status.agents.map((agent) => {
+      const kill = document.createElement("button");
+      kill.className = "danger";
+      kill.textContent = "Kill";
+      kill.addEventListener("click", () => killSession(agent.id));
+      const actions = document.createElement("td");
+      actions.append(kill);
+      return [
+        cell(agent.id, "mono"),
+        cell(agent.user || ""),
+        cell(agent.name),
+        cell(agent.command, "mono"),
+        cell(agent.connected ? `connected (${agent.connections})` : `idle (${agent.connections})`),
+        cell(agent.idleSecs == null ? "" : duration(agent.idleSecs)),
+        cell(agent.buffered ? `${agent.buffered} (${bytes(agent.bufferedBytes)})` : ""),
+        cell(String(agent.messagesIn)),
+        cell(String(agent.messagesOut)),
+        actions,
+      ];
+    }),
+    "No sessions",
+    10,
+  );
+
+  const pool = status.pool;
+  $("pool").textContent = pool
+    ? `Pool: ${pool.total}/${pool.max} agents, ${pool.connected} connected, ${pool.idle} idle, ${pool.warm} warm` +
+      (pool.powerSaving ? " (power saving)" : "")
+    : "No agent pool";
+  $("clients").textContent = status.connections.length
+    ? "Clients: " + status.connections.map((c) => `${c.ip} (${c.count})`).join(", ")
+    : "No open connections";
+
+  const pairing = status.pairing;
+  $("pairing").textContent = !pairing
+    ? "Pairing is disabled"
+    : pairing.used
+      ? `Code ${pairing.code} was used`
+      : pairing.secondsRemaining > 0
+        ? `Code ${pairing.code}, expires in ${pairing.secondsRemaining}s`
+        : `Code ${pairing.code} expired`;
+  for (const id of ["show-qr", "rotate-code"]) {
+    $(id).disabled = !pairing;
+  }
+}
+
+async function refreshStatus() {
+  renderStatus(await api("GET", "/admin/status"));
+}
+
+async function refreshLogs() {
+  const logs = $("logs");
+  const atBottom = logs.scrollTop + logs.clientHeight >= logs.scrollHeight - 4;
+  const { lines } = await api("GET", "/admin/logs");
+  logs.textContent = lines.length ? lines.join("\n") : "No log lines yet";
+  if (atBottom) {
+    logs.scrollTop = logs.scrollHeight;
+  }
+}
+
+async function refreshConfig() {
+  const [devices, transports] = await Promise.all([api("GET", "/admin/devices"), api("GET", "/admin/transports")]);
+  fill(
+    $("devices"),
+    devices.map((d) => [cell(d.name), cell(d.user || ""), cell(d.scopes.join(", "))]),
+    "Only the auth token is configured",
+    3,
+  );
+  fill(
+    $("transports"),
+    transports.map((t) => [cell(t.name), cell(t.serving ? "serving" : "enabled"), cell(t.url || "", "mono")]),
+    "No transports",
+    3,
+  );
+}
+
+async function guarded(action) {
+  try {
+    await action();
+  } catch (e) {
+    if (e instanceof Unauthorized) {
+      signOut("The bridge rejected the token.");
+    } else {
+      notice(`Error: ${e.message}`);
+    }
+  }
+}
+
+async function killSession(id) {
+  if (!confirm(`Kill session ${id}? Its agent process is stopped and its buffered messages are dropped.`)) {
+    return;
+  }
+  await guarded(async () => {
+    await api("POST", `/admin/agents/${encodeURIComponent(id)}/kill`);
+    notice(`Session ${id} killed.`);
+    await refreshStatus();
+  });
+}
+
+async function showQr() {
+  await guarded(async () => {
+    const qr = await api("GET", "/admin/pairing/qr");
+    $("qr-image").src = "data:image/svg+xml;charset=utf-8," + encodeURIComponent(qr.svg);
+    $("qr-url").textContent = qr.url;
+    $("qr").hidden = false;
+    await refreshStatus();
+  });
+}
+
+async function rotateCode() {
+  await guarded(async () => {
+    await api("POST", "/admin/pairing/rotate");
+    $("qr").hidden = true;
+    notice("Pairing code replaced.");
+    await refreshStatus();
+  });
+}
+
+async function rotateToken() {
+  if (!confirm("Replace the auth token? Devices paired with a credentials key receive it over their connection; the others have to pair again. The bridge restarts.")) {
+    return;
+  }
+  await guarded(async () => {
+    const { authToken } = await api("POST", "/admin/token/rotate");
+    $("qr").hidden = true;
+    notice("");
+    $("rotated").textContent =
+      `Auth token replaced and saved to common.toml; pair your devices again. New token: ${authToken}`;
+    $("rotated").hidden = false;
+    // Give the bridge time to restart before polling again.
+    stopPolling();
+    setTimeout(startPolling, RESTART_DELAY_MS);
+  });
+}
+
+function startPolling() {
+  stopPolling();
+  const poll = (fn, ms) => {
+    guarded(fn);
+    timers.push(setInterval(() => guarded(fn), ms));
+  };
+  poll(refreshStatus, STATUS_INTERVAL_MS);
+  poll(refreshLogs, LOGS_INTERVAL_MS);
+  guarded(refreshConfig);
+}
+
+function stopPolling() {
+  timers.forEach(clearInterval);
+  timers = [];
+}
+
+function signOut(message) {
+  stopPolling();
+  sessionStorage.removeItem(TOKEN_KEY);
+  $("dashboard").hidden = true;
+  $("sign-out").hidden = true;
+  $("summary").textContent = "";
+  $("rotated").hidden = true;
+  $("sign-in").hidden = false;
+  $("sign-in-error").textContent = message || "";
+}
+
+function signIn(token) {
+  sessionStorage.setItem(TOKEN_KEY, token);
+  $("sign-in").hidden = true;
+  $("dashboard").hidden = false;
+  $("sign-out").hidden = false;
+  notice("");
+  startPolling();
+}
+
+document.addEventListener("DOMContentLoaded", () => {
+  $("sign-in").addEventListener("submit", (event) => {
+    event.preventDefault();
+    signIn($("token").value.trim());
+    $("token").value = "";
+  });
+  $("sign-out").addEventListener("click", () => signOut());
+  $("show-qr").addEventListener("click", showQr);
+  $("rotate-code").addEventListener("click", rotateCode);
+  $("rotate-token").addEventListener("click", rotateToken);
+
+  const token = sessionStorage.getItem(TOKEN_KEY);
+  if (token) {
+    signIn(token);
+  } else {
+    signOut();
+  }
+});
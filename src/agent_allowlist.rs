@@ -0,0 +1,102 @@
+//! Restricting which agent commands the bridge runs (`[[allowed_agents]]`).
+//!
+//! On a shared service, whoever can change the agent command can make every
+//! connection run an arbitrary program. With an allowlist, the bridge only
+//! starts agents whose binary, after `PATH` lookup and symlinks are resolved,
+//! is one of the listed programs, and whose arguments match when the entry
+//! lists them. `run_bridge` checks the configured command at startup and the
+//! pool checks every command it spawns.
+
+use anyhow::Result;
+use std::path::PathBuf;
+
+use crate::common_config::AllowedAgentConfig;
+
+/// Listed programs, resolved once, and their permitted arguments.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AgentAllowlist {
+    entries: Vec<(AllowedAgentConfig, Option<PathBuf>)>,
+}
+
+impl From<&[AllowedAgentConfig]> for AgentAllowlist {
+    fn from(allowed: &[AllowedAgentConfig]) -> Self {
+        Self { entries: allowed.iter().map(|a| (a.clone(), resolve(&a.program))).collect() }
+    }
+}
+
+impl AgentAllowlist {
+    /// Whether any command is allowed.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Refuse `agent_command` unless an entry allows it. An empty list
+    /// allows everything.
+    pub fn check(&self, agent_command: &str) -> Result<()> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+        let mut parts = agent_command.split_whitespace();
+        let Some(program) = parts.next() else {
+            anyhow::bail!("Empty agent command");
+        };
+        let args: Vec<&str> = parts.collect();
+        let resolved = resolve(program);
+        let allowed = self.entries.iter().any(|(entry, entry_path)| {
+            let same_program = match (&resolved, entry_path) {
+                (Some(a), Some(b)) => a == b,
+                _ => false,
+            };
+            same_program && entry.args.as_ref().is_none_or(|allowed| allowed.iter().map(String::as_str).eq(args.iter().copied()))
+        });
+        if !allowed {
+            anyhow::bail!("Agent command {:?} is not in [[allowed_agents]]", agent_command);
+        }
+        Ok(())
+    }
+}
+
+/// `program` as a canonical path, looked up on `PATH` unless it is a path.
+/// `None` when there is no such executable.
+fn resolve(program: &str) -> Option<PathBuf> {
+    which::which(program).ok()?.canonicalize().ok()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    fn executable(dir: &Path, name: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn only_listed_programs_and_arguments_are_allowed() {
+        let dir = tempfile::tempdir().unwrap();
+        let agent = executable(dir.path(), "agent");
+        let other = executable(dir.path(), "other");
+        let link = dir.path().join("agent-link");
+        std::os::unix::fs::symlink(&agent, &link).unwrap();
+
+        let allowlist = AgentAllowlist::from(
+            &[
+                AllowedAgentConfig { program: agent.display().to_string(), args: Some(vec!["--acp".into()]) },
+                AllowedAgentConfig { program: other.display().to_string(), args: None },
+            ][..],
+        );
+        assert!(allowlist.check(&format!("{} --acp", agent.display())).is_ok());
+        assert!(allowlist.check(&format!("{} --acp", link.display())).is_ok(), "symlinks resolve to the binary");
+        assert!(allowlist.check(&format!("{} --acp --yolo", agent.display())).is_err());
+        assert!(allowlist.check(&format!("{}", agent.display())).is_err());
+        assert!(allowlist.check(&format!("{} anything at all", other.display())).is_ok());
+        let err = allowlist.check("/bin/sh -c id").unwrap_err();
+        assert!(err.to_string().contains("not in [[allowed_agents]]"), "{err}");
+
+        assert!(AgentAllowlist::default().check("/bin/sh -c id").is_ok());
+    }
+}
@@ -0,0 +1,238 @@
+//! Shell hooks around pooled agents (`[[agent_hooks]]` in `common.toml`).
+//!
+//! | Hook | Runs | On failure |
+//! |------|------|------------|
+//! | `pre_spawn` | before the agent process starts, in its working directory | the agent isn't started |
+//! | `post_exit` | after the process exits, stopped or crashed | logged |
+//! | `on_permission_request` | when the agent asks for a permission (`session/request_permission`) | logged |
+//!
+//! Each hook is run with `sh -c` (`cmd /C` on Windows) and killed after
+//! `timeout_secs`. It gets `AGENT_COMMAND` and `AGENT_DIR`, plus
+//! `AGENT_EXIT_STATUS` for `post_exit` and `AGENT_REQUEST` (the JSON-RPC
+//! request) for `on_permission_request`. The exit status and the combined
+//! stdout and stderr (the last [`MAX_OUTPUT_BYTES`]) go to the bridge log,
+//! which `bridge logs` shows.
+
+use std::fmt;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use tokio::io::AsyncReadExt;
+use tracing::{info, warn};
+
+use crate::common_config::AgentHookConfig;
+use crate::error::BridgeError;
+
+/// Most output of one run kept for the log.
+pub const MAX_OUTPUT_BYTES: usize = 4096;
+
+/// How long to wait for the rest of the output once a hook has exited.
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookKind {
+    PreSpawn,
+    PostExit,
+    PermissionRequest,
+}
+
+impl fmt::Display for HookKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HookKind::PreSpawn => "pre_spawn",
+            HookKind::PostExit => "post_exit",
+            HookKind::PermissionRequest => "on_permission_request",
+        })
+    }
+}
+
+/// The hooks for `agent_command`: the first entry whose `agent` occurs in
+/// it, or that has no `agent`.
+pub fn for_agent<'a>(hooks: &'a [AgentHookConfig], agent_command: &str) -> Option<&'a AgentHookConfig> {
+    hooks.iter().find(|h| h.agent.as_deref().is_none_or(|agent| agent_command.contains(agent)))
+}
+
+/// How one hook run ended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HookRun {
+    pub kind: HookKind,
+    /// Exit code; `None` when it timed out, was killed by a signal or
+    /// couldn't be started.
+    pub code: Option<i32>,
+    pub timed_out: bool,
+    /// Why it couldn't be started.
+    pub error: Option<String>,
+    /// Combined stdout and stderr, trimmed.
+    pub output: String,
+    pub elapsed: Duration,
+}
+
+impl HookRun {
+    pub fn succeeded(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    fn outcome(&self) -> String {
+        match (&self.error, self.timed_out, self.code) {
+            (Some(e), _, _) => format!("failed to start: {}", e),
+            (None, true, _) => format!("timed out after {:.1}s", self.elapsed.as_secs_f64()),
+            (None, false, Some(code)) => format!("exit {} in {:.1}s", code, self.elapsed.as_secs_f64()),
+            (None, false, None) => "killed by a signal".to_string(),
+        }
+    }
+
+    /// Write the run to the bridge log: info when it succeeded, else warn.
+    pub fn log(&self, agent_command: &str) {
+        let output = if self.output.is_empty() { String::new() } else { format!("\n{}", self.output) };
+        if self.succeeded() {
+            info!("🪝 {} hook for `{}`: {}{}", self.kind, agent_command, self.outcome(), output);
+        } else {
+            warn!("🪝 {} hook for `{}`: {}{}", self.kind, agent_command, self.outcome(), output);
+        }
+    }
+}
+
+/// Run `script` in `working_dir` with `env`, killing it after `timeout`.
+pub async fn run(kind: HookKind, script: &str, working_dir: &Path, env: &[(&str, String)], timeout: Duration) -> HookRun {
+    let started = Instant::now();
+    let finished = |code, timed_out, error, output| HookRun { kind, code, timed_out, error, output, elapsed: started.elapsed() };
+
+    #[cfg(windows)]
+    let mut command = {
+        let mut command = tokio::process::Command::new("cmd");
+        command.arg("/C").arg(script);
+        command
+    };
+    #[cfg(not(windows))]
+    let mut command = {
+        let mut command = tokio::process::Command::new("sh");
+        command.arg("-c").arg(script);
+        command
+    };
+    command
+        .current_dir(working_dir)
+        .envs(env.iter().map(|(k, v)| (*k, v.as_str())))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => return finished(None, false, Some(e.to_string()), String::new()),
+    };
+
+    // Read both pipes while waiting, so a chatty hook can't block on a full one.
+    let readers = [read_all(child.stdout.take()), read_all(child.stderr.take())];
+    let status = match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(status) => status,
+        Err(_) => {
+            let _ = child.kill().await;
+            return finished(None, true, None, String::new());
+        }
+    };
+    // Something it started in the background may keep the pipes open.
+    let mut output = Vec::new();
+    for reader in readers {
+        if let Ok(Ok(bytes)) = tokio::time::timeout(OUTPUT_GRACE, reader).await {
+            output.extend(bytes);
+        }
+    }
+    match status {
+        Ok(status) => finished(status.code(), false, None, tail(&output)),
+        Err(e) => finished(None, false, Some(e.to_string()), tail(&output)),
+    }
+}
+
+fn read_all(pipe: Option<impl tokio::io::AsyncRead + Unpin + Send + 'static>) -> tokio::task::JoinHandle<Vec<u8>> {
+    tokio::spawn(async move {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes).await;
+        }
+        bytes
+    })
+}
+
+/// The last [`MAX_OUTPUT_BYTES`] of `output`, trimmed.
+fn tail(output: &[u8]) -> String {
+    let text = String::from_utf8_lossy(output);
+    let text = text.trim();
+    let mut start = text.len().saturating_sub(MAX_OUTPUT_BYTES);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    text[start..].to_string()
+}
+
+/// Run the `pre_spawn` hook of `hooks`, if any; an error when it fails.
+pub async fn pre_spawn(hooks: Option<&AgentHookConfig>, agent_command: &str, working_dir: &Path) -> Result<(), BridgeError> {
+    let Some((hooks, script)) = hooks.and_then(|h| h.pre_spawn.as_deref().map(|s| (h, s))) else {
+        return Ok(());
+    };
+    let env = [("AGENT_COMMAND", agent_command.to_string()), ("AGENT_DIR", working_dir.display().to_string())];
+    let run = run(HookKind::PreSpawn, script, working_dir, &env, hooks.timeout()).await;
+    run.log(agent_command);
+    if run.succeeded() {
+        Ok(())
+    } else {
+        Err(BridgeError::Io { message: format!("pre_spawn hook for `{}` failed: {}", agent_command, run.outcome()), source: None })
+    }
+}
+
+/// Start the hook `kind` of `hooks` in the background, if configured.
+pub fn spawn(kind: HookKind, hooks: Option<&AgentHookConfig>, agent_command: &str, working_dir: &Path, extra: Option<(&'static str, String)>) {
+    let Some(hooks) = hooks else { return };
+    let script = match kind {
+        HookKind::PreSpawn => &hooks.pre_spawn,
+        HookKind::PostExit => &hooks.post_exit,
+        HookKind::PermissionRequest => &hooks.on_permission_request,
+    };
+    let Some(script) = script.clone() else { return };
+    let timeout = hooks.timeout();
+    let agent_command = agent_command.to_string();
+    let working_dir = working_dir.to_path_buf();
+    tokio::spawn(async move {
+        let mut env = vec![("AGENT_COMMAND", agent_command.clone()), ("AGENT_DIR", working_dir.display().to_string())];
+        env.extend(extra);
+        run(kind, &script, &working_dir, &env, timeout).await.log(&agent_command);
+    });
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn hooks_capture_output_and_time_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let env = [("AGENT_COMMAND", "claude --acp".to_string())];
+        let second = Duration::from_secs(5);
+
+        let ok = run(HookKind::PreSpawn, "echo \"$AGENT_COMMAND\"; echo warn >&2; pwd", dir.path(), &env, second).await;
+        assert!(ok.succeeded());
+        assert!(ok.output.starts_with("claude --acp\n"), "{:?}", ok.output);
+        assert!(ok.output.contains("warn"));
+
+        let failed = run(HookKind::PostExit, "exit 4", dir.path(), &env, second).await;
+        assert_eq!((failed.code, failed.timed_out), (Some(4), false));
+
+        let slow = run(HookKind::PreSpawn, "sleep 5", dir.path(), &env, Duration::from_millis(200)).await;
+        assert!(slow.timed_out && !slow.succeeded());
+        assert!(slow.elapsed < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn hooks_match_like_sandboxes() {
+        let hook = |agent: Option<&str>, script: &str| AgentHookConfig {
+            agent: agent.map(str::to_string),
+            pre_spawn: Some(script.to_string()),
+            ..AgentHookConfig::default()
+        };
+        let hooks = [hook(Some("claude"), "a"), hook(None, "b")];
+        assert_eq!(for_agent(&hooks, "claude --acp").unwrap().pre_spawn.as_deref(), Some("a"));
+        assert_eq!(for_agent(&hooks, "goose acp").unwrap().pre_spawn.as_deref(), Some("b"));
+        assert!(for_agent(&hooks[..1], "goose acp").is_none());
+        assert_eq!(tail("é".repeat(MAX_OUTPUT_BYTES).as_bytes()).len(), MAX_OUTPUT_BYTES);
+    }
+}
@@ -1,15 +1,26 @@
-use anyhow::{Context, Result};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use crate::error::{BridgeError, Result, ResultExt};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::process::{Child, Command};
-use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::sync::{broadcast, mpsc, watch, Notify, RwLock};
 use tracing::{debug, error, info, warn};
 
+use crate::agent_allowlist::AgentAllowlist;
+use crate::agent_hooks::{self, HookKind};
+use crate::auto_lock::Activity;
+use crate::line_reader::{truncation_message, Line, LineReader};
+use crate::power::PowerSaving;
+use crate::crash_report::{CrashLog, CrashReport, CrashReports};
+use crate::common_config::{AgentHookConfig, EndSessionConfig, EvictionConfig, EvictionPolicy, HealthCheckConfig, InterceptConfig, PoolOverrideConfig, SandboxConfig, UserConfig, WorkspaceConfig, WorktreeConfig};
 use crate::push::PushRelayClient;
+use crate::sandbox;
+use crate::transcript::{Direction, TranscriptSink};
+use crate::worktree::{FinishAction, FinishOutcome, Worktree};
 
 /// Configuration for the agent pool
 #[derive(Debug, Clone)]
@@ -22,6 +33,58 @@ pub struct PoolConfig {
     pub buffer_messages: bool,
     /// Maximum number of buffered messages per agent
     pub max_buffer_size: usize,
+    /// Maximum total bytes of buffered messages per agent (0 = unlimited)
+    pub max_buffer_bytes: usize,
+    /// How long a buffered message is kept for replay (`None` = until the agent exits)
+    pub buffer_ttl: Option<Duration>,
+    /// Number of pre-spawned, already initialized agents kept ready for new tokens
+    pub warm_agents: usize,
+    /// Which idle agent to kill when the pool is full
+    pub eviction: EvictionConfig,
+    /// Per-token and per-agent-command idle timeout and buffer overrides
+    pub overrides: Vec<PoolOverrideConfig>,
+    /// Which `[[users]]` each token belongs to, and their agent quotas
+    pub users: UserNamespaces,
+    /// Longest agent output line passed on (`[limits] max_line_bytes`)
+    pub max_line_bytes: usize,
+    /// `agent_version` from the config, part of [`agent_fingerprint`]
+    pub agent_version: Option<String>,
+}
+
+impl PoolConfig {
+    /// Idle timeout and buffer limits for `token`'s agent running `agent_command`:
+    /// a token override beats an agent override, which beats the pool default.
+    pub fn limits_for(&self, token: &str, agent_command: &str) -> AgentLimits {
+        let by_token = self.overrides.iter().find(|o| o.token.as_deref() == Some(token));
+        let by_agent = self
+            .overrides
+            .iter()
+            .find(|o| o.agent.as_deref().is_some_and(|agent| agent_command.contains(agent)));
+        let pick = |field: fn(&PoolOverrideConfig) -> Option<u64>| {
+            by_token.and_then(field).or_else(|| by_agent.and_then(field))
+        };
+        AgentLimits {
+            idle_timeout: pick(|o| o.idle_timeout_secs).map_or(self.idle_timeout, Duration::from_secs),
+            max_buffer_size: pick(|o| o.max_buffer_size.map(|n| n as u64)).map_or(self.max_buffer_size, |n| n as usize),
+            max_buffer_bytes: pick(|o| o.max_buffer_bytes.map(|n| n as u64)).map_or(self.max_buffer_bytes, |n| n as usize),
+            buffer_ttl: pick(|o| o.buffer_ttl_secs).map_or(self.buffer_ttl, |secs| (secs > 0).then(|| Duration::from_secs(secs))),
+        }
+    }
+}
+
+/// Per-agent settings resolved from [`PoolConfig::limits_for`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AgentLimits {
+    pub idle_timeout: Duration,
+    pub max_buffer_size: usize,
+    pub max_buffer_bytes: usize,
+    pub buffer_ttl: Option<Duration>,
+}
+
+impl Default for AgentLimits {
+    fn default() -> Self {
+        PoolConfig::default().limits_for("", "")
+    }
 }
 
 impl Default for PoolConfig {
@@ -31,28 +94,546 @@ impl Default for PoolConfig {
             max_agents: 10,
             buffer_messages: true,
             max_buffer_size: 10_000,
+            max_buffer_bytes: 8 * 1024 * 1024,
+            buffer_ttl: None,
+            warm_agents: 0,
+            eviction: EvictionConfig::default(),
+            overrides: Vec::new(),
+            users: UserNamespaces::default(),
+            max_line_bytes: crate::line_reader::DEFAULT_MAX_LINE_BYTES,
+            agent_version: None,
         }
     }
 }
 
-/// A pooled agent process with its I/O handles
-pub struct PooledAgent {
-    /// The spawned child process
-    process: Child,
-    /// Sender for messages going to the agent (from WebSocket to stdin)
-    pub ws_to_agent_tx: mpsc::Sender<String>,
-    /// Broadcast sender for messages from agent stdout.
-    /// Each new connection subscribes via .subscribe()
-    pub agent_to_ws_tx: broadcast::Sender<String>,
+/// The `[[users]]` namespace of each pool key, and each user's agent quota.
+/// Tokens not listed belong to the bridge's owner, who has no quota.
+#[derive(Debug, Clone, Default)]
+pub struct UserNamespaces {
+    owners: HashMap<String, String>,
+    quotas: HashMap<String, usize>,
+}
+
+impl UserNamespaces {
+    pub fn new(users: &[UserConfig]) -> Self {
+        let mut namespaces = Self::default();
+        for user in users {
+            for token in std::iter::once(&user.auth_token).chain(user.devices.iter().map(|d| &d.token)) {
+                namespaces.owners.insert(token.clone(), user.name.clone());
+            }
+            if let Some(quota) = user.max_agents {
+                namespaces.quotas.insert(user.name.clone(), quota);
+            }
+        }
+        namespaces
+    }
+
+    /// The user `token` belongs to; `None` for the owner's tokens.
+    pub fn owner(&self, token: &str) -> Option<&str> {
+        self.owners.get(token).map(String::as_str)
+    }
+
+    pub fn quota(&self, user: &str) -> Option<usize> {
+        self.quotas.get(user).copied()
+    }
+}
+
+/// Liveness probe for idle agents (see [`start_health_checker`]).
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthCheck {
+    /// JSON-RPC method of the no-op request. Any response, even a
+    /// "method not found" error, proves the agent is still reading stdin.
+    pub method: String,
+    /// How often idle agents are probed
+    pub interval: Duration,
+    /// How long an agent may take to answer before it is replaced
+    pub timeout: Duration,
+}
+
+impl From<&HealthCheckConfig> for HealthCheck {
+    fn from(config: &HealthCheckConfig) -> Self {
+        Self {
+            method: config.method.clone(),
+            interval: Duration::from_secs(config.interval_secs),
+            timeout: Duration::from_secs(config.timeout_secs),
+        }
+    }
+}
+
+/// Request id prefix of liveness probes; replies carrying it are consumed by
+/// the pool and never reach clients or transcripts.
+const PROBE_ID_PREFIX: &str = "bridge-health-";
+
+/// Probe sequence number of a probe request or its reply.
+fn probe_seq(line: &str) -> Option<u64> {
+    if !line.contains(PROBE_ID_PREFIX) {
+        return None;
+    }
+    let v: serde_json::Value = serde_json::from_str(line).ok()?;
+    v.get("id")?.as_str()?.strip_prefix(PROBE_ID_PREFIX)?.parse().ok()
+}
+
+/// Sequence-numbered history of agent → client messages, used to answer
+/// `bridge/resume`. Sequence numbers start at 1 and never repeat for an agent.
+#[derive(Debug)]
+pub struct ReplayLog {
+    next_seq: u64,
+    entries: VecDeque<(u64, String)>,
+    capacity: usize,
+    /// Highest sequence number the client has acknowledged via `bridge/ack`.
+    acked_seq: u64,
+}
+
+impl ReplayLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            next_seq: 1,
+            entries: VecDeque::new(),
+            capacity,
+            acked_seq: 0,
+        }
+    }
+
+    /// Record a message and return its sequence number. The oldest entry is
+    /// evicted once `capacity` is reached.
+    pub fn push(&mut self, msg: String) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        if self.capacity > 0 {
+            if self.entries.len() >= self.capacity {
+                self.entries.pop_front();
+            }
+            self.entries.push_back((seq, msg));
+        }
+        seq
+    }
+
+    /// Change how many entries are kept, dropping the oldest if over.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Drop all but the newest `keep` entries without changing the capacity;
+    /// returns how many were dropped.
+    pub fn trim(&mut self, keep: usize) -> usize {
+        let dropped = self.entries.len().saturating_sub(keep);
+        self.entries.drain(..dropped);
+        dropped
+    }
+
+    /// Highest sequence number assigned so far (0 if none).
+    pub fn last_seq(&self) -> u64 {
+        self.next_seq - 1
+    }
+
+    /// Record that the client has processed everything up to `seq`. Acked
+    /// messages are dropped from the log; acks never move backwards.
+    pub fn ack(&mut self, seq: u64) {
+        self.acked_seq = self.acked_seq.max(seq.min(self.last_seq()));
+        while self.entries.front().is_some_and(|(s, _)| *s <= self.acked_seq) {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Highest acknowledged sequence number (0 if none).
+    pub fn acked_seq(&self) -> u64 {
+        self.acked_seq
+    }
+
+    /// Messages with a sequence number greater than `after`. The flag is false
+    /// when some of those messages were already evicted (the replay has a gap).
+    pub fn since(&self, after: u64) -> (Vec<(u64, String)>, bool) {
+        let complete = match self.entries.front() {
+            Some((oldest, _)) => *oldest <= after + 1,
+            None => after >= self.last_seq(),
+        };
+        let msgs = self
+            .entries
+            .iter()
+            .filter(|(seq, _)| *seq > after)
+            .cloned()
+            .collect();
+        (msgs, complete)
+    }
+}
+
+/// Publishes agent → client messages. Each message is assigned its sequence
+/// number and broadcast under one lock, so a subscriber always knows the
+/// sequence number of the next message it will receive.
+#[derive(Clone, Debug)]
+pub struct AgentOutput {
+    tx: broadcast::Sender<String>,
+    log: Arc<std::sync::Mutex<ReplayLog>>,
+}
+
+impl AgentOutput {
+    fn new(capacity: usize) -> (Self, broadcast::Receiver<String>) {
+        let (tx, rx) = broadcast::channel::<String>(256);
+        let log = Arc::new(std::sync::Mutex::new(ReplayLog::new(capacity)));
+        (Self { tx, log }, rx)
+    }
+
+    fn log(&self) -> std::sync::MutexGuard<'_, ReplayLog> {
+        self.log.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sequence and broadcast a message. Errors (returning the message) when
+    /// no client is subscribed; the message is still in the replay log.
+    pub fn publish(&self, msg: String) -> std::result::Result<usize, broadcast::error::SendError<String>> {
+        let mut log = self.log();
+        log.push(msg.clone());
+        self.tx.send(msg)
+    }
+
+    /// Subscribe to future messages. Also returns the sequence number of the
+    /// last message the receiver will *not* see.
+    pub fn subscribe(&self) -> (broadcast::Receiver<String>, u64) {
+        let log = self.log();
+        (self.tx.subscribe(), log.last_seq())
+    }
+
+    /// Record a client acknowledgement (see [`ReplayLog::ack`]).
+    pub fn ack(&self, seq: u64) {
+        self.log().ack(seq);
+    }
+
+    /// Drop all but the newest `keep` replay entries; returns how many were dropped.
+    pub fn trim(&self, keep: usize) -> usize {
+        self.log().trim(keep)
+    }
+
+    /// Highest sequence number acknowledged by a client.
+    pub fn acked_seq(&self) -> u64 {
+        self.log().acked_seq()
+    }
+}
+
+/// How readily a buffered message is dropped when the buffer is over budget;
+/// lower classes go first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Retention {
+    /// Intermediate streaming output (`*_chunk` session updates)
+    Chunk,
+    /// Other notifications, e.g. tool call and plan updates
+    Normal,
+    /// Agent requests such as permission prompts, and responses
+    Keep,
+}
+
+impl Retention {
+    fn of(message: &str) -> Self {
+        let Ok(v) = serde_json::from_str::<serde_json::Value>(message) else {
+            return Retention::Normal;
+        };
+        if v.get("id").is_some() {
+            return Retention::Keep;
+        }
+        let chunk = v.get("method").and_then(|m| m.as_str()) == Some("session/update")
+            && v.pointer("/params/update/sessionUpdate")
+                .and_then(|u| u.as_str())
+                .is_some_and(|u| u.ends_with("_chunk"));
+        if chunk { Retention::Chunk } else { Retention::Normal }
+    }
+}
+
+/// What makes consecutive text chunks part of the same streamed message:
+/// session, update kind (`agent_message_chunk`, `agent_thought_chunk`, ...)
+/// and, when the agent sends one, the message id. Shared with
+/// [`OutputShaper`](crate::output_shaping::OutputShaper), which merges held
+/// chunks by the same rule.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ChunkKey {
+    session_id: Option<String>,
+    kind: String,
+    message_id: Option<String>,
+}
+
+impl ChunkKey {
+    /// The key of a `session/update` text chunk; `None` for anything else.
+    pub(crate) fn of(message: &serde_json::Value) -> Option<Self> {
+        if message.get("method").and_then(|m| m.as_str()) != Some("session/update") || message.get("id").is_some() {
+            return None;
+        }
+        let update = message.pointer("/params/update")?;
+        let kind = update.get("sessionUpdate")?.as_str().filter(|k| k.ends_with("_chunk"))?;
+        let content = update.get("content")?;
+        if content.get("type")?.as_str() != Some("text") || !content.get("text")?.is_string() {
+            return None;
+        }
+        let string_at = |value: &serde_json::Value, key: &str| value.get(key).and_then(|v| v.as_str()).map(String::from);
+        Some(Self {
+            session_id: string_at(&message["params"], "sessionId"),
+            kind: kind.to_string(),
+            message_id: string_at(update, "messageId"),
+        })
+    }
+}
+
+/// What running `agent_command` would start now: the resolved binary with
+/// its modification time, and the configured `agent_version`. An agent
+/// whose fingerprint no longer matches was upgraded after it was spawned.
+pub fn agent_fingerprint(agent_command: &str, version: Option<&str>) -> Option<String> {
+    let binary = agent_command
+        .split_whitespace()
+        .next()
+        .and_then(|program| std::fs::canonicalize(which::which(program).ok()?).ok())
+        .and_then(|path| {
+            let modified = std::fs::metadata(&path).ok()?.modified().ok()?;
+            let nanos = modified.duration_since(std::time::UNIX_EPOCH).ok()?.as_nanos();
+            Some(format!("{}@{}", path.display(), nanos))
+        });
+    match (binary, version) {
+        (Some(binary), Some(version)) => Some(format!("{}, version {}", binary, version)),
+        (Some(binary), None) => Some(binary),
+        (None, Some(version)) => Some(format!("version {}", version)),
+        (None, None) => None,
+    }
+}
+
+/// Merge each run of consecutive text chunks of one streamed message into a
+/// single `session/update`, so a reconnecting client replays one
+/// notification per message instead of one per token. Everything else, and
+/// runs of a single chunk, pass through unchanged.
+pub fn collapse_chunks(messages: Vec<String>) -> Vec<String> {
+    struct Run {
+        key: ChunkKey,
+        first: String,
+        merged: serde_json::Value,
+        chunks: usize,
+    }
+    fn flush(run: Option<Run>, out: &mut Vec<String>) {
+        if let Some(run) = run {
+            out.push(if run.chunks == 1 { run.first } else { run.merged.to_string() });
+        }
+    }
+
+    let mut out = Vec::with_capacity(messages.len());
+    let mut run: Option<Run> = None;
+    for message in messages {
+        let parsed = serde_json::from_str::<serde_json::Value>(&message).ok();
+        let Some((key, value)) = parsed.and_then(|v| Some((ChunkKey::of(&v)?, v))) else {
+            flush(run.take(), &mut out);
+            out.push(message);
+            continue;
+        };
+        if let Some(current) = run.as_mut().filter(|r| r.key == key) {
+            let text = value["params"]["update"]["content"]["text"].as_str().unwrap_or_default();
+            if let Some(serde_json::Value::String(merged)) = current.merged.pointer_mut("/params/update/content/text") {
+                merged.push_str(text);
+            }
+            current.chunks += 1;
+            continue;
+        }
+        flush(run.take(), &mut out);
+        run = Some(Run { key, first: message, merged: value, chunks: 1 });
+    }
+    flush(run, &mut out);
+    out
+}
+
+#[derive(Debug)]
+struct Buffered {
+    message: String,
+    /// Sequence number from [`AgentOutput`], or 0 when unknown
+    seq: u64,
+    at: Instant,
+    retention: Retention,
+}
+
+/// Agent messages held for replay while no client is connected, bounded by
+/// count, total bytes and age ([`AgentLimits`]). Over budget, streaming chunks
+/// are dropped before other notifications, and those before requests and
+/// responses, oldest first within each class.
+#[derive(Debug, Default)]
+pub struct MessageBuffer {
+    entries: VecDeque<Buffered>,
+    bytes: usize,
+    limits: AgentLimits,
+}
+
+impl MessageBuffer {
+    pub fn new(limits: AgentLimits) -> Self {
+        Self { limits, ..Self::default() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total size of the buffered messages.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Buffered messages, oldest first.
+    pub fn messages(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|b| b.message.as_str())
+    }
+
+    /// Apply new limits; returns how many messages they dropped.
+    pub fn set_limits(&mut self, limits: AgentLimits) -> usize {
+        self.limits = limits;
+        self.enforce()
+    }
+
+    /// Add a message; returns how many messages were dropped to make room,
+    /// possibly including this one.
+    pub fn push(&mut self, message: String) -> usize {
+        self.push_sequenced(0, message)
+    }
+
+    /// [`Self::push`] for the message with sequence number `seq`, so the
+    /// replay can skip it when it was delivered after all.
+    pub fn push_sequenced(&mut self, seq: u64, message: String) -> usize {
+        let retention = Retention::of(&message);
+        self.bytes += message.len();
+        self.entries.push_back(Buffered { message, seq, at: Instant::now(), retention });
+        self.enforce()
+    }
+
+    /// Move all of `other`'s messages to the end of this buffer, keeping
+    /// their age; returns how many messages were dropped.
+    pub fn append(&mut self, other: &mut MessageBuffer) -> usize {
+        self.bytes += std::mem::take(&mut other.bytes);
+        self.entries.append(&mut other.entries);
+        self.enforce()
+    }
+
+    /// Drop the lowest-priority messages until at most `keep` remain;
+    /// returns how many were dropped.
+    pub fn trim(&mut self, keep: usize) -> usize {
+        let mut dropped = 0;
+        while self.entries.len() > keep && self.evict_one() {
+            dropped += 1;
+        }
+        dropped
+    }
+
+    /// Answer the oldest message matching `pred` with `answer`, removing it
+    /// only when that succeeds: a refused one keeps its place and sequence
+    /// number.
+    pub fn answer_first<T, E>(
+        &mut self,
+        pred: impl Fn(&str) -> bool,
+        answer: impl Fn(&str) -> std::result::Result<T, E>,
+    ) -> Option<std::result::Result<T, E>> {
+        let index = self.entries.iter().position(|b| pred(&b.message))?;
+        let answered = answer(&self.entries[index].message);
+        if answered.is_ok() {
+            if let Some(removed) = self.entries.remove(index) {
+                self.bytes -= removed.message.len();
+            }
+        }
+        Some(answered)
+    }
+
+    /// The unexpired messages, oldest first, leaving the buffer empty.
+    pub fn take(&mut self) -> Vec<String> {
+        self.take_unacked(0)
+    }
+
+    /// [`Self::take`], without the messages a client acknowledged (sequence
+    /// number up to `acked_seq`) and without repeats of a sequence number.
+    pub fn take_unacked(&mut self, acked_seq: u64) -> Vec<String> {
+        self.expire();
+        self.bytes = 0;
+        let mut seen = std::collections::HashSet::new();
+        let mut skipped = 0;
+        let messages = self
+            .entries
+            .drain(..)
+            .filter(|b| {
+                let fresh = b.seq == 0 || (b.seq > acked_seq && seen.insert(b.seq));
+                skipped += usize::from(!fresh);
+                fresh
+            })
+            .map(|b| b.message)
+            .collect();
+        if skipped > 0 {
+            debug!("Skipped {} buffered message(s) the client already received", skipped);
+        }
+        messages
+    }
+
+    fn over_budget(&self) -> bool {
+        self.entries.len() > self.limits.max_buffer_size
+            || (self.limits.max_buffer_bytes > 0 && self.bytes > self.limits.max_buffer_bytes)
+    }
+
+    fn enforce(&mut self) -> usize {
+        let mut dropped = self.expire();
+        while self.over_budget() && self.evict_one() {
+            dropped += 1;
+        }
+        dropped
+    }
+
+    fn expire(&mut self) -> usize {
+        let Some(ttl) = self.limits.buffer_ttl else {
+            return 0;
+        };
+        let before = self.entries.len();
+        self.entries.retain(|b| b.at.elapsed() <= ttl);
+        self.bytes = self.entries.iter().map(|b| b.message.len()).sum();
+        before - self.entries.len()
+    }
+
+    /// Drop the oldest message of the lowest retention class present.
+    fn evict_one(&mut self) -> bool {
+        let Some(lowest) = self.entries.iter().map(|b| b.retention).min() else {
+            return false;
+        };
+        let index = self.entries.iter().position(|b| b.retention == lowest).unwrap_or(0);
+        if let Some(evicted) = self.entries.remove(index) {
+            self.bytes -= evicted.message.len();
+        }
+        true
+    }
+}
+
+/// Result of [`AgentPool::replay_since`].
+#[derive(Debug)]
+pub struct Replay {
+    /// `(seq, message)` pairs in order.
+    pub messages: Vec<(u64, String)>,
+    /// False when some requested messages were already evicted from the log.
+    pub complete: bool,
+    /// Highest sequence number assigned by the agent so far.
+    pub last_seq: u64,
+}
+
+/// What `get_or_spawn` hands a connection: (ws_to_agent_tx, agent_to_ws_rx,
+/// buffered_messages, was_reused, cached_init_response, cached_session_response, agent_output)
+pub type AgentConnection = (mpsc::Sender<String>, broadcast::Receiver<String>, Vec<String>, bool, Option<String>, Option<String>, AgentOutput);
+
+/// Per-connection state of a pooled agent, guarded by the agent's own lock so
+/// sessions never contend on the pool-wide lock for it.
+#[derive(Debug, Default)]
+pub struct AgentState {
     /// Whether a client is currently connected
     pub connected: bool,
+    /// Idle timeout and buffer limits currently applied to this agent
+    pub limits: AgentLimits,
+    /// Number of client connections this agent has served (for LFU eviction)
+    pub connections: u64,
     /// When the client last disconnected (for idle timeout)
     pub disconnected_at: Option<Instant>,
     /// Buffered messages from agent while client was disconnected (written by bridge.rs send-fail path)
-    pub message_buffer: Vec<String>,
-    /// Overflow buffer written by the stdout broadcast task when there are 0 receivers.
-    /// Drained into message_buffer on reconnect.
-    overflow_buffer: Arc<tokio::sync::Mutex<Vec<String>>>,
+    pub message_buffer: MessageBuffer,
+    /// Sequence number of the last message the most recent `get_or_spawn`
+    /// receiver will not see (see [`AgentPool::subscribed_through`]).
+    subscribed_through: u64,
+    /// The connection that last took over delivering agent output to a
+    /// client ([`AgentPool::connect_client`]): its number, and the last
+    /// sequence number its receiver doesn't see.
+    delivery: (u64, u64),
     /// Cached `initialize` response from the agent (raw JSON-RPC result).
     /// On reconnect we intercept the client's `initialize` request and reply
     /// with this cached response instead of forwarding to the agent.
@@ -62,53 +643,381 @@ pub struct PooledAgent {
     /// with this cached response, preserving the same session ID so the agent
     /// keeps its conversation history.
     pub cached_session_response: Option<String>,
+}
+
+impl AgentState {
+    /// Make the latest receiver the one delivering agent output to a client,
+    /// and return its delivery number. Called under the same lock that takes
+    /// the replay buffer, so an older connection can't buffer a message the
+    /// new receiver gets in between.
+    fn claim_delivery(&mut self) -> u64 {
+        self.delivery = (self.delivery.0 + 1, self.subscribed_through);
+        self.delivery.0
+    }
+}
+
+/// The shareable part of a pooled agent: I/O handles plus its own state lock.
+/// Connections hold an `Arc<AgentSlot>` and only touch the pool map to
+/// insert or remove agents.
+pub struct AgentSlot {
+    /// Sender for messages going to the agent (from WebSocket to stdin)
+    pub ws_to_agent_tx: mpsc::Sender<String>,
+    /// Sequenced broadcast of messages from agent stdout.
+    /// Each new connection subscribes via .subscribe()
+    pub output: AgentOutput,
+    /// Overflow buffer written by the stdout broadcast task when there are 0 receivers.
+    /// Drained into message_buffer on reconnect.
+    overflow_buffer: Arc<std::sync::Mutex<MessageBuffer>>,
+    state: std::sync::Mutex<AgentState>,
     /// The agent command used to spawn this agent
-    #[allow(dead_code)]
     pub agent_command: String,
+    /// What the agent was spawned from, see [`agent_fingerprint`]
+    pub fingerprint: Option<String>,
+    /// Working directory the agent was spawned in
+    pub working_dir: PathBuf,
     /// Human-readable agent name (from initialize response). Shared with the
     /// stdout broadcast task for push notification titles.
     pub agent_name: Arc<tokio::sync::RwLock<String>>,
+    /// Transcript session label, shared with the I/O tasks. Empty until a
+    /// warm agent is assigned to a token.
+    transcript_session: Arc<std::sync::RwLock<String>>,
+    buffer_messages: bool,
+    /// Client messages written to the agent's stdin, probes excluded
+    messages_in: Arc<AtomicU64>,
+    /// Sequence number of the last liveness probe sent
+    probe_sent: AtomicU64,
+    /// Sequence number of the last liveness probe answered, set by the stdout task
+    probe_answered: watch::Receiver<u64>,
+    /// Held while the bridge itself prompts the agent (`[[schedules]]`,
+    /// `POST /trigger`), so those prompts take turns.
+    pub bridge_prompts: tokio::sync::Mutex<()>,
+}
+
+impl AgentSlot {
+    /// Lock this agent's state.
+    pub fn state(&self) -> std::sync::MutexGuard<'_, AgentState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Subscribe to agent stdout messages
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.output.subscribe().0
+    }
+
+    /// Mark the client as disconnected. The agent stays alive for idle_timeout.
+    pub fn mark_disconnected(&self) {
+        info!("Client disconnected, agent entering idle state (keep-alive)");
+        let mut state = self.state();
+        state.connected = false;
+        state.disconnected_at = Some(Instant::now());
+    }
+
+    /// Apply the idle timeout and buffer limits resolved for the agent's current token.
+    pub fn set_limits(&self, limits: AgentLimits) {
+        self.overflow().set_limits(limits);
+        self.output.log().set_capacity(limits.max_buffer_size);
+        let mut state = self.state();
+        state.limits = limits;
+        state.message_buffer.set_limits(limits);
+    }
+
+    fn overflow(&self) -> std::sync::MutexGuard<'_, MessageBuffer> {
+        self.overflow_buffer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Buffer a message for replay on the next reconnect
+    pub fn buffer_message(&self, message: String) {
+        if !self.buffer_messages {
+            return;
+        }
+        let dropped = self.state().message_buffer.push(message);
+        if dropped > 0 {
+            debug!("Message buffer over budget for agent, dropped {} message(s)", dropped);
+        }
+    }
+
+    /// Buffer message `seq` that delivery `delivery` (see
+    /// [`AgentPool::connect_client`]) failed to send, unless a later delivery's
+    /// receiver gets it too: the client that replaced a dropped connection
+    /// has it already, and replaying it would show it twice. Returns whether
+    /// the message is still undelivered.
+    pub fn buffer_unsent(&self, seq: u64, message: String, delivery: u64) -> bool {
+        let mut state = self.state();
+        let (latest, latest_through) = state.delivery;
+        if latest != delivery && seq > latest_through {
+            debug!("Not buffering message #{}: a newer connection receives it", seq);
+            return false;
+        }
+        if self.buffer_messages {
+            let dropped = state.message_buffer.push_sequenced(seq, message);
+            if dropped > 0 {
+                debug!("Message buffer over budget for agent, dropped {} message(s)", dropped);
+            }
+        }
+        true
+    }
+
+    /// Answer the buffered `session/request_permission` with `request_id` in
+    /// `session_id` without a client. It leaves the replay buffers only when
+    /// `answer` succeeds. `None` when no client-less request matches, such
+    /// as one a client already received.
+    pub fn answer_permission_request<T, E>(
+        &self,
+        session_id: &str,
+        request_id: &str,
+        answer: impl Fn(&serde_json::Value) -> std::result::Result<T, E>,
+    ) -> Option<std::result::Result<T, E>> {
+        let matches = |message: &str| {
+            crate::push::permission_request(message)
+                .is_some_and(|r| r.request_id == request_id && r.session_id.as_deref() == Some(session_id))
+        };
+        let answer = |message: &str| answer(&serde_json::from_str(message).unwrap_or_default());
+        let buffered = self.state().message_buffer.answer_first(matches, answer);
+        buffered.or_else(|| self.overflow().answer_first(matches, answer))
+    }
+
+    /// Cache the agent's `initialize` response and take the agent name from it.
+    pub fn cache_init_response(&self, response: String) {
+        if let Ok(v) = serde_json::from_str::<serde_json::Value>(&response) {
+            let name = v["result"]["agentInfo"]["name"].as_str()
+                .or_else(|| v["result"]["serverInfo"]["name"].as_str());
+            if let Some(name) = name {
+                let agent_name = Arc::clone(&self.agent_name);
+                let name_owned = name.to_string();
+                tokio::spawn(async move {
+                    *agent_name.write().await = name_owned;
+                });
+                info!("Agent name set to '{}'", name);
+            }
+        }
+        self.state().cached_init_response = Some(response);
+    }
+
+    /// Cache the agent's `createSession` response so reconnections reuse the same session ID
+    pub fn cache_session_response(&self, response: String) {
+        info!("Cached createSession response for agent (keep-alive)");
+        self.state().cached_session_response = Some(response);
+    }
+
+    /// Clear the cached session response (e.g., when agent reports "Session not found")
+    pub fn clear_session_response(&self) {
+        if self.state().cached_session_response.take().is_some() {
+            info!("Cleared cached session response for agent (session invalidated)");
+        }
+    }
+
+    /// Send a liveness probe and wait up to `timeout` for the agent to answer it.
+    pub async fn probe(&self, method: &str, timeout: Duration) -> bool {
+        let seq = self.probe_sent.fetch_add(1, Ordering::Relaxed) + 1;
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": format!("{}{}", PROBE_ID_PREFIX, seq),
+            "method": method,
+            "params": {}
+        });
+        let mut answered = self.probe_answered.clone();
+        let probe = async {
+            // Sending can block too: a hung agent stops draining its stdin.
+            self.ws_to_agent_tx.send(request.to_string()).await.is_ok()
+                && answered.wait_for(|answered| *answered >= seq).await.is_ok()
+        };
+        tokio::time::timeout(timeout, probe).await.unwrap_or(false)
+    }
+
+    /// Sequence number of the last agent message the receiver from the latest
+    /// `get_or_spawn` will not see.
+    pub fn subscribed_through(&self) -> u64 {
+        self.state().subscribed_through
+    }
+
+    /// Cache the agent's `initialize` or session response if `line` is the
+    /// first one seen, for transports without the WebSocket capture logic.
+    pub fn capture_handshake(&self, line: &str) {
+        let (has_init, has_session) = {
+            let state = self.state();
+            (state.cached_init_response.is_some(), state.cached_session_response.is_some())
+        };
+        if !has_init && crate::bridge::is_initialize_response(line) {
+            self.cache_init_response(line.to_string());
+        } else if !has_session && crate::bridge::is_create_session_response(line) {
+            self.cache_session_response(line.to_string());
+        }
+    }
+
+    /// The cached `initialize` response re-addressed to `request`, if `request`
+    /// is an `initialize` and the agent has already been initialized.
+    pub fn cached_initialize_reply(&self, request: &serde_json::Value) -> Option<String> {
+        if request.get("method").and_then(|m| m.as_str()) != Some("initialize") {
+            return None;
+        }
+        let id = request.get("id")?;
+        let cached = self.state().cached_init_response.clone()?;
+        let mut response: serde_json::Value = serde_json::from_str(&cached).ok()?;
+        response["id"] = id.clone();
+        Some(response.to_string())
+    }
+}
+
+/// A pooled agent process with its I/O handles. The child process itself is
+/// owned by a task that waits for it to exit.
+pub struct PooledAgent {
+    slot: Arc<AgentSlot>,
+    /// Tells the stdin writer task to close the agent's stdin
+    close_stdin: Arc<Notify>,
+    /// The exit status, once the process has exited
+    exit: watch::Receiver<Option<ExitStatus>>,
+    /// Tells the waiting task to kill the process
+    kill: Arc<Notify>,
+    /// Set once the bridge stops the agent, so its exit isn't reported as a
+    /// crash (see [`crate::crash_report`])
+    stopping: Arc<AtomicBool>,
+}
+
+impl std::ops::Deref for PooledAgent {
+    type Target = AgentSlot;
+
+    fn deref(&self) -> &AgentSlot {
+        &self.slot
+    }
 }
 
 impl PooledAgent {
     /// Check if this agent's process is still running
     pub fn is_alive(&mut self) -> bool {
-        match self.process.try_wait() {
-            Ok(Some(_)) => false,
-            Ok(None) => true,
-            Err(_) => false,
-        }
+        self.exit.borrow().is_none()
     }
 
-    /// Kill the agent process gracefully
+    /// Kill the agent process and wait for it to exit
     pub async fn kill(&mut self) {
         info!("Killing pooled agent process");
-        if let Err(e) = self.process.kill().await {
-            warn!("Failed to kill agent process: {}", e);
-        }
+        self.stopping.store(true, Ordering::Relaxed);
+        self.kill.notify_one();
+        let _ = self.exit.wait_for(Option::is_some).await;
     }
 
-    /// Subscribe to agent stdout messages
-    pub fn subscribe(&self) -> broadcast::Receiver<String> {
-        self.agent_to_ws_tx.subscribe()
+    /// Shared handle to this agent's channels and state
+    pub fn slot(&self) -> Arc<AgentSlot> {
+        Arc::clone(&self.slot)
     }
+
+    /// Ask the agent to exit, by closing its stdin or sending `config`'s
+    /// shutdown request, and kill it if it hasn't within the timeout.
+    pub async fn end(&mut self, config: &EndSessionConfig) -> SessionEnd {
+        self.stopping.store(true, Ordering::Relaxed);
+        match config.shutdown_method {
+            Some(ref method) => {
+                let request = serde_json::json!({ "jsonrpc": "2.0", "id": SHUTDOWN_REQUEST_ID, "method": method });
+                let _ = self.ws_to_agent_tx.send(request.to_string()).await;
+            }
+            None => self.close_stdin.notify_one(),
+        }
+        let timeout = Duration::from_secs(config.timeout_secs);
+        let exited = match tokio::time::timeout(timeout, self.exit.wait_for(Option::is_some)).await {
+            Ok(Ok(status)) => *status,
+            _ => None,
+        };
+        match exited {
+            Some(status) => {
+                info!("Pooled agent exited ({})", status);
+                SessionEnd { graceful: true, exit_code: status.code() }
+            }
+            _ => {
+                warn!("Pooled agent still running {:?} after being asked to exit", timeout);
+                self.kill().await;
+                SessionEnd { graceful: false, exit_code: None }
+            }
+        }
+    }
+}
+
+/// `id` of the shutdown request [`PooledAgent::end`] sends.
+pub const SHUTDOWN_REQUEST_ID: &str = "bridge-end-session";
+
+/// How [`AgentPool::end_session`] stopped an agent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionEnd {
+    /// The agent exited by itself before the timeout; otherwise it was killed
+    pub graceful: bool,
+    /// Its exit code, if it exited with one
+    pub exit_code: Option<i32>,
 }
 
 /// Manages a pool of long-lived agent processes keyed by auth token
 pub struct AgentPool {
     pub(crate) agents: HashMap<String, PooledAgent>,
+    /// Pre-spawned, initialized agents not yet assigned to a token
+    warm: Vec<PooledAgent>,
+    /// Signalled whenever a warm agent is taken or lost, to wake the backfill task
+    warm_needed: Arc<Notify>,
+    /// Per-token locks serializing connection setup, so near-simultaneous
+    /// connections with one token share an agent instead of spawning two
+    spawn_locks: std::sync::Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
     config: PoolConfig,
     push_relay: Option<Arc<PushRelayClient>>,
     working_dir: PathBuf,
+    /// Project directories clients may switch their agent to
+    workspaces: Vec<WorkspaceConfig>,
+    /// Workspace name selected per token; other tokens use `working_dir`
+    selected_workspaces: HashMap<String, String>,
+    /// Give each token's agent its own git worktree
+    worktrees: Option<WorktreeConfig>,
+    /// The worktree of each token, kept across agent restarts until finished
+    session_worktrees: HashMap<String, Worktree>,
+    /// Where successful connections are recorded for `[auto_lock]`
+    activity: Option<Arc<Activity>>,
+    /// Tighter limits while on a low battery (`[power]`)
+    power_saving: Option<PowerSaving>,
+    /// Agent commands the pool may spawn
+    allowlist: AgentAllowlist,
+    /// Linux sandboxes agents are spawned in (`[[sandbox]]`)
+    sandboxes: Vec<SandboxConfig>,
+    /// Shell hooks run around agents (`[[agent_hooks]]`)
+    hooks: Vec<AgentHookConfig>,
+    /// Reconnect intercepts per agent (`[[intercepts]]`)
+    intercepts: Vec<InterceptConfig>,
+    transcripts: Option<TranscriptSink>,
+    /// How `bridge/endSession` stops an agent
+    end_session: EndSessionConfig,
+    /// Where reports of agents that exit on their own are saved
+    crash_reports: Option<CrashReports>,
+    /// Where to report an agent that couldn't be spawned
+    spawn_failures: Option<mpsc::Sender<String>>,
+}
+
+/// Result of [`AgentPool::select_workspace`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkspaceSelection {
+    pub workspace: WorkspaceConfig,
+    /// The token's agent ran in another directory and was stopped; the next
+    /// connection spawns one in the workspace.
+    pub restarted: bool,
 }
 
 impl AgentPool {
     pub fn new(config: PoolConfig) -> Self {
         Self {
             agents: HashMap::new(),
+            warm: Vec::new(),
+            warm_needed: Arc::new(Notify::new()),
+            spawn_locks: std::sync::Mutex::new(HashMap::new()),
             config,
             push_relay: None,
             working_dir: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+            workspaces: Vec::new(),
+            selected_workspaces: HashMap::new(),
+            worktrees: None,
+            session_worktrees: HashMap::new(),
+            activity: None,
+            power_saving: None,
+            allowlist: AgentAllowlist::default(),
+            sandboxes: Vec::new(),
+            hooks: Vec::new(),
+            intercepts: Vec::new(),
+            transcripts: None,
+            end_session: EndSessionConfig::default(),
+            crash_reports: None,
+            spawn_failures: None,
         }
     }
 
@@ -118,125 +1027,465 @@ impl AgentPool {
         self
     }
 
+    /// Let clients switch their agent between these project directories.
+    pub fn with_workspaces(mut self, workspaces: Vec<WorkspaceConfig>) -> Self {
+        self.workspaces = workspaces;
+        self
+    }
+
+    /// Spawn each token's agent in a git worktree of its own.
+    pub fn with_worktrees(mut self, config: WorktreeConfig) -> Self {
+        self.worktrees = Some(config);
+        self
+    }
+
+    /// Record every successful connection in `activity`.
+    pub fn with_activity(mut self, activity: Arc<Activity>) -> Self {
+        self.activity = Some(activity);
+        self
+    }
+
+    /// Only spawn agent commands `allowlist` allows.
+    pub fn with_agent_allowlist(mut self, allowlist: AgentAllowlist) -> Self {
+        self.allowlist = allowlist;
+        self
+    }
+
+    /// Spawn matching agents inside these sandboxes.
+    pub fn with_sandboxes(mut self, sandboxes: Vec<SandboxConfig>) -> Self {
+        self.sandboxes = sandboxes;
+        self
+    }
+
+    /// Run these hooks before matching agents start, after they exit and
+    /// when they ask for a permission.
+    pub fn with_agent_hooks(mut self, hooks: Vec<AgentHookConfig>) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Answer reconnecting clients of matching agents from the cache with
+    /// these method names and limits.
+    pub fn with_intercepts(mut self, intercepts: Vec<InterceptConfig>) -> Self {
+        self.intercepts = intercepts;
+        self
+    }
+
+    /// The intercept settings of `agent_command`: the first matching
+    /// `[[intercepts]]` entry, or ACP's method names.
+    pub fn intercept_config(&self, agent_command: &str) -> InterceptConfig {
+        crate::intercept::for_agent(&self.intercepts, agent_command).cloned().unwrap_or_default()
+    }
+
     /// Set the push relay client for sending notifications
+    /// Stop agents for `bridge/endSession` as `config` says.
+    pub fn with_end_session(mut self, config: EndSessionConfig) -> Self {
+        self.end_session = config;
+        self
+    }
+
     pub fn with_push_relay(mut self, push_relay: Arc<PushRelayClient>) -> Self {
         self.push_relay = Some(push_relay);
         self
     }
 
+    /// Save a crash report to `reports` when an agent exits on its own
+    pub fn with_crash_reports(mut self, reports: CrashReports) -> Self {
+        self.crash_reports = Some(reports);
+        self
+    }
+
+    /// Send the error to `failures` whenever an agent process can't be spawned
+    pub fn with_spawn_failures(mut self, failures: mpsc::Sender<String>) -> Self {
+        self.spawn_failures = Some(failures);
+        self
+    }
+
+    /// Mirror every line exchanged with pooled agents to a transcript sink
+    pub fn with_transcript_sink(mut self, sink: TranscriptSink) -> Self {
+        self.transcripts = Some(sink);
+        self
+    }
+
+    /// Get or spawn the agent for `token` on a shared pool, holding the token's
+    /// spawn lock so concurrent connections with the same token serialize and
+    /// the later one reuses the earlier one's agent. Also returns
+    /// [`subscribed_through`](Self::subscribed_through) for the new receiver
+    /// and the agent's [`AgentSlot`].
+    pub async fn connect(pool: &Arc<RwLock<AgentPool>>, token: &str, agent_command: &str) -> Result<(AgentConnection, u64, Arc<AgentSlot>)> {
+        let (connection, subscribed_through, slot, _) = Self::attach(pool, token, agent_command, false).await?;
+        Ok((connection, subscribed_through, slot))
+    }
+
+    /// Like [`Self::connect`], for a connection that delivers the agent's
+    /// output to a client and buffers what it fails to send. It takes over
+    /// delivery from any connection it replaces; the returned delivery
+    /// number goes to [`AgentSlot::buffer_unsent`].
+    pub async fn connect_client(
+        pool: &Arc<RwLock<AgentPool>>,
+        token: &str,
+        agent_command: &str,
+    ) -> Result<(AgentConnection, u64, Arc<AgentSlot>, u64)> {
+        Self::attach(pool, token, agent_command, true).await
+    }
+
+    async fn attach(
+        pool: &Arc<RwLock<AgentPool>>,
+        token: &str,
+        agent_command: &str,
+        delivers: bool,
+    ) -> Result<(AgentConnection, u64, Arc<AgentSlot>, u64)> {
+        let lock = pool.read().await.spawn_lock(token);
+        let _guard = lock.lock().await;
+        let mut pool = pool.write().await;
+        let (connection, delivery) = pool.get_or_spawn_as(token, agent_command, delivers).await?;
+        let slot = pool
+            .slot(token)
+            .ok_or_else(|| BridgeError::Other(anyhow::anyhow!("Agent vanished from the pool")))?;
+        if let Some(ref activity) = pool.activity {
+            activity.record_connection();
+        }
+        Ok((connection, slot.subscribed_through(), slot, delivery))
+    }
+
+    /// The spawn lock for `token`, created on first use.
+    fn spawn_lock(&self, token: &str) -> Arc<tokio::sync::Mutex<()>> {
+        let mut locks = self.spawn_locks.lock().unwrap_or_else(|e| e.into_inner());
+        Arc::clone(locks.entry(token.to_string()).or_default())
+    }
+
     /// Get an existing agent or spawn a new one for the given token.
-    /// Returns (ws_to_agent_tx, agent_to_ws_rx, buffered_messages, was_reused, cached_init_response, cached_session_response, broadcast_tx)
+    /// Returns (ws_to_agent_tx, agent_to_ws_rx, buffered_messages, was_reused, cached_init_response, cached_session_response, agent_output)
     pub async fn get_or_spawn(
         &mut self,
         token: &str,
         agent_command: &str,
-    ) -> Result<(mpsc::Sender<String>, broadcast::Receiver<String>, Vec<String>, bool, Option<String>, Option<String>, broadcast::Sender<String>)> {
+    ) -> Result<AgentConnection> {
+        self.get_or_spawn_as(token, agent_command, false).await.map(|(connection, _)| connection)
+    }
+
+    /// [`Self::get_or_spawn`], also returning the number of the connection
+    /// delivering the agent's output: this one's when `delivers`.
+    async fn get_or_spawn_as(
+        &mut self,
+        token: &str,
+        agent_command: &str,
+        delivers: bool,
+    ) -> Result<(AgentConnection, u64)> {
+        // An idle agent spawned from an older binary or `agent_version` is
+        // restarted, taking its cached `initialize` and session responses
+        // with it, so the client sees the new agent's capabilities
+        if let Some(agent) = self.agents.get(token) {
+            if !agent.state().connected && self.is_stale(agent) {
+                info!("🔄 Agent changed since it was spawned, restarting it");
+                if let Some(mut agent) = self.agents.remove(token) {
+                    agent.kill().await;
+                }
+            }
+        }
+
         // Check if we have an existing agent for this token
         if let Some(agent) = self.agents.get_mut(token) {
             if agent.is_alive() {
                 info!("Reusing existing agent for token (keep-alive)");
-                agent.connected = true;
-                agent.disconnected_at = None;
+
+                agent.set_limits(self.config.limits_for(token, agent_command));
 
                 // Drain messages buffered by the stdout task (broadcast Err path)
-                {
-                    let mut overflow = agent.overflow_buffer.lock().await;
-                    let overflow_count = overflow.len();
-                    if overflow_count > 0 {
-                        info!("[push-dbg] draining {} overflow message(s) into replay buffer", overflow_count);
-                    }
-                    for msg in overflow.drain(..) {
-                        if agent.message_buffer.len() < self.config.max_buffer_size {
-                            agent.message_buffer.push(msg);
-                        }
-                    }
+                let mut overflow = std::mem::take(&mut *agent.overflow());
+                if !overflow.is_empty() {
+                    info!("[push-dbg] draining {} overflow message(s) into replay buffer", overflow.len());
                 }
 
-                let buffered = std::mem::take(&mut agent.message_buffer);
+                // Whatever a client acknowledged reached it, even if a
+                // dropped connection buffered it too
+                let acked_seq = agent.output.acked_seq();
+                let mut state = agent.state();
+                state.connected = true;
+                state.connections += 1;
+                state.disconnected_at = None;
+                state.message_buffer.append(&mut overflow);
+
+                let taken = state.message_buffer.take_unacked(acked_seq);
+                let taken_count = taken.len();
+                let buffered = collapse_chunks(taken);
                 if !buffered.is_empty() {
-                    info!("Replaying {} buffered messages", buffered.len());
+                    info!("Replaying {} buffered messages (collapsed from {})", buffered.len(), taken_count);
                 }
 
                 let tx = agent.ws_to_agent_tx.clone();
-                let rx = agent.subscribe();
-                let cached_init = agent.cached_init_response.clone();
-                let cached_session = agent.cached_session_response.clone();
-                let broadcast_tx = agent.agent_to_ws_tx.clone();
-
-                return Ok((tx, rx, buffered, true, cached_init, cached_session, broadcast_tx));
+                let (rx, subscribed_through) = agent.output.subscribe();
+                state.subscribed_through = subscribed_through;
+                let delivery = if delivers { state.claim_delivery() } else { state.delivery.0 };
+                let cached_init = state.cached_init_response.clone();
+                let cached_session = state.cached_session_response.clone();
+                let output = agent.output.clone();
+
+                return Ok(((tx, rx, buffered, true, cached_init, cached_session, output), delivery));
             } else {
                 info!("Agent process died, removing from pool");
                 self.agents.remove(token);
             }
         }
 
+        // A user's agents count against their quota before the pool's
+        if let Some(user) = self.config.users.owner(token).map(str::to_string) {
+            if let Some(quota) = self.config.users.quota(&user) {
+                let owned = self.agents.keys().filter(|key| self.config.users.owner(key) == Some(user.as_str())).count();
+                if owned >= quota {
+                    let Some(key) = self.eviction_candidate(Some(&user)) else {
+                        return Err(BridgeError::Capacity(format!(
+                            "User {} has {} agents, its quota, all connected or pinned. Cannot spawn new agent.",
+                            user, quota
+                        )));
+                    };
+                    info!("Evicting idle agent of user {} to stay within its quota of {}", user, quota);
+                    if let Some(mut agent) = self.agents.remove(&key) {
+                        agent.kill().await;
+                        self.notify_evicted(&agent);
+                    }
+                }
+            }
+        }
+
         // Check max agents limit
-        if self.agents.len() >= self.config.max_agents {
-            let oldest_idle = self
-                .agents
-                .iter()
-                .filter(|(_, a)| !a.connected)
-                .min_by_key(|(_, a)| a.disconnected_at)
-                .map(|(k, _)| k.clone());
-
-            if let Some(key) = oldest_idle {
-                info!("Evicting oldest idle agent to make room");
+        if self.agents.len() >= self.max_agents() {
+            if let Some(key) = self.eviction_candidate(None) {
+                info!("Evicting idle agent for token {}... ({:?}) to make room", &key[..8.min(key.len())], self.config.eviction.policy);
                 if let Some(mut agent) = self.agents.remove(&key) {
                     agent.kill().await;
+                    self.notify_evicted(&agent);
                 }
             } else {
-                anyhow::bail!(
-                    "Agent pool is full ({} agents, all connected). Cannot spawn new agent.",
-                    self.config.max_agents
-                );
+                return Err(BridgeError::Capacity(format!(
+                    "Agent pool is full ({} agents, all connected or pinned). Cannot spawn new agent.",
+                    self.max_agents()
+                )));
+            }
+        }
+
+        // Warm agents run in the default directory, never in a worktree
+        if self.worktrees.is_none() && self.working_dir_for(token) == self.working_dir {
+            if let Some(agent) = self.take_warm_agent().await {
+                info!("🔥 Assigning pre-spawned warm agent");
+                let connection = self.assign_agent(token, agent);
+                return Ok((connection, self.claim_fresh_delivery(token, delivers)));
             }
         }
 
         // Spawn a new agent
         info!("Spawning new pooled agent");
-        self.spawn_agent(token, agent_command).await
+        let connection = self.spawn_agent(token, agent_command).await?;
+        Ok((connection, self.claim_fresh_delivery(token, delivers)))
+    }
+
+    /// Claim delivery on a freshly assigned agent, which no older connection
+    /// delivers for.
+    fn claim_fresh_delivery(&self, token: &str, delivers: bool) -> u64 {
+        let Some(agent) = self.agents.get(token) else {
+            return 0;
+        };
+        let mut state = agent.state();
+        if delivers { state.claim_delivery() } else { state.delivery.0 }
+    }
+
+    /// The idle agent to evict, of `user` only when given: lowest priority
+    /// first, then by policy. Connected and pinned agents are never candidates.
+    fn eviction_candidate(&self, user: Option<&str>) -> Option<String> {
+        let eviction = &self.config.eviction;
+        self.agents
+            .iter()
+            .filter(|(token, _)| !eviction.pinned.contains(token))
+            .filter(|(token, _)| user.is_none_or(|user| self.config.users.owner(token) == Some(user)))
+            .filter_map(|(token, agent)| {
+                let state = agent.state();
+                if state.connected {
+                    return None;
+                }
+                let priority = eviction.priorities.get(token).copied().unwrap_or(0);
+                let uses = match eviction.policy {
+                    EvictionPolicy::Lru => 0,
+                    EvictionPolicy::Lfu => state.connections,
+                };
+                Some(((priority, uses, state.disconnected_at), token.clone()))
+            })
+            .min()
+            .map(|(_, token)| token)
+    }
+
+    /// Tell the evicted agent's device, which is disconnected, via push.
+    fn notify_evicted(&self, agent: &PooledAgent) {
+        let Some(push_relay) = self.push_relay.clone() else {
+            return;
+        };
+        let agent_name = Arc::clone(&agent.agent_name);
+        tokio::spawn(async move {
+            let name = agent_name.read().await.clone();
+            if let Err(e) = push_relay.notify_evicted(&name).await {
+                warn!("Eviction push notification failed: {}", e);
+            }
+        });
     }
 
-    /// Spawn a new agent process and set up I/O channels
+    /// Pop a live warm agent, discarding any that died while waiting or were
+    /// spawned from an older agent.
+    async fn take_warm_agent(&mut self) -> Option<PooledAgent> {
+        while let Some(mut agent) = self.warm.pop() {
+            self.warm_needed.notify_one();
+            if !agent.is_alive() {
+                info!("Warm agent process died, discarding");
+            } else if self.is_stale(&agent) {
+                info!("🔄 Warm agent was spawned from an older agent, discarding");
+                agent.kill().await;
+            } else {
+                return Some(agent);
+            }
+        }
+        None
+    }
+
+    /// Whether `agent` was spawned from another binary or `agent_version`
+    /// than its command would run now.
+    fn is_stale(&self, agent: &AgentSlot) -> bool {
+        let current = agent_fingerprint(&agent.agent_command, self.config.agent_version.as_deref());
+        matches!((current, &agent.fingerprint), (Some(current), Some(spawned)) if current != *spawned)
+    }
+
+    /// Hand a warm agent to `token`. Its cached `initialize` response lets the
+    /// bridge answer the client's `initialize` without a round trip.
+    fn assign_agent(
+        &mut self,
+        token: &str,
+        agent: PooledAgent,
+    ) -> AgentConnection {
+        if let Ok(mut label) = agent.transcript_session.write() {
+            *label = TranscriptSink::session_label(token);
+        }
+        agent.set_limits(self.config.limits_for(token, &agent.agent_command));
+        let tx = agent.ws_to_agent_tx.clone();
+        let (rx, subscribed_through) = agent.output.subscribe();
+        let cached_init = {
+            let mut state = agent.state();
+            state.connected = true;
+            state.connections += 1;
+            state.disconnected_at = None;
+            state.subscribed_through = subscribed_through;
+            state.cached_init_response.clone()
+        };
+        let output = agent.output.clone();
+        self.agents.insert(token.to_string(), agent);
+        (tx, rx, Vec::new(), false, cached_init, None, output)
+    }
+
+    /// Spawn a new agent process for `token` and register it in the pool
     async fn spawn_agent(
         &mut self,
         token: &str,
         agent_command: &str,
-    ) -> Result<(mpsc::Sender<String>, broadcast::Receiver<String>, Vec<String>, bool, Option<String>, Option<String>, broadcast::Sender<String>)> {
+    ) -> Result<AgentConnection> {
+        let limits = self.config.limits_for(token, agent_command);
+        let working_dir = match self.worktrees.clone() {
+            Some(config) => self.session_worktree(token, &config).await?.agent_dir(),
+            None => self.working_dir_for(token).to_path_buf(),
+        };
+        agent_hooks::pre_spawn(agent_hooks::for_agent(&self.hooks, agent_command), agent_command, &working_dir).await?;
+        let (pooled, agent_to_ws_rx) = self.spawn_process(TranscriptSink::session_label(token), agent_command, limits, &working_dir)?;
+        let ws_to_agent_tx = pooled.ws_to_agent_tx.clone();
+        let output = pooled.output.clone();
+        self.agents.insert(token.to_string(), pooled);
+
+        Ok((ws_to_agent_tx, agent_to_ws_rx, Vec::new(), false, None, None, output))
+    }
+
+    /// Spawn an agent process and set up its I/O channels, without adding it to the pool
+    fn spawn_process(
+        &self,
+        transcript_label: String,
+        agent_command: &str,
+        limits: AgentLimits,
+        working_dir: &Path,
+    ) -> Result<(PooledAgent, broadcast::Receiver<String>)> {
+        self.allowlist.check(agent_command)?;
         let parts: Vec<&str> = agent_command.split_whitespace().collect();
         if parts.is_empty() {
-            anyhow::bail!("Empty agent command");
+            return Err(BridgeError::config("Empty agent command"));
         }
 
         let command = parts[0];
         let args = &parts[1..];
 
-        info!("🚀 Spawning pooled agent: {} {:?} (cwd: {})", command, args, self.working_dir.display());
+        let sandbox = sandbox::for_agent(&self.sandboxes, agent_command);
+        info!(
+            "🚀 Spawning pooled agent: {} {:?} (cwd: {}){}",
+            command,
+            args,
+            working_dir.display(),
+            if sandbox.is_some() { " in a sandbox" } else { "" }
+        );
 
-        let mut child = Command::new(command)
-            .args(args)
-            .current_dir(&self.working_dir)
+        let mut child = sandbox::command(sandbox, command, args, working_dir)?
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .kill_on_drop(false)
             .spawn()
-            .context(format!("Failed to spawn agent command: {}", agent_command))?;
+            .map_err(|e| {
+                let error = match e.kind() {
+                    std::io::ErrorKind::NotFound => BridgeError::dependency_missing(command, e),
+                    _ => BridgeError::Io { message: format!("Failed to spawn agent command: {}", agent_command), source: Some(e.into()) },
+                };
+                if let Some(ref failures) = self.spawn_failures {
+                    let _ = failures.try_send(error.to_string());
+                }
+                error
+            })?;
 
-        let stdin = child.stdin.take().context("Failed to open agent stdin")?;
-        let stdout = child.stdout.take().context("Failed to open agent stdout")?;
-        let stderr = child.stderr.take().context("Failed to open agent stderr")?;
+        let stdin = child.stdin.take().io_err("Failed to open agent stdin")?;
+        let stdout = child.stdout.take().io_err("Failed to open agent stdout")?;
+        let stderr = child.stderr.take().io_err("Failed to open agent stderr")?;
 
         // Channel: WebSocket messages to agent stdin (mpsc)
         let (ws_to_agent_tx, mut ws_to_agent_rx) = mpsc::channel::<String>(100);
 
-        // Channel: agent stdout to WebSocket (broadcast, supports reconnection)
-        let (agent_to_ws_tx, agent_to_ws_rx) = broadcast::channel::<String>(256);
+        // Channel: agent stdout to WebSocket (sequenced broadcast, supports reconnection)
+        let (output, agent_to_ws_rx) = AgentOutput::new(limits.max_buffer_size);
+
+        let transcript_session = Arc::new(std::sync::RwLock::new(transcript_label));
 
         // Background task: forward ws_to_agent_rx to agent stdin
         let mut stdin_writer = stdin;
+        let transcript_for_stdin = self.transcripts.clone();
+        let session_for_stdin = Arc::clone(&transcript_session);
+        let messages_in = Arc::new(AtomicU64::new(0));
+        let messages_in_for_stdin = Arc::clone(&messages_in);
+        let close_stdin = Arc::new(Notify::new());
+        let close_for_stdin = Arc::clone(&close_stdin);
+        let crash_log = Arc::new(CrashLog::default());
+        let crash_log_for_stdin = Arc::clone(&crash_log);
+        let stopping = Arc::new(AtomicBool::new(false));
+        let stopping_for_stdin = Arc::clone(&stopping);
         tokio::spawn(async move {
-            while let Some(msg) = ws_to_agent_rx.recv().await {
+            loop {
+                let msg = tokio::select! {
+                    msg = ws_to_agent_rx.recv() => match msg {
+                        Some(msg) => msg,
+                        // The agent was dropped from the pool: it reads EOF
+                        // and is expected to exit.
+                        None => {
+                            stopping_for_stdin.store(true, Ordering::Relaxed);
+                            break;
+                        }
+                    },
+                    // Dropping the writer closes stdin: the agent reads EOF.
+                    _ = close_for_stdin.notified() => break,
+                };
+                if probe_seq(&msg).is_none() {
+                    messages_in_for_stdin.fetch_add(1, Ordering::Relaxed);
+                    crash_log_for_stdin.record_stdin(&msg);
+                    if let Some(ref sink) = transcript_for_stdin {
+                        record_line(sink, &session_for_stdin, Direction::Client, &msg);
+                    }
+                }
                 if let Err(e) = stdin_writer.write_all(msg.as_bytes()).await {
                     error!("Failed to write to pooled agent stdin: {}", e);
                     break;
@@ -254,26 +1503,66 @@ impl AgentPool {
         });
 
         // Background task: forward agent stdout to broadcast channel
-        let stdout_tx = agent_to_ws_tx.clone();
+        let stdout_tx = output.clone();
         let stdout_reader = BufReader::new(stdout);
         let push_relay_for_stdout: Option<Arc<PushRelayClient>> = self.push_relay.clone();
         let agent_name_shared = Arc::new(tokio::sync::RwLock::new("Agent".to_string()));
         let agent_name_for_stdout = Arc::clone(&agent_name_shared);
-        let overflow_buffer = Arc::new(tokio::sync::Mutex::new(Vec::<String>::new()));
+        let overflow_buffer = Arc::new(std::sync::Mutex::new(MessageBuffer::new(limits)));
         let overflow_for_stdout = Arc::clone(&overflow_buffer);
-        let max_buffer = self.config.max_buffer_size;
         let buffer_enabled = self.config.buffer_messages;
+        let transcript_for_stdout = self.transcripts.clone();
+        let session_for_stdout = Arc::clone(&transcript_session);
+        let (probe_tx, probe_answered) = watch::channel(0u64);
+        let max_line_bytes = self.config.max_line_bytes;
+        let hooks = agent_hooks::for_agent(&self.hooks, agent_command).cloned();
+        let hooks_for_stdout = hooks.clone();
+        let hook_target = (agent_command.to_string(), working_dir.to_path_buf());
         tokio::spawn(async move {
-            let mut lines = stdout_reader.lines();
+            let mut lines = LineReader::new(stdout_reader, max_line_bytes);
+            // The session the agent last spoke about, which pushes open
+            let mut last_session: Option<String> = None;
             while let Ok(Some(line)) = lines.next_line().await {
+                let line = match line {
+                    Line::Complete(line) => line,
+                    Line::Truncated { prefix, len } => {
+                        warn!(
+                            "Pooled agent sent a {}-byte line, over [limits] max_line_bytes ({}); dropped: {}",
+                            len,
+                            max_line_bytes,
+                            prefix.chars().take(200).collect::<String>()
+                        );
+                        truncation_message(&prefix, len, max_line_bytes)
+                    }
+                };
                 debug!(
                     "Pooled agent stdout ({} bytes): {}",
                     line.len(),
                     line.chars().take(200).collect::<String>()
                 );
+                if let Some(seq) = probe_seq(&line) {
+                    probe_tx.send_if_modified(|answered| {
+                        let newer = seq > *answered;
+                        *answered = (*answered).max(seq);
+                        newer
+                    });
+                    continue;
+                }
+                if let Some(ref sink) = transcript_for_stdout {
+                    record_line(sink, &session_for_stdout, Direction::Agent, &line);
+                }
+                if line.contains("\"sessionId\"") {
+                    if let Some(session) = serde_json::from_str::<serde_json::Value>(&line).ok().as_ref().and_then(crate::push::session_of) {
+                        last_session = Some(session.to_string());
+                    }
+                }
+                if crate::push::is_permission_request(&line) {
+                    let (ref command, ref dir) = hook_target;
+                    agent_hooks::spawn(HookKind::PermissionRequest, hooks_for_stdout.as_ref(), command, dir, Some(("AGENT_REQUEST", line.clone())));
+                }
 
                 // Attempt to send to broadcast channel
-                match stdout_tx.send(line) {
+                match stdout_tx.publish(line) {
                     Ok(receiver_count) => {
                         // Message was sent successfully; receiver_count = number of active WS clients
                         info!("[push-dbg] agent stdout → broadcast OK ({} receiver(s) connected)", receiver_count);
@@ -281,16 +1570,18 @@ impl AgentPool {
                     Err(e) => {
                         // No receivers = no WebSocket client connected; buffer the message and push
                         let msg = e.0;
+                        let permission = crate::push::permission_request(&msg);
+                        let stop_reason = crate::push::stop_reason(&msg);
                         if buffer_enabled {
-                            let mut buf = overflow_for_stdout.lock().await;
-                            if buf.len() < max_buffer {
-                                info!("[push-dbg] 0 receivers — buffering message #{} ({}B): {}",
-                                    buf.len() + 1,
-                                    msg.len(),
-                                    msg.chars().take(120).collect::<String>());
-                                buf.push(msg);
-                            } else {
-                                warn!("[push-dbg] overflow buffer full ({} messages) — dropping agent message", buf.len());
+                            let mut buf = overflow_for_stdout.lock().unwrap_or_else(|e| e.into_inner());
+                            info!("[push-dbg] 0 receivers — buffering message #{} ({}B): {}",
+                                buf.len() + 1,
+                                msg.len(),
+                                msg.chars().take(120).collect::<String>());
+                            let dropped = buf.push(msg);
+                            if dropped > 0 {
+                                debug!("[push-dbg] overflow buffer over budget ({} messages, {}B) — dropped {} message(s)",
+                                    buf.len(), buf.bytes(), dropped);
                             }
                         } else {
                             info!("[push-dbg] 0 receivers — buffering disabled, message dropped");
@@ -298,7 +1589,14 @@ impl AgentPool {
                         if let Some(ref push_relay) = push_relay_for_stdout {
                             let name = agent_name_for_stdout.read().await.clone();
                             info!("[push-dbg] triggering push notification (overflow-buffer path) for '{}'", name);
-                            match push_relay.notify(&name).await {
+                            let sent = if let Some(ref request) = permission {
+                                push_relay.notify_permission_request(&name, Some(request)).await
+                            } else if let Some(reason) = stop_reason {
+                                push_relay.notify_turn_ended(&name, last_session.as_deref(), &reason).await
+                            } else {
+                                push_relay.notify(&name, last_session.as_deref()).await
+                            };
+                            match sent {
                                 Ok(sent) => info!("[push-dbg] push relay notify: sent={}", sent),
                                 Err(e) => warn!("[push-dbg] push relay notify failed: {}", e),
                             }
@@ -313,63 +1611,240 @@ impl AgentPool {
 
         // Background task: log stderr
         let stderr_reader = BufReader::new(stderr);
+        let crash_log_for_stderr = Arc::clone(&crash_log);
         tokio::spawn(async move {
-            let mut lines = stderr_reader.lines();
+            let mut lines = LineReader::new(stderr_reader, max_line_bytes);
             while let Ok(Some(line)) = lines.next_line().await {
-                warn!("Pooled agent stderr: {}", line);
+                match line {
+                    Line::Complete(line) => {
+                        warn!("Pooled agent stderr: {}", line);
+                        crash_log_for_stderr.record_stderr(&line);
+                    }
+                    Line::Truncated { prefix, len } => {
+                        warn!("Pooled agent stderr ({} bytes, cut): {}", len, prefix.chars().take(200).collect::<String>());
+                        crash_log_for_stderr.record_stderr(&prefix);
+                    }
+                }
             }
             debug!("Pooled agent stderr reader task ended");
         });
 
-        let pooled = PooledAgent {
-            process: child,
-            ws_to_agent_tx: ws_to_agent_tx.clone(),
-            agent_to_ws_tx,
-            connected: true,
-            disconnected_at: None,
-            message_buffer: Vec::new(),
+        let slot = AgentSlot {
+            ws_to_agent_tx,
+            output,
             overflow_buffer,
-            cached_init_response: None,
-            cached_session_response: None,
+            state: std::sync::Mutex::new(AgentState {
+                connected: true,
+                connections: 1,
+                limits,
+                message_buffer: MessageBuffer::new(limits),
+                ..AgentState::default()
+            }),
             agent_command: agent_command.to_string(),
+            fingerprint: agent_fingerprint(agent_command, self.config.agent_version.as_deref()),
+            working_dir: working_dir.to_path_buf(),
             agent_name: agent_name_shared,
+            transcript_session,
+            buffer_messages: self.config.buffer_messages,
+            messages_in,
+            probe_sent: AtomicU64::new(0),
+            probe_answered,
+            bridge_prompts: tokio::sync::Mutex::new(()),
         };
+        // Background task: own the process, and report it if it exits
+        // without being stopped
+        let (exit_tx, exit) = watch::channel(None);
+        let kill = Arc::new(Notify::new());
+        let kill_for_waiter = Arc::clone(&kill);
+        let stopping_for_waiter = Arc::clone(&stopping);
+        let crash = (
+            self.crash_reports.clone(),
+            slot.output.clone(),
+            Arc::clone(&slot.agent_name),
+            agent_command.to_string(),
+            working_dir.to_path_buf(),
+            Instant::now(),
+        );
+        tokio::spawn(async move {
+            let status = tokio::select! {
+                status = child.wait() => status,
+                _ = kill_for_waiter.notified() => {
+                    if let Err(e) = child.kill().await {
+                        warn!("Failed to kill agent process: {}", e);
+                    }
+                    child.wait().await
+                }
+            };
+            let status = status.unwrap_or_else(|e| {
+                warn!("Failed to wait for agent process: {}", e);
+                ExitStatus::default()
+            });
+            exit_tx.send_replace(Some(status));
+            let (reports, output, agent_name, agent_command, working_dir, started) = crash;
+            let exit_status = status.code().map_or_else(|| status.to_string(), |code| code.to_string());
+            agent_hooks::spawn(HookKind::PostExit, hooks.as_ref(), &agent_command, &working_dir, Some(("AGENT_EXIT_STATUS", exit_status)));
+            if stopping_for_waiter.load(Ordering::Relaxed) {
+                return;
+            }
+            let report = CrashReport::new(&crash_log, status, &agent_command, &agent_name.read().await, &working_dir, started.elapsed());
+            error!("💥 Pooled agent exited unexpectedly ({})", report.exit_description());
+            let saved = match reports {
+                Some(reports) => match reports.save(&report) {
+                    Ok(()) => {
+                        info!("💥 Crash report {} saved", report.id);
+                        true
+                    }
+                    Err(e) => {
+                        warn!("Failed to save crash report: {:#}", e);
+                        false
+                    }
+                },
+                None => false,
+            };
+            let _ = output.publish(report.notification(saved));
+        });
+        let pooled = PooledAgent { slot: Arc::new(slot), close_stdin, exit, kill, stopping };
 
-        self.agents.insert(token.to_string(), pooled);
+        Ok((pooled, agent_to_ws_rx))
+    }
+
+    /// The configured workspaces.
+    pub fn workspaces(&self) -> &[WorkspaceConfig] {
+        &self.workspaces
+    }
+
+    /// The workspace `token` selected, if any.
+    pub fn selected_workspace(&self, token: &str) -> Option<&WorkspaceConfig> {
+        let name = self.selected_workspaces.get(token)?;
+        self.workspaces.iter().find(|w| &w.name == name)
+    }
 
-        let broadcast_tx = self.agents.get(token).unwrap().agent_to_ws_tx.clone();
+    /// Directory the agent for `token` is spawned in.
+    pub fn working_dir_for(&self, token: &str) -> &Path {
+        self.selected_workspace(token).map_or(&self.working_dir, |w| &w.path)
+    }
 
-        Ok((ws_to_agent_tx, agent_to_ws_rx, Vec::new(), false, None, None, broadcast_tx))
+    /// Run the agent for `token` in the workspace called `name` from now on.
+    /// An agent already running elsewhere is stopped, since a process can't
+    /// change directory; its session is lost.
+    pub async fn select_workspace(&mut self, token: &str, name: &str) -> Result<WorkspaceSelection> {
+        let workspace = self
+            .workspaces
+            .iter()
+            .find(|w| w.name == name)
+            .cloned()
+            .config_err(format!("Unknown workspace '{}'", name))?;
+        if !workspace.path.is_dir() {
+            return Err(BridgeError::config(format!("Workspace '{}' directory {} doesn't exist", name, workspace.path.display())));
+        }
+        self.selected_workspaces.insert(token.to_string(), workspace.name.clone());
+
+        // With worktrees the agent runs in a worktree of the directory it was started for
+        let started_for = self.session_worktrees.get(token).map(|w| w.source.as_path());
+        let restarted = match self.agents.get(token) {
+            Some(agent) if started_for.unwrap_or(&agent.working_dir) != workspace.path => {
+                if let Some(mut agent) = self.agents.remove(token) {
+                    info!("📂 Stopping agent in {} to switch to workspace '{}'", agent.working_dir.display(), workspace.name);
+                    agent.kill().await;
+                }
+                true
+            }
+            _ => false,
+        };
+        Ok(WorkspaceSelection { workspace, restarted })
+    }
+
+    /// Directory sessions of `token`'s agent should use, when it isn't the
+    /// bridge's own: its worktree or selected workspace.
+    pub fn session_dir(&self, token: &str) -> Option<PathBuf> {
+        match self.session_worktrees.get(token) {
+            Some(worktree) => Some(worktree.agent_dir()),
+            None => self.selected_workspace(token).map(|w| w.path.clone()),
+        }
+    }
+
+    /// Whether agents get worktrees of their own.
+    pub fn worktrees_enabled(&self) -> bool {
+        self.worktrees.is_some()
+    }
+
+    /// The worktree of `token`'s agent, if one was created.
+    pub fn worktree(&self, token: &str) -> Option<&Worktree> {
+        self.session_worktrees.get(token)
+    }
+
+    /// The worktree for `token`'s agent: the existing one, or a new one when
+    /// there is none yet or the token has since switched workspace.
+    async fn session_worktree(&mut self, token: &str, config: &WorktreeConfig) -> Result<&Worktree> {
+        let source = self.working_dir_for(token).to_path_buf();
+        if self.session_worktrees.get(token).is_some_and(|w| w.source != source || !w.path.is_dir()) {
+            if let Some(old) = self.session_worktrees.remove(token) {
+                warn!("🌿 No longer using worktree {} (branch {}) for this device", old.path.display(), old.branch);
+            }
+        }
+        if !self.session_worktrees.contains_key(token) {
+            let worktree = Worktree::create(&source, config).await.io_err("Failed to create worktree")?;
+            self.session_worktrees.insert(token.to_string(), worktree);
+        }
+        Ok(&self.session_worktrees[token])
+    }
+
+    /// Stop `token`'s agent and finish its worktree (see [`Worktree::finish`]).
+    /// Holds the token's spawn lock but not the pool lock while git runs, so
+    /// a slow push only delays this token's connections.
+    pub async fn finish_session(pool: &Arc<RwLock<AgentPool>>, token: &str, action: FinishAction, message: &str) -> Result<FinishOutcome> {
+        let lock = pool.read().await.spawn_lock(token);
+        let _guard = lock.lock().await;
+        let (worktree, push_remote) = {
+            let mut pool = pool.write().await;
+            let config = pool.worktrees.clone().config_err("Worktrees are not enabled")?;
+            let worktree = pool.session_worktrees.get(token).cloned().protocol_err("This device has no worktree")?;
+            if let Some(mut agent) = pool.agents.remove(token) {
+                info!("🌿 Stopping agent to finish worktree {}", worktree.path.display());
+                agent.kill().await;
+            }
+            (worktree, config.push_remote)
+        };
+        let outcome = worktree.finish(action, message, push_remote.as_deref()).await?;
+        pool.write().await.session_worktrees.remove(token);
+        Ok(outcome)
+    }
+
+    /// Stop `token`'s agent for `bridge/endSession` (see [`PooledAgent::end`]).
+    /// It leaves the pool first, so the token's next connection spawns a new
+    /// one while this one is still shutting down.
+    pub async fn end_session(pool: &Arc<RwLock<AgentPool>>, token: &str) -> Result<SessionEnd> {
+        let lock = pool.read().await.spawn_lock(token);
+        let guard = lock.lock().await;
+        let (mut agent, config) = {
+            let mut pool = pool.write().await;
+            let agent = pool.agents.remove(token).protocol_err("This device has no running agent")?;
+            (agent, pool.end_session.clone())
+        };
+        drop(guard);
+        info!("👋 Ending session of agent for token {}...", &token[..8.min(token.len())]);
+        Ok(agent.end(&config).await)
+    }
+
+    /// Shared handle to the agent for `token`, for per-connection state
+    /// updates that don't need the pool lock.
+    pub fn slot(&self, token: &str) -> Option<Arc<AgentSlot>> {
+        self.agents.get(token).map(PooledAgent::slot)
     }
 
     /// Mark a client as disconnected. The agent stays alive for idle_timeout.
-    pub fn mark_disconnected(&mut self, token: &str) {
-        if let Some(agent) = self.agents.get_mut(token) {
-            info!("Client disconnected, agent entering idle state (keep-alive)");
-            agent.connected = false;
-            agent.disconnected_at = Some(Instant::now());
+    pub fn mark_disconnected(&self, token: &str) {
+        if let Some(agent) = self.agents.get(token) {
+            agent.mark_disconnected();
         }
     }
 
     /// Cache the agent's `initialize` response so reconnections can skip re-initialization.
     /// Also extracts and stores the agent name from the response.
-    pub fn cache_init_response(&mut self, token: &str, response: String) {
-        if let Some(agent) = self.agents.get_mut(token) {
+    pub fn cache_init_response(&self, token: &str, response: String) {
+        if let Some(agent) = self.agents.get(token) {
             info!("Cached initialize response for agent (keep-alive)");
-            // Extract agent name from agentInfo.name or serverInfo.name
-            if let Ok(v) = serde_json::from_str::<serde_json::Value>(&response) {
-                let name = v["result"]["agentInfo"]["name"].as_str()
-                    .or_else(|| v["result"]["serverInfo"]["name"].as_str());
-                if let Some(name) = name {
-                    let agent_name = Arc::clone(&agent.agent_name);
-                    let name_owned = name.to_string();
-                    tokio::spawn(async move {
-                        *agent_name.write().await = name_owned;
-                    });
-                    info!("Agent name set to '{}'", name);
-                }
-            }
-            agent.cached_init_response = Some(response);
+            agent.cache_init_response(response);
         }
     }
 
@@ -381,25 +1856,36 @@ impl AgentPool {
     }
 
     /// Cache the agent's `createSession` response so reconnections reuse the same session ID
-    pub fn cache_session_response(&mut self, token: &str, response: String) {
-        if let Some(agent) = self.agents.get_mut(token) {
-            info!("Cached createSession response for agent (keep-alive)");
-            agent.cached_session_response = Some(response);
+    pub fn cache_session_response(&self, token: &str, response: String) {
+        if let Some(agent) = self.agents.get(token) {
+            agent.cache_session_response(response);
         }
     }
 
     /// Clear the cached session response (e.g., when agent reports "Session not found")
-    pub fn clear_session_response(&mut self, token: &str) {
-        if let Some(agent) = self.agents.get_mut(token) {
-            if agent.cached_session_response.is_some() {
-                info!("Cleared cached session response for agent (session invalidated)");
-                agent.cached_session_response = None;
-            }
+    pub fn clear_session_response(&self, token: &str) {
+        if let Some(agent) = self.agents.get(token) {
+            agent.clear_session_response();
         }
     }
 
+    /// Sequence number of the last agent message that the receiver returned by
+    /// the latest `get_or_spawn` for this token will not see. Messages it
+    /// receives are numbered consecutively from this value + 1.
+    pub fn subscribed_through(&self, token: &str) -> u64 {
+        self.agents.get(token).map(|a| a.subscribed_through()).unwrap_or(0)
+    }
+
+    /// Agent messages after sequence number `after`, for answering `bridge/resume`.
+    /// Returns `None` if no agent exists for the token.
+    pub fn replay_since(&self, token: &str, after: u64) -> Option<Replay> {
+        let agent = self.agents.get(token)?;
+        let log = agent.output.log();
+        let (messages, complete) = log.since(after);
+        Some(Replay { messages, complete, last_seq: log.last_seq() })
+    }
+
     /// Remove and kill an agent
-    #[allow(dead_code)]
     pub async fn remove_agent(&mut self, token: &str) {
         if let Some(mut agent) = self.agents.remove(token) {
             agent.kill().await;
@@ -408,7 +1894,6 @@ impl AgentPool {
 
     /// Check for idle agents that have exceeded the timeout and kill them
     pub async fn reap_idle_agents(&mut self) {
-        let timeout = self.config.idle_timeout;
         let mut to_remove = Vec::new();
 
         for (token, agent) in self.agents.iter_mut() {
@@ -418,13 +1903,19 @@ impl AgentPool {
                 continue;
             }
 
-            if !agent.connected {
-                if let Some(disconnected_at) = agent.disconnected_at {
-                    if disconnected_at.elapsed() > timeout {
+            let state = agent.state();
+            if !state.connected {
+                if let Some(disconnected_at) = state.disconnected_at {
+                    let idle_timeout = match self.power_saving {
+                        Some(saving) => state.limits.idle_timeout.min(saving.idle_timeout),
+                        None => state.limits.idle_timeout,
+                    };
+                    if disconnected_at.elapsed() > idle_timeout {
                         info!(
-                            "Agent for token {}... idle for {:?}, terminating",
+                            "Agent for token {}... idle for {:?}, terminating{}",
                             &token[..8.min(token.len())],
-                            disconnected_at.elapsed()
+                            disconnected_at.elapsed(),
+                            if self.power_saving.is_some() { " (power saving)" } else { "" }
                         );
                         to_remove.push(token.clone());
                     }
@@ -432,26 +1923,169 @@ impl AgentPool {
             }
         }
 
-        for token in to_remove {
-            if let Some(mut agent) = self.agents.remove(&token) {
+        for token in to_remove {
+            if let Some(mut agent) = self.agents.remove(&token) {
+                agent.kill().await;
+            }
+        }
+
+        // Drop spawn locks for tokens without an agent that nobody is waiting on
+        let agents = &self.agents;
+        self.spawn_locks
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|token, lock| agents.contains_key(token) || Arc::strong_count(lock) > 1);
+
+        let warm_before = self.warm.len();
+        self.warm.retain_mut(|agent| agent.is_alive());
+        if self.warm.len() < warm_before {
+            info!("{} warm agent(s) died, backfilling", warm_before - self.warm.len());
+            self.warm_needed.notify_one();
+        }
+    }
+
+    /// Probe every idle agent (including warm ones) and kill those that don't
+    /// answer within the timeout, so the next connection for their token gets
+    /// a fresh process instead of a hung one. Returns how many were replaced.
+    pub async fn check_health(pool: &Arc<RwLock<AgentPool>>, check: &HealthCheck) -> usize {
+        let idle: Vec<Arc<AgentSlot>> = {
+            let pool = pool.read().await;
+            pool.agents
+                .values()
+                .chain(pool.warm.iter())
+                .filter(|a| !a.state().connected)
+                .map(PooledAgent::slot)
+                .collect()
+        };
+        if idle.is_empty() {
+            return 0;
+        }
+
+        let answered = futures_util::future::join_all(idle.iter().map(|slot| slot.probe(&check.method, check.timeout))).await;
+        let hung: Vec<Arc<AgentSlot>> = idle
+            .into_iter()
+            .zip(answered)
+            .filter_map(|(slot, ok)| (!ok).then_some(slot))
+            .collect();
+        if hung.is_empty() {
+            return 0;
+        }
+
+        // A client may have connected while the probe was in flight; leave those alone.
+        let is_hung = |agent: &PooledAgent| !agent.state().connected && hung.iter().any(|s| Arc::ptr_eq(s, &agent.slot));
+        let mut pool = pool.write().await;
+        let tokens: Vec<String> = pool.agents.iter().filter(|(_, a)| is_hung(a)).map(|(t, _)| t.clone()).collect();
+        let mut removed: Vec<PooledAgent> = tokens.iter().filter_map(|t| pool.agents.remove(t)).collect();
+        for token in &tokens {
+            warn!("Agent for token {}... did not answer `{}` within {:?}, killing it", &token[..8.min(token.len())], check.method, check.timeout);
+        }
+        let (hung_warm, warm): (Vec<_>, Vec<_>) = std::mem::take(&mut pool.warm).into_iter().partition(|a| is_hung(a));
+        pool.warm = warm;
+        if !hung_warm.is_empty() {
+            warn!("{} warm agent(s) did not answer `{}`, backfilling", hung_warm.len(), check.method);
+            pool.warm_needed.notify_one();
+        }
+        removed.extend(hung_warm);
+
+        let count = removed.len();
+        for mut agent in removed {
+            agent.kill().await;
+        }
+        count
+    }
+
+    /// The agent limit, lowered while power saving.
+    fn max_agents(&self) -> usize {
+        match self.power_saving {
+            Some(saving) => self.config.max_agents.min(saving.max_agents),
+            None => self.config.max_agents,
+        }
+    }
+
+    /// Warm agents to keep, none while power saving pauses the warm pool.
+    fn warm_agents(&self) -> usize {
+        match self.power_saving {
+            Some(saving) if saving.pause_warm_pool => 0,
+            _ => self.config.warm_agents,
+        }
+    }
+
+    /// Switch to the `[power]` limits, or back to normal with `None`. On
+    /// switching, warm agents are stopped when the warm pool pauses, idle
+    /// agents over the lowered limit are evicted, and idle agents past the
+    /// shorter timeout are reaped right away.
+    pub async fn set_power_saving(&mut self, saving: Option<PowerSaving>) {
+        self.power_saving = saving;
+        if saving.is_none() {
+            self.warm_needed.notify_one();
+            return;
+        }
+        let excess_warm = self.warm.len().saturating_sub(self.warm_agents());
+        for mut agent in self.warm.drain(..excess_warm) {
+            agent.kill().await;
+        }
+        while self.agents.len() > self.max_agents() {
+            let Some(key) = self.eviction_candidate(None) else { break };
+            info!("Evicting idle agent for token {}... (power saving)", &key[..8.min(key.len())]);
+            if let Some(mut agent) = self.agents.remove(&key) {
                 agent.kill().await;
+                self.notify_evicted(&agent);
             }
         }
+        self.reap_idle_agents().await;
     }
 
     /// Get pool statistics
     pub fn stats(&self) -> PoolStats {
         let total = self.agents.len();
-        let connected = self.agents.values().filter(|a| a.connected).count();
+        let connected = self.agents.values().filter(|a| a.state().connected).count();
         let idle = total - connected;
         PoolStats {
             total,
             connected,
             idle,
-            max: self.config.max_agents,
+            warm: self.warm.len(),
+            max: self.max_agents(),
+            power_saving: self.power_saving.is_some(),
         }
     }
 
+    /// Every pooled agent, by id, for `bridge top`.
+    pub fn summaries(&self) -> Vec<AgentSummary> {
+        let mut summaries: Vec<AgentSummary> = self
+            .agents
+            .iter()
+            .map(|(token, agent)| {
+                let (buffered, buffered_bytes) = {
+                    let overflow = agent.overflow();
+                    (overflow.len(), overflow.bytes())
+                };
+                let state = agent.state();
+                AgentSummary {
+                    id: TranscriptSink::session_label(token),
+                    user: self.config.users.owner(token).map(str::to_string),
+                    // Written once by the initialize handshake; a busy lock just shows the default
+                    name: agent.agent_name.try_read().map(|n| n.clone()).unwrap_or_default(),
+                    command: agent.agent_command.clone(),
+                    connected: state.connected,
+                    connections: state.connections,
+                    idle_secs: state.disconnected_at.filter(|_| !state.connected).map(|at| at.elapsed().as_secs()),
+                    buffered: buffered + state.message_buffer.len(),
+                    buffered_bytes: buffered_bytes + state.message_buffer.bytes(),
+                    messages_in: agent.messages_in.load(Ordering::Relaxed),
+                    messages_out: agent.output.log().last_seq(),
+                }
+            })
+            .collect();
+        summaries.sort_by(|a, b| a.id.cmp(&b.id));
+        summaries
+    }
+
+    /// Token of the agent with summary id `id`, see [`summaries`](Self::summaries).
+    pub fn token_for_id(&self, id: &str) -> Option<String> {
+        self.agents.keys().find(|token| TranscriptSink::session_label(token) == id).cloned()
+    }
+
     /// Check if the pool contains an agent for the given token
     #[allow(dead_code)]
     pub fn contains(&self, token: &str) -> bool {
@@ -470,17 +2104,33 @@ impl AgentPool {
         }
     }
 
-    /// Buffer a message for a disconnected agent
-    pub fn buffer_message(&mut self, token: &str, message: String) {
-        if !self.config.buffer_messages {
-            return;
+    /// Shed memory: cut every agent's disconnect buffers (streaming chunks
+    /// first) and replay log down to `keep` messages. Returns how many
+    /// messages were dropped.
+    pub async fn trim_buffers(&self, keep: usize) -> usize {
+        let mut dropped = 0;
+        for agent in self.agents.values() {
+            dropped += agent.overflow().trim(keep);
+            dropped += agent.state().message_buffer.trim(keep);
+            dropped += agent.output.trim(keep);
         }
-        if let Some(agent) = self.agents.get_mut(token) {
-            if agent.message_buffer.len() < self.config.max_buffer_size {
-                agent.message_buffer.push(message);
-            } else {
-                warn!("Message buffer full for agent, dropping message");
-            }
+        dropped
+    }
+
+    /// Send `message` to the clients of every agent. Clients that are
+    /// disconnected get it from the replay log when they resume. Returns how
+    /// many agents have a client connected right now.
+    pub fn notify_all(&self, message: &str) -> usize {
+        self.agents
+            .values()
+            .filter(|agent| agent.output.publish(message.to_string()).is_ok())
+            .count()
+    }
+
+    /// Buffer a message for a disconnected agent
+    pub fn buffer_message(&self, token: &str, message: String) {
+        if let Some(agent) = self.agents.get(token) {
+            agent.buffer_message(message);
         }
     }
 
@@ -494,28 +2144,186 @@ impl AgentPool {
                 agent.kill().await;
             }
         }
+        for mut agent in self.warm.drain(..) {
+            agent.kill().await;
+        }
     }
 }
 
 /// Pool statistics
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct PoolStats {
     pub total: usize,
     pub connected: usize,
     pub idle: usize,
+    /// Pre-spawned agents waiting for a token (not counted in `total`)
+    pub warm: usize,
     pub max: usize,
+    /// Whether `max` is the `[power]` limit
+    pub power_saving: bool,
+}
+
+/// One pooled agent, see [`AgentPool::summaries`].
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentSummary {
+    /// Stable label derived from the agent's token (the transcript session
+    /// label); the token itself is never shown
+    pub id: String,
+    /// The `[[users]]` namespace the agent belongs to (`None` for the owner's)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Name from the agent's `initialize` response
+    pub name: String,
+    pub command: String,
+    pub connected: bool,
+    /// Client connections served so far
+    pub connections: u64,
+    /// Seconds since the client disconnected (`None` while connected)
+    pub idle_secs: Option<u64>,
+    /// Messages held for the next reconnect, and their size
+    pub buffered: usize,
+    pub buffered_bytes: usize,
+    /// Messages written to the agent's stdin so far
+    pub messages_in: u64,
+    /// Messages read from the agent's stdout so far
+    pub messages_out: u64,
 }
 
 impl std::fmt::Display for PoolStats {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "AgentPool: {}/{} agents ({} connected, {} idle)",
-            self.total, self.max, self.connected, self.idle
+            "AgentPool: {}/{} agents ({} connected, {} idle, {} warm){}",
+            self.total,
+            self.max,
+            self.connected,
+            self.idle,
+            self.warm,
+            if self.power_saving { ", power saving" } else { "" }
         )
     }
 }
 
+/// Record a transcript line under the agent's current session label.
+fn record_line(sink: &TranscriptSink, session: &std::sync::RwLock<String>, direction: Direction, line: &str) {
+    let label = session.read().map(|l| l.clone()).unwrap_or_default();
+    if !label.is_empty() {
+        sink.record(&label, direction, line);
+    }
+}
+
+/// Request id of the `initialize` the pool sends to warm agents.
+const WARM_INIT_ID: &str = "bridge-warm-init";
+
+/// How long a warm agent may take to answer `initialize`.
+const WARM_INIT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Delay before retrying after a warm agent failed to start.
+const WARM_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Spawn an agent outside the pool and complete the ACP `initialize` handshake,
+/// so its cached response can be replayed to the first client.
+async fn prespawn_warm_agent(pool: &Arc<RwLock<AgentPool>>, agent_command: &str) -> Result<PooledAgent> {
+    let (hooks, working_dir) = {
+        let pool = pool.read().await;
+        (agent_hooks::for_agent(&pool.hooks, agent_command).cloned(), pool.working_dir.clone())
+    };
+    agent_hooks::pre_spawn(hooks.as_ref(), agent_command, &working_dir).await?;
+    let (mut agent, mut rx) = {
+        let pool = pool.read().await;
+        pool.spawn_process(String::new(), agent_command, pool.config.limits_for("", agent_command), &pool.working_dir)?
+    };
+    {
+        let mut state = agent.state();
+        state.connected = false;
+        state.connections = 0;
+    }
+
+    let request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": WARM_INIT_ID,
+        "method": "initialize",
+        "params": {
+            "protocolVersion": 1,
+            "clientCapabilities": {
+                "fs": { "readTextFile": false, "writeTextFile": false },
+                "terminal": false
+            }
+        }
+    });
+    let handshake = async {
+        agent.ws_to_agent_tx.send(request.to_string()).await.protocol_err("Agent stdin closed")?;
+        loop {
+            match rx.recv().await {
+                Ok(line) => {
+                    let is_reply = serde_json::from_str::<serde_json::Value>(&line)
+                        .map(|v| v.get("id").and_then(|id| id.as_str()) == Some(WARM_INIT_ID))
+                        .unwrap_or(false);
+                    if is_reply {
+                        return Ok(line);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return Err(BridgeError::protocol("Agent exited during initialize")),
+            }
+        }
+    };
+    let response = match tokio::time::timeout(WARM_INIT_TIMEOUT, handshake).await {
+        Ok(Ok(line)) => line,
+        Ok(Err(e)) => {
+            agent.kill().await;
+            return Err(e);
+        }
+        Err(_) => {
+            agent.kill().await;
+            return Err(BridgeError::protocol(format!("Agent did not answer initialize within {:?}", WARM_INIT_TIMEOUT)));
+        }
+    };
+    if response.contains("\"error\"") && !response.contains("\"result\"") {
+        agent.kill().await;
+        return Err(BridgeError::protocol(format!("Agent rejected initialize: {}", response.chars().take(200).collect::<String>())));
+    }
+    agent.cache_init_response(response);
+    Ok(agent)
+}
+
+/// Start the background task that keeps `warm_agents` initialized agents ready,
+/// backfilling whenever one is assigned to a token or dies.
+pub fn start_warm_pool(pool: Arc<RwLock<AgentPool>>, agent_command: String) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let notify = Arc::clone(&pool.read().await.warm_needed);
+        loop {
+            let missing = {
+                let p = pool.read().await;
+                let room = p.max_agents().saturating_sub(p.agents.len() + p.warm.len());
+                p.warm_agents().saturating_sub(p.warm.len()).min(room)
+            };
+            let mut failed = false;
+            for _ in 0..missing {
+                let started = Instant::now();
+                match prespawn_warm_agent(&pool, &agent_command).await {
+                    Ok(agent) => {
+                        info!("🔥 Warm agent ready ({:.1}s)", started.elapsed().as_secs_f64());
+                        pool.write().await.warm.push(agent);
+                    }
+                    Err(e) => {
+                        warn!("Failed to pre-spawn warm agent: {:#}", e);
+                        failed = true;
+                        break;
+                    }
+                }
+            }
+            if failed {
+                tokio::time::sleep(WARM_RETRY_DELAY).await;
+            } else {
+                notify.notified().await;
+            }
+        }
+    })
+}
+
 /// Start the background reaper task that periodically checks for idle agents
 pub fn start_reaper(pool: Arc<RwLock<AgentPool>>, check_interval: Duration) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
@@ -532,6 +2340,23 @@ pub fn start_reaper(pool: Arc<RwLock<AgentPool>>, check_interval: Duration) -> t
     })
 }
 
+/// Start the background task that probes idle agents every `check.interval`
+/// (see [`AgentPool::check_health`]).
+pub fn start_health_checker(pool: Arc<RwLock<AgentPool>>, check: HealthCheck) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(check.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let replaced = AgentPool::check_health(&pool, &check).await;
+            if replaced > 0 {
+                info!("Health check replaced {} unresponsive agent(s)", replaced);
+            }
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -542,6 +2367,14 @@ mod tests {
             max_agents: 3,
             buffer_messages: true,
             max_buffer_size: 5,
+            max_buffer_bytes: 0,
+            buffer_ttl: None,
+            warm_agents: 0,
+            eviction: EvictionConfig::default(),
+            overrides: Vec::new(),
+            users: UserNamespaces::default(),
+            max_line_bytes: crate::line_reader::DEFAULT_MAX_LINE_BYTES,
+            agent_version: None,
         }
     }
 
@@ -556,6 +2389,64 @@ mod tests {
         assert_eq!(cfg.max_buffer_size, 10_000);
     }
 
+    // ── ReplayLog ────────────────────────────────────────────────────
+
+    #[test]
+    fn replay_log_returns_messages_after_seq() {
+        let mut log = ReplayLog::new(10);
+        assert_eq!(log.push("a".into()), 1);
+        assert_eq!(log.push("b".into()), 2);
+        assert_eq!(log.push("c".into()), 3);
+        let (msgs, complete) = log.since(1);
+        assert!(complete);
+        assert_eq!(msgs, vec![(2, "b".to_string()), (3, "c".to_string())]);
+        assert!(log.since(3).0.is_empty());
+    }
+
+    #[test]
+    fn replay_log_reports_gap_after_eviction() {
+        let mut log = ReplayLog::new(2);
+        for m in ["a", "b", "c", "d"] {
+            log.push(m.into());
+        }
+        assert_eq!(log.last_seq(), 4);
+        let (msgs, complete) = log.since(0);
+        assert!(!complete, "seq 1-2 were evicted");
+        assert_eq!(msgs.len(), 2);
+        assert!(log.since(2).1, "nothing missing after seq 2");
+    }
+
+    #[test]
+    fn replay_log_ack_trims_and_never_regresses() {
+        let mut log = ReplayLog::new(10);
+        for m in ["a", "b", "c"] {
+            log.push(m.into());
+        }
+        log.ack(2);
+        assert_eq!(log.acked_seq(), 2);
+        assert_eq!(log.since(2).0, vec![(3, "c".to_string())]);
+        log.ack(1);
+        assert_eq!(log.acked_seq(), 2, "ack must not move backwards");
+        log.ack(99);
+        assert_eq!(log.acked_seq(), 3, "ack is capped at the last assigned seq");
+    }
+
+    #[test]
+    fn replay_log_trim_keeps_newest_and_capacity() {
+        let mut log = ReplayLog::new(10);
+        for m in ["a", "b", "c", "d"] {
+            log.push(m.into());
+        }
+        assert_eq!(log.trim(1), 3);
+        assert_eq!(log.since(0).0, vec![(4, "d".to_string())]);
+        assert!(!log.since(0).1, "trimmed messages are a gap");
+        assert_eq!(log.trim(1), 0);
+        for m in ["e", "f"] {
+            log.push(m.into());
+        }
+        assert_eq!(log.since(3).0.len(), 3, "capacity is unchanged");
+    }
+
     // ── AgentPool::new ───────────────────────────────────────────────
 
     #[test]
@@ -616,6 +2507,38 @@ mod tests {
         pool.shutdown_all().await;
     }
 
+    #[tokio::test]
+    async fn selecting_a_workspace_respawns_the_agent_there() {
+        let root = std::env::temp_dir().join(format!("bridge-workspaces-{}", uuid::Uuid::new_v4()));
+        let (api, web) = (root.join("api"), root.join("web"));
+        std::fs::create_dir_all(&api).unwrap();
+        std::fs::create_dir_all(&web).unwrap();
+        let workspace = |name: &str, path: &PathBuf| WorkspaceConfig { name: name.into(), path: path.clone() };
+        let mut pool = AgentPool::new(test_config())
+            .with_working_dir(root.clone())
+            .with_workspaces(vec![workspace("api", &api), workspace("web", &web), workspace("gone", &root.join("gone"))]);
+
+        let _ = pool.get_or_spawn("token_a", "cat").await.unwrap();
+        assert_eq!(pool.slot("token_a").unwrap().working_dir, root);
+
+        let selection = pool.select_workspace("token_a", "api").await.unwrap();
+        assert!(selection.restarted);
+        assert!(pool.slot("token_a").is_none(), "agent in the old directory is stopped");
+        assert_eq!(pool.selected_workspace("token_a").map(|w| w.name.as_str()), Some("api"));
+        assert_eq!(pool.working_dir_for("token_b"), root);
+
+        let _ = pool.get_or_spawn("token_a", "cat").await.unwrap();
+        assert_eq!(pool.slot("token_a").unwrap().working_dir, api);
+        assert!(!pool.select_workspace("token_a", "api").await.unwrap().restarted);
+
+        assert!(pool.select_workspace("token_a", "nope").await.is_err());
+        assert!(pool.select_workspace("token_a", "gone").await.is_err());
+        assert_eq!(pool.slot("token_a").unwrap().working_dir, api, "failed selections change nothing");
+
+        pool.shutdown_all().await;
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
     #[tokio::test]
     async fn spawn_with_invalid_command_fails() {
         let mut pool = AgentPool::new(test_config());
@@ -637,13 +2560,13 @@ mod tests {
         let mut pool = AgentPool::new(test_config());
         let _ = pool.get_or_spawn("token_a", "cat").await.unwrap();
 
-        assert!(pool.agents.get("token_a").unwrap().connected);
+        assert!(pool.agents.get("token_a").unwrap().state().connected);
 
         pool.mark_disconnected("token_a");
 
-        let agent = pool.agents.get("token_a").unwrap();
-        assert!(!agent.connected);
-        assert!(agent.disconnected_at.is_some());
+        let agent = pool.slot("token_a").unwrap();
+        assert!(!agent.state().connected);
+        assert!(agent.state().disconnected_at.is_some());
 
         let stats = pool.stats();
         assert_eq!(stats.connected, 0);
@@ -660,9 +2583,9 @@ mod tests {
 
         // Reconnect
         let _ = pool.get_or_spawn("token_a", "cat").await.unwrap();
-        let agent = pool.agents.get("token_a").unwrap();
-        assert!(agent.connected);
-        assert!(agent.disconnected_at.is_none());
+        let agent = pool.slot("token_a").unwrap();
+        assert!(agent.state().connected);
+        assert!(agent.state().disconnected_at.is_none());
 
         pool.shutdown_all().await;
     }
@@ -687,6 +2610,88 @@ mod tests {
         assert!(!pool.agents.contains_key("t1"), "idle agent t1 should be evicted");
     }
 
+    #[tokio::test]
+    async fn user_quota_evicts_only_that_users_idle_agents() {
+        let alice = UserConfig {
+            name: "alice".into(),
+            agent_id: String::new(),
+            auth_token: "alice-token".into(),
+            max_agents: Some(1),
+            devices: vec![crate::common_config::DeviceConfig { name: "tablet".into(), token: "alice-tablet".into(), scopes: vec![] }],
+        };
+        let mut pool = AgentPool::new(PoolConfig { users: UserNamespaces::new(&[alice]), ..test_config() });
+        let _ = pool.get_or_spawn("owner", "cat").await.unwrap();
+        let _ = pool.get_or_spawn("alice-token", "cat").await.unwrap();
+        // Alice's quota is used up by a connected agent
+        assert!(pool.get_or_spawn("alice-tablet", "cat").await.is_err());
+
+        pool.mark_disconnected("owner");
+        pool.mark_disconnected("alice-token");
+        let _ = pool.get_or_spawn("alice-tablet", "cat").await.unwrap();
+        assert!(pool.agents.contains_key("owner"), "another namespace's idle agent is kept");
+        assert!(!pool.agents.contains_key("alice-token"));
+        let users: Vec<_> = pool.summaries().into_iter().map(|s| s.user).collect();
+        assert!(users.contains(&Some("alice".to_string())) && users.contains(&None));
+        pool.shutdown_all().await;
+    }
+
+    /// Fill a 3-agent pool with idle `t1`..`t3`; `t2` reconnects twice first.
+    async fn full_idle_pool(eviction: EvictionConfig) -> AgentPool {
+        let mut pool = AgentPool::new(PoolConfig { eviction, ..test_config() });
+        for token in ["t1", "t2", "t3"] {
+            let _ = pool.get_or_spawn(token, "cat").await.unwrap();
+        }
+        for _ in 0..2 {
+            pool.mark_disconnected("t2");
+            let _ = pool.get_or_spawn("t2", "cat").await.unwrap();
+        }
+        for token in ["t1", "t2", "t3"] {
+            pool.mark_disconnected(token);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        pool
+    }
+
+    #[tokio::test]
+    async fn lru_evicts_longest_idle() {
+        let mut pool = full_idle_pool(EvictionConfig::default()).await;
+        assert_eq!(pool.eviction_candidate(None).as_deref(), Some("t1"));
+        pool.shutdown_all().await;
+    }
+
+    #[tokio::test]
+    async fn lfu_evicts_least_connected() {
+        let mut pool = full_idle_pool(EvictionConfig { policy: EvictionPolicy::Lfu, ..Default::default() }).await;
+        assert_eq!(pool.slot("t2").unwrap().state().connections, 3);
+        // t1 and t3 were each connected once; t1 has been idle longer.
+        assert_eq!(pool.eviction_candidate(None).as_deref(), Some("t1"));
+        pool.agents.remove("t1").unwrap().kill().await;
+        assert_eq!(pool.eviction_candidate(None).as_deref(), Some("t3"));
+        pool.shutdown_all().await;
+    }
+
+    #[tokio::test]
+    async fn priority_and_pinned_tokens_are_evicted_last() {
+        let eviction = EvictionConfig {
+            priorities: HashMap::from([("t3".to_string(), 5)]),
+            pinned: vec!["t1".to_string()],
+            ..Default::default()
+        };
+        let mut pool = full_idle_pool(eviction).await;
+        assert_eq!(pool.eviction_candidate(None).as_deref(), Some("t2"));
+
+        let _ = pool.get_or_spawn("t4", "cat").await.unwrap();
+        assert!(!pool.contains("t2"));
+        let _ = pool.get_or_spawn("t5", "cat").await.unwrap();
+        assert!(!pool.contains("t3"), "higher priority is still evictable");
+
+        // Only the pinned agent is idle now.
+        let result = pool.get_or_spawn("t6", "cat").await;
+        assert!(result.unwrap_err().to_string().contains("Agent pool is full"));
+        assert!(pool.contains("t1"));
+        pool.shutdown_all().await;
+    }
+
     #[tokio::test]
     async fn max_agents_all_connected_fails() {
         let mut pool = AgentPool::new(test_config()); // max_agents = 3
@@ -712,6 +2717,14 @@ mod tests {
             max_agents: 10,
             buffer_messages: false,
             max_buffer_size: 100,
+            max_buffer_bytes: 0,
+            buffer_ttl: None,
+            warm_agents: 0,
+            eviction: EvictionConfig::default(),
+            overrides: Vec::new(),
+            users: UserNamespaces::default(),
+            max_line_bytes: crate::line_reader::DEFAULT_MAX_LINE_BYTES,
+            agent_version: None,
         };
         let mut pool = AgentPool::new(cfg);
 
@@ -732,6 +2745,14 @@ mod tests {
             max_agents: 10,
             buffer_messages: false,
             max_buffer_size: 100,
+            max_buffer_bytes: 0,
+            buffer_ttl: None,
+            warm_agents: 0,
+            eviction: EvictionConfig::default(),
+            overrides: Vec::new(),
+            users: UserNamespaces::default(),
+            max_line_bytes: crate::line_reader::DEFAULT_MAX_LINE_BYTES,
+            agent_version: None,
         };
         let mut pool = AgentPool::new(cfg);
 
@@ -753,6 +2774,14 @@ mod tests {
             max_agents: 10,
             buffer_messages: false,
             max_buffer_size: 100,
+            max_buffer_bytes: 0,
+            buffer_ttl: None,
+            warm_agents: 0,
+            eviction: EvictionConfig::default(),
+            overrides: Vec::new(),
+            users: UserNamespaces::default(),
+            max_line_bytes: crate::line_reader::DEFAULT_MAX_LINE_BYTES,
+            agent_version: None,
         };
         let mut pool = AgentPool::new(cfg);
 
@@ -766,8 +2795,177 @@ mod tests {
         pool.shutdown_all().await;
     }
 
+    // ── per-token / per-agent overrides ─────────────────────────────
+
+    fn override_config() -> PoolConfig {
+        PoolConfig {
+            overrides: vec![
+                PoolOverrideConfig { agent: Some("claude".into()), idle_timeout_secs: Some(7200), max_buffer_size: Some(5000), ..Default::default() },
+                PoolOverrideConfig { token: Some("test-device".into()), idle_timeout_secs: Some(300), buffer_ttl_secs: Some(600), ..Default::default() },
+            ],
+            ..PoolConfig::default()
+        }
+    }
+
+    #[test]
+    fn limits_prefer_token_then_agent_then_default() {
+        let cfg = override_config();
+        let limits = |token, cmd| cfg.limits_for(token, cmd);
+        assert_eq!(limits("phone", "copilot --acp"), AgentLimits::default());
+        assert_eq!(
+            limits("phone", "npx claude-code-acp"),
+            AgentLimits { idle_timeout: Duration::from_secs(7200), max_buffer_size: 5000, ..AgentLimits::default() }
+        );
+        // Token wins for the fields it sets; the agent override fills the rest.
+        let ttl = Some(Duration::from_secs(600));
+        assert_eq!(
+            limits("test-device", "npx claude-code-acp"),
+            AgentLimits { idle_timeout: Duration::from_secs(300), max_buffer_size: 5000, buffer_ttl: ttl, ..AgentLimits::default() }
+        );
+        assert_eq!(
+            limits("test-device", "copilot --acp"),
+            AgentLimits { idle_timeout: Duration::from_secs(300), max_buffer_size: 10_000, buffer_ttl: ttl, ..AgentLimits::default() }
+        );
+    }
+
+    #[tokio::test]
+    async fn reap_uses_per_token_idle_timeout() {
+        let cfg = PoolConfig {
+            idle_timeout: Duration::from_secs(60),
+            overrides: vec![PoolOverrideConfig { token: Some("short".into()), idle_timeout_secs: Some(1), ..Default::default() }],
+            ..test_config()
+        };
+        let mut pool = AgentPool::new(cfg);
+        let _ = pool.get_or_spawn("short", "cat").await.unwrap();
+        let _ = pool.get_or_spawn("long", "cat").await.unwrap();
+        pool.mark_disconnected("short");
+        pool.mark_disconnected("long");
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        pool.reap_idle_agents().await;
+        assert!(!pool.contains("short"), "per-token timeout should apply");
+        assert!(pool.contains("long"), "default timeout still applies to other tokens");
+
+        pool.shutdown_all().await;
+    }
+
+    #[tokio::test]
+    async fn per_token_buffer_size_caps_buffer() {
+        let cfg = PoolConfig {
+            overrides: vec![PoolOverrideConfig { token: Some("small".into()), max_buffer_size: Some(2), ..Default::default() }],
+            ..test_config()
+        };
+        let mut pool = AgentPool::new(cfg);
+        let _ = pool.get_or_spawn("small", "cat").await.unwrap();
+        pool.mark_disconnected("small");
+        for i in 0..4 {
+            pool.buffer_message("small", format!("msg{}", i));
+        }
+        assert_eq!(pool.slot("small").unwrap().state().message_buffer.len(), 2);
+
+        pool.shutdown_all().await;
+    }
+
     // ── message buffering ────────────────────────────────────────────
 
+    fn chunk(text: &str) -> String {
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "session/update",
+            "params": { "sessionId": "s1", "update": { "sessionUpdate": "agent_message_chunk", "content": { "type": "text", "text": text } } }
+        })
+        .to_string()
+    }
+
+    fn buffer_limits(max_buffer_size: usize, max_buffer_bytes: usize, buffer_ttl: Option<Duration>) -> AgentLimits {
+        AgentLimits { max_buffer_size, max_buffer_bytes, buffer_ttl, ..AgentLimits::default() }
+    }
+
+    #[test]
+    fn collapse_chunks_merges_runs_of_one_message() {
+        let thought = |text: &str| chunk(text).replace("agent_message_chunk", "agent_thought_chunk");
+        let tool_call = r#"{"jsonrpc":"2.0","method":"session/update","params":{"update":{"sessionUpdate":"tool_call"}}}"#.to_string();
+        let image = r#"{"jsonrpc":"2.0","method":"session/update","params":{"sessionId":"s1","update":{"sessionUpdate":"agent_message_chunk","content":{"type":"image","data":""}}}}"#.to_string();
+
+        let collapsed = collapse_chunks(vec![
+            thought("Let me "),
+            thought("think."),
+            chunk("Hel"),
+            chunk("lo, "),
+            chunk("world"),
+            tool_call.clone(),
+            chunk("Done"),
+            image.clone(),
+            chunk("!"),
+        ]);
+        let parsed: Vec<serde_json::Value> = collapsed.iter().map(|m| serde_json::from_str(m).unwrap()).collect();
+        assert_eq!(collapsed.len(), 6);
+        assert_eq!(parsed[0]["params"]["update"]["sessionUpdate"], "agent_thought_chunk");
+        assert_eq!(parsed[0]["params"]["update"]["content"]["text"], "Let me think.");
+        assert_eq!(parsed[1], serde_json::from_str::<serde_json::Value>(&chunk("Hello, world")).unwrap());
+        assert_eq!(collapsed[2..], [tool_call, chunk("Done"), image, chunk("!")]);
+
+        // Chunks of different sessions or message ids stay apart.
+        let other_session = chunk("b").replace("\"s1\"", "\"s2\"");
+        assert_eq!(collapse_chunks(vec![chunk("a"), other_session.clone()]), [chunk("a"), other_session]);
+        let with_id = |id: &str, text: &str| chunk(text).replace("\"content\"", &format!("\"messageId\":\"{id}\",\"content\""));
+        let merged = collapse_chunks(vec![with_id("m1", "a"), with_id("m1", "b")]);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&merged[0]).unwrap()["params"]["update"]["content"]["text"], "ab");
+        assert_eq!(collapse_chunks(vec![with_id("m1", "a"), with_id("m2", "b")]).len(), 2);
+    }
+
+    #[test]
+    fn message_buffer_drops_streaming_chunks_first() {
+        let permission = r#"{"jsonrpc":"2.0","id":7,"method":"session/request_permission","params":{}}"#.to_string();
+        let response = r#"{"jsonrpc":"2.0","id":3,"result":{"stopReason":"end_turn"}}"#.to_string();
+        let tool_call = r#"{"jsonrpc":"2.0","method":"session/update","params":{"update":{"sessionUpdate":"tool_call"}}}"#.to_string();
+
+        let mut buffer = MessageBuffer::new(buffer_limits(3, 0, None));
+        buffer.push(permission.clone());
+        buffer.push(chunk("a"));
+        buffer.push(tool_call.clone());
+        assert_eq!(buffer.push(chunk("b")), 1);
+        assert_eq!(buffer.push(response.clone()), 1);
+        assert_eq!(buffer.messages().collect::<Vec<_>>(), [&permission, &tool_call, &response]);
+
+        // With only requests and responses left, the oldest goes.
+        assert_eq!(buffer.trim(1), 2);
+        assert_eq!(buffer.messages().collect::<Vec<_>>(), [&response]);
+    }
+
+    #[test]
+    fn message_buffer_keeps_within_byte_budget() {
+        let response = r#"{"jsonrpc":"2.0","id":1,"result":{}}"#.to_string();
+        let mut buffer = MessageBuffer::new(buffer_limits(100, 400, None));
+        buffer.push(response.clone());
+        for i in 0..20 {
+            buffer.push(chunk(&format!("token {i}")));
+        }
+        assert!(buffer.bytes() <= 400);
+        let kept: Vec<_> = buffer.messages().collect();
+        assert_eq!(kept[0], response, "responses outlive chunks");
+        assert_eq!(*kept.last().unwrap(), chunk("token 19"), "newest chunks are kept");
+        assert_eq!(buffer.bytes(), kept.iter().map(|m| m.len()).sum::<usize>());
+
+        // Tightening the limits trims immediately.
+        buffer.set_limits(buffer_limits(100, response.len(), None));
+        assert_eq!(buffer.messages().collect::<Vec<_>>(), [&response]);
+    }
+
+    #[test]
+    fn message_buffer_expires_old_messages() {
+        let mut buffer = MessageBuffer::new(buffer_limits(100, 0, Some(Duration::from_millis(50))));
+        buffer.push(chunk("stale"));
+        std::thread::sleep(Duration::from_millis(80));
+        let mut overflow = MessageBuffer::new(buffer.limits);
+        overflow.push(chunk("fresh"));
+        buffer.append(&mut overflow);
+        assert!(overflow.is_empty());
+        assert_eq!(buffer.take(), [chunk("fresh")]);
+        assert!(buffer.is_empty() && buffer.bytes() == 0);
+    }
+
     #[tokio::test]
     async fn buffer_message_stores_messages() {
         let mut pool = AgentPool::new(test_config()); // buffer_messages = true, max_buffer_size = 5
@@ -777,10 +2975,30 @@ mod tests {
         pool.buffer_message("token_a", "msg1".into());
         pool.buffer_message("token_a", "msg2".into());
 
-        let agent = pool.agents.get("token_a").unwrap();
-        assert_eq!(agent.message_buffer.len(), 2);
-        assert_eq!(agent.message_buffer[0], "msg1");
-        assert_eq!(agent.message_buffer[1], "msg2");
+        let agent = pool.slot("token_a").unwrap();
+        assert_eq!(agent.state().message_buffer.messages().collect::<Vec<_>>(), ["msg1", "msg2"]);
+
+        pool.shutdown_all().await;
+    }
+
+    #[tokio::test]
+    async fn summaries_count_messages_without_showing_tokens() {
+        let mut pool = AgentPool::new(test_config());
+        let (tx, mut rx, _, _, _, _, _) = pool.get_or_spawn("token_a", "cat").await.unwrap();
+        tx.send(r#"{"jsonrpc":"2.0","method":"ping"}"#.into()).await.unwrap();
+        rx.recv().await.unwrap();
+        pool.mark_disconnected("token_a");
+        pool.buffer_message("token_a", "held".into());
+
+        let summaries = pool.summaries();
+        assert_eq!(summaries.len(), 1);
+        let agent = &summaries[0];
+        assert_eq!(agent.id, TranscriptSink::session_label("token_a"));
+        assert!(!agent.id.contains("token_a"));
+        assert_eq!((agent.messages_in, agent.messages_out), (1, 1));
+        assert_eq!((agent.buffered, agent.buffered_bytes), (1, 4));
+        assert!(!agent.connected && agent.idle_secs == Some(0));
+        assert_eq!(pool.token_for_id(&agent.id).as_deref(), Some("token_a"));
 
         pool.shutdown_all().await;
     }
@@ -794,8 +3012,8 @@ mod tests {
             pool.buffer_message("token_a", format!("msg{}", i));
         }
 
-        let agent = pool.agents.get("token_a").unwrap();
-        assert_eq!(agent.message_buffer.len(), 5, "should cap at max_buffer_size");
+        let agent = pool.slot("token_a").unwrap();
+        assert_eq!(agent.state().message_buffer.len(), 5, "should cap at max_buffer_size");
 
         pool.shutdown_all().await;
     }
@@ -811,8 +3029,8 @@ mod tests {
 
         pool.buffer_message("token_a", "msg1".into());
 
-        let agent = pool.agents.get("token_a").unwrap();
-        assert!(agent.message_buffer.is_empty(), "buffering disabled, should drop");
+        let agent = pool.slot("token_a").unwrap();
+        assert!(agent.state().message_buffer.is_empty(), "buffering disabled, should drop");
 
         pool.shutdown_all().await;
     }
@@ -834,12 +3052,75 @@ mod tests {
         assert_eq!(buffered[1], "buffered2");
 
         // Buffer should be drained
-        let agent = pool.agents.get("token_a").unwrap();
-        assert!(agent.message_buffer.is_empty());
+        let agent = pool.slot("token_a").unwrap();
+        assert!(agent.state().message_buffer.is_empty());
 
         pool.shutdown_all().await;
     }
 
+    #[test]
+    fn replay_skips_acked_and_repeated_messages() {
+        let mut buffer = MessageBuffer::new(buffer_limits(10, 0, None));
+        buffer.push_sequenced(3, "three".into());
+        buffer.push_sequenced(4, "four".into());
+        buffer.push_sequenced(4, "four".into());
+        buffer.push("unsequenced".into());
+        buffer.push_sequenced(5, "five".into());
+        assert_eq!(buffer.take_unacked(3), ["four", "unsequenced", "five"]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn refused_answer_keeps_the_message_in_place() {
+        let buffer = || {
+            let mut buffer = MessageBuffer::new(buffer_limits(10, 0, None));
+            buffer.push_sequenced(1, "permission".into());
+            buffer.push_sequenced(2, "after".into());
+            buffer
+        };
+        let is_permission = |message: &str| message == "permission";
+
+        let mut refused = buffer();
+        assert_eq!(refused.answer_first(is_permission, |_| Err::<(), _>("no")), Some(Err("no")));
+        assert_eq!(refused.answer_first(|_| false, |_| Ok::<_, ()>(())), None);
+        // Still first, and still acknowledged by its sequence number
+        assert_eq!(buffer().take(), refused.take());
+        let mut refused = buffer();
+        let _ = refused.answer_first(is_permission, |_| Err::<(), _>("no"));
+        assert_eq!(refused.take_unacked(1), ["after"]);
+
+        let mut answered = buffer();
+        assert_eq!(answered.answer_first(is_permission, |m| Ok::<_, ()>(m.len())), Some(Ok(10)));
+        assert_eq!(answered.take(), ["after"]);
+    }
+
+    #[tokio::test]
+    async fn send_failing_during_handover_is_replayed_once() {
+        let pool = Arc::new(RwLock::new(AgentPool::new(test_config())));
+        let ((tx, mut old_rx, _, _, _, _, _), _, slot, old) = AgentPool::connect_client(&pool, "token_a", "cat").await.unwrap();
+        tx.send("one".into()).await.unwrap();
+        assert_eq!(tokio::time::timeout(Duration::from_secs(2), old_rx.recv()).await.unwrap().unwrap(), "one");
+
+        // The phone reconnects before the old connection notices it's gone
+        let ((_, mut new_rx, buffered, _, _, _, _), through, _, new) = AgentPool::connect_client(&pool, "token_a", "cat").await.unwrap();
+        assert!(buffered.is_empty());
+        assert_eq!(through, 1);
+        // A connection that doesn't deliver to a client takes nothing over
+        let _ = AgentPool::connect(&pool, "token_a", "cat").await.unwrap();
+        tx.send("two".into()).await.unwrap();
+        assert_eq!(tokio::time::timeout(Duration::from_secs(2), new_rx.recv()).await.unwrap().unwrap(), "two");
+
+        // The old connection fails to send both: the new one got #2 live
+        assert!(slot.buffer_unsent(1, "one".into(), old));
+        assert!(!slot.buffer_unsent(2, "two".into(), old));
+        assert!(slot.buffer_unsent(3, "three".into(), new));
+
+        let ((_, _, buffered, ..), ..) = AgentPool::connect_client(&pool, "token_a", "cat").await.unwrap();
+        assert_eq!(buffered, ["one", "three"]);
+
+        pool.write().await.shutdown_all().await;
+    }
+
     // ── remove_agent / shutdown_all ──────────────────────────────────
 
     #[tokio::test]
@@ -864,6 +3145,24 @@ mod tests {
         assert_eq!(pool.stats().total, 0);
     }
 
+    #[tokio::test]
+    async fn end_session_lets_the_agent_exit_or_kills_it() {
+        let pool = Arc::new(RwLock::new(AgentPool::new(test_config())));
+        let _ = pool.write().await.get_or_spawn("token_a", "cat").await.unwrap();
+        // `cat` exits once its stdin is closed.
+        let ended = AgentPool::end_session(&pool, "token_a").await.unwrap();
+        assert_eq!(ended, SessionEnd { graceful: true, exit_code: Some(0) });
+        assert_eq!(pool.read().await.stats().total, 0);
+        assert!(AgentPool::end_session(&pool, "token_a").await.is_err());
+
+        // ...but only echoes a shutdown request.
+        let config = EndSessionConfig { shutdown_method: Some("shutdown".into()), timeout_secs: 1 };
+        let pool = Arc::new(RwLock::new(AgentPool::new(test_config()).with_end_session(config)));
+        let _ = pool.write().await.get_or_spawn("token_a", "cat").await.unwrap();
+        let ended = AgentPool::end_session(&pool, "token_a").await.unwrap();
+        assert_eq!(ended, SessionEnd { graceful: false, exit_code: None });
+    }
+
     // ── stats ────────────────────────────────────────────────────────
 
     #[tokio::test]
@@ -883,6 +3182,27 @@ mod tests {
         pool.shutdown_all().await;
     }
 
+    #[tokio::test]
+    async fn power_saving_lowers_limits_until_plugged_in() {
+        let mut pool = AgentPool::new(test_config()); // max_agents = 3
+        for token in ["t1", "t2", "t3"] {
+            let _ = pool.get_or_spawn(token, "cat").await.unwrap();
+        }
+        pool.mark_disconnected("t1");
+        pool.mark_disconnected("t2");
+
+        let saving = PowerSaving { max_agents: 2, idle_timeout: Duration::from_secs(3600), pause_warm_pool: true };
+        pool.set_power_saving(Some(saving)).await;
+        let stats = pool.stats();
+        assert_eq!((stats.total, stats.max, stats.power_saving), (2, 2, true));
+        assert!(!pool.contains("t1"), "longest idle agent is evicted");
+        assert!(format!("{}", stats).contains("power saving"));
+
+        pool.set_power_saving(None).await;
+        assert_eq!(pool.stats().max, 3);
+        pool.shutdown_all().await;
+    }
+
     // ── is_alive ─────────────────────────────────────────────────────
 
     #[tokio::test]
@@ -902,6 +3222,67 @@ mod tests {
         pool.shutdown_all().await;
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn agents_exiting_on_their_own_leave_a_crash_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("agent.sh");
+        std::fs::write(&script, "read line\necho \"panicked at $line\" >&2\nsleep 0.1\nexit 3\n").unwrap();
+        let reports = CrashReports::new(dir.path());
+        let mut pool = AgentPool::new(test_config()).with_crash_reports(reports.clone());
+        let command = format!("sh {}", script.display());
+
+        let (tx, mut rx, ..) = pool.get_or_spawn("token_a", &command).await.unwrap();
+        tx.send(r#"{"jsonrpc":"2.0","id":1,"method":"session/prompt"}"#.into()).await.unwrap();
+        let notification = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap();
+        let v: serde_json::Value = serde_json::from_str(&notification).unwrap();
+        assert_eq!(v["method"], "bridge/agentCrashed");
+        assert_eq!(v["params"]["exitCode"], 3);
+
+        let report = reports.find(v["params"]["reportId"].as_str().unwrap()).unwrap();
+        assert_eq!(report.stdin, [r#"{"jsonrpc":"2.0","id":1,"method":"session/prompt"}"#]);
+        assert!(report.stderr[0].starts_with("panicked at"));
+
+        // Agents the bridge stops are not reported.
+        let _ = pool.get_or_spawn("token_b", "cat").await.unwrap();
+        pool.remove_agent("token_b").await;
+        assert_eq!(reports.list().len(), 1);
+        pool.shutdown_all().await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hooks_run_around_agents_and_a_failed_pre_spawn_blocks_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hooks.log");
+        let hook = |agent: &str, pre_spawn: String| AgentHookConfig {
+            agent: Some(agent.to_string()),
+            pre_spawn: Some(pre_spawn),
+            post_exit: Some(format!("echo \"exit $AGENT_EXIT_STATUS\" >> {}", log.display())),
+            ..AgentHookConfig::default()
+        };
+        let mut pool = AgentPool::new(test_config()).with_working_dir(dir.path().to_path_buf()).with_agent_hooks(vec![
+            hook("cat", format!("echo \"pre $AGENT_COMMAND\" >> {}", log.display())),
+            hook("tail", "echo 'docker is not running' >&2; exit 1".to_string()),
+        ]);
+
+        let _ = pool.get_or_spawn("token_a", "cat").await.unwrap();
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "pre cat\n");
+        pool.remove_agent("token_a").await;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !std::fs::read_to_string(&log).unwrap().contains("exit") {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let err = pool.get_or_spawn("token_b", "tail -f /dev/null").await.unwrap_err();
+        assert!(err.to_string().contains("pre_spawn hook"), "{}", err);
+        assert!(!pool.agents.contains_key("token_b"));
+        pool.shutdown_all().await;
+    }
+
     // ── start_reaper ─────────────────────────────────────────────────
 
     #[tokio::test]
@@ -911,6 +3292,14 @@ mod tests {
             max_agents: 10,
             buffer_messages: false,
             max_buffer_size: 100,
+            max_buffer_bytes: 0,
+            buffer_ttl: None,
+            warm_agents: 0,
+            eviction: EvictionConfig::default(),
+            overrides: Vec::new(),
+            users: UserNamespaces::default(),
+            max_line_bytes: crate::line_reader::DEFAULT_MAX_LINE_BYTES,
+            agent_version: None,
         };
         let pool = Arc::new(RwLock::new(AgentPool::new(cfg)));
 
@@ -941,15 +3330,15 @@ mod tests {
         let _ = pool.get_or_spawn("token_a", "cat").await.unwrap();
 
         // No cached response initially
-        let agent = pool.agents.get("token_a").unwrap();
-        assert!(agent.cached_init_response.is_none());
+        let agent = pool.slot("token_a").unwrap();
+        assert!(agent.state().cached_init_response.is_none());
 
         // Cache a response
         let fake_init = r#"{"jsonrpc":"2.0","id":1,"result":{"capabilities":{}}}"#.to_string();
         pool.cache_init_response("token_a", fake_init.clone());
 
-        let agent = pool.agents.get("token_a").unwrap();
-        assert_eq!(agent.cached_init_response.as_deref(), Some(fake_init.as_str()));
+        let agent = pool.slot("token_a").unwrap();
+        assert_eq!(agent.state().cached_init_response.as_deref(), Some(fake_init.as_str()));
 
         // Disconnect and reconnect — cached response should be returned
         pool.mark_disconnected("token_a");
@@ -992,6 +3381,29 @@ mod tests {
         pool.shutdown_all().await;
     }
 
+    #[tokio::test]
+    async fn upgraded_agent_is_restarted_without_its_cached_responses() {
+        let mut pool = AgentPool::new(PoolConfig { agent_version: Some("1".into()), ..test_config() });
+        let _ = pool.get_or_spawn("token_a", "cat").await.unwrap();
+        let agent = pool.agents.get("token_a").unwrap();
+        assert!(agent.fingerprint.as_deref().unwrap().ends_with("version 1"));
+        agent.cache_init_response(r#"{"jsonrpc":"2.0","id":1,"result":{}}"#.to_string());
+        agent.cache_session_response(r#"{"jsonrpc":"2.0","id":2,"result":{"sessionId":"s"}}"#.to_string());
+        pool.config.agent_version = Some("2".into());
+
+        // Still connected elsewhere: kept until it is idle
+        let (_tx, _rx, _buf, was_reused, cached_init, _, _) = pool.get_or_spawn("token_a", "cat").await.unwrap();
+        assert!(was_reused && cached_init.is_some());
+
+        pool.agents.get("token_a").unwrap().mark_disconnected();
+        let (_tx, _rx, _buf, was_reused, cached_init, cached_session, _) = pool.get_or_spawn("token_a", "cat").await.unwrap();
+        assert!(!was_reused, "upgraded agent should be restarted");
+        assert!(cached_init.is_none() && cached_session.is_none());
+        assert!(pool.agents.get("token_a").unwrap().fingerprint.as_deref().unwrap().ends_with("version 2"));
+
+        pool.shutdown_all().await;
+    }
+
     // ── cached session response ──────────────────────────────────────
 
     #[tokio::test]
@@ -1000,15 +3412,15 @@ mod tests {
         let _ = pool.get_or_spawn("token_a", "cat").await.unwrap();
 
         // No cached session response initially
-        let agent = pool.agents.get("token_a").unwrap();
-        assert!(agent.cached_session_response.is_none());
+        let agent = pool.slot("token_a").unwrap();
+        assert!(agent.state().cached_session_response.is_none());
 
         // Cache a session response
         let fake_session = r#"{"jsonrpc":"2.0","id":2,"result":{"sessionId":"ses-abc-123"}}"#.to_string();
         pool.cache_session_response("token_a", fake_session.clone());
 
-        let agent = pool.agents.get("token_a").unwrap();
-        assert_eq!(agent.cached_session_response.as_deref(), Some(fake_session.as_str()));
+        let agent = pool.slot("token_a").unwrap();
+        assert_eq!(agent.state().cached_session_response.as_deref(), Some(fake_session.as_str()));
 
         // Disconnect and reconnect — cached session response should be returned
         pool.mark_disconnected("token_a");
@@ -1050,4 +3462,39 @@ mod tests {
 
         pool.shutdown_all().await;
     }
+
+    // ── health check ─────────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn health_check_kills_unresponsive_idle_agents() {
+        let pool = Arc::new(RwLock::new(AgentPool::new(test_config())));
+        {
+            let mut p = pool.write().await;
+            // `cat` echoes the probe back, which carries the probe id; `sleep` never answers.
+            let _ = p.get_or_spawn("responsive", "cat").await.unwrap();
+            let _ = p.get_or_spawn("hung", "sleep 60").await.unwrap();
+            let _ = p.get_or_spawn("connected", "sleep 60").await.unwrap();
+            p.mark_disconnected("responsive");
+            p.mark_disconnected("hung");
+        }
+
+        let check = HealthCheck { method: "ping".into(), interval: Duration::from_secs(60), timeout: Duration::from_millis(300) };
+        assert_eq!(AgentPool::check_health(&pool, &check).await, 1);
+
+        let mut p = pool.write().await;
+        assert!(p.contains("responsive"));
+        assert!(!p.contains("hung"), "unresponsive idle agent should be killed");
+        assert!(p.contains("connected"), "agents with a client are not probed");
+        let slot = p.slot("responsive").unwrap();
+        assert!(slot.overflow().is_empty(), "probe replies must not reach clients");
+        p.shutdown_all().await;
+    }
+
+    #[test]
+    fn probe_seq_parses_probe_ids_only() {
+        assert_eq!(probe_seq(r#"{"jsonrpc":"2.0","id":"bridge-health-7","result":{}}"#), Some(7));
+        assert_eq!(probe_seq(r#"{"jsonrpc":"2.0","id":"bridge-health-7","error":{"code":-32601}}"#), Some(7));
+        assert_eq!(probe_seq(r#"{"jsonrpc":"2.0","id":7,"result":{}}"#), None);
+        assert_eq!(probe_seq(r#"{"jsonrpc":"2.0","method":"x","params":{"text":"bridge-health-1"}}"#), None);
+    }
 }
@@ -0,0 +1,214 @@
+//! Repeated authentication failures (`[auth_failures]`).
+//!
+//! Every rejected token or pairing code is counted per client address in a
+//! sliding window. When an address reaches the threshold, the bridge logs it,
+//! alerts through the push relay and/or a webhook (once per window), and from
+//! then on answers that address only after `tarpit_secs`, so a brute-force
+//! run becomes visible and slow instead of silently throttled.
+//!
+//! Behind `tailscale-serve` or Cloudflare the address is the proxy's, as for
+//! the connection quotas.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::common_config::AuthFailureConfig;
+use crate::geoip::GeoIp;
+use crate::push::PushRelayClient;
+
+/// Body POSTed to `webhook_url`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthFailureAlert {
+    /// Always `authFailures`
+    pub event: &'static str,
+    pub ip: String,
+    /// Request path of the failure that crossed the threshold
+    pub path: String,
+    /// Country of `ip`, with `[geoip]`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// Autonomous system of `ip`, with `[geoip]`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub as_org: Option<String>,
+    /// Failures within the window
+    pub failures: usize,
+    pub window_secs: u64,
+    /// RFC 3339 time of the alert
+    pub at: String,
+}
+
+#[derive(Debug, Default)]
+struct Failures {
+    times: Vec<Instant>,
+    alerted_at: Option<Instant>,
+}
+
+/// Failure counts per client address, shared by every connection.
+pub struct AuthFailures {
+    config: AuthFailureConfig,
+    failures: Mutex<HashMap<String, Failures>>,
+    push_relay: Option<Arc<PushRelayClient>>,
+    geoip: Option<Arc<GeoIp>>,
+    http_client: reqwest::Client,
+}
+
+impl AuthFailures {
+    pub fn new(config: AuthFailureConfig) -> Self {
+        Self {
+            config,
+            failures: Mutex::new(HashMap::new()),
+            push_relay: None,
+            geoip: None,
+            http_client: crate::proxy::client_builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Alert through `push_relay` when `push` is enabled.
+    pub fn with_push_relay(mut self, push_relay: Option<Arc<PushRelayClient>>) -> Self {
+        self.push_relay = push_relay.filter(|_| self.config.push);
+        self
+    }
+
+    /// Name the country and network of alerting addresses.
+    pub fn with_geoip(mut self, geoip: Option<Arc<GeoIp>>) -> Self {
+        self.geoip = geoip;
+        self
+    }
+
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.config.window_secs)
+    }
+
+    /// Count a failed authentication from `ip` at `path`, alerting when it
+    /// reaches the threshold. Returns the failures within the window.
+    pub fn record(&self, ip: &str, path: &str) -> usize {
+        let now = Instant::now();
+        let window = self.window();
+        let (count, alert) = {
+            let mut failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+            // Forget addresses that have been quiet for a whole window.
+            failures.retain(|_, f| f.times.last().is_some_and(|t| now.duration_since(*t) < window));
+            let entry = failures.entry(ip.to_string()).or_default();
+            entry.times.retain(|t| now.duration_since(*t) < window);
+            entry.times.push(now);
+            let count = entry.times.len();
+            let alert = count >= self.config.threshold
+                && entry.alerted_at.is_none_or(|at| now.duration_since(at) >= window);
+            if alert {
+                entry.alerted_at = Some(now);
+            }
+            (count, alert)
+        };
+        if alert {
+            warn!(
+                "🚨 {} failed authentications from {}{} within {}s (last: {})",
+                count,
+                ip,
+                crate::geoip::describe(self.geoip.as_deref(), ip),
+                self.config.window_secs,
+                path
+            );
+            self.alert(ip, path, count);
+        }
+        count
+    }
+
+    /// How long to hold a request from `ip` before answering it, while the
+    /// address is over the threshold.
+    pub fn tarpit(&self, ip: &str) -> Option<Duration> {
+        if self.config.tarpit_secs == 0 {
+            return None;
+        }
+        let now = Instant::now();
+        let failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        let recent = failures
+            .get(ip)
+            .map_or(0, |f| f.times.iter().filter(|t| now.duration_since(**t) < self.window()).count());
+        (recent >= self.config.threshold).then(|| Duration::from_secs(self.config.tarpit_secs))
+    }
+
+    fn alert(&self, ip: &str, path: &str, failures: usize) {
+        if let Some(relay) = self.push_relay.clone() {
+            let (ip, path) = (ip.to_string(), path.to_string());
+            tokio::spawn(async move {
+                if let Err(e) = relay.notify_auth_failures(&ip, &path, failures).await {
+                    warn!("Auth failure push notification failed: {}", e);
+                }
+            });
+        }
+        if let Some(url) = self.config.webhook_url.clone() {
+            let location = self.geoip.as_ref().and_then(|g| ip.parse().ok().map(|ip| g.lookup(ip))).unwrap_or_default();
+            let alert = AuthFailureAlert {
+                event: "authFailures",
+                ip: ip.to_string(),
+                path: path.to_string(),
+                country: location.country,
+                asn: location.asn,
+                as_org: location.as_org,
+                failures,
+                window_secs: self.config.window_secs,
+                at: chrono::Utc::now().to_rfc3339(),
+            };
+            let client = self.http_client.clone();
+            tokio::spawn(async move {
+                match client.post(&url).json(&alert).send().await {
+                    Ok(res) if !res.status().is_success() => warn!("Auth failure webhook answered {}", res.status()),
+                    Ok(_) => {}
+                    Err(e) => warn!("Auth failure webhook failed: {}", e),
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn threshold_alerts_once_and_starts_the_tarpit() {
+        let mut server = mockito::Server::new_async().await;
+        let hook = server
+            .mock("POST", "/hook")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "event": "authFailures",
+                "ip": "203.0.113.7",
+                "path": "/acp",
+                "failures": 3,
+            })))
+            .expect(1)
+            .create_async()
+            .await;
+        let failures = AuthFailures::new(AuthFailureConfig {
+            threshold: 3,
+            webhook_url: Some(format!("{}/hook", server.url())),
+            ..AuthFailureConfig::default()
+        });
+
+        assert_eq!(failures.record("203.0.113.7", "/"), 1);
+        assert_eq!(failures.record("203.0.113.7", "/"), 2);
+        assert_eq!(failures.tarpit("203.0.113.7"), None);
+        assert_eq!(failures.record("203.0.113.7", "/acp"), 3);
+        assert_eq!(failures.record("203.0.113.7", "/acp"), 4);
+        assert_eq!(failures.tarpit("203.0.113.7"), Some(Duration::from_secs(5)));
+        assert_eq!(failures.tarpit("198.51.100.1"), None);
+
+        // The webhook is sent in the background.
+        for _ in 0..50 {
+            if hook.matched_async().await {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        hook.assert_async().await;
+    }
+}
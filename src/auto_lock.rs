@@ -0,0 +1,233 @@
+//! Inactivity auto-lock (`[auto_lock]`) for bridges nobody watches.
+//!
+//! Every successful connection is recorded in `activity.json` in the config
+//! directory, so the clock survives restarts. Once `after_days` pass without
+//! one, the auth token and every `[[devices]]` token are replaced in
+//! `common.toml`: tokens stolen from an abandoned phone stop working, and the
+//! owner pairs again with the new QR code. `warn_days` before that a warning
+//! is pushed, once per period.
+//!
+//! `run_bridge` checks at startup and then every [`CHECK_INTERVAL`]; when the
+//! deadline passes while running, it rotates the tokens and stops so the TUI
+//! can start it again with the new ones.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::common_config::{AutoLockConfig, CommonConfig};
+use crate::push::PushRelayClient;
+
+pub const ACTIVITY_FILENAME: &str = "activity.json";
+
+/// How often a running bridge looks at the clock.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Connections within this long of the recorded one aren't written again.
+const WRITE_GRANULARITY_SECS: i64 = 60;
+
+/// Contents of `activity.json`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityRecord {
+    /// Last successful connection, or when the clock was last started
+    pub last_connection_at: Option<DateTime<Utc>>,
+    /// When the warning for the current period was pushed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warned_at: Option<DateTime<Utc>>,
+}
+
+/// Where the inactivity clock stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockState {
+    Active,
+    /// Within `warn_days` of the deadline
+    Expiring { days_left: u64 },
+    /// The deadline has passed
+    Expired,
+}
+
+impl ActivityRecord {
+    /// The lock state at `now` under `config`. A record without a connection
+    /// is treated as one made at `now`.
+    pub fn state(&self, config: &AutoLockConfig, now: DateTime<Utc>) -> LockState {
+        let last = self.last_connection_at.unwrap_or(now);
+        let deadline = last + chrono::Duration::days(config.after_days as i64);
+        if now >= deadline {
+            return LockState::Expired;
+        }
+        let left = deadline - now;
+        if left <= chrono::Duration::days(config.warn_days as i64) {
+            // Round up: 36 hours left is "2 days", not "1 day".
+            let days_left = (left.num_seconds() as u64).div_ceil(86_400);
+            return LockState::Expiring { days_left };
+        }
+        LockState::Active
+    }
+}
+
+/// The activity record of a config directory, shared by the agent pool,
+/// which records connections, and the periodic check.
+#[derive(Debug)]
+pub struct Activity {
+    path: PathBuf,
+    record: Mutex<ActivityRecord>,
+}
+
+impl Activity {
+    /// Open `activity.json` in `dir`, starting the clock now if there is
+    /// none yet (or it can't be read).
+    pub fn open(dir: &Path) -> Self {
+        let path = dir.join(ACTIVITY_FILENAME);
+        let record = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str::<ActivityRecord>(&json).ok())
+            .filter(|r| r.last_connection_at.is_some());
+        let activity = Self { path, record: Mutex::new(record.clone().unwrap_or_default()) };
+        if record.is_none() {
+            activity.restart_clock();
+        }
+        activity
+    }
+
+    /// The current record.
+    pub fn record(&self) -> ActivityRecord {
+        self.lock().clone()
+    }
+
+    /// Note a successful connection.
+    pub fn record_connection(&self) {
+        let now = Utc::now();
+        let mut record = self.lock();
+        let recent = record
+            .last_connection_at
+            .is_some_and(|last| (now - last).num_seconds() < WRITE_GRANULARITY_SECS);
+        if recent && record.warned_at.is_none() {
+            return;
+        }
+        *record = ActivityRecord { last_connection_at: Some(now), warned_at: None };
+        self.save(&record);
+    }
+
+    /// Note that the warning for the current period was pushed.
+    pub fn mark_warned(&self) {
+        let mut record = self.lock();
+        record.warned_at = Some(Utc::now());
+        self.save(&record);
+    }
+
+    /// Start a new period, e.g. after the tokens were rotated.
+    pub fn restart_clock(&self) {
+        let mut record = self.lock();
+        *record = ActivityRecord { last_connection_at: Some(Utc::now()), warned_at: None };
+        self.save(&record);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ActivityRecord> {
+        self.record.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn save(&self, record: &ActivityRecord) {
+        let written = serde_json::to_string_pretty(record)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| std::fs::write(&self.path, json).map_err(Into::into));
+        if let Err(e) = written {
+            warn!("Failed to write {}: {:#}", self.path.display(), e);
+        }
+    }
+}
+
+/// Replace every device token in `config`, save it to `dir`, and start a new
+/// period on `activity`.
+pub fn lock(config: &mut CommonConfig, dir: &Path, activity: &Activity) -> Result<()> {
+    config.rotate_tokens();
+    config
+        .save_to_dir(dir)
+        .context("Failed to save rotated tokens")?;
+    activity.restart_clock();
+    Ok(())
+}
+
+/// Check `activity` every [`CHECK_INTERVAL`], logging and pushing the
+/// warning once per period, and resolve when the deadline has passed.
+/// Never resolves without `[auto_lock]`.
+pub async fn watch(config: Option<AutoLockConfig>, activity: Option<Arc<Activity>>, push_relay: Option<Arc<PushRelayClient>>) {
+    let (Some(config), Some(activity)) = (config, activity) else {
+        return std::future::pending().await;
+    };
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let record = activity.record();
+        match record.state(&config, Utc::now()) {
+            LockState::Active => {}
+            LockState::Expiring { days_left } => {
+                if record.warned_at.is_some() {
+                    continue;
+                }
+                warn!("🔒 No connection for a while: device tokens will be replaced in {} day(s)", days_left);
+                if let Some(ref relay) = push_relay {
+                    if let Err(e) = relay.notify_auto_lock(days_left).await {
+                        warn!("Auto-lock push notification failed: {}", e);
+                    }
+                }
+                activity.mark_warned();
+            }
+            LockState::Expired => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn days_ago(days: i64, now: DateTime<Utc>) -> ActivityRecord {
+        ActivityRecord { last_connection_at: Some(now - chrono::Duration::days(days)), warned_at: None }
+    }
+
+    #[test]
+    fn clock_warns_then_expires() {
+        let config = AutoLockConfig { after_days: 30, warn_days: 3 };
+        let now = Utc::now();
+        assert_eq!(days_ago(10, now).state(&config, now), LockState::Active);
+        assert_eq!(days_ago(27, now).state(&config, now), LockState::Expiring { days_left: 3 });
+        let half_a_day_left = ActivityRecord {
+            last_connection_at: Some(now - chrono::Duration::hours(30 * 24 - 12)),
+            warned_at: None,
+        };
+        assert_eq!(half_a_day_left.state(&config, now), LockState::Expiring { days_left: 1 });
+        assert_eq!(days_ago(30, now).state(&config, now), LockState::Expired);
+        assert_eq!(ActivityRecord::default().state(&config, now), LockState::Active);
+    }
+
+    #[test]
+    fn connections_restart_the_period_and_locking_rotates_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let activity = Activity::open(dir.path());
+        let started = activity.record().last_connection_at.unwrap();
+        assert!(dir.path().join(ACTIVITY_FILENAME).is_file());
+
+        activity.mark_warned();
+        activity.record_connection();
+        let reopened = Activity::open(dir.path()).record();
+        assert_eq!(reopened.warned_at, None, "a connection clears the warning");
+        assert!(reopened.last_connection_at.unwrap() >= started);
+
+        let mut config = CommonConfig { auth_token: "old-auth-token".into(), ..CommonConfig::default() };
+        config.devices.push(crate::common_config::DeviceConfig {
+            name: "tablet".into(),
+            token: "old-device-token".into(),
+            scopes: vec![crate::device_tokens::Scope::Chat],
+        });
+        lock(&mut config, dir.path(), &activity).unwrap();
+        let saved = CommonConfig::load_from_dir(dir.path()).unwrap();
+        assert_ne!(saved.auth_token, "old-auth-token");
+        assert_eq!(saved.auth_token, config.auth_token);
+        assert_ne!(saved.devices[0].token, "old-device-token");
+    }
+}
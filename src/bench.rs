@@ -0,0 +1,343 @@
+//! `bridge bench` and `bridge echo-agent`: load testing a running bridge.
+//!
+//! `echo-agent` is a minimal stdio ACP agent: it answers `initialize` and
+//! `session/new`/`session/load`, and replies to every other request with its
+//! own params. Configure it as the bridge's agent command and point `bench` at
+//! the bridge; every round trip then measures the bridge, not a model.
+//!
+//! `bench` opens N concurrent WebSocket connections, performs the ACP
+//! handshake on each, then sends `bench/echo` requests of a fixed payload size
+//! at a fixed rate and reports throughput, latency percentiles and drops.
+
+use anyhow::{Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::task::JoinSet;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::connect::{self, ConnectTarget};
+
+/// Method of the requests `bench` sends; `echo-agent` echoes any method.
+const ECHO_METHOD: &str = "bench/echo";
+
+/// Run the echo agent on this process's stdin/stdout.
+pub async fn run_echo_agent() -> Result<()> {
+    echo_agent(tokio::io::stdin(), tokio::io::stdout()).await
+}
+
+/// Answer each JSON-RPC request line from `input` on `output`.
+pub async fn echo_agent<R, W>(input: R, mut output: W) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut lines = BufReader::new(input).lines();
+    while let Some(line) = lines.next_line().await? {
+        if let Some(reply) = echo_reply(&line) {
+            output.write_all(reply.as_bytes()).await?;
+            output.write_all(b"\n").await?;
+            output.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+/// The echo agent's response to one line; notifications and garbage get none.
+pub(crate) fn echo_reply(line: &str) -> Option<String> {
+    let request: Value = serde_json::from_str(line).ok()?;
+    let id = request.get("id")?.clone();
+    let result = match request.get("method").and_then(|m| m.as_str())? {
+        "initialize" => json!({
+            "protocolVersion": 1,
+            "agentCapabilities": {},
+            "agentInfo": { "name": "bridge-echo", "version": crate::VERSION },
+        }),
+        "session/new" | "session/load" => json!({ "sessionId": uuid::Uuid::new_v4().to_string() }),
+        _ => request.get("params").cloned().unwrap_or(Value::Null),
+    };
+    Some(json!({ "jsonrpc": "2.0", "id": id, "result": result }).to_string())
+}
+
+/// Load parameters for one `bench` run.
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub target: ConnectTarget,
+    /// Concurrent WebSocket connections.
+    pub connections: usize,
+    /// Payload bytes per request (the JSON envelope adds ~60 bytes).
+    pub message_size: usize,
+    /// Requests per second per connection; 0 sends as fast as the bridge answers.
+    pub rate: u32,
+    /// How long each connection sends for.
+    pub duration: Duration,
+    /// How long to wait for a response before counting the request as dropped.
+    pub timeout: Duration,
+}
+
+/// Aggregated results of a `bench` run.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BenchReport {
+    /// Connections that completed the handshake.
+    pub connected: usize,
+    /// Connections that failed to open or handshake (e.g. rejected by a quota).
+    pub failed: usize,
+    pub sent: u64,
+    pub received: u64,
+    /// Requests with no response within the timeout, or lost with their connection.
+    pub dropped: u64,
+    /// JSON-RPC error responses (e.g. from the rate limiter).
+    pub errors: u64,
+    /// Payload bytes echoed back.
+    pub bytes: u64,
+    pub elapsed: Duration,
+    /// Round-trip latencies of successful requests, sorted ascending.
+    pub latencies: Vec<Duration>,
+}
+
+impl BenchReport {
+    /// Latency at percentile `p` (0–100), or `None` without samples.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let rank = (p / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        Some(self.latencies[rank.clamp(1, self.latencies.len()) - 1])
+    }
+
+    /// Successful responses per second over the whole run.
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { self.received as f64 / secs } else { 0.0 }
+    }
+
+    fn merge(&mut self, other: BenchReport) {
+        self.connected += other.connected;
+        self.failed += other.failed;
+        self.sent += other.sent;
+        self.received += other.received;
+        self.dropped += other.dropped;
+        self.errors += other.errors;
+        self.bytes += other.bytes;
+        self.latencies.extend(other.latencies);
+    }
+}
+
+impl std::fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ms = |p: f64| self.percentile(p).map_or("-".to_string(), |d| format!("{:.2}ms", d.as_secs_f64() * 1000.0));
+        writeln!(f, "connections: {} ok, {} failed", self.connected, self.failed)?;
+        writeln!(
+            f,
+            "requests:    {} sent, {} received, {} dropped, {} errors",
+            self.sent, self.received, self.dropped, self.errors
+        )?;
+        writeln!(
+            f,
+            "throughput:  {:.1} msg/s, {:.1} KiB/s over {:.1}s",
+            self.throughput(),
+            self.bytes as f64 / 1024.0 / self.elapsed.as_secs_f64().max(f64::EPSILON),
+            self.elapsed.as_secs_f64()
+        )?;
+        write!(f, "latency:     p50 {}  p90 {}  p99 {}  max {}", ms(50.0), ms(90.0), ms(99.0), ms(100.0))
+    }
+}
+
+/// Run the load described by `config` and aggregate every connection's results.
+pub async fn run(config: &BenchConfig) -> Result<BenchReport> {
+    if config.connections == 0 {
+        anyhow::bail!("--connections must be at least 1");
+    }
+    let start = Instant::now();
+    let mut tasks = JoinSet::new();
+    for _ in 0..config.connections {
+        let config = config.clone();
+        tasks.spawn(async move { run_connection(&config).await });
+    }
+
+    let mut report = BenchReport::default();
+    while let Some(result) = tasks.join_next().await {
+        match result.context("Bench connection task panicked")? {
+            Ok(connection) => report.merge(connection),
+            Err(e) => {
+                tracing::warn!("Bench connection failed: {:#}", e);
+                report.failed += 1;
+            }
+        }
+    }
+    report.elapsed = start.elapsed();
+    report.latencies.sort_unstable();
+    Ok(report)
+}
+
+/// One connection: handshake, then send/receive until the duration ends and
+/// every outstanding request is answered or timed out.
+async fn run_connection(config: &BenchConfig) -> Result<BenchReport> {
+    let ws = connect::open(&config.target).await?;
+    let (mut sink, mut source) = ws.split();
+
+    for (id, method, params) in [
+        (0, "initialize", json!({ "protocolVersion": 1, "clientCapabilities": {} })),
+        (1, "session/new", json!({ "cwd": ".", "mcpServers": [] })),
+    ] {
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        sink.send(Message::Text(request.to_string().into())).await?;
+        tokio::time::timeout(config.timeout, wait_for_response(&mut source, id))
+            .await
+            .with_context(|| format!("Timed out waiting for {} response", method))??;
+    }
+
+    let mut report = BenchReport { connected: 1, ..Default::default() };
+    let payload = "x".repeat(config.message_size);
+    let mut pending: HashMap<u64, Instant> = HashMap::new();
+    let mut next_id: u64 = 2;
+    let mut ticker = (config.rate > 0).then(|| {
+        let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / config.rate as f64));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        interval
+    });
+    let send_until = tokio::time::Instant::now() + config.duration;
+
+    loop {
+        let sending = tokio::time::Instant::now() < send_until;
+        if !sending && pending.is_empty() {
+            break;
+        }
+        // Unthrottled connections keep a single request in flight.
+        let ready_to_send = sending && (ticker.is_some() || pending.is_empty());
+        tokio::select! {
+            _ = async {
+                if let Some(ticker) = ticker.as_mut() {
+                    ticker.tick().await;
+                }
+            }, if ready_to_send => {
+                let request = json!({
+                    "jsonrpc": "2.0",
+                    "id": next_id,
+                    "method": ECHO_METHOD,
+                    "params": { "payload": payload },
+                });
+                sink.send(Message::Text(request.to_string().into())).await?;
+                pending.insert(next_id, Instant::now());
+                next_id += 1;
+                report.sent += 1;
+            }
+            message = source.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let Ok(v) = serde_json::from_str::<Value>(&text) else { continue };
+                    let Some(sent_at) = v.get("id").and_then(|id| id.as_u64()).and_then(|id| pending.remove(&id)) else {
+                        continue;
+                    };
+                    if v.get("error").is_some() {
+                        report.errors += 1;
+                    } else {
+                        report.received += 1;
+                        report.bytes += config.message_size as u64;
+                        report.latencies.push(sent_at.elapsed());
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                Some(Ok(_)) => {}
+            },
+            // Wake up to expire requests the bridge never answers.
+            _ = tokio::time::sleep(config.timeout) => {}
+            _ = tokio::time::sleep_until(send_until), if sending => {}
+        }
+        let before = pending.len();
+        pending.retain(|_, sent_at| sent_at.elapsed() < config.timeout);
+        report.dropped += (before - pending.len()) as u64;
+    }
+
+    report.dropped += pending.len() as u64;
+    let _ = sink.send(Message::Close(None)).await;
+    Ok(report)
+}
+
+/// Read until the response with `id`, skipping notifications.
+async fn wait_for_response<S>(source: &mut S, id: u64) -> Result<()>
+where
+    S: futures_util::Stream<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+    while let Some(message) = source.next().await {
+        if let Message::Text(text) = message.context("WebSocket error")? {
+            let v: Value = serde_json::from_str(&text).unwrap_or(Value::Null);
+            if v.get("id").and_then(|i| i.as_u64()) != Some(id) {
+                continue;
+            }
+            if let Some(error) = v.get("error") {
+                anyhow::bail!("Bridge returned an error: {}", error);
+            }
+            return Ok(());
+        }
+    }
+    anyhow::bail!("Bridge closed the connection during the handshake")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_agent_replies() {
+        let init: Value = serde_json::from_str(&echo_reply(r#"{"jsonrpc":"2.0","id":1,"method":"initialize"}"#).unwrap()).unwrap();
+        assert!(crate::bridge::is_initialize_response(&init.to_string()));
+        let session = echo_reply(r#"{"jsonrpc":"2.0","id":2,"method":"session/new","params":{}}"#).unwrap();
+        assert!(crate::bridge::is_create_session_response(&session));
+        let echo: Value = serde_json::from_str(&echo_reply(r#"{"jsonrpc":"2.0","id":3,"method":"bench/echo","params":{"payload":"hi"}}"#).unwrap()).unwrap();
+        assert_eq!(echo["id"], 3);
+        assert_eq!(echo["result"]["payload"], "hi");
+        assert!(echo_reply(r#"{"jsonrpc":"2.0","method":"session/cancel"}"#).is_none());
+        assert!(echo_reply("not json").is_none());
+    }
+
+    #[test]
+    fn percentiles() {
+        let report = BenchReport {
+            latencies: (1..=100).map(Duration::from_millis).collect(),
+            ..Default::default()
+        };
+        assert_eq!(report.percentile(50.0), Some(Duration::from_millis(50)));
+        assert_eq!(report.percentile(99.0), Some(Duration::from_millis(99)));
+        assert_eq!(report.percentile(100.0), Some(Duration::from_millis(100)));
+        assert_eq!(report.percentile(0.0), Some(Duration::from_millis(1)));
+        assert_eq!(BenchReport::default().percentile(50.0), None);
+    }
+
+    #[tokio::test]
+    async fn benches_an_echo_server() {
+        // A WebSocket server standing in for a bridge in front of the echo agent.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((tcp, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut ws = tokio_tungstenite::accept_async(tcp).await.unwrap();
+                    while let Some(Ok(Message::Text(text))) = ws.next().await {
+                        if let Some(reply) = echo_reply(&text) {
+                            ws.send(Message::Text(reply.into())).await.unwrap();
+                        }
+                    }
+                });
+            }
+        });
+
+        let config = BenchConfig {
+            target: ConnectTarget { url: format!("ws://{}", addr), token: "t".into(), fingerprint: None },
+            connections: 3,
+            message_size: 64,
+            rate: 50,
+            duration: Duration::from_millis(200),
+            timeout: Duration::from_secs(2),
+        };
+        let report = run(&config).await.unwrap();
+        assert_eq!(report.connected, 3);
+        assert_eq!(report.failed, 0);
+        assert!(report.sent > 0);
+        assert_eq!(report.received, report.sent);
+        assert_eq!(report.dropped, 0);
+        assert_eq!(report.latencies.len() as u64, report.received);
+        assert_eq!(report.bytes, report.received * 64);
+    }
+}
@@ -0,0 +1,291 @@
+//! Pairing over Bluetooth LE (feature `ble-pairing`, Linux/BlueZ).
+//!
+//! For hosts where scanning a QR code is impossible (headless servers, screen
+//! readers), the bridge advertises a GATT service whose single characteristic
+//! holds the same JSON payload `/pair/local` returns. The characteristic is
+//! `encrypt-authenticated-read`, so the phone must complete LE Secure
+//! Connections pairing first: BlueZ generates a passkey, the bridge logs it,
+//! and the user types it on the phone. The payload is issued once, through
+//! the bridge's [`PairingManager`], to the first device that reads it.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use tracing::{info, warn};
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+use zbus::{dbus_interface, fdo, Connection, ConnectionBuilder};
+
+use crate::common_config::BlePairingConfig;
+use crate::pairing::{PairingError, PairingManager};
+
+/// GATT service advertised by the bridge.
+pub const SERVICE_UUID: &str = "6f3a0001-8c5e-4b8e-9d2a-a9e1c0b7f1d4";
+/// Characteristic holding the pairing payload (JSON).
+pub const PAYLOAD_CHAR_UUID: &str = "6f3a0002-8c5e-4b8e-9d2a-a9e1c0b7f1d4";
+
+const APP_PATH: &str = "/org/aptove/bridge";
+const SERVICE_PATH: &str = "/org/aptove/bridge/service0";
+const PAYLOAD_CHAR_PATH: &str = "/org/aptove/bridge/service0/char0";
+const ADVERTISEMENT_PATH: &str = "/org/aptove/bridge/advertisement0";
+const AGENT_PATH: &str = "/org/aptove/bridge/agent";
+
+/// The pairing payload, issued once to the first device that reads it.
+///
+/// BlueZ splits long reads into several `ReadValue` calls with increasing
+/// offsets, so the issuing device may read again; any other device is refused.
+pub struct BlePayload {
+    manager: Arc<PairingManager>,
+    issued: Mutex<Option<(String, Vec<u8>)>>,
+}
+
+impl BlePayload {
+    pub fn new(manager: Arc<PairingManager>) -> Self {
+        Self { manager, issued: Mutex::new(None) }
+    }
+
+    /// Payload bytes from `offset` for the device at `device` (its BlueZ object path).
+    pub fn read(&self, device: &str, offset: usize) -> Result<Vec<u8>, PairingError> {
+        let mut issued = self.issued.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((owner, bytes)) = issued.as_ref() {
+            if owner != device {
+                return Err(PairingError::CodeAlreadyUsed);
+            }
+            return Ok(bytes[offset.min(bytes.len())..].to_vec());
+        }
+        let response = self.manager.validate(&self.manager.get_code())?;
+        let bytes = serde_json::to_vec(&response).unwrap_or_default();
+        info!("✅ Pairing payload issued over Bluetooth LE");
+        let slice = bytes[offset.min(bytes.len())..].to_vec();
+        *issued = Some((device.to_string(), bytes));
+        Ok(slice)
+    }
+}
+
+/// A running BLE pairing peripheral; unregistered when dropped.
+pub struct BlePairing {
+    connection: Connection,
+    adapter: OwnedObjectPath,
+}
+
+/// Register the GATT application, advertisement and passkey agent with BlueZ.
+pub async fn start(config: &BlePairingConfig, manager: Arc<PairingManager>) -> Result<BlePairing> {
+    let adapter = OwnedObjectPath::try_from(format!("/org/bluez/{}", config.adapter)).context("Invalid adapter name")?;
+    let connection = ConnectionBuilder::system()?
+        .serve_at(APP_PATH, fdo::ObjectManager)?
+        .serve_at(SERVICE_PATH, GattService)?
+        .serve_at(PAYLOAD_CHAR_PATH, PayloadCharacteristic { payload: BlePayload::new(manager) })?
+        .serve_at(ADVERTISEMENT_PATH, Advertisement { local_name: config.name.clone() })?
+        .serve_at(AGENT_PATH, PasskeyAgent)?
+        .build()
+        .await
+        .context("Failed to connect to the system D-Bus")?;
+
+    let no_options: HashMap<&str, Value<'_>> = HashMap::new();
+    let agent = ObjectPath::from_static_str_unchecked(AGENT_PATH);
+    connection
+        .call_method(Some("org.bluez"), "/org/bluez", Some("org.bluez.AgentManager1"), "RegisterAgent", &(&agent, "DisplayOnly"))
+        .await
+        .context("Failed to register BlueZ pairing agent — is bluetoothd running?")?;
+    connection
+        .call_method(Some("org.bluez"), "/org/bluez", Some("org.bluez.AgentManager1"), "RequestDefaultAgent", &(&agent,))
+        .await
+        .context("Failed to make the bridge the default pairing agent")?;
+    connection
+        .call_method(
+            Some("org.bluez"),
+            adapter.as_ref(),
+            Some("org.bluez.GattManager1"),
+            "RegisterApplication",
+            &(ObjectPath::from_static_str_unchecked(APP_PATH), &no_options),
+        )
+        .await
+        .with_context(|| format!("Failed to register GATT service on {}", config.adapter))?;
+    connection
+        .call_method(
+            Some("org.bluez"),
+            adapter.as_ref(),
+            Some("org.bluez.LEAdvertisingManager1"),
+            "RegisterAdvertisement",
+            &(ObjectPath::from_static_str_unchecked(ADVERTISEMENT_PATH), &no_options),
+        )
+        .await
+        .with_context(|| format!("Failed to advertise on {}", config.adapter))?;
+
+    info!("📶 Bluetooth LE pairing advertised as \"{}\" on {}", config.name, config.adapter);
+    Ok(BlePairing { connection, adapter })
+}
+
+impl Drop for BlePairing {
+    fn drop(&mut self) {
+        let connection = self.connection.clone();
+        let adapter = self.adapter.clone();
+        tokio::spawn(async move {
+            let calls = [
+                ("org.bluez.LEAdvertisingManager1", "UnregisterAdvertisement", ADVERTISEMENT_PATH),
+                ("org.bluez.GattManager1", "UnregisterApplication", APP_PATH),
+            ];
+            for (interface, method, path) in calls {
+                let path = ObjectPath::from_static_str_unchecked(path);
+                let _ = connection.call_method(Some("org.bluez"), adapter.as_ref(), Some(interface), method, &(&path,)).await;
+            }
+            let agent = ObjectPath::from_static_str_unchecked(AGENT_PATH);
+            let _ = connection
+                .call_method(Some("org.bluez"), "/org/bluez", Some("org.bluez.AgentManager1"), "UnregisterAgent", &(&agent,))
+                .await;
+        });
+    }
+}
+
+struct GattService;
+
+#[dbus_interface(name = "org.bluez.GattService1")]
+impl GattService {
+    #[dbus_interface(property, name = "UUID")]
+    fn uuid(&self) -> String {
+        SERVICE_UUID.to_string()
+    }
+
+    #[dbus_interface(property)]
+    fn primary(&self) -> bool {
+        true
+    }
+}
+
+struct PayloadCharacteristic {
+    payload: BlePayload,
+}
+
+#[dbus_interface(name = "org.bluez.GattCharacteristic1")]
+impl PayloadCharacteristic {
+    fn read_value(&self, options: HashMap<String, OwnedValue>) -> fdo::Result<Vec<u8>> {
+        let offset = options.get("offset").and_then(|v| u16::try_from(v).ok()).unwrap_or(0);
+        let device = options
+            .get("device")
+            .and_then(|v| ObjectPath::try_from(v.clone()).ok())
+            .map(|p| p.to_string())
+            .unwrap_or_default();
+        self.payload.read(&device, offset as usize).map_err(|e| {
+            warn!("🚫 Bluetooth LE pairing refused: {}", e);
+            fdo::Error::AccessDenied(e.to_string())
+        })
+    }
+
+    #[dbus_interface(property, name = "UUID")]
+    fn uuid(&self) -> String {
+        PAYLOAD_CHAR_UUID.to_string()
+    }
+
+    #[dbus_interface(property)]
+    fn service(&self) -> OwnedObjectPath {
+        OwnedObjectPath::try_from(SERVICE_PATH).expect("valid object path")
+    }
+
+    /// Reads require an encrypted link with MITM protection (passkey pairing).
+    #[dbus_interface(property)]
+    fn flags(&self) -> Vec<String> {
+        vec!["encrypt-authenticated-read".to_string()]
+    }
+}
+
+struct Advertisement {
+    local_name: String,
+}
+
+#[dbus_interface(name = "org.bluez.LEAdvertisement1")]
+impl Advertisement {
+    fn release(&self) {
+        info!("Bluetooth LE advertisement released by BlueZ");
+    }
+
+    #[dbus_interface(property, name = "Type")]
+    fn kind(&self) -> String {
+        "peripheral".to_string()
+    }
+
+    #[dbus_interface(property, name = "ServiceUUIDs")]
+    fn service_uuids(&self) -> Vec<String> {
+        vec![SERVICE_UUID.to_string()]
+    }
+
+    #[dbus_interface(property)]
+    fn local_name(&self) -> String {
+        self.local_name.clone()
+    }
+}
+
+/// BlueZ pairing agent with `DisplayOnly` capability: the passkey BlueZ
+/// generates is shown here and typed on the phone.
+struct PasskeyAgent;
+
+#[dbus_interface(name = "org.bluez.Agent1")]
+impl PasskeyAgent {
+    fn release(&self) {}
+
+    fn display_passkey(&self, device: ObjectPath<'_>, passkey: u32, _entered: u16) {
+        warn!("🔑 Bluetooth pairing passkey for {}: {:06}", device, passkey);
+    }
+
+    fn request_pin_code(&self, _device: ObjectPath<'_>) -> fdo::Result<String> {
+        Err(fdo::Error::AccessDenied("Only passkey pairing is supported".into()))
+    }
+
+    fn request_passkey(&self, _device: ObjectPath<'_>) -> fdo::Result<u32> {
+        Err(fdo::Error::AccessDenied("Only passkey pairing is supported".into()))
+    }
+
+    fn request_confirmation(&self, _device: ObjectPath<'_>, _passkey: u32) -> fdo::Result<()> {
+        Err(fdo::Error::AccessDenied("Only passkey pairing is supported".into()))
+    }
+
+    fn request_authorization(&self, _device: ObjectPath<'_>) -> fdo::Result<()> {
+        Err(fdo::Error::AccessDenied("Only passkey pairing is supported".into()))
+    }
+
+    fn authorize_service(&self, _device: ObjectPath<'_>, _uuid: String) -> fdo::Result<()> {
+        Ok(())
+    }
+
+    fn cancel(&self) {
+        info!("Bluetooth pairing cancelled");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager() -> Arc<PairingManager> {
+        Arc::new(PairingManager::new_with_cf(
+            "agent".into(),
+            "wss://192.168.1.2:8765".into(),
+            "token".into(),
+            None,
+            None,
+            None,
+            "/tmp".into(),
+        ))
+    }
+
+    #[test]
+    fn payload_is_issued_to_one_device() {
+        let payload = BlePayload::new(manager());
+        let full = payload.read("/org/bluez/hci0/dev_A", 0).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&full).unwrap();
+        assert_eq!(json["authToken"], "token");
+        assert_eq!(json["url"], "wss://192.168.1.2:8765");
+
+        // Long reads continue at an offset on the same device.
+        assert_eq!(payload.read("/org/bluez/hci0/dev_A", 10).unwrap(), full[10..]);
+        assert!(payload.read("/org/bluez/hci0/dev_A", full.len() + 5).unwrap().is_empty());
+        assert!(matches!(payload.read("/org/bluez/hci0/dev_B", 0), Err(PairingError::CodeAlreadyUsed)));
+    }
+
+    #[test]
+    fn payload_is_refused_once_code_is_used_elsewhere() {
+        let manager = manager();
+        manager.validate(&manager.get_code()).unwrap();
+        let payload = BlePayload::new(manager);
+        assert!(payload.read("/org/bluez/hci0/dev_A", 0).is_err());
+    }
+}
@@ -1,14 +1,13 @@
-use anyhow::{Context, Result};
+use crate::error::{BridgeError, Result, ResultExt};
 use futures_util::{SinkExt, StreamExt};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
-use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::TcpListener;
-use tokio::process::Command;
 use tokio::sync::mpsc;
 use tokio::sync::broadcast;
 use tokio::sync::Mutex;
@@ -16,13 +15,30 @@ use tokio_tungstenite::tungstenite::handshake::server::{Request, Response, Error
 use tokio_tungstenite::tungstenite::protocol::Message;
 use tokio_tungstenite::tungstenite::http::StatusCode;
 use tracing::{debug, error, info, warn};
-
-use crate::agent_pool::AgentPool;
-use crate::common_config::SlashCommandConfig;
+use subtle::ConstantTimeEq;
+
+use crate::admin::{Admin, TokenRotatorFn, TransportSummary};
+use crate::agent_pool::{AgentOutput, AgentPool, Replay, SessionEnd, WorkspaceSelection};
+use crate::common_config::{AuthFailureConfig, ClientAuthConfig, DeviceConfig, GeoFilterConfig, LimitsConfig, ListenerConfig, MemoryWatchdogConfig, OutputShapingConfig, SandboxConfig, SlashCommandConfig, UserConfig};
+use crate::output_shaping::OutputShaper;
+use crate::device_tokens::{denied_response, DeviceTokens, Grant, Scopes};
+use crate::auth_failures::AuthFailures;
+use crate::close_reason::CloseReason;
+use crate::geo_filter::{GeoFilter, COUNTRY_HEADER, VISITOR_IP_HEADER};
+use crate::geoip::GeoIp;
+use crate::intercept::Intercept;
+use crate::streamable_http::header;
+use crate::framing::{client_message, LineSplitter};
+use crate::line_reader::{truncation_message, Line, LineReader};
+use crate::wire_protocol::{self, Negotiation, WireVersion};
 use crate::rate_limiter::RateLimiter;
 use crate::tls::TlsConfig;
-use crate::pairing::{PairingManager, PairingError, PairingErrorResponse};
+use crate::session_token::SessionTokens;
+use crate::tunnel_health::TunnelMonitor;
+use crate::pairing::{PairingDevice, PairingManager, PairingError, PairingErrorResponse};
 use crate::push::PushRelayClient;
+use crate::usage::{Usage, Verdict};
+use crate::worktree::{FinishAction, FinishOutcome};
 
 // ---------------------------------------------------------------------------
 // Webhook support types
@@ -75,6 +91,31 @@ impl TriggerRateLimiter {
     }
 }
 
+/// Per-phase deadlines applied while a new connection is being set up.
+///
+/// A client that stalls in any phase (slow-loris) is logged and dropped so it
+/// cannot hold a task and a rate-limiter slot indefinitely.
+#[derive(Debug, Clone, Copy)]
+pub struct HandshakeTimeouts {
+    /// Time allowed for the TLS handshake.
+    pub tls: Duration,
+    /// Time allowed for the initial HTTP request to arrive (and for each
+    /// webhook body chunk).
+    pub request: Duration,
+    /// Time allowed for the WebSocket upgrade to complete.
+    pub upgrade: Duration,
+}
+
+impl Default for HandshakeTimeouts {
+    fn default() -> Self {
+        Self {
+            tls: Duration::from_secs(10),
+            request: Duration::from_secs(10),
+            upgrade: Duration::from_secs(10),
+        }
+    }
+}
+
 /// Describes how the bridge connects to the ACP agent backend.
 #[derive(Clone)]
 pub enum AgentHandle {
@@ -94,6 +135,12 @@ pub struct StdioBridge {
     bind_addr: String,
     auth_token: Option<String>,
     rate_limiter: Arc<RateLimiter>,
+    /// Deadlines for the TLS handshake, initial request read and WS upgrade.
+    handshake_timeouts: HandshakeTimeouts,
+    /// Longest agent output line passed on to the client.
+    max_line_bytes: usize,
+    /// Rate shaping of streamed output to pooled WebSocket clients.
+    output_shaping: OutputShapingConfig,
     tls_config: Option<Arc<TlsConfig>>,
     pairing_manager: Option<Arc<PairingManager>>,
     agent_pool: Option<Arc<tokio::sync::RwLock<AgentPool>>>,
@@ -108,6 +155,8 @@ pub struct StdioBridge {
     external_tls: bool,
     /// Working directory for spawned agent processes.
     working_dir: PathBuf,
+    /// Linux sandboxes for agent processes spawned per connection.
+    sandboxes: Arc<Vec<SandboxConfig>>,
     /// Slash commands to inject via `available_commands_update` after every
     /// session/new or session/load, for agents that don't send the notification
     /// themselves (e.g. Copilot CLI).
@@ -115,6 +164,41 @@ pub struct StdioBridge {
     /// Path to MEMORY.md — loaded into context on new sessions and appended
     /// to by `bridge/appendMemory` notifications from clients.
     memory_path: Option<PathBuf>,
+    /// Newline-delimited JSON-RPC listeners (TCP / Unix socket) started with the server.
+    line_listeners: Vec<ListenerConfig>,
+    /// Lifetime of session tokens issued at `POST /session-token`; disabled when `None`.
+    session_token_ttl: Option<Duration>,
+    /// Extra device tokens with limited scopes, accepted next to the auth token.
+    devices: Vec<DeviceConfig>,
+    /// Other people's namespaces: their auth tokens and devices.
+    users: Vec<UserConfig>,
+    /// Shed new connections and buffered messages above an RSS limit.
+    memory_watchdog: Option<MemoryWatchdogConfig>,
+    /// Refuse requests by Cloudflare's visitor country header.
+    geo_filter: Option<Arc<GeoFilter>>,
+    /// Country and network of connecting addresses, for logs and alerts.
+    geoip: Option<Arc<GeoIp>>,
+    /// Cloudflare tunnel health reported at `GET /healthz`.
+    tunnel_health: Option<Arc<TunnelMonitor>>,
+    /// Alert on and tarpit addresses with repeated authentication failures.
+    auth_failures: Option<AuthFailureConfig>,
+    /// The only credential the `/admin/...` endpoints accept; disabled when `None`.
+    admin_token: Option<String>,
+    /// Serve the web admin UI at `/admin/`.
+    admin_ui: bool,
+    /// Enabled transports, listed at `GET /admin/transports`.
+    transports: Vec<TransportSummary>,
+    /// Log file tailed at `GET /admin/logs`.
+    log_path: Option<PathBuf>,
+    /// Replaces the auth token for `POST /admin/token/rotate`.
+    token_rotator: Option<TokenRotatorFn>,
+    /// Daily per-device counters and budgets of pooled connections.
+    usage: Option<Arc<Usage>>,
+    /// Whether `?token=` and `bridge/auth` first messages are accepted.
+    client_auth: ClientAuthConfig,
+    /// Set by [`Self::close_connections`]; every WebSocket connection is
+    /// closed with the reason.
+    closing: tokio::sync::watch::Sender<Option<CloseReason>>,
 }
 
 impl StdioBridge {
@@ -125,6 +209,9 @@ impl StdioBridge {
             bind_addr: "0.0.0.0".to_string(),
             auth_token: None,
             rate_limiter: Arc::new(RateLimiter::new(10, 30)),
+            handshake_timeouts: HandshakeTimeouts::default(),
+            max_line_bytes: crate::line_reader::DEFAULT_MAX_LINE_BYTES,
+            output_shaping: OutputShapingConfig::default(),
             tls_config: None,
             pairing_manager: None,
             agent_pool: None,
@@ -133,11 +220,102 @@ impl StdioBridge {
             webhook_rate_limiter: Arc::new(Mutex::new(TriggerRateLimiter::new())),
             external_tls: false,
             working_dir: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+            sandboxes: Arc::new(Vec::new()),
             slash_commands: Arc::new(Vec::new()),
             memory_path: None,
+            line_listeners: Vec::new(),
+            session_token_ttl: None,
+            devices: Vec::new(),
+            users: Vec::new(),
+            memory_watchdog: None,
+            geo_filter: None,
+            geoip: None,
+            tunnel_health: None,
+            auth_failures: None,
+            admin_token: None,
+            admin_ui: false,
+            transports: Vec::new(),
+            log_path: None,
+            token_rotator: None,
+            usage: None,
+            client_auth: ClientAuthConfig::default(),
+            closing: tokio::sync::watch::channel(None).0,
         }
     }
 
+    /// Issue short-lived session tokens at `POST /session-token` and accept
+    /// them wherever the auth token is accepted. Requires an auth token.
+    pub fn with_session_tokens(mut self, ttl: Duration) -> Self {
+        self.session_token_ttl = Some(ttl);
+        self
+    }
+
+    /// Accept these device tokens next to the auth token, each limited to its
+    /// scopes and pooled separately. Requires an auth token.
+    pub fn with_devices(mut self, devices: Vec<DeviceConfig>) -> Self {
+        self.devices = devices;
+        self
+    }
+
+    /// Accept these users' auth tokens and devices next to the auth token,
+    /// each user in a namespace of their own: agents pooled per token (see
+    /// [`crate::agent_pool::UserNamespaces`] for quotas), a memory file of
+    /// their own and the user named in logs. Requires an auth token.
+    pub fn with_users(mut self, users: Vec<UserConfig>) -> Self {
+        self.users = users;
+        self
+    }
+
+    /// Refuse new connections and trim buffered messages while the bridge's
+    /// resident memory exceeds `config.max_rss_mb`.
+    pub fn with_memory_watchdog(mut self, config: MemoryWatchdogConfig) -> Self {
+        self.memory_watchdog = Some(config);
+        self
+    }
+
+    /// Refuse requests whose `CF-IPCountry` header `config` doesn't admit.
+    /// Only for bridges reachable solely through Cloudflare, which sets it.
+    pub fn with_geo_filter(mut self, config: &GeoFilterConfig) -> Self {
+        self.geo_filter = Some(Arc::new(GeoFilter::from(config)));
+        self
+    }
+
+    /// Name the country and network of each connecting address in the log
+    /// and in repeated-failure alerts.
+    pub fn with_geoip(mut self, geoip: Arc<GeoIp>) -> Self {
+        self.geoip = Some(geoip);
+        self
+    }
+
+    /// Report `monitor`'s latest tunnel check at `GET /healthz`, answering
+    /// `503` while Cloudflare sees no edge connection.
+    pub fn with_tunnel_health(mut self, monitor: Arc<TunnelMonitor>) -> Self {
+        self.tunnel_health = Some(monitor);
+        self
+    }
+
+    /// Count rejected tokens and pairing codes per address; over the
+    /// threshold, alert (push relay and/or webhook) and slow that address down.
+    pub fn with_auth_failures(mut self, config: AuthFailureConfig) -> Self {
+        self.auth_failures = Some(config);
+        self
+    }
+
+    /// Count each device's pooled messages in `usage`, and refuse prompts
+    /// over its budget with `over_budget = "deny"`.
+    pub fn with_usage(mut self, usage: Arc<Usage>) -> Self {
+        self.usage = Some(usage);
+        self
+    }
+
+    /// Refuse `?token=` with `query_token = false`, and with `first_message`
+    /// let WebSocket clients that can't set headers authenticate with a
+    /// `bridge/auth` first message.
+    pub fn with_client_auth(mut self, config: ClientAuthConfig) -> Self {
+        self.client_auth = config;
+        self
+    }
+
     /// Set the path to MEMORY.md for persistent memory injection.
     pub fn with_memory_path(mut self, path: PathBuf) -> Self {
         self.memory_path = Some(path);
@@ -158,6 +336,13 @@ impl StdioBridge {
         self
     }
 
+    /// Spawn matching agents inside these Linux sandboxes. Applies to agents
+    /// spawned per connection; pooled agents use [`AgentPool::with_sandboxes`].
+    pub fn with_sandboxes(mut self, sandboxes: Vec<SandboxConfig>) -> Self {
+        self.sandboxes = Arc::new(sandboxes);
+        self
+    }
+
     /// Mark this bridge as sitting behind an external TLS proxy (e.g. Tailscale
     /// serve, Cloudflare tunnel). Suppresses the spurious "TLS disabled" warning
     /// since the public connection is already encrypted end-to-end.
@@ -190,6 +375,33 @@ impl StdioBridge {
         self
     }
 
+    /// Apply connection quotas and handshake timeout from the `[limits]` config section
+    pub fn with_limits(mut self, limits: &LimitsConfig) -> Self {
+        self.rate_limiter = Arc::new(
+            RateLimiter::new(limits.max_connections_per_ip, limits.max_attempts_per_minute)
+                .with_max_total_connections(limits.max_connections),
+        );
+        self.handshake_timeouts = HandshakeTimeouts {
+            tls: Duration::from_secs(limits.handshake_timeout_secs),
+            request: Duration::from_secs(limits.request_timeout_secs),
+            upgrade: Duration::from_secs(limits.upgrade_timeout_secs),
+        };
+        self.max_line_bytes = limits.max_line_bytes;
+        self
+    }
+
+    /// Shape streamed agent output to WebSocket clients (`[output_shaping]`)
+    pub fn with_output_shaping(mut self, config: OutputShapingConfig) -> Self {
+        self.output_shaping = config;
+        self
+    }
+
+    /// Override the per-phase connection setup deadlines
+    pub fn with_handshake_timeouts(mut self, timeouts: HandshakeTimeouts) -> Self {
+        self.handshake_timeouts = timeouts;
+        self
+    }
+
     /// Enable TLS with the given configuration
     pub fn with_tls(mut self, tls_config: TlsConfig) -> Self {
         self.tls_config = Some(Arc::new(tls_config));
@@ -222,19 +434,91 @@ impl StdioBridge {
         self
     }
 
+    /// Also serve the pooled agent over a newline-delimited JSON-RPC listener.
+    /// Requires the agent pool, a command agent handle and an auth token.
+    pub fn with_line_listener(mut self, listener: ListenerConfig) -> Self {
+        self.line_listeners.push(listener);
+        self
+    }
+
+    /// Enable the `/admin/...` endpoints for this token, which clients'
+    /// tokens can't stand in for (see [`crate::admin`]).
+    pub fn with_admin_token(mut self, token: String) -> Self {
+        self.admin_token = Some(token);
+        self
+    }
+
+    /// Serve the web admin UI at `/admin/` (see [`crate::admin`]). Requires
+    /// an admin token.
+    pub fn with_admin_ui(mut self) -> Self {
+        self.admin_ui = true;
+        self
+    }
+
+    /// Transports to list at `GET /admin/transports`.
+    pub fn with_transports(mut self, transports: Vec<TransportSummary>) -> Self {
+        self.transports = transports;
+        self
+    }
+
+    /// Serve the tail of the bridge's log file at `GET /admin/logs`.
+    pub fn with_log_file(mut self, path: PathBuf) -> Self {
+        self.log_path = Some(path);
+        self
+    }
+
+    /// Let `POST /admin/token/rotate` replace the auth token; the caller
+    /// saves it and restarts the bridge.
+    pub fn with_token_rotator(mut self, rotator: TokenRotatorFn) -> Self {
+        self.token_rotator = Some(rotator);
+        self
+    }
+
+    /// Close every open WebSocket connection with `reason`, e.g. before the
+    /// bridge stops. Returns whether any connection was open.
+    pub fn close_connections(&self, reason: CloseReason) -> bool {
+        self.closing.send_replace(Some(reason));
+        self.closing.receiver_count() > 0
+    }
+
     /// Get a reference to the pairing manager (if enabled)
     #[allow(dead_code)]
     pub fn pairing_manager(&self) -> Option<&Arc<PairingManager>> {
         self.pairing_manager.as_ref()
     }
 
+    /// Spawn the configured JSON-RPC listeners next to the WebSocket server.
+    fn start_line_listeners(&self) {
+        if self.line_listeners.is_empty() {
+            return;
+        }
+        let (Some(pool), AgentHandle::Command(cmd), Some(token)) = (&self.agent_pool, &self.agent_handle, &self.auth_token) else {
+            warn!("⚠️  JSON-RPC listeners need the agent pool, an agent command and an auth token — not started");
+            return;
+        };
+        for listener in &self.line_listeners {
+            let serve = crate::line_listener::serve(listener.clone(), token.clone(), Arc::clone(pool), cmd.clone());
+            tokio::spawn(async move {
+                if let Err(e) = serve.await {
+                    error!("JSON-RPC listener stopped: {:#}", e);
+                }
+            });
+        }
+    }
+
     /// Start the bridge server
     pub async fn start(&self) -> Result<()> {
         let addr = format!("{}:{}", self.bind_addr, self.port);
         let listener = TcpListener::bind(&addr)
             .await
-            .context(format!("Failed to bind to {}", addr))?;
+            .config_err(format!("Failed to bind to {}", addr))?;
+        self.serve(listener).await
+    }
 
+    /// Run the bridge server on an already-bound listener (e.g. an ephemeral
+    /// port); the configured port and bind address are ignored.
+    pub async fn serve(&self, listener: TcpListener) -> Result<()> {
+        let addr = listener.local_addr().io_err("Failed to read listener address")?;
         let protocol = if self.tls_config.is_some() { "wss" } else { "ws" };
         info!("✅ WebSocket server listening on {} ({}://{})", addr, protocol, addr);
         
@@ -255,7 +539,56 @@ impl StdioBridge {
         if self.pairing_manager.is_some() {
             info!("🔗 Pairing endpoint available at /pair/local, /pair/tailscale, /pair/cloudflare");
         }
+
+        let session_tokens = match (self.session_token_ttl, &self.auth_token) {
+            (Some(ttl), Some(token)) => {
+                info!("🎟️  Session tokens available at /session-token (valid {}s)", ttl.as_secs());
+                Some(Arc::new(SessionTokens::new(token.clone(), ttl)))
+            }
+            (Some(_), None) => {
+                warn!("⚠️  Session tokens need an auth token — disabled");
+                None
+            }
+            _ => None,
+        };
+
+        let devices = if self.auth_token.is_some() {
+            Arc::new(DeviceTokens::new(&self.devices, self.session_token_ttl).with_users(&self.users, self.session_token_ttl))
+        } else {
+            if !self.devices.is_empty() || !self.users.is_empty() {
+                warn!("⚠️  Device tokens and users need an auth token — ignored");
+            }
+            Arc::new(DeviceTokens::default())
+        };
+        if !devices.is_empty() {
+            if !self.devices.is_empty() {
+                info!("📱 {} scoped device token(s) accepted", self.devices.len());
+            }
+            if !self.users.is_empty() {
+                let names: Vec<&str> = self.users.iter().map(|u| u.name.as_str()).collect();
+                info!("👥 {} user namespace(s): {}", self.users.len(), names.join(", "));
+            }
+        }
+
+        let mut admin = Admin::new(self.admin_token.clone(), Arc::clone(&self.rate_limiter))
+            .with_agent_pool(self.agent_pool.clone())
+            .with_pairing(self.pairing_manager.clone())
+            .with_devices(&self.devices)
+            .with_users(&self.users)
+            .with_transports(self.transports.clone())
+            .with_log_file(self.log_path.clone())
+            .with_token_rotator(self.token_rotator.clone());
+        if self.admin_ui && self.admin_token.is_some() {
+            info!("🛠️  Web admin UI at /admin/");
+            admin = admin.with_ui();
+        }
+        let admin = Arc::new(admin);
         
+        self.start_line_listeners();
+        if let Some(ref config) = self.memory_watchdog {
+            crate::memory_watchdog::start(config.clone(), Arc::clone(&self.rate_limiter), self.agent_pool.clone(), self.push_relay.clone());
+        }
+
         info!("🤖 Ready to accept mobile connections...");
 
         let auth_token = Arc::new(self.auth_token.clone());
@@ -264,6 +597,13 @@ impl StdioBridge {
         let pairing_manager = self.pairing_manager.clone();
         let webhook_resolver = self.webhook_resolver.clone();
         let webhook_rate_limiter = Arc::clone(&self.webhook_rate_limiter);
+        let geo_filter = self.geo_filter.clone();
+        let tunnel_health = self.tunnel_health.clone();
+        let auth_failures = self.auth_failures.clone().map(|config| {
+            info!("🚨 Alerting after {} failed authentications per address in {}s", config.threshold, config.window_secs);
+            Arc::new(AuthFailures::new(config).with_push_relay(self.push_relay.clone()).with_geoip(self.geoip.clone()))
+        });
+        let rejections = Arc::new(tokio::sync::Semaphore::new(MAX_PENDING_REJECTIONS));
 
         loop {
             match listener.accept().await {
@@ -273,25 +613,59 @@ impl StdioBridge {
 
                     // Check rate limits before processing
                     if let Err(e) = rate_limiter.check_connection(client_ip).await {
-                        warn!("🚫 Rate limit exceeded for {}: {}", client_ip, e);
-                        // Connection will be dropped, client should retry later
+                        let retry_after = e.retry_after_secs();
+                        warn!("🚫 Rate limit exceeded for {}: {} (retry after {}s)", client_ip, e, retry_after);
+                        // Tell the client it's throttled (429 / close 1013) rather
+                        // than dropping it; under a flood, fall back to dropping.
+                        let Ok(permit) = Arc::clone(&rejections).try_acquire_owned() else {
+                            continue;
+                        };
+                        let tls_config = tls_config.clone();
+                        let reason = e.to_string();
+                        tokio::spawn(async move {
+                            let _permit = permit;
+                            let reject = async {
+                                match tls_config {
+                                    Some(tls) => reject_rate_limited(tls.acceptor.accept(stream).await?, &reason, retry_after).await,
+                                    None => reject_rate_limited(stream, &reason, retry_after).await,
+                                }
+                            };
+                            match tokio::time::timeout(REJECTION_TIMEOUT, reject).await {
+                                Ok(Err(e)) => debug!("Rate-limit response to {} failed: {}", client_ip, e),
+                                Err(_) => debug!("Rate-limit response to {} timed out", client_ip),
+                                Ok(Ok(())) => {}
+                            }
+                        });
                         continue;
                     }
 
-                    info!("📱 New connection from: {}", addr);
+                    info!("📱 New connection from: {}{}", addr, crate::geoip::describe(self.geoip.as_deref(), &addr.ip().to_string()));
                     let agent_handle = self.agent_handle.clone();
                     let auth_token = Arc::clone(&auth_token);
+                    let session_tokens = session_tokens.clone();
+                    let devices = Arc::clone(&devices);
                     let rate_limiter = Arc::clone(&rate_limiter);
                     let tls_config = tls_config.clone();
                     let pairing_manager = pairing_manager.clone();
+                    let admin = Arc::clone(&admin);
                     let agent_pool = self.agent_pool.clone();
                     let push_relay = self.push_relay.clone();
                     let webhook_resolver = webhook_resolver.clone();
                     let webhook_rate_limiter = Arc::clone(&webhook_rate_limiter);
                     let client_ip_str = addr.ip().to_string();
                     let working_dir = self.working_dir.clone();
+                    let sandboxes = Arc::clone(&self.sandboxes);
                     let slash_commands = Arc::clone(&self.slash_commands);
                     let memory_path = self.memory_path.clone();
+                    let timeouts = self.handshake_timeouts;
+                    let max_line_bytes = self.max_line_bytes;
+                    let output_shaping = self.output_shaping;
+                    let closing = self.closing.subscribe();
+                    let geo_filter = geo_filter.clone();
+                    let tunnel_health = tunnel_health.clone();
+                    let auth_failures = auth_failures.clone();
+                    let usage = self.usage.clone();
+                    let client_auth = self.client_auth;
 
                     tokio::spawn(async move {
                         // Register connection
@@ -299,18 +673,22 @@ impl StdioBridge {
 
                         let result = if let Some(tls) = tls_config {
                             // TLS connection
-                            match tls.acceptor.accept(stream).await {
-                                Ok(tls_stream) => {
-                                    handle_connection_generic(tls_stream, agent_handle, auth_token, pairing_manager, agent_pool, push_relay, webhook_resolver, webhook_rate_limiter, client_ip_str, working_dir, slash_commands, memory_path).await
+                            match tokio::time::timeout(timeouts.tls, tls.acceptor.accept(stream)).await {
+                                Ok(Ok(tls_stream)) => {
+                                    handle_connection_generic(tls_stream, agent_handle, auth_token, session_tokens, devices, pairing_manager, admin, agent_pool, push_relay, webhook_resolver, webhook_rate_limiter, geo_filter, tunnel_health, auth_failures, client_ip_str, working_dir, sandboxes, slash_commands, memory_path, timeouts, max_line_bytes, output_shaping, usage, client_auth, closing).await
                                 }
-                                Err(e) => {
+                                Ok(Err(e)) => {
                                     warn!("🚫 TLS handshake failed: {}", e);
-                                    Err(anyhow::anyhow!("TLS handshake failed: {}", e))
+                                    Err(BridgeError::Protocol { message: "TLS handshake failed".into(), source: Some(e.into()) })
+                                }
+                                Err(_) => {
+                                    warn!("⏱️  TLS handshake timed out after {:?} from {}, closing stalled connection", timeouts.tls, client_ip);
+                                    Err(BridgeError::network("TLS handshake timed out"))
                                 }
                             }
                         } else {
                             // Plain TCP connection
-                            handle_connection_generic(stream, agent_handle, auth_token, pairing_manager, agent_pool, push_relay, webhook_resolver, webhook_rate_limiter, client_ip_str, working_dir, slash_commands, memory_path).await
+                            handle_connection_generic(stream, agent_handle, auth_token, session_tokens, devices, pairing_manager, admin, agent_pool, push_relay, webhook_resolver, webhook_rate_limiter, geo_filter, tunnel_health, auth_failures, client_ip_str, working_dir, sandboxes, slash_commands, memory_path, timeouts, max_line_bytes, output_shaping, usage, client_auth, closing).await
                         };
 
                         // Always remove connection when done
@@ -329,41 +707,246 @@ impl StdioBridge {
     }
 }
 
+/// Rejections (429 / close 1013) in flight at once; past this, throttled
+/// connections are dropped without a response.
+const MAX_PENDING_REJECTIONS: usize = 64;
+
+/// Deadline for answering a throttled connection, TLS handshake included.
+const REJECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Answer a connection refused by the rate limiter: WebSocket upgrades are
+/// accepted and closed with code 1013 (Try Again Later), anything else gets
+/// `429 Too Many Requests`. Both carry `Retry-After`.
+async fn reject_rate_limited<S>(mut stream: S, reason: &str, retry_after: u64) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buffer = vec![0u8; 8192];
+    let n = stream.read(&mut buffer).await.network_err("Failed to read request")?;
+    let request_str = String::from_utf8_lossy(&buffer[..n]);
+
+    if request_str.to_ascii_lowercase().contains("upgrade: websocket") {
+        let request_bytes = with_connection_upgrade(&request_str, &buffer[..n]);
+        #[allow(clippy::result_large_err)]
+        let callback = move |_req: &Request, mut response: Response| -> std::result::Result<Response, ErrorResponse> {
+            response.headers_mut().insert("Retry-After", retry_after.into());
+            Ok(response)
+        };
+        let mut ws = tokio_tungstenite::accept_hdr_async(PrefixedStream::new(request_bytes, stream), callback)
+            .await
+            .protocol_err("WebSocket handshake failed")?;
+        let frame = CloseReason::RateLimited { retry_after }.frame();
+        ws.close(Some(frame)).await.network_err("Failed to close the connection")?;
+        // Wait for the client's close reply so the frame isn't lost to a reset
+        while let Some(Ok(_)) = ws.next().await {}
+    } else {
+        let body = serde_json::json!({ "error": "rate_limited", "message": reason, "retryAfter": retry_after }).to_string();
+        let response = create_http_response(429, "Too Many Requests", &body)
+            .replacen("\r\n", &format!("\r\nRetry-After: {}\r\n", retry_after), 1);
+        stream.write_all(response.as_bytes()).await?;
+        stream.flush().await?;
+    }
+    Ok(())
+}
+
+/// Cloudflare (and other proxies) strip the `Connection: upgrade` hop-by-hop header
+/// before forwarding WebSocket upgrade requests to the origin. tungstenite strictly
+/// requires `Connection: upgrade`, so we inject it if `Upgrade: websocket` is present.
+fn with_connection_upgrade(request_str: &str, request_data: &[u8]) -> Vec<u8> {
+    let lower = request_str.to_ascii_lowercase();
+    if lower.contains("upgrade: websocket") && !lower.contains("connection: upgrade") {
+        // Insert `Connection: upgrade` after the first header line (after the request line)
+        let mut patched = request_str.to_string();
+        if let Some(pos) = patched.find("\r\n") {
+            patched.insert_str(pos + 2, "Connection: upgrade\r\n");
+        }
+        patched.into_bytes()
+    } else {
+        request_data.to_vec()
+    }
+}
+
 /// Handle a single connection (generic over stream type for TLS/non-TLS)
 /// This function first peeks at the HTTP request to determine if it's:
-/// 1. A pairing request (/pair/local) - respond with JSON
+/// 1. A pairing request (/pair/local, or /p/<code>) - respond with JSON
 /// 2. A webhook request (POST /webhook/<token>) - handle and return immediately
-/// 3. A WebSocket upgrade request - proceed with WebSocket handling
+/// 3. A version request (GET /version) - respond with bridge capabilities
+///    (likewise GET /healthz, with liveness and tunnel health)
+/// 4. A streamable HTTP request (GET/POST /acp) - SSE stream or message post
+///    (likewise POST /trigger, a prompt from another system; see [`crate::trigger`],
+///    and POST /push/action, a notification's answer; see [`crate::push_action`])
+/// 5. An admin request (/admin/...) - respond with JSON (see [`crate::admin`])
+/// 6. A WebSocket upgrade request - proceed with WebSocket handling
+#[allow(clippy::too_many_arguments)]
 async fn handle_connection_generic<S>(
     mut stream: S,
     agent_handle: AgentHandle,
     auth_token: Arc<Option<String>>,
+    session_tokens: Option<Arc<SessionTokens>>,
+    devices: Arc<DeviceTokens>,
     pairing_manager: Option<Arc<PairingManager>>,
+    admin: Arc<Admin>,
     agent_pool: Option<Arc<tokio::sync::RwLock<AgentPool>>>,
     push_relay: Option<Arc<PushRelayClient>>,
     webhook_resolver: Option<WebhookResolverFn>,
     webhook_rate_limiter: Arc<Mutex<TriggerRateLimiter>>,
+    geo_filter: Option<Arc<GeoFilter>>,
+    tunnel_health: Option<Arc<TunnelMonitor>>,
+    auth_failures: Option<Arc<AuthFailures>>,
     client_ip: String,
     working_dir: PathBuf,
+    sandboxes: Arc<Vec<SandboxConfig>>,
     slash_commands: Arc<Vec<SlashCommandConfig>>,
     memory_path: Option<PathBuf>,
+    timeouts: HandshakeTimeouts,
+    max_line_bytes: usize,
+    output_shaping: OutputShapingConfig,
+    usage: Option<Arc<Usage>>,
+    client_auth: ClientAuthConfig,
+    closing: tokio::sync::watch::Receiver<Option<CloseReason>>,
 ) -> Result<()>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    // Read the HTTP request headers to determine the request type
+    // Read the HTTP request headers to determine the request type.
+    // A client that connects and never sends anything is dropped after the deadline.
     let mut buffer = vec![0u8; 8192];
-    let n = stream.read(&mut buffer).await.context("Failed to read request")?;
+    let n = match tokio::time::timeout(timeouts.request, stream.read(&mut buffer)).await {
+        Ok(result) => result.network_err("Failed to read request")?,
+        Err(_) => {
+            warn!("⏱️  No request from {} within {:?}, closing stalled connection", client_ip, timeouts.request);
+            return Ok(());
+        }
+    };
     let request_data = &buffer[..n];
 
     // Parse the first line to get the path
     let request_str = String::from_utf8_lossy(request_data);
     let first_line = request_str.lines().next().unwrap_or("");
 
+    // Behind Cloudflare, refuse visitors from countries the config doesn't admit
+    if let Some(ref geo_filter) = geo_filter {
+        if let Err(reason) = geo_filter.check(header(&request_str, COUNTRY_HEADER)) {
+            let visitor = header(&request_str, VISITOR_IP_HEADER).unwrap_or(&client_ip);
+            let path = first_line.split_whitespace().nth(1).unwrap_or("");
+            warn!("🌍 Refused {} from {}: {}", path, visitor, reason);
+            let response = create_http_response(403, "Forbidden", r#"{"error":"forbidden"}"#);
+            stream.write_all(response.as_bytes()).await?;
+            return Ok(());
+        }
+    }
+
+    // Addresses that keep failing to authenticate wait for every answer
+    if let Some(delay) = auth_failures.as_ref().and_then(|f| f.tarpit(&client_ip)) {
+        debug!("🐌 Holding request from {} for {:?}", client_ip, delay);
+        tokio::time::sleep(delay).await;
+    }
+
+    // With `query_token = false`, a token in the URL is refused rather than
+    // ignored, so the client learns to stop sending it
+    if !client_auth.query_token && has_query_token(first_line) {
+        warn!("🚫 Refused a query-string token from {}", client_ip);
+        let response = create_http_response(401, "Unauthorized", r#"{"error":"query_token_disabled","message":"Send the token in the X-Bridge-Token header"}"#);
+        stream.write_all(response.as_bytes()).await?;
+        return Ok(());
+    }
+
     // Check if this is a pairing request
-    if (first_line.contains("/pair/local") || first_line.contains("/pair/cloudflare") || first_line.contains("/pair/tailscale")) && first_line.starts_with("GET") {
+    let short_pairing = first_line.starts_with(&format!("GET {}", crate::pairing::SHORT_PAIRING_PATH));
+    if short_pairing || (first_line.contains("/pair/local") || first_line.contains("/pair/cloudflare") || first_line.contains("/pair/tailscale")) && first_line.starts_with("GET") {
         info!("🔗 Pairing request received");
-        return handle_pairing_request(&mut stream, &request_str, pairing_manager).await;
+        return handle_pairing_request(&mut stream, &request_str, pairing_manager, auth_failures.as_deref(), &client_ip).await.map(|_| ());
+    }
+
+    // Operator snapshot and controls for `bridge top`
+    if crate::admin::matches(first_line) {
+        return Ok(crate::admin::handle_request(&mut stream, &request_str, &admin, auth_failures.as_deref(), &client_ip).await?);
+    }
+
+    // Version / feature-detection request (no auth: it reveals nothing beyond
+    // what the bridge/capabilities notification tells every connected client)
+    if first_line.starts_with("GET /version") {
+        let pool_mode = PoolMode::for_handle(&agent_handle, agent_pool.is_some());
+        let body = bridge_capabilities(pool_mode, push_relay.is_some(), session_tokens.is_some()).to_string();
+        let response = create_http_response(200, "OK", &body);
+        stream.write_all(response.as_bytes()).await?;
+        return Ok(());
+    }
+
+    // Liveness and tunnel health for uptime checks (no auth, like /version)
+    if first_line.starts_with("GET /healthz") {
+        let (status, body) = crate::tunnel_health::healthz(tunnel_health.as_deref());
+        let reason = if status == 200 { "OK" } else { "Service Unavailable" };
+        let response = create_http_response(status, reason, &body);
+        stream.write_all(response.as_bytes()).await?;
+        return Ok(());
+    }
+
+    // Session token exchange / refresh
+    if first_line.starts_with("POST /session-token") {
+        return handle_session_token_request(&mut stream, &request_str, session_tokens.as_deref(), &devices, auth_failures.as_deref(), &client_ip).await;
+    }
+
+    // Streamable HTTP transport (POST + SSE) for clients that can't hold a WebSocket
+    if crate::streamable_http::matches(first_line) {
+        let agent_command = match agent_handle {
+            AgentHandle::Command(ref cmd) => Some(cmd.as_str()),
+            AgentHandle::InProcess { .. } => None,
+        };
+        return Ok(crate::streamable_http::handle_request(
+            &mut stream,
+            request_data,
+            &request_str,
+            &auth_token,
+            session_tokens.as_deref(),
+            &devices,
+            agent_command,
+            agent_pool,
+            auth_failures.as_deref(),
+            &client_ip,
+            timeouts.request,
+        )
+        .await?);
+    }
+
+    // Prompts posted by other systems, answered with the session they went to
+    if crate::trigger::matches(first_line) {
+        let agent_command = match agent_handle {
+            AgentHandle::Command(ref cmd) => Some(cmd.as_str()),
+            AgentHandle::InProcess { .. } => None,
+        };
+        return Ok(crate::trigger::handle_request(
+            &mut stream,
+            request_data,
+            &request_str,
+            &auth_token,
+            session_tokens.as_deref(),
+            &devices,
+            agent_command,
+            agent_pool,
+            push_relay,
+            auth_failures.as_deref(),
+            &client_ip,
+            timeouts.request,
+        )
+        .await?);
+    }
+
+    // Approve/deny buttons on permission request notifications
+    if crate::push_action::matches(first_line) {
+        return Ok(crate::push_action::handle_request(
+            &mut stream,
+            request_data,
+            &request_str,
+            &auth_token,
+            session_tokens.as_deref(),
+            &devices,
+            agent_pool,
+            auth_failures.as_deref(),
+            &client_ip,
+            timeouts.request,
+        )
+        .await?);
     }
 
     // Check if this is a webhook request (POST /webhook/<token>)
@@ -377,90 +960,179 @@ where
             webhook_resolver,
             webhook_rate_limiter,
             client_ip,
+            timeouts.request,
         )
         .await;
     }
     
-    // Cloudflare (and other proxies) strip the `Connection: upgrade` hop-by-hop header
-    // before forwarding WebSocket upgrade requests to the origin. tungstenite strictly
-    // requires `Connection: upgrade`, so we inject it if `Upgrade: websocket` is present.
-    let lower = request_str.to_ascii_lowercase();
-    let request_bytes = if lower.contains("upgrade: websocket") && !lower.contains("connection: upgrade") {
-        // Insert `Connection: upgrade` after the first header line (after the request line)
-        let mut patched = request_str.to_string();
-        if let Some(pos) = patched.find("\r\n") {
-            patched.insert_str(pos + 2, "Connection: upgrade\r\n");
-        }
-        patched.into_bytes()
-    } else {
-        request_data.to_vec()
-    };
+    // Restore the `Connection: upgrade` header proxies strip
+    let request_bytes = with_connection_upgrade(&request_str, request_data);
     
     // Otherwise, it's a WebSocket upgrade - we need to create a stream that
     // "unreads" the data we already consumed
     let prefixed_stream = PrefixedStream::new(request_bytes, stream);
     
     // Continue with WebSocket handling
-    handle_websocket_connection(prefixed_stream, agent_handle, auth_token, agent_pool, push_relay, working_dir, slash_commands, memory_path).await
+    handle_websocket_connection(prefixed_stream, agent_handle, auth_token, session_tokens, devices, pairing_manager, agent_pool, push_relay, auth_failures, client_ip, working_dir, sandboxes, slash_commands, memory_path, timeouts.upgrade, max_line_bytes, output_shaping, usage, client_auth, closing).await
+}
+
+/// How [`handle_pairing_request`] answered.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum PairingOutcome {
+    Paired(PairingDevice),
+    /// A wrong, expired or already used code
+    WrongCode,
+    /// Declined, rate limited, or no code to check
+    Refused,
 }
 
-/// Handle a pairing request - validate the code and return connection details
-async fn handle_pairing_request<S>(
+/// Handle a pairing request - validate the code and return connection details.
+/// Returns how it was answered, with the device if it paired.
+pub(crate) async fn handle_pairing_request<S>(
     stream: &mut S,
     request: &str,
     pairing_manager: Option<Arc<PairingManager>>,
-) -> Result<()>
+    auth_failures: Option<&AuthFailures>,
+    client_ip: &str,
+) -> Result<PairingOutcome>
 where
     S: AsyncWrite + Unpin,
 {
-    // Extract the code from the query string
+    // Extract the code from the query string, or the path of a short URL:
+    // GET /pair/local?code=123456&fp=... HTTP/1.1
+    // GET /p/123456?fp=... HTTP/1.1
     let code = request
         .lines()
         .next()
-        .and_then(|line| {
-            // GET /pair/local?code=123456&fp=... HTTP/1.1
-            let path_part = line.split_whitespace().nth(1)?;
-            let query = path_part.split('?').nth(1)?;
-            query
-                .split('&')
-                .find(|p| p.starts_with("code="))
-                .map(|p| p[5..].to_string())
-        });
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(crate::pairing::code_of)
+        .map(String::from);
 
     let Some(code) = code else {
         let response = create_http_response(400, "Bad Request", r#"{"error":"missing_code","message":"Missing 'code' query parameter"}"#);
         stream.write_all(response.as_bytes()).await?;
-        return Ok(());
+        return Ok(PairingOutcome::Refused);
     };
 
     let Some(manager) = pairing_manager else {
         let response = create_http_response(503, "Service Unavailable", r#"{"error":"pairing_disabled","message":"Pairing is not enabled on this bridge"}"#);
         stream.write_all(response.as_bytes()).await?;
-        return Ok(());
+        return Ok(PairingOutcome::Refused);
     };
 
-    // Validate the pairing code
-    match manager.validate(&code) {
+    // Validate the pairing code, then wait for approval if it's required
+    let device = PairingDevice::from_request(request, client_ip);
+    match manager.pair(&code, device.clone()).await {
         Ok(pairing_response) => {
-            info!("✅ Pairing successful");
+            info!("✅ Pairing successful: {}", device);
             let json = serde_json::to_string(&pairing_response).unwrap_or_default();
             let response = create_http_response(200, "OK", &json);
             stream.write_all(response.as_bytes()).await?;
+            Ok(PairingOutcome::Paired(device))
+        }
+        Err(PairingError::Declined) => {
+            warn!("🚫 Pairing declined for {}", client_ip);
+            let json = serde_json::to_string(&PairingErrorResponse::declined()).unwrap_or_default();
+            let response = create_http_response(403, "Forbidden", &json);
+            stream.write_all(response.as_bytes()).await?;
+            Ok(PairingOutcome::Refused)
         }
         Err(PairingError::RateLimited) => {
-            warn!("🚫 Pairing rate limited");
+            warn!("🚫 Pairing rate limited, code tried from {}", client_ip);
             let json = serde_json::to_string(&PairingErrorResponse::rate_limited()).unwrap_or_default();
             let response = create_http_response(429, "Too Many Requests", &json);
             stream.write_all(response.as_bytes()).await?;
+            Ok(PairingOutcome::Refused)
         }
         Err(_) => {
-            warn!("🚫 Invalid pairing code");
+            warn!("🚫 Invalid pairing code from {} ({} attempt(s) left)", client_ip, manager.attempts_left());
+            if let Some(failures) = auth_failures {
+                // A short URL's path is the code; don't log the guess
+                let path = request_path(request);
+                let path = if path.starts_with(crate::pairing::SHORT_PAIRING_PATH) { crate::pairing::SHORT_PAIRING_PATH } else { path };
+                failures.record(client_ip, path);
+            }
             let json = serde_json::to_string(&PairingErrorResponse::invalid_code()).unwrap_or_default();
             let response = create_http_response(401, "Unauthorized", &json);
             stream.write_all(response.as_bytes()).await?;
+            Ok(PairingOutcome::WrongCode)
         }
     }
+}
+
+/// Path of the request line in `request`, without the query string (which
+/// may carry a token or pairing code).
+pub(crate) fn request_path(request: &str) -> &str {
+    let target = request.lines().next().and_then(|l| l.split_whitespace().nth(1)).unwrap_or("");
+    target.split('?').next().unwrap_or(target)
+}
+
+/// `MEMORY.md` of `user`: `MEMORY.<user>.md` next to the owner's.
+pub(crate) fn user_memory_path(path: &Path, user: &str) -> PathBuf {
+    path.with_file_name(format!("MEMORY.{}.md", user))
+}
 
+/// What `presented` may do when the bridge expects `expected`: the auth token
+/// (or, when enabled, a session token derived from it) gets every scope; a
+/// configured device token (or its session token) gets the device's scopes.
+pub(crate) fn authenticate(
+    presented: &str,
+    expected: &str,
+    session_tokens: Option<&SessionTokens>,
+    devices: &DeviceTokens,
+) -> Option<Grant> {
+    let primary = match session_tokens {
+        Some(tokens) => tokens.accepts(presented),
+        None => bool::from(presented.as_bytes().ct_eq(expected.as_bytes())),
+    };
+    if primary {
+        Some(Grant::full(expected))
+    } else {
+        devices.grant(presented)
+    }
+}
+
+/// Handle `POST /session-token`: exchange the auth token or a device token
+/// for a new short-lived session token, or refresh a still-valid session
+/// token up to [`MAX_LIFETIME`](crate::session_token::MAX_LIFETIME) after
+/// the exchange.
+async fn handle_session_token_request<S>(
+    stream: &mut S,
+    request: &str,
+    session_tokens: Option<&SessionTokens>,
+    devices: &DeviceTokens,
+    auth_failures: Option<&AuthFailures>,
+    client_ip: &str,
+) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let Some(primary) = session_tokens else {
+        let response = create_http_response(404, "Not Found", r#"{"error":"session_tokens_disabled","message":"Session tokens are not enabled on this bridge"}"#);
+        stream.write_all(response.as_bytes()).await?;
+        return Ok(());
+    };
+
+    let presented = crate::streamable_http::client_token(request).unwrap_or_default();
+    let tokens = if primary.accepts(&presented) {
+        Some(primary)
+    } else {
+        devices.session_tokens_for(&presented)
+    };
+    let Some(issued) = tokens.and_then(|tokens| tokens.exchange(&presented)) else {
+        warn!("🚫 Session token request rejected: invalid or missing token");
+        if let Some(failures) = auth_failures {
+            failures.record(client_ip, request_path(request));
+        }
+        let response = create_http_response(401, "Unauthorized", r#"{"error":"unauthorized"}"#);
+        stream.write_all(response.as_bytes()).await?;
+        return Ok(());
+    };
+
+    info!("🎟️  Session token issued (expires in {}s)", issued.expires_in);
+    let json = serde_json::to_string(&issued).unwrap_or_default();
+    let response = create_http_response(200, "OK", &json);
+    stream.write_all(response.as_bytes()).await?;
     Ok(())
 }
 
@@ -482,6 +1154,7 @@ async fn handle_webhook_request<S>(
     resolver: Option<WebhookResolverFn>,
     rate_limiter: Arc<Mutex<TriggerRateLimiter>>,
     client_ip: String,
+    read_timeout: Duration,
 ) -> Result<()>
 where
     S: AsyncWrite + AsyncRead + Unpin,
@@ -558,7 +1231,7 @@ where
     let content_length: usize = headers_str
         .lines()
         .find(|l| l.to_ascii_lowercase().starts_with("content-length:"))
-        .and_then(|l| l.splitn(2, ':').nth(1))
+        .and_then(|l| l.split_once(':').map(|(_, v)| v))
         .and_then(|v| v.trim().parse().ok())
         .unwrap_or(0);
 
@@ -575,7 +1248,13 @@ where
         let remaining = content_length - body.len();
         let read_size = remaining.min(8192);
         let mut chunk = vec![0u8; read_size];
-        let n = stream.read(&mut chunk).await?;
+        let n = match tokio::time::timeout(read_timeout, stream.read(&mut chunk)).await {
+            Ok(result) => result?,
+            Err(_) => {
+                warn!("⏱️  Webhook body from {} stalled for {:?}, closing connection", client_ip, read_timeout);
+                return Ok(());
+            }
+        };
         if n == 0 {
             break;
         }
@@ -586,7 +1265,7 @@ where
     let content_type = headers_str
         .lines()
         .find(|l| l.to_ascii_lowercase().starts_with("content-type:"))
-        .and_then(|l| l.splitn(2, ':').nth(1))
+        .and_then(|l| l.split_once(':').map(|(_, v)| v))
         .map(|v| v.trim().to_string())
         .unwrap_or_else(|| "application/octet-stream".to_string());
 
@@ -596,7 +1275,7 @@ where
         if line.is_empty() {
             break;
         }
-        if let Some((k, v)) = line.splitn(2, ':').collect::<Vec<_>>().as_slice().get(0..2).and_then(|s| Some((s[0], s[1]))) {
+        if let Some((k, v)) = line.splitn(2, ':').collect::<Vec<_>>().as_slice().get(0..2).map(|s| (s[0], s[1])) {
             let key_lower = k.trim().to_ascii_lowercase();
             // Collect X-* headers and a few standard ones
             if key_lower.starts_with("x-")
@@ -731,8 +1410,70 @@ fn format_payload(body: &[u8], content_type: &str) -> String {
     String::from_utf8_lossy(body).into_owned()
 }
 
+/// How agent processes are attached to WebSocket connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PoolMode {
+    /// Agents are pooled per auth token and survive reconnects.
+    KeepAlive,
+    /// A fresh agent process per connection.
+    PerConnection,
+    /// The agent runs inside the bridge process.
+    InProcess,
+}
+
+impl PoolMode {
+    fn for_handle(agent_handle: &AgentHandle, pooled: bool) -> Self {
+        match agent_handle {
+            AgentHandle::InProcess { .. } => PoolMode::InProcess,
+            AgentHandle::Command(_) if pooled => PoolMode::KeepAlive,
+            AgentHandle::Command(_) => PoolMode::PerConnection,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            PoolMode::KeepAlive => "keepAlive",
+            PoolMode::PerConnection => "perConnection",
+            PoolMode::InProcess => "inProcess",
+        }
+    }
+}
+
+/// Version and feature set advertised via `GET /version` and the
+/// `bridge/capabilities` notification, so clients can feature-detect.
+fn bridge_capabilities(pool_mode: PoolMode, push: bool, session_tokens: bool) -> serde_json::Value {
+    let mut extensions = Vec::new();
+    if pool_mode == PoolMode::KeepAlive {
+        extensions.extend(["resume", "seqEnvelope", "streamableHttp"]);
+    }
+    if push {
+        extensions.push("push");
+        if pool_mode == PoolMode::KeepAlive {
+            extensions.push("pushActions");
+        }
+    }
+    if session_tokens {
+        extensions.push("sessionTokens");
+    }
+    serde_json::json!({
+        "version": crate::VERSION,
+        "extensions": extensions,
+        "poolMode": pool_mode.as_str(),
+        "protocols": WireVersion::names(WireVersion::supported(pool_mode == PoolMode::KeepAlive)),
+    })
+}
+
+/// Whether the request line carries a `token` query parameter.
+fn has_query_token(request_line: &str) -> bool {
+    request_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|target| target.split_once('?'))
+        .is_some_and(|(_, query)| query.split('&').any(|p| p.starts_with("token=")))
+}
+
 /// Create an HTTP response with the given status and body
-fn create_http_response(status_code: u16, status_text: &str, body: &str) -> String {
+pub(crate) fn create_http_response(status_code: u16, status_text: &str, body: &str) -> String {
     format!(
         "HTTP/1.1 {} {}\r\n\
          Content-Type: application/json\r\n\
@@ -809,60 +1550,74 @@ impl<S: AsyncWrite + Unpin> AsyncWrite for PrefixedStream<S> {
 }
 
 /// Handle WebSocket connection after initial HTTP parsing
-async fn handle_websocket_connection<S>(stream: S, agent_handle: AgentHandle, auth_token: Arc<Option<String>>, agent_pool: Option<Arc<tokio::sync::RwLock<AgentPool>>>, push_relay: Option<Arc<PushRelayClient>>, working_dir: PathBuf, slash_commands: Arc<Vec<SlashCommandConfig>>, memory_path: Option<PathBuf>) -> Result<()>
+#[allow(clippy::too_many_arguments)]
+async fn handle_websocket_connection<S>(stream: S, agent_handle: AgentHandle, auth_token: Arc<Option<String>>, session_tokens: Option<Arc<SessionTokens>>, devices: Arc<DeviceTokens>, pairing_manager: Option<Arc<PairingManager>>, agent_pool: Option<Arc<tokio::sync::RwLock<AgentPool>>>, push_relay: Option<Arc<PushRelayClient>>, auth_failures: Option<Arc<AuthFailures>>, client_ip: String, working_dir: PathBuf, sandboxes: Arc<Vec<SandboxConfig>>, slash_commands: Arc<Vec<SlashCommandConfig>>, memory_path: Option<PathBuf>, upgrade_timeout: Duration, max_line_bytes: usize, output_shaping: OutputShapingConfig, usage: Option<Arc<Usage>>, client_auth: ClientAuthConfig, closing: tokio::sync::watch::Receiver<Option<CloseReason>>) -> Result<()>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
     // Custom callback to validate auth token during WebSocket handshake
-    // We also extract the token value for pool-based routing
+    // We also extract the grant (pool key and scopes) for pool-based routing
     let auth_token_for_callback = Arc::clone(&auth_token);
-    let extracted_token = Arc::new(tokio::sync::Mutex::new(String::new()));
-    let extracted_token_clone = Arc::clone(&extracted_token);
+    let session_tokens_enabled = session_tokens.is_some();
+    let extracted_grant = Arc::new(tokio::sync::Mutex::new(None::<Grant>));
+    let extracted_grant_clone = Arc::clone(&extracted_grant);
     let extracted_client_id = Arc::new(tokio::sync::Mutex::new(String::new()));
     let extracted_client_id_clone = Arc::clone(&extracted_client_id);
-
-    let callback = move |req: &Request, response: Response| -> std::result::Result<Response, ErrorResponse> {
+    let extracted_device_key = Arc::new(tokio::sync::Mutex::new(None::<String>));
+    let extracted_device_key_clone = Arc::clone(&extracted_device_key);
+    let extracted_wire = Arc::new(tokio::sync::Mutex::new(WireVersion::default()));
+    let extracted_wire_clone = Arc::clone(&extracted_wire);
+    let keep_alive = PoolMode::for_handle(&agent_handle, agent_pool.is_some() && auth_token.is_some()) == PoolMode::KeepAlive;
+    // Upgrades without any token still owe a `bridge/auth` first message
+    let awaiting_auth = Arc::new(AtomicBool::new(false));
+    let awaiting_auth_clone = Arc::clone(&awaiting_auth);
+    let first_message_auth = (session_tokens.clone(), Arc::clone(&devices), auth_failures.clone(), client_ip.clone());
+
+    #[allow(clippy::result_large_err)]
+    let callback = move |req: &Request, mut response: Response| -> std::result::Result<Response, ErrorResponse> {
         if let Some(expected_token) = auth_token_for_callback.as_ref() {
-            // Check for auth token in headers
+            // Accept the auth token, a device token, or a session token derived
+            // from either, in the X-Bridge-Token header or, as a fallback, the
+            // query string
             let header_token = req.headers()
                 .get("X-Bridge-Token")
                 .and_then(|v| v.to_str().ok())
                 .map(|t| t.to_string());
+            let query_token = req.uri().query()
+                .filter(|_| client_auth.query_token)
+                .and_then(|q| {
+                    q.split('&')
+                        .find(|p| p.starts_with("token="))
+                        .map(|p| p[6..].to_string())
+                });
 
-            let token_valid = header_token.as_deref()
-                .map(|t| t == expected_token)
-                .unwrap_or(false);
-
-            // Also check query string as fallback
-            let query_token = if !token_valid {
-                req.uri().query()
-                    .and_then(|q| {
-                        q.split('&')
-                            .find(|p| p.starts_with("token="))
-                            .map(|p| p[6..].to_string())
-                    })
-            } else {
-                None
-            };
-
-            let query_token_valid = query_token.as_deref()
-                .map(|t| t == expected_token)
-                .unwrap_or(false);
-
-            if !token_valid && !query_token_valid {
+            let presented = [header_token, query_token];
+            if client_auth.first_message && presented.iter().all(Option::is_none) {
+                awaiting_auth_clone.store(true, Ordering::Relaxed);
+                return Ok(response);
+            }
+            let grant = presented
+                .iter()
+                .flatten()
+                .find_map(|t| authenticate(t, expected_token, session_tokens.as_deref(), &devices));
+
+            let Some(grant) = grant else {
+                if let Some(ref failures) = auth_failures {
+                    failures.record(&client_ip, req.uri().path());
+                }
                 let error_response = tokio_tungstenite::tungstenite::http::Response::builder()
                     .status(StatusCode::UNAUTHORIZED)
-                    .body(Some("Unauthorized: invalid or missing auth token".into()))
+                    .header("Content-Type", "application/json")
+                    .body(Some(r#"{"error":"unauthorized","message":"Invalid or missing auth token"}"#.into()))
                     .unwrap();
                 return Err(error_response);
-            }
+            };
 
-            // Store the validated token for pool routing
-            if let Some(t) = header_token.filter(|t| t == expected_token).or(query_token.filter(|t| t == expected_token)) {
-                // We can't await here (sync closure), so use try_lock
-                if let Ok(mut guard) = extracted_token_clone.try_lock() {
-                    *guard = t;
-                }
+            // Store the grant, keyed by the auth or device token (not the
+            // session token), so refreshed session tokens reach the same agent
+            // We can't await here (sync closure), so use try_lock
+            if let Ok(mut guard) = extracted_grant_clone.try_lock() {
+                *guard = Some(grant);
             }
         }
 
@@ -877,68 +1632,200 @@ where
             *guard = client_id;
         }
 
+        // X-Device-Id: the credentials key id the device was issued when it
+        // paired, naming it in the connection log
+        if let Some(key_id) = req.headers().get("X-Device-Id").and_then(|v| v.to_str().ok()) {
+            if let Ok(mut guard) = extracted_device_key_clone.try_lock() {
+                *guard = Some(key_id.to_string());
+            }
+        }
+
+        // Wire format generation (see `wire_protocol`)
+        let supported = WireVersion::supported(keep_alive);
+        let offered = req.headers().get_all("Sec-WebSocket-Protocol").iter().filter_map(|v| v.to_str().ok());
+        match wire_protocol::negotiate(offered, supported) {
+            Negotiation::Legacy => {}
+            Negotiation::Selected(version) => {
+                response.headers_mut().insert("Sec-WebSocket-Protocol", version.subprotocol().parse().unwrap());
+                if let Ok(mut guard) = extracted_wire_clone.try_lock() {
+                    *guard = version;
+                }
+            }
+            Negotiation::Unsupported { offered } => {
+                warn!("🚫 Client offered only unsupported protocols: {}", offered.join(", "));
+                let error_response = tokio_tungstenite::tungstenite::http::Response::builder()
+                    .status(StatusCode::UPGRADE_REQUIRED)
+                    .header("Content-Type", "application/json")
+                    .body(Some(wire_protocol::unsupported_body(&offered, supported)))
+                    .unwrap();
+                return Err(error_response);
+            }
+        }
+
         Ok(response)
     };
     
     // Upgrade to WebSocket with auth callback
-    let ws_stream = match tokio_tungstenite::accept_hdr_async(stream, callback).await {
-        Ok(ws) => ws,
-        Err(e) => {
+    let mut ws_stream = match tokio::time::timeout(upgrade_timeout, tokio_tungstenite::accept_hdr_async(stream, callback)).await {
+        Ok(Ok(ws)) => ws,
+        Ok(Err(e)) => {
             warn!("🚫 Connection rejected: {}", e);
-            return Err(anyhow::anyhow!("WebSocket handshake failed: {}", e));
+            return Err(BridgeError::Protocol { message: "WebSocket handshake failed".into(), source: Some(e.into()) });
+        }
+        Err(_) => {
+            warn!("⏱️  WebSocket upgrade did not complete within {:?}, closing stalled connection", upgrade_timeout);
+            return Ok(());
         }
     };
     
-    if auth_token.is_some() {
-        info!("🔓 Auth token validated");
+    // A client that couldn't set headers authenticates before anything is
+    // forwarded, or is closed
+    if awaiting_auth.load(Ordering::Relaxed) {
+        let (session_tokens, devices, auth_failures, client_ip) = first_message_auth;
+        let expected = auth_token.as_deref().unwrap_or_default();
+        let line = match tokio::time::timeout(client_auth.first_message_timeout(), ws_stream.next()).await {
+            Ok(Some(Ok(msg @ (Message::Text(_) | Message::Binary(_))))) => client_message(&msg.into_data()).ok(),
+            _ => None,
+        };
+        let (id, token) = line.as_deref().map(crate::line_listener::auth_request).unwrap_or_default();
+        match token.and_then(|t| authenticate(&t, expected, session_tokens.as_deref(), &devices)) {
+            Some(grant) => {
+                ws_stream.send(Message::Text(crate::line_listener::auth_reply(&id, true).into())).await
+                    .network_err("Failed to send bridge/auth reply")?;
+                *extracted_grant.lock().await = Some(grant);
+            }
+            None => {
+                if let Some(ref failures) = auth_failures {
+                    failures.record(&client_ip, "bridge/auth");
+                }
+                if line.is_some() {
+                    let _ = ws_stream.send(Message::Text(crate::line_listener::auth_reply(&id, false).into())).await;
+                }
+                warn!("🚫 No valid bridge/auth first message within {:?} — closing connection", client_auth.first_message_timeout());
+                let _ = ws_stream.close(Some(CloseReason::Unauthorized.frame())).await;
+                return Ok(());
+            }
+        }
+    }
+
+    // Get the pool key and scopes for routing
+    let grant = extracted_grant.lock().await.clone();
+    let client_token = grant.as_ref().map(|g| g.pool_key.clone()).unwrap_or_default();
+    let scopes = grant.as_ref().map_or_else(Scopes::all, |g| g.scopes);
+    let device_client_id = extracted_client_id.lock().await.clone();
+    let wire = *extracted_wire.lock().await;
+
+    let user = grant.as_ref().and_then(|g| g.user.as_deref());
+    match (user, grant.as_ref().and_then(|g| g.device.as_deref())) {
+        (Some(user), Some(device)) => info!("🔓 Device token validated (user {}, {}: {})", user, device, scopes.names().join(", ")),
+        (Some(user), None) => info!("🔓 Auth token of user {} validated", user),
+        (None, Some(device)) => info!("🔓 Device token validated ({}: {})", device, scopes.names().join(", ")),
+        (None, None) if auth_token.is_some() => info!("🔓 Auth token validated"),
+        (None, None) => {}
     }
+    let device_key = extracted_device_key.lock().await.clone();
+    let mut paired_name = None;
+    if let Some(key_id) = device_key {
+        match pairing_manager.as_ref().and_then(|m| m.paired_device(&key_id)) {
+            Some(paired) => {
+                info!("📱 Paired device: {} (paired {})", paired.device, paired.paired_at.format("%Y-%m-%d"));
+                paired_name = Some(paired.name.unwrap_or(paired.device));
+            }
+            None => debug!("📱 Unknown device key id {}", key_id),
+        }
+    }
+    let usage = usage.map(|usage| (usage, crate::usage::device_name(user, grant.as_ref().and_then(|g| g.device.as_deref()), paired_name.as_deref())));
+    // Each user appends to and loads a memory file of their own
+    let memory_path = match user {
+        Some(user) => memory_path.map(|path| user_memory_path(&path, user)),
+        None => memory_path,
+    };
 
     info!("✅ WebSocket connection established");
 
-    // Get the token value for pool routing
-    let client_token = extracted_token.lock().await.clone();
-    let device_client_id = extracted_client_id.lock().await.clone();
+    // Scopes are enforced by the pooled handler only
+    let pooled = agent_pool.is_some() && !client_token.is_empty();
+    let scope_enforced = pooled && matches!(agent_handle, AgentHandle::Command(_));
+    if !scopes.is_all() && !scope_enforced {
+        warn!("🚫 Scoped device tokens need keep-alive agent pooling — closing connection");
+        let _ = ws_stream.close(Some(CloseReason::ScopeUnsupported.frame())).await;
+        return Ok(());
+    }
+
+    // Advertise version and features before any agent traffic
+    let mut capabilities_params = bridge_capabilities(PoolMode::for_handle(&agent_handle, pooled), push_relay.is_some(), session_tokens_enabled);
+    capabilities_params["scopes"] = serde_json::json!(scopes.names());
+    capabilities_params["protocol"] = serde_json::json!(wire.subprotocol());
+    if let Some(pool) = agent_pool.as_ref().filter(|_| scope_enforced) {
+        let pool = pool.read().await;
+        if let Some(extensions) = capabilities_params["extensions"].as_array_mut() {
+            if !pool.workspaces().is_empty() {
+                extensions.push(serde_json::json!("workspaces"));
+            }
+            if pool.worktrees_enabled() {
+                extensions.push(serde_json::json!("worktrees"));
+            }
+        }
+    }
+    let capabilities = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "bridge/capabilities",
+        "params": capabilities_params,
+    });
+    ws_stream.send(Message::Text(capabilities.to_string().into())).await
+        .network_err("Failed to send bridge/capabilities")?;
 
     // Decide whether to use pool-based or legacy handling
     if let Some(pool) = agent_pool {
         if client_token.is_empty() {
             warn!("Keep-alive enabled but no auth token found, falling back to legacy mode");
-            handle_websocket_with_handle(ws_stream, agent_handle, push_relay, working_dir).await
+            handle_websocket_with_handle(ws_stream, agent_handle, push_relay, working_dir, &sandboxes, max_line_bytes).await
         } else {
             if let AgentHandle::Command(ref cmd) = agent_handle {
-                handle_websocket_pooled(ws_stream, cmd.clone(), client_token, pool, push_relay, working_dir.clone(), slash_commands, device_client_id, memory_path).await
+                handle_websocket_pooled(ws_stream, cmd.clone(), client_token, scopes, wire, pool, push_relay, working_dir.clone(), slash_commands, device_client_id, memory_path, output_shaping, usage, closing).await
             } else {
                 // InProcess handles don't support pooling yet; fall back to per-connection
-                handle_websocket_with_handle(ws_stream, agent_handle, push_relay, working_dir).await
+                handle_websocket_with_handle(ws_stream, agent_handle, push_relay, working_dir, &sandboxes, max_line_bytes).await
             }
         }
     } else {
-        handle_websocket_with_handle(ws_stream, agent_handle, push_relay, working_dir).await
+        handle_websocket_with_handle(ws_stream, agent_handle, push_relay, working_dir, &sandboxes, max_line_bytes).await
     }
 }
 
 /// Handle WebSocket connection with agent pool (keep-alive mode)
+#[allow(clippy::too_many_arguments)]
 async fn handle_websocket_pooled<S>(
     ws_stream: tokio_tungstenite::WebSocketStream<S>,
     agent_command: String,
     token: String,
+    scopes: Scopes,
+    wire: WireVersion,
     pool: Arc<tokio::sync::RwLock<AgentPool>>,
     push_relay: Option<Arc<PushRelayClient>>,
     _working_dir: PathBuf,
     slash_commands: Arc<Vec<SlashCommandConfig>>,
     device_client_id: String,
     memory_path: Option<PathBuf>,
+    output_shaping: OutputShapingConfig,
+    usage: Option<(Arc<Usage>, String)>,
+    mut closing: tokio::sync::watch::Receiver<Option<CloseReason>>,
 ) -> Result<()>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
     let (mut ws_sender, mut ws_receiver) = ws_stream.split();
 
-    // Get or spawn agent from pool
-    let (ws_to_agent_tx, mut agent_to_ws_rx, buffered, was_reused, cached_init, cached_session, broadcast_tx) = {
-        let mut pool = pool.write().await;
-        pool.get_or_spawn(&token, &agent_command).await?
-    };
+    // Get or spawn agent from pool. This connection takes over delivering
+    // the agent's output; one it replaced leaves what this one receives to it
+    let ((ws_to_agent_tx, mut agent_to_ws_rx, buffered, was_reused, cached_init, cached_session, agent_output), subscribed_through, slot, delivery) =
+        match AgentPool::connect_client(&pool, &token, &agent_command).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                let _ = ws_sender.send(Message::Close(Some(CloseReason::AgentUnavailable.frame()))).await;
+                return Err(e);
+            }
+        };
     
     if was_reused {
         info!("♻️  Reconnected to existing agent session");
@@ -950,43 +1837,92 @@ where
     // Set to true only when reusing an agent with a session/load (resume) — memory already in context.
     let mut initial_memory_injected = false;
 
-    // If reconnecting and we have a cached initialize response, intercept the
-    // client's `initialize` request and reply with the cached response.
-    // This prevents the agent from being re-initialized and losing its state.
-    if was_reused {
-        if let Some(ref cached) = cached_init {
-            info!("🔄 Intercepting initialize for session resumption");
-            // Wait for the client's first message (should be `initialize`)
-            let init_handled = handle_initialize_intercept(
-                &mut ws_receiver, &mut ws_sender, cached
-            ).await;
-            if init_handled {
-                info!("✅ Initialize intercepted, session state preserved");
-            } else {
-                warn!("⚠️  First message was not initialize, proceeding normally");
+    // On reconnect the client's first message is either an explicit
+    // `bridge/resume` handshake or a legacy `initialize`. Resume replays from the
+    // client's last sequence number and skips the intercept heuristics below.
+    let mut first_msg = None;
+    let mut resumed = false;
+    // Agent messages up to this sequence number were already replayed by
+    // `bridge/resume`; the live receiver skips them to avoid duplicates.
+    let mut replayed_through = 0;
+    let seq_envelope = Arc::new(AtomicBool::new(wire.seq_envelope()));
+    if was_reused && cached_init.is_some() {
+        first_msg = read_client_text(&mut ws_receiver).await;
+        if let Some(mut req) = first_msg.as_deref().and_then(parse_resume_request) {
+            req.seq_envelope |= wire.seq_envelope();
+            seq_envelope.store(req.seq_envelope, Ordering::Relaxed);
+            if let Some(through) = handle_resume_request(&mut ws_sender, &req, &token, &pool, &agent_output, cached_session.as_deref()).await {
+                resumed = true;
+                replayed_through = through;
             }
-        } else {
-            debug!("No cached initialize response, first connection will capture it");
+            // A rejected resume was answered with an error and the client falls
+            // back to `initialize`, so the intercepts read a fresh message.
+            first_msg = None;
         }
+    }
 
-        // Also intercept session requests (session/new or session/load) to reuse the same session ID
-        if let Some(ref cached) = cached_session {
-            info!("🔄 Intercepting session request for session resumption");
-            let (session_handled, reuse_was_new_session) = handle_create_session_intercept(
-                &mut ws_receiver, &mut ws_sender, cached, &slash_commands
-            ).await;
-            if session_handled {
-                info!("✅ Session request intercepted, reusing existing session (was_new={})", reuse_was_new_session);
-            } else {
-                warn!("⚠️  Next message was not a session request, proceeding normally");
+    if resumed {
+        info!("✅ Session resumed via bridge/resume");
+        initial_memory_injected = true;
+    }
+
+    // If reconnecting (or handed a pre-initialized warm agent) and we have a
+    // cached initialize response, answer the client's `initialize` (and, on
+    // reconnect, its session request) from the cache, so the agent is not
+    // re-initialized and keeps its state. Other messages are queued and
+    // reach the agent in order once Task 1 starts.
+    let intercept_config = pool.read().await.intercept_config(&slot.agent_command);
+    let mut queued = Vec::new();
+    if !resumed {
+        let mut intercept = Intercept::new(intercept_config.clone(), cached_init.clone(), cached_session.clone().filter(|_| was_reused));
+        let mut next = first_msg.take();
+        while !intercept.is_done() {
+            let msg = match next.take() {
+                Some(msg) => msg,
+                None => match read_client_text(&mut ws_receiver).await {
+                    Some(msg) => msg,
+                    None => break,
+                },
+            };
+            for reply in intercept.feed(msg) {
+                if let Err(e) = ws_sender.send(Message::Text(reply.into())).await {
+                    error!("Failed to send cached response: {}", e);
+                }
+            }
+        }
+        if intercept.initialize_intercepted() {
+            info!("✅ Initialize intercepted, session state preserved");
+        } else if cached_init.is_none() && was_reused {
+            debug!("No cached initialize response, first connection will capture it");
+        }
+        if was_reused {
+            match (&cached_session, intercept.session_intercepted()) {
+                (Some(_), Some(_)) if intercept.session_loaded_by_agent() => {
+                    info!("✅ Session load sent to the agent, which replays its own state");
+                }
+                (Some(cached), Some(was_new)) => {
+                    info!("✅ Session request intercepted, reusing existing session (was_new={})", was_new);
+                    // Inject available_commands_update so clients get the command picker
+                    // even when the agent doesn't send this notification itself.
+                    if let Some(session_id) = extract_session_id_from_response(cached).filter(|_| !slash_commands.is_empty()) {
+                        let notification = build_available_commands_notification(&session_id, &slash_commands);
+                        info!("📋 Injecting available_commands_update for cached session {}", session_id);
+                        let _ = ws_sender.send(Message::Text(notification.into())).await;
+                    }
+                }
+                (Some(_), None) => warn!("⚠️  Next message was not a session request, proceeding normally"),
+                (None, _) => debug!("No cached session response, first connection will capture it"),
             }
             // Re-inject memory when the client explicitly reset (session/new).
             // Skip re-injection on session/load (resume) — memory is already in context.
-            initial_memory_injected = !reuse_was_new_session;
-        } else {
-            debug!("No cached session response, first connection will capture it");
+            if cached_session.is_some() {
+                initial_memory_injected = intercept.session_intercepted() != Some(true);
+            }
         }
+        queued = intercept.into_queued();
+    }
 
+    if was_reused && !resumed {
         // Replay buffered messages after session is fully re-established so the
         // client has a valid session context to process them.
         let total = buffered.len();
@@ -994,6 +1930,15 @@ where
             info!("📦 [push-dbg] Replaying {} buffered message(s) after session resume", total);
             for (i, msg) in buffered.into_iter().enumerate() {
                 info!("📦 [push-dbg] Buffered [{}/{}] ({}B): {}", i + 1, total, msg.len(), msg.chars().take(200).collect::<String>());
+                if let Some((ref usage, ref device)) = usage {
+                    if let Verdict::Reached(message) = usage.record_agent(device, &msg) {
+                        warn!("📊 {}", message);
+                    }
+                }
+                // Buffered messages may have been merged or dropped, so v2
+                // envelopes carry the last sequence number they cover: acking
+                // it after the replay acknowledges the whole buffer.
+                let msg = if wire.seq_envelope() { seq_envelope_wrap(subscribed_through, &msg) } else { msg };
                 if let Err(e) = ws_sender.send(Message::Text(msg.into())).await {
                     error!("Failed to replay buffered message: {}", e);
                 }
@@ -1029,8 +1974,6 @@ where
     // For a fresh connection, we need to capture the initialize response
     // from the agent so we can cache it for future reconnections.
     let needs_init_capture = !was_reused;
-    let token_for_capture = token.clone();
-    let pool_for_capture = Arc::clone(&pool);
 
     // Track the request ID of `session/new` so Task 2 can identify the response
     // regardless of the response shape (some agents don't return `sessionId`).
@@ -1063,28 +2006,66 @@ where
     let suppress_response_id: Arc<std::sync::Mutex<Option<String>>> =
         Arc::new(std::sync::Mutex::new(None));
 
+    // Agents run in the token's worktree or selected workspace; sessions are
+    // pointed there too. When `bridge/selectWorkspace`, `bridge/session/finish`
+    // or `bridge/endSession` stops the agent, Task 1 tells Task 2 to close the
+    // connection, with the code and reason, once the answer is sent.
+    let session_dir = pool.read().await.session_dir(&token);
+    let (agent_stopped_tx, mut agent_stopped_rx) = mpsc::channel::<CloseReason>(1);
+
     // Task 1: WebSocket → Agent (via channel)
     let ws_to_agent_tx_clone = ws_to_agent_tx.clone();
-    let broadcast_tx_for_task1 = broadcast_tx.clone();
+    let pool_task1 = Arc::clone(&pool);
+    let token_task1 = token.clone();
+    let output_for_task1 = agent_output.clone();
+    let seq_envelope_task1 = Arc::clone(&seq_envelope);
     let device_client_id_for_task1 = device_client_id.clone();
     let push_relay_for_register = push_relay.clone();
     let memory_path_for_task1 = memory_path.clone();
     let current_session_id_task1 = Arc::clone(&current_session_id);
     let suppress_response_id_task1 = Arc::clone(&suppress_response_id);
+    let usage_task1 = usage.clone();
     let mut ws_to_agent = tokio::spawn(async move {
         // True once memory has been prepended to the first session/prompt of this connection.
         // Pre-set to true for reused agents resuming an existing session (session/load) since
         // memory is already in context. False for fresh agents or session/new resets.
         let mut memory_injected = initial_memory_injected;
+        // Messages queued during the intercepts go first, in arrival order
+        let queued = futures_util::stream::iter(queued.into_iter().map(|text| Ok(Message::Text(text.into()))));
+        let mut ws_receiver = queued.chain(ws_receiver);
         while let Some(msg_result) = ws_receiver.next().await {
             match msg_result {
                 Ok(msg) => {
                     if msg.is_text() || msg.is_binary() {
-                        let data = msg.into_data();
-                        let mut text = String::from_utf8_lossy(&data).to_string();
+                        let mut text = match client_message(&msg.into_data()) {
+                            Ok(text) => text,
+                            Err(e) => {
+                                warn!("📥 Refused a message from Mobile: {}", e);
+                                let _ = inject_tx.send(e.response()).await;
+                                continue;
+                            }
+                        };
                         debug!("📥 Received from Mobile ({} bytes): {}", text.len(),
                             text.chars().take(200).collect::<String>());
 
+                        // Scoped device tokens: answer requests outside the token's
+                        // scopes here, and hide client capabilities it may not use.
+                        if !scopes.is_all() {
+                            if let Ok(mut v) = serde_json::from_str::<serde_json::Value>(&text) {
+                                if let Some(scope) = scopes.denies(&v) {
+                                    warn!("🚫 {} denied: token lacks the '{}' scope",
+                                        v.get("method").and_then(|m| m.as_str()).unwrap_or_default(), scope.as_str());
+                                    if let Some(id) = v.get("id") {
+                                        let _ = inject_tx.send(denied_response(id, scope)).await;
+                                    }
+                                    continue;
+                                }
+                                if scopes.restrict_initialize(&mut v) {
+                                    text = v.to_string();
+                                }
+                            }
+                        }
+
                         // Intercept bridge/registerPushToken and bridge/unregisterPushToken.
                         // These are bridge-protocol messages; never forward them to the agent.
                         if let Ok(v) = serde_json::from_str::<serde_json::Value>(&text) {
@@ -1095,13 +2076,15 @@ where
                                         let platform = params.get("platform").and_then(|p| p.as_str()).unwrap_or("");
                                         let device_token = params.get("deviceToken").and_then(|t| t.as_str()).unwrap_or("");
                                         let bundle_id = params.get("bundleId").and_then(|b| b.as_str()).unwrap_or("");
+                                        // The paired device's credentials key id, so `bridge devices --paired` can show its last push
+                                        let key_id = params.get("keyId").and_then(|k| k.as_str()).map(String::from);
                                         info!("📲 Registering push token: platform={}, bundle_id={}, token={}", platform, bundle_id, device_token);
                                         let relay = Arc::clone(relay);
                                         let platform = platform.to_string();
                                         let device_token = device_token.to_string();
                                         let bundle_id = bundle_id.to_string();
                                         tokio::spawn(async move {
-                                            if let Err(e) = relay.register_device(&device_token, &platform, Some(&bundle_id)).await {
+                                            if let Err(e) = relay.register_device(&device_token, &platform, Some(&bundle_id), key_id.as_deref()).await {
                                                 error!("Failed to register push token: {}", e);
                                             } else {
                                                 info!("✅ Push token registered successfully");
@@ -1111,6 +2094,100 @@ where
                                 }
                                 continue; // Always skip — never forward to agent
                             }
+                            // A `bridge/resume` that reaches this point can't be honoured
+                            // (fresh agent, or sent after the handshake) — reject it so the
+                            // client falls back to `initialize`.
+                            if method == Some("bridge/resume") {
+                                if let Some(id) = v.get("id") {
+                                    let _ = inject_tx.send(resume_error(id, "No resumable session for this connection")).await;
+                                }
+                                continue;
+                            }
+                            // Sequencing capability: once enabled, every agent message is
+                            // wrapped as {"seq": N, "msg": ...} and the client acks with
+                            // bridge/ack so a later bridge/resume replays from that point.
+                            if method == Some("bridge/negotiate") {
+                                // v2 clients always get envelopes
+                                let enable = wire.seq_envelope()
+                                    || v.pointer("/params/capabilities/seqEnvelope")
+                                        .and_then(|b| b.as_bool())
+                                        .unwrap_or(false);
+                                seq_envelope_task1.store(enable, Ordering::Relaxed);
+                                info!("🔢 Sequence envelopes {}", if enable { "enabled" } else { "disabled" });
+                                if let Some(id) = v.get("id") {
+                                    let response = serde_json::json!({
+                                        "jsonrpc": "2.0",
+                                        "id": id,
+                                        "result": {
+                                            "capabilities": { "seqEnvelope": enable },
+                                            "ackedSeq": output_for_task1.acked_seq(),
+                                        }
+                                    });
+                                    let _ = inject_tx.send(response.to_string()).await;
+                                }
+                                continue;
+                            }
+                            if method == Some("bridge/ack") {
+                                if let Some(seq) = v.pointer("/params/seq").and_then(|s| s.as_u64()) {
+                                    output_for_task1.ack(seq);
+                                }
+                                continue;
+                            }
+                            if method == Some("bridge/listWorkspaces") {
+                                if let Some(id) = v.get("id") {
+                                    let response = list_workspaces_response(id, &*pool_task1.read().await, &token_task1);
+                                    let _ = inject_tx.send(response).await;
+                                }
+                                continue;
+                            }
+                            if method == Some("bridge/selectWorkspace") {
+                                let name = v.pointer("/params/name").and_then(|n| n.as_str()).unwrap_or_default();
+                                let selection = pool_task1.write().await.select_workspace(&token_task1, name).await;
+                                match selection {
+                                    Ok(ref s) => info!("📂 Workspace '{}' selected ({})", s.workspace.name, s.workspace.path.display()),
+                                    Err(ref e) => warn!("📂 Workspace selection refused: {}", e),
+                                }
+                                if let Some(id) = v.get("id") {
+                                    let _ = inject_tx.send(select_workspace_response(id, &selection)).await;
+                                }
+                                if selection.is_ok_and(|s| s.restarted) {
+                                    let _ = agent_stopped_tx.try_send(CloseReason::WorkspaceChanged);
+                                }
+                                continue;
+                            }
+                            if method == Some("bridge/session/finish") {
+                                let Some(action) = v.pointer("/params/action").and_then(|a| serde_json::from_value::<FinishAction>(a.clone()).ok()) else {
+                                    if let Some(id) = v.get("id") {
+                                        let _ = inject_tx.send(finish_session_response(id, &Err(BridgeError::protocol("action must be \"discard\" or \"branch\"")))).await;
+                                    }
+                                    continue;
+                                };
+                                let message = v.pointer("/params/message").and_then(|m| m.as_str()).unwrap_or("Work from a bridge session");
+                                let outcome = AgentPool::finish_session(&pool_task1, &token_task1, action, message).await;
+                                if let Err(ref e) = outcome {
+                                    warn!("🌿 Finishing worktree failed: {:#}", e);
+                                }
+                                if let Some(id) = v.get("id") {
+                                    let _ = inject_tx.send(finish_session_response(id, &outcome)).await;
+                                }
+                                if outcome.is_ok() {
+                                    let _ = agent_stopped_tx.try_send(CloseReason::SessionFinished);
+                                }
+                                continue;
+                            }
+                            if method == Some("bridge/endSession") {
+                                let ended = AgentPool::end_session(&pool_task1, &token_task1).await;
+                                if let Err(ref e) = ended {
+                                    warn!("👋 Ending session refused: {}", e);
+                                }
+                                if let Some(id) = v.get("id") {
+                                    let _ = inject_tx.send(end_session_response(id, &ended)).await;
+                                }
+                                if ended.is_ok() {
+                                    let _ = agent_stopped_tx.try_send(CloseReason::SessionEnded);
+                                }
+                                continue;
+                            }
                             if method == Some("bridge/unregisterPushToken") {
                                 if let Some(ref relay) = push_relay_for_register {
                                     if let Some(params) = v.get("params") {
@@ -1205,7 +2282,35 @@ where
                                 continue; // don't forward original notification to agent
                             }
                         }
-                        
+
+                        // Daily usage of this device ([usage]); over budget,
+                        // prompts may be refused.
+                        if let Some((ref usage, ref device)) = usage_task1 {
+                            match usage.record_client(device, &text) {
+                                Verdict::Allow => {}
+                                Verdict::Reached(message) => warn!("📊 {}", message),
+                                Verdict::Deny(response) => {
+                                    warn!("📊 session/prompt from {} refused: over its daily budget", device);
+                                    let _ = inject_tx.send(response).await;
+                                    continue;
+                                }
+                            }
+                        }
+
+                        // Sessions follow the worktree or selected workspace,
+                        // whatever directory the app last knew.
+                        if let Some(ref dir) = session_dir {
+                            if let Ok(mut v) = serde_json::from_str::<serde_json::Value>(&text) {
+                                let method = v.get("method").and_then(|m| m.as_str());
+                                if intercept_config.is_new_session(method) || intercept_config.is_load_session(method) {
+                                    if let Some(params) = v.get_mut("params").and_then(|p| p.as_object_mut()) {
+                                        params.insert("cwd".to_string(), serde_json::json!(dir));
+                                        text = v.to_string();
+                                    }
+                                }
+                            }
+                        }
+
                         // On fresh agents, intercept session/load and return a
                         // synthetic error. A just-spawned agent has no sessions to
                         // load, and some agents (e.g. Goose) hang on unknown
@@ -1216,7 +2321,7 @@ where
                         if needs_init_capture {
                             if let Ok(v) = serde_json::from_str::<serde_json::Value>(&text) {
                                 let method = v.get("method").and_then(|m| m.as_str());
-                                if method == Some("session/load") {
+                                if intercept_config.is_load_session(method) {
                                     if let Some(req_id) = v.get("id") {
                                         let session_id = v.pointer("/params/sessionId")
                                             .and_then(|s| s.as_str())
@@ -1237,7 +2342,7 @@ where
                                     continue; // Don't forward session/load to agent
                                 }
                                 // Track session/new request IDs
-                                if method == Some("session/new") {
+                                if intercept_config.is_new_session(method) {
                                     if let Some(id) = v.get("id") {
                                         info!("📋 Tracking session/new request id={}", id);
                                         if let Ok(mut guard) = pending_session_req_id_writer.lock() {
@@ -1291,7 +2396,7 @@ where
                                         }
                                     });
                                     if let Ok(echo_str) = serde_json::to_string(&echo) {
-                                        let _ = broadcast_tx_for_task1.send(echo_str);
+                                        let _ = output_for_task1.publish(echo_str);
                                     }
                                 }
                             }
@@ -1321,16 +2426,17 @@ where
     
     // Task 2: Agent → WebSocket (via broadcast channel)
     let shutdown_tx_clone = shutdown_tx.clone();
-    let token_for_buffer = token.clone();
-    let pool_for_buffer = Arc::clone(&pool);
-    let agent_name_for_push = {
-        let pool = pool.read().await;
-        pool.get_agent_name(&token)
-    };
+    let slot_for_task2 = Arc::clone(&slot);
+    let agent_name_for_push = Arc::clone(&slot.agent_name);
     let current_session_id_task2 = Arc::clone(&current_session_id);
+    let current_session_id_push = Arc::clone(&current_session_id);
     let suppress_response_id_task2 = Arc::clone(&suppress_response_id);
     let memory_path_for_task2 = memory_path.clone();
+    let seq_envelope_task2 = Arc::clone(&seq_envelope);
+    let ws_to_agent_tx_task2 = ws_to_agent_tx.clone();
     let agent_to_ws = tokio::spawn(async move {
+        // Sequence number of the next message from the broadcast receiver.
+        let mut next_seq = subscribed_through + 1;
         let mut init_captured = false;
         let mut session_captured = false;
         // Accumulates plain text extracted from suppressed memory-update responses.
@@ -1341,19 +2447,76 @@ where
         // connection is treated as dead and closed (frees the rate-limiter slot).
         let mut ping_interval = tokio::time::interval(Duration::from_secs(30));
         ping_interval.tick().await; // skip the immediate first tick
+        let mut shaper = OutputShaper::new(&output_shaping);
+        // Buffer what the client didn't get, and tell the phone there's news.
+        // Messages a newer connection receives are left to it.
+        let client_gone = |unsent: Vec<(u64, String)>| {
+            let unsent: Vec<String> = unsent
+                .into_iter()
+                .filter(|(seq, line)| slot_for_task2.buffer_unsent(*seq, line.clone(), delivery))
+                .map(|(_, line)| line)
+                .collect();
+            if unsent.is_empty() {
+                return;
+            }
+            let permission = unsent.iter().find_map(|line| crate::push::permission_request(line));
+            let stop_reason = unsent.iter().rev().find_map(|line| crate::push::stop_reason(line));
+            let session_id = current_session_id_push.lock().ok().and_then(|guard| guard.clone());
+            if let Some(ref relay) = push_relay {
+                info!("[push-dbg] triggering push via relay (active-connection-drop path)");
+                let relay = Arc::clone(relay);
+                let name = agent_name_for_push.clone();
+                tokio::spawn(async move {
+                    let agent_name = name.read().await.clone();
+                    let sent = if let Some(request) = permission {
+                        relay.notify_permission_request(&agent_name, Some(&request)).await
+                    } else if let Some(reason) = stop_reason {
+                        relay.notify_turn_ended(&agent_name, session_id.as_deref(), &reason).await
+                    } else {
+                        relay.notify(&agent_name, session_id.as_deref()).await
+                    };
+                    match sent {
+                        Ok(sent) => info!("[push-dbg] push relay notify: sent={}", sent),
+                        Err(e) => warn!("[push-dbg] push relay notify failed: {}", e),
+                    }
+                });
+            } else {
+                info!("[push-dbg] no push relay configured — push skipped");
+            }
+        };
         loop {
+            let wake = shaper.as_ref().and_then(OutputShaper::ready_at);
+            let shaper_full = shaper.as_ref().is_some_and(OutputShaper::is_full);
             tokio::select! {
-                result = agent_to_ws_rx.recv() => { match result {
+                result = agent_to_ws_rx.recv(), if !shaper_full => { match result {
                 Ok(line) => {
-                    // On first connection, capture the initialize response
-                    if needs_init_capture && !init_captured {
-                        if is_initialize_response(&line) {
-                            info!("📋 Captured initialize response for future reconnections");
-                            let mut pool = pool_for_capture.write().await;
-                            pool.cache_init_response(&token_for_capture, line.clone());
-                            init_captured = true;
+                    let seq = next_seq;
+                    next_seq += 1;
+                    if seq <= replayed_through {
+                        debug!("Skipping seq {} (already replayed by bridge/resume)", seq);
+                        continue;
+                    }
+
+                    // Refuse agent requests outside the token's scopes (file
+                    // access, terminals) on the client's behalf.
+                    if let Some((scope, id)) = scopes.denied_request(&line) {
+                        warn!("🚫 Agent request denied: token lacks the '{}' scope", scope.as_str());
+                        let _ = ws_to_agent_tx_task2.send(denied_response(&id, scope)).await;
+                        continue;
+                    }
+
+                    if let Some((ref usage, ref device)) = usage {
+                        if let Verdict::Reached(message) = usage.record_agent(device, &line) {
+                            warn!("📊 {}", message);
                         }
                     }
+
+                    // On first connection, capture the initialize response
+                    if needs_init_capture && !init_captured && is_initialize_response(&line) {
+                        info!("📋 Captured initialize response for future reconnections");
+                        slot_for_task2.cache_init_response(line.clone());
+                        init_captured = true;
+                    }
                     
                     // On first connection, capture the createSession response.
                     // First try matching by response shape (result.sessionId), then
@@ -1385,8 +2548,7 @@ where
                         };
                         if is_session_resp {
                             info!("📋 Captured createSession response for future reconnections");
-                            let mut pool = pool_for_capture.write().await;
-                            pool.cache_session_response(&token_for_capture, line.clone());
+                            slot_for_task2.cache_session_response(line.clone());
                             session_captured = true;
                             // Store session ID so Task 1 can send silent memory-update prompts.
                             if let Some(sid) = extract_session_id_from_response(&line) {
@@ -1404,8 +2566,7 @@ where
                         if let Ok(v) = serde_json::from_str::<serde_json::Value>(&line) {
                             if v.get("error").is_some() {
                                 warn!("🗑️ Agent reported 'Session not found' — invalidating cached session");
-                                let mut pool = pool_for_capture.write().await;
-                                pool.clear_session_response(&token_for_capture);
+                                slot_for_task2.clear_session_response();
                             }
                         }
                     }
@@ -1468,55 +2629,68 @@ where
                         && is_create_session_response(&line)
                         && !line.contains("\"error\"");
 
-                    info!("[push-dbg] ws_sender.send() attempting ({} bytes)", line.len());
-                    debug!("📤 Sending to Mobile ({} bytes): {}", line.len(),
-                        line.chars().take(200).collect::<String>());
-
-                    if let Err(e) = ws_sender.send(Message::Text(line.clone().into())).await {
-                        info!("[push-dbg] ws_sender.send() FAILED — client disconnected: {}", e);
-                        let mut pool = pool_for_buffer.write().await;
-                        pool.buffer_message(&token_for_buffer, line);
-                        // Send push notification since client is disconnected
-                        if let Some(ref relay) = push_relay {
-                            info!("[push-dbg] triggering push via relay (active-connection-drop path)");
-                            let relay = Arc::clone(relay);
-                            let name = agent_name_for_push.clone();
-                            tokio::spawn(async move {
-                                let agent_name = name.read().await.clone();
-                                match relay.notify(&agent_name).await {
-                                    Ok(sent) => info!("[push-dbg] push relay notify: sent={}", sent),
-                                    Err(e) => warn!("[push-dbg] push relay notify failed: {}", e),
-                                }
-                            });
-                        } else {
-                            info!("[push-dbg] no push relay configured — push skipped");
+                    let commands_session = inject_commands.then(|| extract_session_id_from_response(&line)).flatten();
+
+                    // With [output_shaping], streaming updates wait in the shaper and
+                    // are sent by the timer arm below; anything else goes out now,
+                    // after whatever the shaper holds.
+                    // The agent exited on its own (see `crash_report`)
+                    let crashed = line.contains("\"bridge/agentCrashed\"");
+                    let batch = match shaper {
+                        Some(ref mut shaper) if OutputShaper::shapes(&line) => {
+                            shaper.push(seq, line);
+                            continue;
                         }
+                        Some(ref mut shaper) => {
+                            let mut batch = shaper.flush();
+                            batch.push((seq, line));
+                            batch
+                        }
+                        None => vec![(seq, line)],
+                    };
+                    if let Err(unsent) = send_agent_lines(&mut ws_sender, batch, seq_envelope_task2.load(Ordering::Relaxed)).await {
+                        client_gone(unsent);
+                        break;
+                    }
+                    if crashed {
+                        let _ = ws_sender.send(Message::Close(Some(CloseReason::AgentExited.frame()))).await;
                         break;
                     }
-                    info!("[push-dbg] ws_sender.send() OK — message delivered to connected client");
 
                     // Inject available_commands_update immediately after the session
                     // response so clients that connect to agents without native support
                     // (e.g. Copilot CLI) still get the command picker populated.
-                    if inject_commands {
-                        if let Some(session_id) = extract_session_id_from_response(&line) {
-                            let notification = build_available_commands_notification(
-                                &session_id, &slash_commands,
-                            );
-                            info!("📋 Injecting available_commands_update for session {}", session_id);
-                            let _ = ws_sender.send(Message::Text(notification.into())).await;
-                        }
+                    if let Some(session_id) = commands_session {
+                        let notification = build_available_commands_notification(
+                            &session_id, &slash_commands,
+                        );
+                        info!("📋 Injecting available_commands_update for session {}", session_id);
+                        let _ = ws_sender.send(Message::Text(notification.into())).await;
                     }
                 }
                 Err(broadcast::error::RecvError::Lagged(n)) => {
+                    next_seq += n;
                     warn!("Agent-to-WS receiver lagged, skipped {} messages", n);
                     continue;
                 }
                 Err(broadcast::error::RecvError::Closed) => {
                     debug!("Agent broadcast channel closed (agent exited)");
+                    if let Some(ref mut shaper) = shaper {
+                        let _ = send_agent_lines(&mut ws_sender, shaper.flush(), seq_envelope_task2.load(Ordering::Relaxed)).await;
+                    }
+                    let _ = ws_sender.send(Message::Close(Some(CloseReason::AgentExited.frame()))).await;
                     break;
                 }
             } } // end match result / end recv arm
+            _ = tokio::time::sleep_until(wake.unwrap_or_else(tokio::time::Instant::now)), if wake.is_some() => {
+                let Some(ref mut shaper) = shaper else { continue };
+                let now = tokio::time::Instant::now();
+                let batch: Vec<_> = std::iter::from_fn(|| shaper.pop_ready(now)).collect();
+                if let Err(unsent) = send_agent_lines(&mut ws_sender, batch, seq_envelope_task2.load(Ordering::Relaxed)).await {
+                    client_gone(unsent);
+                    break;
+                }
+            }
             Some(injected) = inject_rx.recv() => {
                 // Synthetic response injected by Task 1 (e.g., session/load error)
                 debug!("📤 Sending injected response to Mobile ({} bytes)", injected.len());
@@ -1525,6 +2699,27 @@ where
                     break;
                 }
             }
+            Some(reason) = agent_stopped_rx.recv() => {
+                // Flush the answer that stopped the agent, then send the client
+                // off to reconnect to a new one.
+                if let Some(ref mut shaper) = shaper {
+                    let _ = send_agent_lines(&mut ws_sender, shaper.flush(), seq_envelope_task2.load(Ordering::Relaxed)).await;
+                }
+                while let Ok(injected) = inject_rx.try_recv() {
+                    let _ = ws_sender.send(Message::Text(injected.into())).await;
+                }
+                let _ = ws_sender.send(Message::Close(Some(reason.frame()))).await;
+                break;
+            }
+            Ok(()) = closing.changed() => {
+                let Some(reason) = *closing.borrow_and_update() else { continue };
+                info!("👋 Closing connection: {}", reason.name());
+                if let Some(ref mut shaper) = shaper {
+                    let _ = send_agent_lines(&mut ws_sender, shaper.flush(), seq_envelope_task2.load(Ordering::Relaxed)).await;
+                }
+                let _ = ws_sender.send(Message::Close(Some(reason.frame()))).await;
+                break;
+            }
             _ = ping_interval.tick() => {
                 // If the previous ping went unanswered the client is gone.
                 if !pong_received.swap(false, Ordering::Relaxed) {
@@ -1540,6 +2735,13 @@ where
             } // end select!
         }
 
+        // Streaming updates still held when the client went away are replayed
+        // on reconnect.
+        if let Some(mut shaper) = shaper {
+            for (_, line) in shaper.flush() {
+                slot_for_task2.buffer_message(line);
+            }
+        }
         debug!("Agent-to-WS forwarder task ended");
         let _ = shutdown_tx_clone.send(()).await;
     });
@@ -1561,17 +2763,35 @@ where
     agent_to_ws.abort();
     
     // Mark agent as disconnected in pool (don't kill it)
-    {
-        let mut pool = pool.write().await;
-        pool.mark_disconnected(&token);
-    }
+    slot.mark_disconnected();
     
     Ok(())
 }
 
+/// Send agent messages to the client, in sequence envelopes when enabled.
+/// On failure, returns the messages the client didn't get, with their
+/// sequence numbers.
+async fn send_agent_lines<W>(ws_sender: &mut W, lines: Vec<(u64, String)>, seq_envelope: bool) -> std::result::Result<(), Vec<(u64, String)>>
+where
+    W: futures_util::Sink<Message, Error = tokio_tungstenite::tungstenite::Error> + Unpin,
+{
+    let mut lines = lines.into_iter();
+    while let Some((seq, line)) = lines.next() {
+        info!("[push-dbg] ws_sender.send() attempting ({} bytes)", line.len());
+        debug!("📤 Sending to Mobile ({} bytes): {}", line.len(), line.chars().take(200).collect::<String>());
+        let outgoing = if seq_envelope { seq_envelope_wrap(seq, &line) } else { line.clone() };
+        if let Err(e) = ws_sender.send(Message::Text(outgoing.into())).await {
+            info!("[push-dbg] ws_sender.send() FAILED — client disconnected: {}", e);
+            return Err(std::iter::once((seq, line)).chain(lines).collect());
+        }
+        info!("[push-dbg] ws_sender.send() OK — message delivered to connected client");
+    }
+    Ok(())
+}
+
 /// Check if a JSON-RPC message is an `initialize` response.
 /// Supports both MCP-style (capabilities, serverInfo) and ACP-style (agentCapabilities, agentInfo, protocolVersion) responses.
-fn is_initialize_response(msg: &str) -> bool {
+pub(crate) fn is_initialize_response(msg: &str) -> bool {
     if let Ok(v) = serde_json::from_str::<serde_json::Value>(msg) {
         // It's a response (has "result") and the result contains agent/server capabilities
         v.get("result").is_some()
@@ -1586,7 +2806,7 @@ fn is_initialize_response(msg: &str) -> bool {
 }
 
 /// Check if a JSON-RPC message is a `createSession` response (has "result" with "sessionId")
-fn is_create_session_response(msg: &str) -> bool {
+pub(crate) fn is_create_session_response(msg: &str) -> bool {
     if let Ok(v) = serde_json::from_str::<serde_json::Value>(msg) {
         // It's a response (has "result") and the result contains a sessionId
         if let Some(result) = v.get("result") {
@@ -1684,220 +2904,206 @@ fn build_available_commands_notification(
     .unwrap_or_default()
 }
 
-/// Intercept the client's `createSession` request and reply with a cached response.
-/// Returns (intercepted, was_new_session):
-///   intercepted      = true if a session request was handled
-///   was_new_session  = true if the client sent session/new (reset), false for session/load (resume)
-async fn handle_create_session_intercept<S>(
+/// Read the next text/binary message from the client, waiting up to 30 s.
+async fn read_client_text<S>(
     ws_receiver: &mut futures_util::stream::SplitStream<tokio_tungstenite::WebSocketStream<S>>,
-    ws_sender: &mut futures_util::stream::SplitSink<tokio_tungstenite::WebSocketStream<S>, Message>,
-    cached_response: &str,
-    slash_commands: &[SlashCommandConfig],
-) -> (bool, bool)
+) -> Option<String>
 where
     S: AsyncRead + AsyncWrite + Unpin,
 {
-    // The ACP protocol flow after initialize is:
-    //   Client → notifications/initialized (notification, no id)
-    //   Client → session/new (request) OR session/load (request for reconnection)
-    // We need to skip any notifications before finding the session request.
-    let mut request: serde_json::Value;
-    let max_skip = 5; // safety limit to avoid infinite loop
-    let mut skipped = 0;
-    
-    loop {
-        let msg = match tokio::time::timeout(
-            std::time::Duration::from_secs(30),
-            ws_receiver.next(),
-        ).await {
-            Ok(Some(Ok(msg))) if msg.is_text() || msg.is_binary() => {
-                String::from_utf8_lossy(&msg.into_data()).to_string()
-            }
-            _ => return (false, false),
-        };
+    match tokio::time::timeout(
+        std::time::Duration::from_secs(30),
+        ws_receiver.next(),
+    ).await {
+        Ok(Some(Ok(msg))) if msg.is_text() || msg.is_binary() => client_message(&msg.into_data()).ok(),
+        _ => None,
+    }
+}
 
-        request = match serde_json::from_str(&msg) {
-            Ok(v) => v,
-            Err(_) => return (false, false),
-        };
+/// A parsed `bridge/resume` request: `{"id", "params": {"sessionId", "lastSeq", "seqEnvelope"}}`.
+struct ResumeRequest {
+    id: serde_json::Value,
+    session_id: Option<String>,
+    /// Replay after this sequence number; `None` means "after the last ack".
+    last_seq: Option<u64>,
+    /// Wrap replayed and live agent messages in sequence envelopes.
+    seq_envelope: bool,
+}
 
-        let method = request.get("method").and_then(|m| m.as_str());
+fn parse_resume_request(msg: &str) -> Option<ResumeRequest> {
+    let v: serde_json::Value = serde_json::from_str(msg).ok()?;
+    if v.get("method").and_then(|m| m.as_str()) != Some("bridge/resume") {
+        return None;
+    }
+    Some(ResumeRequest {
+        id: v.get("id").cloned().unwrap_or(serde_json::Value::Null),
+        session_id: v.pointer("/params/sessionId").and_then(|s| s.as_str()).map(str::to_string),
+        last_seq: v.pointer("/params/lastSeq").and_then(|s| s.as_u64()),
+        seq_envelope: v.pointer("/params/seqEnvelope").and_then(|b| b.as_bool()).unwrap_or(false),
+    })
+}
 
-        // Accept both session/new (new session) and session/load (resume session)
-        if method == Some("session/new") || method == Some("session/load") {
-            break; // found it
+/// Answer `bridge/listWorkspaces`: the configured workspaces, which one this
+/// token selected, and the directory agents run in otherwise.
+fn list_workspaces_response(id: &serde_json::Value, pool: &AgentPool, token: &str) -> String {
+    let workspaces: Vec<serde_json::Value> = pool
+        .workspaces()
+        .iter()
+        .map(|w| serde_json::json!({ "name": w.name, "path": w.path, "exists": w.path.is_dir() }))
+        .collect();
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "result": {
+            "workspaces": workspaces,
+            "selected": pool.selected_workspace(token).map(|w| &w.name),
+            "workingDir": pool.working_dir_for(token),
+            "worktree": pool.worktree(token).map(|w| serde_json::json!({ "path": w.agent_dir(), "branch": w.branch })),
         }
+    }).to_string()
+}
 
-        // If it's a notification (has method but no id), skip it
-        if method.is_some() && request.get("id").is_none() {
-            info!("📨 Skipping notification during session intercept: {:?}", method);
-            skipped += 1;
-            if skipped >= max_skip {
-                warn!("⚠️  Too many notifications before session request, giving up");
-                return (false, false);
-            }
-            continue;
-        }
-
-        // If it's an initialize request, respond with a minimal ACP initialize response
-        // and continue looking for session/new. This happens when cached_init was None
-        // (e.g., agent's initialize response format wasn't recognized on first connection).
-        if method == Some("initialize") {
-            if let Some(req_id) = request.get("id") {
-                info!("📨 Handling uncached initialize during session intercept (id={})", req_id);
-                let init_response = serde_json::json!({
-                    "jsonrpc": "2.0",
-                    "id": req_id,
-                    "result": {
-                        "protocolVersion": 1,
-                        "agentCapabilities": {},
-                        "agentInfo": {
-                            "name": "bridge",
-                            "version": "1.0.0"
-                        }
-                    }
-                });
-                let resp_str = serde_json::to_string(&init_response).unwrap_or_default();
-                if let Err(e) = ws_sender.send(Message::Text(resp_str.into())).await {
-                    error!("Failed to send synthetic initialize response: {}", e);
-                    return (false, false);
-                }
-                skipped += 1;
-                if skipped >= max_skip {
-                    warn!("⚠️  Too many messages before session request, giving up");
-                    return (false, false);
-                }
-                continue;
+/// Answer `bridge/selectWorkspace`. With `restart`, the bridge closes the
+/// connection next so the client reconnects to an agent in the workspace.
+fn select_workspace_response(id: &serde_json::Value, selection: &Result<WorkspaceSelection>) -> String {
+    match selection {
+        Ok(selection) => serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": {
+                "workspace": { "name": selection.workspace.name, "path": selection.workspace.path },
+                "restart": selection.restarted,
             }
-        }
-
-        // It's some other request, not a session request — can't intercept
-        warn!("⚠️  Message is not session/new or session/load (method={:?}, has_id={}, raw={}), cannot intercept",
-            method, request.get("id").is_some(),
-            msg.chars().take(200).collect::<String>());
-        return (false, false);
+        }),
+        Err(e) => serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": -32602, "message": e.to_string() }
+        }),
     }
+    .to_string()
+}
 
-    let was_new = request.get("method").and_then(|m| m.as_str()) == Some("session/new");
-
-    // Extract the request ID so we can match it in the response
-    let request_id = match request.get("id") {
-        Some(id) => id.clone(),
-        None => return (false, false),
-    };
-
-    let method = request.get("method").and_then(|m| m.as_str()).unwrap_or("unknown");
-    info!("🔄 Intercepting {} request (id={})", method, request_id);
-
-    // Parse the cached response and replace its "id" with the new request's "id"
-    let mut cached: serde_json::Value = match serde_json::from_str(cached_response) {
-        Ok(v) => v,
-        Err(e) => {
-            error!("Failed to parse cached session response: {}", e);
-            return (false, false);
-        }
-    };
-
-    cached["id"] = request_id;
-
-    let response_str = serde_json::to_string(&cached).unwrap_or_default();
-    debug!("🔄 Sending cached session response ({} bytes): {}", response_str.len(),
-        response_str.chars().take(200).collect::<String>());
-
-    if let Err(e) = ws_sender.send(Message::Text(response_str.into())).await {
-        error!("Failed to send cached session response: {}", e);
-        return (false, false);
+/// Answer `bridge/session/finish`. On success the bridge closes the
+/// connection next; the following one gets a fresh worktree.
+fn finish_session_response(id: &serde_json::Value, outcome: &Result<FinishOutcome>) -> String {
+    match outcome {
+        Ok(outcome) => serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": outcome }),
+        Err(e) => serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": -32602, "message": format!("{:#}", e) }
+        }),
     }
+    .to_string()
+}
 
-    // Inject available_commands_update so clients get the command picker
-    // even when the agent doesn't send this notification itself.
-    if !slash_commands.is_empty() {
-        if let Some(session_id) = extract_session_id_from_response(cached_response) {
-            let notification = build_available_commands_notification(&session_id, slash_commands);
-            info!("📋 Injecting available_commands_update for cached session {}", session_id);
-            let _ = ws_sender.send(Message::Text(notification.into())).await;
-        }
+/// Answer `bridge/endSession`. On success the bridge closes the connection
+/// next, with code `1000`.
+fn end_session_response(id: &serde_json::Value, ended: &Result<SessionEnd>) -> String {
+    match ended {
+        Ok(ended) => serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": ended }),
+        Err(e) => serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": -32002, "message": e.to_string() }
+        }),
     }
+    .to_string()
+}
 
-    (true, was_new)
+fn resume_error(id: &serde_json::Value, message: &str) -> String {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": -32002, "message": message }
+    }).to_string()
 }
 
-/// Intercept the client's `initialize` request and reply with a cached response.
-/// Returns true if an initialize was intercepted, false otherwise.
-async fn handle_initialize_intercept<S>(
-    ws_receiver: &mut futures_util::stream::SplitStream<tokio_tungstenite::WebSocketStream<S>>,
+/// Wrap an agent message in a sequence envelope: `{"seq": N, "msg": <message>}`.
+fn seq_envelope_wrap(seq: u64, line: &str) -> String {
+    let msg = serde_json::from_str::<serde_json::Value>(line)
+        .unwrap_or_else(|_| serde_json::Value::String(line.to_string()));
+    serde_json::json!({ "seq": seq, "msg": msg }).to_string()
+}
+
+/// Answer a `bridge/resume` handshake: verify the session matches the pooled
+/// agent's, then replay every agent message after `lastSeq` (or the last ack)
+/// and finish with `bridge/bufferReplayComplete`. Returns the last replayed
+/// sequence number, or `None` (after sending an error) when the session can't
+/// be resumed.
+async fn handle_resume_request<S>(
     ws_sender: &mut futures_util::stream::SplitSink<tokio_tungstenite::WebSocketStream<S>, Message>,
-    cached_response: &str,
-) -> bool
+    req: &ResumeRequest,
+    token: &str,
+    pool: &Arc<tokio::sync::RwLock<AgentPool>>,
+    output: &AgentOutput,
+    cached_session: Option<&str>,
+) -> Option<u64>
 where
     S: AsyncRead + AsyncWrite + Unpin,
 {
-    // Read the first message from the client
-    let first_msg = match tokio::time::timeout(
-        std::time::Duration::from_secs(30),
-        ws_receiver.next(),
-    ).await {
-        Ok(Some(Ok(msg))) if msg.is_text() || msg.is_binary() => {
-            String::from_utf8_lossy(&msg.into_data()).to_string()
-        }
-        _ => return false,
-    };
-    
-    // Parse it as JSON-RPC to check if it's an `initialize` request
-    let request: serde_json::Value = match serde_json::from_str(&first_msg) {
-        Ok(v) => v,
-        Err(_) => return false,
-    };
-    
-    let method = request.get("method").and_then(|m| m.as_str());
-    if method != Some("initialize") {
-        debug!("First message is not initialize (method={:?}), cannot intercept", method);
-        return false;
+    let current = cached_session.and_then(extract_session_id_from_response);
+    if current.is_none() || req.session_id != current {
+        info!("🔄 Rejecting bridge/resume: session {:?} is not the pooled agent's {:?}", req.session_id, current);
+        let _ = ws_sender.send(Message::Text(resume_error(&req.id, "Session not resumable").into())).await;
+        return None;
     }
-    
-    // Extract the request ID so we can match it in the response
-    let request_id = match request.get("id") {
-        Some(id) => id.clone(),
-        None => return false,
+
+    let after = req.last_seq.unwrap_or_else(|| output.acked_seq());
+    let replay = pool.read().await.replay_since(token, after);
+    let Some(Replay { messages, complete, last_seq }) = replay else {
+        let _ = ws_sender.send(Message::Text(resume_error(&req.id, "Session not resumable").into())).await;
+        return None;
     };
-    
-    info!("🔄 Intercepting initialize request (id={})", request_id);
-    
-    // Parse the cached response and replace its "id" with the new request's "id"
-    let mut cached: serde_json::Value = match serde_json::from_str(cached_response) {
-        Ok(v) => v,
-        Err(e) => {
-            error!("Failed to parse cached initialize response: {}", e);
-            return false;
+
+    info!("🔄 bridge/resume from seq {}: replaying {} message(s) (complete={})", after, messages.len(), complete);
+    let response = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": req.id,
+        "result": {
+            "sessionId": current,
+            "replayed": messages.len(),
+            "lastSeq": last_seq,
+            "complete": complete,
         }
-    };
-    
-    cached["id"] = request_id;
-    
-    let response_str = serde_json::to_string(&cached).unwrap_or_default();
-    debug!("🔄 Sending cached initialize response ({} bytes)", response_str.len());
-    
-    if let Err(e) = ws_sender.send(Message::Text(response_str.into())).await {
-        error!("Failed to send cached initialize response: {}", e);
-        return false;
+    });
+    if ws_sender.send(Message::Text(response.to_string().into())).await.is_err() {
+        return None;
     }
-    
-    true
+    let count = messages.len();
+    for (seq, msg) in messages {
+        let outgoing = if req.seq_envelope { seq_envelope_wrap(seq, &msg) } else { msg };
+        if let Err(e) = ws_sender.send(Message::Text(outgoing.into())).await {
+            error!("Failed to replay message: {}", e);
+            return None;
+        }
+    }
+    let notif = format!(
+        r#"{{"jsonrpc":"2.0","method":"bridge/bufferReplayComplete","params":{{"count":{},"lastSeq":{}}}}}"#,
+        count, last_seq
+    );
+    let _ = ws_sender.send(Message::Text(notif.into())).await;
+    Some(last_seq)
 }
 
-
 /// Dispatch to the correct WebSocket handler based on the AgentHandle variant.
 async fn handle_websocket_with_handle<S>(
     ws_stream: tokio_tungstenite::WebSocketStream<S>,
     agent_handle: AgentHandle,
     push_relay: Option<Arc<PushRelayClient>>,
     working_dir: PathBuf,
+    sandboxes: &[SandboxConfig],
+    max_line_bytes: usize,
 ) -> Result<()>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
     match agent_handle {
-        AgentHandle::Command(cmd) => handle_websocket_legacy(ws_stream, cmd, push_relay, working_dir).await,
+        AgentHandle::Command(cmd) => {
+            let sandbox = crate::sandbox::for_agent(sandboxes, &cmd).cloned();
+            handle_websocket_legacy(ws_stream, cmd, push_relay, working_dir, sandbox, max_line_bytes).await
+        }
         AgentHandle::InProcess { stdin_tx, stdout_rx } => {
             handle_websocket_inprocess(ws_stream, stdin_tx, stdout_rx).await
         }
@@ -1922,6 +3128,8 @@ where
     // that were meant for the *next* connection, which would cause the new
     // connection to time out waiting for a reply that was already discarded.
     let (agent_stop_tx, mut agent_stop_rx) = mpsc::channel::<()>(1);
+    // Parse errors for messages that weren't forwarded, sent by agent_to_ws.
+    let (reject_tx, mut reject_rx) = mpsc::channel::<String>(8);
 
     // Task 1: WebSocket → agent channel
     let shutdown_tx_ws = shutdown_tx.clone();
@@ -1929,7 +3137,14 @@ where
         while let Some(msg_result) = ws_receiver.next().await {
             match msg_result {
                 Ok(msg) if msg.is_text() || msg.is_binary() => {
-                    let mut data = msg.into_data().to_vec();
+                    let mut data = match client_message(&msg.into_data()) {
+                        Ok(text) => text.into_bytes(),
+                        Err(e) => {
+                            warn!("📥 Refused a message from Mobile: {}", e);
+                            let _ = reject_tx.send(e.response()).await;
+                            continue;
+                        }
+                    };
                     data.push(b'\n');
                     debug!("📥 WS→agent ({} bytes)", data.len());
                     if stdin_tx.send(data).await.is_err() {
@@ -1955,32 +3170,37 @@ where
     let shutdown_tx_clone = shutdown_tx.clone();
     let agent_to_ws = tokio::spawn(async move {
         let mut rx = stdout_rx.lock().await;
-        loop {
-            tokio::select! {
-                bytes_opt = rx.recv() => {
-                    match bytes_opt {
-                        Some(bytes) => {
-                            let line = String::from_utf8_lossy(&bytes).trim_end_matches('\n').to_string();
-                            debug!("📤 agent→WS ({} bytes)", line.len());
-                            if let Err(e) = ws_sender.send(Message::Text(line.into())).await {
-                                let msg = e.to_string();
-                                if msg.contains("Sending after closing") || msg.contains("connection closed") {
-                                    debug!("WebSocket closed before message could be sent (client disconnected)");
-                                } else {
-                                    error!("Failed to send to WebSocket: {}", e);
-                                }
-                                break;
-                            }
+        let mut splitter = LineSplitter::default();
+        'forward: loop {
+            let lines = tokio::select! {
+                bytes_opt = rx.recv() => match bytes_opt {
+                    Some(bytes) => splitter.push(&bytes),
+                    None => {
+                        if let Some(line) = splitter.finish() {
+                            let _ = ws_sender.send(Message::Text(line.into())).await;
                         }
-                        None => break,
+                        break;
                     }
-                }
+                },
+                Some(rejection) = reject_rx.recv() => vec![rejection],
                 _ = agent_stop_rx.recv() => {
                     // WebSocket is closing; exit immediately so the stdout_rx
                     // mutex is released before the next connection acquires it.
                     debug!("agent_to_ws: stop signal received, releasing stdout_rx");
                     break;
                 }
+            };
+            for line in lines {
+                debug!("📤 agent→WS ({} bytes)", line.len());
+                if let Err(e) = ws_sender.send(Message::Text(line.into())).await {
+                    let msg = e.to_string();
+                    if msg.contains("Sending after closing") || msg.contains("connection closed") {
+                        debug!("WebSocket closed before message could be sent (client disconnected)");
+                    } else {
+                        error!("Failed to send to WebSocket: {}", e);
+                    }
+                    break 'forward;
+                }
             }
         }
         let _ = shutdown_tx_clone.send(()).await;
@@ -1996,7 +3216,7 @@ where
 }
 
 
-async fn handle_websocket_legacy<S>(ws_stream: tokio_tungstenite::WebSocketStream<S>, agent_command: String, _push_relay: Option<Arc<PushRelayClient>>, working_dir: PathBuf) -> Result<()>
+async fn handle_websocket_legacy<S>(ws_stream: tokio_tungstenite::WebSocketStream<S>, agent_command: String, _push_relay: Option<Arc<PushRelayClient>>, working_dir: PathBuf, sandbox: Option<SandboxConfig>, max_line_bytes: usize) -> Result<()>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
@@ -2005,42 +3225,51 @@ where
     // Parse the agent command
     let parts: Vec<&str> = agent_command.split_whitespace().collect();
     if parts.is_empty() {
-        anyhow::bail!("Empty agent command");
+        return Err(BridgeError::config("Empty agent command"));
     }
 
     let command = parts[0];
     let args = &parts[1..];
 
     // Spawn the ACP agent process
-    info!("🚀 Spawning agent: {} {:?} (cwd: {})", command, args, working_dir.display());
-    
-    let mut child = Command::new(command)
-        .args(args)
-        .current_dir(&working_dir)
+    info!(
+        "🚀 Spawning agent: {} {:?} (cwd: {}){}",
+        command,
+        args,
+        working_dir.display(),
+        if sandbox.is_some() { " in a sandbox" } else { "" }
+    );
+
+    let mut child = crate::sandbox::command(sandbox.as_ref(), command, args, &working_dir)?
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .kill_on_drop(true)
         .spawn()
-        .context(format!("Failed to spawn agent command: {}", agent_command))?;
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => BridgeError::dependency_missing(command, e),
+            _ => BridgeError::Io { message: format!("Failed to spawn agent command: {}", agent_command), source: Some(e.into()) },
+        })?;
 
     let stdin = child
         .stdin
         .take()
-        .context("Failed to open agent stdin")?;
+        .io_err("Failed to open agent stdin")?;
     
     let stdout = child
         .stdout
         .take()
-        .context("Failed to open agent stdout")?;
+        .io_err("Failed to open agent stdout")?;
     
     let stderr = child
         .stderr
         .take()
-        .context("Failed to open agent stderr")?;
+        .io_err("Failed to open agent stderr")?;
 
     // Create channels for coordinating the tasks
     let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
+    // Parse errors for messages that weren't forwarded, sent by the stdout task.
+    let (reject_tx, mut reject_rx) = mpsc::channel::<String>(8);
 
     // Task 1: WebSocket -> Agent stdin
     let mut stdin_writer = stdin;
@@ -2049,8 +3278,14 @@ where
             match msg_result {
                 Ok(msg) => {
                     if msg.is_text() || msg.is_binary() {
-                        let raw = msg.into_data();
-                        let data = String::from_utf8_lossy(&raw);
+                        let data = match client_message(&msg.into_data()) {
+                            Ok(text) => text,
+                            Err(e) => {
+                                warn!("📥 Refused a message from Mobile: {}", e);
+                                let _ = reject_tx.send(e.response()).await;
+                                continue;
+                            }
+                        };
                         debug!("📥 Received from Mobile ({} bytes): {}", data.len(),
                             data.chars().take(200).collect::<String>());
 
@@ -2089,10 +3324,29 @@ where
     let shutdown_tx_clone = shutdown_tx.clone();
     let stdout_reader = BufReader::new(stdout);
     let agent_to_ws = tokio::spawn(async move {
-        let mut lines = stdout_reader.lines();
+        let mut lines = LineReader::new(stdout_reader, max_line_bytes);
         info!("📖 Agent stdout reader task started");
 
-        while let Ok(Some(line)) = lines.next_line().await {
+        loop {
+            let line = tokio::select! {
+                read = lines.next_line() => match read {
+                    Ok(Some(Line::Complete(line))) => line,
+                    Ok(Some(Line::Truncated { prefix, len })) => {
+                        warn!(
+                            "Agent sent a {}-byte line, over [limits] max_line_bytes ({}); dropped: {}",
+                            len,
+                            max_line_bytes,
+                            prefix.chars().take(200).collect::<String>()
+                        );
+                        truncation_message(&prefix, len, max_line_bytes)
+                    }
+                    _ => {
+                        let _ = ws_sender.send(Message::Close(Some(CloseReason::AgentExited.frame()))).await;
+                        break;
+                    }
+                },
+                Some(rejection) = reject_rx.recv() => rejection,
+            };
             info!("📤 Agent -> Mobile ({} bytes): {}", line.len(),
                 line.chars().take(200).collect::<String>());
 
@@ -2115,10 +3369,15 @@ where
     // Task 3: Log agent stderr
     let stderr_reader = BufReader::new(stderr);
     let stderr_logger = tokio::spawn(async move {
-        let mut lines = stderr_reader.lines();
+        let mut lines = LineReader::new(stderr_reader, max_line_bytes);
         
         while let Ok(Some(line)) = lines.next_line().await {
-            warn!("🤖 Agent stderr: {}", line);
+            match line {
+                Line::Complete(line) => warn!("🤖 Agent stderr: {}", line),
+                Line::Truncated { prefix, len } => {
+                    warn!("🤖 Agent stderr ({} bytes, cut): {}", len, prefix.chars().take(200).collect::<String>())
+                }
+            }
         }
         
         debug!("Agent stderr reader task ended");
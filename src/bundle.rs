@@ -0,0 +1,339 @@
+//! `bridge export-bundle` / `bridge import-bundle`: move a bridge to another
+//! machine without pairing every device again.
+//!
+//! A bundle holds what identifies the bridge to its devices and to
+//! Cloudflare:
+//!
+//! | Entry | From |
+//! |-------|------|
+//! | `common.toml` | config dir: `agent_id`, auth token, transports (with the tunnel secret and Access service token) |
+//! | `cert.pem`, `key.pem`, `cert-extra-sans.json` | config dir; `key.pem` is read from and restored to the system keystore with `key_storage = "system"` |
+//! | `ca.pem`, `ca-key.pem` | config dir, with `[tls_policy] ca = true`; leaf certificates are issued again on start |
+//! | `device_keys.json` | config dir: credentials keys of paired devices |
+//! | `cloudflared/<tunnel-id>.json` | `~/.cloudflared`, when `common.toml` has no `tunnel_secret` to rebuild it from |
+//!
+//! The entries are gzipped JSON, encrypted either with a passphrase
+//! (scrypt and AES-256-GCM, see [`seal`]) or, with `--recipient`, by the
+//! `age` CLI for one or more age recipients.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::Aes256Gcm;
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::common_config::{CommonConfig, KeyStorage};
+use crate::device_keys::DEVICE_KEYS_FILENAME;
+use crate::error::BridgeError;
+
+/// Start of a passphrase-encrypted bundle.
+const MAGIC: &[u8] = b"aptove-bridge-bundle/v1\n";
+/// Start of an `age` file, binary or armored.
+const AGE_MAGICS: [&[u8]; 2] = [b"age-encryption.org/v1", b"-----BEGIN AGE ENCRYPTED FILE-----"];
+
+/// scrypt cost of new bundles (2^17 iterations, 128 MiB), as `age` uses for
+/// passphrases. Stored in the bundle, so it can be raised later.
+const SCRYPT_LOG_N: u8 = 17;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Files restored to the config directory, as named in the bundle.
+const CONFIG_FILES: [&str; 7] =
+    ["common.toml", "cert.pem", "key.pem", "cert-extra-sans.json", "ca.pem", "ca-key.pem", DEVICE_KEYS_FILENAME];
+/// Prefix of cloudflared credentials, restored to `~/.cloudflared`.
+const CLOUDFLARED_PREFIX: &str = "cloudflared/";
+
+/// How a bundle is encrypted.
+#[derive(Debug, Clone)]
+pub enum Protection {
+    Passphrase(String),
+    /// `age1...` recipients (or SSH public keys), encrypted by the `age` CLI.
+    Recipients(Vec<String>),
+}
+
+/// How to decrypt a bundle.
+#[derive(Debug, Clone)]
+pub enum Unlock {
+    Passphrase(String),
+    /// An `age` identity file.
+    Identity(PathBuf),
+}
+
+/// The decrypted contents of a bundle.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bundle {
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    /// Bridge version that exported it.
+    pub bridge_version: String,
+    /// Entry name to base64 contents.
+    pub files: BTreeMap<String, String>,
+}
+
+impl Bundle {
+    /// Collect the bundle of the bridge configured in `config_dir`.
+    pub fn collect(config_dir: &Path) -> Result<Self> {
+        let config_path = config_dir.join("common.toml");
+        let toml = std::fs::read_to_string(&config_path)
+            .with_context(|| format!("No bridge configured in {} — nothing to export", config_dir.display()))?;
+        let config: CommonConfig =
+            toml::from_str(&toml).with_context(|| format!("Failed to parse {}", config_path.display()))?;
+
+        let mut files = BTreeMap::new();
+        for name in CONFIG_FILES {
+            if let Ok(bytes) = std::fs::read(config_dir.join(name)) {
+                files.insert(name.to_string(), STANDARD.encode(bytes));
+            }
+        }
+        if !files.contains_key("key.pem") && config.tls_policy.key_storage == KeyStorage::System {
+            if let Some(pem) = crate::keystore::load(&crate::tls::keystore_account(config_dir))? {
+                files.insert("key.pem".to_string(), STANDARD.encode(pem));
+            }
+        }
+        for transport in config.transports.values() {
+            if let (Some(tunnel_id), None) = (&transport.tunnel_id, &transport.tunnel_secret) {
+                let path = crate::cloudflare::get_cloudflared_dir()?.join(format!("{}.json", tunnel_id));
+                if let Ok(bytes) = std::fs::read(&path) {
+                    files.insert(format!("{}{}.json", CLOUDFLARED_PREFIX, tunnel_id), STANDARD.encode(bytes));
+                }
+            }
+        }
+        Ok(Self { version: 1, created_at: Utc::now(), bridge_version: crate::VERSION.to_string(), files })
+    }
+
+    /// Where `name` is restored to, or `None` for entries this version
+    /// doesn't know.
+    fn target(name: &str, config_dir: &Path) -> Result<Option<PathBuf>> {
+        if CONFIG_FILES.contains(&name) {
+            return Ok(Some(config_dir.join(name)));
+        }
+        let tunnel_file = name.strip_prefix(CLOUDFLARED_PREFIX).filter(|file| {
+            file.ends_with(".json") && file.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.'))
+        });
+        match tunnel_file {
+            Some(file) if !file.starts_with('.') => Ok(Some(crate::cloudflare::get_cloudflared_dir()?.join(file))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Write the entries to `config_dir` (and `~/.cloudflared`), `0600`.
+    /// With `key_storage = "system"`, `key.pem` goes straight into the system
+    /// keystore instead. Returns the paths written.
+    pub fn restore(&self, config_dir: &Path) -> Result<Vec<PathBuf>> {
+        let Some(config) = self.files.get("common.toml") else {
+            anyhow::bail!("The bundle has no common.toml");
+        };
+        let config = STANDARD.decode(config).context("Bundle entry common.toml is not base64")?;
+        let key_storage = toml::from_str::<CommonConfig>(&String::from_utf8_lossy(&config))
+            .map(|config| config.tls_policy.key_storage)
+            .unwrap_or_default();
+        let mut written = Vec::new();
+        for (name, contents) in &self.files {
+            let Some(path) = Self::target(name, config_dir)? else {
+                tracing::warn!("Skipping unknown bundle entry {:?}", name);
+                continue;
+            };
+            let bytes = STANDARD.decode(contents).with_context(|| format!("Bundle entry {} is not base64", name))?;
+            if name == "key.pem" && key_storage == KeyStorage::System && Self::store_key(config_dir, &bytes) {
+                let _ = std::fs::remove_file(&path);
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+            }
+            crate::private_file::write(&path, bytes).with_context(|| format!("Failed to write {}", path.display()))?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+
+    /// Put the TLS key of `config_dir` into the system keystore. Returns
+    /// `false` when it has to go to disk after all.
+    fn store_key(config_dir: &Path, pem: &[u8]) -> bool {
+        if !crate::keystore::is_available() {
+            tracing::warn!("System keystore unavailable — restoring the TLS private key to key.pem");
+            return false;
+        }
+        let stored = std::str::from_utf8(pem)
+            .map_err(anyhow::Error::from)
+            .and_then(|pem| crate::keystore::store(&crate::tls::keystore_account(config_dir), pem));
+        match stored {
+            Ok(()) => {
+                tracing::info!("🔐 Restored the TLS private key into the system keystore");
+                true
+            }
+            Err(e) => {
+                tracing::warn!("Failed to store the TLS key in the system keystore, restoring key.pem: {:#}", e);
+                false
+            }
+        }
+    }
+
+    fn to_gzip(&self) -> Result<Vec<u8>> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&serde_json::to_vec(self)?)?;
+        Ok(encoder.finish()?)
+    }
+
+    fn from_gzip(bytes: &[u8]) -> Result<Self> {
+        let mut json = Vec::new();
+        flate2::read::GzDecoder::new(bytes)
+            .read_to_end(&mut json)
+            .context("The decrypted bundle is not gzip")?;
+        serde_json::from_slice(&json).context("The decrypted bundle is not a bridge bundle")
+    }
+}
+
+/// Collect the bundle of `config_dir` and write it to `file`, encrypted.
+pub fn export(config_dir: &Path, file: &Path, protection: &Protection) -> Result<Bundle> {
+    let bundle = Bundle::collect(config_dir)?;
+    let plain = bundle.to_gzip()?;
+    let sealed = match protection {
+        Protection::Passphrase(passphrase) => seal(&plain, passphrase, SCRYPT_LOG_N)?,
+        Protection::Recipients(recipients) => {
+            let mut args = Vec::new();
+            for recipient in recipients {
+                args.extend(["-r", recipient.as_str()]);
+            }
+            run_age(&args, &plain)?
+        }
+    };
+    crate::private_file::write(file, sealed).with_context(|| format!("Failed to write {}", file.display()))?;
+    Ok(bundle)
+}
+
+/// Decrypt the bundle in `file` and restore it to `config_dir`. Refuses
+/// while a bridge runs from `config_dir`, and over an existing `common.toml`
+/// unless `force`.
+pub fn import(config_dir: &Path, file: &Path, unlock: &Unlock, force: bool) -> Result<(Bundle, Vec<PathBuf>)> {
+    if crate::runtime_manifest::is_live(config_dir) {
+        anyhow::bail!("A bridge is running from {} — stop it first", config_dir.display());
+    }
+    if config_dir.join("common.toml").exists() && !force {
+        anyhow::bail!("{} already has a common.toml — pass --force to replace it", config_dir.display());
+    }
+    let bundle = open(file, unlock)?;
+    let written = bundle.restore(config_dir)?;
+    Ok((bundle, written))
+}
+
+/// Decrypt the bundle in `file`.
+pub fn open(file: &Path, unlock: &Unlock) -> Result<Bundle> {
+    let sealed = std::fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?;
+    let is_age = AGE_MAGICS.iter().any(|magic| sealed.starts_with(magic));
+    let plain = match unlock {
+        Unlock::Passphrase(_) if is_age => anyhow::bail!("{} is encrypted with age — pass --identity", file.display()),
+        Unlock::Passphrase(passphrase) => unseal(&sealed, passphrase)?,
+        Unlock::Identity(_) if !is_age => anyhow::bail!("{} is encrypted with a passphrase, not age", file.display()),
+        Unlock::Identity(identity) => run_age(&["-d", "-i", &identity.to_string_lossy()], &sealed)?,
+    };
+    Bundle::from_gzip(&plain)
+}
+
+/// Encrypt `plain` as `MAGIC | log_n | salt | nonce | AES-256-GCM(plain)`,
+/// keyed by scrypt of `passphrase`. The header is authenticated.
+fn seal(plain: &[u8], passphrase: &str, log_n: u8) -> Result<Vec<u8>> {
+    let salt = rand::random::<[u8; SALT_LEN]>();
+    let nonce = rand::random::<[u8; NONCE_LEN]>();
+    let mut sealed = [MAGIC, &[log_n], &salt, &nonce].concat();
+    let ciphertext = cipher(passphrase, &salt, log_n)?
+        .encrypt(&nonce.into(), Payload { msg: plain, aad: &sealed })
+        .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
+    sealed.extend(ciphertext);
+    Ok(sealed)
+}
+
+fn unseal(sealed: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let header_len = MAGIC.len() + 1 + SALT_LEN + NONCE_LEN;
+    if !sealed.starts_with(MAGIC) || sealed.len() < header_len {
+        anyhow::bail!("Not a bridge bundle");
+    }
+    let (header, ciphertext) = sealed.split_at(header_len);
+    let log_n = header[MAGIC.len()];
+    let salt = &header[MAGIC.len() + 1..MAGIC.len() + 1 + SALT_LEN];
+    let nonce: [u8; NONCE_LEN] = header[header_len - NONCE_LEN..].try_into()?;
+    cipher(passphrase, salt, log_n)?
+        .decrypt(&nonce.into(), Payload { msg: ciphertext, aad: header })
+        .map_err(|_| anyhow::anyhow!("Wrong passphrase, or the bundle is damaged"))
+}
+
+fn cipher(passphrase: &str, salt: &[u8], log_n: u8) -> Result<Aes256Gcm> {
+    let params = scrypt::Params::new(log_n, 8, 1).map_err(|e| anyhow::anyhow!("Invalid scrypt cost {}: {}", log_n, e))?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut key)
+        .map_err(|e| anyhow::anyhow!("Key derivation failed: {}", e))?;
+    Aes256Gcm::new_from_slice(&key).map_err(|_| anyhow::anyhow!("Invalid key length"))
+}
+
+/// Run `age` with `args`, piping `input` through it.
+fn run_age(args: &[&str], input: &[u8]) -> Result<Vec<u8>> {
+    let mut child = Command::new("age")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => BridgeError::dependency_missing("age", e).into(),
+            _ => anyhow::Error::new(e).context("Failed to run age"),
+        })?;
+    // Written from a thread: age may fill stdout before it has read all of stdin.
+    let mut stdin = child.stdin.take().context("age has no stdin")?;
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().context("Failed to run age")?;
+    let _ = writer.join();
+    if !output.status.success() {
+        anyhow::bail!("age failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_bundles_open_only_with_the_passphrase() {
+        let plain = b"secret config".to_vec();
+        let sealed = seal(&plain, "correct horse", 4).unwrap();
+        assert!(sealed.starts_with(MAGIC));
+        assert_eq!(unseal(&sealed, "correct horse").unwrap(), plain);
+        assert!(unseal(&sealed, "wrong").is_err());
+
+        let mut tampered = sealed.clone();
+        tampered[MAGIC.len()] = 5;
+        assert!(unseal(&tampered, "correct horse").is_err(), "the header is authenticated");
+    }
+
+    #[test]
+    fn bundles_round_trip_the_config_dir() {
+        let from = tempfile::tempdir().unwrap();
+        std::fs::write(from.path().join("common.toml"), "agent_id = \"agent-1\"\nauth_token = \"token\"\n").unwrap();
+        std::fs::write(from.path().join("cert.pem"), "CERT").unwrap();
+        std::fs::write(from.path().join("key.pem"), "KEY").unwrap();
+        std::fs::write(from.path().join("bridge.log"), "not exported").unwrap();
+        let bundle = Bundle::collect(from.path()).unwrap();
+        assert_eq!(bundle.files.keys().collect::<Vec<_>>(), ["cert.pem", "common.toml", "key.pem"]);
+
+        let sealed = seal(&bundle.to_gzip().unwrap(), "pass", 4).unwrap();
+        let opened = Bundle::from_gzip(&unseal(&sealed, "pass").unwrap()).unwrap();
+        assert_eq!(opened, bundle);
+
+        let to = tempfile::tempdir().unwrap();
+        let mut with_unknown = opened.clone();
+        with_unknown.files.insert("../escape".to_string(), STANDARD.encode("x"));
+        assert_eq!(with_unknown.restore(to.path()).unwrap().len(), 3);
+        assert_eq!(std::fs::read_to_string(to.path().join("key.pem")).unwrap(), "KEY");
+        assert!(!to.path().join("bridge.log").exists());
+        assert!(!to.path().parent().unwrap().join("escape").exists());
+    }
+}
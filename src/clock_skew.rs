@@ -0,0 +1,121 @@
+//! Clock skew check against an HTTP `Date` header.
+//!
+//! Service token expiry, session tokens and `[auto_lock]` all compare
+//! timestamps with the system clock, so a clock that is far off makes them
+//! misfire. At startup the bridge sends a `HEAD` to `[clock_check] url`
+//! (through the outbound proxy, if any) and compares the response's `Date`
+//! with the middle of the round trip. Beyond `max_skew_secs` it logs a
+//! warning; `bridge status` shows the same measurement.
+//!
+//! `Date` has a resolution of one second, so skews of a second or two are
+//! noise.
+
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use crate::common_config::ClockCheckConfig;
+
+/// How long the `HEAD` request may take.
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The system clock compared with a server's.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClockSkew {
+    /// Host whose `Date` header was used.
+    pub source: String,
+    /// Seconds the system clock is ahead of the server (negative: behind).
+    pub skew_secs: i64,
+    pub max_skew_secs: u64,
+}
+
+impl ClockSkew {
+    pub fn is_excessive(&self) -> bool {
+        self.skew_secs.unsigned_abs() > self.max_skew_secs
+    }
+}
+
+impl fmt::Display for ClockSkew {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.skew_secs {
+            0 => write!(f, "in sync with {}", self.source),
+            s if s > 0 => write!(f, "{}s ahead of {}", s, self.source),
+            s => write!(f, "{}s behind {}", -s, self.source),
+        }
+    }
+}
+
+/// Skew of a clock that read `sent` and `received` around a response
+/// carrying `date`, an HTTP date (`Sun, 06 Nov 1994 08:49:37 GMT`).
+pub fn skew_from(date: &str, sent: DateTime<Utc>, received: DateTime<Utc>) -> Result<i64> {
+    let server = DateTime::parse_from_rfc2822(date).with_context(|| format!("Invalid Date header {:?}", date))?;
+    let local = sent + (received - sent) / 2;
+    Ok((local - server.with_timezone(&Utc)).num_seconds())
+}
+
+/// Measure the skew of the system clock against `config.url`.
+pub async fn measure(config: &ClockCheckConfig) -> Result<ClockSkew> {
+    let source = reqwest::Url::parse(&config.url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_else(|| config.url.clone());
+    let client = crate::proxy::client_builder()
+        .user_agent(format!("aptove-bridge/{}", crate::VERSION))
+        .timeout(CHECK_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .context("Failed to build HTTP client")?;
+    let sent = Utc::now();
+    let response = client
+        .head(&config.url)
+        .send()
+        .await
+        .with_context(|| format!("HEAD to {} failed", source))?;
+    let received = Utc::now();
+    let date = response
+        .headers()
+        .get(reqwest::header::DATE)
+        .and_then(|value| value.to_str().ok())
+        .with_context(|| format!("{} sent no Date header", source))?;
+    Ok(ClockSkew {
+        skew_secs: skew_from(date, sent, received)?,
+        source,
+        max_skew_secs: config.max_skew_secs,
+    })
+}
+
+/// Startup check: warn when the clock is off by more than `max_skew_secs`.
+/// Failures are logged at debug level only.
+pub async fn warn_if_skewed(config: ClockCheckConfig) {
+    match measure(&config).await {
+        Ok(skew) if skew.is_excessive() => warn!(
+            "🕰️  System clock is {} (more than {}s): token expiry and auto-lock checks are unreliable until it is synced (NTP)",
+            skew, skew.max_skew_secs
+        ),
+        Ok(skew) => debug!("Clock check: {}", skew),
+        Err(e) => debug!("Clock check failed: {:#}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skew_is_measured_from_the_middle_of_the_round_trip() {
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        let date = "Fri, 16 Oct 2026 12:00:00 GMT";
+        assert_eq!(skew_from(date, at("2026-10-16T12:01:39Z"), at("2026-10-16T12:01:41Z")).unwrap(), 100);
+        assert_eq!(skew_from(date, at("2026-10-16T11:59:00Z"), at("2026-10-16T11:59:00Z")).unwrap(), -60);
+        assert!(skew_from("yesterday", Utc::now(), Utc::now()).is_err());
+
+        let skew = ClockSkew { source: "cloudflare.com".into(), skew_secs: -90, max_skew_secs: 60 };
+        assert!(skew.is_excessive());
+        assert_eq!(skew.to_string(), "90s behind cloudflare.com");
+    }
+}
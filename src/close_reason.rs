@@ -0,0 +1,134 @@
+//! Why the bridge closed a WebSocket connection.
+//!
+//! Every connection the bridge ends itself gets a close frame instead of a
+//! dropped socket. The code says what happened, in the standard range where
+//! one fits and in the private-use range (`4000`–`4999`) otherwise. The reason
+//! is compact JSON the app can act on:
+//!
+//! ```json
+//! {"reason":"agent_exited","retry":true}
+//! {"reason":"rate_limited","retry":true,"retryAfter":12}
+//! ```
+//!
+//! `retry` says whether reconnecting can help; `retryAfter`, when present,
+//! how many seconds to wait first.
+
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+
+/// How long a stopping bridge waits for close frames to reach its clients.
+pub const CLOSE_GRACE: Duration = Duration::from_millis(500);
+
+/// Why the bridge closed a connection; see the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// `bridge/endSession` stopped the agent (1000).
+    SessionEnded,
+    /// The bridge is shutting down (1001).
+    ShuttingDown,
+    /// The bridge restarts with a new auth token; paired devices were sent it
+    /// (1012).
+    Restarting,
+    /// `bridge/selectWorkspace` stopped the agent to move it (1012).
+    WorkspaceChanged,
+    /// `bridge/session/finish` stopped the agent and its worktree (1012).
+    SessionFinished,
+    /// Over a `[limits]` connection quota (1013).
+    RateLimited { retry_after: u64 },
+    /// The agent process exited or was evicted from the pool (4000).
+    AgentExited,
+    /// No agent could be started: the pool is full or spawning failed (4001).
+    AgentUnavailable,
+    /// Scoped device tokens need keep-alive agent pooling (4002).
+    ScopeUnsupported,
+    /// `[auto_lock]` replaced every token; the device has to pair again
+    /// (4003).
+    Locked,
+    /// No valid `bridge/auth` first message in time (4004).
+    Unauthorized,
+}
+
+impl CloseReason {
+    pub fn code(self) -> CloseCode {
+        match self {
+            Self::SessionEnded => CloseCode::Normal,
+            Self::ShuttingDown => CloseCode::Away,
+            Self::Restarting | Self::WorkspaceChanged | Self::SessionFinished => CloseCode::Restart,
+            Self::RateLimited { .. } => CloseCode::Again,
+            Self::AgentExited => CloseCode::Library(4000),
+            Self::AgentUnavailable => CloseCode::Library(4001),
+            Self::ScopeUnsupported => CloseCode::Library(4002),
+            Self::Locked => CloseCode::Library(4003),
+            Self::Unauthorized => CloseCode::Library(4004),
+        }
+    }
+
+    /// The `reason` field of the payload.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::SessionEnded => "session_ended",
+            Self::ShuttingDown => "shutting_down",
+            Self::Restarting => "restarting",
+            Self::WorkspaceChanged => "workspace_changed",
+            Self::SessionFinished => "session_finished",
+            Self::RateLimited { .. } => "rate_limited",
+            Self::AgentExited => "agent_exited",
+            Self::AgentUnavailable => "agent_unavailable",
+            Self::ScopeUnsupported => "scope_unsupported",
+            Self::Locked => "locked",
+            Self::Unauthorized => "unauthorized",
+        }
+    }
+
+    /// Whether reconnecting can help.
+    pub fn retry(self) -> bool {
+        !matches!(self, Self::SessionEnded | Self::ScopeUnsupported | Self::Locked | Self::Unauthorized)
+    }
+
+    /// The JSON payload carried as the close reason.
+    pub fn payload(self) -> String {
+        let mut payload = serde_json::json!({ "reason": self.name(), "retry": self.retry() });
+        if let Self::RateLimited { retry_after } = self {
+            payload["retryAfter"] = retry_after.into();
+        }
+        payload.to_string()
+    }
+
+    pub fn frame(self) -> CloseFrame {
+        CloseFrame { code: self.code(), reason: self.payload().into() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn close_reasons_are_distinct_and_fit_a_close_frame() {
+        let reasons = [
+            CloseReason::SessionEnded,
+            CloseReason::ShuttingDown,
+            CloseReason::Restarting,
+            CloseReason::WorkspaceChanged,
+            CloseReason::SessionFinished,
+            CloseReason::RateLimited { retry_after: 86_400 },
+            CloseReason::AgentExited,
+            CloseReason::AgentUnavailable,
+            CloseReason::ScopeUnsupported,
+            CloseReason::Locked,
+            CloseReason::Unauthorized,
+        ];
+        let names: std::collections::HashSet<_> = reasons.iter().map(|r| r.name()).collect();
+        assert_eq!(names.len(), reasons.len());
+        // Close reasons are limited to 123 bytes.
+        assert!(reasons.iter().all(|r| r.payload().len() <= 123));
+
+        let frame = CloseReason::RateLimited { retry_after: 12 }.frame();
+        assert_eq!(u16::from(frame.code), 1013);
+        let payload: serde_json::Value = serde_json::from_str(&frame.reason).unwrap();
+        assert_eq!(payload, serde_json::json!({ "reason": "rate_limited", "retry": true, "retryAfter": 12 }));
+        assert_eq!(u16::from(CloseReason::Locked.code()), 4003);
+        assert!(!CloseReason::Locked.retry());
+    }
+}
@@ -1,10 +1,20 @@
-use anyhow::{Context, Result};
-use reqwest::{Client, header};
+use crate::error::{BridgeError, Result, ResultExt};
+use reqwest::{Client, RequestBuilder, StatusCode, header};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use tracing::{debug, info, warn};
 
 const CLOUDFLARE_API_BASE: &str = "https://api.cloudflare.com/client/v4";
 
+/// Attempts per API call, the first included.
+const MAX_ATTEMPTS: u32 = 5;
+/// Wait before the first retry, doubled for each further one and jittered.
+const BACKOFF_BASE: Duration = Duration::from_millis(500);
+/// Longest wait between attempts; a longer `Retry-After` is cut to this.
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+/// An attempt without an answer by then counts as a network error.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Cloudflare API client for Zero Trust operations
 pub struct CloudflareClient {
     client: Client,
@@ -34,6 +44,85 @@ pub struct ServiceToken {
     pub client_secret: String,
 }
 
+/// A cloudflared instance connected to a tunnel, with its edge connections.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TunnelClient {
+    pub id: String,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub conns: Vec<TunnelConnection>,
+}
+
+/// One connection between cloudflared and a Cloudflare data center.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TunnelConnection {
+    pub id: String,
+    /// Data center, e.g. `FRA`
+    #[serde(default)]
+    pub colo_name: String,
+    #[serde(default)]
+    pub is_pending_reconnect: bool,
+}
+
+/// One rule of a tunnel's ingress. Fields the bridge doesn't set (`path`,
+/// `originRequest`, ...) are kept as they are.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct IngressRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    pub service: String,
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+}
+
+impl IngressRule {
+    /// `hostname` served by the bridge listening on `local_port`.
+    pub fn bridge(hostname: &str, local_port: u16) -> Self {
+        Self {
+            hostname: Some(hostname.to_string()),
+            service: format!("http://localhost:{}", local_port),
+            other: serde_json::Map::new(),
+        }
+    }
+
+    /// The rule cloudflared requires last, for requests no other rule matches.
+    pub fn catch_all() -> Self {
+        Self { hostname: None, service: "http_status:404".to_string(), other: serde_json::Map::new() }
+    }
+
+    fn path(&self) -> Option<&str> {
+        self.other.get("path").and_then(|p| p.as_str())
+    }
+
+    fn is_catch_all(&self) -> bool {
+        self.hostname.is_none() && self.path().is_none()
+    }
+
+    /// Whether this is the rule for all of `hostname`, as `bridge` writes it.
+    fn serves(&self, hostname: &str) -> bool {
+        self.hostname.as_deref() == Some(hostname) && self.path().is_none()
+    }
+}
+
+/// `rules` with `hostname` pointed at `local_port`: its existing rule is
+/// updated in place, otherwise one is added before the catch-all. Rules for
+/// other hostnames, e.g. other bridges behind the same tunnel, are kept.
+pub fn merge_ingress(mut rules: Vec<IngressRule>, hostname: &str, local_port: u16) -> Vec<IngressRule> {
+    let ours = IngressRule::bridge(hostname, local_port);
+    match rules.iter_mut().find(|r| r.serves(hostname)) {
+        Some(existing) => existing.service = ours.service,
+        None => {
+            let at = rules.iter().position(IngressRule::is_catch_all).unwrap_or(rules.len());
+            rules.insert(at, ours);
+        }
+    }
+    if !rules.last().is_some_and(IngressRule::is_catch_all) {
+        rules.push(IngressRule::catch_all());
+    }
+    rules
+}
+
 #[derive(Debug, Deserialize)]
 struct CloudflareResponse {
     #[serde(default)]
@@ -47,8 +136,17 @@ struct CloudflareResponse {
 }
 
 impl CloudflareResponse {
-    fn into_result<T: serde::de::DeserializeOwned>(self) -> anyhow::Result<T> {
-        serde_json::from_value(self.result).context("Failed to deserialize API result")
+    fn into_result<T: serde::de::DeserializeOwned>(self) -> Result<T> {
+        serde_json::from_value(self.result).protocol_err("Failed to deserialize API result")
+    }
+
+    /// The refusal in this response, under `message`.
+    fn rejected(&self, message: &str) -> BridgeError {
+        BridgeError::Api {
+            message: message.to_string(),
+            codes: self.errors.iter().map(|e| e.code).collect(),
+            errors: self.errors.iter().map(|e| format!("{} ({})", e.message, e.code)).collect::<Vec<_>>().join(", "),
+        }
     }
 }
 
@@ -72,8 +170,9 @@ impl CloudflareClient {
             header::HeaderValue::from_static("application/json"),
         );
 
-        let client = Client::builder()
+        let client = crate::proxy::client_builder()
             .default_headers(headers)
+            .timeout(REQUEST_TIMEOUT)
             .build()
             .expect("Failed to build HTTP client");
 
@@ -92,15 +191,9 @@ impl CloudflareClient {
             CLOUDFLARE_API_BASE, self.account_id
         );
 
-        let response: CloudflareResponse = self
-            .client
-            .get(&list_url)
-            .send()
-            .await
-            .context("Failed to list tunnels")?
-            .json()
-            .await
-            .context("Failed to parse tunnel list response")?;
+        let response = self
+            .call(self.client.get(&list_url), "Failed to list tunnels")
+            .await?;
 
         if response.success {
             let tunnels: Vec<Tunnel> = response.into_result().unwrap_or_default();
@@ -139,22 +232,15 @@ impl CloudflareClient {
             "tunnel_secret": tunnel_secret,
         });
 
-        let response: CloudflareResponse = self
-            .client
-            .post(&create_url)
-            .json(&payload)
-            .send()
-            .await
-            .context("Failed to create tunnel")?
-            .json()
-            .await
-            .context("Failed to parse tunnel creation response")?;
+        let response = self
+            .call(self.client.post(&create_url).json(&payload), "Failed to create tunnel")
+            .await?;
 
         if !response.success {
-            anyhow::bail!("Failed to create tunnel: {:?}", response.errors);
+            return Err(response.rejected("Failed to create tunnel"));
         }
 
-        let mut tunnel: Tunnel = response.into_result().context("No tunnel returned after creation")?;
+        let mut tunnel: Tunnel = response.into_result().protocol_err("No tunnel returned after creation")?;
         tunnel.secret = tunnel_secret;
         Ok(tunnel)
     }
@@ -169,23 +255,17 @@ impl CloudflareClient {
         // Get zone ID from zone name
         let zones_url = format!("{}/zones?name={}", CLOUDFLARE_API_BASE, zone_name);
         
-        let zones_response: CloudflareResponse = self
-            .client
-            .get(&zones_url)
-            .send()
-            .await
-            .context("Failed to fetch zone information")?
-            .json()
-            .await
-            .context("Failed to parse zones response")?;
+        let zones_response = self
+            .call(self.client.get(&zones_url), "Failed to fetch zone information")
+            .await?;
 
         #[derive(Deserialize)]
         struct Zone {
             id: String,
         }
 
-        let zones: Vec<Zone> = zones_response.into_result().context("Zone not found")?;
-        let zone_id = zones.into_iter().next().context("Zone not found")?.id;
+        let zones: Vec<Zone> = zones_response.into_result().protocol_err("Zone not found")?;
+        let zone_id = zones.into_iter().next().config_err(format!("Zone {} not found in this account", zone_name))?.id;
 
         // Create DNS record
         let dns_url = format!("{}/zones/{}/dns_records", CLOUDFLARE_API_BASE, zone_id);
@@ -199,16 +279,9 @@ impl CloudflareClient {
             "proxied": true,
         });
 
-        let response: CloudflareResponse = self
-            .client
-            .post(&dns_url)
-            .json(&payload)
-            .send()
-            .await
-            .context("Failed to create DNS record")?
-            .json()
-            .await
-            .context("Failed to parse DNS creation response")?;
+        let response = self
+            .call(self.client.post(&dns_url).json(&payload), "Failed to create DNS record")
+            .await?;
 
         if !response.success {
             // Error 81053/81057: record with that name already exists — update it instead
@@ -217,7 +290,7 @@ impl CloudflareClient {
                 let full_hostname = format!("{}.{}", subdomain, zone_name);
                 return self.update_dns_record(&zone_id, &full_hostname, &tunnel_cname).await;
             }
-            anyhow::bail!("Failed to create DNS record: {:?}", response.errors);
+            return Err(response.rejected("Failed to create DNS record"));
         }
 
         Ok(())
@@ -235,19 +308,13 @@ impl CloudflareClient {
             CLOUDFLARE_API_BASE, zone_id, subdomain
         );
 
-        let list_response: CloudflareResponse = self
-            .client
-            .get(&list_url)
-            .send()
-            .await
-            .context("Failed to list DNS records")?
-            .json()
-            .await
-            .context("Failed to parse DNS records list")?;
-
-        let records: Vec<DnsRecord> = list_response.into_result().context("Failed to parse DNS record list")?;
+        let list_response = self
+            .call(self.client.get(&list_url), "Failed to list DNS records")
+            .await?;
+
+        let records: Vec<DnsRecord> = list_response.into_result().protocol_err("Failed to parse DNS record list")?;
         let record_id = records.into_iter().next()
-            .context("DNS record not found for update")?
+            .protocol_err("DNS record not found for update")?
             .id;
 
         let update_url = format!("{}/zones/{}/dns_records/{}", CLOUDFLARE_API_BASE, zone_id, record_id);
@@ -259,19 +326,12 @@ impl CloudflareClient {
             "proxied": true,
         });
 
-        let response: CloudflareResponse = self
-            .client
-            .put(&update_url)
-            .json(&payload)
-            .send()
-            .await
-            .context("Failed to update DNS record")?
-            .json()
-            .await
-            .context("Failed to parse DNS update response")?;
+        let response = self
+            .call(self.client.put(&update_url).json(&payload), "Failed to update DNS record")
+            .await?;
 
         if !response.success {
-            anyhow::bail!("Failed to update DNS record: {:?}", response.errors);
+            return Err(response.rejected("Failed to update DNS record"));
         }
 
         info!("✅ DNS record updated to point to current tunnel");
@@ -294,16 +354,9 @@ impl CloudflareClient {
             "auto_redirect_to_identity": false,
         });
 
-        let response: CloudflareResponse = self
-            .client
-            .post(&url)
-            .json(&payload)
-            .send()
-            .await
-            .context("Failed to create Access Application")?
-            .json()
-            .await
-            .context("Failed to parse Access Application response")?;
+        let response = self
+            .call(self.client.post(&url).json(&payload), "Failed to create Access Application")
+            .await?;
 
         if !response.success || response.result.is_null() {
             warn!("Access Application creation failed, checking for existing app...");
@@ -313,7 +366,7 @@ impl CloudflareClient {
             return Ok(app);
         }
 
-        let app: AccessApplication = response.into_result().context("Failed to parse Access Application")?;
+        let app: AccessApplication = response.into_result().protocol_err("Failed to parse Access Application")?;
         // Create Service Auth policy
         self.create_service_auth_policy(&app.id, hostname).await?;
         Ok(app)
@@ -326,20 +379,14 @@ impl CloudflareClient {
             CLOUDFLARE_API_BASE, self.account_id
         );
 
-        let response: CloudflareResponse = self
-            .client
-            .get(&url)
-            .send()
-            .await
-            .context("Failed to list Access Applications")?
-            .json()
-            .await
-            .context("Failed to parse Access Applications list")?;
+        let response = self
+            .call(self.client.get(&url), "Failed to list Access Applications")
+            .await?;
 
         let apps: Vec<AccessApplication> = response.into_result().unwrap_or_default();
         apps.into_iter()
             .find(|app| app.domain == hostname)
-            .with_context(|| format!("No Access Application found for hostname: {}", hostname))
+            .protocol_err(format!("No Access Application found for hostname: {}", hostname))
     }
 
     /// Create Service Auth policy for the application
@@ -358,16 +405,9 @@ impl CloudflareClient {
             "precedence": 1,
         });
 
-        let response: CloudflareResponse = self
-            .client
-            .post(&url)
-            .json(&payload)
-            .send()
-            .await
-            .context("Failed to create Service Auth policy")?
-            .json()
-            .await
-            .context("Failed to parse policy response")?;
+        let response = self
+            .call(self.client.post(&url).json(&payload), "Failed to create Service Auth policy")
+            .await?;
 
         if !response.success {
             // Ignore "already exists" type errors — policy from a previous run is fine
@@ -378,7 +418,7 @@ impl CloudflareClient {
                 warn!("Service Auth policy already exists, skipping...");
                 return Ok(());
             }
-            anyhow::bail!("Failed to create Service Auth policy: {:?}", response.errors);
+            return Err(response.rejected("Failed to create Service Auth policy"));
         }
 
         Ok(())
@@ -397,26 +437,17 @@ impl CloudflareClient {
             "duration": "8760h", // 1 year
         });
 
-        let response: CloudflareResponse = self
-            .client
-            .post(&url)
-            .json(&payload)
-            .send()
-            .await
-            .context("Failed to create Service Token")?
-            .json()
-            .await
-            .context("Failed to parse Service Token response")?;
+        let response = self
+            .call(self.client.post(&url).json(&payload), "Failed to create Service Token")
+            .await?;
 
         if !response.success || response.result.is_null() {
             // Auth errors can't be resolved by deleting and retrying — surface immediately.
             if response.errors.iter().any(|e| e.code == 10000) {
-                anyhow::bail!(
-                    "Cloudflare authentication error creating Service Token (code 10000). \
-                     Ensure your API token has 'Access: Service Tokens: Edit' permission. \
-                     Errors: {:?}",
-                    response.errors
-                );
+                return Err(response.rejected(
+                    "Cloudflare authentication error creating Service Token. \
+                     Ensure your API token has 'Access: Service Tokens: Edit' permission",
+                ));
             }
 
             warn!(
@@ -425,24 +456,17 @@ impl CloudflareClient {
             );
             self.delete_service_token_by_name(&token_name).await?;
 
-            let retry: CloudflareResponse = self
-                .client
-                .post(&url)
-                .json(&payload)
-                .send()
-                .await
-                .context("Failed to create Service Token (retry)")?
-                .json()
-                .await
-                .context("Failed to parse Service Token response (retry)")?;
+            let retry = self
+                .call(self.client.post(&url).json(&payload), "Failed to create Service Token (retry)")
+                .await?;
 
             if !retry.success {
-                anyhow::bail!("Failed to create Service Token: {:?}", retry.errors);
+                return Err(retry.rejected("Failed to create Service Token"));
             }
-            return retry.into_result().context("No Service Token returned after retry");
+            return retry.into_result().protocol_err("No Service Token returned after retry");
         }
 
-        response.into_result().context("No Service Token returned")
+        response.into_result().protocol_err("No Service Token returned")
     }
 
     /// List service tokens and delete the one matching `name`.
@@ -458,15 +482,9 @@ impl CloudflareClient {
             CLOUDFLARE_API_BASE, self.account_id
         );
 
-        let list: CloudflareResponse = self
-            .client
-            .get(&list_url)
-            .send()
-            .await
-            .context("Failed to list Service Tokens")?
-            .json()
-            .await
-            .context("Failed to parse Service Token list")?;
+        let list = self
+            .call(self.client.get(&list_url), "Failed to list Service Tokens")
+            .await?;
 
         let tokens: Vec<TokenInfo> = list.into_result().unwrap_or_default();
         for token in tokens {
@@ -475,11 +493,8 @@ impl CloudflareClient {
                     "{}/accounts/{}/access/service_tokens/{}",
                     CLOUDFLARE_API_BASE, self.account_id, token.id
                 );
-                self.client
-                    .delete(&delete_url)
-                    .send()
-                    .await
-                    .context("Failed to delete existing Service Token")?;
+                self.call(self.client.delete(&delete_url), "Failed to delete existing Service Token")
+                    .await?;
                 info!("🗑️  Deleted existing Service Token '{}'", name);
                 return Ok(());
             }
@@ -487,48 +502,72 @@ impl CloudflareClient {
         Ok(()) // not found — that's fine, proceed to create
     }
 
-    /// Configure tunnel ingress rules
+    /// Point `hostname` at `local_port` in the tunnel's ingress, keeping the
+    /// rules of other hostnames and the rest of the remote configuration.
+    /// Returns the ingress as saved.
     pub async fn configure_tunnel_ingress(
         &self,
         tunnel_id: &str,
         hostname: &str,
         local_port: u16,
-    ) -> Result<()> {
+    ) -> Result<Vec<IngressRule>> {
         let url = format!(
             "{}/accounts/{}/cfd_tunnel/{}/configurations",
             CLOUDFLARE_API_BASE, self.account_id, tunnel_id
         );
 
-        let payload = serde_json::json!({
-            "config": {
-                "ingress": [
-                    {
-                        "hostname": hostname,
-                        "service": format!("http://localhost:{}", local_port),
-                    },
-                    {
-                        "service": "http_status:404",
-                    }
-                ],
+        let response = self
+            .call(self.client.get(&url), "Failed to read tunnel configuration")
+            .await?;
+        if !response.success {
+            return Err(response.rejected("Failed to read tunnel configuration"));
+        }
+        let mut config = match response.result.get("config") {
+            Some(serde_json::Value::Object(config)) => config.clone(),
+            _ => serde_json::Map::new(),
+        };
+        let existing: Vec<IngressRule> = match config.remove("ingress") {
+            Some(ingress) if !ingress.is_null() => {
+                serde_json::from_value(ingress).protocol_err("Failed to parse the tunnel's ingress rules")?
             }
-        });
+            _ => Vec::new(),
+        };
+        let others = existing.iter().filter(|r| r.hostname.as_deref().is_some_and(|h| h != hostname)).count();
+        if others > 0 {
+            info!("Keeping {} ingress rule(s) of other hostnames on tunnel {}", others, tunnel_id);
+        }
+        let ingress = merge_ingress(existing, hostname, local_port);
+        config.insert(
+            "ingress".to_string(),
+            serde_json::to_value(&ingress).protocol_err("Failed to encode the tunnel's ingress rules")?,
+        );
+
+        let payload = serde_json::json!({ "config": config });
 
-        let response: CloudflareResponse = self
-            .client
-            .put(&url)
-            .json(&payload)
-            .send()
-            .await
-            .context("Failed to configure tunnel ingress")?
-            .json()
-            .await
-            .context("Failed to parse ingress configuration response")?;
+        let response = self
+            .call(self.client.put(&url).json(&payload), "Failed to configure tunnel ingress")
+            .await?;
 
         if !response.success {
-            anyhow::bail!("Failed to configure tunnel ingress: {:?}", response.errors);
+            return Err(response.rejected("Failed to configure tunnel ingress"));
         }
 
-        Ok(())
+        Ok(ingress)
+    }
+
+    /// The cloudflared instances connected to the tunnel, as the edge sees them
+    pub async fn tunnel_connections(&self, tunnel_id: &str) -> Result<Vec<TunnelClient>> {
+        let url = format!(
+            "{}/accounts/{}/cfd_tunnel/{}/connections",
+            CLOUDFLARE_API_BASE, self.account_id, tunnel_id
+        );
+        let response = self
+            .call(self.client.get(&url), "Failed to list tunnel connections")
+            .await?;
+        if !response.success {
+            return Err(response.rejected("Failed to list tunnel connections"));
+        }
+        Ok(response.into_result().unwrap_or_default())
     }
 
     /// Delete a tunnel by ID
@@ -537,21 +576,52 @@ impl CloudflareClient {
             "{}/accounts/{}/cfd_tunnel/{}",
             CLOUDFLARE_API_BASE, self.account_id, tunnel_id
         );
-        let response: CloudflareResponse = self
-            .client
-            .delete(&url)
-            .send()
-            .await
-            .context("Failed to delete tunnel")?
-            .json()
-            .await
-            .context("Failed to parse tunnel delete response")?;
+        let response = self
+            .call(self.client.delete(&url), "Failed to delete tunnel")
+            .await?;
         if !response.success {
-            anyhow::bail!("Failed to delete tunnel: {:?}", response.errors);
+            return Err(response.rejected("Failed to delete tunnel"));
         }
         Ok(())
     }
 
+    /// Send `request` and parse Cloudflare's response envelope. Rate limits
+    /// (`429`, after `Retry-After`), `5xx` answers and network errors are
+    /// retried with jittered exponential backoff, up to [`MAX_ATTEMPTS`]
+    /// attempts; then every attempt's failure is reported under `what`.
+    async fn call(&self, request: RequestBuilder, what: &str) -> Result<CloudflareResponse> {
+        let mut failures = Vec::new();
+        for attempt in 1..=MAX_ATTEMPTS {
+            let request = request
+                .try_clone()
+                .ok_or_else(|| BridgeError::Other(anyhow::anyhow!("{}: request can't be retried", what)))?;
+            let retry_after = match request.send().await {
+                Ok(response) if is_transient(response.status()) => {
+                    failures.push(format!("HTTP {}", response.status()));
+                    retry_after(response.headers())
+                }
+                Ok(response) => {
+                    return response.json().await.protocol_err(format!("{}: unparseable response", what));
+                }
+                Err(e) => {
+                    failures.push(format!("{:#}", anyhow::Error::from(e)));
+                    None
+                }
+            };
+            if attempt == MAX_ATTEMPTS {
+                break;
+            }
+            let wait = retry_after.unwrap_or_else(|| backoff(attempt)).min(BACKOFF_MAX);
+            warn!("{} ({}), retrying in {:.1}s", what, failures.last().map(String::as_str).unwrap_or_default(), wait.as_secs_f32());
+            tokio::time::sleep(wait).await;
+        }
+        let attempts: Vec<String> = failures.iter().enumerate().map(|(i, f)| format!("attempt {}: {}", i + 1, f)).collect();
+        Err(BridgeError::Network {
+            message: what.to_string(),
+            source: Some(format!("gave up after {} attempts ({})", failures.len(), attempts.join("; ")).into()),
+        })
+    }
+
     /// Generate a secure tunnel secret
     fn generate_tunnel_secret(&self) -> String {
         use base64::{engine::general_purpose, Engine as _};
@@ -566,6 +636,24 @@ impl CloudflareClient {
     }
 }
 
+/// Whether a request answered with `status` may succeed if sent again.
+fn is_transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// The wait a `Retry-After: <seconds>` header asks for.
+fn retry_after(headers: &header::HeaderMap) -> Option<Duration> {
+    let secs = headers.get(header::RETRY_AFTER)?.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(secs))
+}
+
+/// Wait before retry number `attempt` (from 1): [`BACKOFF_BASE`] doubled per
+/// attempt, randomly shortened by up to half so clients don't retry in step.
+fn backoff(attempt: u32) -> Duration {
+    let full = BACKOFF_BASE.saturating_mul(1 << (attempt - 1).min(16)).min(BACKOFF_MAX);
+    full.mul_f64(0.5 + rand::random::<f64>() / 2.0)
+}
+
 /// Write the cloudflared tunnel credentials JSON file to ~/.cloudflared/<tunnel-id>.json.
 /// This file is required by `cloudflared tunnel run` to authenticate to Cloudflare.
 pub fn write_credentials_file(
@@ -575,7 +663,7 @@ pub fn write_credentials_file(
 ) -> Result<std::path::PathBuf> {
     let cloudflared_dir = get_cloudflared_dir()?;
     std::fs::create_dir_all(&cloudflared_dir)
-        .context("Failed to create ~/.cloudflared directory")?;
+        .io_err("Failed to create ~/.cloudflared directory")?;
 
     let credentials_path = cloudflared_dir.join(format!("{}.json", tunnel_id));
     let credentials = serde_json::json!({
@@ -583,15 +671,9 @@ pub fn write_credentials_file(
         "TunnelSecret": tunnel_secret,
         "TunnelID": tunnel_id,
     });
-    std::fs::write(&credentials_path, serde_json::to_string_pretty(&credentials)?)
-        .context("Failed to write tunnel credentials file")?;
-
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let perms = std::fs::Permissions::from_mode(0o600);
-        std::fs::set_permissions(&credentials_path, perms)?;
-    }
+    let json = serde_json::to_string_pretty(&credentials).io_err("Failed to write tunnel credentials file")?;
+    crate::private_file::write(&credentials_path, json)
+        .io_err("Failed to write tunnel credentials file")?;
 
     Ok(credentials_path)
 }
@@ -606,15 +688,13 @@ pub fn write_cloudflared_config(
 ) -> Result<std::path::PathBuf> {
     let cloudflared_dir = get_cloudflared_dir()?;
     std::fs::create_dir_all(&cloudflared_dir)
-        .context("Failed to create ~/.cloudflared directory")?;
+        .io_err("Failed to create ~/.cloudflared directory")?;
 
     let config_path = cloudflared_dir.join("config.yml");
-    let credentials_str = credentials_path.to_string_lossy();
-    let config_content = format!(
-        "tunnel: {tunnel_id}\ncredentials-file: {credentials_str}\n\ningress:\n  - hostname: {hostname}\n    service: http://localhost:{local_port}\n  - service: http_status:404\n"
-    );
+    let ingress = [IngressRule::bridge(hostname, local_port), IngressRule::catch_all()];
+    let config_content = cloudflared_config(tunnel_id, credentials_path, &ingress);
     std::fs::write(&config_path, &config_content)
-        .context("Failed to write cloudflared config.yml")?;
+        .io_err("Failed to write cloudflared config.yml")?;
 
     Ok(config_path)
 }
@@ -629,20 +709,47 @@ pub fn write_cloudflared_config_at(
     hostname: &str,
     local_port: u16,
     config_path: &std::path::Path,
+) -> Result<()> {
+    let ingress = [IngressRule::bridge(hostname, local_port), IngressRule::catch_all()];
+    write_cloudflared_ingress_at(tunnel_id, credentials_path, &ingress, config_path)
+}
+
+/// Like [`write_cloudflared_config_at`], with the whole ingress of a tunnel
+/// shared by several bridges (see [`CloudflareClient::configure_tunnel_ingress`]).
+pub fn write_cloudflared_ingress_at(
+    tunnel_id: &str,
+    credentials_path: &std::path::Path,
+    ingress: &[IngressRule],
+    config_path: &std::path::Path,
 ) -> Result<()> {
     if let Some(parent) = config_path.parent() {
         std::fs::create_dir_all(parent)
-            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+            .io_err(format!("Failed to create directory {}", parent.display()))?;
     }
-    let credentials_str = credentials_path.to_string_lossy();
-    let config_content = format!(
-        "tunnel: {tunnel_id}\ncredentials-file: {credentials_str}\n\ningress:\n  - hostname: {hostname}\n    service: http://localhost:{local_port}\n  - service: http_status:404\n"
-    );
+    let config_content = cloudflared_config(tunnel_id, credentials_path, ingress);
     std::fs::write(config_path, &config_content)
-        .with_context(|| format!("Failed to write cloudflared config to {}", config_path.display()))?;
+        .io_err(format!("Failed to write cloudflared config to {}", config_path.display()))?;
     Ok(())
 }
 
+/// The config.yml contents. Only `hostname`, `path` and `service` of each
+/// rule are written.
+fn cloudflared_config(tunnel_id: &str, credentials_path: &std::path::Path, ingress: &[IngressRule]) -> String {
+    let credentials_str = credentials_path.to_string_lossy();
+    let mut content = format!("tunnel: {tunnel_id}\ncredentials-file: {credentials_str}\n\ningress:\n");
+    for rule in ingress {
+        let mut prefix = "  - ";
+        for (key, value) in [("hostname", rule.hostname.as_deref()), ("path", rule.path())] {
+            if let Some(value) = value {
+                content.push_str(&format!("{prefix}{key}: {value}\n"));
+                prefix = "    ";
+            }
+        }
+        content.push_str(&format!("{prefix}service: {}\n", rule.service));
+    }
+    content
+}
+
 /// Return the path to the cloudflared config YAML (does not check existence).
 pub fn cloudflared_config_path() -> Result<std::path::PathBuf> {
     Ok(get_cloudflared_dir()?.join("config.yml"))
@@ -654,10 +761,10 @@ pub fn cloudflared_credentials_path(tunnel_id: &str) -> Result<std::path::PathBu
     Ok(get_cloudflared_dir()?.join(format!("{}.json", tunnel_id)))
 }
 
-fn get_cloudflared_dir() -> Result<std::path::PathBuf> {
+pub(crate) fn get_cloudflared_dir() -> Result<std::path::PathBuf> {
     let home = std::env::var("HOME")
         .or_else(|_| std::env::var("USERPROFILE"))
-        .context("Cannot determine home directory (HOME not set)")?;
+        .config_err("Cannot determine home directory (HOME not set)")?;
     Ok(std::path::PathBuf::from(home).join(".cloudflared"))
 }
 
@@ -712,4 +819,112 @@ mod tests {
         assert!(content.contains("http://localhost:8080"), "should have local port");
         assert!(content.contains("http_status:404"), "should have fallback rule");
     }
+
+    #[test]
+    fn backoff_doubles_with_jitter() {
+        for attempt in 1..=4 {
+            let full = BACKOFF_BASE * 2u32.pow(attempt - 1);
+            let wait = backoff(attempt);
+            assert!(wait >= full / 2 && wait <= full, "attempt {attempt}: {wait:?}");
+        }
+        assert!(backoff(40) <= BACKOFF_MAX);
+
+        let mut headers = header::HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(header::RETRY_AFTER, header::HeaderValue::from_static("7"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+        assert!(is_transient(StatusCode::TOO_MANY_REQUESTS) && is_transient(StatusCode::BAD_GATEWAY));
+        assert!(!is_transient(StatusCode::FORBIDDEN));
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/accounts", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let answers = [
+                "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n",
+                "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\n",
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 30\r\n",
+            ];
+            for answer in answers {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 4096];
+                let _ = stream.read(&mut request).await.unwrap();
+                let body = if answer.contains("200 OK") { r#"{"success":true,"result":[42]}"# } else { "" };
+                let response = format!("{answer}Connection: close\r\n\r\n{body}");
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let client = CloudflareClient::new("token".into(), "account".into());
+        let response = client.call(client.client.get(&url), "Failed to list").await.unwrap();
+        assert!(response.success);
+        assert_eq!(response.into_result::<Vec<u32>>().unwrap(), vec![42]);
+        server.await.unwrap();
+    }
+
+    fn rule(hostname: Option<&str>, service: &str) -> IngressRule {
+        IngressRule { hostname: hostname.map(str::to_string), service: service.into(), other: serde_json::Map::new() }
+    }
+
+    #[test]
+    fn merging_ingress_keeps_other_hostnames() {
+        let mut with_path = rule(Some("agent.example.com"), "http://localhost:3000");
+        with_path.other.insert("path".into(), "/static".into());
+        let existing: Vec<IngressRule> = serde_json::from_value(serde_json::json!([
+            { "hostname": "project-a.example.com", "service": "http://localhost:8080",
+              "originRequest": { "noTLSVerify": true } },
+            { "hostname": "agent.example.com", "path": "/static", "service": "http://localhost:3000" },
+            { "service": "http_status:404" },
+        ]))
+        .unwrap();
+
+        let merged = merge_ingress(existing.clone(), "agent.example.com", 8765);
+        assert_eq!(merged[0], existing[0], "other bridges' rules are untouched");
+        assert_eq!(merged[1], with_path);
+        assert_eq!(merged[2], IngressRule::bridge("agent.example.com", 8765), "added before the catch-all");
+        assert_eq!(merged[3], IngressRule::catch_all());
+
+        let moved = merge_ingress(merged.clone(), "project-a.example.com", 9000);
+        assert_eq!(moved.len(), 4, "an existing rule is updated in place");
+        assert_eq!(moved[0].service, "http://localhost:9000");
+        assert_eq!(moved[0].other, existing[0].other);
+
+        let fresh = merge_ingress(Vec::new(), "agent.example.com", 8765);
+        assert_eq!(fresh, vec![IngressRule::bridge("agent.example.com", 8765), IngressRule::catch_all()]);
+        let no_catch_all = merge_ingress(vec![rule(Some("b.example.com"), "http://localhost:1")], "agent.example.com", 8765);
+        assert_eq!(no_catch_all.last(), Some(&IngressRule::catch_all()));
+    }
+
+    #[test]
+    fn shared_ingress_is_written_in_order() {
+        let tmp = TempDir::new().unwrap();
+        let creds_path = tmp.path().join("tunnel-abc.json");
+        let config_path = tmp.path().join("cloudflared.yml");
+        let mut with_path = rule(Some("agent.example.com"), "http://localhost:3000");
+        with_path.other.insert("path".into(), "/static".into());
+        let ingress = [
+            rule(Some("project-a.example.com"), "http://localhost:8080"),
+            with_path,
+            IngressRule::bridge("agent.example.com", 8765),
+            IngressRule::catch_all(),
+        ];
+        write_cloudflared_ingress_at("tunnel-abc", &creds_path, &ingress, &config_path).unwrap();
+
+        let content = fs::read_to_string(&config_path).unwrap();
+        let expected = "ingress:
+  - hostname: project-a.example.com
+    service: http://localhost:8080
+  - hostname: agent.example.com
+    path: /static
+    service: http://localhost:3000
+  - hostname: agent.example.com
+    service: http://localhost:8765
+  - service: http_status:404
+";
+        assert!(content.ends_with(expected), "{content}");
+    }
 }
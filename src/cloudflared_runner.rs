@@ -1,6 +1,6 @@
 use anyhow::{Context, Result};
 use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
 use std::sync::mpsc;
 use std::time::{Duration, Instant};
@@ -25,6 +25,9 @@ pub struct CloudflaredRunner {
     child: Option<Child>,
     /// Buffered stderr lines captured during startup (for diagnostics)
     startup_lines: Vec<String>,
+    /// What the child was spawned with, for [`CloudflaredRunner::restart`]
+    config_yml_path: PathBuf,
+    tunnel_id: String,
 }
 
 impl CloudflaredRunner {
@@ -36,14 +39,20 @@ impl CloudflaredRunner {
             anyhow::bail!("{}", INSTALL_HINT);
         }
 
-        let child = Command::new("cloudflared")
+        let mut command = Command::new("cloudflared");
+        command.arg("tunnel");
+        if crate::proxy::in_use() {
+            // cloudflared's default QUIC transport can't go through a proxy.
+            command.args(["--protocol", "http2"]);
+        }
+        let child = command
             .args([
-                "tunnel",
                 "--config",
                 &config_yml_path.to_string_lossy(),
                 "run",
                 tunnel_id,
             ])
+            .envs(crate::proxy::child_env())
             .stdout(Stdio::null())
             .stderr(Stdio::piped())
             .spawn()
@@ -52,9 +61,29 @@ impl CloudflaredRunner {
         Ok(Self {
             child: Some(child),
             startup_lines: Vec::new(),
+            config_yml_path: config_yml_path.to_path_buf(),
+            tunnel_id: tunnel_id.to_string(),
         })
     }
 
+    /// Whether the cloudflared process is still running.
+    pub fn is_running(&mut self) -> bool {
+        self.child
+            .as_mut()
+            .is_some_and(|c| matches!(c.try_wait(), Ok(None)))
+    }
+
+    /// Stop cloudflared and start it again with the same config, blocking
+    /// until it is ready like [`CloudflaredRunner::wait_for_ready`].
+    pub fn restart(&mut self, timeout: Duration) -> Result<()> {
+        self.kill_child();
+        if let Some(mut child) = self.child.take() {
+            let _ = child.wait();
+        }
+        *self = Self::spawn(&self.config_yml_path, &self.tunnel_id)?;
+        self.wait_for_ready(timeout)
+    }
+
     /// Block until cloudflared reports it has established a tunnel connection,
     /// or until `timeout` elapses. Returns an error with diagnostic stderr lines
     /// if the timeout expires before a ready marker is seen.
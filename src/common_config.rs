@@ -4,65 +4,1853 @@
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::OnceLock;
 
+use crate::device_tokens::Scope;
+use crate::push::{PushCategory, QuietHours};
+use crate::push_providers::PushProvider;
+
 /// Global custom config directory for CommonConfig (set via --config-dir).
 static COMMON_CUSTOM_CONFIG_DIR: OnceLock<PathBuf> = OnceLock::new();
 
-/// Set a custom config directory (call before any config operations).
-pub fn set_config_dir(path: PathBuf) {
-    COMMON_CUSTOM_CONFIG_DIR.set(path).ok();
+/// Set a custom config directory (call before any config operations).
+pub fn set_config_dir(path: PathBuf) {
+    COMMON_CUSTOM_CONFIG_DIR.set(path).ok();
+}
+
+/// A slash command advertised to connected clients via `available_commands_update`.
+///
+/// Define these in `common.toml` for agents that don't send `available_commands_update`
+/// themselves (e.g. Copilot CLI, Goose).
+///
+/// Example `common.toml` entry:
+/// ```toml
+/// [[slash_commands]]
+/// name        = "fix"
+/// description = "Fix the selected code"
+///
+/// [[slash_commands]]
+/// name        = "explain"
+/// description = "Explain the code, optionally with a focus"
+/// input_hint  = "what to focus on"
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SlashCommandConfig {
+    /// Command name without the leading `/` (e.g. `"fix"`).
+    pub name: String,
+    /// Human-readable description shown in the picker.
+    pub description: String,
+    /// If set, the command accepts free-text input; this string is shown as
+    /// the placeholder hint in the text field.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_hint: Option<String>,
+}
+
+/// Push relay configuration for sending background notifications.
+///
+/// The four connection fields are required — push is silently disabled if the
+/// section is absent or any of them is empty. With `provider = "ntfy"` or
+/// `"gotify"`, pushes go to that server instead (see
+/// [`crate::push_providers`]) and only `url`, `topic` and `token` apply:
+///
+/// ```toml
+/// [push_relay]
+/// provider = "ntfy"
+/// url      = "https://ntfy.sh"
+/// topic    = "bridge-5f0c9e27a1"
+/// ```
+///
+/// Example `common.toml` entry, besides the connection fields:
+/// ```toml
+/// [push_relay]
+/// cooldown_secs = 60          # between "new activity" pushes
+///
+/// [push_relay.cooldowns]
+/// scheduledTask = 3600        # per category, in seconds
+///
+/// [push_relay.quiet_hours]
+/// start = "23:00"             # local time; only permission requests get through
+/// end   = "07:00"
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PushRelayConfig {
+    /// Where pushes go (default: the relay).
+    #[serde(default, skip_serializing_if = "PushProvider::is_relay")]
+    pub provider: PushProvider,
+    /// Base URL of the push relay service (e.g. "https://push.aptove.com"),
+    /// or of the ntfy or Gotify server.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub url: String,
+    /// ntfy topic to publish to.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub topic: String,
+    /// ntfy access token (optional), or Gotify application token.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub token: String,
+    /// Base URL of the JWT token service (e.g. "https://token.aptove.com").
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub token_url: String,
+    /// OAuth2 client_id issued by the token service.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub client_id: String,
+    /// OAuth2 client_secret issued by the token service.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub client_secret: String,
+    /// Seconds between "new activity" pushes (default: 30).
+    #[serde(default = "push_cooldown_default", skip_serializing_if = "is_push_cooldown_default")]
+    pub cooldown_secs: u64,
+    /// Seconds between pushes of a category, overriding `cooldown_secs` for
+    /// `activity`. Other categories have no cooldown unless listed.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub cooldowns: BTreeMap<PushCategory, u64>,
+    /// Daily window in which only permission requests are pushed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quiet_hours: Option<QuietHoursConfig>,
+}
+
+fn push_cooldown_default() -> u64 {
+    crate::push::DEFAULT_COOLDOWN.as_secs()
+}
+
+fn is_push_cooldown_default(secs: &u64) -> bool {
+    *secs == push_cooldown_default()
+}
+
+impl Default for PushRelayConfig {
+    fn default() -> Self {
+        Self {
+            provider: PushProvider::Relay,
+            url: String::new(),
+            topic: String::new(),
+            token: String::new(),
+            token_url: String::new(),
+            client_id: String::new(),
+            client_secret: String::new(),
+            cooldown_secs: push_cooldown_default(),
+            cooldowns: BTreeMap::new(),
+            quiet_hours: None,
+        }
+    }
+}
+
+impl PushRelayConfig {
+    pub fn validate(&self) -> Result<()> {
+        match self.provider {
+            PushProvider::Relay => {}
+            PushProvider::Ntfy if self.topic.is_empty() => anyhow::bail!("[push_relay] provider = \"ntfy\" needs a topic"),
+            PushProvider::Gotify if self.token.is_empty() => {
+                anyhow::bail!("[push_relay] provider = \"gotify\" needs the application token as token")
+            }
+            PushProvider::Ntfy | PushProvider::Gotify => {
+                if !self.url.starts_with("https://") && !self.url.starts_with("http://") {
+                    anyhow::bail!("[push_relay] url must be the http(s) URL of the ntfy or Gotify server");
+                }
+            }
+        }
+        if let Some(ref quiet) = self.quiet_hours {
+            QuietHours::parse(&quiet.start, &quiet.end).context("[push_relay.quiet_hours]")?;
+        }
+        Ok(())
+    }
+}
+
+/// `[push_relay.quiet_hours]`: `HH:MM` local times. A `start` after `end`
+/// spans midnight.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct QuietHoursConfig {
+    pub start: String,
+    pub end: String,
+}
+
+/// Extra client listener speaking newline-delimited JSON-RPC (no HTTP or
+/// WebSocket framing), for desktop tools and scripts.
+///
+/// Example `common.toml` entries:
+/// ```toml
+/// [[listeners]]
+/// type    = "tcp"
+/// address = "127.0.0.1:8766"
+///
+/// [[listeners]]
+/// type = "unix"
+/// path = "/tmp/bridge.sock"
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ListenerConfig {
+    /// TCP socket; clients authenticate with `bridge/auth` as their first line.
+    Tcp { address: String },
+    /// Unix domain socket, created with mode 0600; the file permissions are the
+    /// authentication.
+    Unix { path: PathBuf },
+}
+
+/// S3-compatible object storage sink for session transcripts.
+///
+/// When present, every line exchanged between client and agent is mirrored to
+/// gzip'd JSONL objects under `<prefix><YYYY-MM-DD>/<agent_id>/`. Nothing is
+/// written to local disk.
+///
+/// Example `common.toml` entry:
+/// ```toml
+/// [transcripts]
+/// endpoint          = "https://s3.eu-central-1.amazonaws.com"
+/// bucket            = "my-bridge-transcripts"
+/// region            = "eu-central-1"
+/// access_key_id     = "AKIA..."
+/// secret_access_key = "..."
+/// prefix            = "bridge/"
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TranscriptConfig {
+    /// Base URL of the S3 API (AWS, MinIO, R2, ...). Requests use path-style addressing.
+    pub endpoint: String,
+    /// Target bucket.
+    pub bucket: String,
+    /// Signing region (default: "us-east-1"; R2 uses "auto").
+    #[serde(default = "transcript_region_default")]
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Key prefix prepended to every object (e.g. "bridge/").
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub prefix: String,
+    /// Upload a session's pending lines at least this often (default: 300).
+    #[serde(default = "transcript_flush_default")]
+    pub flush_interval_secs: u64,
+    /// Upload early once a session's uncompressed pending lines exceed this size (default: 8 MiB).
+    #[serde(default = "transcript_chunk_default")]
+    pub max_chunk_bytes: usize,
+}
+
+fn transcript_region_default() -> String { "us-east-1".to_string() }
+fn transcript_flush_default() -> u64 { 300 }
+fn transcript_chunk_default() -> usize { 8 * 1024 * 1024 }
+
+impl TranscriptConfig {
+    /// Reject configurations that could never upload.
+    pub fn validate(&self) -> Result<()> {
+        if !self.endpoint.starts_with("http://") && !self.endpoint.starts_with("https://") {
+            anyhow::bail!("[transcripts] endpoint must be an http(s) URL");
+        }
+        if self.bucket.is_empty() || self.access_key_id.is_empty() || self.secret_access_key.is_empty() {
+            anyhow::bail!("[transcripts] bucket, access_key_id and secret_access_key are required");
+        }
+        if self.flush_interval_secs == 0 {
+            anyhow::bail!("[transcripts] flush_interval_secs must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// Connection quotas and handshake limits for the WebSocket listener.
+///
+/// Every field has a default, so the section can be omitted entirely or
+/// only partially specified.
+///
+/// Example `common.toml` entry:
+/// ```toml
+/// [limits]
+/// max_connections_per_ip  = 10
+/// max_attempts_per_minute = 30
+/// max_connections         = 100
+/// handshake_timeout_secs  = 10
+/// request_timeout_secs    = 10
+/// upgrade_timeout_secs    = 10
+/// max_line_bytes          = 16777216
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Maximum concurrent connections from a single IP address.
+    pub max_connections_per_ip: usize,
+    /// Maximum connection attempts per IP address in a rolling 60 s window.
+    pub max_attempts_per_minute: usize,
+    /// Maximum concurrent connections across all clients (0 = unlimited).
+    pub max_connections: usize,
+    /// Seconds a client has to complete the TLS handshake before it is dropped.
+    pub handshake_timeout_secs: u64,
+    /// Seconds a client has to send its initial HTTP request (and, for
+    /// webhooks, each subsequent body chunk) before it is dropped.
+    pub request_timeout_secs: u64,
+    /// Seconds a client has to complete the WebSocket upgrade before it is dropped.
+    pub upgrade_timeout_secs: u64,
+    /// Longest line of agent output passed on, in bytes. Longer lines are
+    /// dropped and the client is sent an error in their place.
+    pub max_line_bytes: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_connections_per_ip: 10,
+            max_attempts_per_minute: 30,
+            max_connections: 100,
+            handshake_timeout_secs: 10,
+            request_timeout_secs: 10,
+            upgrade_timeout_secs: 10,
+            max_line_bytes: crate::line_reader::DEFAULT_MAX_LINE_BYTES,
+        }
+    }
+}
+
+impl LimitsConfig {
+    /// Reject values that would make the listener refuse every connection.
+    pub fn validate(&self) -> Result<()> {
+        if self.max_connections_per_ip == 0 {
+            anyhow::bail!("[limits] max_connections_per_ip must be at least 1");
+        }
+        if self.max_attempts_per_minute == 0 {
+            anyhow::bail!("[limits] max_attempts_per_minute must be at least 1");
+        }
+        if self.handshake_timeout_secs == 0 {
+            anyhow::bail!("[limits] handshake_timeout_secs must be at least 1");
+        }
+        if self.request_timeout_secs == 0 {
+            anyhow::bail!("[limits] request_timeout_secs must be at least 1");
+        }
+        if self.upgrade_timeout_secs == 0 {
+            anyhow::bail!("[limits] upgrade_timeout_secs must be at least 1");
+        }
+        if self.max_line_bytes < 1024 {
+            anyhow::bail!("[limits] max_line_bytes must be at least 1024");
+        }
+        Ok(())
+    }
+}
+
+/// Liveness probe for idle pooled agents. Disabled when the section is absent.
+///
+/// Every `interval_secs`, each agent with no client connected is sent a
+/// JSON-RPC request for `method`; any response (even an error) counts as
+/// alive. Agents that don't answer within `timeout_secs` are killed, so the
+/// next connection spawns a fresh process instead of landing on a hung one.
+///
+/// Example `common.toml` entry:
+/// ```toml
+/// [health_check]
+/// method        = "ping"
+/// interval_secs = 60
+/// timeout_secs  = 10
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct HealthCheckConfig {
+    /// JSON-RPC method to call (default: `"ping"`). Use a no-op the agent
+    /// implements or advertises if it treats unknown methods as fatal.
+    pub method: String,
+    /// Seconds between probes (default: 60).
+    pub interval_secs: u64,
+    /// Seconds an agent has to answer a probe (default: 10).
+    pub timeout_secs: u64,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            method: "ping".to_string(),
+            interval_secs: 60,
+            timeout_secs: 10,
+        }
+    }
+}
+
+impl HealthCheckConfig {
+    /// Reject probes that could never succeed or would run continuously.
+    pub fn validate(&self) -> Result<()> {
+        if self.method.is_empty() {
+            anyhow::bail!("[health_check] method must not be empty");
+        }
+        if self.interval_secs == 0 {
+            anyhow::bail!("[health_check] interval_secs must be at least 1");
+        }
+        if self.timeout_secs == 0 {
+            anyhow::bail!("[health_check] timeout_secs must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// Memory watchdog for small hosts. Disabled when the section is absent.
+///
+/// Every `interval_secs` the bridge's resident memory (RSS) is sampled. Above
+/// `max_rss_mb` it stops accepting connections (answering 429 / close 1013),
+/// trims every agent's buffered messages, and logs and pushes a warning. It
+/// accepts again once RSS falls below 90% of the limit. The cap on concurrent
+/// connections is `[limits] max_connections`.
+///
+/// Example `common.toml` entry:
+/// ```toml
+/// [memory_watchdog]
+/// max_rss_mb    = 512
+/// interval_secs = 10
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct MemoryWatchdogConfig {
+    /// Resident memory, in MiB, above which new connections are refused (default: 512).
+    pub max_rss_mb: u64,
+    /// Seconds between samples (default: 10).
+    pub interval_secs: u64,
+}
+
+impl Default for MemoryWatchdogConfig {
+    fn default() -> Self {
+        Self { max_rss_mb: 512, interval_secs: 10 }
+    }
+}
+
+impl MemoryWatchdogConfig {
+    /// Reject a zero limit (every sample would shed) and a zero interval.
+    pub fn validate(&self) -> Result<()> {
+        if self.max_rss_mb == 0 {
+            anyhow::bail!("[memory_watchdog] max_rss_mb must be at least 1");
+        }
+        if self.interval_secs == 0 {
+            anyhow::bail!("[memory_watchdog] interval_secs must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// Tighter pool limits while a laptop runs on a low battery (see
+/// [`crate::power`]).
+///
+/// Every `interval_secs` the power source is read (Linux and macOS). On
+/// battery below `battery_below_percent`, the pool keeps at most `max_agents`
+/// agents, reaps idle ones after `idle_timeout_secs`, and pauses the warm
+/// pool. Normal limits return once the machine is plugged in.
+///
+/// ```toml
+/// [power]
+/// battery_below_percent = 30
+/// max_agents            = 2
+/// idle_timeout_secs     = 300
+/// pause_warm_pool       = true
+/// interval_secs         = 60
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct PowerConfig {
+    /// Battery charge, in percent, below which power saving starts (default: 30).
+    pub battery_below_percent: u8,
+    /// Agent limit while saving, if lower than the pool's (default: 2).
+    pub max_agents: usize,
+    /// Idle timeout in seconds while saving, if shorter than the agent's (default: 300).
+    pub idle_timeout_secs: u64,
+    /// Stop keeping warm agents while saving (default: true).
+    pub pause_warm_pool: bool,
+    /// Seconds between readings of the power source (default: 60).
+    pub interval_secs: u64,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self { battery_below_percent: 30, max_agents: 2, idle_timeout_secs: 300, pause_warm_pool: true, interval_secs: 60 }
+    }
+}
+
+impl PowerConfig {
+    /// Reject thresholds outside 1–100 and zero limits or intervals.
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=100).contains(&self.battery_below_percent) {
+            anyhow::bail!("[power] battery_below_percent must be between 1 and 100");
+        }
+        if self.max_agents == 0 {
+            anyhow::bail!("[power] max_agents must be at least 1");
+        }
+        if self.idle_timeout_secs == 0 {
+            anyhow::bail!("[power] idle_timeout_secs must be at least 1");
+        }
+        if self.interval_secs == 0 {
+            anyhow::bail!("[power] interval_secs must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// Alert on and slow down addresses that keep failing to authenticate (see
+/// [`crate::auth_failures`]).
+///
+/// ```toml
+/// [auth_failures]
+/// threshold   = 10
+/// window_secs = 600
+/// tarpit_secs = 5
+/// push        = true
+/// webhook_url = "https://hooks.example.com/bridge"
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct AuthFailureConfig {
+    /// Failures from one address within `window_secs` that trigger the alert
+    /// and the tarpit (default: 10).
+    pub threshold: usize,
+    /// Length of the sliding window in seconds (default: 600).
+    pub window_secs: u64,
+    /// Seconds every request from an address over the threshold waits before
+    /// it is answered (default: 5, 0 = no tarpit).
+    pub tarpit_secs: u64,
+    /// Alert through the push relay, when configured (default: true).
+    pub push: bool,
+    /// Also POST a JSON alert to this URL.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook_url: Option<String>,
+}
+
+impl Default for AuthFailureConfig {
+    fn default() -> Self {
+        Self { threshold: 10, window_secs: 600, tarpit_secs: 5, push: true, webhook_url: None }
+    }
+}
+
+impl AuthFailureConfig {
+    /// Reject zero thresholds and windows, tarpits long enough to pin
+    /// connection slots for minutes, and webhook URLs that aren't HTTP(S).
+    pub fn validate(&self) -> Result<()> {
+        if self.threshold == 0 {
+            anyhow::bail!("[auth_failures] threshold must be at least 1");
+        }
+        if self.window_secs == 0 {
+            anyhow::bail!("[auth_failures] window_secs must be at least 1");
+        }
+        if self.tarpit_secs > 60 {
+            anyhow::bail!("[auth_failures] tarpit_secs must be at most 60");
+        }
+        if let Some(ref url) = self.webhook_url {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                anyhow::bail!("[auth_failures] webhook_url must be an http(s) URL");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Refuse requests from unexpected countries on the Cloudflare transport,
+/// by Cloudflare's `CF-IPCountry` header (see [`crate::geo_filter`]).
+///
+/// ```toml
+/// [geo_filter]
+/// allow_countries = ["DE", "NL"]
+/// deny_countries  = ["T1"]
+/// allow_unknown   = false
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct GeoFilterConfig {
+    /// ISO 3166-1 alpha-2 codes allowed to connect; any country when empty.
+    pub allow_countries: Vec<String>,
+    /// Codes refused even when allowed (`T1` is Tor).
+    pub deny_countries: Vec<String>,
+    /// Admit requests whose country Cloudflare doesn't know (`XX`) or
+    /// that lack the header (default: false).
+    pub allow_unknown: bool,
+}
+
+impl GeoFilterConfig {
+    /// Reject a filter that admits everything and codes that aren't two letters or digits.
+    pub fn validate(&self) -> Result<()> {
+        if self.allow_countries.is_empty() && self.deny_countries.is_empty() {
+            anyhow::bail!("[geo_filter] needs allow_countries or deny_countries");
+        }
+        for code in self.allow_countries.iter().chain(&self.deny_countries) {
+            if code.len() != 2 || !code.chars().all(|c| c.is_ascii_alphanumeric()) {
+                anyhow::bail!("[geo_filter] {:?} is not a two-letter country code", code);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Local MaxMind databases for the country and network of connecting
+/// addresses in logs and alerts (see [`crate::geoip`]).
+///
+/// ```toml
+/// [geoip]
+/// country_db = "/var/lib/GeoIP/GeoLite2-Country.mmdb"
+/// asn_db     = "/var/lib/GeoIP/GeoLite2-ASN.mmdb"
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct GeoIpConfig {
+    /// GeoLite2-Country or GeoIP2-Country database.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country_db: Option<PathBuf>,
+    /// GeoLite2-ASN database.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asn_db: Option<PathBuf>,
+}
+
+impl GeoIpConfig {
+    /// Reject a section without a database.
+    pub fn validate(&self) -> Result<()> {
+        if self.country_db.is_none() && self.asn_db.is_none() {
+            anyhow::bail!("[geoip] needs country_db or asn_db");
+        }
+        Ok(())
+    }
+}
+
+/// Short-lived session tokens issued at `POST /session-token`.
+///
+/// ```toml
+/// [session_tokens]
+/// ttl_secs = 900
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct SessionTokenConfig {
+    /// Lifetime of each session token in seconds (default: 900).
+    pub ttl_secs: u64,
+}
+
+impl Default for SessionTokenConfig {
+    fn default() -> Self {
+        Self { ttl_secs: 900 }
+    }
+}
+
+impl SessionTokenConfig {
+    /// Reject lifetimes too short for a client to refresh in time.
+    pub fn validate(&self) -> Result<()> {
+        if self.ttl_secs < 60 {
+            anyhow::bail!("[session_tokens] ttl_secs must be at least 60");
+        }
+        Ok(())
+    }
+}
+
+/// How clients may present their token besides the `X-Bridge-Token` and
+/// `Authorization: Bearer` headers.
+///
+/// ```toml
+/// [client_auth]
+/// query_token   = false  # refuse ?token= (default: true)
+/// first_message = true   # let WebSocket clients send bridge/auth instead
+/// first_message_timeout_secs = 10
+/// ```
+///
+/// With `first_message`, a WebSocket upgrade without any token is accepted,
+/// and nothing else happens on the connection until its first message,
+/// `{"jsonrpc":"2.0","id":0,"method":"bridge/auth","params":{"token":"…"}}`,
+/// presents a valid token. Otherwise it is closed after the timeout.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct ClientAuthConfig {
+    /// Accept the token as `?token=` in the URL, which proxies and servers
+    /// tend to log (default: true).
+    pub query_token: bool,
+    /// Accept WebSocket connections without a token that authenticate with a
+    /// `bridge/auth` first message (default: false).
+    pub first_message: bool,
+    /// Seconds such a connection has to send it (default: 10).
+    pub first_message_timeout_secs: u64,
+}
+
+impl Default for ClientAuthConfig {
+    fn default() -> Self {
+        Self { query_token: true, first_message: false, first_message_timeout_secs: 10 }
+    }
+}
+
+impl ClientAuthConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn first_message_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.first_message_timeout_secs)
+    }
+
+    /// Reject a timeout no client could meet, or one that holds unauthenticated
+    /// connections open for long.
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=120).contains(&self.first_message_timeout_secs) {
+            anyhow::bail!("[client_auth] first_message_timeout_secs must be between 1 and 120");
+        }
+        Ok(())
+    }
+}
+
+/// Require fresh pairing after a stretch without any connection (see
+/// [`crate::auto_lock`]).
+///
+/// ```toml
+/// [auto_lock]
+/// after_days = 30
+/// warn_days  = 3
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct AutoLockConfig {
+    /// Days without a successful connection after which every device token
+    /// is replaced (default: 30).
+    pub after_days: u64,
+    /// Days before that to push a warning (default: 3, 0 = no warning).
+    pub warn_days: u64,
+}
+
+impl Default for AutoLockConfig {
+    fn default() -> Self {
+        Self { after_days: 30, warn_days: 3 }
+    }
+}
+
+impl AutoLockConfig {
+    /// Reject a zero period and warnings that would start before it does.
+    pub fn validate(&self) -> Result<()> {
+        if self.after_days == 0 {
+            anyhow::bail!("[auto_lock] after_days must be at least 1");
+        }
+        if self.warn_days >= self.after_days {
+            anyhow::bail!("[auto_lock] warn_days must be less than after_days");
+        }
+        Ok(())
+    }
+}
+
+/// Daily message counts per device and soft budgets (see [`crate::usage`]).
+///
+/// A device is a `[[devices]]` token or a paired device, with the user's
+/// name in front for `[[users]]` (`alice/phone`). Budgets apply to each
+/// device separately; `devices` overrides them for some.
+///
+/// ```toml
+/// [usage]
+/// daily_requests = 200          # requests to the agent per device and day
+/// daily_bytes    = 50_000_000   # messages in both directions
+/// over_budget    = "deny"       # refuse session/prompt; "warn" (default) only logs
+/// retention_days = 90
+///
+/// [usage.devices."alice/phone"]
+/// daily_requests = 50
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct UsageConfig {
+    /// Requests to the agent per device and day; unlimited when absent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_requests: Option<u64>,
+    /// Bytes of messages, both directions, per device and day; unlimited when absent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_bytes: Option<u64>,
+    /// What happens once a device is over its budget (default: warn).
+    pub over_budget: OverBudget,
+    /// Days of counters kept in `usage.json` (default: 90).
+    pub retention_days: u32,
+    /// Budgets of single devices, keyed by device name.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub devices: BTreeMap<String, UsageBudget>,
+}
+
+impl Default for UsageConfig {
+    fn default() -> Self {
+        Self { daily_requests: None, daily_bytes: None, over_budget: OverBudget::Warn, retention_days: 90, devices: BTreeMap::new() }
+    }
+}
+
+/// A device's daily budget in `[usage.devices]`; the `[usage]` value
+/// applies to a limit left out.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UsageBudget {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_requests: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_bytes: Option<u64>,
+}
+
+/// What the bridge does once a device is over its daily budget.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OverBudget {
+    /// Log a warning, once per device and day.
+    #[default]
+    Warn,
+    /// Also answer `session/prompt` with an error until the next day.
+    Deny,
+}
+
+impl UsageConfig {
+    /// The budget of `device`: its `[usage.devices]` entry over the defaults.
+    pub fn budget(&self, device: &str) -> UsageBudget {
+        let own = self.devices.get(device).copied().unwrap_or_default();
+        UsageBudget {
+            daily_requests: own.daily_requests.or(self.daily_requests),
+            daily_bytes: own.daily_bytes.or(self.daily_bytes),
+        }
+    }
+
+    /// Reject zero budgets, which would refuse everything, and zero retention.
+    pub fn validate(&self) -> Result<()> {
+        if self.retention_days == 0 {
+            anyhow::bail!("[usage] retention_days must be at least 1");
+        }
+        let budgets = std::iter::once(("[usage]".to_string(), UsageBudget { daily_requests: self.daily_requests, daily_bytes: self.daily_bytes }))
+            .chain(self.devices.iter().map(|(device, budget)| (format!("[usage.devices.\"{}\"]", device), *budget)));
+        for (section, budget) in budgets {
+            if budget.daily_requests == Some(0) {
+                anyhow::bail!("{} daily_requests must be at least 1", section);
+            }
+            if budget.daily_bytes == Some(0) {
+                anyhow::bail!("{} daily_bytes must be at least 1", section);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An agent command the bridge may run (see [`crate::agent_allowlist`]).
+///
+/// ```toml
+/// [[allowed_agents]]
+/// program = "copilot"           # name on PATH or path to the binary
+/// args    = ["--acp"]           # exactly these arguments; any when omitted
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AllowedAgentConfig {
+    /// Binary, as a name looked up on `PATH` or a path.
+    pub program: String,
+    /// The only arguments it may be given. Any arguments when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<String>>,
+}
+
+impl AllowedAgentConfig {
+    /// Reject empty programs and programs with arguments folded in.
+    pub fn validate(&self) -> Result<()> {
+        if self.program.trim().is_empty() {
+            anyhow::bail!("[[allowed_agents]] program must not be empty");
+        }
+        if self.program.contains(char::is_whitespace) {
+            anyhow::bail!("[[allowed_agents]] {:?}: put arguments in args, not program", self.program);
+        }
+        Ok(())
+    }
+}
+
+/// An extra device token limited to some scopes (see [`crate::device_tokens`]).
+///
+/// ```toml
+/// [[devices]]
+/// name   = "team-tablet"
+/// token  = "<random string, e.g. from `openssl rand -hex 24`>"
+/// scopes = ["chat"]
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DeviceConfig {
+    /// Shown in logs.
+    pub name: String,
+    /// Token the device presents instead of the auth token.
+    pub token: String,
+    /// Any of `chat`, `file-download`, `file-upload`, `terminal`, `admin`.
+    pub scopes: Vec<Scope>,
+}
+
+impl DeviceConfig {
+    /// Reject unnamed devices, short tokens and tokens without scopes.
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            anyhow::bail!("[[devices]] name must not be empty");
+        }
+        if self.token.len() < 16 {
+            anyhow::bail!("[[devices]] {:?}: token must be at least 16 characters", self.name);
+        }
+        if self.scopes.is_empty() {
+            anyhow::bail!("[[devices]] {:?}: scopes must not be empty", self.name);
+        }
+        Ok(())
+    }
+}
+
+/// Another person sharing the bridge, with their own auth token, devices,
+/// agent ID and agent quota. Their agents, memory file and transcripts are
+/// kept apart from the owner's and every other user's.
+///
+/// ```toml
+/// [[users]]
+/// name       = "alice"
+/// auth_token = "<random string, e.g. from `openssl rand -hex 24`>"
+/// max_agents = 3
+///
+/// [[users.devices]]
+/// name   = "alice-tablet"
+/// token  = "<another random string>"
+/// scopes = ["chat"]
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct UserConfig {
+    /// Shown in logs; letters, digits, `-` and `_`.
+    pub name: String,
+    /// The user's stable ID, like the top-level `agent_id`. Generated on
+    /// first start.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub agent_id: String,
+    /// The user's token with every scope, like the top-level `auth_token`.
+    pub auth_token: String,
+    /// Pooled agents the user's tokens may hold at once; only the pool-wide
+    /// limit applies when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_agents: Option<usize>,
+    /// The user's own device tokens.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<DeviceConfig>,
+}
+
+impl UserConfig {
+    /// Reject names unfit for logs and file names, short tokens, a zero
+    /// quota and invalid devices.
+    pub fn validate(&self) -> Result<()> {
+        if self.name.is_empty() || !self.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            anyhow::bail!("[[users]] {:?}: name must be letters, digits, '-' or '_'", self.name);
+        }
+        if self.auth_token.len() < 16 {
+            anyhow::bail!("[[users]] {:?}: auth_token must be at least 16 characters", self.name);
+        }
+        if self.max_agents == Some(0) {
+            anyhow::bail!("[[users]] {:?}: max_agents must be at least 1", self.name);
+        }
+        for device in &self.devices {
+            device.validate().with_context(|| format!("[[users]] {:?}", self.name))?;
+        }
+        Ok(())
+    }
+}
+
+/// A project directory the app can switch the agent to with
+/// `bridge/selectWorkspace`.
+///
+/// ```toml
+/// [[workspaces]]
+/// name = "api"
+/// path = "/home/me/src/api"
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct WorkspaceConfig {
+    /// Shown in the app and used to select the workspace.
+    pub name: String,
+    /// Working directory of the agent (absolute).
+    pub path: PathBuf,
+}
+
+impl WorkspaceConfig {
+    /// Reject unnamed workspaces and relative paths.
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            anyhow::bail!("[[workspaces]] name must not be empty");
+        }
+        if !self.path.is_absolute() {
+            anyhow::bail!("[[workspaces]] {:?}: path must be absolute", self.name);
+        }
+        Ok(())
+    }
+}
+
+/// A git worktree per device, so the agents of simultaneous sessions don't
+/// edit the same checkout (see [`crate::worktree`]).
+///
+/// ```toml
+/// [worktrees]
+/// root          = "/home/me/.bridge-worktrees"
+/// branch_prefix = "bridge/"
+/// push_remote   = "origin"
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct WorktreeConfig {
+    /// Directory the worktrees are created in (absolute).
+    pub root: PathBuf,
+    /// Prefix of each worktree's branch name (default: `bridge/`).
+    #[serde(default = "branch_prefix_default")]
+    pub branch_prefix: String,
+    /// Remote that `bridge/session/finish` pushes kept branches to. Branches
+    /// stay local when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub push_remote: Option<String>,
+}
+
+fn branch_prefix_default() -> String { "bridge/".to_string() }
+
+impl WorktreeConfig {
+    /// Reject relative roots and prefixes git wouldn't accept in a branch name.
+    pub fn validate(&self) -> Result<()> {
+        if !self.root.is_absolute() {
+            anyhow::bail!("[worktrees] root must be absolute");
+        }
+        if self.branch_prefix.contains(char::is_whitespace) || self.branch_prefix.contains("..") || self.branch_prefix.starts_with('-') {
+            anyhow::bail!("[worktrees] branch_prefix {:?} is not a valid branch name prefix", self.branch_prefix);
+        }
+        Ok(())
+    }
+}
+
+/// Run `bridge wake-relay`: accept connections on an always-on machine and
+/// forward them to a workstation's bridge, waking it with a Wake-on-LAN magic
+/// packet first when it's asleep (see [`crate::wake_relay`]).
+///
+/// ```toml
+/// [wake_relay]
+/// listen    = "0.0.0.0:8765"
+/// target    = "192.168.1.20:8765"
+/// mac       = "3c:7c:3f:12:34:56"
+/// broadcast = "192.168.1.255:9"
+/// wait_secs = 120
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct WakeRelayConfig {
+    /// Address the relay accepts connections on (default: `0.0.0.0:8765`).
+    #[serde(default = "wake_listen_default")]
+    pub listen: String,
+    /// `host:port` of the workstation's bridge.
+    pub target: String,
+    /// MAC address of the workstation's network interface.
+    pub mac: String,
+    /// Where to send the magic packet (default: `255.255.255.255:9`). Use the
+    /// subnet's broadcast address when the relay has several interfaces.
+    #[serde(default = "wake_broadcast_default")]
+    pub broadcast: String,
+    /// Seconds to wait for the bridge to come up before giving up on a
+    /// connection (default: 120).
+    #[serde(default = "wake_wait_secs_default")]
+    pub wait_secs: u64,
+}
+
+fn wake_listen_default() -> String { "0.0.0.0:8765".to_string() }
+fn wake_broadcast_default() -> String { "255.255.255.255:9".to_string() }
+fn wake_wait_secs_default() -> u64 { 120 }
+
+impl WakeRelayConfig {
+    /// Reject missing addresses, malformed MACs and a zero wait.
+    pub fn validate(&self) -> Result<()> {
+        if self.target.trim().is_empty() {
+            anyhow::bail!("[wake_relay] target must be the workstation bridge's host:port");
+        }
+        crate::wake_relay::parse_mac(&self.mac).context("[wake_relay] mac")?;
+        if self.broadcast.parse::<std::net::SocketAddr>().is_err() {
+            anyhow::bail!("[wake_relay] broadcast must be an IP:port, e.g. 192.168.1.255:9");
+        }
+        if self.wait_secs == 0 {
+            anyhow::bail!("[wake_relay] wait_secs must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// Proxy for outbound HTTP traffic and cloudflared (see [`crate::proxy`]).
+/// Without it, the `HTTPS_PROXY` / `HTTP_PROXY` / `NO_PROXY` environment
+/// variables apply.
+///
+/// ```toml
+/// [proxy]
+/// url      = "http://proxy.corp.example:3128"
+/// no_proxy = "localhost,127.0.0.1,.corp.example"
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProxyConfig {
+    /// `http://`, `https://`, `socks5://` or `socks5h://` URL of the proxy,
+    /// with `user:password@` when it needs credentials.
+    pub url: String,
+    /// Comma-separated hosts, domains (`.example.com`) and CIDR ranges that
+    /// are reached directly.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<String>,
+}
+
+impl ProxyConfig {
+    /// Reject URLs that aren't an HTTP or SOCKS5 proxy.
+    pub fn validate(&self) -> Result<()> {
+        let url = reqwest::Url::parse(&self.url).with_context(|| format!("[proxy] url {:?} is not a URL", self.url))?;
+        if !matches!(url.scheme(), "http" | "https" | "socks5" | "socks5h") || url.host_str().is_none() {
+            anyhow::bail!("[proxy] url must be an http://, https://, socks5:// or socks5h:// proxy URL");
+        }
+        reqwest::Proxy::all(url.as_str()).context("[proxy] url")?;
+        Ok(())
+    }
+}
+
+/// Bridge-side approval of each pairing, so a shoulder-surfed QR code isn't
+/// enough to obtain the auth token.
+///
+/// ```toml
+/// [pairing_approval]
+/// auto_approve = []
+/// timeout_secs = 60
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct PairingApprovalConfig {
+    /// Networks (CIDR) whose devices are approved without asking. Matches the
+    /// connection's peer address, which is the proxy's behind
+    /// `tailscale-serve` or Cloudflare. Anyone on a listed network who sees a
+    /// QR code pairs unprompted, so keep this to single hosts (`/32`) if used.
+    pub auto_approve: Vec<String>,
+    /// Seconds to wait for an answer before declining (default: 60).
+    pub timeout_secs: u64,
+}
+
+impl Default for PairingApprovalConfig {
+    fn default() -> Self {
+        Self {
+            auto_approve: Vec::new(),
+            timeout_secs: 60,
+        }
+    }
+}
+
+impl PairingApprovalConfig {
+    /// Reject malformed networks and a zero timeout (every prompt would be declined).
+    pub fn validate(&self) -> Result<()> {
+        for net in &self.auto_approve {
+            net.parse::<ipnet::IpNet>()
+                .map_err(|_| anyhow::anyhow!("[pairing_approval] auto_approve entry {:?} is not a CIDR like \"10.0.0.0/8\"", net))?;
+        }
+        if self.timeout_secs == 0 {
+            anyhow::bail!("[pairing_approval] timeout_secs must be at least 1");
+        }
+        Ok(())
+    }
+
+    /// Whether a device connecting from `ip` is approved by policy.
+    pub fn auto_approves(&self, ip: &str) -> bool {
+        let Ok(ip) = ip.parse::<std::net::IpAddr>() else {
+            return false;
+        };
+        self.auto_approve
+            .iter()
+            .filter_map(|net| net.parse::<ipnet::IpNet>().ok())
+            .any(|net| net.contains(&ip))
+    }
+}
+
+/// App deep links (`aptove://pair?...`) printed alongside the pairing QR code.
+///
+/// ```toml
+/// [deep_link]
+/// scheme = "aptove"
+/// qr     = true      # encode the deep link in the QR instead of the raw URL
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct DeepLinkConfig {
+    /// URL scheme the mobile app registers (default: `"aptove"`).
+    pub scheme: String,
+    /// Encode the deep link in the QR code instead of the pairing URL or
+    /// connection JSON (default: false, for apps that predate deep links).
+    pub qr: bool,
+}
+
+impl Default for DeepLinkConfig {
+    fn default() -> Self {
+        Self {
+            scheme: "aptove".to_string(),
+            qr: false,
+        }
+    }
+}
+
+impl DeepLinkConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Reject schemes that aren't valid per RFC 3986 (`ALPHA *( ALPHA / DIGIT / "+" / "-" / "." )`).
+    pub fn validate(&self) -> Result<()> {
+        let mut chars = self.scheme.chars();
+        let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+            && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+        if !valid {
+            anyhow::bail!("[deep_link] scheme {:?} is not a valid URL scheme", self.scheme);
+        }
+        Ok(())
+    }
+}
+
+/// Clock skew check at startup and in `bridge status` (see [`crate::clock_skew`]).
+///
+/// ```toml
+/// [clock_check]
+/// enabled       = true
+/// url           = "https://cloudflare.com"
+/// max_skew_secs = 60
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct ClockCheckConfig {
+    /// Check the clock at all (default: true).
+    pub enabled: bool,
+    /// `http(s)://` URL whose `Date` response header is trusted (default:
+    /// `"https://cloudflare.com"`).
+    pub url: String,
+    /// Warn when the clock is off by more than this many seconds (default: 60).
+    pub max_skew_secs: u64,
+}
+
+impl Default for ClockCheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            url: "https://cloudflare.com".to_string(),
+            max_skew_secs: 60,
+        }
+    }
+}
+
+impl ClockCheckConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Reject non-HTTP URLs and a zero threshold.
+    pub fn validate(&self) -> Result<()> {
+        if !(self.url.starts_with("https://") || self.url.starts_with("http://")) {
+            anyhow::bail!("[clock_check] url {:?} must start with https:// or http://", self.url);
+        }
+        if self.max_skew_secs == 0 {
+            anyhow::bail!("[clock_check] max_skew_secs must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// Pairing over Bluetooth LE (requires the `ble-pairing` feature, Linux/BlueZ).
+///
+/// ```toml
+/// [ble_pairing]
+/// adapter = "hci0"
+/// name    = "Aptove Bridge"
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct BlePairingConfig {
+    /// BlueZ adapter to advertise on (default: `"hci0"`).
+    pub adapter: String,
+    /// Advertised local name (default: `"Aptove Bridge"`).
+    pub name: String,
+}
+
+impl Default for BlePairingConfig {
+    fn default() -> Self {
+        Self {
+            adapter: "hci0".to_string(),
+            name: "Aptove Bridge".to_string(),
+        }
+    }
+}
+
+impl BlePairingConfig {
+    /// Reject adapter names BlueZ could never expose as an object path.
+    pub fn validate(&self) -> Result<()> {
+        if self.adapter.is_empty() || !self.adapter.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            anyhow::bail!("[ble_pairing] adapter must be an adapter name like \"hci0\"");
+        }
+        if self.name.is_empty() {
+            anyhow::bail!("[ble_pairing] name must not be empty");
+        }
+        Ok(())
+    }
+}
+
+/// Idle timeout and buffer limits for one device's or one agent's pooled
+/// agents, overriding the pool defaults (30 min, and the `[buffer]` section).
+///
+/// Set exactly one of `token` (a device's auth token) or `agent` (matches
+/// agent commands containing this string). When both a token and an agent
+/// override apply, the token's settings win field by field.
+///
+/// Example `common.toml` entries:
+/// ```toml
+/// [[pool_overrides]]
+/// agent             = "claude"
+/// idle_timeout_secs = 7200
+/// max_buffer_size   = 5000
+///
+/// [[pool_overrides]]
+/// token             = "<test device's auth token>"
+/// idle_timeout_secs = 300
+/// buffer_ttl_secs   = 600
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct PoolOverrideConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+    /// Seconds an agent with no client connected is kept alive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_secs: Option<u64>,
+    /// Agent messages kept for replay while no client is connected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_buffer_size: Option<usize>,
+    /// Total bytes of those messages (0 = unlimited).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_buffer_bytes: Option<usize>,
+    /// Seconds a buffered message is kept (0 = until the agent exits).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buffer_ttl_secs: Option<u64>,
+}
+
+impl PoolOverrideConfig {
+    /// Reject overrides that match nothing, everything, or change nothing.
+    pub fn validate(&self) -> Result<()> {
+        match (&self.token, &self.agent) {
+            (Some(_), Some(_)) | (None, None) => {
+                anyhow::bail!("[[pool_overrides]] needs exactly one of `token` or `agent`")
+            }
+            (Some(v), None) | (None, Some(v)) if v.is_empty() => {
+                anyhow::bail!("[[pool_overrides]] `token`/`agent` must not be empty")
+            }
+            _ => {}
+        }
+        if self.idle_timeout_secs.is_none()
+            && self.max_buffer_size.is_none()
+            && self.max_buffer_bytes.is_none()
+            && self.buffer_ttl_secs.is_none()
+        {
+            anyhow::bail!("[[pool_overrides]] doesn't override anything");
+        }
+        if self.idle_timeout_secs == Some(0) {
+            anyhow::bail!("[[pool_overrides]] idle_timeout_secs must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// Run matching agents inside a Linux sandbox (see [`crate::sandbox`]).
+/// The first entry whose `agent` is a substring of the agent command applies;
+/// an entry without `agent` applies to every agent.
+///
+/// ```toml
+/// [[sandbox]]
+/// agent    = "claude"
+/// network  = true
+/// seccomp  = true
+/// writable = ["/home/me/.claude"]
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SandboxConfig {
+    /// Substring of the agent command this sandbox is for; every agent when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+    /// Keep network access (default: true).
+    #[serde(default = "sandbox_network_default")]
+    pub network: bool,
+    /// Refuse syscalls agents never need, such as `mount`, `ptrace` and
+    /// module loading (default: true).
+    #[serde(default = "sandbox_seccomp_default")]
+    pub seccomp: bool,
+    /// Absolute paths writable besides the agent's working directory, e.g.
+    /// the agent's own config directory. Missing paths are skipped.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub writable: Vec<PathBuf>,
+}
+
+fn desktop_notifications_default() -> bool { true }
+fn is_desktop_notifications_default(enabled: &bool) -> bool { *enabled }
+
+fn sandbox_network_default() -> bool { true }
+fn sandbox_seccomp_default() -> bool { true }
+
+impl SandboxConfig {
+    /// Reject empty agent patterns and relative writable paths.
+    pub fn validate(&self) -> Result<()> {
+        if self.agent.as_deref().is_some_and(|a| a.trim().is_empty()) {
+            anyhow::bail!("[[sandbox]] agent must not be empty");
+        }
+        if let Some(path) = self.writable.iter().find(|p| !p.is_absolute()) {
+            anyhow::bail!("[[sandbox]] writable path {} must be absolute", path.display());
+        }
+        Ok(())
+    }
+}
+
+/// Shell hooks run around matching agents (see [`crate::agent_hooks`]).
+///
+/// Example `common.toml` entry:
+/// ```toml
+/// [[agent_hooks]]
+/// agent                 = "claude"
+/// pre_spawn             = "git pull --ff-only && docker compose up -d"
+/// post_exit             = "docker compose down"
+/// on_permission_request = "notify-send 'The agent is waiting for approval'"
+/// timeout_secs          = 60
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct AgentHookConfig {
+    /// Substring of the agent command these hooks are for; every agent when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+    /// Run before the agent starts; the agent isn't started if it fails.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_spawn: Option<String>,
+    /// Run after the agent process exits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_exit: Option<String>,
+    /// Run when the agent asks for a permission.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_permission_request: Option<String>,
+    /// Seconds a hook may run before it is killed (default: 60).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// Longest `timeout_secs` of `[[agent_hooks]]`.
+const MAX_HOOK_TIMEOUT_SECS: u64 = 3600;
+
+impl AgentHookConfig {
+    /// How long each hook may run.
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.timeout_secs.unwrap_or(60))
+    }
+
+    /// Reject empty patterns and scripts, entries without a hook, and
+    /// timeouts of 0 or over an hour.
+    pub fn validate(&self) -> Result<()> {
+        if self.agent.as_deref().is_some_and(|a| a.trim().is_empty()) {
+            anyhow::bail!("[[agent_hooks]] agent must not be empty");
+        }
+        let scripts = [&self.pre_spawn, &self.post_exit, &self.on_permission_request];
+        if scripts.iter().all(|s| s.is_none()) {
+            anyhow::bail!("[[agent_hooks]] needs pre_spawn, post_exit or on_permission_request");
+        }
+        if scripts.iter().any(|s| s.as_deref().is_some_and(|s| s.trim().is_empty())) {
+            anyhow::bail!("[[agent_hooks]] hooks must not be empty");
+        }
+        if let Some(secs) = self.timeout_secs.filter(|s| *s == 0 || *s > MAX_HOOK_TIMEOUT_SECS) {
+            anyhow::bail!("[[agent_hooks]] timeout_secs must be between 1 and {}, got {}", MAX_HOOK_TIMEOUT_SECS, secs);
+        }
+        Ok(())
+    }
+}
+
+/// How a reconnecting client's handshake is answered from cached responses
+/// for matching agents (see [`crate::intercept`]), for agents whose methods
+/// aren't named as in ACP.
+///
+/// Example `common.toml` entry:
+/// ```toml
+/// [[intercepts]]
+/// agent                = "my-agent"
+/// initialize           = ["initialize"]
+/// new_session          = ["session/create"]
+/// load_session         = ["session/resume"]
+/// max_skipped          = 10
+/// synthetic_initialize = { protocolVersion = 1, agentCapabilities = {} }
+/// resume               = "load"
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct InterceptConfig {
+    /// Substring of the agent command this is for; every agent when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+    /// Requests answered with the cached `initialize` response.
+    #[serde(default = "default_initialize_methods")]
+    pub initialize: Vec<String>,
+    /// Requests that start a session, answered with the cached session.
+    /// Memory is injected again after them.
+    #[serde(default = "default_new_session_methods")]
+    pub new_session: Vec<String>,
+    /// Requests that resume a session, answered with the cached session.
+    /// A fresh agent has nothing to resume, so they get an error there.
+    #[serde(default = "default_load_session_methods")]
+    pub load_session: Vec<String>,
+    /// Messages passed on while waiting for the session request (default: 5).
+    #[serde(default = "default_max_skipped")]
+    pub max_skipped: usize,
+    /// `result` of the `initialize` reply sent when none was cached.
+    #[serde(default = "default_synthetic_initialize")]
+    pub synthetic_initialize: serde_json::Value,
+    /// How a session load on a reused agent is answered (default: cache).
+    #[serde(default)]
+    pub resume: SessionResume,
+}
+
+/// How `[[intercepts]]` answers a reconnecting client's session load.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionResume {
+    /// With the session response cached on the first connection.
+    #[default]
+    Cache,
+    /// By loading the agent's session with the agent's own load request, when
+    /// its `initialize` response advertises `loadSession`; else as `cache`.
+    Load,
+}
+
+fn default_initialize_methods() -> Vec<String> {
+    vec!["initialize".to_string()]
+}
+
+fn default_new_session_methods() -> Vec<String> {
+    vec!["session/new".to_string()]
+}
+
+fn default_load_session_methods() -> Vec<String> {
+    vec!["session/load".to_string()]
+}
+
+fn default_max_skipped() -> usize {
+    5
+}
+
+fn default_synthetic_initialize() -> serde_json::Value {
+    serde_json::json!({
+        "protocolVersion": 1,
+        "agentCapabilities": {},
+        "agentInfo": { "name": "bridge", "version": "1.0.0" }
+    })
+}
+
+/// Largest `max_skipped` of `[[intercepts]]`.
+const MAX_INTERCEPT_SKIPPED: usize = 100;
+
+impl Default for InterceptConfig {
+    fn default() -> Self {
+        Self {
+            agent: None,
+            initialize: default_initialize_methods(),
+            new_session: default_new_session_methods(),
+            load_session: default_load_session_methods(),
+            max_skipped: default_max_skipped(),
+            synthetic_initialize: default_synthetic_initialize(),
+            resume: SessionResume::default(),
+        }
+    }
+}
+
+impl InterceptConfig {
+    /// Whether `method` is answered with the cached `initialize` response.
+    pub fn is_initialize(&self, method: Option<&str>) -> bool {
+        method.is_some_and(|m| self.initialize.iter().any(|i| i == m))
+    }
+
+    /// Whether `method` starts a session.
+    pub fn is_new_session(&self, method: Option<&str>) -> bool {
+        method.is_some_and(|m| self.new_session.iter().any(|n| n == m))
+    }
+
+    /// Whether `method` resumes a session.
+    pub fn is_load_session(&self, method: Option<&str>) -> bool {
+        method.is_some_and(|m| self.load_session.iter().any(|l| l == m))
+    }
+
+    /// Reject empty patterns and method lists, a method in two lists,
+    /// `max_skipped` of 0 or over 100, and a non-object `synthetic_initialize`.
+    pub fn validate(&self) -> Result<()> {
+        if self.agent.as_deref().is_some_and(|a| a.trim().is_empty()) {
+            anyhow::bail!("[[intercepts]] agent must not be empty");
+        }
+        let lists = [("initialize", &self.initialize), ("new_session", &self.new_session), ("load_session", &self.load_session)];
+        for (name, methods) in lists {
+            if methods.is_empty() || methods.iter().any(|m| m.trim().is_empty()) {
+                anyhow::bail!("[[intercepts]] {} needs at least one method, and no empty names", name);
+            }
+        }
+        for (i, (name, methods)) in lists.iter().enumerate() {
+            for (other, others) in &lists[i + 1..] {
+                if let Some(method) = methods.iter().find(|m| others.contains(m)) {
+                    anyhow::bail!("[[intercepts]] method {:?} is in both {} and {}", method, name, other);
+                }
+            }
+        }
+        if self.max_skipped == 0 || self.max_skipped > MAX_INTERCEPT_SKIPPED {
+            anyhow::bail!("[[intercepts]] max_skipped must be between 1 and {}, got {}", MAX_INTERCEPT_SKIPPED, self.max_skipped);
+        }
+        if !self.synthetic_initialize.is_object() {
+            anyhow::bail!("[[intercepts]] synthetic_initialize must be a table");
+        }
+        Ok(())
+    }
+}
+
+/// Limits on agent messages buffered for replay while no client is connected.
+///
+/// When a buffer is over budget, intermediate streaming chunks are dropped
+/// first, then other notifications; permission requests and responses go last.
+///
+/// Example `common.toml` entry (defaults shown):
+/// ```toml
+/// [buffer]
+/// max_messages = 10000
+/// max_bytes    = 8388608   # 8 MiB; 0 = unlimited
+/// ttl_secs     = 0         # 0 = keep until the agent exits
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct BufferConfig {
+    /// Messages kept per agent.
+    pub max_messages: usize,
+    /// Total bytes kept per agent (0 = unlimited).
+    pub max_bytes: usize,
+    /// Seconds a message is kept (0 = until the agent exits).
+    pub ttl_secs: u64,
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        Self { max_messages: 10_000, max_bytes: 8 * 1024 * 1024, ttl_secs: 0 }
+    }
+}
+
+impl BufferConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.max_messages == 0 {
+            anyhow::bail!("[buffer] max_messages must be at least 1");
+        }
+        Ok(())
+    }
 }
 
-/// A slash command advertised to connected clients via `available_commands_update`.
+/// How `bridge/endSession` stops a client's agent.
 ///
-/// Define these in `common.toml` for agents that don't send `available_commands_update`
-/// themselves (e.g. Copilot CLI, Goose).
+/// The agent's stdin is closed, or, with `shutdown_method`, it is sent that
+/// JSON-RPC request instead. Either way it has `timeout_secs` to exit before
+/// it is killed.
 ///
 /// Example `common.toml` entry:
 /// ```toml
-/// [[slash_commands]]
-/// name        = "fix"
-/// description = "Fix the selected code"
+/// [end_session]
+/// shutdown_method = "shutdown"   # default: close stdin
+/// timeout_secs    = 5
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct EndSessionConfig {
+    /// JSON-RPC method to call instead of closing stdin.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shutdown_method: Option<String>,
+    /// Seconds the agent has to exit (default: 5).
+    pub timeout_secs: u64,
+}
+
+impl Default for EndSessionConfig {
+    fn default() -> Self {
+        Self { shutdown_method: None, timeout_secs: 5 }
+    }
+}
+
+impl EndSessionConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.shutdown_method.as_deref() == Some("") {
+            anyhow::bail!("[end_session] shutdown_method must not be empty");
+        }
+        if self.timeout_secs == 0 {
+            anyhow::bail!("[end_session] timeout_secs must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// Per-connection rate shaping of streamed agent output to WebSocket
+/// clients (see [`crate::output_shaping`]). Off unless a rate is set.
 ///
-/// [[slash_commands]]
-/// name        = "explain"
-/// description = "Explain the code, optionally with a focus"
-/// input_hint  = "what to focus on"
+/// Example `common.toml` entry:
+/// ```toml
+/// [output_shaping]
+/// messages_per_sec = 10
+/// bytes_per_sec    = 32768
+/// burst_secs       = 2
 /// ```
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct SlashCommandConfig {
-    /// Command name without the leading `/` (e.g. `"fix"`).
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct OutputShapingConfig {
+    /// Streaming updates sent per second (0 = unlimited).
+    pub messages_per_sec: u64,
+    /// Bytes of streaming updates sent per second (0 = unlimited).
+    pub bytes_per_sec: u64,
+    /// Seconds of unused rate a connection may send at once (default: 1).
+    pub burst_secs: u64,
+}
+
+impl Default for OutputShapingConfig {
+    fn default() -> Self {
+        Self { messages_per_sec: 0, bytes_per_sec: 0, burst_secs: 1 }
+    }
+}
+
+impl OutputShapingConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.burst_secs == 0 {
+            anyhow::bail!("[output_shaping] burst_secs must be at least 1");
+        }
+        if self.bytes_per_sec > 0 && self.bytes_per_sec < 1024 {
+            anyhow::bail!("[output_shaping] bytes_per_sec must be 0 or at least 1024");
+        }
+        Ok(())
+    }
+}
+
+/// A prompt sent to an agent on a schedule (see [`crate::scheduler`]).
+///
+/// Example `common.toml` entry:
+/// ```toml
+/// [[schedules]]
+/// name   = "ci-summary"
+/// cron   = "0 8 * * mon-fri"   # minute hour day-of-month month day-of-week, local time
+/// prompt = "Summarize last night's CI failures"
+/// device = "tablet"            # optional: a [[devices]] entry's agent
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScheduleConfig {
+    /// Shown in logs and push notifications.
     pub name: String,
-    /// Human-readable description shown in the picker.
-    pub description: String,
-    /// If set, the command accepts free-text input; this string is shown as
-    /// the placeholder hint in the text field.
+    /// When to run, as a 5-field cron expression in local time.
+    pub cron: String,
+    /// Text sent as a `session/prompt`.
+    pub prompt: String,
+    /// `[[devices]]` entry whose agent gets the prompt (default: the auth
+    /// token's, i.e. the phone's).
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub input_hint: Option<String>,
+    pub device: Option<String>,
+    /// Push the start of the agent's reply; when false, the push only says
+    /// the run finished (default: true).
+    #[serde(default = "schedule_push_default")]
+    pub push: bool,
+    /// Seconds the agent has to answer before the prompt is cancelled
+    /// (default: 600).
+    #[serde(default = "schedule_timeout_default")]
+    pub timeout_secs: u64,
 }
 
-/// Push relay configuration for sending background notifications.
+fn schedule_push_default() -> bool { true }
+fn schedule_timeout_default() -> u64 { 600 }
+
+impl ScheduleConfig {
+    /// Reject unnamed schedules, bad cron expressions and empty prompts.
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            anyhow::bail!("[[schedules]] name must not be empty");
+        }
+        if let Err(e) = crate::scheduler::Cron::parse(&self.cron) {
+            anyhow::bail!("[[schedules]] {:?}: {:#}", self.name, e);
+        }
+        if self.prompt.trim().is_empty() {
+            anyhow::bail!("[[schedules]] {:?}: prompt must not be empty", self.name);
+        }
+        if self.timeout_secs == 0 {
+            anyhow::bail!("[[schedules]] {:?}: timeout_secs must be at least 1", self.name);
+        }
+        Ok(())
+    }
+}
+
+/// Which idle agent the pool kills when it is full and a new token connects.
 ///
-/// All four fields are required — push is silently disabled if the section is
-/// absent or any field is empty.
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
-pub struct PushRelayConfig {
-    /// Base URL of the push relay service (e.g. "https://push.aptove.com").
-    #[serde(default, skip_serializing_if = "String::is_empty")]
-    pub url: String,
-    /// Base URL of the JWT token service (e.g. "https://token.aptove.com").
-    #[serde(default, skip_serializing_if = "String::is_empty")]
-    pub token_url: String,
-    /// OAuth2 client_id issued by the token service.
-    #[serde(default, skip_serializing_if = "String::is_empty")]
-    pub client_id: String,
-    /// OAuth2 client_secret issued by the token service.
-    #[serde(default, skip_serializing_if = "String::is_empty")]
-    pub client_secret: String,
+/// Agents with a connected client are never evicted. Among idle agents, lower
+/// `priorities` go first and `policy` breaks ties; `pinned` tokens are never
+/// evicted.
+///
+/// Example `common.toml` entry:
+/// ```toml
+/// [eviction]
+/// policy = "lfu"
+/// pinned = ["<primary phone's auth token>"]
+///
+/// [eviction.priorities]
+/// "<tablet's auth token>" = 10
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct EvictionConfig {
+    /// Order among idle agents of equal priority (default: `"lru"`).
+    pub policy: EvictionPolicy,
+    /// Per-token priority (default 0); higher-priority agents are evicted last.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub priorities: HashMap<String, u32>,
+    /// Tokens whose agents are never evicted (they still time out when idle).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pinned: Vec<String>,
+}
+
+impl EvictionConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Eviction order among idle agents of equal priority.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EvictionPolicy {
+    /// Least recently used: the agent idle the longest.
+    #[default]
+    Lru,
+    /// Least frequently used: the agent with the fewest client connections,
+    /// then the one idle the longest.
+    Lfu,
+}
+
+/// TLS protocol policy for transports where the bridge terminates TLS itself
+/// (local, tailscale-ip). Ignored when TLS is handled by an external proxy.
+///
+/// Example `common.toml` entry:
+/// ```toml
+/// [tls_policy]
+/// min_version   = "1.2"
+/// cipher_suites = ["TLS13_AES_256_GCM_SHA384", "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384"]
+/// alpn          = ["http/1.1"]
+/// key_storage   = "system"
+/// ca            = true
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct TlsPolicyConfig {
+    /// Lowest protocol version accepted: `"1.3"` (default) or `"1.2"`.
+    pub min_version: String,
+    /// Cipher suites to offer, by IANA name. Empty means the provider defaults
+    /// for the enabled protocol versions.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cipher_suites: Vec<String>,
+    /// ALPN protocol identifiers to advertise (default: `["http/1.1"]`).
+    pub alpn: Vec<String>,
+    /// Where the self-signed certificate's private key is kept.
+    pub key_storage: KeyStorage,
+    /// Instead of one self-signed certificate, keep a long-lived local CA
+    /// (`ca.pem`) that signs a short-lived leaf certificate per transport.
+    /// Pairing pins the CA, so renewing a leaf or changing addresses never
+    /// makes devices pair again. The CA key is always kept in `ca-key.pem`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub ca: bool,
+    /// Days a leaf certificate is valid with `ca = true` (default 30). A leaf
+    /// is renewed at start once less than a third of that is left.
+    pub leaf_validity_days: u32,
+}
+
+/// Storage backend for the TLS private key.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyStorage {
+    /// `key.pem` in the config directory (mode 0600).
+    #[default]
+    File,
+    /// The OS keystore (macOS Keychain, Linux Secret Service), falling back to
+    /// `File` when no keystore is reachable.
+    System,
+}
+
+impl Default for TlsPolicyConfig {
+    fn default() -> Self {
+        Self {
+            min_version: "1.3".to_string(),
+            cipher_suites: Vec::new(),
+            alpn: vec!["http/1.1".to_string()],
+            key_storage: KeyStorage::File,
+            ca: false,
+            leaf_validity_days: 30,
+        }
+    }
 }
 
 /// Stable agent identity and multi-transport settings.
@@ -70,6 +1858,11 @@ pub struct PushRelayConfig {
 /// Replaces the old `BridgeConfig` / `bridge.toml`. Stored as `common.toml`.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CommonConfig {
+    /// Layout version of this file; older layouts are upgraded on load (see
+    /// [`crate::config_migration`]). Missing in files older than version 1.
+    #[serde(default)]
+    pub version: u32,
+
     /// Stable UUID that identifies this agent across all transports.
     #[serde(default, skip_serializing_if = "String::is_empty")]
     pub agent_id: String,
@@ -78,6 +1871,12 @@ pub struct CommonConfig {
     #[serde(default, skip_serializing_if = "String::is_empty")]
     pub auth_token: String,
 
+    /// Management credential for the `/admin/...` endpoints, the web admin
+    /// UI and `bridge top`. Never handed out by pairing, and no client token
+    /// is accepted in its place. Generated on first start.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub admin_token: String,
+
     /// Per-transport configuration, keyed by transport name
     /// (e.g., `"local"`, `"cloudflare"`, `"tailscale-serve"`).
     #[serde(default)]
@@ -94,11 +1893,23 @@ pub struct CommonConfig {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub push_relay: Option<PushRelayConfig>,
 
+    /// Without a push relay, show permission requests and finished turns as
+    /// desktop notifications on this machine while no client is connected
+    /// (default: true).
+    #[serde(default = "desktop_notifications_default", skip_serializing_if = "is_desktop_notifications_default")]
+    pub desktop_notifications: bool,
+
     /// Agent command to launch (e.g., "copilot --acp"). Stored here so the
     /// wizard only asks once; previously it was a CLI flag on `bridge run`.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub agent_command: Option<String>,
 
+    /// Bump to restart idle pooled agents and drop their cached `initialize`
+    /// and session responses, for upgrades that don't change the agent
+    /// binary's modification time (e.g. `npx` packages).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_version: Option<String>,
+
     /// TCP address to bind the WebSocket server (default: "0.0.0.0").
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub bind_address: Option<String>,
@@ -114,9 +1925,192 @@ pub struct CommonConfig {
     /// Minimum log level shown in the TUI (ERROR / WARN / INFO / DEBUG / TRACE).
     #[serde(default = "log_level_default")]
     pub log_level: String,
+
+    /// Per-IP and global connection quotas for the WebSocket listener.
+    #[serde(default)]
+    pub limits: LimitsConfig,
+
+    /// TLS protocol version, cipher suite, and ALPN policy.
+    #[serde(default)]
+    pub tls_policy: TlsPolicyConfig,
+
+    /// Mirror session transcripts to S3-compatible storage. Disabled when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcripts: Option<TranscriptConfig>,
+
+    /// Newline-delimited JSON-RPC listeners (`tcp`, `unix`) alongside the
+    /// WebSocket server.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub listeners: Vec<ListenerConfig>,
+
+    /// Agent processes to pre-spawn and initialize at startup, so the first
+    /// connection from a new device skips the agent's cold start (default: 0).
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub warm_agents: usize,
+
+    /// Count, size and age limits for messages buffered while a client is away.
+    #[serde(default, skip_serializing_if = "BufferConfig::is_default")]
+    pub buffer: BufferConfig,
+
+    /// Per-device and per-agent idle timeout and buffer overrides.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pool_overrides: Vec<PoolOverrideConfig>,
+
+    /// Linux sandboxes for agent processes. Agents run unsandboxed when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sandbox: Vec<SandboxConfig>,
+
+    /// Shell hooks run before matching agents start, after they exit and
+    /// when they ask for a permission.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub agent_hooks: Vec<AgentHookConfig>,
+
+    /// Method names and limits of the reconnect intercepts, per agent.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub intercepts: Vec<InterceptConfig>,
+
+    /// Which idle agent to evict when the pool is full.
+    #[serde(default, skip_serializing_if = "EvictionConfig::is_default")]
+    pub eviction: EvictionConfig,
+
+    /// Liveness probes for idle pooled agents. Disabled when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheckConfig>,
+
+    /// How `bridge/endSession` stops an agent.
+    #[serde(default, skip_serializing_if = "EndSessionConfig::is_default")]
+    pub end_session: EndSessionConfig,
+
+    /// Rate shaping of streamed agent output to WebSocket clients.
+    #[serde(default, skip_serializing_if = "OutputShapingConfig::is_default")]
+    pub output_shaping: OutputShapingConfig,
+
+    /// Prompts sent to agents on a schedule.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedules: Vec<ScheduleConfig>,
+
+    /// Shed load when the bridge's memory use exceeds a limit. Disabled when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_watchdog: Option<MemoryWatchdogConfig>,
+
+    /// Tighter pool limits on a low battery. Disabled when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power: Option<PowerConfig>,
+
+    /// Alert on and tarpit addresses with repeated authentication failures.
+    /// Disabled when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_failures: Option<AuthFailureConfig>,
+
+    /// Refuse Cloudflare requests by visitor country. Disabled when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geo_filter: Option<GeoFilterConfig>,
+
+    /// Country and network of connecting addresses in logs and alerts, from
+    /// local MaxMind databases. Disabled when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geoip: Option<GeoIpConfig>,
+
+    /// Issue short-lived session tokens derived from the auth token.
+    /// Disabled when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_tokens: Option<SessionTokenConfig>,
+
+    /// Where clients may present their token: the query string, and a
+    /// `bridge/auth` first message on WebSocket connections.
+    #[serde(default, skip_serializing_if = "ClientAuthConfig::is_default")]
+    pub client_auth: ClientAuthConfig,
+
+    /// Replace every device token after days without a connection.
+    /// Disabled when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_lock: Option<AutoLockConfig>,
+
+    /// Daily per-device usage counters and soft budgets. Disabled when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<UsageConfig>,
+
+    /// Agent commands the bridge may run; any command when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_agents: Vec<AllowedAgentConfig>,
+
+    /// Extra device tokens with limited scopes, e.g. chat-only access for a
+    /// shared device. The auth token keeps every scope.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<DeviceConfig>,
+
+    /// Other people sharing this bridge, each in a namespace of their own.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<UserConfig>,
+
+    /// Project directories the app can switch the agent between.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub workspaces: Vec<WorkspaceConfig>,
+
+    /// Give each device's agent its own git worktree. Disabled when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worktrees: Option<WorktreeConfig>,
+
+    /// Ask on the bridge before releasing the auth token to a pairing device.
+    /// Disabled when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pairing_approval: Option<PairingApprovalConfig>,
+
+    /// Scheme for the app deep link shown with the pairing QR code.
+    #[serde(default, skip_serializing_if = "DeepLinkConfig::is_default")]
+    pub deep_link: DeepLinkConfig,
+
+    /// Put the pairing code in the URL path (`https://host:port/p/<code>`) for
+    /// a shorter QR code (default: false).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub short_pairing_url: bool,
+
+    /// Advertise pairing over Bluetooth LE. Disabled when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ble_pairing: Option<BlePairingConfig>,
+
+    /// Forwarding to a sleeping workstation for `bridge wake-relay`. Only read
+    /// by that command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wake_relay: Option<WakeRelayConfig>,
+
+    /// Outbound proxy; the proxy environment variables apply when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyConfig>,
+
+    /// Compare the system clock with an HTTP server's at startup.
+    #[serde(default, skip_serializing_if = "ClockCheckConfig::is_default")]
+    pub clock_check: ClockCheckConfig,
+
+    /// Check GitHub for a newer release at startup and log it (default: false).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub check_for_updates: bool,
+
+    /// Serve the web admin UI at `/admin/` (default: false). The `/admin/...`
+    /// JSON endpoints are available either way.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub admin_ui: bool,
+
+    /// Stop the bridge with an error when an agent process can't be spawned,
+    /// instead of failing that connection and trying again on the next one
+    /// (default: false; always on in headless mode, see [`crate::container`]).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub exit_on_spawn_failure: bool,
+
+    /// Set by `bridge start --insecure-dev` (see [`crate::insecure_dev`]):
+    /// serve without TLS and without auth. Never read from or written to
+    /// `common.toml`.
+    #[serde(skip)]
+    pub insecure_dev: bool,
+
+    /// `BRIDGE_*` environment variables applied on load (see
+    /// [`crate::env_overrides`]); left out when saving.
+    #[serde(skip)]
+    pub env_overrides: Vec<crate::env_overrides::Override>,
 }
 
 fn keep_alive_default() -> bool { true }
+fn is_zero(n: &usize) -> bool { *n == 0 }
 fn log_level_default() -> String { "WARN".to_string() }
 
 /// Configuration for a single transport.
@@ -132,6 +2126,20 @@ pub struct TransportConfig {
     /// Enable TLS on this transport (default: true for local).
     pub tls: Option<bool>,
 
+    // ---- User-provided certificate (local transport) ----
+    /// PEM certificate (chain) to serve instead of the generated self-signed cert.
+    pub cert_file: Option<PathBuf>,
+    /// PEM private key matching `cert_file`.
+    pub key_file: Option<PathBuf>,
+    /// PKCS#12 bundle with certificate chain and key (alternative to `cert_file`/`key_file`).
+    pub pkcs12_file: Option<PathBuf>,
+    /// Password protecting `pkcs12_file` (empty when unset).
+    pub pkcs12_password: Option<String>,
+
+    /// Serve the tailnet-issued certificate from `tailscale cert` (tailscale-ip transport,
+    /// default: true). Clients then validate via the public CA chain instead of a pinned fingerprint.
+    pub tailscale_cert: Option<bool>,
+
     // ---- Cloudflare Zero Trust fields (transport name: "cloudflare") ----
     pub hostname: Option<String>,
     pub tunnel_id: Option<String>,
@@ -141,6 +2149,43 @@ pub struct TransportConfig {
     pub client_secret: Option<String>,
     pub domain: Option<String>,
     pub subdomain: Option<String>,
+    /// Cloudflare API token, kept so the running bridge can rotate the
+    /// service token without asking again.
+    pub api_token: Option<String>,
+    /// Unix timestamp (seconds) when the service token was issued.
+    pub service_token_issued_at: Option<i64>,
+}
+
+impl TransportConfig {
+    /// Service token lifetime: 1 year in seconds
+    const SERVICE_TOKEN_LIFETIME_SECS: i64 = 365 * 24 * 3600;
+    /// Rotate when fewer than 30 days remain
+    const SERVICE_TOKEN_ROTATE_THRESHOLD_SECS: i64 = 30 * 24 * 3600;
+
+    /// Returns true if the Cloudflare service token is expired or will
+    /// expire within 30 days.
+    pub fn service_token_needs_rotation(&self) -> bool {
+        let has_token = self.client_id.as_deref().is_some_and(|id| !id.is_empty());
+        let issued_at = match self.service_token_issued_at {
+            Some(ts) => ts,
+            // No timestamp recorded → assume old/unknown, rotate to be safe
+            None => return has_token,
+        };
+        let age = unix_now() - issued_at;
+        age >= Self::SERVICE_TOKEN_LIFETIME_SECS - Self::SERVICE_TOKEN_ROTATE_THRESHOLD_SECS
+    }
+
+    /// Record now as the service token issuance time.
+    pub fn stamp_service_token_issued(&mut self) {
+        self.service_token_issued_at = Some(unix_now());
+    }
+}
+
+fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
 }
 
 impl Default for CommonConfig {
@@ -148,16 +2193,61 @@ impl Default for CommonConfig {
         // No transports pre-enabled: the setup wizard will ask the user to
         // choose one on first run (or any time no transport is configured).
         Self {
+            version: crate::config_migration::CURRENT_VERSION,
             agent_id: String::new(),
             auth_token: String::new(),
+            admin_token: String::new(),
             transports: HashMap::new(),
             slash_commands: Vec::new(),
             push_relay: None,
+            desktop_notifications: true,
             agent_command: None,
+            agent_version: None,
             bind_address: None,
             advertise_addr: None,
             keep_alive: true,
             log_level: "WARN".to_string(),
+            limits: LimitsConfig::default(),
+            tls_policy: TlsPolicyConfig::default(),
+            transcripts: None,
+            listeners: Vec::new(),
+            warm_agents: 0,
+            buffer: BufferConfig::default(),
+            pool_overrides: Vec::new(),
+            sandbox: Vec::new(),
+            agent_hooks: Vec::new(),
+            intercepts: Vec::new(),
+            eviction: EvictionConfig::default(),
+            health_check: None,
+            end_session: EndSessionConfig::default(),
+            output_shaping: OutputShapingConfig::default(),
+            schedules: Vec::new(),
+            memory_watchdog: None,
+            power: None,
+            auth_failures: None,
+            geo_filter: None,
+            geoip: None,
+            session_tokens: None,
+            client_auth: ClientAuthConfig::default(),
+            auto_lock: None,
+            usage: None,
+            allowed_agents: Vec::new(),
+            devices: Vec::new(),
+            users: Vec::new(),
+            workspaces: Vec::new(),
+            worktrees: None,
+            pairing_approval: None,
+            deep_link: DeepLinkConfig::default(),
+            short_pairing_url: false,
+            ble_pairing: None,
+            wake_relay: None,
+            proxy: None,
+            clock_check: ClockCheckConfig::default(),
+            check_for_updates: false,
+            admin_ui: false,
+            exit_on_spawn_failure: false,
+            insecure_dev: false,
+            env_overrides: Vec::new(),
         }
     }
 }
@@ -186,16 +2276,56 @@ impl CommonConfig {
         Self::load_from_dir(&Self::config_dir())
     }
 
-    /// Load from `common.toml` in a specific directory, or return defaults.
+    /// Load from `common.toml` in a specific directory, or return defaults,
+    /// with the `BRIDGE_*` environment variables applied over either.
+    /// A file in an older layout is upgraded and saved, after a backup of
+    /// the original (see [`crate::config_migration`]).
     pub fn load_from_dir(dir: &Path) -> Result<Self> {
+        Self::load_with_env(dir, std::env::vars())
+    }
+
+    /// [`Self::load_from_dir`] with `vars` as the environment.
+    pub fn load_with_env(dir: &Path, vars: impl IntoIterator<Item = (String, String)>) -> Result<Self> {
         let path = dir.join("common.toml");
-        if !path.exists() {
+        let text = if path.exists() {
+            Some(fs::read_to_string(&path).with_context(|| format!("Failed to read {:?}", path))?)
+        } else {
+            None
+        };
+        let (mut table, migrated) = match &text {
+            Some(text) => {
+                let mut table: toml::Table = toml::from_str(text)
+                    .with_context(|| format!("Failed to parse {:?}", path))?;
+                let migrated = crate::config_migration::migrate(&mut table, dir)?;
+                (table, migrated)
+            }
+            None => (toml::Table::try_from(Self::default()).context("Failed to serialize CommonConfig")?, None),
+        };
+        let overrides = crate::env_overrides::apply(&mut table, vars)?;
+        if text.is_none() && overrides.is_empty() {
             return Ok(Self::default());
         }
-        let text = fs::read_to_string(&path)
-            .with_context(|| format!("Failed to read {:?}", path))?;
-        let config: Self = toml::from_str(&text)
-            .with_context(|| format!("Failed to parse {:?}", path))?;
+        let mut config: Self = table.try_into().with_context(|| {
+            let vars: Vec<&str> = overrides.iter().map(|o| o.var.as_str()).collect();
+            match vars.is_empty() {
+                true => format!("Failed to parse {:?}", path),
+                false => format!("Failed to parse {:?} with {}", path, vars.join(", ")),
+            }
+        })?;
+        config.env_overrides = overrides;
+        if let (Some(from), Some(text)) = (migrated, &text) {
+            // The upgrade is only saved once the original is backed up; a
+            // directory that can't be written still loads, upgraded in memory.
+            let saved = crate::config_migration::backup(dir, from, text)
+                .and_then(|backup| config.save_to_dir(dir).map(|_| backup));
+            match saved {
+                Ok(backup) => tracing::info!(
+                    "Upgraded {:?} from version {} to {} (original kept as {:?})",
+                    path, from, config.version, backup
+                ),
+                Err(e) => tracing::warn!("Failed to save the upgraded {:?}: {:#}", path, e),
+            }
+        }
         Ok(config)
     }
 
@@ -208,16 +2338,15 @@ impl CommonConfig {
     pub fn save_to_dir(&self, dir: &Path) -> Result<()> {
         fs::create_dir_all(dir)?;
         let path = dir.join("common.toml");
-        let text = toml::to_string_pretty(self).context("Failed to serialize CommonConfig")?;
-        fs::write(&path, &text).with_context(|| format!("Failed to write {:?}", path))?;
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            let mut perms = fs::metadata(&path)?.permissions();
-            perms.set_mode(0o600);
-            fs::set_permissions(&path, perms)?;
+        let text = if self.env_overrides.is_empty() {
+            toml::to_string_pretty(self)
+        } else {
+            let mut table = toml::Table::try_from(self).context("Failed to serialize CommonConfig")?;
+            crate::env_overrides::revert(&mut table, &self.env_overrides);
+            toml::to_string_pretty(&table)
         }
-        Ok(())
+        .context("Failed to serialize CommonConfig")?;
+        crate::private_file::write(&path, &text).with_context(|| format!("Failed to write {:?}", path))
     }
 
     /// Generate a UUID v4 `agent_id` if one is not already set.
@@ -225,6 +2354,11 @@ impl CommonConfig {
         if self.agent_id.is_empty() {
             self.agent_id = uuid::Uuid::new_v4().to_string();
         }
+        for user in &mut self.users {
+            if user.agent_id.is_empty() {
+                user.agent_id = uuid::Uuid::new_v4().to_string();
+            }
+        }
     }
 
     /// Generate a random URL-safe authentication token (32 random bytes, base64).
@@ -241,6 +2375,67 @@ impl CommonConfig {
         }
     }
 
+    /// Generate an admin token if none is set.
+    pub fn ensure_admin_token(&mut self) {
+        if self.admin_token.is_empty() {
+            self.admin_token = Self::generate_auth_token();
+        }
+    }
+
+    /// Replace the auth token, every `[[devices]]` token and every
+    /// `[[users]]` token with new random ones, so all paired devices have to
+    /// pair again.
+    pub fn rotate_tokens(&mut self) {
+        self.auth_token = Self::generate_auth_token();
+        for device in &mut self.devices {
+            device.token = Self::generate_auth_token();
+        }
+        for user in &mut self.users {
+            user.auth_token = Self::generate_auth_token();
+            for device in &mut user.devices {
+                device.token = Self::generate_auth_token();
+            }
+        }
+    }
+
+    /// Reject a short admin token, or one that a client also holds.
+    pub fn validate_admin_token(&self) -> Result<()> {
+        if self.admin_token.is_empty() {
+            return Ok(());
+        }
+        if self.admin_token.len() < 16 {
+            anyhow::bail!("admin_token must be at least 16 characters");
+        }
+        if self.admin_token == self.auth_token || self.devices.iter().any(|d| d.token == self.admin_token) {
+            anyhow::bail!("admin_token must differ from the auth token and every device token");
+        }
+        Ok(())
+    }
+
+    /// Reject invalid or same-named `[[users]]` and any token used twice
+    /// across the auth and admin tokens, `[[devices]]` and `[[users]]`, since
+    /// a token identifies its namespace.
+    pub fn validate_users(&self) -> Result<()> {
+        for (i, user) in self.users.iter().enumerate() {
+            user.validate()?;
+            if self.users[..i].iter().any(|u| u.name == user.name) {
+                anyhow::bail!("[[users]] {:?}: names must be unique", user.name);
+            }
+        }
+        let mut seen = std::collections::HashSet::new();
+        seen.insert(self.auth_token.as_str());
+        seen.insert(self.admin_token.as_str());
+        seen.extend(self.devices.iter().map(|d| d.token.as_str()));
+        for user in &self.users {
+            for token in std::iter::once(&user.auth_token).chain(user.devices.iter().map(|d| &d.token)) {
+                if !seen.insert(token) {
+                    anyhow::bail!("[[users]] {:?}: every token must differ from the auth and admin tokens, other devices and other users", user.name);
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Returns all enabled transports, sorted by name for deterministic ordering.
     pub fn enabled_transports(&self) -> Vec<(&str, &TransportConfig)> {
         let mut result: Vec<_> = self
@@ -255,9 +2450,16 @@ impl CommonConfig {
 
     /// Build a static connection JSON payload for a QR code.
     ///
-    /// Includes `agentId`, `url`, `protocol`, `version`, `authToken`, and
-    /// Cloudflare credentials if present in the transport config.
-    pub fn to_connection_json(&self, hostname: &str, transport_name: &str, cwd: &str) -> Result<String> {
+    /// Includes `agentId`, `url`, `protocol`, `version`, `authToken`, the
+    /// TLS fingerprint to pin (if any), and Cloudflare credentials if present
+    /// in the transport config.
+    pub fn to_connection_json(
+        &self,
+        hostname: &str,
+        transport_name: &str,
+        cwd: &str,
+        cert_fingerprint: Option<&str>,
+    ) -> Result<String> {
         use serde_json::{Map, Value};
         let transport = self.transports.get(transport_name);
         let mut map = Map::new();
@@ -274,6 +2476,9 @@ impl CommonConfig {
                 Value::String(self.auth_token.clone()),
             );
         }
+        if let Some(fp) = cert_fingerprint {
+            map.insert("certFingerprint".to_string(), Value::String(fp.to_string()));
+        }
         if let Some(t) = transport {
             if let Some(ref id) = t.client_id {
                 if !id.is_empty() {
@@ -288,4 +2493,116 @@ impl CommonConfig {
         }
         serde_json::to_string(&Value::Object(map)).context("Failed to serialize connection info")
     }
+
+    /// Connection JSON for several transports at once, as
+    /// `(transport, url, cert_fingerprint)` in order of preference.
+    ///
+    /// The first endpoint's fields stay at the top level, so an app that reads
+    /// only one connection uses it. `endpoints` lists every endpoint with its
+    /// `transport`, `url`, `certFingerprint`, `clientId` and `clientSecret`;
+    /// the app tries them and keeps the first it reaches.
+    pub fn to_multi_connection_json(
+        &self,
+        endpoints: &[(String, String, Option<String>)],
+        cwd: &str,
+    ) -> Result<String> {
+        use serde_json::{Map, Value};
+        let Some((first_name, first_url, first_fp)) = endpoints.first() else {
+            anyhow::bail!("No endpoint to pair over");
+        };
+        let mut map: Map<String, Value> =
+            serde_json::from_str(&self.to_connection_json(first_url, first_name, cwd, first_fp.as_deref())?)?;
+        let mut list = Vec::new();
+        for (name, url, fp) in endpoints {
+            let full: Map<String, Value> = serde_json::from_str(&self.to_connection_json(url, name, cwd, fp.as_deref())?)?;
+            let mut endpoint = Map::new();
+            endpoint.insert("transport".to_string(), Value::String(name.clone()));
+            for key in ["url", "certFingerprint", "clientId", "clientSecret"] {
+                if let Some(value) = full.get(key) {
+                    endpoint.insert(key.to_string(), value.clone());
+                }
+            }
+            list.push(Value::Object(endpoint));
+        }
+        map.insert("endpoints".to_string(), Value::Array(list));
+        serde_json::to_string(&Value::Object(map)).context("Failed to serialize connection info")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection(json: &str) -> serde_json::Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn connection_json_pins_the_certificate_when_there_is_one() {
+        let mut config = CommonConfig { agent_id: "agent-1".into(), auth_token: "token-1".into(), ..CommonConfig::default() };
+        let cloudflare = TransportConfig {
+            enabled: true,
+            client_id: Some("id.access".into()),
+            client_secret: Some("secret".into()),
+            ..TransportConfig::default()
+        };
+        config.transports.insert("cloudflare".into(), cloudflare);
+
+        let local = connection(&config.to_connection_json("wss://10.0.0.5:8765", "local", "/work", Some("AB:CD")).unwrap());
+        assert_eq!(local["url"], "wss://10.0.0.5:8765");
+        assert_eq!(local["authToken"], "token-1");
+        assert_eq!(local["certFingerprint"], "AB:CD");
+        assert!(local.get("clientId").is_none());
+
+        let tunnel = connection(&config.to_connection_json("wss://bridge.example.com", "cloudflare", "/work", None).unwrap());
+        assert!(tunnel.get("certFingerprint").is_none());
+        assert_eq!(tunnel["clientId"], "id.access");
+        assert_eq!(tunnel["clientSecret"], "secret");
+    }
+
+    #[test]
+    fn multi_transport_json_keeps_the_first_endpoint_on_top() {
+        let mut config = CommonConfig { auth_token: "token-1".into(), ..CommonConfig::default() };
+        let cloudflare = TransportConfig { enabled: true, client_id: Some("id.access".into()), ..TransportConfig::default() };
+        config.transports.insert("cloudflare".into(), cloudflare);
+        let endpoints = [
+            ("local".to_string(), "wss://10.0.0.5:8765".to_string(), Some("AB:CD".to_string())),
+            ("cloudflare".to_string(), "wss://bridge.example.com".to_string(), None),
+        ];
+
+        let json = connection(&config.to_multi_connection_json(&endpoints, "/work").unwrap());
+        assert_eq!(json["url"], "wss://10.0.0.5:8765");
+        assert_eq!(json["certFingerprint"], "AB:CD");
+        assert!(json.get("clientId").is_none());
+        assert_eq!(
+            json["endpoints"],
+            serde_json::json!([
+                { "transport": "local", "url": "wss://10.0.0.5:8765", "certFingerprint": "AB:CD" },
+                { "transport": "cloudflare", "url": "wss://bridge.example.com", "clientId": "id.access" },
+            ])
+        );
+        assert!(config.to_multi_connection_json(&[], "/work").is_err());
+    }
+
+    #[test]
+    fn admin_token_is_long_and_held_by_no_client() {
+        let mut config = CommonConfig { auth_token: "client-token-0123456789".into(), ..CommonConfig::default() };
+        config.validate_admin_token().unwrap();
+        config.ensure_admin_token();
+        let generated = config.admin_token.clone();
+        assert!(generated.len() >= 16 && generated != config.auth_token);
+        config.validate_admin_token().unwrap();
+
+        // Rotating the client tokens leaves the admin token alone.
+        config.rotate_tokens();
+        assert_eq!(config.admin_token, generated);
+
+        config.admin_token = "too-short".into();
+        assert!(config.validate_admin_token().unwrap_err().to_string().contains("16 characters"));
+        config.admin_token = config.auth_token.clone();
+        assert!(config.validate_admin_token().is_err());
+        config.devices = vec![DeviceConfig { name: "tablet".into(), token: "tablet-token-0123456789".into(), scopes: vec![] }];
+        config.admin_token = "tablet-token-0123456789".into();
+        assert!(config.validate_admin_token().is_err());
+    }
 }
@@ -69,18 +69,10 @@ impl BridgeConfig {
         let json = serde_json::to_string_pretty(self)
             .context("Failed to serialize configuration")?;
         
-        fs::write(&config_path, &json)
+        // Readable by the owner only (Unix)
+        crate::private_file::write(&config_path, &json)
             .context(format!("Failed to write configuration to {:?}", config_path))?;
-        
-        // Set restrictive file permissions (Unix only)
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            let mut perms = fs::metadata(&config_path)?.permissions();
-            perms.set_mode(0o600); // rw-------
-            fs::set_permissions(&config_path, perms)?;
-        }
-        
+
         Ok(())
     }
 
@@ -98,35 +90,6 @@ impl BridgeConfig {
         }
     }
 
-    /// Service token lifetime: 1 year in seconds
-    const SERVICE_TOKEN_LIFETIME_SECS: i64 = 365 * 24 * 3600;
-    /// Rotate when fewer than 30 days remain
-    const SERVICE_TOKEN_ROTATE_THRESHOLD_SECS: i64 = 30 * 24 * 3600;
-
-    /// Returns true if the service token is expired or will expire within 30 days.
-    pub fn service_token_needs_rotation(&self) -> bool {
-        let issued_at = match self.service_token_issued_at {
-            Some(ts) => ts,
-            // No timestamp recorded → assume old/unknown, rotate to be safe
-            None => return !self.client_id.is_empty(),
-        };
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_secs() as i64)
-            .unwrap_or(0);
-        let age = now - issued_at;
-        age >= Self::SERVICE_TOKEN_LIFETIME_SECS - Self::SERVICE_TOKEN_ROTATE_THRESHOLD_SECS
-    }
-
-    /// Record now as the service token issuance time.
-    pub fn stamp_service_token_issued(&mut self) {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_secs() as i64)
-            .unwrap_or(0);
-        self.service_token_issued_at = Some(now);
-    }
-
     /// Load configuration from disk
     pub fn load() -> Result<Self> {
         let config_path = Self::config_path();
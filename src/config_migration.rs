@@ -0,0 +1,254 @@
+//! Upgrades of `common.toml` from older layouts.
+//!
+//! `common.toml` carries a `version`. [`CommonConfig::load_from_dir`] hands a
+//! file older than [`CURRENT_VERSION`] to [`migrate`], which runs every
+//! registered [`Migration`] past the file's version on the raw TOML, in
+//! order. The original is then copied to `common.toml.v<old>-<time>.bak` and
+//! the upgraded config saved. A file newer than this bridge understands is
+//! refused rather than loaded with its unknown settings dropped.
+//!
+//! A change to the layout adds a migration to [`MIGRATIONS`] and bumps
+//! [`CURRENT_VERSION`] to its `to`.
+//!
+//! [`CommonConfig::load_from_dir`]: crate::common_config::CommonConfig::load_from_dir
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+use tracing::info;
+
+/// The layout this bridge writes.
+pub const CURRENT_VERSION: u32 = 1;
+
+/// One step from `to - 1` to `to`, applied to the raw TOML of `common.toml`
+/// in the given config directory.
+pub struct Migration {
+    pub to: u32,
+    pub summary: &'static str,
+    pub apply: fn(&mut Table, &Path) -> Result<()>,
+}
+
+/// Every upgrade, oldest first.
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    to: 1,
+    summary: "move the legacy BridgeConfig settings into [transports.cloudflare]",
+    apply: legacy_bridge_config,
+}];
+
+/// The settings of the legacy `BridgeConfig` that now live in the
+/// cloudflare transport.
+const LEGACY_CLOUDFLARE_FIELDS: &[&str] = &[
+    "hostname",
+    "tunnel_id",
+    "tunnel_secret",
+    "account_id",
+    "client_id",
+    "client_secret",
+    "domain",
+    "subdomain",
+    "api_token",
+    "service_token_issued_at",
+];
+
+/// The legacy `BridgeConfig` file, read once by the first migration.
+const LEGACY_CONFIG_FILENAME: &str = "config.json";
+
+/// Upgrade `table`, read from `common.toml` in `dir`, to [`CURRENT_VERSION`].
+/// Returns the version it had when it was upgraded, `None` when it was
+/// already current.
+pub fn migrate(table: &mut Table, dir: &Path) -> Result<Option<u32>> {
+    let version = match table.get("version") {
+        None => 0,
+        Some(v) => v
+            .as_integer()
+            .and_then(|v| u32::try_from(v).ok())
+            .context("`version` in common.toml must be a non-negative integer")?,
+    };
+    if version > CURRENT_VERSION {
+        anyhow::bail!(
+            "common.toml is version {}, but this bridge only understands up to version {} — update the bridge",
+            version,
+            CURRENT_VERSION
+        );
+    }
+    if version == CURRENT_VERSION {
+        return Ok(None);
+    }
+    for migration in MIGRATIONS.iter().filter(|m| m.to > version) {
+        (migration.apply)(table, dir)
+            .with_context(|| format!("Failed to upgrade common.toml to version {}", migration.to))?;
+        info!("common.toml version {}: {}", migration.to, migration.summary);
+    }
+    table.insert("version".into(), Value::Integer(CURRENT_VERSION.into()));
+    Ok(Some(version))
+}
+
+/// Keep `text`, the `common.toml` of version `from`, next to it in `dir`.
+pub fn backup(dir: &Path, from: u32, text: &str) -> Result<PathBuf> {
+    let stamp = chrono::Utc::now().format("%Y%m%dT%H%M%S");
+    let path = dir.join(format!("common.toml.v{}-{}.bak", from, stamp));
+    crate::private_file::write(&path, text).with_context(|| format!("Failed to write {:?}", path))?;
+    Ok(path)
+}
+
+/// Version 1: the settings `BridgeConfig` kept at the top level, or in
+/// `config.json`, move into `[transports.cloudflare]`. Settings already in
+/// the transport win. `cert_fingerprint` is dropped; the fingerprint is read
+/// from the certificate.
+fn legacy_bridge_config(table: &mut Table, dir: &Path) -> Result<()> {
+    let mut legacy = Table::new();
+    for field in LEGACY_CLOUDFLARE_FIELDS {
+        if let Some(value) = table.remove(*field) {
+            legacy.insert(field.to_string(), value);
+        }
+    }
+    table.remove("cert_fingerprint");
+
+    let has_cloudflare = table
+        .get("transports")
+        .and_then(Value::as_table)
+        .is_some_and(|t| t.contains_key("cloudflare"));
+    let json_path = dir.join(LEGACY_CONFIG_FILENAME);
+    if legacy.is_empty() && !has_cloudflare && json_path.exists() {
+        let json = fs::read_to_string(&json_path).with_context(|| format!("Failed to read {:?}", json_path))?;
+        let old: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(&json).with_context(|| format!("Failed to parse {:?}", json_path))?;
+        for field in LEGACY_CLOUDFLARE_FIELDS {
+            let value = match old.get(*field) {
+                Some(serde_json::Value::String(s)) => Value::String(s.clone()),
+                Some(serde_json::Value::Number(n)) => match n.as_i64() {
+                    Some(n) => Value::Integer(n),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            legacy.insert(field.to_string(), value);
+        }
+        let auth_token = old.get("auth_token").and_then(|v| v.as_str()).filter(|t| !t.is_empty());
+        let has_auth_token = table.get("auth_token").and_then(Value::as_str).is_some_and(|t| !t.is_empty());
+        if let (Some(token), false) = (auth_token, has_auth_token) {
+            table.insert("auth_token".into(), Value::String(token.to_string()));
+        }
+    }
+    // The legacy config wrote empty strings for settings it didn't have.
+    legacy.retain(|_, v| v.as_str() != Some(""));
+    if legacy.is_empty() {
+        return Ok(());
+    }
+
+    let transports = table
+        .entry("transports")
+        .or_insert_with(|| Value::Table(Table::new()))
+        .as_table_mut()
+        .context("`transports` in common.toml must be a table")?;
+    let cloudflare = transports
+        .entry("cloudflare")
+        .or_insert_with(|| Value::Table(Table::from_iter([("enabled".to_string(), Value::Boolean(true))])))
+        .as_table_mut()
+        .context("`transports.cloudflare` in common.toml must be a table")?;
+    for (field, value) in legacy {
+        cloudflare.entry(field).or_insert(value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common_config::CommonConfig;
+
+    fn backups(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|n| n.ends_with(".bak"))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn the_registry_ends_at_the_current_version() {
+        let versions: Vec<u32> = MIGRATIONS.iter().map(|m| m.to).collect();
+        assert_eq!(versions, (1..=CURRENT_VERSION).collect::<Vec<_>>());
+        assert_eq!(CommonConfig::default().version, CURRENT_VERSION);
+    }
+
+    #[test]
+    fn flat_legacy_settings_move_into_the_cloudflare_transport() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = r#"
+auth_token = "token"
+hostname = "https://agent.example.com"
+tunnel_id = "tunnel"
+client_id = "id"
+api_token = ""
+cert_fingerprint = "AB:CD"
+
+[transports.local]
+enabled = true
+port = 8765
+"#;
+        fs::write(dir.path().join("common.toml"), original).unwrap();
+
+        let config = CommonConfig::load_from_dir(dir.path()).unwrap();
+        assert_eq!(config.version, CURRENT_VERSION);
+        assert_eq!(config.auth_token, "token");
+        let cloudflare = &config.transports["cloudflare"];
+        assert!(cloudflare.enabled);
+        assert_eq!(cloudflare.hostname.as_deref(), Some("https://agent.example.com"));
+        assert_eq!(cloudflare.tunnel_id.as_deref(), Some("tunnel"));
+        assert_eq!(cloudflare.client_id.as_deref(), Some("id"));
+        assert_eq!(cloudflare.api_token, None, "empty legacy values are dropped");
+        assert_eq!(config.transports["local"].port, Some(8765));
+
+        let saved = fs::read_to_string(dir.path().join("common.toml")).unwrap();
+        assert!(saved.contains("version = 1"), "{saved}");
+        assert!(!saved.contains("cert_fingerprint"));
+        let backups = backups(dir.path());
+        assert_eq!(backups.len(), 1);
+        assert!(backups[0].starts_with("common.toml.v0-"));
+        assert_eq!(fs::read_to_string(dir.path().join(&backups[0])).unwrap(), original);
+
+        CommonConfig::load_from_dir(dir.path()).unwrap();
+        assert_eq!(self::backups(dir.path()).len(), 1, "a current file is left alone");
+    }
+
+    #[test]
+    fn legacy_config_json_is_imported_once() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join(LEGACY_CONFIG_FILENAME),
+            r#"{"hostname":"https://agent.example.com","tunnel_id":"tunnel","tunnel_secret":"secret",
+                "account_id":"account","client_id":"id","client_secret":"cs","domain":"example.com",
+                "subdomain":"agent","auth_token":"legacy-token","service_token_issued_at":1700000000}"#,
+        )
+        .unwrap();
+        fs::write(dir.path().join("common.toml"), "agent_id = \"agent\"\n").unwrap();
+
+        let config = CommonConfig::load_from_dir(dir.path()).unwrap();
+        assert_eq!(config.auth_token, "legacy-token");
+        let cloudflare = &config.transports["cloudflare"];
+        assert_eq!(cloudflare.tunnel_secret.as_deref(), Some("secret"));
+        assert_eq!(cloudflare.service_token_issued_at, Some(1_700_000_000));
+
+        // An existing transport is kept as it is.
+        let mut table: Table = toml::from_str("[transports.cloudflare]\nenabled = false\ntunnel_id = \"new\"\n").unwrap();
+        assert_eq!(migrate(&mut table, dir.path()).unwrap(), Some(0));
+        let cloudflare = table["transports"]["cloudflare"].as_table().unwrap();
+        assert_eq!(cloudflare.get("tunnel_secret"), None);
+        assert_eq!(cloudflare["enabled"].as_bool(), Some(false));
+    }
+
+    #[test]
+    fn newer_files_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let newer = format!("version = {}\nagent_id = \"agent\"\n", CURRENT_VERSION + 1);
+        fs::write(dir.path().join("common.toml"), &newer).unwrap();
+        let error = CommonConfig::load_from_dir(dir.path()).unwrap_err();
+        assert!(format!("{error:#}").contains("update the bridge"), "{error:#}");
+        assert_eq!(fs::read_to_string(dir.path().join("common.toml")).unwrap(), newer);
+        assert!(backups(dir.path()).is_empty());
+    }
+}
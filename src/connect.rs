@@ -0,0 +1,245 @@
+//! `bridge connect`: a stdio ACP agent that is really a remote bridge.
+//!
+//! Desktop ACP clients launch agents as subprocesses and speak JSON-RPC over
+//! stdio. Pointing such a client at `bridge connect --url wss://…` lets it use
+//! the remote bridge's pooled agent instead: every stdin line is sent as a
+//! WebSocket text message and every message from the bridge is written to
+//! stdout as one line. Status goes to stderr, which ACP clients leave alone.
+
+use anyhow::{Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::client::Request;
+use tokio_tungstenite::tungstenite::http::{HeaderValue, Uri};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tracing::info;
+
+use crate::common_config::CommonConfig;
+use crate::tls;
+
+/// Where `bridge connect` connects to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectTarget {
+    /// `ws://` or `wss://` URL of the bridge.
+    pub url: String,
+    /// Auth token sent as `X-Bridge-Token`.
+    pub token: String,
+    /// Pinned SHA256 certificate fingerprint; `None` uses the OS trust store.
+    pub fingerprint: Option<String>,
+}
+
+impl ConnectTarget {
+    /// The bridge configured in this config directory, over loopback on its
+    /// `local` transport. Its certificate is pinned like a paired device would.
+    pub fn local(config: &CommonConfig) -> Result<Self> {
+        let transport = config
+            .transports
+            .get("local")
+            .filter(|t| t.enabled)
+            .context("No enabled `local` transport in common.toml")?;
+        if config.auth_token.is_empty() {
+            anyhow::bail!("No auth_token in common.toml — start the bridge once to generate it");
+        }
+        let port = transport.port.unwrap_or(8765);
+        let (url, fingerprint) = if transport.tls.unwrap_or(true) {
+            let cert = match (&transport.cert_file, &transport.pkcs12_file) {
+                (Some(cert), _) => cert.clone(),
+                (None, Some(_)) => anyhow::bail!("--local cannot pin a PKCS#12 certificate; use --url with --fingerprint"),
+                (None, None) => tls::pinned_cert_path(&CommonConfig::config_dir(), &config.tls_policy),
+            };
+            (format!("wss://127.0.0.1:{}", port), Some(tls::pem_file_fingerprint(&cert)?))
+        } else {
+            (format!("ws://127.0.0.1:{}", port), None)
+        };
+        Ok(Self { url, token: config.auth_token.clone(), fingerprint })
+    }
+}
+
+/// Byte stream under the WebSocket: plain TCP or TLS.
+pub(crate) trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+/// Connect to `target` and pipe this process's stdin/stdout through it until
+/// either side closes.
+pub async fn run(target: &ConnectTarget) -> Result<()> {
+    let ws = open(target).await?;
+    info!("🔗 Connected to {}", target.url);
+    pipe(ws, tokio::io::stdin(), tokio::io::stdout()).await
+}
+
+pub(crate) async fn open(target: &ConnectTarget) -> Result<WebSocketStream<Box<dyn Io>>> {
+    Ok(open_with_protocols(target, &[]).await?.0)
+}
+
+/// Like [`open`], offering `protocols` as `Sec-WebSocket-Protocol`; also
+/// returns the one the bridge selected.
+pub(crate) async fn open_with_protocols(
+    target: &ConnectTarget,
+    protocols: &[&str],
+) -> Result<(WebSocketStream<Box<dyn Io>>, Option<String>)> {
+    let request = client_request(target, protocols)?;
+    let (host, port, secure) = endpoint(request.uri())?;
+    let stream = connect_stream(&host, port, secure, target.fingerprint.as_deref()).await?;
+
+    let (ws, response) = tokio_tungstenite::client_async(request, stream)
+        .await
+        .context("WebSocket handshake failed")?;
+    let protocol = response
+        .headers()
+        .get("Sec-WebSocket-Protocol")
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    Ok((ws, protocol))
+}
+
+/// The upgrade request for `target`, with its token and `protocols`.
+pub(crate) fn client_request(target: &ConnectTarget, protocols: &[&str]) -> Result<Request> {
+    let mut request = target.url.as_str().into_client_request().context("Invalid bridge URL")?;
+    // An empty token sends none, for bridges that take `bridge/auth` first
+    if !target.token.is_empty() {
+        request
+            .headers_mut()
+            .insert("X-Bridge-Token", HeaderValue::from_str(&target.token).context("Invalid auth token")?);
+    }
+    if !protocols.is_empty() {
+        request
+            .headers_mut()
+            .insert("Sec-WebSocket-Protocol", HeaderValue::from_str(&protocols.join(", ")).context("Invalid protocol list")?);
+    }
+    Ok(request)
+}
+
+/// Host, port and whether TLS is used, of a `ws://`/`wss://` (or
+/// `http://`/`https://`) URL.
+pub(crate) fn endpoint(uri: &Uri) -> Result<(String, u16, bool)> {
+    let secure = match uri.scheme_str() {
+        Some("wss" | "https") => true,
+        Some("ws" | "http") => false,
+        _ => anyhow::bail!("Bridge URL must start with ws:// or wss://"),
+    };
+    let host = uri.host().context("Bridge URL has no host")?.trim_matches(['[', ']']).to_string();
+    let port = uri.port_u16().unwrap_or(if secure { 443 } else { 80 });
+    Ok((host, port, secure))
+}
+
+/// TCP connection to `host:port`, inside TLS when `secure`: pinned to
+/// `fingerprint`, or verified against the OS trust store when `None`.
+pub(crate) async fn connect_stream(host: &str, port: u16, secure: bool, fingerprint: Option<&str>) -> Result<Box<dyn Io>> {
+    let tcp = tcp_connect(host, port).await?;
+    if !secure {
+        return Ok(Box::new(tcp));
+    }
+    tls_connect(host, tcp, fingerprint).await
+}
+
+pub(crate) async fn tcp_connect(host: &str, port: u16) -> Result<TcpStream> {
+    let tcp = TcpStream::connect((host, port))
+        .await
+        .with_context(|| format!("Failed to connect to {}:{}", host, port))?;
+    tcp.set_nodelay(true)?;
+    Ok(tcp)
+}
+
+/// TLS over `tcp`, pinned to `fingerprint` or verified against the OS trust store.
+pub(crate) async fn tls_connect(host: &str, tcp: TcpStream, fingerprint: Option<&str>) -> Result<Box<dyn Io>> {
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(tls::client_config(fingerprint)?));
+    let server_name = ServerName::try_from(host.to_string()).context("Invalid TLS server name")?;
+    Ok(Box::new(connector.connect(server_name, tcp).await.context("TLS handshake failed")?))
+}
+
+/// Notifications the bridge sends on its own (e.g. `bridge/capabilities`).
+/// A stdio ACP client doesn't know them, so they are not passed on.
+fn is_bridge_notification(text: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(text).is_ok_and(|v| {
+        v.get("id").is_none() && v.get("method").and_then(|m| m.as_str()).is_some_and(|m| m.starts_with("bridge/"))
+    })
+}
+
+/// Send each line of `input` as a text message and write each text message
+/// received to `output` as one line.
+pub async fn pipe<S, R, W>(ws: WebSocketStream<S>, input: R, mut output: W) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let (mut sink, mut source) = ws.split();
+    let mut lines = BufReader::new(input).lines();
+    loop {
+        tokio::select! {
+            line = lines.next_line() => match line.context("Failed to read stdin")? {
+                Some(line) if line.trim().is_empty() => {}
+                Some(line) => sink.send(Message::Text(line.into())).await.context("Failed to send to bridge")?,
+                None => {
+                    let _ = sink.send(Message::Close(None)).await;
+                    break;
+                }
+            },
+            message = source.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    if is_bridge_notification(&text) {
+                        continue;
+                    }
+                    output.write_all(text.as_bytes()).await?;
+                    output.write_all(b"\n").await?;
+                    output.flush().await?;
+                }
+                Some(Ok(Message::Close(_))) | None => {
+                    info!("🔌 Bridge closed the connection");
+                    break;
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e).context("WebSocket error"),
+            },
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bridge_notifications_are_filtered() {
+        assert!(is_bridge_notification(r#"{"jsonrpc":"2.0","method":"bridge/capabilities","params":{}}"#));
+        assert!(!is_bridge_notification(r#"{"jsonrpc":"2.0","method":"session/update","params":{}}"#));
+        assert!(!is_bridge_notification(r#"{"jsonrpc":"2.0","id":1,"method":"bridge/resume"}"#));
+        assert!(!is_bridge_notification("not json"));
+    }
+
+    #[tokio::test]
+    async fn pipes_lines_both_ways() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(tcp).await.unwrap();
+            ws.send(Message::Text(r#"{"jsonrpc":"2.0","method":"bridge/capabilities","params":{}}"#.into())).await.unwrap();
+            // Echo until the client closes.
+            while let Some(Ok(Message::Text(text))) = ws.next().await {
+                ws.send(Message::Text(text)).await.unwrap();
+            }
+        });
+
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let (ws, _) = tokio_tungstenite::client_async(format!("ws://{}", addr), tcp).await.unwrap();
+        let (mut stdin_writer, stdin) = tokio::io::duplex(1024);
+        let (stdout, stdout_reader) = tokio::io::duplex(1024);
+        let client = tokio::spawn(pipe(ws, stdin, stdout));
+
+        stdin_writer.write_all(b"{\"id\":1}\n\n{\"id\":2}\n").await.unwrap();
+        let mut out = BufReader::new(stdout_reader).lines();
+        assert_eq!(out.next_line().await.unwrap().as_deref(), Some(r#"{"id":1}"#));
+        assert_eq!(out.next_line().await.unwrap().as_deref(), Some(r#"{"id":2}"#));
+
+        drop(stdin_writer);
+        client.await.unwrap().unwrap();
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,328 @@
+//! Running the bridge as a container's entrypoint.
+//!
+//! - **Headless start**: with `BRIDGE_TRANSPORT=<name>` (or
+//!   `--non-interactive`), `bridge` and `bridge start` serve that transport
+//!   without the TUI or its transport picker. A missing `local` or `tailscale-serve` section is created with
+//!   the TUI's defaults; `cloudflare` has to be set up beforehand. The
+//!   pairing URL is logged, and SIGTERM stops the bridge like Ctrl+C.
+//! - **Spawn failures**: in headless mode the bridge exits non-zero when the
+//!   agent program can't be found at startup or an agent can't be spawned
+//!   later, instead of retrying, so the orchestrator sees the failure
+//!   (`exit_on_spawn_failure` in `common.toml` does the same elsewhere).
+//! - **PID 1**: on Linux, a bridge started as PID 1 runs itself as a child
+//!   and stays as a minimal init: it forwards SIGTERM, SIGINT, SIGHUP and
+//!   SIGQUIT to the child, reaps every process that exits (orphaned agent
+//!   subprocesses included), and exits with the child's status.
+//! - **JSON logs**: `BRIDGE_LOG_FORMAT=json` writes headless logs to stdout
+//!   as one JSON object per line; `text` writes them to stderr. The default
+//!   is `json` under the PID 1 init and `text` otherwise.
+//! - **Secrets**: `BRIDGE_<KEY>_FILE` reads a key's value from a file, see
+//!   [`crate::env_overrides`].
+
+use std::io::Write;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use tokio::sync::mpsc;
+use tracing::field::{Field, Visit};
+use tracing::{error, info, Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+use crate::common_config::{CommonConfig, TransportConfig};
+use crate::error::BridgeError;
+use crate::tui::events::{AppEvent, BridgeEvent};
+
+/// Transport to serve headless, skipping the TUI.
+pub const TRANSPORT_VAR: &str = "BRIDGE_TRANSPORT";
+
+/// `json` or `text`.
+pub const LOG_FORMAT_VAR: &str = "BRIDGE_LOG_FORMAT";
+
+/// Set on the bridge the PID 1 init runs, so it doesn't become one too.
+pub const INIT_CHILD_VAR: &str = "BRIDGE_INIT_CHILD";
+
+/// The transport `BRIDGE_TRANSPORT` asks for, if any.
+pub fn headless_transport() -> Option<String> {
+    std::env::var(TRANSPORT_VAR).ok().filter(|t| !t.trim().is_empty()).map(|t| t.trim().to_string())
+}
+
+/// Whether this bridge was started by the PID 1 init.
+pub fn is_init_child() -> bool {
+    std::env::var_os(INIT_CHILD_VAR).is_some()
+}
+
+/// Whether headless logs are JSON, from `format` (`BRIDGE_LOG_FORMAT`).
+pub fn json_logs(format: Option<&str>) -> Result<bool> {
+    match format.map(str::to_lowercase).as_deref() {
+        None | Some("") => Ok(is_init_child()),
+        Some("json") => Ok(true),
+        Some("text") => Ok(false),
+        Some(other) => anyhow::bail!("{}={} is not supported — use json or text", LOG_FORMAT_VAR, other),
+    }
+}
+
+/// Serve `transport` until SIGTERM or Ctrl+C, without a terminal.
+pub async fn run_headless(mut config: CommonConfig, transport: &str) -> Result<()> {
+    config.ensure_agent_id();
+    config.ensure_auth_token();
+    config.ensure_admin_token();
+    config.save()?;
+    // Not saved: the environment picks the transport on every start.
+    select_transport(&mut config, transport)?;
+    config.exit_on_spawn_failure = true;
+
+    let command = config.agent_command.as_deref().ok_or_else(|| {
+        BridgeError::config("No agent_command in config — set it in common.toml or BRIDGE_AGENT_COMMAND")
+    })?;
+    let program = command.split_whitespace().next().ok_or_else(|| BridgeError::config("agent_command is empty"))?;
+    which::which(program).map_err(|e| BridgeError::dependency_missing(program, e))?;
+    info!("📦 Serving {} headless (agent: {})", transport, command);
+
+    let (event_tx, mut event_rx) = mpsc::channel::<AppEvent>(64);
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        let _ = shutdown_tx.send(());
+    });
+    tokio::spawn(async move {
+        while let Some(event) = event_rx.recv().await {
+            match event {
+                AppEvent::Bridge(BridgeEvent::PairingUrlReady { url, .. }) => info!("📱 Pairing URL: {}", url),
+                AppEvent::Bridge(BridgeEvent::BridgeError { message }) => error!("{}", message),
+                _ => {}
+            }
+        }
+    });
+    crate::runner::run_bridge(config, transport.to_string(), event_tx, shutdown_rx).await
+}
+
+/// Enable `transport`, adding a `local` or `tailscale-serve` section with
+/// the defaults the TUI would write when there is none.
+pub fn select_transport(config: &mut CommonConfig, transport: &str) -> Result<()> {
+    let default = match transport {
+        "local" => TransportConfig { enabled: true, port: Some(8765), tls: Some(true), ..Default::default() },
+        "tailscale-serve" => TransportConfig { enabled: true, port: Some(8766), tls: None, ..Default::default() },
+        _ if config.transports.contains_key(transport) => TransportConfig::default(),
+        _ => anyhow::bail!(
+            "Transport '{}' not found in {} — configure it first (`bridge setup` for cloudflare)",
+            transport,
+            CommonConfig::config_path().display()
+        ),
+    };
+    let transport_cfg = config.transports.entry(transport.to_string()).or_insert(default);
+    if transport == "cloudflare" && transport_cfg.tunnel_id.is_none() {
+        anyhow::bail!("The cloudflare transport has no tunnel yet — run `bridge setup` first");
+    }
+    transport_cfg.enabled = true;
+    Ok(())
+}
+
+/// Ctrl+C, or SIGTERM (`docker stop`).
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// A `tracing` layer writing each record to stdout as a JSON object:
+/// `{"timestamp", "level", "target", "message", ...fields}`.
+pub struct JsonLogLayer {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonLogLayer {
+    pub fn stdout() -> Self {
+        Self { out: Mutex::new(Box::new(std::io::stdout())) }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for JsonLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = JsonVisitor::default();
+        event.record(&mut visitor);
+        let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        let line = json_line(&timestamp, *event.metadata().level(), event.metadata().target(), visitor.fields);
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        // Nowhere to report a failed log write.
+        let _ = writeln!(out, "{}", line);
+        let _ = out.flush();
+    }
+}
+
+fn json_line(timestamp: &str, level: Level, target: &str, fields: serde_json::Map<String, serde_json::Value>) -> String {
+    let mut record = serde_json::Map::new();
+    record.insert("timestamp".into(), timestamp.into());
+    record.insert("level".into(), level.as_str().into());
+    record.insert("target".into(), target.into());
+    record.extend(fields);
+    serde_json::Value::Object(record).to_string()
+}
+
+/// Collects an event's fields as JSON values.
+#[derive(Default)]
+struct JsonVisitor {
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Visit for JsonVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.fields.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.fields.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.fields.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.fields.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.fields.insert(field.name().into(), format!("{:?}", value).into());
+    }
+}
+
+/// When this process is PID 1, run the bridge as a child and act as its
+/// init until it exits. Returns the exit code to exit with, or `None` to
+/// carry on as the bridge.
+#[cfg(target_os = "linux")]
+pub fn run_as_init() -> Result<Option<i32>> {
+    use std::sync::atomic::Ordering;
+
+    if std::process::id() != 1 || is_init_child() {
+        return Ok(None);
+    }
+    let exe = std::env::current_exe()?;
+    let child = std::process::Command::new(exe).args(std::env::args_os().skip(1)).env(INIT_CHILD_VAR, "1").spawn()?;
+    let pid = child.id() as libc::pid_t;
+    init::CHILD.store(pid, Ordering::SeqCst);
+    for signal in init::FORWARDED {
+        // SAFETY: `forward` only calls async-signal-safe functions.
+        unsafe { libc::signal(signal, init::forward as extern "C" fn(libc::c_int) as libc::sighandler_t) };
+    }
+    loop {
+        let mut status = 0;
+        // SAFETY: `status` outlives the call.
+        let reaped = unsafe { libc::waitpid(-1, &mut status, 0) };
+        if reaped == pid {
+            return Ok(Some(init::exit_code(status)));
+        }
+        if reaped == -1 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() != std::io::ErrorKind::Interrupted {
+                return Err(e.into());
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod init {
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    /// The bridge the init runs.
+    pub(super) static CHILD: AtomicI32 = AtomicI32::new(0);
+
+    pub(super) const FORWARDED: [libc::c_int; 4] = [libc::SIGTERM, libc::SIGINT, libc::SIGHUP, libc::SIGQUIT];
+
+    pub(super) extern "C" fn forward(signal: libc::c_int) {
+        let pid = CHILD.load(Ordering::SeqCst);
+        if pid > 0 {
+            // SAFETY: kill is async-signal-safe.
+            unsafe { libc::kill(pid, signal) };
+        }
+    }
+
+    /// The shell's convention: the exit status, or 128 + the signal.
+    pub(super) fn exit_code(status: libc::c_int) -> i32 {
+        if libc::WIFEXITED(status) {
+            libc::WEXITSTATUS(status)
+        } else if libc::WIFSIGNALED(status) {
+            128 + libc::WTERMSIG(status)
+        } else {
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_lines_carry_every_field() {
+        let mut fields = serde_json::Map::new();
+        fields.insert("message".into(), "Agent spawned".into());
+        fields.insert("pid".into(), 42.into());
+        let line = json_line("2026-10-16T12:00:00.000Z", Level::INFO, "bridge::agent_pool", fields);
+        let parsed: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed["level"], "INFO");
+        assert_eq!(parsed["target"], "bridge::agent_pool");
+        assert_eq!(parsed["message"], "Agent spawned");
+        assert_eq!(parsed["pid"], 42);
+        assert!(!line.contains('\n'));
+
+        assert!(json_logs(Some("JSON")).unwrap());
+        assert!(!json_logs(Some("text")).unwrap());
+        assert!(json_logs(Some("yaml")).is_err());
+    }
+
+    #[test]
+    fn headless_transports_get_the_tui_defaults() {
+        let mut config = CommonConfig::default();
+        select_transport(&mut config, "local").unwrap();
+        assert!(config.transports["local"].enabled);
+        assert_eq!(config.transports["local"].port, Some(8765));
+        assert!(select_transport(&mut config, "cloudflare").is_err());
+
+        config.transports.insert("cloudflare".to_string(), TransportConfig { tunnel_id: Some("t".into()), ..Default::default() });
+        select_transport(&mut config, "cloudflare").unwrap();
+        assert!(config.transports["cloudflare"].enabled);
+        assert_eq!(config.transports["cloudflare"].tunnel_id.as_deref(), Some("t"));
+    }
+
+    #[test]
+    fn chosen_transports_must_be_configured() {
+        let mut config = CommonConfig::default();
+        let err = select_transport(&mut config, "tailscale-ip").unwrap_err().to_string();
+        assert!(err.contains("'tailscale-ip' not found"), "{err}");
+        assert!(config.transports.is_empty());
+
+        // A cloudflare section without a tunnel is left disabled.
+        config.transports.insert("cloudflare".to_string(), TransportConfig::default());
+        let err = select_transport(&mut config, "cloudflare").unwrap_err().to_string();
+        assert!(err.contains("bridge setup"), "{err}");
+        assert!(!config.transports["cloudflare"].enabled);
+
+        select_transport(&mut config, "tailscale-serve").unwrap();
+        assert_eq!(config.transports["tailscale-serve"].port, Some(8766));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn init_exits_like_a_shell() {
+        assert_eq!(init::exit_code(3 << 8), 3);
+        assert_eq!(init::exit_code(libc::SIGTERM), 128 + libc::SIGTERM);
+    }
+}
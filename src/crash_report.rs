@@ -0,0 +1,242 @@
+//! Crash reports of pooled agents that exit on their own.
+//!
+//! Each pooled agent keeps its last [`MAX_STDERR_LINES`] stderr lines and its
+//! last [`MAX_STDIN_MESSAGES`] client messages. When the agent exits without
+//! the bridge having stopped it, they are saved with its exit status to
+//! `crashes/<id>.json` in the config directory (`0600`, since the messages
+//! hold prompts), keeping the newest [`MAX_REPORTS`], and its clients are
+//! sent:
+//!
+//! ```json
+//! {"jsonrpc":"2.0","method":"bridge/agentCrashed",
+//!  "params":{"reportId":"20261016T141502318-3f2a9c1e","exitCode":101}}
+//! ```
+//!
+//! `bridge crashes list` and `bridge crashes show <id>` read the reports.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub const CRASHES_DIRNAME: &str = "crashes";
+
+/// Stderr lines kept per agent.
+pub const MAX_STDERR_LINES: usize = 50;
+
+/// Client messages kept per agent.
+pub const MAX_STDIN_MESSAGES: usize = 20;
+
+/// Characters kept of each line or message.
+const MAX_LINE_CHARS: usize = 2000;
+
+/// Reports kept; saving more deletes the oldest.
+pub const MAX_REPORTS: usize = 50;
+
+/// The recent stderr and stdin of one agent.
+#[derive(Debug, Default)]
+pub struct CrashLog {
+    stderr: Mutex<VecDeque<String>>,
+    stdin: Mutex<VecDeque<String>>,
+}
+
+impl CrashLog {
+    pub fn record_stderr(&self, line: &str) {
+        push(&self.stderr, line, MAX_STDERR_LINES);
+    }
+
+    pub fn record_stdin(&self, message: &str) {
+        push(&self.stdin, message, MAX_STDIN_MESSAGES);
+    }
+
+    fn snapshot(lines: &Mutex<VecDeque<String>>) -> Vec<String> {
+        lines.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect()
+    }
+}
+
+fn push(lines: &Mutex<VecDeque<String>>, line: &str, max: usize) {
+    let mut lines = lines.lock().unwrap_or_else(|e| e.into_inner());
+    if lines.len() == max {
+        lines.pop_front();
+    }
+    lines.push_back(line.chars().take(MAX_LINE_CHARS).collect());
+}
+
+/// What the bridge knew about an agent when it exited; see the module docs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CrashReport {
+    pub id: String,
+    pub crashed_at: DateTime<Utc>,
+    pub agent_command: String,
+    /// Name from the agent's `initialize` response, or "Agent"
+    pub agent_name: String,
+    pub working_dir: PathBuf,
+    pub exit_code: Option<i32>,
+    /// The signal that killed it (Unix)
+    pub signal: Option<i32>,
+    pub uptime_secs: u64,
+    /// Its last stderr lines, oldest first
+    pub stderr: Vec<String>,
+    /// The last client messages written to its stdin, oldest first
+    pub stdin: Vec<String>,
+}
+
+impl CrashReport {
+    pub fn new(log: &CrashLog, status: ExitStatus, agent_command: &str, agent_name: &str, working_dir: &Path, uptime: Duration) -> Self {
+        #[cfg(unix)]
+        let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+        #[cfg(not(unix))]
+        let signal = None;
+        let crashed_at = Utc::now();
+        let suffix = uuid::Uuid::new_v4().simple().to_string();
+        Self {
+            id: format!("{}-{}", crashed_at.format("%Y%m%dT%H%M%S%3f"), &suffix[..8]),
+            crashed_at,
+            agent_command: agent_command.to_string(),
+            agent_name: agent_name.to_string(),
+            working_dir: working_dir.to_path_buf(),
+            exit_code: status.code(),
+            signal,
+            uptime_secs: uptime.as_secs(),
+            stderr: CrashLog::snapshot(&log.stderr),
+            stdin: CrashLog::snapshot(&log.stdin),
+        }
+    }
+
+    /// "exit code 101", "signal 9" or "unknown status".
+    pub fn exit_description(&self) -> String {
+        match (self.exit_code, self.signal) {
+            (Some(code), _) => format!("exit code {}", code),
+            (None, Some(signal)) => format!("signal {}", signal),
+            (None, None) => "unknown status".to_string(),
+        }
+    }
+
+    /// The `bridge/agentCrashed` notification; `saved` says whether the
+    /// report could be written, without which it carries no `reportId`.
+    pub fn notification(&self, saved: bool) -> String {
+        let mut params = serde_json::json!({ "exitCode": self.exit_code });
+        if let Some(signal) = self.signal {
+            params["signal"] = signal.into();
+        }
+        if saved {
+            params["reportId"] = self.id.clone().into();
+        }
+        serde_json::json!({ "jsonrpc": "2.0", "method": "bridge/agentCrashed", "params": params }).to_string()
+    }
+}
+
+/// The crash reports of a config directory.
+#[derive(Debug, Clone)]
+pub struct CrashReports {
+    dir: PathBuf,
+}
+
+impl CrashReports {
+    pub fn new(config_dir: &Path) -> Self {
+        Self { dir: config_dir.join(CRASHES_DIRNAME) }
+    }
+
+    /// Save `report`, deleting the oldest reports over [`MAX_REPORTS`].
+    pub fn save(&self, report: &CrashReport) -> Result<()> {
+        std::fs::create_dir_all(&self.dir).with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let path = self.dir.join(format!("{}.json", report.id));
+        crate::private_file::write(&path, serde_json::to_string_pretty(report)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        let ids = self.ids();
+        for id in &ids[..ids.len().saturating_sub(MAX_REPORTS)] {
+            let _ = std::fs::remove_file(self.dir.join(format!("{}.json", id)));
+        }
+        Ok(())
+    }
+
+    /// The saved reports, newest first; unreadable files are skipped.
+    pub fn list(&self) -> Vec<CrashReport> {
+        self.ids().iter().rev().filter_map(|id| self.load(id).ok()).collect()
+    }
+
+    /// The report whose id is or starts with `id`.
+    pub fn find(&self, id: &str) -> Result<CrashReport> {
+        let matches: Vec<String> = self.ids().into_iter().filter(|candidate| candidate.starts_with(id)).collect();
+        match &matches[..] {
+            [only] => self.load(only),
+            [] => anyhow::bail!("No crash report {} in {}", id, self.dir.display()),
+            _ => anyhow::bail!("{} crash reports start with {} — give more of the id", matches.len(), id),
+        }
+    }
+
+    fn load(&self, id: &str) -> Result<CrashReport> {
+        let path = self.dir.join(format!("{}.json", id));
+        let json = std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&json).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Ids of the saved reports, oldest first (ids start with the time).
+    fn ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = std::fs::read_dir(&self.dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str()?.strip_suffix(".json").map(str::to_string))
+            .collect();
+        ids.sort();
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    fn exit_status(code: i32) -> ExitStatus {
+        std::os::unix::process::ExitStatusExt::from_raw(code << 8)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_keep_the_last_lines_and_are_found_by_prefix() {
+        let log = CrashLog::default();
+        for i in 0..MAX_STDERR_LINES + 5 {
+            log.record_stderr(&format!("line {}", i));
+        }
+        log.record_stdin(&"x".repeat(MAX_LINE_CHARS + 10));
+        let report = CrashReport::new(&log, exit_status(101), "agent --acp", "Agent", Path::new("/tmp"), Duration::from_secs(3));
+        assert_eq!(report.stderr.len(), MAX_STDERR_LINES);
+        assert_eq!(report.stderr[0], "line 5");
+        assert_eq!(report.stdin[0].len(), MAX_LINE_CHARS);
+        assert_eq!(report.exit_description(), "exit code 101");
+
+        let dir = tempfile::tempdir().unwrap();
+        let reports = CrashReports::new(dir.path());
+        reports.save(&report).unwrap();
+        assert_eq!(reports.find(&report.id[..20]).unwrap(), report);
+        assert!(reports.find("2001").is_err());
+
+        let notification: serde_json::Value = serde_json::from_str(&report.notification(true)).unwrap();
+        assert_eq!(notification["method"], "bridge/agentCrashed");
+        assert_eq!(notification["params"], serde_json::json!({ "reportId": report.id, "exitCode": 101 }));
+    }
+
+    #[test]
+    fn oldest_reports_are_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let reports = CrashReports::new(dir.path());
+        let log = CrashLog::default();
+        let mut ids = Vec::new();
+        for _ in 0..MAX_REPORTS + 2 {
+            let report = CrashReport::new(&log, ExitStatus::default(), "agent", "Agent", Path::new("/tmp"), Duration::ZERO);
+            reports.save(&report).unwrap();
+            ids.push(report.id);
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        let listed: Vec<String> = reports.list().into_iter().map(|r| r.id).collect();
+        ids.reverse();
+        assert_eq!(listed, ids[..MAX_REPORTS]);
+    }
+}
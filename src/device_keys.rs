@@ -0,0 +1,280 @@
+//! Per-device keys for rolling credentials over without re-pairing.
+//!
+//! Each successful pairing issues the device a random AES-256-GCM key,
+//! returned once in the pairing response as `credentialsKey: {"id", "key"}`
+//! and kept in `device_keys.json` in the config directory (permissions
+//! `0600`). When the bridge replaces a credential the device holds (the
+//! auth token from the admin endpoints, or the Cloudflare service token), it
+//! sends one `bridge/credentialsUpdate` notification per key over the pooled
+//! agents' connections:
+//!
+//! ```json
+//! {"jsonrpc":"2.0","method":"bridge/credentialsUpdate",
+//!  "params":{"keyId":"…","nonce":"…","ciphertext":"…"}}
+//! ```
+//!
+//! `nonce` and `ciphertext` are base64url without padding. The ciphertext
+//! (tag appended) decrypts under the device's key, with the `keyId` as
+//! associated data, to a [`CredentialsUpdate`]. Every connection sees every
+//! update; a device keeps the one with its own `keyId`.
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::Aes256Gcm;
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::agent_pool::AgentPool;
+use crate::pairing::PairingDevice;
+
+pub const DEVICE_KEYS_FILENAME: &str = "device_keys.json";
+
+/// Keys kept at most; pairing more devices forgets the oldest.
+pub const MAX_KEYS: usize = 64;
+
+/// How long a bridge about to restart keeps serving after sending a
+/// credentials update, so the update reaches connected devices first.
+pub const DELIVERY_GRACE: Duration = Duration::from_secs(2);
+
+/// A paired device's key, as stored in `device_keys.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceKey {
+    pub id: String,
+    /// 32 bytes, base64url without padding
+    pub key: String,
+    /// The device as described when it paired (see [`PairingDevice`])
+    pub device: String,
+    /// What the device said about itself when it paired; absent from keys
+    /// issued before this was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    pub paired_at: DateTime<Utc>,
+}
+
+/// The key as handed to the device in the pairing response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CredentialsKey {
+    pub id: String,
+    pub key: String,
+}
+
+/// The new credentials in a `bridge/credentialsUpdate`; fields that didn't
+/// change are left out.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialsUpdate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+}
+
+/// The device keys of a config directory.
+#[derive(Debug)]
+pub struct DeviceKeys {
+    path: PathBuf,
+    keys: Mutex<Vec<DeviceKey>>,
+}
+
+impl DeviceKeys {
+    /// Open `device_keys.json` in `dir`; a missing or unreadable file starts
+    /// empty.
+    pub fn open(dir: &Path) -> Self {
+        let path = dir.join(DEVICE_KEYS_FILENAME);
+        let keys = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Self { path, keys: Mutex::new(keys) }
+    }
+
+    /// The stored keys, oldest first.
+    pub fn keys(&self) -> Vec<DeviceKey> {
+        self.lock().clone()
+    }
+
+    /// The key with id `id`.
+    pub fn get(&self, id: &str) -> Option<DeviceKey> {
+        self.lock().iter().find(|key| key.id == id).cloned()
+    }
+
+    /// Issue and save a key for `device`.
+    pub fn issue(&self, device: &PairingDevice) -> Result<CredentialsKey> {
+        let key = DeviceKey {
+            id: uuid::Uuid::new_v4().to_string(),
+            key: URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>()),
+            device: device.to_string(),
+            name: device.name.clone(),
+            platform: device.platform.clone(),
+            app_version: device.app_version.clone(),
+            ip: Some(device.ip.clone()),
+            paired_at: Utc::now(),
+        };
+        let mut keys = self.lock();
+        keys.push(key.clone());
+        let excess = keys.len().saturating_sub(MAX_KEYS);
+        keys.drain(..excess);
+        self.save(&keys)?;
+        Ok(CredentialsKey { id: key.id, key: key.key })
+    }
+
+    /// One `bridge/credentialsUpdate` notification per key carrying `update`.
+    pub fn notifications(&self, update: &CredentialsUpdate) -> Vec<String> {
+        let plaintext = serde_json::to_vec(update).unwrap_or_default();
+        self.lock()
+            .iter()
+            .filter_map(|key| match encrypt(key, &plaintext) {
+                Ok(params) => Some(
+                    serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "method": "bridge/credentialsUpdate",
+                        "params": params,
+                    })
+                    .to_string(),
+                ),
+                Err(e) => {
+                    warn!("Skipping credentials update for device key {}: {:#}", key.id, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Send `update` to the clients of every pooled agent. Returns how many
+    /// devices it was encrypted for.
+    pub fn distribute(&self, pool: &AgentPool, update: &CredentialsUpdate) -> usize {
+        let notifications = self.notifications(update);
+        for notification in &notifications {
+            pool.notify_all(notification);
+        }
+        notifications.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<DeviceKey>> {
+        self.keys.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn save(&self, keys: &[DeviceKey]) -> Result<()> {
+        let json = serde_json::to_string_pretty(keys)?;
+        crate::private_file::write(&self.path, json).with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+/// Encrypt `plaintext` under `key`, as `{"keyId", "nonce", "ciphertext"}`.
+fn encrypt(key: &DeviceKey, plaintext: &[u8]) -> Result<serde_json::Value> {
+    let cipher = cipher(key)?;
+    let nonce = rand::random::<[u8; 12]>();
+    let ciphertext = cipher
+        .encrypt(&nonce.into(), Payload { msg: plaintext, aad: key.id.as_bytes() })
+        .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
+    Ok(serde_json::json!({
+        "keyId": key.id,
+        "nonce": URL_SAFE_NO_PAD.encode(nonce),
+        "ciphertext": URL_SAFE_NO_PAD.encode(ciphertext),
+    }))
+}
+
+fn cipher(key: &DeviceKey) -> Result<Aes256Gcm> {
+    let bytes = URL_SAFE_NO_PAD.decode(&key.key).context("Device key is not base64url")?;
+    Aes256Gcm::new_from_slice(&bytes).map_err(|_| anyhow::anyhow!("Device key is not 32 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(name: &str) -> PairingDevice {
+        PairingDevice { name: Some(name.into()), ip: "192.168.1.20".into(), ..PairingDevice::default() }
+    }
+
+    /// What the app does with a notification meant for `key`.
+    fn open(key: &CredentialsKey, notification: &str) -> Option<CredentialsUpdate> {
+        let v: serde_json::Value = serde_json::from_str(notification).unwrap();
+        assert_eq!(v["method"], "bridge/credentialsUpdate");
+        let params = &v["params"];
+        if params["keyId"] != key.id.as_str() {
+            return None;
+        }
+        let stored = DeviceKey {
+            id: key.id.clone(),
+            key: key.key.clone(),
+            device: String::new(),
+            name: None,
+            platform: None,
+            app_version: None,
+            ip: None,
+            paired_at: Utc::now(),
+        };
+        let nonce: [u8; 12] = URL_SAFE_NO_PAD.decode(params["nonce"].as_str().unwrap()).unwrap().try_into().unwrap();
+        let ciphertext = URL_SAFE_NO_PAD.decode(params["ciphertext"].as_str().unwrap()).unwrap();
+        let plaintext = cipher(&stored)
+            .unwrap()
+            .decrypt(&nonce.into(), Payload { msg: &ciphertext, aad: key.id.as_bytes() })
+            .ok()?;
+        Some(serde_json::from_slice(&plaintext).unwrap())
+    }
+
+    #[test]
+    fn each_device_decrypts_only_its_own_update() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = DeviceKeys::open(dir.path());
+        let phone = keys.issue(&device("phone")).unwrap();
+        let tablet = keys.issue(&device("tablet")).unwrap();
+        assert_ne!(phone.key, tablet.key);
+
+        let update = CredentialsUpdate { auth_token: Some("new-token".into()), ..CredentialsUpdate::default() };
+        let notifications = DeviceKeys::open(dir.path()).notifications(&update);
+        assert_eq!(notifications.len(), 2, "keys survive a reload");
+        let for_phone: Vec<_> = notifications.iter().filter_map(|n| open(&phone, n)).collect();
+        assert_eq!(for_phone, vec![update.clone()]);
+        assert!(!notifications[0].contains("new-token"));
+
+        // A key id swapped onto another device's ciphertext fails to decrypt.
+        let forged = notifications[1].replace(&tablet.id, &phone.id);
+        assert_eq!(open(&phone, &forged), None);
+    }
+
+    #[test]
+    fn oldest_keys_are_forgotten() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = DeviceKeys::open(dir.path());
+        let first = keys.issue(&device("first")).unwrap();
+        for _ in 0..MAX_KEYS {
+            keys.issue(&device("other")).unwrap();
+        }
+        let stored = keys.keys();
+        assert_eq!(stored.len(), MAX_KEYS);
+        assert!(stored.iter().all(|k| k.id != first.id));
+    }
+
+    #[test]
+    fn device_metadata_is_kept_and_optional() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = DeviceKeys::open(dir.path());
+        let device = PairingDevice { app_version: Some("2.3.0".into()), ..device("phone") };
+        let issued = keys.issue(&device).unwrap();
+        let stored = DeviceKeys::open(dir.path()).get(&issued.id).unwrap();
+        assert_eq!((stored.name.as_deref(), stored.app_version.as_deref()), (Some("phone"), Some("2.3.0")));
+
+        // Files written before the metadata was recorded still load.
+        let old = r#"[{"id":"k1","key":"AA","device":"A device (IP 10.0.0.5)","pairedAt":"2025-01-01T00:00:00Z"}]"#;
+        std::fs::write(dir.path().join(DEVICE_KEYS_FILENAME), old).unwrap();
+        let stored = DeviceKeys::open(dir.path()).get("k1").unwrap();
+        assert_eq!((stored.name, stored.ip), (None, None));
+    }
+}
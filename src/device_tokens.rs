@@ -0,0 +1,357 @@
+//! Extra device tokens with per-capability scopes.
+//!
+//! Besides the bridge's auth token (which has every scope), `common.toml` can
+//! list named devices, each with its own token and the scopes it may use:
+//!
+//! | Scope           | Allows                                                                                         |
+//! |-----------------|------------------------------------------------------------------------------------------------|
+//! | `chat`          | `session/*` requests (prompting, creating and loading sessions)                                |
+//! | `file-upload`   | the agent reading files from the device (`fs/read_text_file`)                                  |
+//! | `file-download` | the agent writing files to the device (`fs/write_text_file`)                                   |
+//! | `terminal`      | the agent running terminals through the device (`terminal/*`)                                  |
+//! | `admin`         | bridge-level changes: `bridge/appendMemory`, `bridge/selectWorkspace`, `bridge/session/finish` |
+//!
+//! Denied requests are answered with a JSON-RPC error instead of being
+//! forwarded, and the matching `clientCapabilities` are removed from
+//! `initialize` so the agent doesn't offer what it can't use. Each device
+//! token is pooled separately, so a shared device never sees the owner's
+//! sessions.
+//!
+//! `[[users]]` add namespaces for other people: a user's auth token has every
+//! scope like the bridge's, and their `[[users.devices]]` are limited like
+//! any device. Grants from either name the user, so the pool, logs, memory
+//! file and transcripts can keep users apart.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+use crate::common_config::{DeviceConfig, UserConfig};
+use crate::session_token::SessionTokens;
+
+/// JSON-RPC error code for a request outside the token's scopes.
+pub const SCOPE_DENIED: i64 = -32003;
+
+/// A capability a device token may be granted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope {
+    Chat,
+    FileDownload,
+    FileUpload,
+    Terminal,
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 5] = [Scope::Chat, Scope::FileDownload, Scope::FileUpload, Scope::Terminal, Scope::Admin];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Chat => "chat",
+            Scope::FileDownload => "file-download",
+            Scope::FileUpload => "file-upload",
+            Scope::Terminal => "terminal",
+            Scope::Admin => "admin",
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// A set of [`Scope`]s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Scopes(u8);
+
+impl Scopes {
+    /// Every scope, as held by the bridge's auth token.
+    pub fn all() -> Self {
+        Scope::ALL.into_iter().collect()
+    }
+
+    pub fn contains(self, scope: Scope) -> bool {
+        self.0 & scope.bit() != 0
+    }
+
+    pub fn is_all(self) -> bool {
+        self == Self::all()
+    }
+
+    /// Scope names, for the `bridge/capabilities` notification.
+    pub fn names(self) -> Vec<&'static str> {
+        Scope::ALL.into_iter().filter(|s| self.contains(*s)).map(Scope::as_str).collect()
+    }
+
+    /// The scope `method` requires, in either direction, or `None` when any
+    /// authenticated device may use it.
+    pub fn required_for(method: &str) -> Option<Scope> {
+        match method {
+            "fs/read_text_file" => Some(Scope::FileUpload),
+            "fs/write_text_file" => Some(Scope::FileDownload),
+            "bridge/appendMemory" | "bridge/selectWorkspace" | "bridge/session/finish" => Some(Scope::Admin),
+            m if m.starts_with("terminal/") => Some(Scope::Terminal),
+            m if m.starts_with("session/") => Some(Scope::Chat),
+            _ => None,
+        }
+    }
+
+    /// The scope `message` needs that these scopes lack, if any.
+    pub fn denies(self, message: &serde_json::Value) -> Option<Scope> {
+        let method = message.get("method").and_then(|m| m.as_str())?;
+        Self::required_for(method).filter(|s| !self.contains(*s))
+    }
+
+    /// The scope the request in `line` needs that these scopes lack, with its
+    /// id; `None` for allowed requests, notifications and responses.
+    pub fn denied_request(self, line: &str) -> Option<(Scope, serde_json::Value)> {
+        if self.is_all() {
+            return None;
+        }
+        let message = serde_json::from_str::<serde_json::Value>(line).ok()?;
+        Some((self.denies(&message)?, message.get("id")?.clone()))
+    }
+
+    /// Remove the `clientCapabilities` of an `initialize` request that these
+    /// scopes don't allow; returns whether anything changed.
+    pub fn restrict_initialize(self, message: &mut serde_json::Value) -> bool {
+        if message.get("method").and_then(|m| m.as_str()) != Some("initialize") {
+            return false;
+        }
+        let Some(caps) = message.pointer_mut("/params/clientCapabilities").and_then(|c| c.as_object_mut()) else {
+            return false;
+        };
+        let mut changed = false;
+        if let Some(fs) = caps.get_mut("fs").and_then(|f| f.as_object_mut()) {
+            for (key, scope) in [("readTextFile", Scope::FileUpload), ("writeTextFile", Scope::FileDownload)] {
+                if !self.contains(scope) && fs.get(key) == Some(&serde_json::Value::Bool(true)) {
+                    fs.insert(key.to_string(), serde_json::Value::Bool(false));
+                    changed = true;
+                }
+            }
+        }
+        if !self.contains(Scope::Terminal) && caps.get("terminal") == Some(&serde_json::Value::Bool(true)) {
+            caps.insert("terminal".to_string(), serde_json::Value::Bool(false));
+            changed = true;
+        }
+        changed
+    }
+}
+
+impl FromIterator<Scope> for Scopes {
+    fn from_iter<I: IntoIterator<Item = Scope>>(iter: I) -> Self {
+        Scopes(iter.into_iter().fold(0, |bits, s| bits | s.bit()))
+    }
+}
+
+/// JSON-RPC error answering request `id`, which needed `scope`.
+pub fn denied_response(id: &serde_json::Value, scope: Scope) -> String {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": SCOPE_DENIED,
+            "message": format!("This device token lacks the '{}' scope", scope.as_str()),
+        }
+    })
+    .to_string()
+}
+
+/// What an accepted token may do, and which pooled agent it reaches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grant {
+    /// Device name from the registry (`None` for the auth token or a user's).
+    pub device: Option<String>,
+    /// The `[[users]]` namespace of the token (`None` for the owner's).
+    pub user: Option<String>,
+    /// Token the agent pool is keyed by: the auth or device token itself,
+    /// never a session token derived from it.
+    pub pool_key: String,
+    pub scopes: Scopes,
+}
+
+impl Grant {
+    /// The auth token's grant: every scope.
+    pub fn full(auth_token: &str) -> Self {
+        Self { device: None, user: None, pool_key: auth_token.to_string(), scopes: Scopes::all() }
+    }
+}
+
+struct Device {
+    /// `None` for a user's auth token
+    name: Option<String>,
+    user: Option<String>,
+    token: String,
+    scopes: Scopes,
+    session_tokens: Option<SessionTokens>,
+}
+
+impl Device {
+    fn new(config: &DeviceConfig, user: Option<&str>, session_token_ttl: Option<Duration>) -> Self {
+        Self {
+            name: Some(config.name.clone()),
+            user: user.map(str::to_string),
+            token: config.token.clone(),
+            scopes: config.scopes.iter().copied().collect(),
+            session_tokens: session_token_ttl.map(|ttl| SessionTokens::new(config.token.clone(), ttl)),
+        }
+    }
+}
+
+/// The configured device tokens, each optionally issuing session tokens.
+#[derive(Default)]
+pub struct DeviceTokens {
+    devices: Vec<Device>,
+}
+
+impl DeviceTokens {
+    /// Build the registry; with `session_token_ttl`, each device can exchange
+    /// its token at `POST /session-token` like the auth token.
+    pub fn new(devices: &[DeviceConfig], session_token_ttl: Option<Duration>) -> Self {
+        let devices = devices
+            .iter()
+            .map(|d| Device::new(d, None, session_token_ttl))
+            .collect();
+        Self { devices }
+    }
+
+    /// Also accept each user's auth token, with every scope, and their
+    /// devices, all granted in the user's namespace.
+    pub fn with_users(mut self, users: &[UserConfig], session_token_ttl: Option<Duration>) -> Self {
+        for user in users {
+            self.devices.push(Device {
+                name: None,
+                user: Some(user.name.clone()),
+                token: user.auth_token.clone(),
+                scopes: Scopes::all(),
+                session_tokens: session_token_ttl.map(|ttl| SessionTokens::new(user.auth_token.clone(), ttl)),
+            });
+            self.devices.extend(user.devices.iter().map(|d| Device::new(d, Some(&user.name), session_token_ttl)));
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// The grant for `presented`: a device token or a live session token
+    /// derived from one.
+    pub fn grant(&self, presented: &str) -> Option<Grant> {
+        self.find(presented).map(|d| Grant { device: d.name.clone(), user: d.user.clone(), pool_key: d.token.clone(), scopes: d.scopes })
+    }
+
+    /// The session token issuer of the device `presented` authenticates as.
+    pub fn session_tokens_for(&self, presented: &str) -> Option<&SessionTokens> {
+        self.find(presented).and_then(|d| d.session_tokens.as_ref())
+    }
+
+    /// The token of the device named `name` in `user`'s namespace.
+    pub fn token_of(&self, name: &str, user: Option<&str>) -> Option<&str> {
+        self.devices
+            .iter()
+            .find(|d| d.name.as_deref() == Some(name) && d.user.as_deref() == user)
+            .map(|d| d.token.as_str())
+    }
+
+    fn find(&self, presented: &str) -> Option<&Device> {
+        if presented.is_empty() {
+            return None;
+        }
+        self.devices.iter().find(|d| match &d.session_tokens {
+            Some(tokens) => tokens.accepts(presented),
+            None => bool::from(d.token.as_bytes().ct_eq(presented.as_bytes())),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn device(name: &str, token: &str, scopes: &[Scope]) -> DeviceConfig {
+        DeviceConfig { name: name.into(), token: token.into(), scopes: scopes.to_vec() }
+    }
+
+    #[test]
+    fn scopes_gate_methods_in_both_directions() {
+        let chat_only: Scopes = [Scope::Chat].into_iter().collect();
+        assert_eq!(chat_only.denies(&json!({ "method": "session/prompt" })), None);
+        assert_eq!(chat_only.denies(&json!({ "method": "initialize" })), None);
+        assert_eq!(chat_only.denies(&json!({ "method": "fs/read_text_file" })), Some(Scope::FileUpload));
+        assert_eq!(chat_only.denies(&json!({ "method": "fs/write_text_file" })), Some(Scope::FileDownload));
+        assert_eq!(chat_only.denies(&json!({ "method": "terminal/create" })), Some(Scope::Terminal));
+        assert_eq!(chat_only.denies(&json!({ "method": "bridge/appendMemory" })), Some(Scope::Admin));
+        assert_eq!(chat_only.denies(&json!({ "method": "bridge/selectWorkspace" })), Some(Scope::Admin));
+        assert_eq!(chat_only.denies(&json!({ "method": "bridge/session/finish" })), Some(Scope::Admin));
+        assert_eq!(chat_only.denies(&json!({ "method": "bridge/listWorkspaces" })), None);
+        assert_eq!(chat_only.denies(&json!({ "id": 1, "result": {} })), None);
+        assert_eq!(
+            chat_only.denied_request(r#"{"id":7,"method":"fs/write_text_file"}"#),
+            Some((Scope::FileDownload, json!(7)))
+        );
+        assert_eq!(chat_only.denied_request(r#"{"method":"terminal/output"}"#), None);
+        assert_eq!(Scopes::all().denies(&json!({ "method": "terminal/create" })), None);
+        assert_eq!(chat_only.names(), vec!["chat"]);
+        assert!(Scopes::all().is_all() && !chat_only.is_all());
+    }
+
+    #[test]
+    fn initialize_loses_capabilities_outside_scope() {
+        let scopes: Scopes = [Scope::Chat, Scope::FileUpload].into_iter().collect();
+        let mut init = json!({
+            "method": "initialize",
+            "params": { "clientCapabilities": { "fs": { "readTextFile": true, "writeTextFile": true }, "terminal": true } }
+        });
+        assert!(scopes.restrict_initialize(&mut init));
+        assert_eq!(
+            init["params"]["clientCapabilities"],
+            json!({ "fs": { "readTextFile": true, "writeTextFile": false }, "terminal": false })
+        );
+        assert!(!scopes.restrict_initialize(&mut init));
+        assert!(!Scopes::all().restrict_initialize(&mut json!({ "method": "session/new", "params": {} })));
+    }
+
+    #[test]
+    fn device_tokens_grant_their_scopes() {
+        let devices = DeviceTokens::new(
+            &[device("team-tablet", "tablet-token", &[Scope::Chat]), device("laptop", "laptop-token", &Scope::ALL)],
+            Some(Duration::from_secs(900)),
+        );
+        let grant = devices.grant("tablet-token").unwrap();
+        assert_eq!(grant.device.as_deref(), Some("team-tablet"));
+        assert_eq!(grant.pool_key, "tablet-token");
+        assert_eq!(grant.scopes.names(), vec!["chat"]);
+        assert!(devices.grant("laptop-token").unwrap().scopes.is_all());
+        assert!(devices.grant("other").is_none());
+        assert!(devices.grant("").is_none());
+
+        // Session tokens derived from a device token keep its grant.
+        let session = devices.session_tokens_for("tablet-token").unwrap().issue().token;
+        assert_eq!(devices.grant(&session).unwrap().pool_key, "tablet-token");
+        assert!(devices.session_tokens_for(&session).unwrap().exchange(&session).is_some());
+        assert!(DeviceTokens::new(&[device("t", "tablet-token", &[Scope::Chat])], None).grant(&session).is_none());
+    }
+
+    #[test]
+    fn user_tokens_are_granted_in_the_users_namespace() {
+        let alice = UserConfig {
+            name: "alice".into(),
+            agent_id: String::new(),
+            auth_token: "alice-token".into(),
+            max_agents: None,
+            devices: vec![device("alice-tablet", "alice-tablet-token", &[Scope::Chat])],
+        };
+        let devices = DeviceTokens::new(&[device("team-tablet", "tablet-token", &[Scope::Chat])], None).with_users(&[alice], None);
+        let grant = devices.grant("alice-token").unwrap();
+        assert_eq!((grant.device, grant.user.as_deref()), (None, Some("alice")));
+        assert!(grant.scopes.is_all());
+        let grant = devices.grant("alice-tablet-token").unwrap();
+        assert_eq!((grant.device.as_deref(), grant.user.as_deref()), (Some("alice-tablet"), Some("alice")));
+        assert_eq!(grant.scopes.names(), vec!["chat"]);
+        assert_eq!(devices.grant("tablet-token").unwrap().user, None);
+    }
+}
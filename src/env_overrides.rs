@@ -0,0 +1,279 @@
+//! `BRIDGE_*` environment variables overriding `common.toml`.
+//!
+//! Every key can be set from the environment, for containers that configure
+//! the bridge without a mounted file. The variable name is the key path in
+//! upper case, with `__` between sections:
+//!
+//! ```sh
+//! BRIDGE_AUTH_TOKEN=...                      # auth_token
+//! BRIDGE_TRANSPORTS__LOCAL__ENABLED=true     # [transports.local] enabled
+//! BRIDGE_TRANSPORTS__LOCAL__PORT=9000        # [transports.local] port
+//! BRIDGE_TRANSPORTS__TAILSCALE_SERVE__ENABLED=true
+//! BRIDGE_PUSH_RELAY__URL=https://push.example.com
+//! ```
+//!
+//! A segment names an existing key with `-` or `_` alike; a new transport
+//! name is written with `-` (`tailscale-serve`). Values are read as TOML
+//! (`9000`, `true`, `["a", "b"]`), and as a plain string when they don't
+//! parse or the key already holds a string; quote a string that would parse
+//! as something else (`BRIDGE_AUTH_TOKEN='"12345"'`). Arrays of tables
+//! (`[[devices]]`) can't be addressed.
+//!
+//! For secrets, `BRIDGE_<KEY>_FILE` names a file holding the value instead
+//! (Docker and Kubernetes secrets), read with its trailing newline removed:
+//! `BRIDGE_AUTH_TOKEN_FILE=/run/secrets/auth_token`. Keys that themselves end
+//! in `_file` (`cert_file`, `key_file`, `pkcs12_file`) are set directly.
+//!
+//! Overrides win over `common.toml`, which wins over the defaults. They are
+//! never written back: [`crate::common_config::CommonConfig::save`] keeps
+//! the file's own values for overridden keys.
+
+use anyhow::{Context, Result};
+
+/// Prefix of override variables.
+pub const PREFIX: &str = "BRIDGE_";
+
+/// Suffix of a variable naming a file that holds the value.
+pub const FILE_SUFFIX: &str = "_FILE";
+
+/// Keys ending in `_file` themselves, which [`FILE_SUFFIX`] doesn't redirect.
+const FILE_KEYS: [&str; 3] = ["CERT_FILE", "KEY_FILE", "PKCS12_FILE"];
+
+/// `BRIDGE_*` variables the bridge reads for other purposes.
+const RESERVED: [&str; 5] = [
+    "BRIDGE_BUNDLE_PASSPHRASE",
+    "BRIDGE_BUNDLE_PASSPHRASE_FILE",
+    crate::container::TRANSPORT_VAR,
+    crate::container::LOG_FORMAT_VAR,
+    crate::container::INIT_CHILD_VAR,
+];
+
+/// One applied override.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Override {
+    pub var: String,
+    /// Key path it set.
+    pub path: Vec<String>,
+    /// How many keys of `path` existed before; the rest were created.
+    existed: usize,
+    /// The value it replaced, when the whole path existed.
+    previous: Option<toml::Value>,
+}
+
+/// Apply the `BRIDGE_*` entries of `vars` to `table`, in name order.
+pub fn apply(table: &mut toml::Table, vars: impl IntoIterator<Item = (String, String)>) -> Result<Vec<Override>> {
+    let mut vars: Vec<(String, String)> = vars
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(PREFIX) && name.len() > PREFIX.len() && !RESERVED.contains(&name.as_str()))
+        .collect();
+    vars.sort();
+    let names: Vec<String> = vars.iter().map(|(name, _)| name.clone()).collect();
+
+    let mut applied = Vec::new();
+    for (var, raw) in vars {
+        let (key_var, raw) = match secret_file_target(&var) {
+            Some(target) => {
+                if names.contains(&target) {
+                    anyhow::bail!("{} and {} are both set", target, var);
+                }
+                (target, read_secret_file(&var, &raw)?)
+            }
+            None => (var.clone(), raw),
+        };
+        let segments: Vec<String> = key_var[PREFIX.len()..].to_lowercase().split("__").map(str::to_string).collect();
+        if segments.iter().any(String::is_empty) {
+            anyhow::bail!("{}: empty key segment", var);
+        }
+        let mut current = &mut *table;
+        let mut path = Vec::new();
+        let mut existed = 0;
+        for (i, segment) in segments.iter().enumerate() {
+            let parent = path.last().map(String::as_str);
+            let key = resolve_key(current, segment, parent);
+            path.push(key.clone());
+            if current.contains_key(&key) && existed == i {
+                existed += 1;
+            }
+            if i + 1 == segments.len() {
+                let previous = current.get(&key).cloned();
+                let value = parse_value(&raw, previous.as_ref());
+                current.insert(key, value);
+                applied.push(Override { var: var.clone(), path: path.clone(), existed, previous });
+                break;
+            }
+            let entry = current.entry(key).or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            current = match entry {
+                toml::Value::Table(inner) => inner,
+                _ => anyhow::bail!("{}: {} is not a section", var, path.join(".")),
+            };
+        }
+    }
+    Ok(applied)
+}
+
+/// Put back the values `overrides` replaced, and remove the keys and
+/// sections they created, so they aren't saved.
+pub fn revert(table: &mut toml::Table, overrides: &[Override]) {
+    'overrides: for applied in overrides.iter().rev() {
+        let keep = applied.existed.min(applied.path.len() - 1);
+        let mut current = &mut *table;
+        for section in &applied.path[..keep] {
+            match current.get_mut(section) {
+                Some(toml::Value::Table(inner)) => current = inner,
+                _ => continue 'overrides,
+            }
+        }
+        let key = &applied.path[keep];
+        match (&applied.previous, applied.existed == applied.path.len()) {
+            (Some(value), true) => {
+                current.insert(key.clone(), value.clone());
+            }
+            _ => {
+                current.remove(key);
+            }
+        }
+    }
+}
+
+/// The variable `var` stands for when it names a secret file
+/// (`BRIDGE_AUTH_TOKEN_FILE` → `BRIDGE_AUTH_TOKEN`).
+fn secret_file_target(var: &str) -> Option<String> {
+    let target = var.strip_suffix(FILE_SUFFIX)?;
+    let last = var.rsplit("__").next().unwrap_or(var);
+    if target.len() <= PREFIX.len() || FILE_KEYS.contains(&last) {
+        return None;
+    }
+    Some(target.to_string())
+}
+
+/// The contents of the file `var` names, without its trailing newline.
+pub fn read_secret_file(var: &str, path: &str) -> Result<String> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("{}: failed to read {}", var, path))?;
+    Ok(text.strip_suffix('\n').map(|t| t.strip_suffix('\r').unwrap_or(t)).unwrap_or(&text).to_string())
+}
+
+/// The key of `table` that `segment` names: an existing key equal to it
+/// with `-` read as `_`, else `segment` itself, with `_` written as `-` for
+/// transport names.
+fn resolve_key(table: &toml::Table, segment: &str, parent: Option<&str>) -> String {
+    if let Some(key) = table.keys().find(|key| key.to_lowercase().replace('-', "_") == segment) {
+        return key.clone();
+    }
+    match parent {
+        Some("transports") => segment.replace('_', "-"),
+        _ => segment.to_string(),
+    }
+}
+
+fn parse_value(raw: &str, previous: Option<&toml::Value>) -> toml::Value {
+    if matches!(previous, Some(toml::Value::String(_))) {
+        return toml::Value::String(raw.to_string());
+    }
+    format!("v = {}", raw)
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut parsed| parsed.remove("v"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn overrides_set_nested_keys_and_keep_string_types() {
+        let original: toml::Table = r#"
+            auth_token = "from-file"
+            [transports.tailscale-serve]
+            enabled = false
+        "#
+        .parse()
+        .unwrap();
+        let mut table = original.clone();
+        let applied = apply(
+            &mut table,
+            vars(&[
+                ("BRIDGE_AUTH_TOKEN", "12345"),
+                ("BRIDGE_TRANSPORTS__TAILSCALE_SERVE__ENABLED", "true"),
+                ("BRIDGE_TRANSPORTS__TAILSCALE_IP__PORT", "9000"),
+                ("BRIDGE_PUSH_RELAY__URL", "https://push.example.com"),
+                ("BRIDGE_BUNDLE_PASSPHRASE", "not config"),
+                ("HOME", "/root"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(applied.len(), 4);
+        assert_eq!(table["auth_token"].as_str(), Some("12345"), "an existing string stays a string");
+        assert_eq!(table["transports"]["tailscale-serve"]["enabled"].as_bool(), Some(true));
+        assert_eq!(table["transports"]["tailscale-ip"]["port"].as_integer(), Some(9000));
+        assert_eq!(table["push_relay"]["url"].as_str(), Some("https://push.example.com"));
+        assert!(!table.contains_key("bundle_passphrase"));
+
+        revert(&mut table, &applied);
+        assert_eq!(table["auth_token"].as_str(), Some("from-file"));
+        assert_eq!(table["transports"]["tailscale-serve"]["enabled"].as_bool(), Some(false));
+        assert!(table["transports"].get("tailscale-ip").is_none());
+        assert!(!table.contains_key("push_relay"), "sections an override created are removed");
+    }
+
+    #[test]
+    fn overriding_inside_a_value_is_refused() {
+        let mut table: toml::Table = "agent_command = \"copilot --acp\"".parse().unwrap();
+        assert!(apply(&mut table, vars(&[("BRIDGE_AGENT_COMMAND__X", "1")])).is_err());
+        assert!(apply(&mut table, vars(&[("BRIDGE_LIMITS____X", "1")])).is_err());
+    }
+
+    #[test]
+    fn file_variables_read_secrets_but_not_file_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret = dir.path().join("auth_token");
+        std::fs::write(&secret, "from-secret\n").unwrap();
+        let secret = secret.to_str().unwrap();
+
+        let mut table = toml::Table::new();
+        let applied = apply(
+            &mut table,
+            vars(&[
+                ("BRIDGE_AUTH_TOKEN_FILE", secret),
+                ("BRIDGE_TRANSPORTS__LOCAL__CERT_FILE", "/etc/bridge/cert.pem"),
+                ("BRIDGE_TRANSPORT", "local"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(applied.len(), 2);
+        assert_eq!(applied[0].var, "BRIDGE_AUTH_TOKEN_FILE");
+        assert_eq!(table["auth_token"].as_str(), Some("from-secret"));
+        assert_eq!(table["transports"]["local"]["cert_file"].as_str(), Some("/etc/bridge/cert.pem"));
+
+        let both = vars(&[("BRIDGE_AUTH_TOKEN", "x"), ("BRIDGE_AUTH_TOKEN_FILE", secret)]);
+        assert!(apply(&mut toml::Table::new(), both).is_err());
+        let missing = vars(&[("BRIDGE_ADMIN_TOKEN_FILE", "/nonexistent/admin_token")]);
+        assert!(apply(&mut toml::Table::new(), missing).is_err());
+    }
+
+    #[test]
+    fn overrides_are_loaded_but_not_saved() {
+        use crate::common_config::CommonConfig;
+
+        let dir = tempfile::tempdir().unwrap();
+        let env = vars(&[("BRIDGE_AUTH_TOKEN", "from-env"), ("BRIDGE_TRANSPORTS__LOCAL__PORT", "9000")]);
+        let config = CommonConfig::load_with_env(dir.path(), env.clone()).unwrap();
+        assert_eq!(config.auth_token, "from-env");
+        assert_eq!(config.transports["local"].port, Some(9000));
+
+        std::fs::write(dir.path().join("common.toml"), "auth_token = \"from-file\"\nagent_id = \"a\"\n").unwrap();
+        let mut config = CommonConfig::load_with_env(dir.path(), env).unwrap();
+        assert_eq!(config.auth_token, "from-env");
+        config.agent_id = "b".to_string();
+        config.save_to_dir(dir.path()).unwrap();
+
+        let saved = CommonConfig::load_with_env(dir.path(), Vec::new()).unwrap();
+        assert_eq!((saved.auth_token.as_str(), saved.agent_id.as_str()), ("from-file", "b"));
+        assert!(!saved.transports.contains_key("local"));
+    }
+}
@@ -0,0 +1,198 @@
+//! Typed errors of the library modules.
+//!
+//! `cloudflare`, `tls`, `agent_pool` and the entry points of `bridge` return
+//! [`BridgeError`], so callers can tell a setting to fix from a network hiccup
+//! worth retrying, a program to install, or a peer that broke the protocol.
+//! The message of each variant reads like an `anyhow` context, with the
+//! underlying error as its source: `{:#}` on an `anyhow::Error` made from it
+//! prints the whole chain.
+//!
+//! ```ignore
+//! match bridge.start().await {
+//!     Err(BridgeError::Config { .. }) => eprintln!("Check common.toml"),
+//!     Err(BridgeError::DependencyMissing { program, .. }) => eprintln!("Install {program}"),
+//!     other => other?,
+//! }
+//! ```
+
+use thiserror::Error;
+
+/// Any error, kept as the source of a [`BridgeError`].
+pub type Source = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+pub type Result<T> = std::result::Result<T, BridgeError>;
+
+#[derive(Debug, Error)]
+pub enum BridgeError {
+    /// Settings that can't work as given: a certificate that doesn't load, a
+    /// TLS policy, an agent command, a workspace, a port already in use.
+    #[error("{message}")]
+    Config {
+        message: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// A request or connection that didn't go through; trying again may help.
+    #[error("{message}")]
+    Network {
+        message: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// A program the bridge runs isn't installed or not on `PATH`.
+    #[error("{program} not found — is it installed and on PATH?")]
+    DependencyMissing {
+        program: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// A peer answered outside the expected protocol: a failed handshake, an
+    /// agent that doesn't answer `initialize`, a response that doesn't parse.
+    #[error("{message}")]
+    Protocol {
+        message: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// The bridge is at a limit, e.g. a full agent pool; trying again once
+    /// something is freed may help.
+    #[error("{0}")]
+    Capacity(String),
+    /// The Cloudflare API refused a request.
+    #[error("{message}: {errors}")]
+    Api {
+        message: String,
+        /// Cloudflare's error codes, e.g. `10000` for a token without permission
+        codes: Vec<i32>,
+        /// Cloudflare's error messages, joined
+        errors: String,
+    },
+    /// Local I/O: files, pipes to child processes.
+    #[error("{message}")]
+    Io {
+        message: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// An error from a module without typed errors yet.
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl BridgeError {
+    pub fn config(message: impl Into<String>) -> Self {
+        BridgeError::Config { message: message.into(), source: None }
+    }
+
+    pub fn network(message: impl Into<String>) -> Self {
+        BridgeError::Network { message: message.into(), source: None }
+    }
+
+    pub fn protocol(message: impl Into<String>) -> Self {
+        BridgeError::Protocol { message: message.into(), source: None }
+    }
+
+    pub fn dependency_missing(program: impl Into<String>, source: impl Into<Source>) -> Self {
+        BridgeError::DependencyMissing { program: program.into(), source: Some(source.into()) }
+    }
+
+    /// Whether the same call may succeed later without changing anything.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, BridgeError::Network { .. } | BridgeError::Capacity(_))
+    }
+}
+
+/// A dropped or refused connection is [`BridgeError::Network`], any other
+/// I/O error [`BridgeError::Io`].
+impl From<std::io::Error> for BridgeError {
+    fn from(error: std::io::Error) -> Self {
+        use std::io::ErrorKind::*;
+        let network = matches!(
+            error.kind(),
+            ConnectionRefused | ConnectionReset | ConnectionAborted | NotConnected | BrokenPipe | TimedOut | UnexpectedEof
+        );
+        let message = if network { "Connection failed" } else { "I/O error" }.to_string();
+        let source = Some(error.into());
+        if network {
+            BridgeError::Network { message, source }
+        } else {
+            BridgeError::Io { message, source }
+        }
+    }
+}
+
+/// An `anyhow::Error` wrapping a `BridgeError` gives the `BridgeError` back;
+/// anything else becomes [`BridgeError::Other`].
+impl From<anyhow::Error> for BridgeError {
+    fn from(error: anyhow::Error) -> Self {
+        error.downcast().unwrap_or_else(BridgeError::Other)
+    }
+}
+
+/// Attach a message and a kind to a failed `Result` or an empty `Option`,
+/// like `anyhow::Context`.
+pub trait ResultExt<T> {
+    fn config_err(self, message: impl Into<String>) -> Result<T>;
+    fn network_err(self, message: impl Into<String>) -> Result<T>;
+    fn protocol_err(self, message: impl Into<String>) -> Result<T>;
+    fn io_err(self, message: impl Into<String>) -> Result<T>;
+}
+
+impl<T, E: Into<Source>> ResultExt<T> for std::result::Result<T, E> {
+    fn config_err(self, message: impl Into<String>) -> Result<T> {
+        self.map_err(|e| BridgeError::Config { message: message.into(), source: Some(e.into()) })
+    }
+
+    fn network_err(self, message: impl Into<String>) -> Result<T> {
+        self.map_err(|e| BridgeError::Network { message: message.into(), source: Some(e.into()) })
+    }
+
+    fn protocol_err(self, message: impl Into<String>) -> Result<T> {
+        self.map_err(|e| BridgeError::Protocol { message: message.into(), source: Some(e.into()) })
+    }
+
+    fn io_err(self, message: impl Into<String>) -> Result<T> {
+        self.map_err(|e| BridgeError::Io { message: message.into(), source: Some(e.into()) })
+    }
+}
+
+impl<T> ResultExt<T> for Option<T> {
+    fn config_err(self, message: impl Into<String>) -> Result<T> {
+        self.ok_or_else(|| BridgeError::config(message))
+    }
+
+    fn network_err(self, message: impl Into<String>) -> Result<T> {
+        self.ok_or_else(|| BridgeError::network(message))
+    }
+
+    fn protocol_err(self, message: impl Into<String>) -> Result<T> {
+        self.ok_or_else(|| BridgeError::protocol(message))
+    }
+
+    fn io_err(self, message: impl Into<String>) -> Result<T> {
+        self.ok_or_else(|| BridgeError::Io { message: message.into(), source: None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kinds_survive_a_round_trip_through_anyhow() {
+        let io = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+        let error = Err::<(), _>(io).network_err("Failed to reach the API").unwrap_err();
+        assert!(error.is_transient());
+
+        let wrapped = anyhow::Error::from(error);
+        assert_eq!(format!("{:#}", wrapped), "Failed to reach the API: refused");
+        assert!(matches!(BridgeError::from(wrapped), BridgeError::Network { .. }));
+
+        let other = BridgeError::from(anyhow::anyhow!("untyped"));
+        assert!(matches!(other, BridgeError::Other(_)));
+        assert_eq!(other.to_string(), "untyped");
+
+        let missing = None::<()>.config_err("No certificate found").unwrap_err();
+        assert!(matches!(missing, BridgeError::Config { source: None, .. }));
+    }
+}
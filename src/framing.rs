@@ -0,0 +1,132 @@
+//! Framing of ACP messages between WebSocket clients and agents.
+//!
+//! Agents read newline-delimited JSON, so a client message has to reach
+//! them as exactly one line of valid JSON. [`client_message`] checks each
+//! WebSocket message before it is forwarded: bytes that aren't UTF-8, and
+//! text that isn't one JSON value, are answered with a JSON-RPC parse error
+//! ([`FrameError::response`]) instead of reaching the agent mangled, and a
+//! pretty-printed message is put on one line.
+//!
+//! In the other direction, [`LineSplitter`] puts an in-process agent's
+//! output back together into lines: its chunks may split a line, or a
+//! multi-byte character, anywhere.
+
+use serde::de::IgnoredAny;
+use tracing::warn;
+
+/// Why a client message wasn't forwarded.
+#[derive(Debug, thiserror::Error)]
+pub enum FrameError {
+    #[error("message is not valid UTF-8 (invalid byte at offset {valid_up_to})")]
+    Utf8 { valid_up_to: usize },
+    #[error("message is not a single JSON value: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl FrameError {
+    /// JSON-RPC `-32700` Parse error for the client. The message's `id`
+    /// can't be read, so the response's is `null`.
+    pub fn response(&self) -> String {
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": null,
+            "error": { "code": -32700, "message": "Parse error", "data": { "reason": self.to_string() } },
+        })
+        .to_string()
+    }
+}
+
+/// The line to write to the agent for the WebSocket message `data`, without
+/// its newline.
+pub fn client_message(data: &[u8]) -> Result<String, FrameError> {
+    let text = std::str::from_utf8(data).map_err(|e| FrameError::Utf8 { valid_up_to: e.valid_up_to() })?;
+    serde_json::from_str::<IgnoredAny>(text)?;
+    // Inside a JSON string a line break has to be escaped, so in valid JSON
+    // it can only be whitespace between tokens.
+    Ok(text.replace(['\n', '\r'], " "))
+}
+
+/// Splits a byte stream delivered in arbitrary chunks into lines.
+#[derive(Debug, Default)]
+pub struct LineSplitter {
+    pending: Vec<u8>,
+}
+
+impl LineSplitter {
+    /// The lines completed by `chunk`, without line endings; blank lines are
+    /// skipped. Output that ends in a complete JSON value without a newline
+    /// is passed on too, since in-process agents don't always send one.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(chunk);
+        let mut lines = Vec::new();
+        let mut start = 0;
+        while let Some(end) = self.pending[start..].iter().position(|&b| b == b'\n') {
+            lines.extend(decode(&self.pending[start..start + end]));
+            start += end + 1;
+        }
+        self.pending.drain(..start);
+        if self.pending.trim_ascii_end().ends_with(b"}") && serde_json::from_slice::<IgnoredAny>(&self.pending).is_ok() {
+            lines.extend(self.finish());
+        }
+        lines
+    }
+
+    /// Whatever is left, as a last line.
+    pub fn finish(&mut self) -> Option<String> {
+        decode(&std::mem::take(&mut self.pending))
+    }
+}
+
+fn decode(line: &[u8]) -> Option<String> {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    if line.trim_ascii().is_empty() {
+        return None;
+    }
+    let text = String::from_utf8_lossy(line);
+    if let std::borrow::Cow::Owned(_) = text {
+        warn!("Agent output is not valid UTF-8; invalid bytes were replaced");
+    }
+    Some(text.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_messages_reach_the_agent_as_one_json_line() {
+        let request = r#"{"jsonrpc":"2.0","id":1,"method":"session/prompt","params":{"text":"é\n"}}"#;
+        assert_eq!(client_message(request.as_bytes()).unwrap(), request);
+        let pretty = "{\r\n  \"jsonrpc\": \"2.0\",\n  \"method\": \"x\"\n}";
+        let line = client_message(pretty.as_bytes()).unwrap();
+        assert!(!line.contains('\n') && !line.contains('\r'));
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&line).unwrap()["method"], "x");
+
+        let cut = &"{\"text\":\"é\"}".as_bytes()[..10];
+        let err = client_message(cut).unwrap_err();
+        assert!(matches!(err, FrameError::Utf8 { valid_up_to: 9 }));
+        for bad in [&b"{\"id\":1"[..], b"{} {}", b"", b"not json"] {
+            let err = client_message(bad).unwrap_err();
+            let response: serde_json::Value = serde_json::from_str(&err.response()).unwrap();
+            assert_eq!(response["error"]["code"], -32700);
+            assert!(response["id"].is_null());
+        }
+    }
+
+    #[test]
+    fn split_chunks_make_whole_lines() {
+        let output = "{\"text\":\"héllo\"}\r\n\n{\"id\":2}\n{\"id\":3}";
+        // Split inside "é" and inside the line ending.
+        let bytes = output.as_bytes();
+        let mut splitter = LineSplitter::default();
+        let mut lines = splitter.push(&bytes[..11]);
+        assert!(lines.is_empty());
+        lines.extend(splitter.push(&bytes[11..18]));
+        lines.extend(splitter.push(&bytes[18..]));
+        assert_eq!(lines, ["{\"text\":\"héllo\"}", "{\"id\":2}", "{\"id\":3}"]);
+        assert_eq!(splitter.finish(), None);
+
+        assert!(splitter.push(b"{\"id\":4,\"result\":{}").is_empty());
+        assert_eq!(splitter.finish().as_deref(), Some("{\"id\":4,\"result\":{}"));
+    }
+}
@@ -0,0 +1,83 @@
+//! Country filtering for the Cloudflare transport (`[geo_filter]`).
+//!
+//! Cloudflare tags every proxied request with the visitor's country in
+//! `CF-IPCountry`: an ISO 3166-1 alpha-2 code, `XX` when unknown, and `T1`
+//! for Tor. Behind `cloudflared` the bridge can refuse requests from
+//! unexpected countries before routing them. On direct transports anyone can
+//! send the header, so the runner only enables the filter for `cloudflare`.
+
+use crate::common_config::GeoFilterConfig;
+
+/// Header Cloudflare puts the visitor's country in.
+pub const COUNTRY_HEADER: &str = "CF-IPCountry";
+
+/// Header Cloudflare puts the visitor's address in; the peer is `cloudflared`.
+pub const VISITOR_IP_HEADER: &str = "CF-Connecting-IP";
+
+/// Allow and deny lists from `[geo_filter]`, normalized to upper case.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoFilter {
+    allow: Vec<String>,
+    deny: Vec<String>,
+    allow_unknown: bool,
+}
+
+impl From<&GeoFilterConfig> for GeoFilter {
+    fn from(config: &GeoFilterConfig) -> Self {
+        let upper = |codes: &[String]| codes.iter().map(|c| c.to_ascii_uppercase()).collect();
+        Self {
+            allow: upper(&config.allow_countries),
+            deny: upper(&config.deny_countries),
+            allow_unknown: config.allow_unknown,
+        }
+    }
+}
+
+impl GeoFilter {
+    /// Check a request from `country` (the `CF-IPCountry` value, if any).
+    /// The error says why it is refused.
+    pub fn check(&self, country: Option<&str>) -> Result<(), String> {
+        let country = country.map(str::trim).filter(|c| !c.is_empty()).map(str::to_ascii_uppercase);
+        let Some(country) = country.filter(|c| c != "XX") else {
+            return if self.allow_unknown { Ok(()) } else { Err("country unknown".to_string()) };
+        };
+        if self.deny.contains(&country) {
+            return Err(format!("country {} is denied", country));
+        }
+        if !self.allow.is_empty() && !self.allow.contains(&country) {
+            return Err(format!("country {} is not allowed", country));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(allow: &[&str], deny: &[&str], allow_unknown: bool) -> GeoFilter {
+        GeoFilter::from(&GeoFilterConfig {
+            allow_countries: allow.iter().map(|c| c.to_string()).collect(),
+            deny_countries: deny.iter().map(|c| c.to_string()).collect(),
+            allow_unknown,
+        })
+    }
+
+    #[test]
+    fn allow_list_admits_only_its_countries() {
+        let geo = filter(&["de", "NL"], &[], false);
+        assert_eq!(geo.check(Some("DE")), Ok(()));
+        assert_eq!(geo.check(Some("nl")), Ok(()));
+        assert_eq!(geo.check(Some("US")), Err("country US is not allowed".into()));
+        assert_eq!(geo.check(Some("XX")), Err("country unknown".into()));
+        assert_eq!(geo.check(None), Err("country unknown".into()));
+    }
+
+    #[test]
+    fn deny_list_refuses_its_countries() {
+        let geo = filter(&[], &["T1"], true);
+        assert_eq!(geo.check(Some("T1")), Err("country T1 is denied".into()));
+        assert_eq!(geo.check(Some("FR")), Ok(()));
+        assert_eq!(geo.check(None), Ok(()));
+    }
+}
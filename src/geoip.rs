@@ -0,0 +1,166 @@
+//! Country and network lookups for connecting addresses (`[geoip]`).
+//!
+//! With local MaxMind databases (GeoLite2-Country and/or GeoLite2-ASN, or
+//! the commercial equivalents) configured, the bridge adds the country and
+//! autonomous system of each connecting address to its connection log and to
+//! repeated-failure alerts, so scanning from hosting networks abroad stands
+//! out from your own devices. Lookups are local; no address leaves the
+//! machine. Private, loopback and Tailscale (CGNAT) addresses are reported as
+//! such without a lookup.
+
+use anyhow::{Context, Result};
+use maxminddb::{geoip2, Reader};
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::common_config::GeoIpConfig;
+
+/// Where an address is, as far as the databases know.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Location {
+    /// ISO 3166-1 alpha-2 code
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+    /// Organization of the autonomous system
+    pub as_org: Option<String>,
+    /// A private, loopback, link-local or CGNAT address; nothing was looked up
+    pub private: bool,
+}
+
+impl Location {
+    /// Neither a country nor an autonomous system is known.
+    pub fn is_unknown(&self) -> bool {
+        !self.private && self.country.is_none() && self.asn.is_none()
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.private {
+            return write!(f, "private network");
+        }
+        let mut parts = Vec::new();
+        if let Some(ref country) = self.country {
+            parts.push(country.clone());
+        }
+        match (self.asn, self.as_org.as_deref()) {
+            (Some(asn), Some(org)) => parts.push(format!("AS{} {}", asn, org)),
+            (Some(asn), None) => parts.push(format!("AS{}", asn)),
+            _ => {}
+        }
+        if parts.is_empty() {
+            write!(f, "unknown location")
+        } else {
+            write!(f, "{}", parts.join(", "))
+        }
+    }
+}
+
+/// The databases from `[geoip]`, loaded into memory.
+pub struct GeoIp {
+    country: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+impl GeoIp {
+    /// Load the databases `config` names.
+    pub fn open(config: &GeoIpConfig) -> Result<Self> {
+        let open = |path: &std::path::Path| {
+            Reader::open_readfile(path).with_context(|| format!("Failed to open GeoIP database {}", path.display()))
+        };
+        Ok(Self {
+            country: config.country_db.as_deref().map(open).transpose()?,
+            asn: config.asn_db.as_deref().map(open).transpose()?,
+        })
+    }
+
+    /// Country and autonomous system of `ip`; fields the databases don't
+    /// have are `None`.
+    pub fn lookup(&self, ip: IpAddr) -> Location {
+        if is_private(ip) {
+            return Location { private: true, ..Location::default() };
+        }
+        let mut location = Location::default();
+        if let Some(ref reader) = self.country {
+            if let Ok(record) = reader.lookup::<geoip2::Country>(ip) {
+                location.country = record
+                    .country
+                    .or(record.registered_country)
+                    .and_then(|c| c.iso_code)
+                    .map(String::from);
+            }
+        }
+        if let Some(ref reader) = self.asn {
+            if let Ok(record) = reader.lookup::<geoip2::Asn>(ip) {
+                location.asn = record.autonomous_system_number;
+                location.as_org = record.autonomous_system_organization.map(String::from);
+            }
+        }
+        location
+    }
+
+    /// ` (DE, AS3320 Deutsche Telekom AG)` for appending to a log line about
+    /// `ip`, or an empty string when nothing is known.
+    pub fn describe(&self, ip: &str) -> String {
+        match ip.parse().map(|ip| self.lookup(ip)) {
+            Ok(location) if !location.is_unknown() => format!(" ({})", location),
+            _ => String::new(),
+        }
+    }
+}
+
+/// [`GeoIp::describe`] when `geoip` is configured.
+pub fn describe(geoip: Option<&GeoIp>, ip: &str) -> String {
+    geoip.map(|g| g.describe(ip)).unwrap_or_default()
+}
+
+/// Addresses no database places: private, loopback, link-local, unique
+/// local, and the CGNAT range Tailscale uses.
+fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            v4.is_private() || v4.is_loopback() || v4.is_link_local() || v4.is_unspecified() || (a == 100 && (64..128).contains(&b))
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_private(IpAddr::V4(v4));
+            }
+            let first = v6.segments()[0];
+            v6.is_loopback() || v6.is_unspecified() || (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_and_tailscale_addresses_are_not_looked_up() {
+        let geoip = GeoIp::open(&GeoIpConfig::default()).unwrap();
+        for ip in ["192.168.1.20", "10.0.0.1", "127.0.0.1", "100.101.102.103", "fd7a:115c:a1e0::1", "::ffff:10.1.2.3"] {
+            assert!(geoip.lookup(ip.parse().unwrap()).private, "{}", ip);
+            assert_eq!(geoip.describe(ip), " (private network)");
+        }
+        assert!(!geoip.lookup("203.0.113.7".parse().unwrap()).private);
+        assert_eq!(geoip.describe("203.0.113.7"), "");
+        assert_eq!(describe(None, "192.168.1.20"), "");
+    }
+
+    #[test]
+    fn location_reads_country_then_network() {
+        let full = Location { country: Some("DE".into()), asn: Some(3320), as_org: Some("Deutsche Telekom AG".into()), private: false };
+        assert_eq!(full.to_string(), "DE, AS3320 Deutsche Telekom AG");
+        let asn_only = Location { asn: Some(14061), ..Location::default() };
+        assert_eq!(asn_only.to_string(), "AS14061");
+        assert!(Location::default().is_unknown());
+    }
+
+    #[test]
+    fn missing_database_is_an_error() {
+        let config = GeoIpConfig { country_db: Some("/nonexistent/GeoLite2-Country.mmdb".into()), asn_db: None };
+        let err = GeoIp::open(&config).err().unwrap();
+        assert!(format!("{:#}", err).contains("GeoLite2-Country.mmdb"));
+    }
+}
@@ -0,0 +1,235 @@
+//! `bridge start --insecure-dev`: plaintext, unauthenticated development mode.
+//!
+//! For testing against an emulator or simulator on the same machine. The
+//! bridge serves the local transport over `ws://` with TLS and token
+//! authentication both off, bound to `127.0.0.1` unless `--bind` says
+//! otherwise. Nothing is written to `common.toml`: [`apply`] changes the
+//! loaded config in memory only, and marks it with
+//! [`CommonConfig::insecure_dev`], which [`crate::runner::run_bridge`]
+//! reads. The pairing URL carries `insecure=1` and the pairing response
+//! `"insecureDev": true`, so the app can tell the user, and a warning is
+//! logged every [`REMINDER_INTERVAL`] while it runs.
+//!
+//! `bridge start --adb-reverse` serves the same way on `127.0.0.1`, keeping
+//! the auth token unless `--insecure-dev` is given too, and forwards the
+//! port on connected Android devices (see [`crate::adb`]).
+
+use anyhow::Result;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::common_config::{CommonConfig, TransportConfig};
+use crate::tui::events::{AppEvent, BridgeEvent};
+
+/// The transport dev mode serves.
+pub const TRANSPORT: &str = "local";
+
+/// Default bind address: reachable from emulators and simulators on this
+/// machine only.
+pub const DEFAULT_BIND: &str = "127.0.0.1";
+
+/// How often the running bridge repeats its warning.
+pub const REMINDER_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Port of the local transport when `common.toml` doesn't set one.
+const DEFAULT_PORT: u16 = 8765;
+
+/// How `bridge start` serves without the TUI.
+#[derive(Debug, Clone, Default)]
+pub struct StartOptions {
+    /// `--insecure-dev`: no auth either
+    pub insecure: bool,
+    /// `--adb-reverse`: forward the port on connected Android devices
+    pub adb_reverse: bool,
+    /// `--bind`: listen here instead of [`DEFAULT_BIND`]
+    pub bind: Option<String>,
+    /// `--agent-command`: run this instead of `agent_command` from `common.toml`
+    pub agent_command: Option<String>,
+}
+
+/// Turn `config` into the dev-mode config: [`serve_plaintext`], and
+/// everything that relies on a token (admin endpoints, device and user
+/// tokens, session tokens, auto-lock) or waits on the TUI (pairing approval)
+/// off.
+pub fn apply(config: &mut CommonConfig, bind: Option<String>, agent_command: Option<String>) {
+    serve_plaintext(config, bind, agent_command);
+    config.admin_token.clear();
+    config.devices.clear();
+    config.users.clear();
+    config.session_tokens = None;
+    config.auto_lock = None;
+    config.pairing_approval = None;
+    config.insecure_dev = true;
+}
+
+/// Serve only the local transport, without TLS, bound to and advertised on
+/// `bind` (default [`DEFAULT_BIND`]). Auth stays as configured.
+pub fn serve_plaintext(config: &mut CommonConfig, bind: Option<String>, agent_command: Option<String>) {
+    let port = config.transports.get(TRANSPORT).and_then(|t| t.port);
+    config.transports.clear();
+    config.transports.insert(
+        TRANSPORT.to_string(),
+        TransportConfig { enabled: true, port, tls: Some(false), ..TransportConfig::default() },
+    );
+    let bind = bind.unwrap_or_else(|| DEFAULT_BIND.to_string());
+    // The pairing URL names the address listened on, unless that's every
+    // interface; then the LAN address is advertised as usual.
+    if !bind.parse::<IpAddr>().is_ok_and(|ip| ip.is_unspecified()) {
+        config.advertise_addr = Some(bind.clone());
+    }
+    config.bind_address = Some(bind);
+    if agent_command.is_some() {
+        config.agent_command = agent_command;
+    }
+}
+
+/// Whether `addr` only accepts connections from this machine.
+pub fn is_loopback(addr: &str) -> bool {
+    addr == "localhost" || addr.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// The warning shown at start, boxed so it stands out in a scrolling log.
+pub fn banner(bind: &str) -> String {
+    let mut lines = vec![
+        "⚠️  INSECURE DEV MODE — for local emulator testing only".to_string(),
+        "   No TLS: traffic is plaintext (ws://)".to_string(),
+        "   No auth: anyone who can reach the port controls the agent".to_string(),
+    ];
+    if !is_loopback(bind) {
+        lines.push(format!("   Bound to {}: reachable from other machines!", bind));
+    }
+    let rule = "━".repeat(64);
+    format!("{rule}\n{}\n{rule}", lines.join("\n"))
+}
+
+/// Log the dev-mode warning every [`REMINDER_INTERVAL`]. Never resolves.
+pub async fn remind(bind: String) {
+    let mut interval = tokio::time::interval(REMINDER_INTERVAL);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        warn!("⚠️  Insecure dev mode: no TLS, no auth, listening on {}", bind);
+    }
+}
+
+/// Run the bridge as `options` say on `config`, without the TUI, printing
+/// the pairing QR code to stderr, until Ctrl-C.
+pub async fn run(mut config: CommonConfig, options: StartOptions) -> Result<()> {
+    if options.insecure {
+        apply(&mut config, options.bind, options.agent_command);
+    } else {
+        serve_plaintext(&mut config, options.bind, options.agent_command);
+    }
+    config.ensure_agent_id();
+    config.ensure_auth_token();
+    let bind = config.bind_address.clone().unwrap_or_else(|| DEFAULT_BIND.to_string());
+    if options.insecure {
+        eprintln!("{}", banner(&bind));
+    }
+
+    // Forwarded until the bridge stops; devices connecting later are added.
+    let adb = if options.adb_reverse {
+        let port = config.transports[TRANSPORT].port.unwrap_or(DEFAULT_PORT);
+        let guard = tokio::task::spawn_blocking(move || crate::adb::reverse_all(port)).await??;
+        eprintln!("📱 Forwarding port {} on {}", port, guard.serials().join(", "));
+        let guard = Arc::new(Mutex::new(guard));
+        let watched = Arc::clone(&guard);
+        let watcher = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(crate::adb::RECHECK_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let watched = Arc::clone(&watched);
+                let refreshed = tokio::task::spawn_blocking(move || watched.lock().unwrap_or_else(|e| e.into_inner()).refresh()).await;
+                if let Ok(Err(e)) = refreshed {
+                    warn!("Failed to list Android devices: {:#}", e);
+                }
+            }
+        });
+        Some((guard, watcher))
+    } else {
+        None
+    };
+
+    let (event_tx, mut event_rx) = mpsc::channel::<AppEvent>(64);
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        let _ = tokio::signal::ctrl_c().await;
+        let _ = shutdown_tx.send(());
+    });
+    tokio::spawn(async move {
+        while let Some(event) = event_rx.recv().await {
+            if let AppEvent::Bridge(BridgeEvent::PairingUrlReady { url, .. }) = event {
+                match crate::qr::render_pairing_qr(&url) {
+                    Ok((qr, _)) => eprintln!("{}", qr),
+                    Err(e) => warn!("Failed to render the QR code: {:#}", e),
+                }
+                eprintln!("Pairing URL: {}", url);
+            }
+        }
+    });
+    let result = crate::runner::run_bridge(config, TRANSPORT.to_string(), event_tx, shutdown_rx).await;
+
+    // The watcher holds the guard; it has to be gone for the forwards to be removed.
+    if let Some((guard, watcher)) = adb {
+        watcher.abort();
+        let _ = watcher.await;
+        drop(guard);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dev_mode_serves_plaintext_on_loopback_only() {
+        let mut config = CommonConfig { admin_token: "admin-token-for-tests".into(), ..CommonConfig::default() };
+        config.transports.insert(
+            TRANSPORT.into(),
+            TransportConfig { enabled: true, port: Some(9000), tls: Some(true), ..TransportConfig::default() },
+        );
+        config.transports.insert("cloudflare".into(), TransportConfig { enabled: true, ..TransportConfig::default() });
+
+        apply(&mut config, None, Some("echo-agent".into()));
+        assert!(config.insecure_dev);
+        assert_eq!(config.transports.len(), 1);
+        let local = &config.transports[TRANSPORT];
+        assert_eq!((local.port, local.tls), (Some(9000), Some(false)));
+        assert_eq!(config.bind_address.as_deref(), Some(DEFAULT_BIND));
+        assert_eq!(config.advertise_addr.as_deref(), Some(DEFAULT_BIND));
+        assert_eq!(config.agent_command.as_deref(), Some("echo-agent"));
+        assert!(config.admin_token.is_empty());
+
+        let mut adb = CommonConfig { admin_token: "admin-token-for-tests".into(), ..CommonConfig::default() };
+        serve_plaintext(&mut adb, None, None);
+        assert!(!adb.insecure_dev, "--adb-reverse alone keeps auth");
+        assert_eq!(adb.transports[TRANSPORT].tls, Some(false));
+        assert_eq!(adb.advertise_addr.as_deref(), Some(DEFAULT_BIND));
+        assert!(!adb.admin_token.is_empty());
+
+        let mut everywhere = CommonConfig::default();
+        apply(&mut everywhere, Some("0.0.0.0".into()), None);
+        assert_eq!(everywhere.advertise_addr, None, "the LAN address is advertised");
+
+        assert!(is_loopback("127.0.0.1") && is_loopback("::1") && is_loopback("localhost"));
+        assert!(!is_loopback("0.0.0.0"));
+        assert!(!banner(DEFAULT_BIND).contains("other machines"));
+        assert!(banner("0.0.0.0").contains("other machines"));
+    }
+
+    #[test]
+    fn dev_mode_is_never_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = CommonConfig::default();
+        apply(&mut config, None, None);
+        config.save_to_dir(dir.path()).unwrap();
+        let saved = std::fs::read_to_string(dir.path().join("common.toml")).unwrap();
+        assert!(!saved.contains("insecure"), "{saved}");
+        assert!(!CommonConfig::load_from_dir(dir.path()).unwrap().insecure_dev);
+    }
+}
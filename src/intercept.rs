@@ -0,0 +1,325 @@
+//! Answering a reconnecting client's handshake from cached responses, so a
+//! reused agent is not initialized twice and keeps its session.
+//!
+//! Clients often send `initialize`, a notification and `session/new` (or
+//! `session/load`) back-to-back, before the bridge has answered any of them.
+//! [`Intercept`] looks at the client's messages one at a time: the handshake
+//! requests it can answer from the cache are answered, and everything else
+//! is queued. Once interception is over, the queue is forwarded to the agent
+//! in arrival order, ahead of anything the client sends later, so no message
+//! is dropped or overtakes an earlier one.
+//!
+//! The method names and limits are ACP's unless an `[[intercepts]]` entry
+//! of `common.toml` matches the agent (see [`InterceptConfig`]). With
+//! `resume = "load"`, a session load is not answered from the cache when the
+//! agent advertises `loadSession`: the bridge sends the agent a load of its
+//! own session instead, so the agent answers and replays its actual state.
+
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::common_config::{InterceptConfig, SessionResume};
+
+/// The first `[[intercepts]]` entry whose `agent` is part of `agent_command`.
+pub fn for_agent<'a>(intercepts: &'a [InterceptConfig], agent_command: &str) -> Option<&'a InterceptConfig> {
+    intercepts
+        .iter()
+        .find(|i| i.agent.as_deref().is_none_or(|agent| agent_command.contains(agent)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for `initialize`, answered with the cached response.
+    Initialize,
+    /// Waiting for a session request, answered with the cached session.
+    Session,
+    /// Everything from here on goes to the agent.
+    Done,
+}
+
+/// The intercept state machine of one connection.
+#[derive(Debug)]
+pub struct Intercept {
+    config: InterceptConfig,
+    state: State,
+    cached_init: Option<String>,
+    cached_session: Option<String>,
+    queued: Vec<String>,
+    skipped: usize,
+    initialize: bool,
+    /// `Some(true)` once a new session was answered, `Some(false)` for a loaded one.
+    session: Option<bool>,
+    /// Whether session loads go to the agent (`resume = "load"` and `loadSession`).
+    agent_loads: bool,
+    loaded_by_agent: bool,
+}
+
+impl Intercept {
+    /// Intercept `initialize` if there is a cached response to it, then the
+    /// session request if there is a cached session.
+    pub fn new(config: InterceptConfig, cached_init: Option<String>, cached_session: Option<String>) -> Self {
+        let agent_loads = config.resume == SessionResume::Load && cached_init.as_deref().is_some_and(advertises_load_session);
+        let mut intercept = Self {
+            config,
+            state: State::Initialize,
+            cached_init,
+            cached_session,
+            queued: Vec::new(),
+            skipped: 0,
+            initialize: false,
+            session: None,
+            agent_loads,
+            loaded_by_agent: false,
+        };
+        if intercept.cached_init.is_none() {
+            intercept.state = intercept.after_initialize();
+        }
+        intercept
+    }
+
+    /// Whether interception is over and the rest goes to the agent.
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Whether `initialize` was answered from the cache.
+    pub fn initialize_intercepted(&self) -> bool {
+        self.initialize
+    }
+
+    /// `Some(true)` if a new session request was answered from the cache,
+    /// `Some(false)` for a session load, answered or sent to the agent.
+    pub fn session_intercepted(&self) -> Option<bool> {
+        self.session
+    }
+
+    /// Whether the session load was sent to the agent rather than answered
+    /// from the cache.
+    pub fn session_loaded_by_agent(&self) -> bool {
+        self.loaded_by_agent
+    }
+
+    /// Take one client message; returns the replies to send to the client.
+    /// Messages not answered here are queued for the agent.
+    pub fn feed(&mut self, message: String) -> Vec<String> {
+        let request: Value = serde_json::from_str(&message).unwrap_or_default();
+        let method = request.get("method").and_then(|m| m.as_str());
+        let id = request.get("id").cloned();
+
+        if self.state == State::Initialize {
+            if let (true, Some(id)) = (self.config.is_initialize(method), &id) {
+                if let Some(reply) = self.cached_init.as_deref().and_then(|cached| with_id(cached, id)) {
+                    info!("🔄 Intercepting initialize request (id={})", id);
+                    self.initialize = true;
+                    self.state = self.after_initialize();
+                    return vec![reply];
+                }
+            }
+            warn!("⚠️  First message was not initialize (method={:?}), proceeding normally", method);
+            self.state = self.after_initialize();
+        }
+
+        if self.state == State::Session {
+            let new_session = self.config.is_new_session(method);
+            match (method, &id) {
+                (Some(method), Some(id)) if !new_session && self.agent_loads && self.config.is_load_session(Some(method)) => {
+                    let session_id = self.cached_session.as_deref().and_then(session_id);
+                    info!("🔄 Loading session {:?} on the agent for {} request (id={})", session_id, method, id);
+                    let mut load = request.clone();
+                    if let (Some(session_id), Some(params)) = (session_id, load.get_mut("params").and_then(|p| p.as_object_mut())) {
+                        params.insert("sessionId".to_string(), Value::String(session_id));
+                    }
+                    self.queued.push(load.to_string());
+                    self.session = Some(false);
+                    self.loaded_by_agent = true;
+                    self.state = State::Done;
+                    return Vec::new();
+                }
+                (Some(method), Some(id)) if new_session || self.config.is_load_session(Some(method)) => {
+                    if let Some(reply) = self.cached_session.as_deref().and_then(|cached| with_id(cached, id)) {
+                        info!("🔄 Intercepting {} request (id={})", method, id);
+                        self.session = Some(new_session);
+                        self.state = State::Done;
+                        return vec![reply];
+                    }
+                    self.state = State::Done;
+                }
+                // The agent was initialized by an earlier connection, but its
+                // response wasn't recognized and cached: answer minimally
+                (Some(_), Some(id)) if self.config.is_initialize(method) => {
+                    info!("📨 Handling uncached initialize during session intercept (id={})", id);
+                    self.skip();
+                    let reply = serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": self.config.synthetic_initialize });
+                    return vec![reply.to_string()];
+                }
+                (Some(method), None) => {
+                    info!("📨 Queued notification during session intercept: {}", method);
+                    self.queued.push(message);
+                    self.skip();
+                    return Vec::new();
+                }
+                _ => {
+                    warn!("⚠️  Message is not a session request (method={:?}), proceeding normally", method);
+                    self.state = State::Done;
+                }
+            }
+        }
+
+        self.queued.push(message);
+        Vec::new()
+    }
+
+    /// The messages for the agent, in arrival order.
+    pub fn into_queued(self) -> Vec<String> {
+        self.queued
+    }
+
+    fn after_initialize(&self) -> State {
+        if self.cached_session.is_some() { State::Session } else { State::Done }
+    }
+
+    fn skip(&mut self) {
+        self.skipped += 1;
+        if self.skipped >= self.config.max_skipped {
+            warn!("⚠️  Too many messages before session request, giving up");
+            self.state = State::Done;
+        }
+    }
+}
+
+/// Whether an `initialize` response lists the `loadSession` agent capability.
+fn advertises_load_session(response: &str) -> bool {
+    serde_json::from_str::<Value>(response)
+        .ok()
+        .and_then(|v| v.pointer("/result/agentCapabilities/loadSession").and_then(|l| l.as_bool()))
+        .unwrap_or(false)
+}
+
+/// The `sessionId` of a session response.
+fn session_id(response: &str) -> Option<String> {
+    let v: Value = serde_json::from_str(response).ok()?;
+    v.pointer("/result/sessionId").and_then(|s| s.as_str()).map(str::to_string)
+}
+
+/// `cached` with its `id` replaced by the client's.
+fn with_id(cached: &str, id: &Value) -> Option<String> {
+    let mut response: Value = serde_json::from_str(cached).ok()?;
+    response["id"] = id.clone();
+    Some(response.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INIT: &str = r#"{"jsonrpc":"2.0","id":0,"result":{"protocolVersion":1}}"#;
+    const SESSION: &str = r#"{"jsonrpc":"2.0","id":0,"result":{"sessionId":"s1"}}"#;
+
+    fn request(id: u64, method: &str) -> String {
+        serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": {} }).to_string()
+    }
+
+    fn acp(cached_init: Option<&str>, cached_session: Option<&str>) -> Intercept {
+        Intercept::new(InterceptConfig::default(), cached_init.map(str::to_string), cached_session.map(str::to_string))
+    }
+
+    #[test]
+    fn back_to_back_handshake_is_answered_and_the_rest_queued_in_order() {
+        let mut intercept = acp(Some(INIT), Some(SESSION));
+        let reply = intercept.feed(request(1, "initialize"));
+        assert!(reply[0].contains(r#""id":1"#) && reply[0].contains("protocolVersion"));
+        let notification = r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#.to_string();
+        assert!(intercept.feed(notification.clone()).is_empty());
+        let reply = intercept.feed(request(2, "session/load"));
+        assert!(reply[0].contains(r#""id":2"#) && reply[0].contains("s1"));
+        assert!(intercept.is_done());
+        assert!(intercept.initialize_intercepted());
+        assert_eq!(intercept.session_intercepted(), Some(false));
+        assert_eq!(intercept.into_queued(), vec![notification]);
+    }
+
+    #[test]
+    fn unexpected_messages_are_queued_not_dropped() {
+        let mut intercept = acp(Some(INIT), Some(SESSION));
+        assert!(intercept.feed(request(1, "session/prompt")).is_empty());
+        assert!(intercept.is_done());
+        assert!(!intercept.initialize_intercepted());
+        assert_eq!(intercept.into_queued(), vec![request(1, "session/prompt")]);
+
+        // Uncached initialize gets a synthetic answer while waiting for the session
+        let mut intercept = acp(None, Some(SESSION));
+        assert!(intercept.feed(request(1, "initialize"))[0].contains(r#""name":"bridge""#));
+        assert!(intercept.feed(request(2, "session/new"))[0].contains("s1"));
+        assert_eq!(intercept.session_intercepted(), Some(true));
+
+        let mut intercept = acp(Some(INIT), None);
+        intercept.feed(request(1, "initialize"));
+        assert!(intercept.is_done());
+        assert!(acp(None, None).is_done());
+
+        let mut intercept = acp(None, Some(SESSION));
+        for _ in 0..5 {
+            intercept.feed(r#"{"method":"n"}"#.into());
+        }
+        assert!(intercept.is_done());
+        assert_eq!(intercept.into_queued().len(), 5);
+    }
+
+    #[test]
+    fn method_names_and_limits_follow_the_config() {
+        let config = InterceptConfig {
+            agent: Some("my-agent".into()),
+            initialize: vec!["init".into()],
+            new_session: vec!["session/create".into()],
+            load_session: vec!["session/resume".into()],
+            max_skipped: 2,
+            synthetic_initialize: serde_json::json!({ "protocolVersion": 2 }),
+            resume: SessionResume::Cache,
+        };
+        let intercepts = [config.clone()];
+        assert_eq!(for_agent(&intercepts, "npx my-agent --acp"), Some(&config));
+        assert_eq!(for_agent(&intercepts, "claude"), None);
+
+        let mut intercept = Intercept::new(config.clone(), Some(INIT.into()), Some(SESSION.into()));
+        assert_eq!(intercept.feed(request(1, "init")).len(), 1);
+        assert_eq!(intercept.feed(request(2, "session/resume")).len(), 1);
+        assert_eq!(intercept.session_intercepted(), Some(false));
+
+        let mut intercept = Intercept::new(config.clone(), None, Some(SESSION.into()));
+        assert!(intercept.feed(request(1, "init"))[0].contains(r#""protocolVersion":2"#));
+        assert!(intercept.feed(request(2, "session/new")).is_empty(), "ACP names don't apply");
+        assert!(intercept.is_done());
+
+        let mut intercept = Intercept::new(config, None, Some(SESSION.into()));
+        intercept.feed(r#"{"method":"a"}"#.into());
+        intercept.feed(r#"{"method":"b"}"#.into());
+        assert!(intercept.is_done());
+    }
+
+    #[test]
+    fn agents_that_load_sessions_load_their_own() {
+        let load = InterceptConfig { resume: SessionResume::Load, ..InterceptConfig::default() };
+        let loads = r#"{"jsonrpc":"2.0","id":0,"result":{"agentCapabilities":{"loadSession":true}}}"#;
+        let client_load = serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "session/load", "params": { "sessionId": "old", "cwd": "/w" } });
+
+        let mut intercept = Intercept::new(load.clone(), Some(loads.into()), Some(SESSION.into()));
+        intercept.feed(request(1, "initialize"));
+        assert!(intercept.feed(client_load.to_string()).is_empty());
+        assert!(intercept.is_done() && intercept.session_loaded_by_agent());
+        assert_eq!(intercept.session_intercepted(), Some(false));
+        let queued: Value = serde_json::from_str(&intercept.into_queued()[0]).unwrap();
+        assert_eq!(queued["id"], 2);
+        assert_eq!(queued["params"], serde_json::json!({ "sessionId": "s1", "cwd": "/w" }));
+
+        // session/new still reuses the cached session
+        let mut intercept = Intercept::new(load.clone(), Some(loads.into()), Some(SESSION.into()));
+        intercept.feed(request(1, "initialize"));
+        assert!(intercept.feed(request(2, "session/new"))[0].contains("s1"));
+
+        // Without loadSession the cache answers
+        let mut intercept = Intercept::new(load, Some(INIT.into()), Some(SESSION.into()));
+        intercept.feed(request(1, "initialize"));
+        assert!(intercept.feed(client_load.to_string())[0].contains("s1"));
+        assert!(!intercept.session_loaded_by_agent());
+    }
+}
@@ -0,0 +1,173 @@
+//! OS keystore access for secrets that shouldn't live on disk in plain PEM.
+//!
+//! Uses the platform CLI (`security` on macOS, `secret-tool` on Linux) so no
+//! native bindings are needed. Callers fall back to file storage when
+//! [`is_available`] returns `false`.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use tracing::debug;
+
+/// Service name under which all bridge secrets are filed.
+const SERVICE: &str = "aptove-bridge";
+
+/// Returns `true` if a supported keystore CLI is present on this machine.
+pub fn is_available() -> bool {
+    let probe = if cfg!(target_os = "macos") {
+        Command::new("security").arg("list-keychains").output()
+    } else if cfg!(target_os = "linux") {
+        Command::new("secret-tool").arg("--version").output()
+    } else {
+        return false;
+    };
+    probe.map(|o| o.status.success()).unwrap_or(false)
+}
+
+/// Store (or replace) the secret for `account`, and read it back to check
+/// the keystore kept it whole.
+///
+/// The secret is base64-encoded first: `security -w` prints multi-line values
+/// as hex, and a single-line value round-trips identically on every backend.
+pub fn store(account: &str, secret: &str) -> Result<()> {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(secret);
+    let Some((program, args, input)) = store_command(account, &encoded) else {
+        anyhow::bail!("No system keystore supported on this platform");
+    };
+    let mut command = Command::new(program);
+    command.args(&args);
+    let status = run_with_stdin(command, &input).with_context(|| format!("Failed to run '{} {}'", program, args[0]))?;
+
+    if !status.status.success() {
+        anyhow::bail!(
+            "Keystore rejected secret for '{}': {}",
+            account,
+            String::from_utf8_lossy(&status.stderr).trim()
+        );
+    }
+    if load(account)?.as_deref() != Some(secret) {
+        anyhow::bail!("Keystore did not keep the secret for '{}' intact", account);
+    }
+    debug!("Stored secret '{}' in system keystore", account);
+    Ok(())
+}
+
+/// The program, arguments and stdin that store `encoded` for `account`.
+/// The secret only ever goes to stdin, never to argv, where any local user
+/// could read it from the process list.
+fn store_command(account: &str, encoded: &str) -> Option<(&'static str, Vec<String>, String)> {
+    if cfg!(target_os = "macos") {
+        // `security -i` reads commands from stdin, without prompting on the
+        // terminal; -U updates an existing item instead of failing with a
+        // duplicate error.
+        let command = format!("add-generic-password -U -s {} -a {} -w {}\n", quote(SERVICE), quote(account), encoded);
+        Some(("security", vec!["-i".into()], command))
+    } else if cfg!(target_os = "linux") {
+        // secret-tool reads the secret from stdin.
+        let label = format!("{} ({})", SERVICE, account);
+        let args = ["store", "--label", &label, "service", SERVICE, "account", account];
+        Some(("secret-tool", args.map(String::from).to_vec(), encoded.to_string()))
+    } else {
+        None
+    }
+}
+
+/// `value` as one double-quoted word of a `security -i` command line.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Run `command` with `input` on its stdin, capturing stderr.
+fn run_with_stdin(mut command: Command, input: &str) -> std::io::Result<std::process::Output> {
+    let mut child = command.stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::piped()).spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes())?;
+    }
+    child.wait_with_output()
+}
+
+/// Load the secret for `account`, or `None` if the keystore has no such
+/// entry. A keystore that can't be read, such as a locked keychain, is an
+/// error, not a missing entry.
+pub fn load(account: &str) -> Result<Option<String>> {
+    let output = if cfg!(target_os = "macos") {
+        Command::new("security")
+            .args(["find-generic-password", "-s", SERVICE, "-a", account, "-w"])
+            .output()
+            .context("Failed to run 'security find-generic-password'")?
+    } else if cfg!(target_os = "linux") {
+        Command::new("secret-tool")
+            .args(["lookup", "service", SERVICE, "account", account])
+            .output()
+            .context("Failed to run 'secret-tool lookup'")?
+    } else {
+        return Ok(None);
+    };
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if is_not_found(output.status.code(), &stderr) {
+            return Ok(None);
+        }
+        anyhow::bail!("Failed to read '{}' from the system keystore: {}", account, stderr.trim());
+    }
+    if output.stdout.is_empty() {
+        return Ok(None);
+    }
+    let encoded = String::from_utf8_lossy(&output.stdout);
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .with_context(|| format!("Keystore entry '{}' is not valid base64", account))?;
+    let secret = String::from_utf8(decoded).context("Keystore returned non-UTF-8 secret")?;
+    Ok(Some(secret))
+}
+
+/// Whether a failed lookup means the entry doesn't exist: `security` exits
+/// with `errSecItemNotFound` (44), and `secret-tool` with 1 and nothing on
+/// stderr. Anything else is the keystore failing.
+fn is_not_found(code: Option<i32>, stderr: &str) -> bool {
+    if cfg!(target_os = "macos") {
+        code == Some(44)
+    } else {
+        code == Some(1) && stderr.trim().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_is_stored_through_stdin_only() {
+        let Some((program, args, input)) = store_command("tls-key:/home/me/my \"bridge\"", "c2VjcmV0") else {
+            return;
+        };
+        assert!(args.iter().all(|arg| !arg.contains("c2VjcmV0")), "{program} {args:?}");
+        assert!(input.contains("c2VjcmV0"));
+        if program == "security" {
+            assert_eq!(args, ["-i"]);
+            assert!(input.contains(r#"-a "tls-key:/home/me/my \"bridge\"""#), "{input}");
+        }
+    }
+
+    #[test]
+    fn stdin_reaches_the_child() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "read line; test \"$line\" = c2VjcmV0 || { echo \"got $line\" >&2; exit 3; }"]);
+        let output = run_with_stdin(command, "c2VjcmV0\n").unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    #[test]
+    fn only_a_missing_entry_counts_as_not_found() {
+        if cfg!(target_os = "macos") {
+            assert!(is_not_found(Some(44), "The specified item could not be found in the keychain."));
+            assert!(!is_not_found(Some(36), "User interaction is not allowed."));
+        } else {
+            assert!(is_not_found(Some(1), ""));
+            assert!(!is_not_found(Some(1), "Cannot autolaunch D-Bus without X11 $DISPLAY"));
+        }
+        assert!(!is_not_found(None, ""));
+    }
+}
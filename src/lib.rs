@@ -3,17 +3,77 @@
 /// The version of this bridge crate, extracted at compile time from Cargo.toml.
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+pub mod adb;
+pub mod admin;
+pub mod agent_allowlist;
+pub mod agent_hooks;
 pub mod agent_pool;
+pub mod auth_failures;
+pub mod auto_lock;
+pub mod bench;
 pub mod bridge;
+pub mod bundle;
+pub mod clock_skew;
+pub mod close_reason;
 pub mod cloudflare;
 pub mod cloudflared_runner;
 pub mod common_config;
 pub mod config;
+pub mod config_migration;
+pub mod connect;
+pub mod container;
+pub mod crash_report;
+pub mod device_keys;
+pub mod device_tokens;
+pub mod env_overrides;
+pub mod error;
+pub mod framing;
+pub mod geo_filter;
+pub mod geoip;
+pub mod insecure_dev;
+pub mod intercept;
+pub mod keystore;
+pub mod line_listener;
+pub mod line_reader;
+pub mod log_file;
+pub mod memory_watchdog;
+pub mod nfc;
+pub mod output;
+pub mod output_shaping;
+pub mod pair_json;
+pub mod pair_server;
 pub mod pairing;
+pub mod power;
+pub mod preflight;
+pub mod private_file;
+pub mod proxy;
 pub mod push;
+pub mod push_action;
+pub mod push_providers;
 pub mod qr;
 pub mod rate_limiter;
 pub mod runner;
+pub mod runtime_manifest;
+pub mod sandbox;
+pub mod scheduler;
+pub mod service_token_rotation;
+pub mod session_token;
+pub mod status;
+pub mod streamable_http;
 pub mod tailscale;
+pub mod test_connection;
+#[cfg(feature = "testkit")]
+pub mod testkit;
+#[cfg(all(feature = "ble-pairing", target_os = "linux"))]
+pub mod ble_pairing;
 pub mod tls;
+pub mod top;
+pub mod transcript;
+pub mod trigger;
 pub mod tui;
+pub mod tunnel_health;
+pub mod update;
+pub mod usage;
+pub mod wake_relay;
+pub mod wire_protocol;
+pub mod worktree;
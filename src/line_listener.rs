@@ -0,0 +1,239 @@
+//! Newline-delimited JSON-RPC listeners over raw TCP and Unix sockets.
+//!
+//! Each line a client writes is one JSON-RPC message for the pooled agent, and
+//! each line the agent writes is sent back unchanged. There is no HTTP upgrade,
+//! so `nc`, `socat` or a few lines of any language are enough to drive the agent.
+//!
+//! TCP clients must authenticate with their first line:
+//!
+//! ```json
+//! {"jsonrpc":"2.0","id":0,"method":"bridge/auth","params":{"token":"<auth_token>"}}
+//! ```
+//!
+//! Unix socket clients are trusted by file permission (the socket is created
+//! with mode 0600) and skip this step. Both share the agent of the bridge's
+//! auth token with WebSocket clients.
+
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc, RwLock};
+use tracing::{debug, error, info, warn};
+
+use crate::agent_pool::AgentPool;
+use crate::common_config::ListenerConfig;
+
+/// How long a TCP client has to send `bridge/auth`.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Shared state every accepted client needs.
+#[derive(Clone)]
+struct ListenerContext {
+    auth_token: String,
+    pool: Arc<RwLock<AgentPool>>,
+    agent_command: String,
+}
+
+/// Run one listener until it fails to bind or accept.
+pub async fn serve(
+    config: ListenerConfig,
+    auth_token: String,
+    pool: Arc<RwLock<AgentPool>>,
+    agent_command: String,
+) -> Result<()> {
+    let ctx = ListenerContext { auth_token, pool, agent_command };
+    match config {
+        ListenerConfig::Tcp { address } => serve_tcp(&address, ctx).await,
+        ListenerConfig::Unix { path } => serve_unix(&path, ctx).await,
+    }
+}
+
+async fn serve_tcp(address: &str, ctx: ListenerContext) -> Result<()> {
+    let listener = TcpListener::bind(address)
+        .await
+        .with_context(|| format!("Failed to bind JSON-RPC listener to {}", address))?;
+    info!("🔌 JSON-RPC TCP listener on {}", address);
+    loop {
+        let (stream, addr) = listener.accept().await.context("Failed to accept TCP client")?;
+        info!("🔌 JSON-RPC client connected from {}", addr);
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let (reader, writer) = stream.into_split();
+            if let Err(e) = handle_client(reader, writer, true, ctx).await {
+                error!("JSON-RPC client error: {:#}", e);
+            }
+        });
+    }
+}
+
+#[cfg(unix)]
+async fn serve_unix(path: &std::path::Path, ctx: ListenerContext) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    // A socket file left behind by a previous run would make bind fail.
+    if path.exists() {
+        std::fs::remove_file(path).with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind Unix socket {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to restrict permissions on {}", path.display()))?;
+    info!("🔌 JSON-RPC Unix socket listener on {}", path.display());
+    loop {
+        let (stream, _) = listener.accept().await.context("Failed to accept Unix socket client")?;
+        info!("🔌 JSON-RPC client connected on Unix socket");
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let (reader, writer) = stream.into_split();
+            if let Err(e) = handle_client(reader, writer, false, ctx).await {
+                error!("JSON-RPC client error: {:#}", e);
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(_path: &std::path::Path, _ctx: ListenerContext) -> Result<()> {
+    anyhow::bail!("Unix socket listeners are not supported on this platform")
+}
+
+/// The `id` and token of a `bridge/auth` request; no token for any other message.
+pub(crate) fn auth_request(line: &str) -> (serde_json::Value, Option<String>) {
+    let request: serde_json::Value = serde_json::from_str(line).unwrap_or_default();
+    let id = request.get("id").cloned().unwrap_or(serde_json::Value::Null);
+    let token = (request.get("method").and_then(|m| m.as_str()) == Some("bridge/auth"))
+        .then(|| request.pointer("/params/token").and_then(|t| t.as_str()).map(str::to_string))
+        .flatten();
+    (id, token)
+}
+
+/// Answer `bridge/auth` request `id`.
+pub(crate) fn auth_reply(id: &serde_json::Value, ok: bool) -> String {
+    let reply = if ok {
+        serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": { "authenticated": true } })
+    } else {
+        serde_json::json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32001, "message": "Unauthorized" } })
+    };
+    reply.to_string()
+}
+
+/// Check a `bridge/auth` line. Returns the JSON-RPC reply and whether it succeeded.
+fn check_auth(line: &str, expected: &str) -> (String, bool) {
+    let (id, token) = auth_request(line);
+    let ok = token.is_some_and(|t| bool::from(t.as_bytes().ct_eq(expected.as_bytes())));
+    (auth_reply(&id, ok), ok)
+}
+
+async fn write_line<W: AsyncWrite + Unpin>(writer: &mut W, line: &str) -> std::io::Result<()> {
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    writer.flush().await
+}
+
+async fn handle_client<R, W>(reader: R, mut writer: W, require_auth: bool, ctx: ListenerContext) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let mut lines = BufReader::new(reader).lines();
+
+    if require_auth {
+        let first = match tokio::time::timeout(AUTH_TIMEOUT, lines.next_line()).await {
+            Ok(line) => line?.unwrap_or_default(),
+            Err(_) => {
+                warn!("⏱️  JSON-RPC client did not authenticate within {:?}", AUTH_TIMEOUT);
+                return Ok(());
+            }
+        };
+        let (reply, ok) = check_auth(&first, &ctx.auth_token);
+        write_line(&mut writer, &reply).await?;
+        if !ok {
+            warn!("🚫 JSON-RPC client rejected: invalid bridge/auth");
+            return Ok(());
+        }
+    }
+
+    let ((ws_to_agent_tx, mut agent_rx, buffered, _, _, _, output), _, slot) =
+        AgentPool::connect(&ctx.pool, &ctx.auth_token, &ctx.agent_command).await?;
+
+    // Replies the bridge answers itself (cached initialize) go only to this client.
+    let (inject_tx, mut inject_rx) = mpsc::channel::<String>(16);
+    let slot_for_writer = Arc::clone(&slot);
+    let writer_task = tokio::spawn(async move {
+        for msg in buffered {
+            if write_line(&mut writer, &msg).await.is_err() {
+                return;
+            }
+        }
+        loop {
+            let line = tokio::select! {
+                result = agent_rx.recv() => match result {
+                    Ok(line) => {
+                        slot_for_writer.capture_handshake(&line);
+                        line
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("JSON-RPC client lagged, skipped {} messages", n);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                Some(line) = inject_rx.recv() => line,
+            };
+            if let Err(e) = write_line(&mut writer, &line).await {
+                debug!("JSON-RPC client write failed: {}", e);
+                break;
+            }
+        }
+    });
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Ok(message) = serde_json::from_str::<serde_json::Value>(&line) {
+            if let Some(reply) = slot.cached_initialize_reply(&message) {
+                info!("🔄 Intercepting initialize from JSON-RPC client");
+                let _ = inject_tx.send(reply).await;
+                continue;
+            }
+            if message.get("method").and_then(|m| m.as_str()) == Some("bridge/ack") {
+                if let Some(seq) = message.pointer("/params/seq").and_then(|s| s.as_u64()) {
+                    output.ack(seq);
+                }
+                continue;
+            }
+        }
+        if ws_to_agent_tx.send(line).await.is_err() {
+            break;
+        }
+    }
+
+    writer_task.abort();
+    slot.mark_disconnected();
+    info!("🔌 JSON-RPC client disconnected, agent stays alive in pool");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auth_line_is_checked() {
+        let (reply, ok) = check_auth(r#"{"jsonrpc":"2.0","id":7,"method":"bridge/auth","params":{"token":"s3cret"}}"#, "s3cret");
+        assert!(ok);
+        assert!(reply.contains(r#""id":7"#) && reply.contains("authenticated"));
+
+        let (reply, ok) = check_auth(r#"{"jsonrpc":"2.0","id":7,"method":"bridge/auth","params":{"token":"nope"}}"#, "s3cret");
+        assert!(!ok);
+        assert!(reply.contains("-32001"));
+
+        let (_, ok) = check_auth(r#"{"jsonrpc":"2.0","id":1,"method":"initialize"}"#, "s3cret");
+        assert!(!ok, "anything but bridge/auth is rejected");
+        assert!(!check_auth("not json", "s3cret").1);
+    }
+}
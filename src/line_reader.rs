@@ -0,0 +1,198 @@
+//! Reading an agent's output line by line within a memory bound.
+//!
+//! ACP messages are newline-delimited JSON. [`LineReader`] keeps at most
+//! `max_line_bytes` of a line in memory: the rest of a longer line is read
+//! and thrown away, and the line comes back as [`Line::Truncated`] with its
+//! first bytes, so the client can be told (see [`truncation_message`]).
+//! A last line without a newline is returned at EOF, and bytes that aren't
+//! UTF-8 are replaced rather than ending the stream.
+
+use std::io;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+/// Default for `[limits] max_line_bytes`.
+pub const DEFAULT_MAX_LINE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Bytes of a truncated line kept, for logs and to find its `id`.
+const PREFIX_BYTES: usize = 4096;
+
+/// One line, without its line ending.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+    Complete(String),
+    /// A line longer than the limit: its first bytes and its full length.
+    Truncated { prefix: String, len: usize },
+}
+
+pub struct LineReader<R> {
+    reader: R,
+    max_line_bytes: usize,
+    /// The line read so far, kept here so `next_line` is cancel safe.
+    line: Vec<u8>,
+    len: usize,
+}
+
+impl<R: AsyncBufRead + Unpin> LineReader<R> {
+    pub fn new(reader: R, max_line_bytes: usize) -> Self {
+        Self { reader, max_line_bytes, line: Vec::new(), len: 0 }
+    }
+
+    /// The next line, or `None` at EOF. Cancel safe: a line that was being
+    /// read is continued by the next call.
+    pub async fn next_line(&mut self) -> io::Result<Option<Line>> {
+        loop {
+            let available = self.reader.fill_buf().await?;
+            if available.is_empty() {
+                // EOF: a final line without a newline still counts.
+                return Ok((self.len > 0).then(|| self.finish()));
+            }
+            let (chunk, done) = match available.iter().position(|&b| b == b'\n') {
+                Some(i) => (&available[..i], true),
+                None => (available, false),
+            };
+            let (line, len) = (&mut self.line, self.len);
+            let keep = if len + chunk.len() <= self.max_line_bytes {
+                chunk.len()
+            } else {
+                PREFIX_BYTES.saturating_sub(line.len()).min(chunk.len())
+            };
+            if len + chunk.len() > self.max_line_bytes && line.len() > PREFIX_BYTES {
+                line.truncate(PREFIX_BYTES);
+            }
+            line.extend_from_slice(&chunk[..keep]);
+            self.len += chunk.len();
+            let consumed = chunk.len() + usize::from(done);
+            self.reader.consume(consumed);
+            if done {
+                return Ok(Some(self.finish()));
+            }
+        }
+    }
+
+    fn finish(&mut self) -> Line {
+        let (mut line, len) = (std::mem::take(&mut self.line), std::mem::take(&mut self.len));
+        if line.last() == Some(&b'\r') && len <= self.max_line_bytes {
+            line.pop();
+        }
+        let text = String::from_utf8_lossy(&line).into_owned();
+        if len > self.max_line_bytes {
+            Line::Truncated { prefix: text, len }
+        } else {
+            Line::Complete(text)
+        }
+    }
+}
+
+/// What the client is sent instead of a line of `len` bytes over
+/// `max_line_bytes`: an error response when the line starts like a response
+/// with an `id`, so the request doesn't hang, otherwise a
+/// `bridge/agentOutputTruncated` notification.
+pub fn truncation_message(prefix: &str, len: usize, max_line_bytes: usize) -> String {
+    let message = format!(
+        "Agent message of {} bytes exceeds [limits] max_line_bytes ({}) and was dropped",
+        len, max_line_bytes
+    );
+    match top_level_id(prefix) {
+        Some(id) => serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": -32603, "message": message, "data": { "lineBytes": len, "maxLineBytes": max_line_bytes } },
+        }),
+        None => serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "bridge/agentOutputTruncated",
+            "params": { "message": message, "lineBytes": len, "maxLineBytes": max_line_bytes },
+        }),
+    }
+    .to_string()
+}
+
+/// The `id` of the JSON object `prefix` begins, if it comes before the
+/// prefix ends and the object has no `method` before it (a request from the
+/// agent isn't answered here).
+fn top_level_id(prefix: &str) -> Option<serde_json::Value> {
+    let bytes = prefix.as_bytes();
+    let (mut depth, mut i) = (0usize, 0usize);
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => depth = depth.checked_sub(1)?,
+            b'"' => {
+                let start = i + 1;
+                i = start;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                let key = prefix.get(start..i.min(bytes.len()))?;
+                let rest = prefix.get(i + 1..)?.trim_start();
+                if depth == 1 && rest.starts_with(':') {
+                    match key {
+                        "method" => return None,
+                        "id" => {
+                            let value = serde_json::Deserializer::from_str(&rest[1..])
+                                .into_iter::<serde_json::Value>()
+                                .next()?
+                                .ok()?;
+                            return (value.is_number() || value.is_string()).then_some(value);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read_all(input: &[u8], max: usize) -> Vec<Line> {
+        // A small buffer, so lines span several reads.
+        let mut reader = LineReader::new(tokio::io::BufReader::with_capacity(7, input), max);
+        let mut lines = Vec::new();
+        while let Some(line) = reader.next_line().await.unwrap() {
+            lines.push(line);
+        }
+        lines
+    }
+
+    #[tokio::test]
+    async fn long_lines_are_cut_and_last_lines_kept() {
+        let long = format!("{{\"id\":7,\"result\":\"{}\"}}", "x".repeat(100));
+        let input = [format!("short\r\n{}\n", long).as_bytes(), b"\xffok\nno newline"].concat();
+        let lines = read_all(&input, 64).await;
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], Line::Complete("short".into()));
+        assert_eq!(lines[1], Line::Truncated { prefix: long.clone(), len: long.len() });
+        assert_eq!(lines[2], Line::Complete("\u{fffd}ok".into()));
+        assert_eq!(lines[3], Line::Complete("no newline".into()));
+
+        let huge = "y".repeat(3 * PREFIX_BYTES);
+        match &read_all(huge.as_bytes(), 16).await[..] {
+            [Line::Truncated { prefix, len }] => assert_eq!((prefix.len(), *len), (PREFIX_BYTES, huge.len())),
+            other => panic!("{other:?}"),
+        }
+        assert!(read_all(b"", 16).await.is_empty());
+    }
+
+    #[test]
+    fn truncated_responses_answer_their_request() {
+        let response: serde_json::Value =
+            serde_json::from_str(&truncation_message(r#"{"jsonrpc":"2.0","id":"r-1","result":{"content":"#, 99, 10)).unwrap();
+        assert_eq!(response["id"], "r-1");
+        assert_eq!(response["error"]["data"]["lineBytes"], 99);
+
+        for prefix in [
+            r#"{"jsonrpc":"2.0","method":"session/update","params":{"id":3,"#,
+            r#"{"jsonrpc":"2.0","result":{"items":[{"id":4}"#,
+            "not json",
+        ] {
+            let v: serde_json::Value = serde_json::from_str(&truncation_message(prefix, 99, 10)).unwrap();
+            assert_eq!(v["method"], "bridge/agentOutputTruncated", "{prefix}");
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Recent bridge logs on disk, for `bridge logs`.
+//!
+//! The running bridge appends every DEBUG-and-above record to `bridge.log`
+//! in the config directory. The file is a two-segment ring: once it reaches
+//! [`MAX_SEGMENT_BYTES`] it is renamed to `bridge.log.1` (replacing the older
+//! segment) and a fresh one is started, so disk use stays bounded no matter
+//! how long the bridge runs.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+use crate::tui::log_layer::{level_to_u8, MessageVisitor};
+
+pub const LOG_FILENAME: &str = "bridge.log";
+
+/// Size of each of the two segments.
+pub const MAX_SEGMENT_BYTES: u64 = 2 * 1024 * 1024;
+
+/// Most verbose level written to the file (DEBUG).
+const FILE_LEVEL: u8 = 4;
+
+/// How often `follow` checks the file for new lines.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The older segment of the log at `path`.
+pub fn rotated_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".1");
+    PathBuf::from(name)
+}
+
+/// One log line: `<date> <time> <LEVEL> <message>`, with the message on a
+/// single line.
+pub fn format_line(timestamp: &str, level: Level, message: &str) -> String {
+    let message = message.replace('\r', "").replace('\n', " ⏎ ");
+    format!("{} {:<5} {}\n", timestamp, level.as_str(), message)
+}
+
+/// Level of a line written by [`format_line`], as in
+/// [`level_to_u8`]; `None` for anything else.
+pub fn line_level(line: &str) -> Option<u8> {
+    let level = line.split_whitespace().nth(2)?;
+    level.parse::<Level>().ok().map(level_to_u8)
+}
+
+/// Parse a `--level` argument.
+pub fn parse_level(name: &str) -> Result<u8, String> {
+    name.parse::<Level>()
+        .map(level_to_u8)
+        .map_err(|_| format!("unknown level '{}' (error, warn, info, debug or trace)", name))
+}
+
+/// The file under a [`FileLogLayer`].
+struct Segment {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    max_bytes: u64,
+}
+
+impl Segment {
+    fn open(path: &Path, max_bytes: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600));
+        }
+        let len = file.metadata()?.len();
+        Ok(Self { path: path.to_path_buf(), file, len, max_bytes })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.len > 0 && self.len + line.len() as u64 > self.max_bytes {
+            std::fs::rename(&self.path, rotated_path(&self.path))?;
+            *self = Self::open(&self.path, self.max_bytes)?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.len += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// A `tracing` layer that appends DEBUG-and-above records to the ring file.
+pub struct FileLogLayer {
+    segment: Mutex<Segment>,
+}
+
+impl FileLogLayer {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::with_max_bytes(path, MAX_SEGMENT_BYTES)
+    }
+
+    fn with_max_bytes(path: &Path, max_bytes: u64) -> io::Result<Self> {
+        Ok(Self { segment: Mutex::new(Segment::open(path, max_bytes)?) })
+    }
+
+    fn write(&self, level: Level, message: &str) {
+        let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string();
+        let line = format_line(&timestamp, level, message);
+        let mut segment = self.segment.lock().unwrap_or_else(|e| e.into_inner());
+        // Nowhere to report a failed log write; the TUI still shows the record.
+        let _ = segment.write_line(&line);
+    }
+}
+
+impl<S: Subscriber> Layer<S> for FileLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let level = *event.metadata().level();
+        if level_to_u8(level) > FILE_LEVEL {
+            return;
+        }
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        self.write(level, &visitor.message);
+    }
+}
+
+/// The last `count` lines at `min_level` or more severe from both segments,
+/// oldest first.
+pub fn read_recent(path: &Path, min_level: u8, count: usize) -> io::Result<Vec<String>> {
+    let mut lines = Vec::new();
+    for segment in [rotated_path(path), path.to_path_buf()] {
+        let file = match File::open(&segment) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line_level(&line).is_some_and(|l| l <= min_level) {
+                lines.push(line);
+            }
+        }
+    }
+    let skip = lines.len().saturating_sub(count);
+    lines.drain(..skip);
+    Ok(lines)
+}
+
+/// Pass each line appended to `path` from now on at `min_level` or more
+/// severe to `on_line`, across rotations. Runs until reading fails.
+pub async fn follow(path: &Path, min_level: u8, mut on_line: impl FnMut(&str)) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut current = identity(&file.metadata()?);
+    file.seek(SeekFrom::End(0))?;
+    let mut reader = BufReader::new(file);
+    let mut partial = String::new();
+    loop {
+        read_available(&mut reader, &mut partial, min_level, &mut on_line)?;
+        tokio::time::sleep(FOLLOW_INTERVAL).await;
+        // Missing between the rename and the new segment's first write
+        let Ok(meta) = std::fs::metadata(path) else { continue };
+        let rotated = match (identity(&meta), current) {
+            (Some(id), Some(cur)) => id != cur,
+            _ => meta.len() < reader.stream_position()?,
+        };
+        if rotated {
+            // Finish the old segment, then start the new one from the top
+            read_available(&mut reader, &mut partial, min_level, &mut on_line)?;
+            partial.clear();
+            let file = File::open(path)?;
+            current = identity(&file.metadata()?);
+            reader = BufReader::new(file);
+        }
+    }
+}
+
+/// Hand every complete line left in `reader` to `on_line`; a trailing
+/// partial line stays in `partial` until the writer finishes it.
+fn read_available(
+    reader: &mut BufReader<File>,
+    partial: &mut String,
+    min_level: u8,
+    on_line: &mut impl FnMut(&str),
+) -> io::Result<()> {
+    while reader.read_line(partial)? > 0 {
+        if !partial.ends_with('\n') {
+            continue;
+        }
+        let line = partial.trim_end_matches('\n');
+        if line_level(line).is_some_and(|l| l <= min_level) {
+            on_line(line);
+        }
+        partial.clear();
+    }
+    Ok(())
+}
+
+/// Which file a path currently names, where the platform can tell.
+#[cfg(unix)]
+fn identity(meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn identity(_meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_log(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bridge-log-{}-{}", name, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(LOG_FILENAME)
+    }
+
+    #[test]
+    fn lines_carry_their_level() {
+        let line = format_line("2026-10-15 09:00:00.000", Level::WARN, "first\nsecond");
+        assert_eq!(line, "2026-10-15 09:00:00.000 WARN  first ⏎ second\n");
+        assert_eq!(line_level(&line), Some(2));
+        assert_eq!(line_level("garbage"), None);
+        assert_eq!(parse_level("debug"), Ok(4));
+        assert_eq!(parse_level("INFO"), Ok(3));
+        assert!(parse_level("loud").is_err());
+    }
+
+    #[test]
+    fn rotates_into_two_segments_and_reads_both() {
+        let path = temp_log("rotate");
+        let layer = FileLogLayer::with_max_bytes(&path, 200).unwrap();
+        for i in 0..20 {
+            let level = if i % 2 == 0 { Level::INFO } else { Level::DEBUG };
+            layer.write(level, &format!("message {i:02}"));
+        }
+        assert!(std::fs::metadata(&path).unwrap().len() <= 200);
+        assert!(rotated_path(&path).exists());
+
+        let recent = read_recent(&path, 4, 3).unwrap();
+        assert_eq!(recent.len(), 3);
+        assert!(recent[0].ends_with("message 17") && recent[2].ends_with("message 19"));
+
+        let info = read_recent(&path, 3, 100).unwrap();
+        assert!(!info.is_empty() && info.iter().all(|l| line_level(l) == Some(3)));
+        assert!(info.last().unwrap().ends_with("message 18"));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn follow_sees_new_lines_across_rotation() {
+        let path = temp_log("follow");
+        let layer = FileLogLayer::with_max_bytes(&path, 120).unwrap();
+        layer.write(Level::INFO, "before follow");
+
+        let seen = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let sink = std::sync::Arc::clone(&seen);
+        let follow_path = path.clone();
+        let task = tokio::spawn(async move {
+            follow(&follow_path, 3, |line| sink.lock().unwrap().push(line.to_string())).await
+        });
+        tokio::time::sleep(FOLLOW_INTERVAL * 2).await;
+        layer.write(Level::INFO, "after follow");
+        layer.write(Level::DEBUG, "too verbose");
+        tokio::time::sleep(FOLLOW_INTERVAL * 2).await;
+        layer.write(Level::WARN, "in the next segment after a rotation");
+        tokio::time::sleep(FOLLOW_INTERVAL * 2).await;
+        task.abort();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2, "{seen:?}");
+        assert!(seen[0].ends_with("after follow"));
+        assert!(seen[1].ends_with("in the next segment after a rotation"));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
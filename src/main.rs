@@ -1,12 +1,14 @@
 use std::sync::{Arc, atomic::AtomicU8};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 use tokio::sync::mpsc;
 use tracing_subscriber::prelude::*;
 
 use bridge::common_config::{self as common_config, CommonConfig};
 use bridge::config;
+use bridge::error::BridgeError;
+use bridge::output::{self as output, OutputFormat};
 use bridge::tui::{
     app::App,
     events::AppEvent,
@@ -26,6 +28,23 @@ struct Cli {
     #[arg(short = 'c', long, global = true)]
     config_dir: Option<std::path::PathBuf>,
 
+    /// Output format of `status`, `devices`, `crashes` and `start --check`
+    #[arg(short = 'o', long, global = true, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
+
+    /// Never prompt: fail with an error where input would be needed.
+    /// `bridge` and `bridge start` run without the TUI
+    #[arg(long, global = true)]
+    non_interactive: bool,
+
+    /// How to draw QR codes: Unicode half blocks, or `##` for consoles without them
+    #[arg(long, global = true, value_enum, default_value_t = bridge::qr::QrStyle::Auto)]
+    qr_style: bridge::qr::QrStyle,
+
+    /// Answer yes to confirmation prompts (never to pairing approvals)
+    #[arg(short = 'y', long, global = true)]
+    yes: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -34,10 +53,264 @@ struct Cli {
 enum Commands {
     /// Set up Cloudflare Zero Trust (interactive TUI wizard, no flags required)
     Setup,
+    /// Start the bridge (same as running `bridge` without a command)
+    Start {
+        /// Development only: serve the local transport over plaintext ws://
+        /// without auth, on 127.0.0.1, without the TUI. common.toml is not changed
+        #[arg(long)]
+        insecure_dev: bool,
+        /// Serve plaintext ws:// on 127.0.0.1 without the TUI, and forward the
+        /// port on every connected Android device with `adb reverse`
+        #[arg(long)]
+        adb_reverse: bool,
+        /// With --insecure-dev, listen on this address instead of 127.0.0.1
+        #[arg(long, requires = "insecure_dev", conflicts_with = "adb_reverse")]
+        bind: Option<String>,
+        /// With --insecure-dev or --adb-reverse, run this agent command instead of the configured one
+        #[arg(long)]
+        agent_command: Option<String>,
+        /// Check the config, agent, ports, TLS material and tunnel prerequisites,
+        /// print a report and exit (non-zero if a check fails) without starting
+        #[arg(long, conflicts_with_all = ["insecure_dev", "adb_reverse", "agent_command"])]
+        check: bool,
+        /// Serve this transport instead of asking which one (default: the only
+        /// enabled one); with --check, check only this one (default: every enabled one)
+        #[arg(long, conflicts_with_all = ["insecure_dev", "adb_reverse"])]
+        transport: Option<String>,
+    },
+    /// Download and install the latest release over this executable
+    SelfUpdate {
+        /// Only report whether a newer release exists
+        #[arg(long)]
+        check: bool,
+    },
+    /// Act as a stdio ACP agent backed by a running bridge (for desktop ACP clients)
+    Connect {
+        /// Bridge WebSocket URL (ws:// or wss://)
+        #[arg(long, required_unless_present = "local", conflicts_with = "local")]
+        url: Option<String>,
+        /// Connect to the bridge configured in this config directory over loopback
+        #[arg(long)]
+        local: bool,
+        /// Auth token (default: `auth_token` from common.toml)
+        #[arg(long)]
+        token: Option<String>,
+        /// Pin the server certificate by SHA256 fingerprint instead of trusting the OS store
+        #[arg(long)]
+        fingerprint: Option<String>,
+    },
+    /// Load test a running bridge with concurrent echo requests
+    Bench {
+        /// Bridge WebSocket URL (ws:// or wss://)
+        #[arg(long, required_unless_present = "local", conflicts_with = "local")]
+        url: Option<String>,
+        /// Bench the bridge configured in this config directory over loopback
+        #[arg(long)]
+        local: bool,
+        /// Auth token (default: `auth_token` from common.toml)
+        #[arg(long)]
+        token: Option<String>,
+        /// Pin the server certificate by SHA256 fingerprint instead of trusting the OS store
+        #[arg(long)]
+        fingerprint: Option<String>,
+        /// Concurrent connections
+        #[arg(long, default_value_t = 10)]
+        connections: usize,
+        /// Payload bytes per request
+        #[arg(long, default_value_t = 256)]
+        message_size: usize,
+        /// Requests per second per connection (0 = as fast as responses arrive)
+        #[arg(long, default_value_t = 10)]
+        rate: u32,
+        /// Seconds to send for
+        #[arg(long, default_value_t = 10)]
+        duration: u64,
+        /// Seconds to wait for a response before counting it as dropped
+        #[arg(long, default_value_t = 5)]
+        timeout: u64,
+    },
+    /// Run a synthetic stdio ACP agent that echoes requests (agent command for `bench`)
+    EchoAgent,
+    /// Connect like a client would and report the first stage that fails
+    TestConnection {
+        /// Pairing URL or deep link to pair with (uses up its one-time code)
+        #[arg(conflicts_with_all = ["url", "local", "token"])]
+        pairing_url: Option<String>,
+        /// Bridge WebSocket URL (ws:// or wss://)
+        #[arg(long, conflicts_with = "local")]
+        url: Option<String>,
+        /// Test the bridge configured in this config directory over loopback
+        #[arg(long)]
+        local: bool,
+        /// Auth token (default: `auth_token` from common.toml)
+        #[arg(long)]
+        token: Option<String>,
+        /// Pin the server certificate by SHA256 fingerprint instead of trusting the OS store
+        #[arg(long)]
+        fingerprint: Option<String>,
+        /// Seconds each stage may take
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+    },
+    /// Show the connection QR code for a second device
+    ShowQr {
+        /// Transport to show (default: the first enabled transport)
+        #[arg(long)]
+        transport: Option<String>,
+        /// List every enabled transport; the app uses the first it reaches
+        #[arg(long, conflicts_with = "transport")]
+        all: bool,
+        /// Also copy the connection JSON to the system clipboard
+        #[arg(long)]
+        copy: bool,
+        /// Print the connection JSON to stdout instead of the QR code
+        #[arg(long)]
+        stdout_json: bool,
+    },
+    /// Pair a device (QR code, or plain-text fields with --manual)
+    Pair {
+        /// Print the connection details as plain text to type into the app, then
+        /// check the confirmation code it shows
+        #[arg(long)]
+        manual: bool,
+        /// Transport to pair over (default: the first enabled transport)
+        #[arg(long)]
+        transport: Option<String>,
+        /// Pair over every enabled transport; the app uses the first it reaches
+        #[arg(long, conflicts_with_all = ["transport", "manual"])]
+        all: bool,
+        /// Keep serving one-time codes (a new one after each pairing or expiry)
+        /// without starting an agent, to provision several devices
+        #[arg(long, conflicts_with_all = ["manual", "all"])]
+        serve: bool,
+        /// With --serve, stop after this many devices have paired
+        #[arg(long, requires = "serve")]
+        max_pairings: Option<usize>,
+        /// With --serve, open the QR image in the image viewer
+        #[arg(long, requires = "serve")]
+        open: bool,
+        /// With --serve, clear the screen for each new code so only the
+        /// current QR code and its countdown show
+        #[arg(long, requires = "serve")]
+        watch: bool,
+        /// For simulators: write the connection JSON to pair.json, copy it, and
+        /// serve it at http://127.0.0.1:<port>/pair.json until Ctrl-C
+        #[arg(long, conflicts_with_all = ["manual", "all", "serve"])]
+        local_dev: bool,
+        /// With --local-dev, serve on this port (default: 8767)
+        #[arg(long, requires = "local_dev")]
+        port: Option<u16>,
+        /// Write an NDEF message for an NFC tag to this file: the pairing URL
+        /// without a code, so the app asks for the one the bridge shows. With
+        /// --serve, then serve codes
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["manual", "all", "local_dev"])]
+        nfc_export: Option<std::path::PathBuf>,
+    },
+    /// Show the configuration and probe whether each enabled transport is reachable
+    Status,
+    /// List the device tokens configured under [[devices]] and [[users]], and their scopes
+    Devices {
+        /// List the devices paired with this bridge instead: name, platform
+        /// and app version as each reported when it paired
+        #[arg(long)]
+        paired: bool,
+    },
+    /// Requests, responses and bytes per device over the last days, counted
+    /// with [usage] in common.toml
+    Usage {
+        /// Days to add up, today included
+        #[arg(long, default_value_t = 7, value_parser = clap::value_parser!(u32).range(1..))]
+        days: u32,
+    },
+    /// Inspect the reports saved when a pooled agent exited on its own
+    Crashes {
+        #[command(subcommand)]
+        action: CrashesCommand,
+    },
+    /// Check push notifications through the [push_relay] in common.toml
+    Push {
+        #[command(subcommand)]
+        action: PushCommand,
+    },
+    /// Live dashboard of the running bridge: connections, pooled agents,
+    /// message rates, the tunnel and recent logs
+    Top {
+        /// Seconds between refreshes
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+    },
+    /// Print recent logs of the bridge running from this config directory
+    Logs {
+        /// Keep printing new lines as they are logged
+        #[arg(short, long)]
+        follow: bool,
+        /// Least severe level to show (error, warn, info, debug)
+        #[arg(long, default_value = "info", value_parser = bridge::log_file::parse_level)]
+        level: u8,
+        /// Number of recent lines to print
+        #[arg(short = 'n', long, default_value_t = 100)]
+        lines: usize,
+    },
+    /// Forward connections to a sleeping workstation's bridge, waking it with
+    /// Wake-on-LAN first (configured under [wake_relay])
+    WakeRelay,
+    /// Write common.toml, the TLS certificate and key, paired device keys and
+    /// cloudflared credentials to an encrypted file, to move the bridge to
+    /// another machine
+    ExportBundle {
+        /// File to write
+        file: std::path::PathBuf,
+        /// Encrypt to this age recipient (repeatable; needs the `age` CLI)
+        /// instead of a passphrase
+        #[arg(long = "recipient")]
+        recipients: Vec<String>,
+        /// Read the passphrase from the first line of this file instead of
+        /// BRIDGE_BUNDLE_PASSPHRASE or a prompt
+        #[arg(long, conflicts_with = "recipients")]
+        passphrase_file: Option<std::path::PathBuf>,
+    },
+    /// Restore a file written by `export-bundle` into the config directory
+    ImportBundle {
+        /// File to read
+        file: std::path::PathBuf,
+        /// age identity file, for bundles encrypted to a recipient
+        #[arg(long)]
+        identity: Option<std::path::PathBuf>,
+        /// Read the passphrase from the first line of this file instead of
+        /// BRIDGE_BUNDLE_PASSPHRASE or a prompt
+        #[arg(long, conflicts_with = "identity")]
+        passphrase_file: Option<std::path::PathBuf>,
+        /// Replace an existing common.toml
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum PushCommand {
+    /// Send a silent test notification to every device registered with the
+    /// relay, and report whether each was delivered
+    Test,
+}
+
+#[derive(Subcommand)]
+enum CrashesCommand {
+    /// List the crash reports, newest first
+    List,
+    /// Print one crash report: exit status, last stderr lines and last messages
+    Show {
+        /// Report id, or enough of its start to be unique
+        id: String,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    #[cfg(target_os = "linux")]
+    if let Some(code) = bridge::container::run_as_init()? {
+        std::process::exit(code);
+    }
+
     let cli = Cli::parse();
 
     // Apply custom config directory before anything else.
@@ -45,20 +318,202 @@ async fn main() -> Result<()> {
         config::set_config_dir(dir.clone());
         common_config::set_config_dir(dir.clone());
     }
+    bridge::qr::set_style(cli.qr_style);
 
-    match cli.command {
+    // Route outbound HTTP through [proxy] before any client is built. A
+    // config that doesn't parse is reported by the command that needs it.
+    if let Some(proxy) = CommonConfig::load().ok().and_then(|c| c.proxy) {
+        proxy.validate()?;
+        bridge::proxy::configure(Some(proxy));
+    }
+
+    let prompts = Prompts { non_interactive: cli.non_interactive, yes: cli.yes };
+    let result = match cli.command {
+        Some(Commands::Setup) if !prompts.can_ask() => Err(anyhow::anyhow!(
+            "`bridge setup` is an interactive wizard — without a terminal, set [transports.cloudflare] in common.toml or BRIDGE_TRANSPORTS__CLOUDFLARE__* instead"
+        )),
         Some(Commands::Setup) => run_setup_wizard().await,
-        None => run_tui().await,
+        Some(Commands::Start { check: true, transport, .. }) => run_start_check(transport.as_deref(), cli.output),
+        Some(Commands::Start { insecure_dev: false, adb_reverse: false, agent_command: None, transport, .. }) => {
+            run_start(transport, prompts).await
+        }
+        None => run_start(None, prompts).await,
+        Some(Commands::Start { insecure_dev: false, adb_reverse: false, .. }) => {
+            anyhow::bail!("--agent-command needs --insecure-dev or --adb-reverse; without them the agent is chosen in the TUI")
+        }
+        Some(Commands::Start { insecure_dev, adb_reverse, bind, agent_command, .. }) => {
+            init_stderr_logging();
+            let options = bridge::insecure_dev::StartOptions { insecure: insecure_dev, adb_reverse, bind, agent_command };
+            bridge::insecure_dev::run(CommonConfig::load()?, options).await
+        }
+        Some(Commands::SelfUpdate { check }) => run_self_update(check).await,
+        Some(Commands::Connect { url, local, token, fingerprint }) => {
+            init_stderr_logging();
+            let target = connect_target(url, local, token, fingerprint)?;
+            bridge::connect::run(&target).await
+        }
+        Some(Commands::Bench { url, local, token, fingerprint, connections, message_size, rate, duration, timeout }) => {
+            init_stderr_logging();
+            let config = bridge::bench::BenchConfig {
+                target: connect_target(url, local, token, fingerprint)?,
+                connections,
+                message_size,
+                rate,
+                duration: std::time::Duration::from_secs(duration),
+                timeout: std::time::Duration::from_secs(timeout),
+            };
+            println!("Benchmarking {} with {} connections...", config.target.url, connections);
+            println!("{}", bridge::bench::run(&config).await?);
+            Ok(())
+        }
+        Some(Commands::EchoAgent) => bridge::bench::run_echo_agent().await,
+        Some(Commands::TestConnection { pairing_url, url, local, token, fingerprint, timeout }) => {
+            init_stderr_logging();
+            run_test_connection(pairing_url, url, local, token, fingerprint, timeout, cli.output).await
+        }
+        Some(Commands::ShowQr { transport, all, copy, stdout_json }) => {
+            init_stderr_logging();
+            run_show_qr(transport, all, copy, stdout_json)
+        }
+        Some(Commands::Pair { manual, transport, all, serve, max_pairings, open, watch, local_dev, port, nfc_export }) => {
+            init_stderr_logging();
+            if let Some(ref path) = nfc_export {
+                run_pair_nfc_export(transport.clone(), path, serve)?;
+            }
+            if local_dev {
+                run_pair_local_dev(transport, port.unwrap_or(bridge::pair_json::DEFAULT_PORT)).await
+            } else if serve {
+                run_pair_serve(transport, max_pairings, open, watch).await
+            } else if manual {
+                run_pair_manual(transport, prompts)
+            } else if nfc_export.is_some() {
+                Ok(())
+            } else {
+                run_show_qr(transport, all, false, false)
+            }
+        }
+        Some(Commands::Status) => {
+            init_stderr_logging();
+            run_status(cli.output).await
+        }
+        Some(Commands::Devices { paired: false }) => run_devices(cli.output),
+        Some(Commands::Devices { paired: true }) => run_paired_devices(cli.output),
+        Some(Commands::Usage { days }) => run_usage(days, cli.output),
+        Some(Commands::Crashes { action }) => run_crashes(action, cli.output),
+        Some(Commands::Push { action: PushCommand::Test }) => {
+            init_stderr_logging();
+            run_push_test(cli.output).await
+        }
+        Some(Commands::Top { interval }) => run_top(interval).await,
+        Some(Commands::Logs { follow, level, lines }) => run_logs(follow, level, lines).await,
+        Some(Commands::WakeRelay) => {
+            init_stderr_logging();
+            run_wake_relay().await
+        }
+        Some(Commands::ExportBundle { file, recipients, passphrase_file }) => {
+            run_export_bundle(&file, recipients, passphrase_file.as_deref(), prompts)
+        }
+        Some(Commands::ImportBundle { file, identity, passphrase_file, force }) => {
+            init_stderr_logging();
+            run_import_bundle(&file, identity, passphrase_file.as_deref(), force, prompts)
+        }
+    };
+    if let Some(hint) = result.as_ref().err().and_then(error_hint) {
+        eprintln!("💡 {}", hint);
     }
+    result
+}
+
+/// What to do about an error, by its kind.
+fn error_hint(error: &anyhow::Error) -> Option<String> {
+    let error = error.chain().find_map(|e| e.downcast_ref::<BridgeError>())?;
+    match error {
+        BridgeError::Config { .. } => Some(format!("Check {}", CommonConfig::config_path().display())),
+        BridgeError::DependencyMissing { program, .. } => Some(format!("Install {}, or fix the command that runs it", program)),
+        BridgeError::Api { codes, .. } if codes.contains(&10000) => {
+            Some("The Cloudflare API token lacks a permission — see docs/transport/cloudflare.md".to_string())
+        }
+        e if e.is_transient() => Some("This may be temporary — try again".to_string()),
+        _ => None,
+    }
+}
+
+/// How commands may ask for input (`--non-interactive`, `--yes`).
+#[derive(Clone, Copy)]
+struct Prompts {
+    non_interactive: bool,
+    yes: bool,
 }
 
-/// Launch the full TUI (wizard if needed, then running screen).
-async fn run_tui() -> Result<()> {
+impl Prompts {
+    /// Whether a prompt can wait for input: not `--non-interactive`, and
+    /// stdin is a terminal.
+    fn can_ask(self) -> bool {
+        use std::io::IsTerminal;
+        !self.non_interactive && std::io::stdin().is_terminal()
+    }
+
+    /// Ask `question` as y/N: yes with `--yes`, no when no prompt can be shown.
+    fn confirm(self, question: &str) -> Result<bool> {
+        use std::io::{BufRead, Write};
+
+        if self.yes {
+            return Ok(true);
+        }
+        if !self.can_ask() {
+            return Ok(false);
+        }
+        eprint!("{} [y/N] ", question);
+        std::io::stderr().flush()?;
+        let mut line = String::new();
+        std::io::stdin().lock().read_line(&mut line)?;
+        Ok(matches!(line.trim().to_lowercase().as_str(), "y" | "yes"))
+    }
+}
+
+/// `bridge` and `bridge start`: the TUI, or, with `--non-interactive` or
+/// BRIDGE_TRANSPORT, the bridge alone on `transport` (default: the only
+/// enabled one).
+async fn run_start(transport: Option<String>, prompts: Prompts) -> Result<()> {
+    use std::io::IsTerminal;
+
+    let from_env = bridge::container::headless_transport();
+    if prompts.non_interactive || from_env.is_some() {
+        let transport = match transport.or(from_env) {
+            Some(transport) => transport,
+            None => only_enabled_transport()?,
+        };
+        return run_headless(&transport).await;
+    }
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        anyhow::bail!("No terminal for the TUI — pass --non-interactive (with --transport <name>), or set BRIDGE_TRANSPORT");
+    }
+    run_tui(transport).await
+}
+
+/// The transport to serve when none was chosen: the only enabled one.
+fn only_enabled_transport() -> Result<String> {
+    let config = CommonConfig::load()?;
+    let enabled: Vec<&str> = config.enabled_transports().into_iter().map(|(name, _)| name).collect();
+    match enabled[..] {
+        [name] => Ok(name.to_string()),
+        [] => anyhow::bail!("No transport enabled — pass --transport <name> (local, tailscale-serve, ...)"),
+        _ => anyhow::bail!("Several transports are enabled ({}) — pass --transport <name> to choose one", enabled.join(", ")),
+    }
+}
+
+/// Launch the full TUI (wizard if needed, then running screen), serving
+/// `transport` without asking when given.
+async fn run_tui(transport: Option<String>) -> Result<()> {
     // Load config early so we can read the saved log level.
     let mut config = CommonConfig::load()?;
     config.ensure_agent_id();
     config.ensure_auth_token();
+    config.ensure_admin_token();
     config.save()?;
+    if let Some(ref transport) = transport {
+        bridge::container::select_transport(&mut config, transport)?;
+    }
 
     // Channel capacity: generous to avoid dropping log records.
     let (event_tx, event_rx) = mpsc::channel::<AppEvent>(512);
@@ -70,11 +525,20 @@ async fn run_tui() -> Result<()> {
     // EnvFilter is "trace" so all events reach the layer; the layer filters by min_level.
     // No fmt layer — stdout would corrupt the ratatui alternate screen.
     let log_layer = TuiLogLayer::new(event_tx.clone(), Arc::clone(&log_level_arc));
+    // Recent logs also go to a ring file for `bridge logs`, whatever the TUI level.
+    let file_layer = bridge::log_file::FileLogLayer::open(&CommonConfig::config_dir().join(bridge::log_file::LOG_FILENAME))
+        .map_err(|e| eprintln!("⚠️  Logs won't be available to `bridge logs`: {}", e))
+        .ok();
     tracing_subscriber::registry()
         .with(tracing_subscriber::EnvFilter::new("trace"))
         .with(log_layer)
+        .with(file_layer)
         .init();
 
+    if config.check_for_updates {
+        tokio::spawn(bridge::update::log_if_outdated());
+    }
+
     // Tick timer — keeps the draw loop alive even when no events arrive.
     let tick_tx = event_tx.clone();
     tokio::spawn(async move {
@@ -90,6 +554,8 @@ async fn run_tui() -> Result<()> {
     // Keyboard/mouse input thread — crossterm::event::read() blocks.
     let key_tx = event_tx.clone();
     std::thread::spawn(move || loop {
+        // Sending in a match guard would hide the side effect.
+        #[allow(clippy::collapsible_match)]
         match crossterm::event::read() {
             Ok(crossterm::event::Event::Key(key)) => {
                 if key_tx.blocking_send(AppEvent::Key(key)).is_err() { break; }
@@ -104,10 +570,687 @@ async fn run_tui() -> Result<()> {
         }
     });
 
-    let app = App::new(config, event_tx, log_level_arc);
+    let mut app = App::new(config, event_tx, log_level_arc);
+    if let Some(ref transport) = transport {
+        app = app.with_transport(transport);
+    }
     app.run(event_rx).await
 }
 
+/// Serve `transport` without the TUI (`BRIDGE_TRANSPORT`), logging to
+/// stdout as JSON or to stderr as text.
+async fn run_headless(transport: &str) -> Result<()> {
+    let json = bridge::container::json_logs(std::env::var(bridge::container::LOG_FORMAT_VAR).ok().as_deref())?;
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+    let file_layer = bridge::log_file::FileLogLayer::open(&CommonConfig::config_dir().join(bridge::log_file::LOG_FILENAME))
+        .map_err(|e| eprintln!("⚠️  Logs won't be available to `bridge logs`: {}", e))
+        .ok();
+    let registry = tracing_subscriber::registry().with(filter).with(file_layer);
+    if json {
+        registry.with(bridge::container::JsonLogLayer::stdout()).init();
+    } else {
+        registry.with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr)).init();
+    }
+    bridge::container::run_headless(CommonConfig::load()?, transport).await
+}
+
+/// Run `bridge self-update`: plain stdout output, no TUI.
+async fn run_self_update(check_only: bool) -> Result<()> {
+    use bridge::update::UpdateOutcome;
+
+    println!("Checking for updates (running {})...", bridge::VERSION);
+    match bridge::update::self_update(check_only).await? {
+        UpdateOutcome::UpToDate { version } => println!("✅ Bridge {} is up to date", version),
+        UpdateOutcome::Available { version } => println!("⬆️  Bridge {} is available — run `bridge self-update` to install", version),
+        UpdateOutcome::Installed { version, path } => println!("✅ Installed bridge {} at {}", version, path.display()),
+    }
+    Ok(())
+}
+
+/// Run `bridge show-qr`: a static connection payload (auth token included, no
+/// one-time code) for the chosen transport, as a QR code, JSON, or clipboard text.
+/// Load the config (generating an agent ID and auth token if missing) and
+/// resolve the endpoint of `transport`, or of the first enabled transport.
+/// Returns the config, transport name, URL and certificate fingerprint.
+fn resolve_pairing_endpoint(transport: Option<String>) -> Result<(CommonConfig, String, String, Option<String>)> {
+    let config = load_pairing_config()?;
+    let enabled = config.enabled_transports();
+    let (name, transport_cfg) = match transport {
+        Some(ref name) => enabled.iter().find(|(n, _)| n == name).copied().ok_or_else(|| {
+            anyhow::anyhow!("Transport '{}' is not enabled in {}", name, CommonConfig::config_path().display())
+        })?,
+        None => enabled.first().copied().ok_or_else(|| {
+            anyhow::anyhow!("No transport enabled — run `bridge` once to configure one")
+        })?,
+    };
+    let (hostname, fingerprint) =
+        bridge::runner::resolve_endpoint(name, transport_cfg, &config, &CommonConfig::config_dir())?;
+    let name = name.to_string();
+    Ok((config, name, hostname, fingerprint))
+}
+
+/// Load the config, generating an agent ID and auth token if missing.
+fn load_pairing_config() -> Result<CommonConfig> {
+    let mut config = CommonConfig::load()?;
+    config.ensure_agent_id();
+    config.ensure_auth_token();
+    config.save()?;
+    Ok(config)
+}
+
+/// Like [`resolve_pairing_endpoint`] for every enabled transport, as
+/// `(name, url, fingerprint)`, nearest network first. Transports whose
+/// endpoint can't be resolved here (e.g. Tailscale is down) are left out with
+/// a warning.
+#[allow(clippy::type_complexity)]
+fn resolve_all_pairing_endpoints() -> Result<(CommonConfig, Vec<(String, String, Option<String>)>)> {
+    let config = load_pairing_config()?;
+    let enabled = config.enabled_transports();
+    if enabled.is_empty() {
+        anyhow::bail!("No transport enabled — run `bridge` once to configure one");
+    }
+    let mut endpoints = Vec::new();
+    for (name, transport_cfg) in enabled {
+        match bridge::runner::resolve_endpoint(name, transport_cfg, &config, &CommonConfig::config_dir()) {
+            Ok((url, fingerprint)) => endpoints.push((name.to_string(), url, fingerprint)),
+            Err(e) => eprintln!("⚠️  Leaving out {}: {:#}", name, e),
+        }
+    }
+    // Nearest network first: a phone on the LAN shouldn't go through the tunnel.
+    let rank = |name: &str| ["local", "tailscale-ip", "tailscale-serve", "cloudflare"].iter().position(|n| *n == name).unwrap_or(0);
+    endpoints.sort_by_key(|(name, _, _)| rank(name));
+    if endpoints.is_empty() {
+        anyhow::bail!("No enabled transport has a reachable endpoint — run `bridge status` to check them");
+    }
+    Ok((config, endpoints))
+}
+
+fn run_show_qr(transport: Option<String>, all: bool, copy: bool, stdout_json: bool) -> Result<()> {
+    let cwd = std::env::current_dir().unwrap_or_default().display().to_string();
+    let (config, name, connection_json) = if all {
+        let (config, endpoints) = resolve_all_pairing_endpoints()?;
+        let connection_json = config.to_multi_connection_json(&endpoints, &cwd)?;
+        (config, endpoints[0].0.clone(), connection_json)
+    } else {
+        let (config, name, hostname, fingerprint) = resolve_pairing_endpoint(transport)?;
+        let connection_json = config.to_connection_json(&hostname, &name, &cwd, fingerprint.as_deref())?;
+        (config, name, connection_json)
+    };
+
+    if stdout_json {
+        println!("{}", connection_json);
+    } else {
+        config.deep_link.validate()?;
+        let link = bridge::pairing::deep_link(&config.deep_link.scheme, &connection_json);
+        let qr_data = if config.deep_link.qr { &link } else { &connection_json };
+        bridge::qr::display_qr_code(qr_data, &connection_json, &name)?;
+        println!("  📲 Send this link to your phone to pair without the camera:");
+        println!("  {}\n", link);
+    }
+    if copy {
+        if cfg!(target_os = "linux") {
+            eprintln!("📋 Holding the connection JSON on the clipboard for 60s — paste it now");
+        }
+        bridge::qr::copy_to_clipboard(&connection_json)?;
+        eprintln!("📋 Connection JSON copied to the clipboard");
+    }
+    Ok(())
+}
+
+/// `bridge pair --manual`: print the connection details as plain labelled
+/// lines (no QR code, clipboard or emoji, so screen readers and bare
+/// terminals work), then check the confirmation code the app shows once
+/// they are typed in.
+fn run_pair_manual(transport: Option<String>, prompts: Prompts) -> Result<()> {
+    use std::io::{BufRead, Write};
+
+    let (config, name, url, fingerprint) = resolve_pairing_endpoint(transport)?;
+    let host = url.split_once("://").map_or(url.as_str(), |(_, rest)| rest);
+    println!("Type these details into the app's manual pairing screen.");
+    println!();
+    let field = |label: &str, value: &str| println!("{:<15}{}", format!("{}:", label), value);
+    field("Transport", &name);
+    field("Host", host);
+    field("URL", &url);
+    field("Token", &config.auth_token);
+    let no_fingerprint = if url.starts_with("wss://") { "none (certificate is publicly trusted)" } else { "none (no TLS)" };
+    field("Fingerprint", fingerprint.as_deref().unwrap_or(no_fingerprint));
+    if let Some(t) = config.transports.get(&name) {
+        if let Some(id) = t.client_id.as_deref().filter(|s| !s.is_empty()) {
+            field("Client ID", id);
+        }
+        if let Some(secret) = t.client_secret.as_deref().filter(|s| !s.is_empty()) {
+            field("Client secret", secret);
+        }
+    }
+    println!();
+
+    if prompts.yes || !prompts.can_ask() {
+        return Ok(());
+    }
+    let stdin = std::io::stdin();
+    let expected = bridge::pairing::confirmation_code(&url, &config.auth_token, fingerprint.as_deref());
+    for _ in 0..3 {
+        print!("Confirmation code shown in the app (Enter to skip): ");
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        let typed: String = line.chars().filter(|c| c.is_ascii_digit()).collect();
+        if typed == expected {
+            println!("Confirmed: the app has the correct details.");
+            return Ok(());
+        }
+        println!("That code does not match. Check each field in the app, then try again.");
+    }
+    anyhow::bail!("Confirmation code did not match after 3 attempts")
+}
+
+/// `bridge pair --serve`: issue one-time pairing codes until `max_pairings`
+/// devices have paired or Ctrl-C, then print who paired.
+async fn run_pair_serve(transport: Option<String>, max_pairings: Option<usize>, open: bool, watch: bool) -> Result<()> {
+    let (config, name, _, _) = resolve_pairing_endpoint(transport)?;
+    let summary = bridge::runner::run_pair_server(config, &name, max_pairings, open, watch).await?;
+    println!("\n{}", summary);
+    Ok(())
+}
+
+/// `bridge pair --nfc-export`: the code-less pairing URL as an NDEF message
+/// in `path` (see [`bridge::nfc`]), as a deep link when `[deep_link] qr` is set.
+fn run_pair_nfc_export(transport: Option<String>, path: &std::path::Path, serving: bool) -> Result<()> {
+    let (config, name, hostname, fingerprint) = resolve_pairing_endpoint(transport)?;
+    config.deep_link.validate()?;
+    let url = bridge::pairing::stable_pairing_url(&hostname, &name, fingerprint.as_deref());
+    let uri = if config.deep_link.qr { bridge::pairing::deep_link(&config.deep_link.scheme, &url) } else { url };
+    let size = bridge::nfc::export(path, &uri)?;
+    eprintln!("🏷️  NDEF message for {} written to {} ({} bytes)", name, path.display(), size);
+    eprintln!("🔗 {}", uri);
+    match bridge::nfc::smallest_tag(&bridge::nfc::uri_message(&uri)) {
+        Some(tag) => eprintln!("   Fits an {} or larger tag", tag),
+        None => eprintln!("⚠️  Too long for common NTAG21x tags"),
+    }
+    if !serving {
+        eprintln!("   After a tap the app asks for the pairing code: run `bridge pair --serve`, or use /qr in the running bridge");
+    }
+    Ok(())
+}
+
+/// `bridge pair --local-dev`: the connection JSON as a file, on the
+/// clipboard, and at `http://127.0.0.1:<port>/pair.json` until Ctrl-C.
+async fn run_pair_local_dev(transport: Option<String>, port: u16) -> Result<()> {
+    let (config, name, hostname, fingerprint) = resolve_pairing_endpoint(transport)?;
+    let cwd = std::env::current_dir().unwrap_or_default().display().to_string();
+    let connection_json = config.to_connection_json(&hostname, &name, &cwd, fingerprint.as_deref())?;
+
+    let path = bridge::pair_json::write(&CommonConfig::config_dir(), &connection_json)?;
+    eprintln!("📄 Connection JSON for {} written to {}", name, path.display());
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+        .await
+        .with_context(|| format!("Failed to listen on 127.0.0.1:{}", port))?;
+    eprintln!("🌐 Serving it at http://127.0.0.1:{}/{} — Ctrl-C to stop", port, bridge::pair_json::FILENAME);
+    // On Linux the clipboard is held by a blocking call; serve meanwhile.
+    let clipboard_json = connection_json.clone();
+    tokio::task::spawn_blocking(move || match bridge::qr::copy_to_clipboard(&clipboard_json) {
+        Ok(()) => eprintln!("📋 Connection JSON copied to the clipboard"),
+        Err(e) => eprintln!("⚠️  Not copied to the clipboard: {:#}", e),
+    });
+    tokio::select! {
+        result = bridge::pair_json::serve(listener, connection_json) => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    }
+}
+
+/// `bridge status`: the configuration and a live probe of every enabled transport.
+async fn run_status(format: OutputFormat) -> Result<()> {
+    let config = CommonConfig::load()?;
+    if format == OutputFormat::Table {
+        println!("Probing enabled transports...\n");
+    }
+    output::print(format, &bridge::status::collect(&config, &CommonConfig::config_dir()).await)
+}
+
+/// `bridge start --check`: report whether `bridge start` would come up.
+fn run_start_check(transport: Option<&str>, format: OutputFormat) -> Result<()> {
+    let config = CommonConfig::load()?;
+    let report = bridge::preflight::run(&config, &CommonConfig::config_dir(), transport);
+    output::print(format, &report)?;
+    match report.failures() {
+        0 => Ok(()),
+        n => anyhow::bail!("{} check(s) failed", n),
+    }
+}
+
+/// `bridge test-connection`: without a pairing URL, the target is chosen
+/// like `bridge connect`'s.
+async fn run_test_connection(
+    pairing_url: Option<String>,
+    url: Option<String>,
+    local: bool,
+    token: Option<String>,
+    fingerprint: Option<String>,
+    timeout: u64,
+    format: OutputFormat,
+) -> Result<()> {
+    use bridge::test_connection::TestTarget;
+
+    let target = match pairing_url {
+        Some(pairing_url) => match (TestTarget::pairing(&pairing_url)?, fingerprint) {
+            (TestTarget::Pairing { url, .. }, Some(fp)) => TestTarget::Pairing { url, fingerprint: Some(fp) },
+            (target, _) => target,
+        },
+        None => TestTarget::Token(connect_target(url, local, token, fingerprint)?),
+    };
+    let report = bridge::test_connection::run(&target, std::time::Duration::from_secs(timeout)).await;
+    output::print(format, &report)?;
+    match report.failed_stage() {
+        None => Ok(()),
+        Some(stage) => anyhow::bail!("Connection failed at the {} stage", stage),
+    }
+}
+
+/// `bridge devices`: the `[[devices]]` tokens, and those of each
+/// `[[users]]`, with their scopes. Only the first characters of each token
+/// are shown.
+fn run_devices(format: OutputFormat) -> Result<()> {
+    let config = CommonConfig::load()?;
+    let token_prefix = |token: &str| format!("{}…", token.chars().take(4).collect::<String>());
+    let devices: Vec<(Option<&str>, &bridge::common_config::DeviceConfig)> = config
+        .devices
+        .iter()
+        .map(|d| (None, d))
+        .chain(config.users.iter().flat_map(|u| u.devices.iter().map(move |d| (Some(u.name.as_str()), d))))
+        .collect();
+    if format == OutputFormat::Json {
+        let devices: Vec<_> = devices
+            .iter()
+            .map(|(user, d)| {
+                let mut device = serde_json::json!({ "name": d.name, "scopes": d.scopes, "tokenPrefix": token_prefix(&d.token) });
+                if let Some(user) = user {
+                    device["user"] = serde_json::json!(user);
+                }
+                device
+            })
+            .collect();
+        return output::print_json(&devices);
+    }
+    if devices.is_empty() {
+        println!("No [[devices]] in {} — every client uses the auth token", CommonConfig::config_path().display());
+        return Ok(());
+    }
+    let mut table = output::Table::new(&["NAME", "USER", "TOKEN", "SCOPES"]);
+    for (user, device) in devices {
+        let scopes: Vec<&str> = device.scopes.iter().map(|s| s.as_str()).collect();
+        table.row([device.name.clone(), user.unwrap_or("-").to_string(), token_prefix(&device.token), scopes.join(", ")]);
+    }
+    println!("{}", table);
+    Ok(())
+}
+
+/// `bridge usage`: the counters in `usage.json`, which a running bridge
+/// writes every minute.
+fn run_usage(days: u32, format: OutputFormat) -> Result<()> {
+    let config = CommonConfig::load()?;
+    let record = bridge::usage::UsageRecord::load(&CommonConfig::config_dir())?;
+    if config.usage.is_none() && record.days.is_empty() && format == OutputFormat::Table {
+        println!("Usage isn't counted: add a [usage] section to common.toml");
+        return Ok(());
+    }
+    let report = bridge::usage::UsageReport::new(&record, config.usage.as_ref(), days, chrono::Local::now().date_naive());
+    output::print(format, &report)
+}
+
+/// `bridge devices --paired`: the devices in `device_keys.json`.
+fn run_paired_devices(format: OutputFormat) -> Result<()> {
+    let keys = bridge::device_keys::DeviceKeys::open(&CommonConfig::config_dir()).keys();
+    let pushes = bridge::push::PushRegistrations::open(&CommonConfig::config_dir());
+    if format == OutputFormat::Json {
+        let devices: Vec<_> = keys
+            .iter()
+            .map(|k| {
+                serde_json::json!({
+                    "id": k.id,
+                    "name": k.name,
+                    "platform": k.platform,
+                    "appVersion": k.app_version,
+                    "ip": k.ip,
+                    "pairedAt": k.paired_at,
+                    "lastPushAt": pushes.last_push_for_key(&k.id),
+                })
+            })
+            .collect();
+        return output::print_json(&devices);
+    }
+    if keys.is_empty() {
+        println!("No devices have paired with the bridge in {}", CommonConfig::config_dir().display());
+        return Ok(());
+    }
+    let dash = |v: &Option<String>| v.clone().unwrap_or_else(|| "-".to_string());
+    let mut table = output::Table::new(&["ID", "NAME", "PLATFORM", "APP VERSION", "IP", "PAIRED", "LAST PUSH"]);
+    for key in keys.iter().rev() {
+        // Keys from before the metadata was recorded only have the description
+        let name = key.name.clone().unwrap_or_else(|| if key.ip.is_none() { key.device.clone() } else { "-".to_string() });
+        table.row([
+            key.id.chars().take(8).collect(),
+            name,
+            dash(&key.platform),
+            dash(&key.app_version),
+            dash(&key.ip),
+            key.paired_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string(),
+            pushes
+                .last_push_for_key(&key.id)
+                .map_or_else(|| "-".to_string(), |at| at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string()),
+        ]);
+    }
+    println!("{}", table);
+    Ok(())
+}
+
+/// `bridge push test`: a silent test push to every registered device, with
+/// the relay's result for each.
+async fn run_push_test(format: OutputFormat) -> Result<()> {
+    let config = CommonConfig::load()?;
+    let config_dir = CommonConfig::config_dir();
+    let relay = bridge::runner::push_relay_client(&config, &config_dir)?
+        .with_context(|| format!("No complete [push_relay] in {}", CommonConfig::config_path().display()))?;
+    let results = relay.test().await?;
+    if format == OutputFormat::Json {
+        output::print_json(&results)?;
+    } else if results.is_empty() {
+        println!("The relay has no devices registered for this bridge — open the app while connected to register one");
+    } else {
+        let registrations = bridge::push::PushRegistrations::open(&config_dir).list();
+        let keys = bridge::device_keys::DeviceKeys::open(&config_dir);
+        let paired_name = |token: &str| {
+            registrations
+                .iter()
+                .find(|r| r.device_token == token)
+                .and_then(|r| r.key_id.as_deref())
+                .and_then(|id| keys.get(id))
+                .map(|key| key.name.unwrap_or(key.device))
+                .unwrap_or_else(|| "-".to_string())
+        };
+        let mut table = output::Table::new(&["DEVICE TOKEN", "PLATFORM", "PAIRED DEVICE", "RESULT"]);
+        for delivery in &results {
+            let (result, tone) = match (delivery.delivered, &delivery.error) {
+                (true, _) => ("delivered".to_string(), output::Tone::Good),
+                (false, Some(e)) => (format!("failed: {}", e), output::Tone::Bad),
+                (false, None) => ("failed".to_string(), output::Tone::Bad),
+            };
+            let token = if delivery.device_token.is_empty() {
+                "-".to_string()
+            } else {
+                format!("{}…", delivery.device_token.chars().take(8).collect::<String>())
+            };
+            let paired = paired_name(&delivery.device_token);
+            table.toned_row([
+                (token.as_str(), output::Tone::Plain),
+                (delivery.platform.as_str(), output::Tone::Plain),
+                (paired.as_str(), output::Tone::Plain),
+                (result.as_str(), tone),
+            ]);
+        }
+        println!("{}", table);
+    }
+    let failed = results.iter().filter(|d| !d.delivered).count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} device(s) didn't receive the test push", failed, results.len());
+    }
+    Ok(())
+}
+
+/// `bridge crashes`: the crash reports in the config directory.
+fn run_crashes(action: CrashesCommand, format: OutputFormat) -> Result<()> {
+    let reports = bridge::crash_report::CrashReports::new(&CommonConfig::config_dir());
+    match action {
+        CrashesCommand::List => {
+            let reports = reports.list();
+            if format == OutputFormat::Json {
+                return output::print_json(&reports);
+            }
+            if reports.is_empty() {
+                println!("No agent crashes recorded in {}", CommonConfig::config_dir().display());
+                return Ok(());
+            }
+            let mut table = output::Table::new(&["ID", "TIME", "AGENT", "EXIT", "UPTIME", "LAST STDERR"]);
+            for report in &reports {
+                table.row([
+                    report.id.clone(),
+                    report.crashed_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+                    report.agent_name.clone(),
+                    report.exit_description(),
+                    format!("{}s", report.uptime_secs),
+                    report.stderr.last().map_or_else(|| "-".to_string(), |line| line.chars().take(60).collect()),
+                ]);
+            }
+            println!("{}", table);
+        }
+        CrashesCommand::Show { id } => {
+            let report = reports.find(&id)?;
+            if format == OutputFormat::Json {
+                return output::print_json(&report);
+            }
+            println!("Crash report {}", report.id);
+            println!("  Time:     {}", report.crashed_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S"));
+            println!("  Agent:    {} ({})", report.agent_name, report.agent_command);
+            println!("  Cwd:      {}", report.working_dir.display());
+            println!("  Exit:     {} after {}s", report.exit_description(), report.uptime_secs);
+            println!("\nLast stderr lines ({}):", report.stderr.len());
+            for line in &report.stderr {
+                println!("  {}", line);
+            }
+            println!("\nLast messages to the agent ({}):", report.stdin.len());
+            for message in &report.stdin {
+                println!("  {}", message);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `bridge export-bundle`: encrypt the bridge's identity to `file`.
+fn run_export_bundle(
+    file: &std::path::Path,
+    recipients: Vec<String>,
+    passphrase_file: Option<&std::path::Path>,
+    prompts: Prompts,
+) -> Result<()> {
+    use bridge::bundle::Protection;
+
+    let protection = if recipients.is_empty() {
+        Protection::Passphrase(bundle_passphrase(passphrase_file, true, prompts)?)
+    } else {
+        Protection::Recipients(recipients)
+    };
+    let bundle = bridge::bundle::export(&CommonConfig::config_dir(), file, &protection)?;
+    println!("Wrote {} ({}).", file.display(), bundle.files.keys().cloned().collect::<Vec<_>>().join(", "));
+    println!("It holds the auth token and TLS key: keep it as safe as the config directory.");
+    Ok(())
+}
+
+/// `bridge import-bundle`: restore a bundle into the config directory.
+fn run_import_bundle(
+    file: &std::path::Path,
+    identity: Option<std::path::PathBuf>,
+    passphrase_file: Option<&std::path::Path>,
+    force: bool,
+    prompts: Prompts,
+) -> Result<()> {
+    use bridge::bundle::Unlock;
+
+    let config_dir = CommonConfig::config_dir();
+    let force = force
+        || (config_dir.join("common.toml").exists()
+            && prompts.confirm(&format!("{} already has a common.toml. Replace it?", config_dir.display()))?);
+    let unlock = match identity {
+        Some(identity) => Unlock::Identity(identity),
+        None => Unlock::Passphrase(bundle_passphrase(passphrase_file, false, prompts)?),
+    };
+    let (bundle, written) = bridge::bundle::import(&config_dir, file, &unlock, force)?;
+    println!(
+        "Restored the bundle exported {} by bridge {}:",
+        bundle.created_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M"),
+        bundle.bridge_version
+    );
+    for path in &written {
+        println!("  {}", path.display());
+    }
+    println!("Run `bridge status` to check the transports from this machine.");
+    Ok(())
+}
+
+/// The bundle passphrase: the first line of `file`, BRIDGE_BUNDLE_PASSPHRASE
+/// (or the file BRIDGE_BUNDLE_PASSPHRASE_FILE names), or typed without echo
+/// (twice when `confirm`).
+fn bundle_passphrase(file: Option<&std::path::Path>, confirm: bool, prompts: Prompts) -> Result<String> {
+    let passphrase = if let Some(file) = file {
+        let contents = std::fs::read_to_string(file).with_context(|| format!("Failed to read {}", file.display()))?;
+        contents.lines().next().unwrap_or_default().to_string()
+    } else if let Ok(passphrase) = std::env::var("BRIDGE_BUNDLE_PASSPHRASE") {
+        passphrase
+    } else if let Ok(path) = std::env::var("BRIDGE_BUNDLE_PASSPHRASE_FILE") {
+        bridge::env_overrides::read_secret_file("BRIDGE_BUNDLE_PASSPHRASE_FILE", &path)?
+    } else if prompts.non_interactive {
+        anyhow::bail!("No passphrase — set BRIDGE_BUNDLE_PASSPHRASE or BRIDGE_BUNDLE_PASSPHRASE_FILE, or pass --passphrase-file");
+    } else {
+        let passphrase = read_hidden("Passphrase: ")?;
+        if confirm && read_hidden("Passphrase again: ")? != passphrase {
+            anyhow::bail!("The passphrases don't match");
+        }
+        passphrase
+    };
+    if passphrase.is_empty() {
+        anyhow::bail!("The passphrase is empty");
+    }
+    Ok(passphrase)
+}
+
+/// Read a line from the terminal without echoing it.
+fn read_hidden(prompt: &str) -> Result<String> {
+    use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+    use std::io::{IsTerminal, Write};
+
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!("No terminal to ask for the passphrase — set BRIDGE_BUNDLE_PASSPHRASE or pass --passphrase-file");
+    }
+    eprint!("{}", prompt);
+    std::io::stderr().flush()?;
+    crossterm::terminal::enable_raw_mode()?;
+    let mut line = String::new();
+    let result = loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => match key.code {
+                KeyCode::Enter => break Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break Err(anyhow::anyhow!("Cancelled")),
+                KeyCode::Char(c) => line.push(c),
+                KeyCode::Backspace => {
+                    line.pop();
+                }
+                _ => {}
+            },
+            Ok(_) => {}
+            Err(e) => break Err(e.into()),
+        }
+    };
+    crossterm::terminal::disable_raw_mode()?;
+    eprintln!();
+    result.map(|()| line)
+}
+
+/// `bridge top`: the dashboard of the bridge running from this config directory.
+async fn run_top(interval: u64) -> Result<()> {
+    use bridge::runtime_manifest::{self, RuntimeManifest};
+
+    let config = CommonConfig::load()?;
+    let config_dir = CommonConfig::config_dir();
+    let manifest = match RuntimeManifest::read(&config_dir)? {
+        Some(manifest) if runtime_manifest::is_live(&config_dir) => manifest,
+        _ => anyhow::bail!("No bridge is running from {} — start it with `bridge` first", config_dir.display()),
+    };
+    if config.admin_token.is_empty() {
+        anyhow::bail!("No admin_token in {} — the admin endpoints need one", CommonConfig::config_path().display());
+    }
+    let client = bridge::top::AdminClient::local(&manifest, config.admin_token.clone());
+    // Fail before taking over the terminal if the bridge won't answer.
+    client.status().await?;
+    let log_path = config_dir.join(bridge::log_file::LOG_FILENAME);
+    bridge::top::run(client, manifest, log_path, std::time::Duration::from_secs(interval)).await
+}
+
+/// `bridge wake-relay`: forward to the workstation configured under
+/// `[wake_relay]` until interrupted.
+async fn run_wake_relay() -> Result<()> {
+    let config = CommonConfig::load()?
+        .wake_relay
+        .ok_or_else(|| anyhow::anyhow!("No [wake_relay] section in {}", CommonConfig::config_path().display()))?;
+    config.validate()?;
+    eprintln!("Relaying {} → {} (waking {} via {})", config.listen, config.target, config.mac, config.broadcast);
+    bridge::wake_relay::run(config).await
+}
+
+/// `bridge logs`: print the last `lines` records at `level` or more severe
+/// from the running bridge's log file, then with `follow` keep printing new ones.
+async fn run_logs(follow: bool, level: u8, lines: usize) -> Result<()> {
+    use bridge::log_file;
+
+    let path = CommonConfig::config_dir().join(log_file::LOG_FILENAME);
+    if !path.exists() {
+        anyhow::bail!(
+            "No log file at {} — has the bridge been started with this config directory?",
+            path.display()
+        );
+    }
+    for line in log_file::read_recent(&path, level, lines)? {
+        println!("{}", line);
+    }
+    if follow {
+        log_file::follow(&path, level, |line| println!("{}", line)).await?;
+    }
+    Ok(())
+}
+
+/// Log to stderr for subcommands whose stdout is data (warnings only unless RUST_LOG is set).
+fn init_stderr_logging() {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn")),
+        )
+        .init();
+}
+
+/// Resolve the bridge to reach from `--url`/`--local` and the auth flags
+/// (`bridge connect` and `bridge bench`).
+fn connect_target(
+    url: Option<String>,
+    local: bool,
+    token: Option<String>,
+    fingerprint: Option<String>,
+) -> Result<bridge::connect::ConnectTarget> {
+    use bridge::connect::ConnectTarget;
+
+    let config = CommonConfig::load()?;
+    let mut target = match url {
+        Some(url) if !local => ConnectTarget { url, token: config.auth_token.clone(), fingerprint: None },
+        _ => ConnectTarget::local(&config)?,
+    };
+    if let Some(token) = token {
+        target.token = token;
+    }
+    if fingerprint.is_some() {
+        target.fingerprint = fingerprint;
+    }
+    if target.token.is_empty() {
+        anyhow::bail!("No auth token: pass --token or set auth_token in common.toml");
+    }
+    Ok(target)
+}
+
 /// Run the `bridge setup` Cloudflare wizard as a standalone TUI flow.
 ///
 /// This simply launches the TUI in a mode where the wizard starts at the
@@ -135,6 +1278,8 @@ async fn run_setup_wizard() -> Result<()> {
 
     let key_tx = event_tx.clone();
     std::thread::spawn(move || loop {
+        // Sending in a match guard would hide the side effect.
+        #[allow(clippy::collapsible_match)]
         match crossterm::event::read() {
             Ok(crossterm::event::Event::Key(key)) => {
                 if key_tx.blocking_send(AppEvent::Key(key)).is_err() { break; }
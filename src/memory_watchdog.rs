@@ -0,0 +1,139 @@
+//! Memory-pressure shedding for small hosts (`[memory_watchdog]`).
+//!
+//! The watchdog samples the bridge's resident set size. While it is above the
+//! configured limit the rate limiter refuses new connections (clients get
+//! 429 / close 1013 with `Retry-After`), and on entering that state every
+//! pooled agent's buffered messages are trimmed and a warning is logged and
+//! pushed. It accepts again once RSS drops below [`RESUME_RATIO`] of the limit,
+//! so it doesn't flap around the threshold.
+
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::agent_pool::AgentPool;
+use crate::common_config::MemoryWatchdogConfig;
+use crate::push::PushRelayClient;
+use crate::rate_limiter::RateLimiter;
+
+/// Fraction of the limit RSS must fall below before connections resume.
+pub const RESUME_RATIO: f64 = 0.9;
+
+/// Buffered messages kept per agent when trimming under pressure.
+const TRIM_KEEP: usize = 100;
+
+/// Resident set size of this process in bytes, where the platform exposes it.
+pub fn rss_bytes() -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        parse_vm_rss(&status)
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let output = std::process::Command::new("ps")
+            .args(["-o", "rss=", "-p", &std::process::id().to_string()])
+            .output()
+            .ok()?;
+        let kib: u64 = String::from_utf8_lossy(&output.stdout).trim().parse().ok()?;
+        Some(kib * 1024)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        None
+    }
+}
+
+/// `VmRSS` from `/proc/<pid>/status`, in bytes.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Whether the watchdog should be shedding after a sample of `rss_mb`.
+fn next_state(shedding: bool, rss_mb: u64, max_mb: u64) -> bool {
+    if shedding {
+        (rss_mb as f64) >= max_mb as f64 * RESUME_RATIO
+    } else {
+        rss_mb > max_mb
+    }
+}
+
+/// Sample RSS every `interval_secs` and shed load through `rate_limiter`
+/// while it is over `max_rss_mb`.
+pub fn start(
+    config: MemoryWatchdogConfig,
+    rate_limiter: Arc<RateLimiter>,
+    pool: Option<Arc<RwLock<AgentPool>>>,
+    push_relay: Option<Arc<PushRelayClient>>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        if rss_bytes().is_none() {
+            warn!("⚠️  [memory_watchdog] can't read memory usage on this platform — disabled");
+            return;
+        }
+        info!("🧮 Memory watchdog: shedding new connections above {} MB RSS", config.max_rss_mb);
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+        loop {
+            interval.tick().await;
+            let Some(rss) = rss_bytes() else { continue };
+            let rss_mb = rss / (1024 * 1024);
+            let was_shedding = rate_limiter.is_shedding();
+            let shedding = next_state(was_shedding, rss_mb, config.max_rss_mb);
+            if shedding == was_shedding {
+                continue;
+            }
+            rate_limiter.set_shedding(shedding);
+            if !shedding {
+                info!("✅ Memory back to {} MB — accepting connections again", rss_mb);
+                continue;
+            }
+
+            warn!("⚠️  Memory use {} MB exceeds {} MB — refusing new connections", rss_mb, config.max_rss_mb);
+            if let Some(ref pool) = pool {
+                let dropped = pool.read().await.trim_buffers(TRIM_KEEP).await;
+                if dropped > 0 {
+                    warn!("✂️  Trimmed {} buffered message(s) to free memory", dropped);
+                }
+            }
+            if let Some(ref relay) = push_relay {
+                let relay = Arc::clone(relay);
+                let max_mb = config.max_rss_mb;
+                tokio::spawn(async move {
+                    if let Err(e) = relay.notify_memory_pressure(rss_mb, max_mb).await {
+                        warn!("Failed to send memory pressure push: {}", e);
+                    }
+                });
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_vm_rss_from_proc_status() {
+        let status = "Name:\tbridge\nVmPeak:\t  300000 kB\nVmRSS:\t   20480 kB\nThreads:\t8\n";
+        assert_eq!(parse_vm_rss(status), Some(20480 * 1024));
+        assert_eq!(parse_vm_rss("Name:\tbridge\n"), None);
+    }
+
+    #[test]
+    fn sheds_above_limit_and_resumes_below_ninety_percent() {
+        assert!(!next_state(false, 512, 512));
+        assert!(next_state(false, 513, 512));
+        assert!(next_state(true, 500, 512));
+        assert!(!next_state(true, 460, 512));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn reads_own_rss() {
+        assert!(rss_bytes().unwrap() > 0);
+    }
+}
@@ -0,0 +1,81 @@
+//! `bridge pair --nfc-export`: the pairing URL as an NDEF message, for
+//! writing to an NFC tag with any tag-writing app or reader.
+//!
+//! The tag carries [`crate::pairing::stable_pairing_url`], which has no
+//! one-time code: tapping it opens the app's pairing screen, which asks for
+//! the code the bridge is showing (TUI `/qr` or `bridge pair --serve`). The
+//! tag is written once and stays valid while codes rotate, so devices that
+//! are re-provisioned often pair with a tap plus six digits, and a lost tag
+//! gives nobody the auth token.
+//!
+//! The file holds one NDEF URI record (NFC Forum RTD URI), without the
+//! TLV wrapping of a particular tag type.
+
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// URI prefixes the URI record abbreviates to one byte (NFC Forum RTD URI,
+/// table 3); only those pairing URLs can start with.
+const URI_PREFIXES: [(u8, &str); 4] = [(0x02, "https://www."), (0x01, "http://www."), (0x04, "https://"), (0x03, "http://")];
+
+/// Common tags and their user memory in bytes.
+const TAGS: [(&str, usize); 3] = [("NTAG213", 144), ("NTAG215", 504), ("NTAG216", 888)];
+
+/// A one-record NDEF message holding `uri`.
+pub fn uri_message(uri: &str) -> Vec<u8> {
+    let (code, rest) = URI_PREFIXES
+        .iter()
+        .find_map(|(code, prefix)| uri.strip_prefix(prefix).map(|rest| (*code, rest)))
+        .unwrap_or((0x00, uri));
+    let mut payload = Vec::with_capacity(rest.len() + 1);
+    payload.push(code);
+    payload.extend_from_slice(rest.as_bytes());
+
+    // MB | ME, SR for payloads that fit a one-byte length; TNF 1 = well-known type
+    let short = payload.len() <= u8::MAX as usize;
+    let mut message = vec![0x80 | 0x40 | if short { 0x10 } else { 0 } | 0x01, 1];
+    if short {
+        message.push(payload.len() as u8);
+    } else {
+        message.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    }
+    message.push(b'U');
+    message.extend(payload);
+    message
+}
+
+/// The smallest common tag `message` fits on, with its TLV and terminator.
+pub fn smallest_tag(message: &[u8]) -> Option<&'static str> {
+    let length_bytes = if message.len() < 0xFF { 1 } else { 3 };
+    let needed = 1 + length_bytes + message.len() + 1;
+    TAGS.iter().find(|(_, memory)| needed <= *memory).map(|(name, _)| *name)
+}
+
+/// Write the NDEF message of `uri` to `path`, returning its size.
+pub fn export(path: &Path, uri: &str) -> Result<usize> {
+    let message = uri_message(uri);
+    std::fs::write(path, &message).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(message.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uri_records_abbreviate_the_scheme() {
+        let message = uri_message("https://10.0.0.2:8765/pair/local");
+        assert_eq!(&message[..5], &[0xD1, 0x01, 25, b'U', 0x04]);
+        assert_eq!(&message[5..], b"10.0.0.2:8765/pair/local");
+        assert_eq!(smallest_tag(&message), Some("NTAG213"));
+
+        let link = uri_message("aptove://pair?url=x");
+        assert_eq!((link[2], link[4]), (20, 0x00));
+
+        let long = uri_message(&format!("https://{}", "a".repeat(300)));
+        assert_eq!(&long[..7], &[0xC1, 0x01, 0, 0, 1, 45, b'U']);
+        assert_eq!(long.len(), 7 + 301);
+        assert_eq!(smallest_tag(&long), Some("NTAG215"));
+        assert_eq!(smallest_tag(&uri_message(&"a".repeat(900))), None);
+    }
+}
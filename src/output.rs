@@ -0,0 +1,160 @@
+//! Output of the reporting subcommands, as an aligned table for people or as
+//! JSON for scripts (`--output json`).
+//!
+//! Subcommands build their result as a value that is both `Serialize` and
+//! `Display` and hand it to [`print`]; the `Display` side lays out lists with
+//! [`Table`] and highlights states with [`paint`], so every command aligns
+//! and colours the same way. Colour is only used on a terminal and never
+//! when `NO_COLOR` is set.
+
+use std::fmt;
+use std::io::IsTerminal;
+
+use anyhow::Result;
+use crossterm::style::{Color, Stylize};
+use serde::Serialize;
+
+/// `--output` of the reporting subcommands.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Aligned, coloured text.
+    #[default]
+    Table,
+    /// Pretty-printed JSON on stdout, nothing else.
+    Json,
+}
+
+/// Print `value` to stdout in `format`.
+pub fn print<T: Serialize + fmt::Display>(format: OutputFormat, value: &T) -> Result<()> {
+    match format {
+        OutputFormat::Table => println!("{}", value),
+        OutputFormat::Json => print_json(value)?,
+    }
+    Ok(())
+}
+
+/// Print `value` to stdout as pretty JSON, for results whose table form is
+/// built separately.
+pub fn print_json<T: Serialize + ?Sized>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Whether stdout gets colour.
+pub fn color_enabled() -> bool {
+    std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && std::io::stdout().is_terminal()
+}
+
+/// Semantic colours for states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tone {
+    Plain,
+    Good,
+    Bad,
+    Muted,
+}
+
+/// `text` in `tone`, when stdout gets colour.
+pub fn paint(text: &str, tone: Tone) -> String {
+    paint_if(color_enabled(), text, tone)
+}
+
+fn paint_if(color: bool, text: &str, tone: Tone) -> String {
+    if !color {
+        return text.to_string();
+    }
+    match tone {
+        Tone::Plain => text.to_string(),
+        Tone::Good => text.with(Color::Green).to_string(),
+        Tone::Bad => text.with(Color::Red).to_string(),
+        Tone::Muted => text.with(Color::DarkGrey).to_string(),
+    }
+}
+
+/// Rows under a header, each column padded to its widest cell.
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    headers: Vec<String>,
+    rows: Vec<Vec<(String, Tone)>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&str]) -> Self {
+        Self { headers: headers.iter().map(|h| h.to_string()).collect(), rows: Vec::new() }
+    }
+
+    /// Append a row of plain cells.
+    pub fn row<S: Into<String>>(&mut self, cells: impl IntoIterator<Item = S>) -> &mut Self {
+        self.toned_row(cells.into_iter().map(|c| (c, Tone::Plain)))
+    }
+
+    /// Append a row whose cells each have a [`Tone`].
+    pub fn toned_row<S: Into<String>>(&mut self, cells: impl IntoIterator<Item = (S, Tone)>) -> &mut Self {
+        self.rows.push(cells.into_iter().map(|(c, tone)| (c.into(), tone)).collect());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    fn widths(&self) -> Vec<usize> {
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| h.chars().count()).collect();
+        for row in &self.rows {
+            for (i, (cell, _)) in row.iter().enumerate() {
+                if i >= widths.len() {
+                    widths.push(0);
+                }
+                widths[i] = widths[i].max(cell.chars().count());
+            }
+        }
+        widths
+    }
+
+    /// The table as lines, coloured when `color` is set.
+    pub fn render(&self, color: bool) -> String {
+        let widths = self.widths();
+        // Pad before painting so escape codes don't count towards the width;
+        // the last column isn't padded to keep lines free of trailing spaces.
+        let line = |cells: Vec<(&str, Tone)>| {
+            let last = cells.len().saturating_sub(1);
+            cells
+                .into_iter()
+                .enumerate()
+                .map(|(i, (cell, tone))| {
+                    let padded = if i == last { cell.to_string() } else { format!("{:<width$}", cell, width = widths[i]) };
+                    paint_if(color, &padded, tone)
+                })
+                .collect::<Vec<_>>()
+                .join("  ")
+        };
+        let mut lines = vec![line(self.headers.iter().map(|h| (h.as_str(), Tone::Muted)).collect())];
+        lines.extend(self.rows.iter().map(|row| line(row.iter().map(|(c, tone)| (c.as_str(), *tone)).collect())));
+        lines.join("\n")
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(color_enabled()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columns_are_aligned_to_the_widest_cell() {
+        let mut table = Table::new(&["NAME", "STATE", "URL"]);
+        table.row(["local", "reachable", "wss://10.0.0.5:8765"]);
+        table.toned_row([("tailscale-serve", Tone::Bad), ("unreachable", Tone::Bad), ("-", Tone::Plain)]);
+        assert_eq!(
+            table.render(false),
+            "NAME             STATE        URL\n\
+             local            reachable    wss://10.0.0.5:8765\n\
+             tailscale-serve  unreachable  -"
+        );
+        assert!(table.render(true).contains("\u{1b}["));
+    }
+}
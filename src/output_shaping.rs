@@ -0,0 +1,312 @@
+//! Rate shaping of agent output to WebSocket clients on slow or metered
+//! links (`[output_shaping]` in `common.toml`).
+//!
+//! Agents stream replies token by token, one `session/update` notification
+//! each. [`OutputShaper`] holds those notifications back to a configured
+//! number of messages and bytes per second, with a burst allowance, and
+//! merges consecutive text chunks of one streamed message while they wait,
+//! so the phone gets fewer, larger frames. Everything else — responses and
+//! agent requests — is never held: it is sent at once, after whatever is
+//! queued, so the client still sees messages in order.
+
+use std::collections::VecDeque;
+use tokio::time::{Duration, Instant};
+
+use crate::agent_pool::ChunkKey;
+use crate::common_config::OutputShapingConfig;
+
+/// Queued messages at which the connection stops reading agent output until
+/// the queue drains.
+const MAX_QUEUED: usize = 1000;
+
+/// Tokens refilled at `rate` per second, up to `capacity`. Spending more
+/// than is left leaves a debt that has to be refilled first.
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64, burst_secs: u64, now: Instant) -> Option<Self> {
+        let rate = rate as f64;
+        let capacity = rate * burst_secs as f64;
+        (rate > 0.0).then_some(Self { rate, capacity, tokens: capacity, updated: now })
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// When `cost` can be spent. A cost over the capacity only needs a full
+    /// bucket.
+    fn ready_at(&self, cost: f64) -> Instant {
+        let needed = cost.min(self.capacity) - self.tokens;
+        if needed <= 0.0 {
+            self.updated
+        } else {
+            self.updated + Duration::from_secs_f64(needed / self.rate)
+        }
+    }
+}
+
+/// Per-connection output shaping; see the module docs.
+#[derive(Debug)]
+pub struct OutputShaper {
+    messages: Option<Bucket>,
+    bytes: Option<Bucket>,
+    /// Held messages with the sequence number of the last agent message
+    /// each covers.
+    queue: VecDeque<(u64, String)>,
+    /// A text chunk held after everything in `queue`, which later chunks of
+    /// the same message are appended to.
+    open: Option<OpenChunk>,
+}
+
+/// A held text chunk, kept parsed with its text apart so appending costs
+/// only the appended text. It is serialized once, when it is sent.
+#[derive(Debug)]
+struct OpenChunk {
+    /// Sequence number of the last agent message merged in.
+    seq: u64,
+    key: ChunkKey,
+    /// The first chunk, with an empty text.
+    message: serde_json::Value,
+    text: String,
+    /// Length of the chunk once serialized.
+    len: usize,
+}
+
+impl OpenChunk {
+    fn new(seq: u64, key: ChunkKey, mut message: serde_json::Value) -> Option<Self> {
+        let text = message.pointer_mut("/params/update/content/text")?.take();
+        let text = text.as_str()?.to_string();
+        *message.pointer_mut("/params/update/content/text")? = "".into();
+        let len = message.to_string().len() + escaped_len(&text);
+        Some(Self { seq, key, message, text, len })
+    }
+
+    fn append(&mut self, seq: u64, text: &str) {
+        self.seq = seq;
+        self.text.push_str(text);
+        self.len += escaped_len(text);
+    }
+
+    fn into_line(mut self) -> (u64, String) {
+        if let Some(text) = self.message.pointer_mut("/params/update/content/text") {
+            *text = self.text.into();
+        }
+        (self.seq, self.message.to_string())
+    }
+}
+
+/// Bytes `text` takes inside a JSON string.
+fn escaped_len(text: &str) -> usize {
+    serde_json::to_string(text).map_or(text.len(), |quoted| quoted.len() - 2)
+}
+
+impl OutputShaper {
+    /// A shaper for `config`, or `None` when it sets no limit.
+    pub fn new(config: &OutputShapingConfig) -> Option<Self> {
+        let now = Instant::now();
+        let messages = Bucket::new(config.messages_per_sec, config.burst_secs, now);
+        let bytes = Bucket::new(config.bytes_per_sec, config.burst_secs, now);
+        (messages.is_some() || bytes.is_some()).then(|| Self { messages, bytes, queue: VecDeque::new(), open: None })
+    }
+
+    /// Whether `line` is held back by the shaper: `session/update`
+    /// notifications are, everything else is sent at once.
+    pub fn shapes(line: &str) -> bool {
+        line.contains("\"session/update\"")
+            && serde_json::from_str::<serde_json::Value>(line)
+                .is_ok_and(|v| v.get("method").and_then(|m| m.as_str()) == Some("session/update") && v.get("id").is_none())
+    }
+
+    /// Queue agent message `seq`, merging it into the last queued message
+    /// when both are text chunks of the same streamed message (see
+    /// [`collapse_chunks`](crate::agent_pool::collapse_chunks)). The text is
+    /// appended to the held chunk, so a long reply costs no more than its
+    /// length.
+    pub fn push(&mut self, seq: u64, line: String) {
+        let chunk = serde_json::from_str::<serde_json::Value>(&line)
+            .ok()
+            .and_then(|v| Some((ChunkKey::of(&v)?, v)));
+        let Some((key, value)) = chunk else {
+            self.close_open();
+            self.queue.push_back((seq, line));
+            return;
+        };
+        if let Some(open) = self.open.as_mut().filter(|o| o.key == key) {
+            open.append(seq, value["params"]["update"]["content"]["text"].as_str().unwrap_or_default());
+            return;
+        }
+        self.close_open();
+        match OpenChunk::new(seq, key, value) {
+            Some(open) => self.open = Some(open),
+            None => self.queue.push_back((seq, line)),
+        }
+    }
+
+    /// Whether the queue is long enough that no more output should be read.
+    pub fn is_full(&self) -> bool {
+        self.queue.len() + usize::from(self.open.is_some()) >= MAX_QUEUED
+    }
+
+    /// When the next queued message may be sent, if any is queued.
+    pub fn ready_at(&self) -> Option<Instant> {
+        let len = match self.queue.front() {
+            Some((_, line)) => line.len(),
+            None => self.open.as_ref()?.len,
+        };
+        let at = [(&self.messages, 1.0), (&self.bytes, len as f64)]
+            .into_iter()
+            .filter_map(|(bucket, cost)| bucket.as_ref().map(|b| b.ready_at(cost)))
+            .max();
+        at.or_else(|| Some(Instant::now()))
+    }
+
+    /// The next queued message, if the limits allow sending it at `now`.
+    pub fn pop_ready(&mut self, now: Instant) -> Option<(u64, String)> {
+        self.refill(now);
+        if self.ready_at()? > now {
+            return None;
+        }
+        let (seq, line) = match self.queue.pop_front() {
+            Some(queued) => queued,
+            None => self.open.take()?.into_line(),
+        };
+        self.spend(&line);
+        Some((seq, line))
+    }
+
+    /// Everything queued, to be sent now regardless of the limits: ahead of
+    /// a message that isn't shaped, or when the connection ends.
+    pub fn flush(&mut self) -> Vec<(u64, String)> {
+        self.refill(Instant::now());
+        self.close_open();
+        let batch: Vec<_> = self.queue.drain(..).collect();
+        for (_, line) in &batch {
+            self.spend(line);
+        }
+        batch
+    }
+
+    /// Queue the held chunk as a line; nothing more is appended to it.
+    fn close_open(&mut self) {
+        if let Some(open) = self.open.take() {
+            self.queue.push_back(open.into_line());
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        self.messages.iter_mut().chain(self.bytes.iter_mut()).for_each(|b| b.refill(now));
+    }
+
+    fn spend(&mut self, line: &str) {
+        if let Some(ref mut bucket) = self.messages {
+            bucket.tokens -= 1.0;
+        }
+        if let Some(ref mut bucket) = self.bytes {
+            bucket.tokens -= line.len() as f64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(kind: &str, text: &str) -> String {
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "session/update",
+            "params": { "sessionId": "s1", "update": { "sessionUpdate": kind, "content": { "type": "text", "text": text } } }
+        })
+        .to_string()
+    }
+
+    fn text(line: &str) -> String {
+        let v: serde_json::Value = serde_json::from_str(line).unwrap();
+        v["params"]["update"]["content"]["text"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn held_chunks_are_merged_into_fewer_frames() {
+        let config = OutputShapingConfig { messages_per_sec: 2, bytes_per_sec: 0, burst_secs: 1 };
+        let mut shaper = OutputShaper::new(&config).unwrap();
+        let start = Instant::now();
+        assert!(OutputShaper::shapes(&chunk("agent_message_chunk", "a")));
+        assert!(!OutputShaper::shapes(r#"{"jsonrpc":"2.0","id":1,"result":{"stopReason":"end_turn"}}"#));
+
+        // The burst covers two messages; the rest wait, merged by kind.
+        for (seq, (kind, t)) in
+            [("agent_message_chunk", "He"), ("agent_message_chunk", "llo"), ("agent_thought_chunk", "hm"), ("agent_thought_chunk", "m")]
+                .into_iter()
+                .enumerate()
+        {
+            shaper.push(seq as u64 + 1, chunk(kind, t));
+            if seq == 1 {
+                let (first, line) = shaper.pop_ready(start).unwrap();
+                assert_eq!((first, text(&line)), (2, "Hello".to_string()));
+            }
+        }
+        let (seq, line) = shaper.pop_ready(start).unwrap();
+        assert_eq!((seq, text(&line)), (4, "hmm".to_string()));
+
+        shaper.push(5, chunk("agent_message_chunk", "x"));
+        shaper.push(6, r#"{"jsonrpc":"2.0","method":"session/update","params":{"sessionId":"s1","update":{"sessionUpdate":"tool_call"}}}"#.into());
+        assert!(shaper.pop_ready(start).is_none());
+        assert!(shaper.ready_at().unwrap() >= start + Duration::from_millis(499));
+        assert_eq!(shaper.pop_ready(start + Duration::from_millis(500)).map(|(seq, _)| seq), Some(5));
+
+        // Unshaped messages flush the queue.
+        let batch = shaper.flush();
+        assert_eq!(batch.iter().map(|(seq, _)| *seq).collect::<Vec<_>>(), [6]);
+        assert!(shaper.ready_at().is_none());
+    }
+
+    #[test]
+    fn long_replies_are_appended_in_place() {
+        let config = OutputShapingConfig { messages_per_sec: 1, bytes_per_sec: 0, burst_secs: 1 };
+        let mut shaper = OutputShaper::new(&config).unwrap();
+        let parts = ["Say \"hi\"", "\n", "naïve ", "\u{1}", "😀"];
+        let mut expected = String::new();
+        for seq in 1..=1000 {
+            let part = parts[seq % parts.len()];
+            expected.push_str(part);
+            shaper.push(seq as u64, chunk("agent_message_chunk", part));
+        }
+        let held_len = shaper.open.as_ref().unwrap().len;
+        let batch = shaper.flush();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].1.len(), held_len);
+        assert_eq!((batch[0].0, text(&batch[0].1)), (1000, expected));
+
+        // Another message id starts a new frame
+        let with_id = |id: &str, t: &str| chunk("agent_message_chunk", t).replace("\"content\"", &format!("\"messageId\":\"{id}\",\"content\""));
+        shaper.push(1, with_id("m1", "a"));
+        shaper.push(2, with_id("m1", "b"));
+        shaper.push(3, with_id("m2", "c"));
+        let texts: Vec<_> = shaper.flush().iter().map(|(_, line)| text(line)).collect();
+        assert_eq!(texts, ["ab", "c"]);
+    }
+
+    #[test]
+    fn large_messages_wait_for_a_full_bucket() {
+        let config = OutputShapingConfig { messages_per_sec: 0, bytes_per_sec: 100, burst_secs: 1 };
+        let mut shaper = OutputShaper::new(&config).unwrap();
+        let start = Instant::now();
+        shaper.push(1, "x".repeat(250));
+        assert_eq!(shaper.pop_ready(start).map(|(seq, _)| seq), Some(1));
+        // 150 bytes of debt plus a full bucket: 2.5 s.
+        shaper.push(2, "y".repeat(250));
+        assert!(shaper.pop_ready(start + Duration::from_secs(2)).is_none());
+        assert!(shaper.pop_ready(start + Duration::from_millis(2500)).is_some());
+
+        assert!(OutputShaper::new(&OutputShapingConfig::default()).is_none());
+    }
+}
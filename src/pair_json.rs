@@ -0,0 +1,168 @@
+//! `bridge pair --local-dev`: the connection JSON for simulator-based app
+//! development, without scanning a QR code off the monitor.
+//!
+//! The JSON (the same payload as `bridge show-qr`) is written to
+//! `pair.json` in the config directory (permissions `0600`), copied to the
+//! clipboard, and served at `http://127.0.0.1:<port>/pair.json` until
+//! Ctrl-C. The endpoint answers CORS requests from pages on `localhost`,
+//! `127.0.0.1` or `[::1]` only: the payload carries the auth token, and any
+//! other site open in a browser on this machine could otherwise read it.
+//! Requests without an `Origin` (native apps, `curl`) are always answered.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tracing::warn;
+
+/// Port of the endpoint when `--port` isn't given.
+pub const DEFAULT_PORT: u16 = 8767;
+
+/// The file in the config directory, and the path it's served at.
+pub const FILENAME: &str = "pair.json";
+
+/// How long a client may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Write `json` to [`FILENAME`] in `dir`, readable by this user only.
+pub fn write(dir: &Path, json: &str) -> Result<PathBuf> {
+    let path = dir.join(FILENAME);
+    crate::private_file::write(&path, json).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Whether a page at `origin` runs on this machine.
+fn is_local_origin(origin: &str) -> bool {
+    let Some((scheme, rest)) = origin.split_once("://") else {
+        return false;
+    };
+    let host = match rest.strip_prefix('[') {
+        Some(v6) => v6.split_once(']').map_or("", |(host, _)| host),
+        None => rest.split(':').next().unwrap_or(""),
+    };
+    matches!(scheme, "http" | "https") && matches!(host, "localhost" | "127.0.0.1" | "::1")
+}
+
+/// The answer to `request`.
+pub fn response(request: &str, json: &str) -> String {
+    let mut lines = request.lines();
+    let first_line = lines.next().unwrap_or("");
+    let origin = lines
+        .take_while(|l| !l.is_empty())
+        .find_map(|l| l.split_once(':').filter(|(name, _)| name.trim().eq_ignore_ascii_case("origin")))
+        .map(|(_, value)| value.trim());
+    if origin.is_some_and(|o| !is_local_origin(o)) {
+        return http_response(403, "Forbidden", None, r#"{"error":"origin_not_allowed"}"#);
+    }
+    let path = crate::bridge::request_path(request);
+    let method = first_line.split_whitespace().next().unwrap_or("");
+    match (method, path == format!("/{}", FILENAME)) {
+        ("OPTIONS", true) => http_response(204, "No Content", origin, ""),
+        ("GET", true) => http_response(200, "OK", origin, json),
+        (_, true) => http_response(405, "Method Not Allowed", origin, r#"{"error":"method_not_allowed"}"#),
+        _ => http_response(404, "Not Found", origin, r#"{"error":"not_found"}"#),
+    }
+}
+
+fn http_response(status: u16, text: &str, origin: Option<&str>, body: &str) -> String {
+    let cors = origin
+        .map(|o| {
+            format!(
+                "Access-Control-Allow-Origin: {}\r\n\
+                 Access-Control-Allow-Methods: GET, OPTIONS\r\n\
+                 Access-Control-Allow-Headers: *\r\n\
+                 Vary: Origin\r\n",
+                o
+            )
+        })
+        .unwrap_or_default();
+    format!(
+        "HTTP/1.1 {} {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Cache-Control: no-store\r\n\
+         {}\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        status,
+        text,
+        body.len(),
+        cors,
+        body
+    )
+}
+
+/// Answer requests on `listener` with `json`. Never resolves unless
+/// accepting fails.
+pub async fn serve(listener: TcpListener, json: String) -> Result<()> {
+    loop {
+        let (mut stream, _) = listener.accept().await.context("Failed to accept a connection")?;
+        let json = json.clone();
+        tokio::spawn(async move {
+            let mut buffer = vec![0u8; 8192];
+            let n = match tokio::time::timeout(REQUEST_TIMEOUT, stream.read(&mut buffer)).await {
+                Ok(Ok(n)) => n,
+                _ => return,
+            };
+            let request = String::from_utf8_lossy(&buffer[..n]);
+            if let Err(e) = stream.write_all(response(&request, &json).as_bytes()).await {
+                warn!("Failed to answer a {} request: {}", FILENAME, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSON: &str = r#"{"url":"wss://192.168.1.2:8765","authToken":"token"}"#;
+
+    #[test]
+    fn only_pages_on_this_machine_get_the_token() {
+        let get = |origin: Option<&str>| {
+            let origin = origin.map(|o| format!("Origin: {}\r\n", o)).unwrap_or_default();
+            response(&format!("GET /pair.json HTTP/1.1\r\nHost: 127.0.0.1\r\n{}\r\n", origin), JSON)
+        };
+        let native = get(None);
+        assert!(native.starts_with("HTTP/1.1 200"));
+        assert!(native.ends_with(JSON));
+        assert!(!native.contains("Access-Control"));
+
+        let expo = get(Some("http://localhost:19006"));
+        assert!(expo.starts_with("HTTP/1.1 200"));
+        assert!(expo.contains("Access-Control-Allow-Origin: http://localhost:19006\r\n"));
+        assert!(get(Some("http://[::1]:3000")).starts_with("HTTP/1.1 200"));
+
+        for origin in ["https://evil.example", "http://localhost.evil.example", "null"] {
+            let refused = get(Some(origin));
+            assert!(refused.starts_with("HTTP/1.1 403"), "{origin}");
+            assert!(!refused.contains("token"));
+        }
+
+        let preflight = response("OPTIONS /pair.json HTTP/1.1\r\nOrigin: http://127.0.0.1:8081\r\n\r\n", JSON);
+        assert!(preflight.starts_with("HTTP/1.1 204"));
+        assert!(preflight.contains("Access-Control-Allow-Methods: GET, OPTIONS"));
+        assert!(response("GET /other HTTP/1.1\r\n\r\n", JSON).starts_with("HTTP/1.1 404"));
+    }
+
+    #[tokio::test]
+    async fn serves_the_json_over_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, JSON.to_string()));
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /pair.json?t=1 HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut answer = String::new();
+        stream.read_to_string(&mut answer).await.unwrap();
+        assert!(answer.starts_with("HTTP/1.1 200"), "{answer}");
+        assert!(answer.ends_with(JSON));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(dir.path(), JSON).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), JSON);
+    }
+}
@@ -0,0 +1,317 @@
+//! `bridge pair --serve`: a pairing-only server for provisioning several
+//! devices in a row.
+//!
+//! Only the `/pair/...` endpoints are served; no agent is started. Every time
+//! a code is used or expires, a fresh one is issued and shown, without a
+//! prompt, until `max_pairings` devices have paired or the process is
+//! interrupted. While a code is showing, its countdown and every wrong code
+//! tried (with the source IP) are reported as [`PairEvent`]s. The run ends
+//! with a summary of the devices that paired.
+
+use anyhow::{Context, Result};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::auth_failures::AuthFailures;
+use crate::bridge::{create_http_response, handle_pairing_request, HandshakeTimeouts, PairingOutcome};
+use crate::pairing::{PairingDevice, PairingManager};
+use crate::tls::TlsConfig;
+
+/// How often the current code is checked for expiry.
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A device that paired during the run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairedDevice {
+    pub device: PairingDevice,
+    /// Local time of the pairing, `HH:MM:SS`
+    pub at: String,
+}
+
+/// What a pairing run did, printed when it ends.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PairSummary {
+    pub paired: Vec<PairedDevice>,
+    /// Codes shown, including the one showing when the run ended
+    pub codes_issued: usize,
+    /// Wrong codes tried, across all codes
+    pub failed_attempts: usize,
+    pub elapsed: Duration,
+}
+
+/// What [`PairServer::run`] reports for display.
+#[derive(Clone, Copy)]
+pub enum PairEvent<'a> {
+    /// A code was issued, with the number of devices paired so far
+    Code(&'a PairingManager, usize),
+    /// Once a second while a code is showing
+    Tick(&'a PairingManager),
+    /// A wrong code was tried from this IP address
+    FailedAttempt(&'a PairingManager, &'a str),
+}
+
+impl fmt::Display for PairSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.elapsed.as_secs();
+        writeln!(
+            f,
+            "Paired {} device(s) in {}m {:02}s ({} code(s) issued, {} wrong code(s) tried)",
+            self.paired.len(),
+            secs / 60,
+            secs % 60,
+            self.codes_issued,
+            self.failed_attempts
+        )?;
+        for paired in &self.paired {
+            writeln!(f, "  {}  {}", paired.at, paired.device)?;
+        }
+        Ok(())
+    }
+}
+
+/// Server state: where to listen, how to answer, and when to stop.
+pub struct PairServer {
+    listener: TcpListener,
+    tls: Option<tokio_rustls::TlsAcceptor>,
+    timeouts: HandshakeTimeouts,
+    auth_failures: Option<Arc<AuthFailures>>,
+    max_pairings: Option<usize>,
+}
+
+impl PairServer {
+    pub fn new(listener: TcpListener, tls: Option<TlsConfig>) -> Self {
+        Self { listener, tls: tls.map(|t| t.acceptor), timeouts: HandshakeTimeouts::default(), auth_failures: None, max_pairings: None }
+    }
+
+    /// Time limits for the TLS handshake and the request.
+    pub fn with_timeouts(mut self, timeouts: HandshakeTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Count and tarpit wrong codes like the bridge does.
+    pub fn with_auth_failures(mut self, auth_failures: Option<Arc<AuthFailures>>) -> Self {
+        self.auth_failures = auth_failures;
+        self
+    }
+
+    /// Stop after this many devices have paired (default: never).
+    pub fn with_max_pairings(mut self, max_pairings: Option<usize>) -> Self {
+        self.max_pairings = max_pairings;
+        self
+    }
+
+    /// Serve pairing requests with `manager`'s details until `max_pairings`
+    /// devices paired or `shutdown` resolves. `report` is called with each
+    /// code issued, starting with `manager`'s, each wrong code tried, and
+    /// once a second in between.
+    pub async fn run<F>(
+        self,
+        manager: PairingManager,
+        mut report: F,
+        shutdown: impl std::future::Future<Output = ()>,
+    ) -> Result<PairSummary>
+    where
+        F: FnMut(PairEvent<'_>),
+    {
+        let started = Instant::now();
+        let mut summary = PairSummary::default();
+        if self.max_pairings == Some(0) {
+            return Ok(summary);
+        }
+        let current = Arc::new(manager);
+        report(PairEvent::Code(&current, 0));
+        summary.codes_issued = 1;
+
+        // Every answered request reports here, with the client's address.
+        let (done_tx, mut done_rx) = mpsc::channel::<(String, PairingOutcome)>(16);
+        let mut expiry_check = tokio::time::interval(EXPIRY_CHECK_INTERVAL);
+        tokio::pin!(shutdown);
+        loop {
+            let renew = tokio::select! {
+                accepted = self.listener.accept() => {
+                    let (stream, addr) = accepted.context("Failed to accept a connection")?;
+                    let manager = Arc::clone(&current);
+                    let tls = self.tls.clone();
+                    let timeouts = self.timeouts;
+                    let auth_failures = self.auth_failures.clone();
+                    let done_tx = done_tx.clone();
+                    tokio::spawn(async move {
+                        let client_ip = addr.ip().to_string();
+                        let result = match tls {
+                            Some(acceptor) => match tokio::time::timeout(timeouts.tls, acceptor.accept(stream)).await {
+                                Ok(Ok(stream)) => answer(stream, manager, auth_failures.as_deref(), &client_ip, timeouts).await,
+                                Ok(Err(e)) => Err(anyhow::anyhow!("TLS handshake failed: {}", e)),
+                                Err(_) => Err(anyhow::anyhow!("TLS handshake timed out")),
+                            },
+                            None => answer(stream, manager, auth_failures.as_deref(), &client_ip, timeouts).await,
+                        };
+                        let outcome = result.unwrap_or_else(|e| {
+                            debug!("Pairing connection from {} failed: {:#}", client_ip, e);
+                            PairingOutcome::Refused
+                        });
+                        let _ = done_tx.send((client_ip, outcome)).await;
+                    });
+                    false
+                }
+                Some((client_ip, outcome)) = done_rx.recv() => {
+                    match outcome {
+                        PairingOutcome::Paired(device) => {
+                            info!("📱 {} paired", device);
+                            summary.paired.push(PairedDevice { device, at: chrono::Local::now().format("%H:%M:%S").to_string() });
+                            if self.max_pairings.is_some_and(|max| summary.paired.len() >= max) {
+                                break;
+                            }
+                        }
+                        PairingOutcome::WrongCode => {
+                            summary.failed_attempts += 1;
+                            report(PairEvent::FailedAttempt(&current, &client_ip));
+                        }
+                        PairingOutcome::Refused => {}
+                    }
+                    // A code is single use, whether the device paired or was declined.
+                    current.is_used()
+                }
+                _ = expiry_check.tick() => {
+                    let expired = current.is_expired();
+                    if !expired {
+                        report(PairEvent::Tick(&current));
+                    }
+                    expired
+                }
+                _ = &mut shutdown => break,
+            };
+            if renew {
+                current.renew();
+                summary.codes_issued += 1;
+                report(PairEvent::Code(&current, summary.paired.len()));
+            }
+        }
+        summary.elapsed = started.elapsed();
+        Ok(summary)
+    }
+}
+
+/// Read one request and answer it if it's for `/pair/...`.
+async fn answer<S>(
+    mut stream: S,
+    manager: Arc<PairingManager>,
+    auth_failures: Option<&AuthFailures>,
+    client_ip: &str,
+    timeouts: HandshakeTimeouts,
+) -> Result<PairingOutcome>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buffer = vec![0u8; 8192];
+    let n = match tokio::time::timeout(timeouts.request, stream.read(&mut buffer)).await {
+        Ok(result) => result.context("Failed to read request")?,
+        Err(_) => return Ok(PairingOutcome::Refused),
+    };
+    let request = String::from_utf8_lossy(&buffer[..n]);
+    if let Some(delay) = auth_failures.and_then(|f| f.tarpit(client_ip)) {
+        tokio::time::sleep(delay).await;
+    }
+    let first_line = request.lines().next().unwrap_or("");
+    if first_line.starts_with("GET /pair/") || first_line.starts_with(&format!("GET {}", crate::pairing::SHORT_PAIRING_PATH)) {
+        return Ok(handle_pairing_request(&mut stream, &request, Some(manager), auth_failures, client_ip).await?);
+    }
+    warn!("🚫 {} asked for {} — only pairing is served", client_ip, crate::bridge::request_path(&request));
+    let response = create_http_response(404, "Not Found", r#"{"error":"not_found","message":"This bridge is only pairing devices"}"#);
+    stream.write_all(response.as_bytes()).await?;
+    Ok(PairingOutcome::Refused)
+}
+
+/// The live status line under a code: time left and wrong codes tried.
+pub fn countdown_line(manager: &PairingManager) -> String {
+    let secs = manager.seconds_remaining();
+    let left = manager.attempts_left();
+    let attempts = match left {
+        0 => "no attempts left, a new code follows expiry".to_string(),
+        1 => "1 attempt left".to_string(),
+        n => format!("{} attempts left", n),
+    };
+    format!("⏱️  Expires in {}:{:02} | {}", secs / 60, secs % 60, attempts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpStream;
+
+    async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(format!("GET {} HTTP/1.1\r\nHost: bridge\r\n\r\n", path).as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn issues_a_new_code_after_each_pairing_until_the_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let manager = PairingManager::new_with_cf(
+            "agent".into(),
+            format!("ws://{}", addr),
+            "token".into(),
+            None,
+            None,
+            None,
+            "/tmp".into(),
+        );
+        let (codes_tx, mut codes_rx) = mpsc::unbounded_channel();
+        let (failed_tx, mut failed_rx) = mpsc::unbounded_channel();
+        let server = PairServer::new(listener, None).with_max_pairings(Some(2));
+        let run = tokio::spawn(server.run(
+            manager,
+            move |event| match event {
+                PairEvent::Code(manager, _) => codes_tx.send(manager.get_code()).unwrap(),
+                PairEvent::FailedAttempt(manager, ip) => failed_tx.send((ip.to_string(), countdown_line(manager))).unwrap(),
+                PairEvent::Tick(_) => {}
+            },
+            std::future::pending(),
+        ));
+
+        let first = codes_rx.recv().await.unwrap();
+        assert!(get(addr, &format!("/pair/local?code={}&device=Tablet%201", first)).await.starts_with("HTTP/1.1 200"));
+        assert!(get(addr, "/acp").await.starts_with("HTTP/1.1 404"));
+        let second = codes_rx.recv().await.unwrap();
+        assert_ne!(first, second);
+        assert!(get(addr, &format!("/pair/local?code={}", first)).await.starts_with("HTTP/1.1 401"));
+        let (ip, status) = failed_rx.recv().await.unwrap();
+        assert_eq!(ip, "127.0.0.1");
+        assert!(status.ends_with("| 4 attempts left"), "{}", status);
+        assert!(get(addr, &format!("/pair/local?code={}&device=Tablet%202", second)).await.starts_with("HTTP/1.1 200"));
+
+        let summary = tokio::time::timeout(Duration::from_secs(5), run).await.unwrap().unwrap().unwrap();
+        let names: Vec<_> = summary.paired.iter().map(|p| p.device.name.clone().unwrap()).collect();
+        assert_eq!(names, ["Tablet 1", "Tablet 2"]);
+        assert_eq!(summary.codes_issued, 2);
+        assert_eq!(summary.failed_attempts, 1);
+    }
+
+    #[test]
+    fn countdown_shows_time_and_attempts_left() {
+        let manager = PairingManager::new_with_cf("agent".into(), "ws://127.0.0.1:1".into(), "token".into(), None, None, None, "/tmp".into());
+        let line = countdown_line(&manager);
+        assert!(line.starts_with("⏱️  Expires in 1:00 |") || line.starts_with("⏱️  Expires in 0:59 |"), "{}", line);
+        assert!(line.ends_with("| 5 attempts left"), "{}", line);
+
+        let lines: Vec<_> = (0..5)
+            .map(|_| {
+                assert!(manager.validate("not-a-code").is_err());
+                countdown_line(&manager)
+            })
+            .collect();
+        assert!(lines[3].ends_with("| 1 attempt left"), "{}", lines[3]);
+        assert!(lines[4].ends_with("| no attempts left, a new code follows expiry"), "{}", lines[4]);
+        manager.renew();
+        assert!(countdown_line(&manager).ends_with("| 5 attempts left"));
+    }
+}
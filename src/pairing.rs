@@ -1,8 +1,14 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use subtle::ConstantTimeEq;
 use thiserror::Error;
 
+use crate::device_keys::{CredentialsKey, DeviceKey, DeviceKeys};
+
 /// Errors that can occur during pairing
 #[derive(Error, Debug)]
 pub enum PairingError {
@@ -12,10 +18,102 @@ pub enum PairingError {
     CodeAlreadyUsed,
     #[error("Too many failed attempts. Please restart the bridge to get a new code.")]
     RateLimited,
+    #[error("Pairing was declined on the bridge")]
+    Declined,
+}
+
+/// The device asking to pair, as shown in the bridge-side approval prompt.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PairingDevice {
+    /// Name the app sent in the `device` query parameter, if any.
+    pub name: Option<String>,
+    /// Platform the app sent in the `platform` query parameter, or else one
+    /// guessed from the `User-Agent` header (e.g. "iPhone").
+    pub platform: Option<String>,
+    /// App version the app sent in the `appVersion` query parameter, if any.
+    pub app_version: Option<String>,
+    /// Peer address of the connection (the proxy's, behind a tunnel).
+    pub ip: String,
+}
+
+impl PairingDevice {
+    /// Describe the device sending the raw HTTP `request` from `ip`.
+    pub fn from_request(request: &str, ip: &str) -> Self {
+        let platform = query_param(request, "platform").or_else(|| {
+            request
+                .lines()
+                .skip(1)
+                .find_map(|line| {
+                    let (key, value) = line.split_once(':')?;
+                    key.trim().eq_ignore_ascii_case("user-agent").then(|| value.trim())
+                })
+                .and_then(platform_from_user_agent)
+        });
+        Self {
+            name: query_param(request, "device"),
+            platform,
+            app_version: query_param(request, "appVersion"),
+            ip: ip.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for PairingDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name {
+            Some(ref name) => write!(f, "Device \"{}\"", name)?,
+            None => write!(f, "A device")?,
+        }
+        write!(f, " (")?;
+        if let Some(ref platform) = self.platform {
+            write!(f, "{}, ", platform)?;
+        }
+        if let Some(ref version) = self.app_version {
+            write!(f, "app {}, ", version)?;
+        }
+        write!(f, "IP {})", self.ip)
+    }
+}
+
+/// Query parameter `key` of the request line, decoded, with control
+/// characters removed and cut to 64 characters; `None` when blank.
+fn query_param(request: &str, key: &str) -> Option<String> {
+    request
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|path| path.split_once('?'))
+        .and_then(|(_, query)| {
+            query.split('&').find_map(|p| p.split_once('=').filter(|(k, _)| *k == key).map(|(_, v)| v))
+        })
+        .and_then(|v| urlencoding::decode(&v.replace('+', " ")).ok().map(|v| v.into_owned()))
+        .map(|v| v.chars().filter(|c| !c.is_control()).take(64).collect::<String>())
+        .filter(|v| !v.trim().is_empty())
 }
 
+fn platform_from_user_agent(user_agent: &str) -> Option<String> {
+    const PLATFORMS: &[(&str, &str)] = &[
+        ("iPhone", "iPhone"),
+        ("iPad", "iPad"),
+        ("Android", "Android"),
+        ("Darwin", "Apple device"),
+        ("Macintosh", "Mac"),
+        ("Windows", "Windows"),
+        ("Linux", "Linux"),
+    ];
+    PLATFORMS
+        .iter()
+        .find(|(needle, _)| user_agent.contains(needle))
+        .map(|(_, platform)| platform.to_string())
+}
+
+/// Callback asked to approve each pairing with a valid code before the auth
+/// token is released; resolves to `true` to approve.
+pub type PairingApproverFn =
+    Arc<dyn Fn(PairingDevice) -> Pin<Box<dyn Future<Output = bool> + Send>> + Send + Sync>;
+
 /// Result type for pairing response
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct PairingResponse {
     /// Stable agent identity shared across all transports.
     #[serde(rename = "agentId")]
@@ -37,6 +135,12 @@ pub struct PairingResponse {
     /// Mobile clients use this to know whether to register their push token.
     #[serde(rename = "pushRelayUrl", skip_serializing_if = "Option::is_none")]
     pub relay_url: Option<String>,
+    /// Key for decrypting `bridge/credentialsUpdate`, issued to this device only.
+    #[serde(rename = "credentialsKey", default, skip_serializing_if = "Option::is_none")]
+    pub credentials_key: Option<CredentialsKey>,
+    /// The bridge runs in `--insecure-dev` mode: no TLS, no auth.
+    #[serde(rename = "insecureDev", default, skip_serializing_if = "std::ops::Not::not")]
+    pub insecure_dev: bool,
 }
 
 /// Error response for failed pairing attempts
@@ -60,16 +164,21 @@ impl PairingErrorResponse {
             message: "Too many failed attempts. Please restart the bridge to get a new code.".to_string(),
         }
     }
+
+    pub fn declined() -> Self {
+        Self {
+            error: "declined".to_string(),
+            message: "Pairing was declined on the bridge".to_string(),
+        }
+    }
 }
 
 /// Manages one-time pairing codes for secure client registration
 pub struct PairingManager {
     /// Stable agent identity included in every pairing response.
     pub agent_id: String,
-    /// Current 6-digit pairing code
-    code: String,
-    /// When the code was created (for expiration)
-    created_at: Instant,
+    /// Current 6-digit pairing code and when it was issued (for expiration)
+    code: std::sync::Mutex<(String, Instant)>,
     /// Whether the code has been successfully used
     used: AtomicBool,
     /// Number of failed validation attempts (for rate limiting)
@@ -78,8 +187,9 @@ pub struct PairingManager {
     websocket_url: String,
     auth_token: String,
     cert_fingerprint: Option<String>,
-    client_id: Option<String>,
-    client_secret: Option<String>,
+    /// Cloudflare service token `(client_id, client_secret)`, replaced when
+    /// the running bridge rotates it
+    service_token: std::sync::Mutex<(Option<String>, Option<String>)>,
     /// The working directory where the bridge was started.
     cwd: String,
     /// Push relay URL included in the pairing response when push is configured.
@@ -90,6 +200,14 @@ pub struct PairingManager {
     max_attempts: u32,
     /// Whether to emit /pair/tailscale instead of /pair/local in the QR URL
     tailscale_path: bool,
+    /// Asked to approve each pairing before the auth token is released
+    approver: Option<PairingApproverFn>,
+    /// Issues each paired device a key for `bridge/credentialsUpdate`
+    device_keys: Option<Arc<DeviceKeys>>,
+    /// Mark the pairing URL and response as insecure dev mode
+    insecure_dev: bool,
+    /// Emit `/p/<code>` instead of `/pair/<transport>?code=<code>` in the QR URL
+    short_url: bool,
 }
 
 impl PairingManager {
@@ -106,23 +224,24 @@ impl PairingManager {
         client_secret: Option<String>,
         cwd: String,
     ) -> Self {
-        let code = generate_pairing_code();
         Self {
             agent_id,
-            code,
-            created_at: Instant::now(),
+            code: std::sync::Mutex::new((generate_pairing_code(), Instant::now())),
             used: AtomicBool::new(false),
             attempts: AtomicU32::new(0),
             websocket_url,
             auth_token,
             cert_fingerprint,
-            client_id,
-            client_secret,
+            service_token: std::sync::Mutex::new((client_id, client_secret)),
             cwd,
             relay_url: None,
             expiry_duration: Duration::from_secs(60),
             max_attempts: 5,
             tailscale_path: false,
+            approver: None,
+            device_keys: None,
+            insecure_dev: false,
+            short_url: false,
         }
     }
 
@@ -139,27 +258,95 @@ impl PairingManager {
         self
     }
 
+    /// Issue each paired device a credentials key from `keys` (see
+    /// [`crate::device_keys`]).
+    pub fn with_device_keys(mut self, keys: Arc<DeviceKeys>) -> Self {
+        self.device_keys = Some(keys);
+        self
+    }
+
+    /// Issue short pairing URLs (`<base>/p/<code>`, see [`SHORT_PAIRING_PATH`])
+    /// for smaller QR codes.
+    pub fn with_short_url(mut self) -> Self {
+        self.short_url = true;
+        self
+    }
+
+    /// Mark the pairing URL (`insecure=1`) and response (`insecureDev`) as
+    /// coming from a bridge in `--insecure-dev` mode.
+    pub fn with_insecure_dev(mut self) -> Self {
+        self.insecure_dev = true;
+        self
+    }
+
+    /// Require approval on the bridge side for every pairing with a valid code.
+    pub fn with_approver(mut self, approver: PairingApproverFn) -> Self {
+        self.approver = Some(approver);
+        self
+    }
+
+    /// Replace the code with a fresh one and reset the attempt count, once
+    /// the current code is used or expired, or to revoke it.
+    pub fn renew(&self) {
+        *self.issued() = (generate_pairing_code(), Instant::now());
+        self.attempts.store(0, Ordering::SeqCst);
+        self.used.store(false, Ordering::SeqCst);
+    }
+
+    fn issued(&self) -> std::sync::MutexGuard<'_, (String, Instant)> {
+        self.code.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The Cloudflare service token handed out on pairing, as
+    /// `(client_id, client_secret)`.
+    pub fn service_token(&self) -> (Option<String>, Option<String>) {
+        self.service_token.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Hand out a rotated Cloudflare service token from now on.
+    pub fn set_service_token(&self, client_id: String, client_secret: String) {
+        *self.service_token.lock().unwrap_or_else(|e| e.into_inner()) = (Some(client_id), Some(client_secret));
+    }
+
     /// Get the current pairing code
-    #[allow(dead_code)]
-    pub fn get_code(&self) -> &str {
-        &self.code
+    pub fn get_code(&self) -> String {
+        self.issued().0.clone()
     }
 
     /// Get the pairing URL (for QR code)
     pub fn get_pairing_url(&self, base_url: &str) -> String {
-        if self.client_id.is_some() {
+        let url = self.pairing_url_for(base_url);
+        if self.insecure_dev {
+            let separator = if url.contains('?') { '&' } else { '?' };
+            format!("{}{}insecure=1", url, separator)
+        } else {
+            url
+        }
+    }
+
+    fn pairing_url_for(&self, base_url: &str) -> String {
+        let code = self.get_code();
+        if self.short_url {
+            // Short mode: the code is the path; the fingerprint without colons
+            let mut url = format!("{}{}{}", base_url, SHORT_PAIRING_PATH, code);
+            if let Some(fp) = self.cert_fingerprint.as_ref().filter(|_| self.service_token().0.is_none()) {
+                url.push_str("?fp=");
+                url.push_str(&fp.replace(':', ""));
+            }
+            url
+        } else if self.service_token().0.is_some() {
             // Cloudflare mode: use /pair/cloudflare path, no fingerprint needed
-            format!("{}/pair/cloudflare?code={}", base_url, self.code)
+            format!("{}/pair/cloudflare?code={}", base_url, code)
         } else if self.tailscale_path {
             // Tailscale mode: /pair/tailscale; fingerprint present for ip mode, absent for serve mode
-            let mut url = format!("{}/pair/tailscale?code={}", base_url, self.code);
+            let mut url = format!("{}/pair/tailscale?code={}", base_url, code);
             if let Some(ref fp) = self.cert_fingerprint {
                 url.push_str("&fp=");
                 url.push_str(&urlencoding::encode(fp));
             }
             url
         } else {
-            let mut url = format!("{}/pair/local?code={}", base_url, self.code);
+            let mut url = format!("{}/pair/local?code={}", base_url, code);
             if let Some(ref fp) = self.cert_fingerprint {
                 url.push_str("&fp=");
                 url.push_str(&urlencoding::encode(fp));
@@ -168,20 +355,32 @@ impl PairingManager {
         }
     }
 
+    /// The pairing URL on the bridge's own address (the WebSocket URL over
+    /// HTTP(S)), as shown in the QR code
+    pub fn pairing_url(&self) -> String {
+        let base_url = self.websocket_url.replace("wss://", "https://").replace("ws://", "http://");
+        self.get_pairing_url(&base_url)
+    }
+
     /// Check if the code has expired
     pub fn is_expired(&self) -> bool {
-        self.created_at.elapsed() > self.expiry_duration
+        self.issued().1.elapsed() > self.expiry_duration
     }
 
     /// Check if the code has been used
-    #[allow(dead_code)]
     pub fn is_used(&self) -> bool {
         self.used.load(Ordering::SeqCst)
     }
 
+    /// Wrong codes that may still be tried before the code is locked until
+    /// it's renewed
+    pub fn attempts_left(&self) -> u32 {
+        self.max_attempts.saturating_sub(self.attempts.load(Ordering::SeqCst))
+    }
+
     /// Get remaining seconds until expiration
     pub fn seconds_remaining(&self) -> u64 {
-        let elapsed = self.created_at.elapsed();
+        let elapsed = self.issued().1.elapsed();
         if elapsed > self.expiry_duration {
             0
         } else {
@@ -210,7 +409,7 @@ impl PairingManager {
         // Validate code using constant-time comparison to prevent timing side-channel attacks.
         // A standard != on a 6-digit string would leak information about how many characters
         // match, reducing the effective search space before the rate limit is reached.
-        let code_matches = code.as_bytes().ct_eq(self.code.as_bytes());
+        let code_matches = code.as_bytes().ct_eq(self.get_code().as_bytes());
         if code_matches.unwrap_u8() == 0 {
             self.attempts.fetch_add(1, Ordering::SeqCst);
             return Err(PairingError::InvalidCode);
@@ -222,6 +421,7 @@ impl PairingManager {
             return Err(PairingError::CodeAlreadyUsed);
         }
 
+        let service_token = self.service_token();
         Ok(PairingResponse {
             agent_id: self.agent_id.clone(),
             url: self.websocket_url.clone(),
@@ -229,18 +429,138 @@ impl PairingManager {
             version: "1.0".to_string(),
             auth_token: self.auth_token.clone(),
             cert_fingerprint: self.cert_fingerprint.clone(),
-            client_id: self.client_id.clone(),
-            client_secret: self.client_secret.clone(),
+            client_id: service_token.0,
+            client_secret: service_token.1,
             cwd: self.cwd.clone(),
             relay_url: self.relay_url.clone(),
+            credentials_key: None,
+            insecure_dev: self.insecure_dev,
         })
     }
 
+    /// Validate a pairing code, then ask the approver (if any) about `device`.
+    ///
+    /// The code is consumed before the prompt, so a declined device cannot
+    /// retry with the same code.
+    pub async fn pair(&self, code: &str, device: PairingDevice) -> Result<PairingResponse, PairingError> {
+        let mut response = self.validate(code)?;
+        if let Some(ref approver) = self.approver {
+            if !approver(device.clone()).await {
+                return Err(PairingError::Declined);
+            }
+        }
+        if let Some(ref keys) = self.device_keys {
+            match keys.issue(&device) {
+                Ok(key) => response.credentials_key = Some(key),
+                Err(e) => tracing::warn!("No credentials key for {}: {:#}", device, e),
+            }
+        }
+        Ok(response)
+    }
+
     /// Get the certificate fingerprint (if available)
     #[allow(dead_code)]
     pub fn get_cert_fingerprint(&self) -> Option<&str> {
         self.cert_fingerprint.as_deref()
     }
+
+    /// The device paired with credentials key `key_id`, if it's still kept.
+    pub fn paired_device(&self, key_id: &str) -> Option<DeviceKey> {
+        self.device_keys.as_ref()?.get(key_id)
+    }
+
+    /// Get the pairing URL wrapped in an app deep link (see [`deep_link`])
+    pub fn get_deep_link(&self, base_url: &str, scheme: &str) -> String {
+        deep_link(scheme, &self.get_pairing_url(base_url))
+    }
+}
+
+/// Build a `<scheme>://pair?...` deep link that opens the mobile app straight
+/// into pairing when tapped (e.g. in iMessage or email).
+///
+/// A pairing URL is carried URL-encoded as `url=`; a static connection JSON
+/// payload (`bridge show-qr`) as unpadded base64url in `data=`.
+pub fn deep_link(scheme: &str, payload: &str) -> String {
+    use base64::Engine;
+    if payload.starts_with('{') {
+        let data = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(payload);
+        format!("{}://pair?data={}", scheme, data)
+    } else {
+        format!("{}://pair?url={}", scheme, urlencoding::encode(payload))
+    }
+}
+
+/// Pairing endpoint of `transport`: `/pair/cloudflare`, `/pair/tailscale`
+/// or `/pair/local`.
+pub fn pairing_path(transport: &str) -> &'static str {
+    match transport {
+        "cloudflare" => "/pair/cloudflare",
+        t if t.starts_with("tailscale") => "/pair/tailscale",
+        _ => "/pair/local",
+    }
+}
+
+/// The pairing URL of `websocket_url` without a `code`, for an NFC tag that
+/// is written once (see [`crate::nfc`]): the app asks for the code the
+/// bridge is currently showing, so the tag stays valid as codes rotate.
+pub fn stable_pairing_url(websocket_url: &str, transport: &str, fingerprint: Option<&str>) -> String {
+    let base_url = websocket_url.replace("wss://", "https://").replace("ws://", "http://");
+    let mut url = format!("{}{}", base_url, pairing_path(transport));
+    if let Some(fp) = fingerprint.filter(|_| transport != "cloudflare") {
+        url.push_str("?fp=");
+        url.push_str(&urlencoding::encode(fp));
+    }
+    url
+}
+
+/// `pairing_url` with a shorter `fp`: the hex digits without colons, which
+/// need no percent-encoding. Clients compare fingerprints ignoring colons and
+/// case, so both forms pin the same certificate; the QR code of this one is
+/// a few sizes smaller.
+pub fn compact_pairing_url(pairing_url: &str) -> String {
+    let Some((base, query)) = pairing_url.split_once('?') else {
+        return pairing_url.to_string();
+    };
+    let query: Vec<String> = query
+        .split('&')
+        .map(|pair| match pair.strip_prefix("fp=") {
+            Some(fp) => {
+                let fp = urlencoding::decode(fp).map(|fp| fp.into_owned()).unwrap_or_else(|_| fp.to_string());
+                format!("fp={}", urlencoding::encode(&fp.replace(':', "")))
+            }
+            None => pair.to_string(),
+        })
+        .collect();
+    format!("{}?{}", base, query.join("&"))
+}
+
+/// Path prefix of short pairing URLs: `GET /p/<code>` pairs like
+/// `GET /pair/<transport>?code=<code>` and answers with the same payload.
+pub const SHORT_PAIRING_PATH: &str = "/p/";
+
+/// The one-time code in a pairing URL (or a short one, or a request target),
+/// for showing next to its QR code.
+pub fn code_of(pairing_url: &str) -> Option<&str> {
+    let (path, query) = pairing_url.split_once('?').unwrap_or((pairing_url, ""));
+    match path.split_once(SHORT_PAIRING_PATH) {
+        Some((_, code)) if !code.is_empty() && !code.contains('/') => Some(code),
+        _ => query.split('&').find_map(|pair| pair.strip_prefix("code=")),
+    }
+}
+
+/// Six-digit code the mobile app shows after the connection details are typed
+/// in by hand (`bridge pair --manual`); the user reads it back to the bridge
+/// to confirm nothing was mistyped.
+///
+/// It is the first four bytes of SHA-256 over `url`, `auth_token` and the
+/// fingerprint (hex, colons removed, uppercase; empty when absent), joined by
+/// newlines, as a big-endian integer modulo 1,000,000, zero-padded.
+pub fn confirmation_code(url: &str, auth_token: &str, fingerprint: Option<&str>) -> String {
+    use sha2::{Digest, Sha256};
+    let fingerprint = fingerprint.unwrap_or_default().replace(':', "").to_ascii_uppercase();
+    let digest = Sha256::digest(format!("{}\n{}\n{}", url, auth_token, fingerprint));
+    let n = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+    format!("{:06}", n % 1_000_000)
 }
 
 /// Generate a cryptographically random 6-digit pairing code
@@ -253,6 +573,35 @@ fn generate_pairing_code() -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn confirmation_code_ignores_fingerprint_formatting() {
+        // Reference value for the app's implementation.
+        let code = confirmation_code("wss://192.168.1.2:8765", "token", Some("ab:cd:ef"));
+        assert_eq!(code, "018095");
+        assert_eq!(code, confirmation_code("wss://192.168.1.2:8765", "token", Some("ABCDEF")));
+        assert_ne!(code, confirmation_code("wss://192.168.1.2:8765", "tokem", Some("ABCDEF")));
+        assert_eq!(confirmation_code("u", "t", None), confirmation_code("u", "t", Some("")));
+    }
+
+    #[test]
+    fn insecure_dev_is_marked_in_url_and_response() {
+        let manager = PairingManager::new_with_cf(
+            "agent".to_string(),
+            "ws://127.0.0.1:8765".to_string(),
+            "token".to_string(),
+            None,
+            None,
+            None,
+            "/tmp".to_string(),
+        );
+        assert!(!manager.pairing_url().contains("insecure"));
+        let manager = manager.with_insecure_dev();
+        assert!(manager.pairing_url().ends_with("&insecure=1"), "{}", manager.pairing_url());
+        let response = manager.validate(&manager.get_code()).unwrap();
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["insecureDev"], true);
+    }
+
     #[test]
     fn test_code_generation() {
         let code = generate_pairing_code();
@@ -272,7 +621,7 @@ mod tests {
             "/tmp/test".to_string(),
         );
 
-        let code = manager.get_code().to_string();
+        let code = manager.get_code();
         let result = manager.validate(&code);
         assert!(result.is_ok());
 
@@ -281,6 +630,30 @@ mod tests {
         assert_eq!(response.auth_token, "test-token");
     }
 
+    #[test]
+    fn renewing_issues_a_fresh_code() {
+        let manager = PairingManager::new_with_cf(
+            "test-agent-id".to_string(),
+            "wss://192.168.1.100:8080".to_string(),
+            "test-token".to_string(),
+            None,
+            None,
+            None,
+            "/tmp/test".to_string(),
+        )
+        .with_tailscale_path();
+        let code = manager.get_code();
+        manager.validate(&code).unwrap();
+
+        manager.renew();
+        assert!(!manager.is_used());
+        assert_ne!(manager.get_code(), code);
+        assert!(manager.pairing_url().starts_with("https://192.168.1.100:8080/pair/tailscale?code="));
+        assert!(matches!(manager.validate(&code), Err(PairingError::InvalidCode)));
+        let response = manager.validate(&manager.get_code()).unwrap();
+        assert_eq!(response.auth_token, "test-token");
+    }
+
     #[test]
     fn test_pairing_manager_invalid_code() {
         let manager = PairingManager::new_with_cf(
@@ -309,7 +682,7 @@ mod tests {
             "/tmp/test".to_string(),
         );
 
-        let code = manager.get_code().to_string();
+        let code = manager.get_code();
 
         // First use should succeed
         assert!(manager.validate(&code).is_ok());
@@ -393,4 +766,142 @@ mod tests {
         assert!(url.contains("/pair/tailscale?code="), "Expected /pair/tailscale in URL, got: {}", url);
         assert!(url.contains("&fp=SHA256"), "ip mode should include fingerprint");
     }
+
+    #[test]
+    fn test_deep_link_wraps_pairing_url() {
+        let manager = PairingManager::new_with_cf(
+            "test-agent-id".to_string(),
+            "wss://192.168.1.100:8080".to_string(),
+            "test-token".to_string(),
+            Some("SHA256:AB:CD".to_string()),
+            None,
+            None,
+            "/tmp/test".to_string(),
+        );
+        let pairing_url = manager.get_pairing_url("https://192.168.1.100:8080");
+        let link = manager.get_deep_link("https://192.168.1.100:8080", "aptove");
+
+        let encoded = link.strip_prefix("aptove://pair?url=").unwrap();
+        assert_eq!(urlencoding::decode(encoded).unwrap(), pairing_url);
+        assert!(!encoded.contains('&'));
+    }
+
+    #[test]
+    fn test_deep_link_carries_connection_json() {
+        use base64::Engine;
+        let json = r#"{"url":"wss://10.0.0.2:8765","authToken":"t"}"#;
+        let link = deep_link("myapp", json);
+
+        let data = link.strip_prefix("myapp://pair?data=").unwrap();
+        let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(data).unwrap();
+        assert_eq!(decoded, json.as_bytes());
+    }
+
+    #[test]
+    fn test_stable_pairing_url_has_no_code() {
+        let manager = test_manager();
+        let url = stable_pairing_url("wss://10.0.0.2:8765", "local", Some("SHA256:AB:CD"));
+        assert_eq!(url, "https://10.0.0.2:8765/pair/local?fp=SHA256%3AAB%3ACD");
+        assert_eq!(code_of(&url), None);
+        assert_eq!(stable_pairing_url("wss://host.ts.net", "tailscale-serve", None), "https://host.ts.net/pair/tailscale");
+        assert_eq!(stable_pairing_url("wss://agent.example.com", "cloudflare", Some("x")), "https://agent.example.com/pair/cloudflare");
+        assert_eq!(code_of(&manager.pairing_url()), Some(manager.get_code().as_str()));
+    }
+
+    #[test]
+    fn test_short_pairing_url_carries_code_in_path() {
+        let short_manager = || {
+            PairingManager::new_with_cf(
+                "test-agent-id".to_string(),
+                "wss://192.168.1.100:8080".to_string(),
+                "test-token".to_string(),
+                Some("AB:CD".to_string()),
+                None,
+                None,
+                "/tmp".to_string(),
+            )
+            .with_short_url()
+        };
+        let manager = short_manager();
+        let url = manager.pairing_url();
+        assert_eq!(url, format!("https://192.168.1.100:8080/p/{}?fp=ABCD", manager.get_code()));
+        assert_eq!(code_of(&url), Some(manager.get_code().as_str()));
+        assert_eq!(code_of(&format!("/p/{}", manager.get_code())), Some(manager.get_code().as_str()));
+        assert!(short_manager().with_insecure_dev().pairing_url().ends_with("?fp=ABCD&insecure=1"));
+    }
+
+    #[test]
+    fn test_compact_pairing_url_drops_fingerprint_colons() {
+        let url = "https://10.0.0.2:8765/pair/local?code=123456&fp=AB%3ACD%3AEF";
+        assert_eq!(compact_pairing_url(url), "https://10.0.0.2:8765/pair/local?code=123456&fp=ABCDEF");
+        let cloudflare = "https://agent.example.com/pair/cloudflare?code=123456";
+        assert_eq!(compact_pairing_url(cloudflare), cloudflare);
+    }
+
+    fn test_manager() -> PairingManager {
+        PairingManager::new_with_cf(
+            "test-agent-id".to_string(),
+            "wss://192.168.1.100:8080".to_string(),
+            "test-token".to_string(),
+            None,
+            None,
+            None,
+            "/tmp/test".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_pairing_device_from_request() {
+        let request = "GET /pair/local?code=123456&device=Ana%27s+iPhone HTTP/1.1\r\n\
+                       Host: 192.168.1.100:8765\r\n\
+                       User-Agent: Aptove/1.4 CFNetwork/1490 Darwin/23.2.0 (iPhone15,2)\r\n\r\n";
+        let device = PairingDevice::from_request(request, "192.168.1.23");
+        assert_eq!(device.name.as_deref(), Some("Ana's iPhone"));
+        assert_eq!(device.platform.as_deref(), Some("iPhone"));
+        assert_eq!(device.to_string(), "Device \"Ana's iPhone\" (iPhone, IP 192.168.1.23)");
+
+        let bare = PairingDevice::from_request("GET /pair/local?code=1 HTTP/1.1\r\n\r\n", "10.0.0.5");
+        assert_eq!(bare.to_string(), "A device (IP 10.0.0.5)");
+
+        // What the app says about itself beats the User-Agent guess.
+        let request = "GET /pair/local?code=1&device=Pixel&platform=Android+15&appVersion=2.3.0 HTTP/1.1\r\n\
+                       User-Agent: okhttp/4.12.0 (Linux)\r\n\r\n";
+        let device = PairingDevice::from_request(request, "10.0.0.5");
+        assert_eq!(device.to_string(), "Device \"Pixel\" (Android 15, app 2.3.0, IP 10.0.0.5)");
+    }
+
+    #[tokio::test]
+    async fn test_pair_waits_for_approval() {
+        let device = PairingDevice { ip: "10.0.0.5".to_string(), ..PairingDevice::default() };
+
+        let approved = test_manager().with_approver(Arc::new(|_| Box::pin(async { true })));
+        assert!(approved.pair(&approved.get_code(), device.clone()).await.is_ok());
+
+        let declined = test_manager().with_approver(Arc::new(|_| Box::pin(async { false })));
+        let code = declined.get_code();
+        assert!(matches!(declined.pair(&code, device.clone()).await, Err(PairingError::Declined)));
+        // The declined code is spent.
+        assert!(matches!(declined.pair(&code, device).await, Err(PairingError::CodeAlreadyUsed)));
+    }
+
+    #[tokio::test]
+    async fn test_pair_issues_a_credentials_key() {
+        let device = PairingDevice { name: Some("phone".into()), ip: "10.0.0.5".to_string(), ..PairingDevice::default() };
+        let plain = test_manager();
+        let response = plain.pair(&plain.get_code(), device.clone()).await.unwrap();
+        assert!(response.credentials_key.is_none());
+        assert!(!serde_json::to_string(&response).unwrap().contains("credentialsKey"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let keys = Arc::new(DeviceKeys::open(dir.path()));
+        let manager = test_manager().with_device_keys(keys.clone());
+        let response = manager.pair(&manager.get_code(), device).await.unwrap();
+        let key = response.credentials_key.unwrap();
+        let stored = keys.keys();
+        assert_eq!(stored.len(), 1);
+        assert_eq!((stored[0].id.as_str(), stored[0].key.as_str()), (key.id.as_str(), key.key.as_str()));
+        assert!(stored[0].device.contains("phone"));
+        let paired = manager.paired_device(&key.id).unwrap();
+        assert_eq!((paired.name.as_deref(), paired.ip.as_deref()), (Some("phone"), Some("10.0.0.5")));
+    }
 }
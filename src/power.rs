@@ -0,0 +1,229 @@
+//! Battery awareness for laptops (`[power]`).
+//!
+//! The bridge reads the power source every `interval_secs`. On battery below
+//! the threshold it switches the agent pool to power saving: fewer agents,
+//! shorter idle timeouts and no warm agents. It switches back once the machine
+//! is plugged in, not when the charge merely creeps back over the threshold,
+//! so it doesn't flap. The state is logged and kept in `runtime.json`, where
+//! `bridge status` shows it.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::agent_pool::AgentPool;
+use crate::common_config::PowerConfig;
+use crate::runtime_manifest::RuntimeManifest;
+
+/// Where Linux exposes batteries and chargers.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const SYSFS_POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+/// The power source at one point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerReading {
+    /// Running from the battery (not plugged in)
+    pub on_battery: bool,
+    /// Charge of the system battery; `None` without one
+    pub battery_percent: Option<u8>,
+}
+
+/// Pool limits while power saving, from `[power]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerSaving {
+    pub max_agents: usize,
+    pub idle_timeout: Duration,
+    pub pause_warm_pool: bool,
+}
+
+impl From<&PowerConfig> for PowerSaving {
+    fn from(config: &PowerConfig) -> Self {
+        Self {
+            max_agents: config.max_agents,
+            idle_timeout: Duration::from_secs(config.idle_timeout_secs),
+            pause_warm_pool: config.pause_warm_pool,
+        }
+    }
+}
+
+/// Power state recorded in `runtime.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PowerStatus {
+    pub on_battery: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery_percent: Option<u8>,
+    /// Whether the pool runs with the `[power]` limits
+    pub saving: bool,
+    /// RFC 3339 time `saving` last changed
+    pub since: String,
+}
+
+/// The current power source, where the platform exposes it.
+pub fn read() -> Option<PowerReading> {
+    #[cfg(target_os = "linux")]
+    {
+        read_sysfs(Path::new(SYSFS_POWER_SUPPLY))
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let output = std::process::Command::new("pmset").args(["-g", "batt"]).output().ok()?;
+        parse_pmset(&String::from_utf8_lossy(&output.stdout))
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        None
+    }
+}
+
+/// Read the chargers and system batteries under `dir` (laid out like
+/// `/sys/class/power_supply`). Batteries of peripherals (`scope` `Device`)
+/// are ignored.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn read_sysfs(dir: &Path) -> Option<PowerReading> {
+    let attr = |supply: &Path, name: &str| std::fs::read_to_string(supply.join(name)).map(|v| v.trim().to_string()).ok();
+    let mut plugged_in = false;
+    let mut discharging = false;
+    let mut percents = Vec::new();
+    for entry in std::fs::read_dir(dir).ok()?.flatten() {
+        let supply = entry.path();
+        match attr(&supply, "type").as_deref() {
+            Some("Mains") | Some("USB") | Some("USB_C") | Some("USB_PD") => {
+                plugged_in |= attr(&supply, "online").as_deref() == Some("1");
+            }
+            Some("Battery") if attr(&supply, "scope").as_deref() != Some("Device") => {
+                discharging |= attr(&supply, "status").as_deref() == Some("Discharging");
+                if let Some(percent) = attr(&supply, "capacity").and_then(|c| c.parse::<u8>().ok()) {
+                    percents.push(percent);
+                }
+            }
+            _ => {}
+        }
+    }
+    let battery_percent = (!percents.is_empty()).then(|| (percents.iter().map(|&p| p as usize).sum::<usize>() / percents.len()) as u8);
+    Some(PowerReading { on_battery: discharging && !plugged_in, battery_percent })
+}
+
+/// Parse `pmset -g batt`, e.g.
+/// `Now drawing from 'Battery Power'` / ` -InternalBattery-0 (id=…)\t23%; discharging; …`.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_pmset(output: &str) -> Option<PowerReading> {
+    let source = output.lines().next()?;
+    let battery_percent = output
+        .split(|c: char| c.is_whitespace() || c == ';')
+        .find_map(|word| word.strip_suffix('%')?.parse::<u8>().ok());
+    Some(PowerReading { on_battery: source.contains("'Battery Power'"), battery_percent })
+}
+
+/// Whether to be power saving after `reading`: start on battery below
+/// `threshold` percent, stop only once plugged in.
+fn next_saving(saving: bool, reading: PowerReading, threshold: u8) -> bool {
+    if !reading.on_battery {
+        return false;
+    }
+    saving || reading.battery_percent.is_some_and(|p| p < threshold)
+}
+
+/// Read the power source every `interval_secs` and switch `pool` in and out
+/// of power saving. With `manifest`, keeps the state in `runtime.json` in
+/// that directory.
+pub fn start(
+    config: PowerConfig,
+    pool: Arc<RwLock<AgentPool>>,
+    mut manifest: Option<(RuntimeManifest, PathBuf)>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        if read().is_none() {
+            warn!("⚠️  [power] can't read the power source on this platform — disabled");
+            return;
+        }
+        info!("🔋 Power saving below {}% battery", config.battery_below_percent);
+        let mut saving = false;
+        let mut since = chrono::Local::now().to_rfc3339();
+        let mut recorded = None;
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+        loop {
+            interval.tick().await;
+            let Some(reading) = read() else { continue };
+            let next = next_saving(saving, reading, config.battery_below_percent);
+            if next != saving {
+                saving = next;
+                since = chrono::Local::now().to_rfc3339();
+                let percent = reading.battery_percent.map_or_else(|| "?".to_string(), |p| p.to_string());
+                if saving {
+                    warn!(
+                        "🪫 On battery at {}% — power saving: at most {} agent(s), idle timeout {}s{}",
+                        percent,
+                        config.max_agents,
+                        config.idle_timeout_secs,
+                        if config.pause_warm_pool { ", warm pool paused" } else { "" }
+                    );
+                    pool.write().await.set_power_saving(Some(PowerSaving::from(&config))).await;
+                } else {
+                    info!("🔌 Plugged in — normal pool limits restored");
+                    pool.write().await.set_power_saving(None).await;
+                }
+            }
+            if recorded == Some((reading, saving)) {
+                continue;
+            }
+            recorded = Some((reading, saving));
+            if let Some((ref mut manifest, ref dir)) = manifest {
+                manifest.power = Some(PowerStatus {
+                    on_battery: reading.on_battery,
+                    battery_percent: reading.battery_percent,
+                    saving,
+                    since: since.clone(),
+                });
+                if let Err(e) = manifest.save(dir) {
+                    warn!("Failed to record the power state: {:#}", e);
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn supply(dir: &Path, name: &str, attrs: &[(&str, &str)]) {
+        let path = dir.join(name);
+        std::fs::create_dir(&path).unwrap();
+        for (attr, value) in attrs {
+            std::fs::write(path.join(attr), format!("{}\n", value)).unwrap();
+        }
+    }
+
+    #[test]
+    fn reads_linux_power_supplies() {
+        let dir = tempfile::tempdir().unwrap();
+        supply(dir.path(), "AC", &[("type", "Mains"), ("online", "0")]);
+        supply(dir.path(), "BAT0", &[("type", "Battery"), ("status", "Discharging"), ("capacity", "23")]);
+        supply(dir.path(), "hid-mouse", &[("type", "Battery"), ("scope", "Device"), ("status", "Discharging"), ("capacity", "90")]);
+        assert_eq!(read_sysfs(dir.path()), Some(PowerReading { on_battery: true, battery_percent: Some(23) }));
+
+        std::fs::write(dir.path().join("AC/online"), "1\n").unwrap();
+        assert!(!read_sysfs(dir.path()).unwrap().on_battery);
+    }
+
+    #[test]
+    fn parses_pmset() {
+        let on_battery = "Now drawing from 'Battery Power'\n -InternalBattery-0 (id=4653155)\t23%; discharging; 2:41 remaining present: true\n";
+        assert_eq!(parse_pmset(on_battery), Some(PowerReading { on_battery: true, battery_percent: Some(23) }));
+        let plugged_in = "Now drawing from 'AC Power'\n -InternalBattery-0 (id=4653155)\t100%; charged; 0:00 remaining present: true\n";
+        assert_eq!(parse_pmset(plugged_in), Some(PowerReading { on_battery: false, battery_percent: Some(100) }));
+    }
+
+    #[test]
+    fn saving_starts_below_threshold_and_ends_when_plugged_in() {
+        let battery = |p| PowerReading { on_battery: true, battery_percent: Some(p) };
+        assert!(!next_saving(false, battery(30), 30));
+        assert!(next_saving(false, battery(29), 30));
+        assert!(next_saving(true, battery(45), 30), "charging a little on battery doesn't end it");
+        assert!(!next_saving(true, PowerReading { on_battery: false, battery_percent: Some(29) }, 30));
+    }
+}
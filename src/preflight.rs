@@ -0,0 +1,279 @@
+//! `bridge start --check`: everything `bridge start` would trip over,
+//! checked without spawning the agent, starting a tunnel or keeping a port,
+//! for pipelines that provision bridge hosts.
+//!
+//! | Check | Passes when |
+//! |-------|-------------|
+//! | `config` | every section of `common.toml` validates, as at startup |
+//! | `agent` | `agent_command` is set, its program is on `PATH` and `[[allowed_agents]]` allows it |
+//! | `lock` | no bridge is running from the config directory |
+//! | `<transport> port` | the listen address is free to bind |
+//! | `<transport> tls` | the imported or self-signed certificate and its key load (a missing self-signed pair is only generated at start) |
+//! | `cloudflare cloudflared` | `cloudflared` is on `PATH` and the tunnel has credentials |
+//! | `tailscale-* tailscale` | Tailscale runs and has the IP or MagicDNS name the transport needs |
+//!
+//! Without `--transport`, every enabled transport is checked. A failed check
+//! makes the command exit non-zero; warnings don't.
+
+use std::fmt;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::common_config::{CommonConfig, KeyStorage, TransportConfig};
+use crate::output::{Table, Tone};
+use crate::runtime_manifest::{self, RuntimeManifest};
+use crate::tls::{self, TlsConfig};
+
+/// Outcome of one check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckState {
+    Pass,
+    /// Startup will work, but maybe not as intended.
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub state: CheckState,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, result: Result<String, String>) -> Self {
+        let (state, detail) = match result {
+            Ok(detail) => (CheckState::Pass, detail),
+            Err(detail) => (CheckState::Fail, detail),
+        };
+        Self { name: name.into(), state, detail }
+    }
+
+    fn warn(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self { name: name.into(), state: CheckState::Warn, detail: detail.into() }
+    }
+}
+
+/// Everything `bridge start --check` prints.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreflightReport {
+    pub config_path: std::path::PathBuf,
+    pub checks: Vec<Check>,
+}
+
+impl PreflightReport {
+    /// Number of failed checks.
+    pub fn failures(&self) -> usize {
+        self.checks.iter().filter(|c| c.state == CheckState::Fail).count()
+    }
+}
+
+/// Run every check for `transport` (default: every enabled transport).
+pub fn run(config: &CommonConfig, config_dir: &Path, transport: Option<&str>) -> PreflightReport {
+    let mut checks = vec![
+        Check::new("config", crate::runner::validate_config(config).map(|()| "valid".to_string()).map_err(|e| format!("{:#}", e))),
+        Check::new("agent", check_agent(config)),
+        Check::new("lock", check_lock(config_dir)),
+    ];
+
+    let transports: Vec<(&str, &TransportConfig)> = match transport {
+        Some(name) => match config.transports.get_key_value(name) {
+            Some((name, cfg)) => vec![(name.as_str(), cfg)],
+            None => {
+                checks.push(Check::new("transport", Err(format!("Transport '{}' not found in config", name))));
+                Vec::new()
+            }
+        },
+        None => config.enabled_transports(),
+    };
+    if transport.is_none() && transports.is_empty() {
+        checks.push(Check::new("transport", Err("no transport enabled — run `bridge` once to configure one".to_string())));
+    }
+    for (name, transport_cfg) in transports {
+        checks.extend(check_transport(name, transport_cfg, config, config_dir));
+    }
+
+    PreflightReport { config_path: CommonConfig::config_path(), checks }
+}
+
+fn check_agent(config: &CommonConfig) -> Result<String, String> {
+    let command = config.agent_command.as_deref().ok_or("no agent_command in config")?;
+    let program = command.split_whitespace().next().ok_or("agent_command is empty")?;
+    let path = which::which(program).map_err(|_| format!("{} not found — is it installed and on PATH?", program))?;
+    crate::agent_allowlist::AgentAllowlist::from(&config.allowed_agents[..])
+        .check(command)
+        .map_err(|e| format!("{:#}", e))?;
+    Ok(format!("{} ({})", command, path.display()))
+}
+
+fn check_lock(config_dir: &Path) -> Result<String, String> {
+    if !runtime_manifest::is_live(config_dir) {
+        return Ok(format!("no bridge running from {}", config_dir.display()));
+    }
+    let pid = RuntimeManifest::read(config_dir).ok().flatten().map(|m| format!(" (pid {})", m.pid)).unwrap_or_default();
+    Err(format!("a bridge is already running from {}{}", config_dir.display(), pid))
+}
+
+fn check_transport(name: &str, transport_cfg: &TransportConfig, config: &CommonConfig, config_dir: &Path) -> Vec<Check> {
+    let mut checks = Vec::new();
+    if !transport_cfg.enabled {
+        checks.push(Check::warn(name, "not enabled in common.toml"));
+    }
+
+    let bind = if name == "tailscale-serve" { "127.0.0.1" } else { config.bind_address.as_deref().unwrap_or("0.0.0.0") };
+    let port = transport_cfg.port.unwrap_or(if name == "tailscale-serve" { 8766 } else { 8765 });
+    checks.push(Check::new(
+        format!("{} port", name),
+        std::net::TcpListener::bind((bind, port))
+            .map(|_| format!("{}:{} is free", bind, port))
+            .map_err(|e| format!("{}:{}: {}", bind, port, e)),
+    ));
+
+    match name {
+        "cloudflare" => checks.push(Check::new(format!("{} cloudflared", name), check_cloudflared(transport_cfg))),
+        "tailscale-serve" | "tailscale-ip" => checks.push(Check::new(format!("{} tailscale", name), check_tailscale(name))),
+        _ => {}
+    }
+
+    if name != "cloudflare" && name != "tailscale-serve" {
+        if transport_cfg.tls == Some(false) {
+            checks.push(Check::warn(format!("{} tls", name), "TLS disabled: devices connect over plaintext ws://"));
+        } else {
+            checks.push(Check::new(format!("{} tls", name), check_tls(name, transport_cfg, config, config_dir)));
+        }
+    }
+    checks
+}
+
+fn check_cloudflared(transport_cfg: &TransportConfig) -> Result<String, String> {
+    let path = which::which("cloudflared").map_err(|_| "cloudflared not found — is it installed and on PATH?".to_string())?;
+    let tunnel_id = transport_cfg.tunnel_id.as_deref().ok_or("no tunnel_id — run `bridge setup`")?;
+    if transport_cfg.hostname.as_deref().unwrap_or_default().is_empty() {
+        return Err("no hostname — run `bridge setup`".to_string());
+    }
+    if transport_cfg.tunnel_secret.is_none() || transport_cfg.account_id.is_none() {
+        let fallback = crate::cloudflare::cloudflared_config_path().map_err(|e| format!("{:#}", e))?;
+        if !fallback.exists() {
+            return Err(format!("no tunnel_secret/account_id and no {}", fallback.display()));
+        }
+    }
+    Ok(format!("{}, tunnel {}", path.display(), tunnel_id))
+}
+
+fn check_tailscale(name: &str) -> Result<String, String> {
+    if !crate::tailscale::is_tailscale_available() {
+        return Err("Tailscale is not installed or not running".to_string());
+    }
+    if name == "tailscale-ip" {
+        return crate::tailscale::get_tailscale_ipv4().map(|ip| format!("tailnet IP {}", ip)).map_err(|e| format!("{:#}", e));
+    }
+    match crate::tailscale::get_tailscale_hostname() {
+        Ok(Some(hostname)) => Ok(format!("MagicDNS name {}", hostname)),
+        Ok(None) => Err("tailscale-serve requires MagicDNS + HTTPS enabled on your tailnet".to_string()),
+        Err(e) => Err(format!("{:#}", e)),
+    }
+}
+
+/// Load an imported certificate (which also checks it covers the advertised
+/// host), or check the self-signed pair without generating or moving it.
+fn check_tls(name: &str, transport_cfg: &TransportConfig, config: &CommonConfig, config_dir: &Path) -> Result<String, String> {
+    if let Some(import) = crate::runner::cert_import(transport_cfg).map_err(|e| format!("{:#}", e))? {
+        let host = match name {
+            "tailscale-ip" => crate::tailscale::get_tailscale_ipv4().map_err(|e| format!("{:#}", e))?,
+            _ => config
+                .advertise_addr
+                .clone()
+                .or_else(|| local_ip_address::local_ip().ok().map(|ip| ip.to_string()))
+                .unwrap_or_else(|| "127.0.0.1".to_string()),
+        };
+        let tls = TlsConfig::load_imported(&import, &host, &config.tls_policy).map_err(|e| format!("{:#}", e))?;
+        return Ok(format!("imported certificate for {} ({})", host, tls.fingerprint_short()));
+    }
+    if name == "tailscale-ip" && transport_cfg.tailscale_cert.unwrap_or(true) {
+        return Ok("Tailscale certificate, fetched at start".to_string());
+    }
+    if config.tls_policy.ca {
+        let ca_path = config_dir.join(tls::CA_CERT_FILENAME);
+        if !ca_path.exists() {
+            return Ok("local CA will be generated at start".to_string());
+        }
+        let fingerprint = tls::pem_file_fingerprint(&ca_path).map_err(|e| format!("{:#}", e))?;
+        if !config_dir.join("ca-key.pem").exists() {
+            return Err(format!("{} has no ca-key.pem next to it — delete it to generate a new CA (devices pair again)", ca_path.display()));
+        }
+        return Ok(format!("leaf signed by {} ({}…)", ca_path.display(), fingerprint.chars().take(23).collect::<String>()));
+    }
+    let cert_path = config_dir.join(tls::CERT_FILENAME);
+    if !cert_path.exists() {
+        return Ok("self-signed certificate will be generated at start".to_string());
+    }
+    let fingerprint = tls::pem_file_fingerprint(&cert_path).map_err(|e| format!("{:#}", e))?;
+    let key_on_disk = config_dir.join("key.pem").exists();
+    if !key_on_disk && config.tls_policy.key_storage == KeyStorage::File {
+        return Err(format!("{} has no key.pem next to it — delete it to generate a new pair (devices pair again)", cert_path.display()));
+    }
+    Ok(format!("{} ({}…)", cert_path.display(), fingerprint.chars().take(23).collect::<String>()))
+}
+
+impl fmt::Display for PreflightReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Config: {}", self.config_path.display())?;
+        writeln!(f)?;
+        let mut table = Table::new(&["CHECK", "STATE", "DETAIL"]);
+        for check in &self.checks {
+            let state = match check.state {
+                CheckState::Pass => ("ok", Tone::Good),
+                CheckState::Warn => ("warn", Tone::Muted),
+                CheckState::Fail => ("fail", Tone::Bad),
+            };
+            table.toned_row([(check.name.as_str(), Tone::Plain), state, (check.detail.as_str(), Tone::Muted)]);
+        }
+        write!(f, "{}", table)?;
+        if self.failures() == 0 {
+            write!(f, "\nReady to start.")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn busy_ports_and_missing_agents_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut config = CommonConfig {
+            agent_command: Some("definitely-not-an-agent-binary --acp".to_string()),
+            bind_address: Some("127.0.0.1".to_string()),
+            ..CommonConfig::default()
+        };
+        config.transports.insert(
+            "local".to_string(),
+            TransportConfig {
+                enabled: true,
+                port: Some(listener.local_addr().unwrap().port()),
+                tls: Some(false),
+                ..TransportConfig::default()
+            },
+        );
+
+        let report = run(&config, dir.path(), None);
+        let state = |name: &str| report.checks.iter().find(|c| c.name == name).map(|c| c.state);
+        assert_eq!(state("config"), Some(CheckState::Pass));
+        assert_eq!(state("agent"), Some(CheckState::Fail));
+        assert_eq!(state("lock"), Some(CheckState::Pass));
+        assert_eq!(state("local port"), Some(CheckState::Fail));
+        assert_eq!(state("local tls"), Some(CheckState::Warn));
+        assert_eq!(report.failures(), 2);
+
+        config.agent_command = Some("sh -c true".to_string());
+        drop(listener);
+        assert_eq!(run(&config, dir.path(), Some("local")).failures(), 0);
+        assert_eq!(run(&config, dir.path(), Some("cloudflare")).failures(), 1);
+    }
+}
@@ -0,0 +1,49 @@
+//! Files only their owner may read: the config with the auth token, TLS
+//! keys, device keys, bundles and the like.
+
+use std::io::Write;
+use std::path::Path;
+
+/// Write `contents` to `path`, readable only by the owner on Unix. A new
+/// file is created with mode `0600`, and an existing one is restricted
+/// before anything is written, so the contents are never readable by others.
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(contents.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_and_existing_files_are_owner_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let fresh = dir.path().join("fresh");
+        write(&fresh, "secret").unwrap();
+        assert_eq!(std::fs::read_to_string(&fresh).unwrap(), "secret");
+
+        let existing = dir.path().join("existing");
+        std::fs::write(&existing, "a much longer old secret").unwrap();
+        write(&existing, b"new").unwrap();
+        assert_eq!(std::fs::read_to_string(&existing).unwrap(), "new");
+
+        #[cfg(unix)]
+        for path in [fresh, existing] {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+    }
+}
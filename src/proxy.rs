@@ -0,0 +1,106 @@
+//! Outbound proxy for the bridge's own HTTP traffic (`[proxy]`).
+//!
+//! Every outbound HTTP client (Cloudflare API, push relay, transcript upload,
+//! auth failure webhook, update check, `bridge status` probes) is built with
+//! [`client_builder`]. Without `[proxy]`, the clients follow the usual
+//! `HTTPS_PROXY` / `HTTP_PROXY` / `ALL_PROXY` / `NO_PROXY` environment
+//! variables. With it, the configured proxy (HTTP CONNECT or SOCKS5) is used
+//! instead and cloudflared is started with the same variables set.
+
+use std::sync::OnceLock;
+
+use crate::common_config::ProxyConfig;
+
+/// Proxy from `common.toml`, set once at startup.
+static PROXY: OnceLock<ProxyConfig> = OnceLock::new();
+
+/// Environment variables that select a proxy for child processes.
+const ENV_VARS: [&str; 6] = ["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"];
+
+/// Use `config` for every client built afterwards (call before any client
+/// is created).
+pub fn configure(config: Option<ProxyConfig>) {
+    if let Some(config) = config {
+        PROXY.set(config).ok();
+    }
+}
+
+/// A `reqwest` client builder that goes through the configured proxy.
+pub fn client_builder() -> reqwest::ClientBuilder {
+    builder_for(PROXY.get())
+}
+
+fn builder_for(config: Option<&ProxyConfig>) -> reqwest::ClientBuilder {
+    let builder = reqwest::Client::builder();
+    let Some(config) = config else {
+        return builder;
+    };
+    match reqwest::Proxy::all(&config.url) {
+        Ok(proxy) => builder.proxy(proxy.no_proxy(config.no_proxy.as_deref().and_then(reqwest::NoProxy::from_string))),
+        // `ProxyConfig::validate` rejects URLs reqwest can't use.
+        Err(_) => builder,
+    }
+}
+
+/// Whether outbound traffic goes through a proxy, configured or from the
+/// environment.
+pub fn in_use() -> bool {
+    PROXY.get().is_some() || ENV_VARS.iter().any(|var| std::env::var_os(var).is_some_and(|v| !v.is_empty()))
+}
+
+/// Variables to set on child processes (cloudflared) so they use the
+/// configured proxy; empty without `[proxy]`, leaving the inherited
+/// environment alone.
+pub fn child_env() -> Vec<(&'static str, String)> {
+    env_for(PROXY.get())
+}
+
+fn env_for(config: Option<&ProxyConfig>) -> Vec<(&'static str, String)> {
+    let Some(config) = config else {
+        return Vec::new();
+    };
+    let mut env: Vec<(&'static str, String)> = ENV_VARS.iter().map(|var| (*var, config.url.clone())).collect();
+    if let Some(no_proxy) = &config.no_proxy {
+        env.extend([("NO_PROXY", no_proxy.clone()), ("no_proxy", no_proxy.clone())]);
+    }
+    env
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn requests_go_through_the_proxy_unless_excluded() {
+        let mut proxy = mockito::Server::new_async().await;
+        let forwarded = proxy
+            .mock("GET", "/ping")
+            .match_header("host", "relay.invalid")
+            .with_body("pong")
+            .expect(1)
+            .create_async()
+            .await;
+        let config = ProxyConfig { url: proxy.url(), no_proxy: None };
+        let client = builder_for(Some(&config)).build().unwrap();
+        let body = client.get("http://relay.invalid/ping").send().await.unwrap().text().await.unwrap();
+        assert_eq!(body, "pong");
+        forwarded.assert_async().await;
+
+        // A dead proxy, bypassed for the excluded host.
+        let mut direct = mockito::Server::new_async().await;
+        direct.mock("GET", "/ping").with_body("direct").create_async().await;
+        let config = ProxyConfig { url: "http://127.0.0.1:9".into(), no_proxy: Some("127.0.0.1".into()) };
+        let client = builder_for(Some(&config)).build().unwrap();
+        let body = client.get(format!("{}/ping", direct.url())).send().await.unwrap().text().await.unwrap();
+        assert_eq!(body, "direct");
+    }
+
+    #[test]
+    fn child_processes_get_the_proxy_variables() {
+        assert!(env_for(None).is_empty());
+        let config = ProxyConfig { url: "socks5h://proxy.corp:1080".into(), no_proxy: Some(".corp".into()) };
+        let env = env_for(Some(&config));
+        assert!(env.contains(&("HTTPS_PROXY", "socks5h://proxy.corp:1080".to_string())));
+        assert!(env.contains(&("NO_PROXY", ".corp".to_string())));
+    }
+}
@@ -1,17 +1,109 @@
 use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveTime, Timelike, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use tracing::{debug, error, info, warn};
 
+/// Default minimum time between activity pushes.
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+pub const PUSH_DEVICES_FILENAME: &str = "push_devices.json";
+
 /// Cached JWT token with expiry tracking.
 struct JwtCache {
     token: String,
     expires_at: Instant,
 }
 
+/// What a push notification is about. Each category has its own cooldown,
+/// and quiet hours hold back all but permission requests. The names are
+/// the `event` values in the notification data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PushCategory {
+    /// Agent output while no client is connected
+    Activity,
+    /// The agent asked for permission while no client is connected
+    PermissionRequest,
+    Evicted,
+    MemoryPressure,
+    AutoLock,
+    CredentialsRotated,
+    AuthFailures,
+    ScheduledTask,
+    TriggeredPrompt,
+    /// `bridge push test`; never held back
+    Test,
+}
+
+/// A notification on its way to a [`PushSender`].
+#[derive(Debug, Clone, Copy)]
+pub struct PushMessage<'a> {
+    pub category: PushCategory,
+    pub title: &'a str,
+    pub body: &'a str,
+    pub data: Option<&'a HashMap<String, String>>,
+    /// The conversation the notification is about
+    pub session_id: Option<&'a str>,
+}
+
+impl<'a> PushMessage<'a> {
+    /// The `event` in the data, when there is one.
+    pub fn event(&self) -> Option<&'a str> {
+        self.data.and_then(|d| d.get("event")).map(String::as_str)
+    }
+}
+
+/// What [`PushSender::send`] returns.
+pub type SendFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// A backend that delivers notifications: the relay
+/// ([`PushRelayClient`] itself), or one from [`crate::push_providers`].
+pub trait PushSender: Send + Sync {
+    /// Name for logs and `bridge push test`.
+    fn name(&self) -> &str;
+
+    /// Whether this backend delivers `message` at all. Messages it
+    /// doesn't want are dropped before they count against a cooldown.
+    fn wants(&self, _message: &PushMessage<'_>) -> bool {
+        true
+    }
+
+    /// Deliver `message`; an error says why it wasn't.
+    fn send<'a>(&'a self, message: PushMessage<'a>) -> SendFuture<'a>;
+}
+
+/// A daily window without pushes, in local time. `start` after `end` spans
+/// midnight.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    /// Parse `HH:MM` times.
+    pub fn parse(start: &str, end: &str) -> Result<Self> {
+        let time = |s: &str| NaiveTime::parse_from_str(s, "%H:%M").with_context(|| format!("{:?} is not a HH:MM time", s));
+        Ok(Self { start: time(start)?, end: time(end)? })
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        let time = time.with_second(0).and_then(|t| t.with_nanosecond(0)).unwrap_or(time);
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
 /// Push relay client for forwarding device tokens and sending push notifications
 /// via the centralized push relay service (Cloudflare Worker).
 ///
@@ -21,15 +113,21 @@ struct JwtCache {
 pub struct PushRelayClient {
     relay_url: String,
     http_client: reqwest::Client,
-    /// Per-token debounce tracking: token → last notification time
-    debounce: Arc<RwLock<HashMap<String, Instant>>>,
-    /// Debounce cooldown duration (default 30s)
-    cooldown: Duration,
+    /// Debounce tracking: category → last notification time
+    debounce: Arc<RwLock<HashMap<PushCategory, Instant>>>,
+    /// Minimum time between pushes of a category (default: 30s for
+    /// activity, none for the rest)
+    cooldowns: HashMap<PushCategory, Duration>,
+    quiet_hours: Option<QuietHours>,
     /// JWT auth — set by with_jwt_credentials()
     token_url: Option<String>,
     client_id: Option<String>,
     client_secret: Option<String>,
     jwt_cache: Arc<RwLock<Option<JwtCache>>>,
+    /// Device tokens registered through this bridge, and their last push
+    registrations: Option<Arc<PushRegistrations>>,
+    /// Delivers instead of the relay, when set (see [`Self::for_sender`])
+    sender: Option<Arc<dyn PushSender>>,
 }
 
 /// Request to register a device token with the relay
@@ -48,12 +146,20 @@ struct UnregisterRequest {
 }
 
 /// Request to send a push notification
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
 struct PushRequest {
     title: String,
     body: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     data: Option<HashMap<String, String>>,
+    /// Conversation the app opens from the notification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_id: Option<String>,
+    /// What the notification is about; the app picks its action buttons by
+    /// it (approve and deny for `permissionRequest`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<PushCategory>,
 }
 
 /// Token service response for POST /token
@@ -63,6 +169,15 @@ struct TokenResponse {
     expires_in: u64,
 }
 
+/// Request for a test notification to every device registered for this
+/// client
+#[derive(Debug, Serialize)]
+struct TestRequest {
+    /// Deliver without showing anything (a background push)
+    silent: bool,
+    data: HashMap<String, String>,
+}
+
 /// Push relay API response
 #[derive(Debug, Deserialize)]
 struct RelayResponse {
@@ -71,6 +186,122 @@ struct RelayResponse {
     error: Option<String>,
     #[serde(default)]
     message: Option<String>,
+    /// Per-device results, from relays that report them
+    #[serde(default)]
+    results: Option<Vec<Delivery>>,
+}
+
+/// What the relay reported for one device.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Delivery {
+    pub device_token: String,
+    #[serde(default)]
+    pub platform: String,
+    /// APNs/FCM accepted the notification
+    pub delivered: bool,
+    /// Why it wasn't, e.g. `BadDeviceToken` or `Unregistered`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A device token registered with the relay through this bridge, as stored
+/// in `push_devices.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PushRegistration {
+    pub device_token: String,
+    pub platform: String,
+    /// The paired device's credentials key id, when the app sent it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    pub registered_at: DateTime<Utc>,
+    /// The last push the relay accepted for this device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_push_at: Option<DateTime<Utc>>,
+}
+
+/// The push registrations of a config directory (`push_devices.json`,
+/// permissions `0600`), kept so `bridge push test` and `bridge devices
+/// --paired` can tell which devices receive pushes.
+#[derive(Debug)]
+pub struct PushRegistrations {
+    path: PathBuf,
+    registrations: Mutex<Vec<PushRegistration>>,
+}
+
+impl PushRegistrations {
+    /// Open `push_devices.json` in `dir`; a missing or unreadable file
+    /// starts empty.
+    pub fn open(dir: &Path) -> Self {
+        let path = dir.join(PUSH_DEVICES_FILENAME);
+        let registrations = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Self { path, registrations: Mutex::new(registrations) }
+    }
+
+    /// The registrations, oldest first.
+    pub fn list(&self) -> Vec<PushRegistration> {
+        self.lock().clone()
+    }
+
+    /// The last push accepted for any token of the paired device `key_id`.
+    pub fn last_push_for_key(&self, key_id: &str) -> Option<DateTime<Utc>> {
+        self.lock().iter().filter(|r| r.key_id.as_deref() == Some(key_id)).filter_map(|r| r.last_push_at).max()
+    }
+
+    /// Record `device_token`, replacing an earlier registration of it.
+    pub fn register(&self, device_token: &str, platform: &str, key_id: Option<&str>) {
+        let mut registrations = self.lock();
+        let last_push_at = registrations.iter().find(|r| r.device_token == device_token).and_then(|r| r.last_push_at);
+        registrations.retain(|r| r.device_token != device_token);
+        registrations.push(PushRegistration {
+            device_token: device_token.to_string(),
+            platform: platform.to_string(),
+            key_id: key_id.map(String::from),
+            registered_at: Utc::now(),
+            last_push_at,
+        });
+        self.save(&registrations);
+    }
+
+    pub fn unregister(&self, device_token: &str) {
+        let mut registrations = self.lock();
+        registrations.retain(|r| r.device_token != device_token);
+        self.save(&registrations);
+    }
+
+    /// Stamp the devices a push reached: those `results` reports delivered,
+    /// or every registration when the relay only said it was accepted.
+    pub fn record_push(&self, results: Option<&[Delivery]>) {
+        let now = Utc::now();
+        let mut registrations = self.lock();
+        for registration in registrations.iter_mut() {
+            let delivered = results.is_none_or(|results| {
+                results.iter().any(|d| d.delivered && d.device_token == registration.device_token)
+            });
+            if delivered {
+                registration.last_push_at = Some(now);
+            }
+        }
+        self.save(&registrations);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<PushRegistration>> {
+        self.registrations.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn save(&self, registrations: &[PushRegistration]) {
+        let write = || -> Result<()> {
+            crate::private_file::write(&self.path, serde_json::to_string_pretty(registrations)?)?;
+            Ok(())
+        };
+        if let Err(e) = write() {
+            warn!("Failed to write {}: {:#}", self.path.display(), e);
+        }
+    }
 }
 
 impl PushRelayClient {
@@ -79,7 +310,7 @@ impl PushRelayClient {
     /// - `relay_url`: Base URL of the push relay (e.g., "https://push.aptove.com")
     /// - `_relay_token`: Kept for API compatibility; unused when JWT credentials are set
     pub fn new(relay_url: String, _relay_token: String) -> Self {
-        let http_client = reqwest::Client::builder()
+        let http_client = crate::proxy::client_builder()
             .timeout(Duration::from_secs(10))
             .build()
             .expect("Failed to create HTTP client");
@@ -88,14 +319,33 @@ impl PushRelayClient {
             relay_url: relay_url.trim_end_matches('/').to_string(),
             http_client,
             debounce: Arc::new(RwLock::new(HashMap::new())),
-            cooldown: Duration::from_secs(30),
+            cooldowns: HashMap::from([(PushCategory::Activity, DEFAULT_COOLDOWN)]),
+            quiet_hours: None,
             token_url: None,
             client_id: None,
             client_secret: None,
             jwt_cache: Arc::new(RwLock::new(None)),
+            registrations: None,
+            sender: None,
         }
     }
 
+    /// A client that delivers through `sender` (ntfy, Gotify, the desktop)
+    /// instead of a relay, with the same categories, cooldowns and quiet
+    /// hours. Device token registrations are ignored.
+    pub fn for_sender(sender: Arc<dyn PushSender>) -> Self {
+        let mut client = Self::new(String::new(), String::new());
+        client.sender = Some(sender);
+        client
+    }
+
+    /// Keep track of registered device tokens and their last push in
+    /// `registrations`.
+    pub fn with_registrations(mut self, registrations: Arc<PushRegistrations>) -> Self {
+        self.registrations = Some(registrations);
+        self
+    }
+
     /// Configure JWT authentication credentials from the token service.
     pub fn with_jwt_credentials(
         mut self,
@@ -109,6 +359,23 @@ impl PushRelayClient {
         self
     }
 
+    /// Minimum time between activity pushes (default 30s).
+    pub fn with_cooldown(self, cooldown: Duration) -> Self {
+        self.with_category_cooldown(PushCategory::Activity, cooldown)
+    }
+
+    /// Minimum time between pushes of `category`; zero sends every one.
+    pub fn with_category_cooldown(mut self, category: PushCategory, cooldown: Duration) -> Self {
+        self.cooldowns.insert(category, cooldown);
+        self
+    }
+
+    /// Send nothing but permission requests during `quiet_hours`.
+    pub fn with_quiet_hours(mut self, quiet_hours: QuietHours) -> Self {
+        self.quiet_hours = Some(quiet_hours);
+        self
+    }
+
     /// Fetch (or return cached) a JWT from the token service.
     ///
     /// The token is cached until it has < 60 seconds remaining.
@@ -186,12 +453,18 @@ impl PushRelayClient {
     /// Register a device token with the push relay.
     ///
     /// Called when the mobile app sends `bridge/registerPushToken` over WebSocket.
+    /// `key_id` is the paired device's credentials key id, when the app sent it.
     pub async fn register_device(
         &self,
         device_token: &str,
         platform: &str,
         bundle_id: Option<&str>,
+        key_id: Option<&str>,
     ) -> Result<()> {
+        if let Some(ref sender) = self.sender {
+            debug!("Ignoring a {} device token: pushes go through {}", platform, sender.name());
+            return Ok(());
+        }
         let url = format!("{}/register", self.relay_url);
         let body = RegisterRequest {
             device_token: device_token.to_string(),
@@ -217,6 +490,9 @@ impl PushRelayClient {
 
         if response.ok {
             info!("✅ Device token registered with push relay");
+            if let Some(ref registrations) = self.registrations {
+                registrations.register(device_token, platform, key_id);
+            }
             Ok(())
         } else {
             let err_msg = response
@@ -230,6 +506,9 @@ impl PushRelayClient {
 
     /// Unregister a device token from the push relay.
     pub async fn unregister_device(&self, device_token: &str) -> Result<()> {
+        if self.sender.is_some() {
+            return Ok(());
+        }
         let url = format!("{}/register", self.relay_url);
         let body = UnregisterRequest {
             device_token: device_token.to_string(),
@@ -251,63 +530,312 @@ impl PushRelayClient {
 
         if response.ok {
             info!("✅ Device token unregistered from push relay");
+            if let Some(ref registrations) = self.registrations {
+                registrations.unregister(device_token);
+            }
         }
         Ok(())
     }
 
+    /// Ask the relay to send a silent test notification to every device
+    /// registered for this bridge's client, and report what APNs/FCM said
+    /// for each. With another sender, send it a visible test notification
+    /// and report its one result. Not subject to cooldowns or quiet hours.
+    pub async fn test(&self) -> Result<Vec<Delivery>> {
+        if let Some(ref sender) = self.sender {
+            let data = HashMap::from([("event".to_string(), "test".to_string())]);
+            let message = PushMessage {
+                category: PushCategory::Test,
+                title: "Bridge test notification",
+                body: "Push notifications from the bridge work",
+                data: Some(&data),
+                session_id: None,
+            };
+            info!("🔔 Sending test push notification via {}", sender.name());
+            let outcome = sender.send(message).await;
+            return Ok(vec![Delivery {
+                device_token: String::new(),
+                platform: sender.name().to_string(),
+                delivered: outcome.is_ok(),
+                error: outcome.err().map(|e| format!("{:#}", e)),
+            }]);
+        }
+        let url = format!("{}/test", self.relay_url);
+        let body = TestRequest { silent: true, data: HashMap::from([("event".to_string(), "test".to_string())]) };
+        info!("🔔 Sending test push notification via relay");
+        let builder = self.http_client.post(&url).json(&body);
+        let builder = self.authorized_request(builder).await?;
+        let res = builder.send().await.context("Failed to contact push relay for the test")?;
+        let status = res.status();
+        if status == reqwest::StatusCode::NOT_FOUND {
+            anyhow::bail!("The push relay at {} doesn't support test notifications (HTTP 404 for /test)", self.relay_url);
+        }
+        let response: RelayResponse = res.json().await.context("Failed to parse push relay response")?;
+        if !response.ok {
+            let err_msg = response.error.or(response.message).unwrap_or_else(|| format!("HTTP {}", status));
+            anyhow::bail!("Push relay test failed: {}", err_msg);
+        }
+        let results = response.results.unwrap_or_default();
+        if let Some(ref registrations) = self.registrations {
+            registrations.record_push(Some(&results));
+        }
+        Ok(results)
+    }
+
+    /// Tell the device that its idle agent was evicted to make room for
+    /// another one. Evictions are rare and always worth a push, so they
+    /// have no cooldown unless one is configured.
+    pub async fn notify_evicted(&self, agent_name: &str) -> Result<bool> {
+        let mut data = HashMap::new();
+        data.insert("agentName".to_string(), agent_name.to_string());
+        data.insert("event".to_string(), "evicted".to_string());
+        let body = PushRequest {
+            title: agent_name.to_string(),
+            body: "Your idle session was closed to make room for another device".to_string(),
+            data: Some(data),
+            ..PushRequest::default()
+        };
+        info!("🔔 Sending eviction push notification via relay for agent '{}'", agent_name);
+        self.send_push(PushCategory::Evicted, &body).await
+    }
+
+    /// Warn the device that the bridge host is low on memory and is refusing
+    /// new connections. The caller rate-limits these (once per episode).
+    pub async fn notify_memory_pressure(&self, rss_mb: u64, max_mb: u64) -> Result<bool> {
+        let mut data = HashMap::new();
+        data.insert("event".to_string(), "memoryPressure".to_string());
+        data.insert("rssMb".to_string(), rss_mb.to_string());
+        data.insert("maxRssMb".to_string(), max_mb.to_string());
+        let body = PushRequest {
+            title: "Bridge low on memory".to_string(),
+            body: format!("Using {} MB of {} MB; new connections are paused", rss_mb, max_mb),
+            data: Some(data),
+            ..PushRequest::default()
+        };
+        info!("🔔 Sending memory pressure push notification via relay");
+        self.send_push(PushCategory::MemoryPressure, &body).await
+    }
+
+    /// Warn the device that the bridge will require fresh pairing in
+    /// `days_left` days unless something connects. Sent once per period by
+    /// the caller.
+    pub async fn notify_auto_lock(&self, days_left: u64) -> Result<bool> {
+        let mut data = HashMap::new();
+        data.insert("event".to_string(), "autoLock".to_string());
+        data.insert("daysLeft".to_string(), days_left.to_string());
+        let body = PushRequest {
+            title: "Bridge will lock soon".to_string(),
+            body: format!("Connect within {} day(s) or pair your device again", days_left),
+            data: Some(data),
+            ..PushRequest::default()
+        };
+        info!("🔔 Sending auto-lock warning push notification via relay");
+        self.send_push(PushCategory::AutoLock, &body).await
+    }
+
+    /// Tell the device that the bridge replaced the credentials for
+    /// `transport`, so it should pair again before the old ones expire.
+    pub async fn notify_credentials_rotated(&self, transport: &str) -> Result<bool> {
+        let mut data = HashMap::new();
+        data.insert("event".to_string(), "credentialsRotated".to_string());
+        data.insert("transport".to_string(), transport.to_string());
+        let body = PushRequest {
+            title: "Bridge credentials replaced".to_string(),
+            body: format!("Pair your device again to keep connecting over {}", transport),
+            data: Some(data),
+            ..PushRequest::default()
+        };
+        info!("🔔 Sending credentials rotation push notification via relay");
+        self.send_push(PushCategory::CredentialsRotated, &body).await
+    }
+
+    /// Tell the device that `ip` keeps failing to authenticate. The caller
+    /// sends this once per address and window.
+    pub async fn notify_auth_failures(&self, ip: &str, path: &str, failures: usize) -> Result<bool> {
+        let mut data = HashMap::new();
+        data.insert("event".to_string(), "authFailures".to_string());
+        data.insert("ip".to_string(), ip.to_string());
+        data.insert("path".to_string(), path.to_string());
+        data.insert("failures".to_string(), failures.to_string());
+        let body = PushRequest {
+            title: "Repeated failed logins".to_string(),
+            body: format!("{} failed attempts from {} ({})", failures, ip, path),
+            data: Some(data),
+            ..PushRequest::default()
+        };
+        info!("🔔 Sending auth failure push notification via relay");
+        self.send_push(PushCategory::AuthFailures, &body).await
+    }
+
+    /// Report a finished `[[schedules]]` run: `body` is the start of the
+    /// agent's reply, or why the run failed.
+    pub async fn notify_scheduled_task(&self, name: &str, body: &str, succeeded: bool) -> Result<bool> {
+        let mut data = HashMap::new();
+        data.insert("event".to_string(), "scheduledTask".to_string());
+        data.insert("schedule".to_string(), name.to_string());
+        data.insert("succeeded".to_string(), succeeded.to_string());
+        let body = PushRequest {
+            title: format!("Scheduled: {}", name),
+            body: body.to_string(),
+            data: Some(data),
+            ..PushRequest::default()
+        };
+        info!("🔔 Sending scheduled task push notification via relay for '{}'", name);
+        self.send_push(PushCategory::ScheduledTask, &body).await
+    }
+
+    /// Report a finished `POST /trigger` prompt: `body` is the start of the
+    /// agent's reply, or why it failed.
+    pub async fn notify_triggered_prompt(&self, session_id: &str, body: &str, succeeded: bool) -> Result<bool> {
+        let mut data = HashMap::new();
+        data.insert("event".to_string(), "triggeredPrompt".to_string());
+        data.insert("sessionId".to_string(), session_id.to_string());
+        data.insert("succeeded".to_string(), succeeded.to_string());
+        let body = PushRequest {
+            title: "Triggered prompt".to_string(),
+            body: body.to_string(),
+            data: Some(data),
+            ..PushRequest::default()
+        };
+        info!("🔔 Sending triggered prompt push notification via relay");
+        self.send_push(PushCategory::TriggeredPrompt, &body).await
+    }
+
     /// Send a push notification via the relay.
     ///
-    /// Includes per-agent debounce: if a notification was sent within the
-    /// cooldown window (default 30s), the new one is silently dropped.
+    /// Subject to the activity cooldown: if a notification was sent within
+    /// it (default 30s), the new one is silently dropped.
     ///
     /// The notification content is fixed ("Your agent has new activity")
-    /// to prevent leaking agent response content.
-    pub async fn notify(&self, agent_name: &str) -> Result<bool> {
-        // Use client_id as debounce key (unique per bridge identity)
-        let debounce_key = self
-            .client_id
-            .clone()
-            .unwrap_or_else(|| self.relay_url.clone());
+    /// to prevent leaking agent response content. `session_id` is the
+    /// conversation the app opens from it.
+    pub async fn notify(&self, agent_name: &str, session_id: Option<&str>) -> Result<bool> {
+        let mut data = HashMap::new();
+        data.insert("agentName".to_string(), agent_name.to_string());
+        let body = PushRequest {
+            title: agent_name.to_string(),
+            body: "Your agent has new activity".to_string(),
+            data: Some(data),
+            session_id: session_id.map(String::from),
+            ..PushRequest::default()
+        };
 
-        // Debounce check
-        {
-            let debounce = self.debounce.read().await;
-            if let Some(last) = debounce.get(&debounce_key) {
-                if last.elapsed() < self.cooldown {
-                    debug!(
-                        "Push notification throttled ({}s remaining)",
-                        (self.cooldown - last.elapsed()).as_secs()
-                    );
-                    return Ok(false);
-                }
-            }
-        }
+        info!("🔔 Sending push notification via relay for agent '{}'", agent_name);
+        self.send_push(PushCategory::Activity, &body).await
+    }
 
-        // Update debounce timestamp
-        {
-            let mut debounce = self.debounce.write().await;
-            debounce.insert(debounce_key, Instant::now());
-        }
+    /// Tell the device the agent finished its turn with `stop_reason`
+    /// (`end_turn`, `cancelled`, ...). An activity push, with the same
+    /// cooldown as [`Self::notify`], that says so.
+    pub async fn notify_turn_ended(&self, agent_name: &str, session_id: Option<&str>, stop_reason: &str) -> Result<bool> {
+        let mut data = HashMap::new();
+        data.insert("agentName".to_string(), agent_name.to_string());
+        data.insert("stopReason".to_string(), stop_reason.to_string());
+        let body = match stop_reason {
+            "end_turn" => "Your agent has finished".to_string(),
+            "cancelled" => "Your agent's turn was cancelled".to_string(),
+            reason => format!("Your agent stopped ({})", reason),
+        };
+        let body = PushRequest {
+            title: agent_name.to_string(),
+            body,
+            data: Some(data),
+            session_id: session_id.map(String::from),
+            ..PushRequest::default()
+        };
+        info!("🔔 Sending turn end push notification for agent '{}'", agent_name);
+        self.send_push(PushCategory::Activity, &body).await
+    }
 
-        let url = format!("{}/push", self.relay_url);
+    /// Tell the device the agent is waiting for a permission decision. Sent
+    /// during quiet hours too. The content is fixed, like [`Self::notify`]'s.
+    /// With the `request`, the app can answer it from the notification
+    /// through `POST /push/action` (see [`crate::push_action`]).
+    pub async fn notify_permission_request(&self, agent_name: &str, request: Option<&PermissionRequest>) -> Result<bool> {
         let mut data = HashMap::new();
         data.insert("agentName".to_string(), agent_name.to_string());
+        data.insert("event".to_string(), "permissionRequest".to_string());
+        if let Some(request) = request {
+            data.insert("requestId".to_string(), request.request_id.clone());
+        }
         let body = PushRequest {
             title: agent_name.to_string(),
-            body: "Your agent has new activity".to_string(),
+            body: "Your agent is waiting for your approval".to_string(),
             data: Some(data),
+            session_id: request.and_then(|r| r.session_id.clone()),
+            ..PushRequest::default()
         };
+        info!("🔔 Sending permission request push notification via relay for agent '{}'", agent_name);
+        self.send_push(PushCategory::PermissionRequest, &body).await
+    }
 
-        info!("🔔 Sending push notification via relay for agent '{}'", agent_name);
+    /// Whether a push of `category` may go out now, recording it if so:
+    /// outside quiet hours (permission requests excepted) and its cooldown.
+    async fn allows(&self, category: PushCategory) -> bool {
+        if category != PushCategory::PermissionRequest && self.quiet_hours.is_some_and(|q| q.contains(Local::now().time())) {
+            debug!("Push notification ({:?}) held back: quiet hours", category);
+            return false;
+        }
+        let cooldown = self.cooldowns.get(&category).copied().unwrap_or_default();
+        let mut debounce = self.debounce.write().await;
+        if let Some(last) = debounce.get(&category) {
+            if last.elapsed() < cooldown {
+                debug!(
+                    "Push notification ({:?}) throttled ({}s remaining)",
+                    category,
+                    (cooldown - last.elapsed()).as_secs()
+                );
+                return false;
+            }
+        }
+        debounce.insert(category, Instant::now());
+        true
+    }
 
-        let builder = self.http_client.post(&url).json(&body);
-        let builder = match self.authorized_request(builder).await {
-            Ok(b) => b,
+    /// Deliver a notification through the sender (the relay unless
+    /// [`Self::for_sender`] set another), unless quiet hours or the
+    /// category's cooldown hold it back. Delivery failures are logged and
+    /// reported as `false`.
+    async fn send_push(&self, category: PushCategory, body: &PushRequest) -> Result<bool> {
+        let sender: &dyn PushSender = self.sender.as_deref().unwrap_or(self);
+        let message = PushMessage {
+            category,
+            title: &body.title,
+            body: &body.body,
+            data: body.data.as_ref(),
+            session_id: body.session_id.as_deref(),
+        };
+        if !sender.wants(&message) {
+            debug!("Push notification ({:?}) not sent: {} doesn't show it", category, sender.name());
+            return Ok(false);
+        }
+        if !self.allows(category).await {
+            return Ok(false);
+        }
+        match sender.send(message).await {
+            Ok(()) => {
+                info!("✅ Push notification sent via {}", sender.name());
+                Ok(true)
+            }
             Err(e) => {
-                warn!("⚠️  Failed to get JWT for push notification: {}", e);
-                return Ok(false);
+                warn!("⚠️  Push notification via {} failed: {:#}", sender.name(), e);
+                Ok(false)
             }
+        }
+    }
+
+    /// POST a notification to the relay's `/push` endpoint.
+    async fn push_to_relay(&self, message: PushMessage<'_>) -> Result<()> {
+        let body = PushRequest {
+            title: message.title.to_string(),
+            body: message.body.to_string(),
+            data: message.data.cloned(),
+            session_id: message.session_id.map(String::from),
+            category: Some(message.category),
         };
+        let url = format!("{}/push", self.relay_url);
+        let builder = self.http_client.post(&url).json(&body);
+        let builder = self.authorized_request(builder).await.context("Failed to get JWT for push notification")?;
 
         let res = builder
             .send()
@@ -321,15 +849,185 @@ impl PushRelayClient {
             .context("Failed to parse push relay response")?;
 
         if response.ok {
-            info!("✅ Push notification sent via relay");
-            Ok(true)
+            if let Some(ref registrations) = self.registrations {
+                registrations.record_push(response.results.as_deref());
+            }
+            Ok(())
         } else {
             let err_msg = response
                 .error
                 .or(response.message)
                 .unwrap_or_else(|| format!("HTTP {}", status));
-            warn!("⚠️  Push relay notification failed: {}", err_msg);
-            Ok(false)
+            anyhow::bail!("Push relay answered: {}", err_msg)
         }
     }
 }
+
+impl PushSender for PushRelayClient {
+    fn name(&self) -> &str {
+        "relay"
+    }
+
+    fn send<'a>(&'a self, message: PushMessage<'a>) -> SendFuture<'a> {
+        Box::pin(self.push_to_relay(message))
+    }
+}
+
+/// Whether `message` is the agent asking the client for permission.
+pub fn is_permission_request(message: &str) -> bool {
+    message.contains("session/request_permission")
+        && serde_json::from_str::<serde_json::Value>(message).is_ok_and(|v| {
+            v.get("method").and_then(|m| m.as_str()) == Some("session/request_permission") && v.get("id").is_some()
+        })
+}
+
+/// A `session/request_permission` the app can answer from its notification.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PermissionRequest {
+    /// `params.sessionId`
+    pub session_id: Option<String>,
+    /// The JSON-RPC id: a string id as is, a number as its digits
+    pub request_id: String,
+}
+
+/// The permission request `message` is, if it is one.
+pub fn permission_request(message: &str) -> Option<PermissionRequest> {
+    if !is_permission_request(message) {
+        return None;
+    }
+    let value: serde_json::Value = serde_json::from_str(message).ok()?;
+    Some(PermissionRequest {
+        session_id: session_of(&value).map(String::from),
+        request_id: request_id_text(value.get("id")?),
+    })
+}
+
+/// A JSON-RPC id as it appears in push data.
+pub fn request_id_text(id: &serde_json::Value) -> String {
+    match id {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// The `params.sessionId` of an agent message.
+pub fn session_of(message: &serde_json::Value) -> Option<&str> {
+    message.get("params")?.get("sessionId")?.as_str()
+}
+
+/// The `stopReason` of a response that ends a prompt turn.
+pub fn stop_reason(message: &str) -> Option<String> {
+    if !message.contains("stopReason") {
+        return None;
+    }
+    let value: serde_json::Value = serde_json::from_str(message).ok()?;
+    if value.get("method").is_some() {
+        return None;
+    }
+    value.get("result")?.get("stopReason")?.as_str().map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quiet_hours_may_span_midnight() {
+        let at = |s: &str| NaiveTime::parse_from_str(s, "%H:%M:%S").unwrap();
+        let night = QuietHours::parse("23:00", "07:00").unwrap();
+        assert!(night.contains(at("23:00:00")) && night.contains(at("02:30:00")) && night.contains(at("06:59:59")));
+        assert!(!night.contains(at("07:00:00")) && !night.contains(at("22:59:59")));
+        let lunch = QuietHours::parse("12:00", "13:00").unwrap();
+        assert!(lunch.contains(at("12:30:00")) && !lunch.contains(at("13:00:00")));
+        assert!(QuietHours::parse("25:00", "07:00").is_err());
+        assert!(QuietHours::parse("11pm", "07:00").is_err());
+    }
+
+    #[tokio::test]
+    async fn categories_have_their_own_cooldowns_and_permission_requests_ignore_quiet_hours() {
+        let client = PushRelayClient::new("https://push.example".into(), String::new())
+            .with_category_cooldown(PushCategory::ScheduledTask, Duration::from_secs(3600));
+        assert!(client.allows(PushCategory::Activity).await);
+        assert!(!client.allows(PushCategory::Activity).await);
+        assert!(client.allows(PushCategory::ScheduledTask).await);
+        assert!(!client.allows(PushCategory::ScheduledTask).await);
+        // No cooldown by default.
+        assert!(client.allows(PushCategory::Evicted).await && client.allows(PushCategory::Evicted).await);
+
+        let always_quiet = QuietHours { start: NaiveTime::MIN, end: NaiveTime::from_hms_opt(23, 59, 59).unwrap() };
+        let client = PushRelayClient::new("https://push.example".into(), String::new())
+            .with_cooldown(Duration::ZERO)
+            .with_quiet_hours(always_quiet);
+        assert!(!client.allows(PushCategory::Activity).await);
+        assert!(client.allows(PushCategory::PermissionRequest).await);
+
+        assert!(is_permission_request(r#"{"jsonrpc":"2.0","id":3,"method":"session/request_permission","params":{}}"#));
+        assert!(!is_permission_request(r#"{"jsonrpc":"2.0","id":3,"result":{"method":"session/request_permission"}}"#));
+        assert_eq!(stop_reason(r#"{"jsonrpc":"2.0","id":4,"result":{"stopReason":"end_turn"}}"#).as_deref(), Some("end_turn"));
+        assert_eq!(stop_reason(r#"{"jsonrpc":"2.0","method":"session/update","params":{"stopReason":"end_turn"}}"#), None);
+    }
+
+    #[tokio::test]
+    async fn test_push_reports_each_device_and_stamps_the_delivered_ones() {
+        let mut server = mockito::Server::new_async().await;
+        let _token = server
+            .mock("POST", "/token")
+            .with_body(r#"{"access_token":"jwt","expires_in":3600}"#)
+            .create_async()
+            .await;
+        let test = server
+            .mock("POST", "/test")
+            .match_header("authorization", "Bearer jwt")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({"silent": true, "data": {"event": "test"}})))
+            .with_body(r#"{"ok":true,"results":[
+                {"device_token":"phone-token","platform":"ios","delivered":true},
+                {"device_token":"old-token","platform":"android","delivered":false,"error":"Unregistered"}]}"#)
+            .create_async()
+            .await;
+        let _register = server.mock("POST", "/register").with_body(r#"{"ok":true}"#).create_async().await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let registrations = Arc::new(PushRegistrations::open(dir.path()));
+        let client = PushRelayClient::new(server.url(), String::new())
+            .with_jwt_credentials(server.url(), "client".into(), "secret".into())
+            .with_registrations(registrations.clone());
+        client.register_device("phone-token", "ios", None, Some("key-1")).await.unwrap();
+        client.register_device("old-token", "android", None, None).await.unwrap();
+
+        let results = client.test().await.unwrap();
+        test.assert_async().await;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].error.as_deref(), Some("Unregistered"));
+        assert!(registrations.last_push_for_key("key-1").is_some());
+        let reopened = PushRegistrations::open(dir.path()).list();
+        assert!(reopened.iter().find(|r| r.device_token == "old-token").unwrap().last_push_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn permission_pushes_carry_their_session_category_and_request() {
+        let line = r#"{"jsonrpc":"2.0","id":7,"method":"session/request_permission","params":{"sessionId":"sess_42","options":[]}}"#;
+        let request = permission_request(line).unwrap();
+        assert_eq!(request, PermissionRequest { session_id: Some("sess_42".into()), request_id: "7".into() });
+
+        let mut server = mockito::Server::new_async().await;
+        let _token = server
+            .mock("POST", "/token")
+            .with_body(r#"{"access_token":"jwt","expires_in":3600}"#)
+            .create_async()
+            .await;
+        let push = server
+            .mock("POST", "/push")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "sessionId": "sess_42",
+                "category": "permissionRequest",
+                "data": {"event": "permissionRequest", "requestId": "7"},
+            })))
+            .with_body(r#"{"ok":true}"#)
+            .create_async()
+            .await;
+        let client = PushRelayClient::new(server.url(), String::new())
+            .with_jwt_credentials(server.url(), "client".into(), "secret".into());
+        assert!(client.notify_permission_request("copilot", Some(&request)).await.unwrap());
+        push.assert_async().await;
+    }
+}
@@ -0,0 +1,271 @@
+//! `POST /push/action`: answer a permission request from its notification.
+//!
+//! A `permissionRequest` push carries the `sessionId` and, in its data, the
+//! `requestId` of the agent's `session/request_permission`, so the app can
+//! show approve and deny buttons on the notification. The button posts here:
+//!
+//! ```json
+//! {"sessionId": "sess_42", "requestId": "7", "action": "approve"}
+//! ```
+//!
+//! The bridge answers the agent with the first option of the matching kind
+//! (`allow_once`, then `allow_always`; `reject_once`, then `reject_always`),
+//! or with `optionId` when the request names one. Denying a request that
+//! offers no reject option cancels it. Only requests still waiting in the
+//! replay buffer can be answered; one a client already received gets `404`.
+//! The request authenticates like `POST /acp`, needs the `chat` scope, and
+//! reaches the token's own pooled agent.
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::agent_pool::AgentPool;
+use crate::auth_failures::AuthFailures;
+use crate::bridge::{authenticate, create_http_response};
+use crate::device_tokens::{DeviceTokens, Grant, Scope};
+use crate::session_token::SessionTokens;
+use crate::streamable_http::{client_token, header, read_body};
+
+/// Path of the action endpoint.
+pub const PATH: &str = "/push/action";
+
+/// Largest accepted request body.
+const MAX_BODY: usize = 16 * 1024;
+
+/// Whether the request line targets the action endpoint.
+pub fn matches(first_line: &str) -> bool {
+    let mut parts = first_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("").split('?').next().unwrap_or("");
+    method == "POST" && path == PATH
+}
+
+/// What the notification's button asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    Approve,
+    Deny,
+}
+
+/// The body of a `POST /push/action` request.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct PushAction {
+    session_id: String,
+    request_id: String,
+    action: Action,
+    /// A specific option of the request, for apps that show them all
+    #[serde(default)]
+    option_id: Option<String>,
+}
+
+/// An HTTP error answer: status, reason phrase and JSON body.
+type Refusal = (u16, &'static str, String);
+
+fn refusal(status: u16, reason: &'static str, error: &str, message: &str) -> Refusal {
+    (status, reason, serde_json::json!({ "error": error, "message": message }).to_string())
+}
+
+fn parse(body: &[u8]) -> Result<PushAction, Refusal> {
+    serde_json::from_slice(body).map_err(|e| refusal(400, "Bad Request", "invalid_request", &e.to_string()))
+}
+
+/// The agent's answer to `request` for `action`, and the option chosen
+/// (`None` when the request is cancelled).
+fn answer(request: &Value, action: &PushAction) -> Result<(Value, Option<String>), Refusal> {
+    let options = request["params"]["options"].as_array().map(Vec::as_slice).unwrap_or_default();
+    let option_id = |option: &Value| option["optionId"].as_str().map(String::from);
+    let chosen = match action.option_id {
+        Some(ref wanted) => {
+            let found = options.iter().filter_map(option_id).find(|id| id == wanted);
+            Some(found.ok_or_else(|| refusal(400, "Bad Request", "unknown_option", &format!("The request has no option {:?}", wanted)))?)
+        }
+        None => {
+            let kinds: &[&str] = match action.action {
+                Action::Approve => &["allow_once", "allow_always"],
+                Action::Deny => &["reject_once", "reject_always"],
+            };
+            let found = kinds
+                .iter()
+                .find_map(|kind| options.iter().find(|o| o["kind"].as_str() == Some(kind)).and_then(option_id));
+            if found.is_none() && action.action == Action::Approve {
+                return Err(refusal(409, "Conflict", "no_allow_option", "The request offers no option to allow it"));
+            }
+            found
+        }
+    };
+    let outcome = match chosen {
+        Some(ref id) => serde_json::json!({ "outcome": "selected", "optionId": id }),
+        None => serde_json::json!({ "outcome": "cancelled" }),
+    };
+    let response = serde_json::json!({ "jsonrpc": "2.0", "id": request["id"], "result": { "outcome": outcome } });
+    Ok((response, chosen))
+}
+
+/// Serve one `POST /push/action` request.
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_request<S>(
+    stream: &mut S,
+    raw: &[u8],
+    request: &str,
+    auth_token: &Option<String>,
+    session_tokens: Option<&SessionTokens>,
+    devices: &DeviceTokens,
+    pool: Option<Arc<RwLock<AgentPool>>>,
+    auth_failures: Option<&AuthFailures>,
+    client_ip: &str,
+    read_timeout: Duration,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let token = client_token(request).unwrap_or_default();
+    let grant = match auth_token {
+        Some(expected) => authenticate(&token, expected, session_tokens, devices),
+        None => (!token.is_empty()).then(|| Grant::full(&token)),
+    };
+    let Some(grant) = grant else {
+        warn!("🚫 Push action rejected: invalid or missing auth token");
+        if let Some(failures) = auth_failures {
+            failures.record(client_ip, PATH);
+        }
+        let resp = create_http_response(401, "Unauthorized", r#"{"error":"unauthorized"}"#);
+        stream.write_all(resp.as_bytes()).await?;
+        return Ok(());
+    };
+
+    let Some(pool) = pool else {
+        let resp = create_http_response(
+            503,
+            "Service Unavailable",
+            r#"{"error":"pool_disabled","message":"Push actions require keep-alive agent pooling"}"#,
+        );
+        stream.write_all(resp.as_bytes()).await?;
+        return Ok(());
+    };
+
+    let content_length: usize = header(request, "Content-Length").and_then(|v| v.parse().ok()).unwrap_or(0);
+    if content_length > MAX_BODY {
+        let resp = create_http_response(413, "Payload Too Large", r#"{"error":"payload_too_large"}"#);
+        stream.write_all(resp.as_bytes()).await?;
+        return Ok(());
+    }
+    let Some(body) = read_body(stream, raw, content_length, read_timeout).await? else {
+        warn!("⏱️  Push action body stalled for {:?}, closing connection", read_timeout);
+        return Ok(());
+    };
+
+    let action = match parse(&body) {
+        Ok(action) if grant.scopes.contains(Scope::Chat) => action,
+        Ok(_) => {
+            let (status, reason, body) = refusal(403, "Forbidden", "scope_denied", "This token lacks the 'chat' scope");
+            stream.write_all(create_http_response(status, reason, &body).as_bytes()).await?;
+            return Ok(());
+        }
+        Err((status, reason, body)) => {
+            warn!("🚫 Push action refused ({}): {}", status, body);
+            stream.write_all(create_http_response(status, reason, &body).as_bytes()).await?;
+            return Ok(());
+        }
+    };
+
+    // A refused answer leaves the request in the replay buffer, in place,
+    // for the app to show on the next connection
+    let slot = pool.read().await.slot(&grant.pool_key);
+    let answered = slot
+        .as_ref()
+        .and_then(|slot| slot.answer_permission_request(&action.session_id, &action.request_id, |request| answer(request, &action)));
+    let (Some(slot), Some(answered)) = (slot, answered) else {
+        let (status, reason, body) = refusal(
+            404,
+            "Not Found",
+            "not_pending",
+            "No unanswered permission request with this id; it may have been answered already",
+        );
+        stream.write_all(create_http_response(status, reason, &body).as_bytes()).await?;
+        return Ok(());
+    };
+    let (response, chosen) = match answered {
+        Ok(answered) => answered,
+        Err((status, reason, body)) => {
+            warn!("🚫 Push action refused ({}): {}", status, body);
+            stream.write_all(create_http_response(status, reason, &body).as_bytes()).await?;
+            return Ok(());
+        }
+    };
+    if slot.ws_to_agent_tx.send(response.to_string()).await.is_err() {
+        let (status, reason, body) = refusal(502, "Bad Gateway", "agent_unavailable", "The agent has exited");
+        stream.write_all(create_http_response(status, reason, &body).as_bytes()).await?;
+        return Ok(());
+    }
+    info!(
+        "📲 Permission request {} in session {} answered from a notification: {}",
+        action.request_id,
+        action.session_id,
+        chosen.as_deref().unwrap_or("cancelled")
+    );
+    let body = serde_json::json!({ "ok": true, "optionId": chosen }).to_string();
+    stream.write_all(create_http_response(200, "OK", &body).as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn permission(options: Value) -> Value {
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 7,
+            "method": "session/request_permission",
+            "params": { "sessionId": "sess_42", "options": options },
+        })
+    }
+
+    fn action(body: &str) -> PushAction {
+        parse(body.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn buttons_pick_the_option_of_their_kind() {
+        assert!(matches("POST /push/action HTTP/1.1"));
+        assert!(!matches("GET /push/action HTTP/1.1"));
+        assert_eq!(parse(br#"{"sessionId":"sess_42","requestId":"7","action":"maybe"}"#).unwrap_err().0, 400);
+
+        let request = permission(serde_json::json!([
+            { "optionId": "always", "name": "Always allow", "kind": "allow_always" },
+            { "optionId": "once", "name": "Allow", "kind": "allow_once" },
+            { "optionId": "no", "name": "Reject", "kind": "reject_once" },
+        ]));
+        let (response, chosen) = answer(&request, &action(r#"{"sessionId":"sess_42","requestId":"7","action":"approve"}"#)).unwrap();
+        assert_eq!(chosen.as_deref(), Some("once"));
+        assert_eq!(response["id"], 7);
+        assert_eq!(response["result"]["outcome"], serde_json::json!({ "outcome": "selected", "optionId": "once" }));
+
+        let deny = action(r#"{"sessionId":"sess_42","requestId":"7","action":"deny"}"#);
+        assert_eq!(answer(&request, &deny).unwrap().1.as_deref(), Some("no"));
+        let always = action(r#"{"sessionId":"sess_42","requestId":"7","action":"approve","optionId":"always"}"#);
+        assert_eq!(answer(&request, &always).unwrap().1.as_deref(), Some("always"));
+        let unknown = action(r#"{"sessionId":"sess_42","requestId":"7","action":"approve","optionId":"later"}"#);
+        assert_eq!(answer(&request, &unknown).unwrap_err().0, 400);
+    }
+
+    #[test]
+    fn denying_without_a_reject_option_cancels() {
+        let request = permission(serde_json::json!([{ "optionId": "once", "name": "Allow", "kind": "allow_once" }]));
+        let (response, chosen) = answer(&request, &action(r#"{"sessionId":"sess_42","requestId":"7","action":"deny"}"#)).unwrap();
+        assert_eq!(chosen, None);
+        assert_eq!(response["result"]["outcome"]["outcome"], "cancelled");
+
+        let no_options = permission(serde_json::json!([]));
+        let approve = action(r#"{"sessionId":"sess_42","requestId":"7","action":"approve"}"#);
+        assert_eq!(answer(&no_options, &approve).unwrap_err().0, 409);
+    }
+}
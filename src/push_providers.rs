@@ -0,0 +1,290 @@
+//! Push backends besides the APNs/FCM relay, for self-hosters
+//! (`[push_relay] provider`).
+//!
+//! - `ntfy`: publishes to a topic on ntfy.sh or a self-hosted ntfy server;
+//!   the ntfy app on the phone subscribes to it.
+//! - `gotify`: posts a message to a Gotify server with an application token;
+//!   the Gotify app on the phone shows it.
+//! - the desktop: without `[push_relay]`, permission requests and finished
+//!   turns show as native notifications on the bridge host, for a "mobile"
+//!   client that is a tablet on the same desk.
+//!
+//! All implement [`PushSender`], like the relay, so every category, cooldown
+//! and quiet hour of [`PushRelayClient`](crate::push::PushRelayClient)
+//! applies unchanged. The phone registers nothing with the bridge: whoever
+//! subscribes to the topic or application gets the notifications, so use an
+//! unguessable ntfy topic or an access token.
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::push::{PushCategory, PushMessage, PushSender, SendFuture};
+
+/// Application name desktop notifications are shown under.
+const DESKTOP_APP_NAME: &str = "Aptove Bridge";
+
+/// Which backend `[push_relay]` configures.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PushProvider {
+    /// The APNs/FCM relay, with JWT credentials from the token service
+    #[default]
+    Relay,
+    Ntfy,
+    Gotify,
+}
+
+impl PushProvider {
+    pub fn is_relay(&self) -> bool {
+        *self == PushProvider::Relay
+    }
+}
+
+/// Whether a notification should interrupt: the agent waiting on you, or
+/// someone guessing tokens.
+fn urgent(category: PushCategory) -> bool {
+    matches!(category, PushCategory::PermissionRequest | PushCategory::AuthFailures)
+}
+
+fn http_client() -> reqwest::Client {
+    crate::proxy::client_builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_default()
+}
+
+/// Publishes to an ntfy topic.
+pub struct NtfySender {
+    server: String,
+    topic: String,
+    /// Access token for protected topics
+    token: Option<String>,
+    http_client: reqwest::Client,
+}
+
+/// JSON publish body (`POST /` on the server).
+#[derive(Debug, Serialize)]
+struct NtfyMessage<'a> {
+    topic: &'a str,
+    title: &'a str,
+    message: &'a str,
+    /// 1 (min) to 5 (max); 3 is the default
+    priority: u8,
+    tags: Vec<&'a str>,
+}
+
+impl NtfySender {
+    pub fn new(server: &str, topic: &str, token: Option<String>) -> Self {
+        Self {
+            server: server.trim_end_matches('/').to_string(),
+            topic: topic.to_string(),
+            token: token.filter(|t| !t.is_empty()),
+            http_client: http_client(),
+        }
+    }
+}
+
+impl PushSender for NtfySender {
+    fn name(&self) -> &str {
+        "ntfy"
+    }
+
+    fn send<'a>(&'a self, message: PushMessage<'a>) -> SendFuture<'a> {
+        Box::pin(async move {
+            let body = NtfyMessage {
+                topic: &self.topic,
+                title: message.title,
+                message: message.body,
+                priority: if urgent(message.category) { 4 } else { 3 },
+                tags: message.event().into_iter().collect(),
+            };
+            let mut request = self.http_client.post(format!("{}/", self.server)).json(&body);
+            if let Some(ref token) = self.token {
+                request = request.bearer_auth(token);
+            }
+            let res = request.send().await.context("Failed to contact the ntfy server")?;
+            if !res.status().is_success() {
+                anyhow::bail!("ntfy answered HTTP {}", res.status());
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Posts to a Gotify server as an application.
+pub struct GotifySender {
+    server: String,
+    app_token: String,
+    http_client: reqwest::Client,
+}
+
+/// Body of `POST /message`.
+#[derive(Debug, Serialize)]
+struct GotifyMessage<'a> {
+    title: &'a str,
+    message: &'a str,
+    /// 0 to 10; the Android app alerts from 4 and pops up from 8
+    priority: u8,
+}
+
+impl GotifySender {
+    pub fn new(server: &str, app_token: &str) -> Self {
+        Self {
+            server: server.trim_end_matches('/').to_string(),
+            app_token: app_token.to_string(),
+            http_client: http_client(),
+        }
+    }
+}
+
+impl PushSender for GotifySender {
+    fn name(&self) -> &str {
+        "Gotify"
+    }
+
+    fn send<'a>(&'a self, message: PushMessage<'a>) -> SendFuture<'a> {
+        Box::pin(async move {
+            let body = GotifyMessage {
+                title: message.title,
+                message: message.body,
+                priority: if urgent(message.category) { 8 } else { 5 },
+            };
+            let res = self
+                .http_client
+                .post(format!("{}/message", self.server))
+                .header("X-Gotify-Key", &self.app_token)
+                .json(&body)
+                .send()
+                .await
+                .context("Failed to contact the Gotify server")?;
+            if !res.status().is_success() {
+                anyhow::bail!("Gotify answered HTTP {}", res.status());
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Shows notifications on the bridge host's desktop: permission requests,
+/// finished turns, and `bridge push test`. Other categories are left to a
+/// phone.
+pub struct DesktopSender;
+
+impl DesktopSender {
+    /// Whether there is a desktop session to show notifications in. Always
+    /// on macOS and Windows; on Linux and the BSDs, a display or session bus
+    /// must be in the environment.
+    pub fn available() -> bool {
+        if cfg!(any(target_os = "macos", target_os = "windows")) {
+            return true;
+        }
+        ["DISPLAY", "WAYLAND_DISPLAY", "DBUS_SESSION_BUS_ADDRESS"]
+            .iter()
+            .any(|var| std::env::var_os(var).is_some_and(|v| !v.is_empty()))
+    }
+}
+
+impl PushSender for DesktopSender {
+    fn name(&self) -> &str {
+        "desktop"
+    }
+
+    fn wants(&self, message: &PushMessage<'_>) -> bool {
+        match message.category {
+            PushCategory::PermissionRequest | PushCategory::Test => true,
+            PushCategory::Activity => message.data.is_some_and(|d| d.contains_key("stopReason")),
+            _ => false,
+        }
+    }
+
+    fn send<'a>(&'a self, message: PushMessage<'a>) -> SendFuture<'a> {
+        let title = message.title.to_string();
+        let body = message.body.to_string();
+        let urgent = urgent(message.category);
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                let mut notification = notify_rust::Notification::new();
+                notification.appname(DESKTOP_APP_NAME).summary(&title).body(&body);
+                #[cfg(all(unix, not(target_os = "macos")))]
+                if urgent {
+                    notification.urgency(notify_rust::Urgency::Critical);
+                }
+                #[cfg(not(all(unix, not(target_os = "macos"))))]
+                let _ = urgent;
+                notification.show().map(|_| ())
+            })
+            .await
+            .context("Desktop notification task failed")?
+            .context("Failed to show a desktop notification")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::push::PushRelayClient;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn ntfy_and_gotify_receive_pushes_with_their_priority() {
+        let mut server = mockito::Server::new_async().await;
+        let ntfy = server
+            .mock("POST", "/")
+            .match_header("authorization", "Bearer tk_test")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "topic": "bridge-topic",
+                "title": "copilot",
+                "message": "Your agent is waiting for your approval",
+                "priority": 4,
+                "tags": ["permissionRequest"],
+            })))
+            .expect(1)
+            .create_async()
+            .await;
+        let gotify = server
+            .mock("POST", "/message")
+            .match_header("x-gotify-key", "app-token")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "title": "copilot",
+                "message": "Your agent has new activity",
+                "priority": 5,
+            })))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let client = PushRelayClient::for_sender(Arc::new(NtfySender::new(&server.url(), "bridge-topic", Some("tk_test".into()))));
+        assert!(client.notify_permission_request("copilot", None).await.unwrap());
+        let client = PushRelayClient::for_sender(Arc::new(GotifySender::new(&format!("{}/", server.url()), "app-token")));
+        assert!(client.notify("copilot", None).await.unwrap());
+        // Cooldowns apply as with the relay
+        assert!(!client.notify("copilot", None).await.unwrap());
+
+        ntfy.assert_async().await;
+        gotify.assert_async().await;
+    }
+
+    #[test]
+    fn desktop_shows_permission_requests_and_finished_turns_only() {
+        let finished = std::collections::HashMap::from([("stopReason".to_string(), "end_turn".to_string())]);
+        let message = |category, data| PushMessage { category, title: "copilot", body: "", data, session_id: None };
+        assert!(DesktopSender.wants(&message(PushCategory::PermissionRequest, None)));
+        assert!(DesktopSender.wants(&message(PushCategory::Activity, Some(&finished))));
+        assert!(!DesktopSender.wants(&message(PushCategory::Activity, None)));
+        assert!(!DesktopSender.wants(&message(PushCategory::AuthFailures, None)));
+    }
+
+    #[tokio::test]
+    async fn test_push_reports_the_provider_result() {
+        let mut server = mockito::Server::new_async().await;
+        let _refused = server.mock("POST", "/message").with_status(401).create_async().await;
+        let client = PushRelayClient::for_sender(Arc::new(GotifySender::new(&server.url(), "wrong")));
+        let results = client.test().await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(!results[0].delivered);
+        assert_eq!(results[0].platform, "Gotify");
+        assert!(results[0].error.as_deref().unwrap().contains("401"));
+    }
+}
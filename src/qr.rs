@@ -1,7 +1,8 @@
 use anyhow::{Context, Result};
 use qrcode::{QrCode, EcLevel};
 use crate::pairing::PairingManager;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 /// Unicode block characters for compact QR rendering
 /// Uses upper/lower half blocks to fit 2 rows per line
@@ -9,9 +10,111 @@ const BOTH_BLACK: &str = "█";
 const TOP_BLACK: &str = "▀";
 const BOTTOM_BLACK: &str = "▄";
 const BOTH_WHITE: &str = " ";
+/// ASCII fallback: one module per two characters, one row per line
+const ASCII_BLACK: &str = "##";
+const ASCII_WHITE: &str = "  ";
 
-/// Save a QR code as a PNG image file for easier scanning
-fn save_qr_code_image(data: &str, path: &PathBuf) -> Result<()> {
+/// How QR codes are drawn in the terminal (`--qr-style`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum QrStyle {
+    /// Unicode half blocks where the terminal can likely show them, else ASCII.
+    #[default]
+    Auto,
+    /// Unicode half blocks, two module rows per line.
+    Unicode,
+    /// `##` per dark module, for consoles without Unicode block characters.
+    Ascii,
+}
+
+/// `--qr-style`, set once at startup.
+static STYLE: OnceLock<QrStyle> = OnceLock::new();
+
+/// Draw QR codes in `style` from now on (call before any is rendered).
+pub fn set_style(style: QrStyle) {
+    STYLE.set(style).ok();
+}
+
+/// The style QR codes are drawn in: `--qr-style`, or detected from the
+/// environment.
+fn style() -> QrStyle {
+    match STYLE.get().copied().unwrap_or_default() {
+        QrStyle::Auto => detect_style(|name| std::env::var(name).ok(), cfg!(windows)),
+        style => style,
+    }
+}
+
+/// Unicode unless the terminal likely can't show it: a legacy Windows
+/// console (neither Windows Terminal nor an emulator setting `TERM_PROGRAM`),
+/// a dumb or VT100-class serial terminal, or a locale that isn't UTF-8.
+fn detect_style(env: impl Fn(&str) -> Option<String>, windows: bool) -> QrStyle {
+    if windows {
+        return match env("WT_SESSION").or_else(|| env("TERM_PROGRAM")) {
+            Some(_) => QrStyle::Unicode,
+            None => QrStyle::Ascii,
+        };
+    }
+    if matches!(env("TERM").as_deref(), Some("dumb" | "vt100" | "vt102" | "vt220")) {
+        return QrStyle::Ascii;
+    }
+    // The first of these that is set decides, as in the C library.
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"].iter().find_map(|name| env(name).filter(|v| !v.is_empty()));
+    match locale.map(|l| l.to_ascii_lowercase()) {
+        Some(l) if !l.contains("utf-8") && !l.contains("utf8") => QrStyle::Ascii,
+        _ => QrStyle::Unicode,
+    }
+}
+
+/// Columns of the terminal on stdout, if it is one.
+fn terminal_columns() -> Option<usize> {
+    crossterm::terminal::size().ok().map(|(columns, _)| columns as usize)
+}
+
+/// File name of the pairing QR image inside the config directory.
+pub const QR_IMAGE_FILENAME: &str = "pairing-qr.png";
+
+/// The pairing QR code as a PNG in the config directory, for phones that
+/// can't scan the terminal. It holds a one-time code, so it is readable by
+/// this user only, replaced with each new code and deleted on drop.
+pub struct QrImage {
+    path: PathBuf,
+    open: bool,
+    opened: bool,
+}
+
+impl QrImage {
+    /// `open`: launch the platform image viewer on the first image written.
+    pub fn new(config_dir: &Path, open: bool) -> Self {
+        let path = config_dir.join(QR_IMAGE_FILENAME);
+        // Left behind by a run that was killed; its code is stale.
+        let _ = std::fs::remove_file(&path);
+        Self { path, open, opened: false }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replace the image with the QR code of `data`.
+    pub fn write(&mut self, data: &str) -> Result<()> {
+        save_qr_code_image(data, &self.path)?;
+        if self.open && !self.opened {
+            self.opened = true;
+            if let Err(e) = open::that_detached(&self.path) {
+                tracing::warn!("Could not open {}: {}", self.path.display(), e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for QrImage {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Save a QR code as a PNG image file for easier scanning, `0600`
+fn save_qr_code_image(data: &str, path: &Path) -> Result<()> {
     use image::{Luma, GrayImage};
     
     let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::L)
@@ -39,16 +142,94 @@ fn save_qr_code_image(data: &str, path: &PathBuf) -> Result<()> {
         }
     }
     
-    img.save(path).context("Failed to save QR code image")?;
-    Ok(())
+    let mut png = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut png, image::ImageFormat::Png).context("Failed to encode QR code image")?;
+    crate::private_file::write(path, png.into_inner()).context("Failed to save QR code image")
 }
 
-/// Render a QR code to a string for terminal display
+/// Render a QR code as an SVG document for the web admin UI
+pub fn render_qr_svg(data: &str) -> Result<String> {
+    let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::L)
+        .context("Failed to generate QR code")?;
+    Ok(code
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(240, 240)
+        .build())
+}
+
+/// Render a QR code to a string for terminal display, in the style of
+/// `--qr-style`
 pub fn render_qr_code(data: &str) -> Result<String> {
     // Use lower error correction to reduce QR code size
     let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::L)
         .context("Failed to generate QR code")?;
-    
+    Ok(match style() {
+        QrStyle::Ascii => render_ascii(&code),
+        _ => render_unicode(&code),
+    })
+}
+
+/// Columns `code` takes in `style`, quiet zone included.
+fn rendered_width(code: &QrCode, style: QrStyle) -> usize {
+    match style {
+        QrStyle::Ascii => (code.width() + 4) * ASCII_BLACK.len(),
+        _ => code.width() + 4,
+    }
+}
+
+/// Whether the QR code of `data` fits the terminal (always, when stdout
+/// isn't one).
+fn fits_terminal(data: &str) -> Result<bool> {
+    let Some(columns) = terminal_columns() else {
+        return Ok(true);
+    };
+    let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::L)
+        .context("Failed to generate QR code")?;
+    Ok(rendered_width(&code, style()) <= columns)
+}
+
+/// Render the QR code of a pairing URL, switching to the
+/// [compact form](crate::pairing::compact_pairing_url) when the full one is
+/// wider than the terminal. Returns the code and the URL it encodes.
+pub fn render_pairing_qr(pairing_url: &str) -> Result<(String, String)> {
+    let mut url = pairing_url.to_string();
+    if !fits_terminal(&url)? {
+        url = crate::pairing::compact_pairing_url(pairing_url);
+        if !fits_terminal(&url)? {
+            tracing::warn!("The QR code is wider than the terminal — widen it or use a smaller font");
+        }
+    }
+    Ok((render_qr_code(&url)?, url))
+}
+
+/// `##` per dark module, two-module quiet zone
+fn render_ascii(code: &QrCode) -> String {
+    let modules = code.to_colors();
+    let width = code.width();
+    let blank_row = ASCII_WHITE.repeat(width + 4);
+
+    let mut output = String::from("\n");
+    for _ in 0..2 {
+        output.push_str(&blank_row);
+        output.push('\n');
+    }
+    for row in modules.chunks(width) {
+        output.push_str(&ASCII_WHITE.repeat(2));
+        for &color in row {
+            output.push_str(if color == qrcode::Color::Dark { ASCII_BLACK } else { ASCII_WHITE });
+        }
+        output.push_str(&ASCII_WHITE.repeat(2));
+        output.push('\n');
+    }
+    for _ in 0..2 {
+        output.push_str(&blank_row);
+        output.push('\n');
+    }
+    output
+}
+
+/// Unicode half blocks, two module rows per line
+fn render_unicode(code: &QrCode) -> String {
     let modules = code.to_colors();
     let width = code.width();
     
@@ -57,7 +238,7 @@ pub fn render_qr_code(data: &str) -> Result<String> {
     let mut output = String::new();
     
     // Add quiet zone (1 row of white)
-    output.push_str("\n");
+    output.push('\n');
     for _ in 0..width + 4 {
         output.push(' ');
     }
@@ -99,26 +280,30 @@ pub fn render_qr_code(data: &str) -> Result<String> {
     }
     output.push('\n');
     
-    Ok(output)
+    output
 }
 
 /// Display a QR code with pairing URL for secure mobile connection.
 ///
 /// `hostname` is the WebSocket URL (e.g. `wss://192.168.1.1:8765`); it is
-/// converted to HTTPS/HTTP for the pairing endpoint.
-pub fn display_qr_code_with_pairing(hostname: &str, pairing: &PairingManager) -> Result<()> {
+/// converted to HTTPS/HTTP for the pairing endpoint. `image` is replaced with
+/// the new code's QR code.
+pub fn display_qr_code_with_pairing(hostname: &str, pairing: &PairingManager, image: &mut QrImage) -> Result<()> {
     // Build the base URL for pairing (HTTPS)
     let base_url = hostname.replace("wss://", "https://").replace("ws://", "http://");
     let pairing_url = pairing.get_pairing_url(&base_url);
     
-    // Render the QR code
-    let qr_output = render_qr_code(&pairing_url)?;
+    // Render the QR code, compact if the terminal is narrow
+    let (qr_output, _) = render_pairing_qr(&pairing_url)?;
     
     // Save QR code as image for easier scanning
-    let qr_image_path = std::env::temp_dir().join("bridge_pairing_qr.png");
-    if let Err(e) = save_qr_code_image(&pairing_url, &qr_image_path) {
-        tracing::warn!("Could not save QR code image: {}", e);
-    }
+    let saved = match image.write(&pairing_url) {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::warn!("Could not save QR code image: {:#}", e);
+            false
+        }
+    };
     
     // Display expiration notice
     println!("\n━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
@@ -131,10 +316,11 @@ pub fn display_qr_code_with_pairing(hostname: &str, pairing: &PairingManager) ->
     // Display the full pairing URL and image path
     println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
     println!("  📱 Scan QR code with your mobile app");
+    println!("  🔢 Or tap an NFC tag and enter code {}", pairing.get_code());
     println!("  🔗 {}", pairing_url);
-    if qr_image_path.exists() {
-        println!("  🖼️  QR image saved to: {}", qr_image_path.display());
-        println!("     (Open this file if terminal QR code doesn't scan)");
+    if saved {
+        println!("  🖼️  QR image saved to: {}", image.path().display());
+        println!("     (Open this file if terminal QR code doesn't scan; it is deleted once the code is used or expires)");
     }
     println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━\n");
     
@@ -143,13 +329,17 @@ pub fn display_qr_code_with_pairing(hostname: &str, pairing: &PairingManager) ->
 
 /// Display a static QR code in the terminal for mobile scanning (no pairing handshake).
 ///
-/// `connection_json` is the pre-built JSON string to encode (e.g. from
-/// `CommonConfig::to_connection_json()` or `BridgeConfig::to_connection_json()`).
-pub fn display_qr_code(connection_json: &str, transport: &str) -> Result<()> {
+/// `qr_data` is what the QR encodes: usually `connection_json` itself, the
+/// pre-built JSON string (e.g. from `CommonConfig::to_connection_json()` or
+/// `BridgeConfig::to_connection_json()`), or a deep link wrapping it.
+pub fn display_qr_code(qr_data: &str, connection_json: &str, transport: &str) -> Result<()> {
     // Render the QR code
-    let qr_output = render_qr_code(connection_json)?;
+    let qr_output = render_qr_code(qr_data)?;
 
     println!("{}", qr_output);
+    if !fits_terminal(qr_data)? {
+        println!("⚠️  The QR code is wider than the terminal — widen it, or send the link below instead\n");
+    }
 
     // Parse and pretty-print the QR code content
     let json_value: serde_json::Value = serde_json::from_str(connection_json)
@@ -191,11 +381,20 @@ pub fn display_qr_code(connection_json: &str, transport: &str) -> Result<()> {
             println!("  TLS Fingerprint: {}", fingerprint);
         }
     }
+    if let Some(endpoints) = json_value.get("endpoints").and_then(|v| v.as_array()) {
+        println!("  Endpoints (the app uses the first it reaches):");
+        for endpoint in endpoints {
+            let name = endpoint.get("transport").and_then(|v| v.as_str()).unwrap_or("?");
+            let url = endpoint.get("url").and_then(|v| v.as_str()).unwrap_or("?");
+            println!("    {:<16} {}", name, url);
+        }
+    }
     
     println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
     let mode_label = match transport {
         "cloudflare"      => "Cloudflare Zero Trust (internet accessible)",
         "tailscale-serve" => "Tailscale (MagicDNS + HTTPS)",
+        "tailscale-ip"    => "Tailscale (direct tailnet IP)",
         _                 => "Local Network",
     };
     println!("  Mode: {}", mode_label);
@@ -203,3 +402,75 @@ pub fn display_qr_code(connection_json: &str, transport: &str) -> Result<()> {
     
     Ok(())
 }
+
+/// How long `copy_to_clipboard` keeps serving the clipboard on Linux, where
+/// X11/Wayland selections vanish when the owning process exits.
+#[cfg(target_os = "linux")]
+const CLIPBOARD_HOLD: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Place `text` on the system clipboard.
+///
+/// On Linux this blocks for up to a minute (or until something else is
+/// copied) so the text can still be pasted after the command would exit.
+pub fn copy_to_clipboard(text: &str) -> Result<()> {
+    let mut clipboard = arboard::Clipboard::new().context("System clipboard unavailable")?;
+    #[cfg(target_os = "linux")]
+    {
+        use arboard::SetExtLinux;
+        clipboard
+            .set()
+            .wait_until(std::time::Instant::now() + CLIPBOARD_HOLD)
+            .text(text)
+            .context("Failed to copy to clipboard")
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        clipboard.set_text(text).context("Failed to copy to clipboard")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn style_falls_back_to_ascii_without_unicode() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| vars.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+        };
+        assert_eq!(detect_style(env(&[("LANG", "en_US.UTF-8")]), false), QrStyle::Unicode);
+        assert_eq!(detect_style(env(&[]), false), QrStyle::Unicode);
+        assert_eq!(detect_style(env(&[("LC_ALL", "C"), ("LANG", "en_US.UTF-8")]), false), QrStyle::Ascii);
+        assert_eq!(detect_style(env(&[("TERM", "vt220"), ("LANG", "en_US.UTF-8")]), false), QrStyle::Ascii);
+        assert_eq!(detect_style(env(&[]), true), QrStyle::Ascii);
+        assert_eq!(detect_style(env(&[("WT_SESSION", "1")]), true), QrStyle::Unicode);
+    }
+
+    #[test]
+    fn ascii_rendering_is_two_columns_per_module() {
+        let code = QrCode::with_error_correction_level(b"https://127.0.0.1:8765/pair/local?code=1", EcLevel::L).unwrap();
+        let ascii = render_ascii(&code);
+        assert!(ascii.is_ascii());
+        let rows: Vec<&str> = ascii.lines().skip(1).collect();
+        assert_eq!(rows.len(), code.width() + 4);
+        assert!(rows.iter().all(|row| row.len() == rendered_width(&code, QrStyle::Ascii)));
+    }
+
+    #[test]
+    fn qr_image_is_private_and_removed_on_drop() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = {
+            let mut image = QrImage::new(dir.path(), false);
+            image.write("https://127.0.0.1:8765/pair/local?code=123456").unwrap();
+            assert!(image.path().exists());
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = std::fs::metadata(image.path()).unwrap().permissions().mode();
+                assert_eq!(mode & 0o777, 0o600);
+            }
+            image.path().to_path_buf()
+        };
+        assert!(!path.exists());
+    }
+}
@@ -1,19 +1,30 @@
 use std::collections::HashMap;
 use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 
+/// Retry hint for connection-count limits, which free up at an unknown time.
+const BUSY_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Retry hint while new connections are shed under memory pressure.
+const SHEDDING_RETRY_AFTER: Duration = Duration::from_secs(30);
+
 /// Simple rate limiter to prevent abuse
 pub struct RateLimiter {
     /// Maximum concurrent connections per IP
     max_connections_per_ip: usize,
     /// Maximum connection attempts per minute per IP
     max_attempts_per_minute: usize,
+    /// Maximum concurrent connections across all IPs (0 = unlimited)
+    max_total_connections: usize,
     /// Current connection counts per IP
     connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
     /// Recent connection attempts per IP (timestamp of each attempt)
     attempts: Arc<Mutex<HashMap<IpAddr, Vec<Instant>>>>,
+    /// Set by the memory watchdog: refuse every new connection
+    shedding: AtomicBool,
 }
 
 impl RateLimiter {
@@ -21,14 +32,36 @@ impl RateLimiter {
         Self {
             max_connections_per_ip,
             max_attempts_per_minute,
+            max_total_connections: 0,
             connections: Arc::new(Mutex::new(HashMap::new())),
             attempts: Arc::new(Mutex::new(HashMap::new())),
+            shedding: AtomicBool::new(false),
         }
     }
 
+    /// Cap the number of concurrent connections across all IPs (0 = unlimited)
+    pub fn with_max_total_connections(mut self, max: usize) -> Self {
+        self.max_total_connections = max;
+        self
+    }
+
+    /// Refuse (or, with `false`, resume accepting) all new connections
+    pub fn set_shedding(&self, shedding: bool) {
+        self.shedding.store(shedding, Ordering::Relaxed);
+    }
+
+    /// Whether new connections are currently refused by [`set_shedding`](Self::set_shedding)
+    pub fn is_shedding(&self) -> bool {
+        self.shedding.load(Ordering::Relaxed)
+    }
+
     /// Check if a new connection is allowed from this IP
     /// Returns Ok(()) if allowed, Err with reason if denied
     pub async fn check_connection(&self, ip: IpAddr) -> Result<(), RateLimitError> {
+        if self.is_shedding() {
+            return Err(RateLimitError::Shedding);
+        }
+
         // Check rate limit (attempts per minute)
         {
             let mut attempts = self.attempts.lock().await;
@@ -43,9 +76,15 @@ impl RateLimiter {
             
             // Check if we've exceeded the rate limit
             if ip_attempts.len() >= self.max_attempts_per_minute {
+                // The next slot opens when the oldest attempt leaves the window
+                let retry_after = ip_attempts
+                    .first()
+                    .map(|oldest| (*oldest + Duration::from_secs(60)).saturating_duration_since(now))
+                    .unwrap_or(Duration::from_secs(60));
                 return Err(RateLimitError::TooManyAttempts {
                     attempts: ip_attempts.len(),
                     max: self.max_attempts_per_minute,
+                    retry_after,
                 });
             }
             
@@ -53,9 +92,18 @@ impl RateLimiter {
             ip_attempts.push(now);
         }
 
-        // Check concurrent connection limit
+        // Check concurrent connection limits
         {
             let connections = self.connections.lock().await;
+            if self.max_total_connections > 0 {
+                let total: usize = connections.values().sum();
+                if total >= self.max_total_connections {
+                    return Err(RateLimitError::ServerFull {
+                        current: total,
+                        max: self.max_total_connections,
+                    });
+                }
+            }
             if let Some(&count) = connections.get(&ip) {
                 if count >= self.max_connections_per_ip {
                     return Err(RateLimitError::TooManyConnections {
@@ -87,12 +135,36 @@ impl RateLimiter {
             }
         }
     }
+
+    /// Active connections per IP, most first
+    pub async fn connections(&self) -> Vec<(IpAddr, usize)> {
+        let mut connections: Vec<(IpAddr, usize)> = self.connections.lock().await.iter().map(|(ip, n)| (*ip, *n)).collect();
+        connections.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        connections
+    }
 }
 
 #[derive(Debug)]
 pub enum RateLimitError {
     TooManyConnections { current: usize, max: usize },
-    TooManyAttempts { attempts: usize, max: usize },
+    TooManyAttempts { attempts: usize, max: usize, retry_after: Duration },
+    ServerFull { current: usize, max: usize },
+    /// The memory watchdog is refusing new connections
+    Shedding,
+}
+
+impl RateLimitError {
+    /// Whole seconds a client should wait before retrying, with up to 50%
+    /// random jitter added so throttled clients don't all return at once.
+    pub fn retry_after_secs(&self) -> u64 {
+        let base = match self {
+            RateLimitError::TooManyAttempts { retry_after, .. } => *retry_after,
+            RateLimitError::TooManyConnections { .. } | RateLimitError::ServerFull { .. } => BUSY_RETRY_AFTER,
+            RateLimitError::Shedding => SHEDDING_RETRY_AFTER,
+        };
+        let base = base.as_secs_f64().ceil().max(1.0) as u64;
+        base + rand::random_range(0..=base / 2)
+    }
 }
 
 impl std::fmt::Display for RateLimitError {
@@ -101,11 +173,109 @@ impl std::fmt::Display for RateLimitError {
             RateLimitError::TooManyConnections { current, max } => {
                 write!(f, "Too many concurrent connections ({}/{})", current, max)
             }
-            RateLimitError::TooManyAttempts { attempts, max } => {
+            RateLimitError::TooManyAttempts { attempts, max, .. } => {
                 write!(f, "Too many connection attempts ({}/{} per minute)", attempts, max)
             }
+            RateLimitError::ServerFull { current, max } => {
+                write!(f, "Server connection limit reached ({}/{})", current, max)
+            }
+            RateLimitError::Shedding => {
+                write!(f, "Server is low on memory; not accepting new connections")
+            }
         }
     }
 }
 
 impl std::error::Error for RateLimitError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn attempt_limit_hints_when_the_window_frees_up() {
+        let limiter = RateLimiter::new(10, 2);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        limiter.check_connection(ip).await.unwrap();
+        limiter.check_connection(ip).await.unwrap();
+        let err = limiter.check_connection(ip).await.unwrap_err();
+        let RateLimitError::TooManyAttempts { retry_after, .. } = err else {
+            panic!("expected TooManyAttempts, got {err:?}");
+        };
+        assert!(retry_after > Duration::from_secs(58) && retry_after <= Duration::from_secs(60));
+        for _ in 0..20 {
+            assert!((60..=90).contains(&err_retry(&limiter, ip).await));
+        }
+    }
+
+    #[tokio::test]
+    async fn connection_limits_hint_a_short_jittered_wait() {
+        let limiter = RateLimiter::new(1, 100);
+        let ip: IpAddr = "10.0.0.2".parse().unwrap();
+        limiter.add_connection(ip).await;
+        for _ in 0..20 {
+            assert!((5..=7).contains(&err_retry(&limiter, ip).await));
+        }
+    }
+
+    #[tokio::test]
+    async fn shedding_refuses_everyone_until_cleared() {
+        let limiter = RateLimiter::new(10, 100);
+        let ip: IpAddr = "10.0.0.3".parse().unwrap();
+        limiter.set_shedding(true);
+        assert!(matches!(limiter.check_connection(ip).await, Err(RateLimitError::Shedding)));
+        assert!((30..=45).contains(&err_retry(&limiter, ip).await));
+        limiter.set_shedding(false);
+        assert!(limiter.check_connection(ip).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn global_cap_counts_connections_from_every_ip() {
+        let limiter = RateLimiter::new(10, 100).with_max_total_connections(2);
+        let a: IpAddr = "10.0.0.4".parse().unwrap();
+        let b: IpAddr = "10.0.0.5".parse().unwrap();
+        let c: IpAddr = "10.0.0.6".parse().unwrap();
+        limiter.add_connection(a).await;
+        limiter.add_connection(b).await;
+        assert!(matches!(
+            limiter.check_connection(c).await,
+            Err(RateLimitError::ServerFull { current: 2, max: 2 })
+        ));
+        limiter.remove_connection(a).await;
+        assert!(limiter.check_connection(c).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn zero_global_cap_is_unlimited() {
+        let limiter = RateLimiter::new(100, 100).with_max_total_connections(0);
+        for i in 0..50u8 {
+            limiter.add_connection(IpAddr::from([10, 1, 0, i])).await;
+        }
+        assert!(limiter.check_connection("10.1.1.1".parse().unwrap()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn configured_per_ip_limits_apply_to_that_ip_only() {
+        let limiter = RateLimiter::new(2, 3);
+        let busy: IpAddr = "10.0.0.7".parse().unwrap();
+        let other: IpAddr = "10.0.0.8".parse().unwrap();
+        limiter.add_connection(busy).await;
+        assert!(limiter.check_connection(busy).await.is_ok());
+        limiter.add_connection(busy).await;
+        for _ in 0..2 {
+            assert!(matches!(
+                limiter.check_connection(busy).await,
+                Err(RateLimitError::TooManyConnections { current: 2, max: 2 })
+            ));
+        }
+        assert!(matches!(
+            limiter.check_connection(busy).await,
+            Err(RateLimitError::TooManyAttempts { attempts: 3, max: 3, .. })
+        ));
+        assert!(limiter.check_connection(other).await.is_ok());
+    }
+
+    async fn err_retry(limiter: &RateLimiter, ip: IpAddr) -> u64 {
+        limiter.check_connection(ip).await.unwrap_err().retry_after_secs()
+    }
+}
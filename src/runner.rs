@@ -5,27 +5,41 @@
 
 use anyhow::{Context, Result};
 use tokio::sync::mpsc;
-use tracing::{info, warn};
-
-use crate::bridge::StdioBridge;
-use crate::cloudflare::{write_credentials_file, write_cloudflared_config_at, cloudflared_config_path};
+use tracing::{debug, info, warn};
+
+use crate::auto_lock::{self, Activity, LockState};
+use crate::device_keys::{self, CredentialsUpdate, DeviceKeys};
+use crate::service_token_rotation;
+use crate::tunnel_health::{self, TunnelMonitor};
+use crate::auth_failures::AuthFailures;
+use crate::close_reason::{CloseReason, CLOSE_GRACE};
+use crate::crash_report::CrashReports;
+use crate::bridge::{HandshakeTimeouts, StdioBridge};
+use crate::pair_server::{PairEvent, PairServer, PairSummary};
+use crate::cloudflare::{write_credentials_file, write_cloudflared_ingress_at, cloudflared_config_path, IngressRule};
 use crate::cloudflared_runner::CloudflaredRunner;
-use crate::common_config::{CommonConfig, SlashCommandConfig, TransportConfig};
-use crate::pairing::PairingManager;
-use crate::push::PushRelayClient;
-use crate::tailscale::{get_tailscale_hostname, tailscale_serve_start, TailscaleServeGuard};
-use crate::tls::TlsConfig;
+use crate::common_config::{CommonConfig, PairingApprovalConfig, SlashCommandConfig, TransportConfig};
+use crate::pairing::{PairingApproverFn, PairingDevice, PairingManager};
+use crate::push::{PushRegistrations, PushRelayClient, QuietHours};
+use crate::push_providers::{DesktopSender, GotifySender, NtfySender, PushProvider};
+use crate::runtime_manifest::{RuntimeManifest, RUNTIME_FILENAME};
+use crate::tailscale::{fetch_tailscale_cert, get_tailscale_hostname, get_tailscale_ipv4, tailscale_serve_start, TailscaleServeGuard};
+use crate::tls::{CertImport, TlsConfig};
+use crate::transcript::TranscriptSink;
 use crate::tui::events::{AppEvent, BridgeEvent};
-use crate::agent_pool::{AgentPool, PoolConfig, start_reaper};
+use crate::admin::{TokenRotatorFn, TransportSummary};
+use crate::agent_allowlist::AgentAllowlist;
+use crate::agent_pool::{AgentPool, HealthCheck, PoolConfig, UserNamespaces, start_health_checker, start_reaper, start_warm_pool};
 
 /// Build a `PairingManager` and optionally a `TlsConfig` for a single transport.
 ///
 /// Returns `(hostname, pairing_manager, tls_config, tailscale_guard, cf_runner)`.
-pub fn build_transport(
+#[allow(clippy::type_complexity)]
+pub async fn build_transport(
     transport_name: &str,
     transport_cfg: &TransportConfig,
     common: &CommonConfig,
-    config_dir: &std::path::PathBuf,
+    config_dir: &std::path::Path,
     advertise_addr: Option<&str>,
     cwd: &str,
 ) -> Result<(String, PairingManager, Option<TlsConfig>, Option<TailscaleServeGuard>, Option<CloudflaredRunner>)> {
@@ -56,7 +70,8 @@ pub fn build_transport(
                 ) {
                     let credentials_path = write_credentials_file(account_id, &tunnel_id, secret)
                         .context("Failed to write cloudflared credentials file")?;
-                    write_cloudflared_config_at(&tunnel_id, &credentials_path, hostname_bare, port, &per_project_config)
+                    let ingress = sync_tunnel_ingress(transport_cfg, hostname_bare, port).await;
+                    write_cloudflared_ingress_at(&tunnel_id, &credentials_path, &ingress, &per_project_config)
                         .context("Failed to write per-project cloudflared config")?;
                     per_project_config
                 } else {
@@ -94,16 +109,63 @@ pub fn build_transport(
             Ok((hostname, pm, None, Some(guard), None))
         }
 
-        _ => {
-            let extra_sans: Vec<String> = advertise_addr
-                .map(|a| vec![a.to_string()])
-                .unwrap_or_default();
+        "tailscale-ip" => {
+            let ts_ip = get_tailscale_ipv4()?;
+            let ts_hostname = get_tailscale_hostname().ok().flatten();
+
+            // Prefer the tailnet-issued certificate: devices already trust its CA chain,
+            // so no fingerprint needs to be pinned in the pairing URL.
+            if use_tls && transport_cfg.tailscale_cert.unwrap_or(true) && cert_import(transport_cfg)?.is_none() {
+                if let Some(ref ts_host) = ts_hostname {
+                    match fetch_tailscale_cert(ts_host, config_dir).and_then(|(cert, key)| {
+                        Ok(TlsConfig::load_imported(&CertImport::Pem { cert: &cert, key: &key }, ts_host, &common.tls_policy)?)
+                    }) {
+                        Ok(tls) => {
+                            let hostname = format!("wss://{}:{}", ts_host, port);
+                            let pm = PairingManager::new_with_cf(
+                                common.agent_id.clone(),
+                                hostname.clone(),
+                                common.auth_token.clone(),
+                                None,
+                                None,
+                                None,
+                                cwd.to_string(),
+                            ).with_tailscale_path();
+                            return Ok((hostname, pm, Some(tls), None, None));
+                        }
+                        Err(e) => warn!("Tailscale certificate unavailable, falling back to self-signed: {:#}", e),
+                    }
+                }
+            }
+
+            let mut extra_sans = vec![ts_ip.clone()];
+            extra_sans.extend(ts_hostname);
             let tls_config = if use_tls {
-                Some(TlsConfig::load_or_generate(config_dir, &extra_sans)?)
+                match cert_import(transport_cfg)? {
+                    Some(import) => Some(TlsConfig::load_imported(&import, &ts_ip, &common.tls_policy)?),
+                    None => Some(TlsConfig::load_or_generate_for_transport(config_dir, transport_name, &extra_sans, &common.tls_policy)?),
+                }
             } else {
                 None
             };
-            let cert_fingerprint = tls_config.as_ref().map(|t| t.fingerprint.clone());
+            let protocol = if tls_config.is_some() { "wss" } else { "ws" };
+            let hostname = format!("{}://{}:{}", protocol, ts_ip, port);
+            let pm = PairingManager::new_with_cf(
+                common.agent_id.clone(),
+                hostname.clone(),
+                common.auth_token.clone(),
+                tls_config.as_ref().map(|t| t.fingerprint.clone()),
+                None,
+                None,
+                cwd.to_string(),
+            ).with_tailscale_path();
+            Ok((hostname, pm, tls_config, None, None))
+        }
+
+        _ => {
+            let extra_sans: Vec<String> = advertise_addr
+                .map(|a| vec![a.to_string()])
+                .unwrap_or_default();
             let ip = match advertise_addr {
                 Some(addr) => addr.to_string(),
                 None => match local_ip_address::local_ip() {
@@ -111,6 +173,15 @@ pub fn build_transport(
                     Err(_) => "127.0.0.1".to_string(),
                 },
             };
+            let tls_config = if use_tls {
+                match cert_import(transport_cfg)? {
+                    Some(import) => Some(TlsConfig::load_imported(&import, &ip, &common.tls_policy)?),
+                    None => Some(TlsConfig::load_or_generate_for_transport(config_dir, transport_name, &extra_sans, &common.tls_policy)?),
+                }
+            } else {
+                None
+            };
+            let cert_fingerprint = tls_config.as_ref().map(|t| t.fingerprint.clone());
             let protocol = if tls_config.is_some() { "wss" } else { "ws" };
             let hostname = format!("{}://{}:{}", protocol, ip, port);
             let pm = PairingManager::new_with_cf(
@@ -127,32 +198,247 @@ pub fn build_transport(
     }
 }
 
+/// Resolve the WebSocket URL and certificate fingerprint a transport serves,
+/// without starting tunnels or `tailscale serve`.
+///
+/// Used by `bridge show-qr` to build a static connection payload. Generates
+/// the self-signed certificate (or local CA and leaf) on first use, like
+/// [`build_transport`].
+pub fn resolve_endpoint(
+    transport_name: &str,
+    transport_cfg: &TransportConfig,
+    common: &CommonConfig,
+    config_dir: &std::path::Path,
+) -> Result<(String, Option<String>)> {
+    let default_port: u16 = if transport_name == "tailscale-serve" { 8766 } else { 8765 };
+    let port = transport_cfg.port.unwrap_or(default_port);
+    let use_tls = transport_cfg.tls.unwrap_or(true);
+
+    let (host, extra_sans) = match transport_name {
+        "cloudflare" => return Ok((transport_cfg.hostname.clone().unwrap_or_default(), None)),
+        "tailscale-serve" => {
+            let ts_hostname = get_tailscale_hostname()?
+                .ok_or_else(|| anyhow::anyhow!(
+                    "tailscale-serve requires MagicDNS + HTTPS enabled on your tailnet"
+                ))?;
+            return Ok((format!("wss://{}", ts_hostname), None));
+        }
+        "tailscale-ip" => {
+            let ts_ip = get_tailscale_ipv4()?;
+            let ts_hostname = get_tailscale_hostname().ok().flatten();
+            if use_tls && transport_cfg.tailscale_cert.unwrap_or(true) && cert_import(transport_cfg)?.is_none() {
+                if let Some(ts_host) = ts_hostname {
+                    return Ok((format!("wss://{}:{}", ts_host, port), None));
+                }
+            }
+            let mut extra_sans = vec![ts_ip.clone()];
+            extra_sans.extend(ts_hostname);
+            (ts_ip, extra_sans)
+        }
+        _ => {
+            let ip = match common.advertise_addr.as_deref() {
+                Some(addr) => addr.to_string(),
+                None => match local_ip_address::local_ip() {
+                    Ok(addr) => addr.to_string(),
+                    Err(_) => "127.0.0.1".to_string(),
+                },
+            };
+            (ip, common.advertise_addr.iter().cloned().collect())
+        }
+    };
+
+    if !use_tls {
+        return Ok((format!("ws://{}:{}", host, port), None));
+    }
+    let tls_config = match cert_import(transport_cfg)? {
+        Some(import) => TlsConfig::load_imported(&import, &host, &common.tls_policy)?,
+        None => TlsConfig::load_or_generate_for_transport(config_dir, transport_name, &extra_sans, &common.tls_policy)?,
+    };
+    Ok((format!("wss://{}:{}", host, port), Some(tls_config.fingerprint)))
+}
+
+/// Resolve the user-provided certificate settings of a transport, if any.
+pub(crate) fn cert_import(transport_cfg: &TransportConfig) -> Result<Option<CertImport<'_>>> {
+    match (&transport_cfg.cert_file, &transport_cfg.key_file, &transport_cfg.pkcs12_file) {
+        (None, None, None) => Ok(None),
+        (Some(cert), Some(key), None) => Ok(Some(CertImport::Pem { cert, key })),
+        (None, None, Some(path)) => Ok(Some(CertImport::Pkcs12 {
+            path,
+            password: transport_cfg.pkcs12_password.as_deref().unwrap_or(""),
+        })),
+        (_, _, Some(_)) => anyhow::bail!("Set either pkcs12_file or cert_file/key_file, not both"),
+        _ => anyhow::bail!("cert_file and key_file must be set together"),
+    }
+}
+
+/// Point the tunnel's remote ingress for `hostname` at `port`, keeping the
+/// rules of other bridges behind the same tunnel, and return the whole
+/// ingress for the local cloudflared config. Without an `api_token`, or when
+/// the API call fails, only this bridge's rule is returned.
+async fn sync_tunnel_ingress(transport_cfg: &TransportConfig, hostname: &str, port: u16) -> Vec<IngressRule> {
+    let own = vec![IngressRule::bridge(hostname, port), IngressRule::catch_all()];
+    let Some((client, tunnel_id)) = tunnel_health::client_for(transport_cfg) else {
+        return own;
+    };
+    match client.configure_tunnel_ingress(&tunnel_id, hostname, port).await {
+        Ok(ingress) => ingress,
+        Err(e) => {
+            warn!("Failed to update the tunnel's ingress, serving only {}: {:#}", hostname, e);
+            own
+        }
+    }
+}
+
+/// Approve pairings by `[pairing_approval]` policy, otherwise by asking in the
+/// TUI; unanswered requests are declined after `timeout_secs`.
+fn pairing_approver(approval: PairingApprovalConfig, event_tx: mpsc::Sender<AppEvent>) -> PairingApproverFn {
+    std::sync::Arc::new(move |device: PairingDevice| {
+        let approval = approval.clone();
+        let event_tx = event_tx.clone();
+        Box::pin(async move {
+            if approval.auto_approves(&device.ip) {
+                info!("✅ {} approved by pairing policy", device);
+                return true;
+            }
+            warn!("🔐 {} wants to pair — approve in the bridge window", device);
+            let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+            if event_tx.send(AppEvent::PairingApproval { device: device.clone(), reply: reply_tx }).await.is_err() {
+                return false;
+            }
+            match tokio::time::timeout(std::time::Duration::from_secs(approval.timeout_secs), reply_rx).await {
+                Ok(Ok(approved)) => approved,
+                _ => {
+                    warn!("⏱️  No answer for {} within {}s, pairing declined", device, approval.timeout_secs);
+                    false
+                }
+            }
+        })
+    })
+}
+
+/// Take the exclusive `bridge.lock` of the config dir, held until the
+/// returned file is dropped.
+fn lock_config_dir() -> Result<std::fs::File> {
+    use fs2::FileExt;
+    let lock_path = CommonConfig::config_dir().join("bridge.lock");
+    let lock_file = std::fs::OpenOptions::new()
+        .create(true).write(true).truncate(false)
+        .open(&lock_path)
+        .with_context(|| format!("Failed to open bridge lock file: {}", lock_path.display()))?;
+    lock_file.try_lock_exclusive().map_err(|_| anyhow::anyhow!(
+        "Another bridge instance is already running from this folder."
+    ))?;
+    Ok(lock_file)
+}
+
+/// Check every section of `config` that [`run_bridge`] reads. Also run by
+/// `bridge start --check`.
+pub fn validate_config(config: &CommonConfig) -> Result<()> {
+    for allowed in &config.allowed_agents {
+        allowed.validate()?;
+    }
+    config.limits.validate()?;
+    if let Some(ref transcripts) = config.transcripts {
+        transcripts.validate()?;
+    }
+    config.buffer.validate()?;
+    config.end_session.validate()?;
+    config.output_shaping.validate()?;
+    if let Some(ref push_relay) = config.push_relay {
+        push_relay.validate()?;
+    }
+    crate::scheduler::jobs(config)?;
+    for pool_override in &config.pool_overrides {
+        pool_override.validate()?;
+    }
+    for sandbox in &config.sandbox {
+        sandbox.validate()?;
+    }
+    crate::sandbox::check_supported(&config.sandbox)?;
+    for hooks in &config.agent_hooks {
+        hooks.validate()?;
+    }
+    for intercept in &config.intercepts {
+        intercept.validate()?;
+    }
+    if let Some(ref health_check) = config.health_check {
+        health_check.validate()?;
+    }
+    if let Some(ref watchdog) = config.memory_watchdog {
+        watchdog.validate()?;
+    }
+    config.deep_link.validate()?;
+    config.clock_check.validate()?;
+    if let Some(ref approval) = config.pairing_approval {
+        approval.validate()?;
+    }
+    config.client_auth.validate()?;
+    if let Some(ref session_tokens) = config.session_tokens {
+        session_tokens.validate()?;
+    }
+    for (i, device) in config.devices.iter().enumerate() {
+        device.validate()?;
+        if device.token == config.auth_token || config.devices[..i].iter().any(|d| d.token == device.token) {
+            anyhow::bail!("[[devices]] {:?}: token must differ from the auth token and other devices", device.name);
+        }
+    }
+    config.validate_users()?;
+    config.validate_admin_token()?;
+    for (i, workspace) in config.workspaces.iter().enumerate() {
+        workspace.validate()?;
+        if config.workspaces[..i].iter().any(|w| w.name == workspace.name) {
+            anyhow::bail!("[[workspaces]] {:?}: names must be unique", workspace.name);
+        }
+    }
+    if let Some(ref worktrees) = config.worktrees {
+        worktrees.validate()?;
+    }
+    if let Some(ref auto_lock) = config.auto_lock {
+        auto_lock.validate()?;
+    }
+    if let Some(ref usage) = config.usage {
+        usage.validate()?;
+    }
+    if let Some(ref power) = config.power {
+        power.validate()?;
+    }
+    if let Some(ref auth_failures) = config.auth_failures {
+        auth_failures.validate()?;
+    }
+    if let Some(ref geo_filter) = config.geo_filter {
+        geo_filter.validate()?;
+    }
+    if let Some(ref geoip) = config.geoip {
+        geoip.validate()?;
+    }
+    if let Some(ref ble_pairing) = config.ble_pairing {
+        ble_pairing.validate()?;
+    }
+    Ok(())
+}
+
 /// Start the bridge on the given `transport_name`.
 ///
 /// This function runs until the bridge exits or `shutdown_rx` fires.
 /// Progress / status events are sent via `event_tx`.
 pub async fn run_bridge(
-    config: CommonConfig,
+    mut config: CommonConfig,
     transport_name: String,
     event_tx: mpsc::Sender<AppEvent>,
     mut shutdown_rx: tokio::sync::oneshot::Receiver<()>,
 ) -> Result<()> {
     let agent_command = config.agent_command.clone()
         .ok_or_else(|| anyhow::anyhow!("No agent_command in config"))?;
+    validate_config(&config)?;
+    let allowlist = AgentAllowlist::from(&config.allowed_agents[..]);
+    allowlist.check(&agent_command)?;
+    if !allowlist.is_empty() {
+        info!("🔐 Agent commands restricted to {} allowed program(s)", config.allowed_agents.len());
+    }
+    let schedules = crate::scheduler::jobs(&config)?;
 
     // Acquire exclusive lock on the config dir.
-    let _bridge_lock = {
-        use fs2::FileExt;
-        let lock_path = CommonConfig::config_dir().join("bridge.lock");
-        let lock_file = std::fs::OpenOptions::new()
-            .create(true).write(true)
-            .open(&lock_path)
-            .with_context(|| format!("Failed to open bridge lock file: {}", lock_path.display()))?;
-        lock_file.try_lock_exclusive().map_err(|_| anyhow::anyhow!(
-            "Another bridge instance is already running from this folder."
-        ))?;
-        lock_file
-    };
+    let _bridge_lock = lock_config_dir()?;
 
     let transport_cfg = config.transports.get(&transport_name)
         .cloned()
@@ -164,6 +450,16 @@ pub async fn run_bridge(
         .to_string_lossy()
         .to_string();
 
+    // Replace expired tokens before anything hands them out.
+    let activity = config.auto_lock.as_ref().map(|_| std::sync::Arc::new(Activity::open(&config_dir)));
+    if let (Some(auto_lock), Some(activity)) = (config.auto_lock.clone(), activity.as_deref()) {
+        if activity.record().state(&auto_lock, chrono::Utc::now()) == LockState::Expired {
+            warn!("🔒 No connection for {} days: replacing every device token, pair again", auto_lock.after_days);
+            auto_lock::lock(&mut config, &config_dir, activity)?;
+            let _ = event_tx.send(AppEvent::Bridge(BridgeEvent::AutoLocked { restart: false })).await;
+        }
+    }
+
     let bind_address = if transport_name == "tailscale-serve" {
         "127.0.0.1".to_string()
     } else {
@@ -173,14 +469,15 @@ pub async fn run_bridge(
     let default_port: u16 = if transport_name == "tailscale-serve" { 8766 } else { 8765 };
     let port = transport_cfg.port.unwrap_or(default_port);
 
-    let (hostname, pm, tls_config, _ts_guard, _cf_runner) = build_transport(
+    let (hostname, pm, tls_config, _ts_guard, cf_runner) = build_transport(
         &transport_name,
         &transport_cfg,
         &config,
         &config_dir,
         config.advertise_addr.as_deref(),
         &cwd,
-    )?;
+    )
+    .await?;
 
     // Attach push relay URL to pairing responses.
     let pm = if let Some(ref push_cfg) = config.push_relay {
@@ -189,11 +486,26 @@ pub async fn run_bridge(
         } else { pm }
     } else { pm };
 
+    let device_keys = std::sync::Arc::new(DeviceKeys::open(&config_dir));
+    let pm = pm.with_device_keys(device_keys.clone());
+    let pm = if config.insecure_dev { pm.with_insecure_dev() } else { pm };
+    let pm = if config.short_pairing_url { pm.with_short_url() } else { pm };
+
+    let pm = match config.pairing_approval.clone() {
+        Some(approval) => {
+            info!("🔐 Pairing requires approval on the bridge");
+            pm.with_approver(pairing_approver(approval, event_tx.clone()))
+        }
+        None => pm,
+    };
+
     // Send pairing URL to TUI so /qr can render it.
     let base_url = hostname.replace("wss://", "https://").replace("ws://", "http://");
     let pairing_url = pm.get_pairing_url(&base_url);
+    let deep_link = pm.get_deep_link(&base_url, &config.deep_link.scheme);
     let _ = event_tx.send(AppEvent::Bridge(BridgeEvent::PairingUrlReady {
         url: pairing_url,
+        deep_link,
         transport: transport_name.clone(),
     })).await;
 
@@ -211,46 +523,181 @@ pub async fn run_bridge(
     info!("Bridge started on {} transport: {}", transport_name, hostname);
     info!("Agent command: {}", agent_command);
 
+    // runtime.json for external tooling; removed again when this run ends.
+    let manifest = RuntimeManifest::new(
+        &transport_name,
+        &bind_address,
+        port,
+        &hostname,
+        tls_config.as_ref().map(|t| t.fingerprint.clone()),
+    );
+    let _manifest_guard = manifest
+        .write(&config_dir)
+        .map_err(|e| warn!("Failed to write {}: {:#}", RUNTIME_FILENAME, e))
+        .ok();
+
     // Build push relay client.
-    let push_relay_arc: Option<std::sync::Arc<PushRelayClient>> = if let Some(push_cfg) = &config.push_relay {
-        if !push_cfg.url.is_empty() && !push_cfg.token_url.is_empty() && !push_cfg.client_id.is_empty() {
-            let client = PushRelayClient::new(push_cfg.url.clone(), String::new())
-                .with_jwt_credentials(
-                    push_cfg.token_url.clone(),
-                    push_cfg.client_id.clone(),
-                    push_cfg.client_secret.clone(),
-                );
-            info!("Push relay: JWT auth (client_id={}, relay={})", push_cfg.client_id, push_cfg.url);
-            Some(std::sync::Arc::new(client))
-        } else {
-            warn!("Push relay config incomplete — push notifications disabled");
-            None
-        }
-    } else {
-        None
-    };
+    let push_relay_arc = push_relay_client(&config, &config_dir)?.map(std::sync::Arc::new);
+
+    if config.clock_check.enabled {
+        tokio::spawn(crate::clock_skew::warn_if_skewed(config.clock_check.clone()));
+    }
 
     let uses_external_tls = matches!(transport_name.as_str(), "tailscale-serve" | "cloudflare");
 
+    // Kept until the bridge stops, repeating the dev-mode warning.
+    let insecure_reminder = config.insecure_dev.then(|| {
+        warn!("⚠️  Insecure dev mode: serving {} without TLS and without auth", hostname);
+        tokio::spawn(crate::insecure_dev::remind(bind_address.clone()))
+    });
+
     let mut bridge = StdioBridge::new(agent_command.clone(), port)
         .with_bind_addr(bind_address)
-        .with_auth_token(Some(config.auth_token.clone()))
+        .with_auth_token((!config.insecure_dev).then(|| config.auth_token.clone()))
+        .with_limits(&config.limits)
+        .with_output_shaping(config.output_shaping)
         .with_pairing(pm);
 
+    #[cfg(all(feature = "ble-pairing", target_os = "linux"))]
+    let _ble_pairing = match (&config.ble_pairing, bridge.pairing_manager()) {
+        (Some(ble), Some(pm)) => match crate::ble_pairing::start(ble, pm.clone()).await {
+            Ok(peripheral) => Some(peripheral),
+            Err(e) => {
+                warn!("Bluetooth LE pairing unavailable: {:#}", e);
+                None
+            }
+        },
+        _ => None,
+    };
+    #[cfg(not(all(feature = "ble-pairing", target_os = "linux")))]
+    if config.ble_pairing.is_some() {
+        warn!("[ble_pairing] is configured but this build lacks the `ble-pairing` feature (Linux only) — ignoring");
+    }
+
+    bridge = bridge.with_client_auth(config.client_auth);
+    if let Some(ref session_tokens) = config.session_tokens {
+        bridge = bridge.with_session_tokens(std::time::Duration::from_secs(session_tokens.ttl_secs));
+    }
+    if !config.devices.is_empty() {
+        bridge = bridge.with_devices(config.devices.clone());
+    }
+    if !config.users.is_empty() {
+        bridge = bridge.with_users(config.users.clone());
+    }
+    if let Some(ref watchdog) = config.memory_watchdog {
+        bridge = bridge.with_memory_watchdog(watchdog.clone());
+    }
+    if let Some(ref auth_failures) = config.auth_failures {
+        bridge = bridge.with_auth_failures(auth_failures.clone());
+    }
+    if let Some(ref geo_filter) = config.geo_filter {
+        // Only cloudflared sets CF-IPCountry; elsewhere clients could forge it.
+        if transport_name == "cloudflare" {
+            info!("🌍 Filtering Cloudflare requests by country");
+            bridge = bridge.with_geo_filter(geo_filter);
+        } else {
+            warn!("[geo_filter] only applies to the cloudflare transport — ignoring on {}", transport_name);
+        }
+    }
+    if let Some(geoip) = open_geoip(&config)? {
+        bridge = bridge.with_geoip(geoip);
+    }
+    let usage = config.usage.clone().map(|usage| std::sync::Arc::new(crate::usage::Usage::open(&config_dir, usage)));
+    let mut usage_flusher = None;
+    if let Some(ref usage) = usage {
+        bridge = bridge.with_usage(usage.clone());
+        usage_flusher = Some(tokio::spawn(usage.clone().watch()));
+    }
+
+    // Kept until the bridge stops; the tunnel watcher restarts it in place.
+    let cf_runner = cf_runner.map(|runner| std::sync::Arc::new(std::sync::Mutex::new(runner)));
+    let mut tunnel_watcher = None;
+    if transport_name == "cloudflare" {
+        if let Some((client, tunnel_id)) = tunnel_health::client_for(&transport_cfg) {
+            info!("☁️  Checking tunnel {} through the Cloudflare API every {}s", tunnel_id, tunnel_health::CHECK_INTERVAL.as_secs());
+            let monitor = std::sync::Arc::new(TunnelMonitor::default());
+            bridge = bridge.with_tunnel_health(monitor.clone());
+            tunnel_watcher = Some(tokio::spawn(tunnel_health::watch(client, tunnel_id, monitor, cf_runner.clone())));
+        }
+    }
+
     if let Some(tls) = tls_config {
         bridge = bridge.with_tls(tls);
     } else if uses_external_tls {
         bridge = bridge.with_external_tls();
     }
 
-    let mut pool_builder = AgentPool::new(PoolConfig::default())
-        .with_working_dir(cwd.clone().into());
+    let pool_config = PoolConfig {
+        max_buffer_size: config.buffer.max_messages,
+        max_buffer_bytes: config.buffer.max_bytes,
+        buffer_ttl: (config.buffer.ttl_secs > 0).then(|| std::time::Duration::from_secs(config.buffer.ttl_secs)),
+        warm_agents: config.warm_agents,
+        eviction: config.eviction.clone(),
+        overrides: config.pool_overrides.clone(),
+        users: UserNamespaces::new(&config.users),
+        max_line_bytes: config.limits.max_line_bytes,
+        agent_version: config.agent_version.clone(),
+        ..PoolConfig::default()
+    };
+    let mut pool_builder = AgentPool::new(pool_config)
+        .with_working_dir(cwd.clone().into())
+        .with_workspaces(config.workspaces.clone())
+        .with_agent_allowlist(allowlist)
+        .with_sandboxes(config.sandbox.clone())
+        .with_agent_hooks(config.agent_hooks.clone())
+        .with_intercepts(config.intercepts.clone())
+        .with_end_session(config.end_session.clone())
+        .with_crash_reports(CrashReports::new(&config_dir));
     if let Some(ref relay) = push_relay_arc {
         pool_builder = pool_builder.with_push_relay(std::sync::Arc::clone(relay));
     }
+    let (spawn_failed_tx, mut spawn_failed_rx) = mpsc::channel::<String>(1);
+    if config.exit_on_spawn_failure {
+        pool_builder = pool_builder.with_spawn_failures(spawn_failed_tx);
+    }
+    if let Some(ref worktrees) = config.worktrees {
+        pool_builder = pool_builder.with_worktrees(worktrees.clone());
+    }
+    if let Some(ref activity) = activity {
+        pool_builder = pool_builder.with_activity(std::sync::Arc::clone(activity));
+    }
+    if let Some(ref transcripts) = config.transcripts {
+        pool_builder = pool_builder.with_transcript_sink(TranscriptSink::start(transcripts.clone(), config.agent_id.clone(), &config.users));
+    }
     let pool = std::sync::Arc::new(tokio::sync::RwLock::new(pool_builder));
     let _reaper = start_reaper(pool.clone(), std::time::Duration::from_secs(60));
+    let _warm_pool = (config.warm_agents > 0).then(|| {
+        info!("🔥 Keeping {} warm agent(s) ready", config.warm_agents);
+        start_warm_pool(pool.clone(), agent_command.clone())
+    });
+    let _health_checker = config.health_check.as_ref().map(|check| {
+        info!("🩺 Probing idle agents with `{}` every {}s", check.method, check.interval_secs);
+        start_health_checker(pool.clone(), HealthCheck::from(check))
+    });
+    // Stopped before the manifest is removed, so it can't write it back.
+    let power_watcher = config
+        .power
+        .clone()
+        .map(|power| crate::power::start(power, pool.clone(), Some((manifest.clone(), config_dir.clone()))));
+    let service_token_watcher = service_token_rotation::enabled(&config).then(|| {
+        let pairing = (transport_name == service_token_rotation::TRANSPORT)
+            .then(|| bridge.pairing_manager().cloned())
+            .flatten();
+        tokio::spawn(service_token_rotation::watch(
+            config_dir.clone(),
+            pairing,
+            pool.clone(),
+            device_keys.clone(),
+            push_relay_arc.clone(),
+        ))
+    });
+    let scheduler = (!schedules.is_empty()).then(|| {
+        info!("⏰ Running {} scheduled prompt(s)", schedules.len());
+        crate::scheduler::start(pool.clone(), schedules, agent_command.clone(), push_relay_arc.clone())
+    });
+    let credentials_pool = pool.clone();
     bridge = bridge.with_agent_pool(pool);
+    let inactivity = auto_lock::watch(config.auto_lock.clone(), activity.clone(), push_relay_arc.clone());
 
     if let Some(relay) = push_relay_arc {
         bridge = bridge.with_push_relay(relay);
@@ -269,6 +716,10 @@ pub async fn run_bridge(
     };
     bridge = bridge.with_slash_commands(slash_commands);
 
+    for listener in &config.listeners {
+        bridge = bridge.with_line_listener(listener.clone());
+    }
+
     // MEMORY.md
     let memory_path = config_dir.join("MEMORY.md");
     if !memory_path.exists() {
@@ -276,14 +727,101 @@ pub async fn run_bridge(
     }
     bridge = bridge.with_memory_path(memory_path);
 
+    // Admin endpoints: what they list, and replacing the auth token, which
+    // restarts the bridge below once the new token is saved.
+    let mut transports: Vec<TransportSummary> = config
+        .transports
+        .iter()
+        .filter(|(_, t)| t.enabled)
+        .map(|(name, _)| TransportSummary {
+            name: name.clone(),
+            serving: *name == transport_name,
+            url: (*name == transport_name).then(|| manifest.url.clone()),
+        })
+        .collect();
+    transports.sort_by(|a, b| a.name.cmp(&b.name));
+    let (rotated_tx, mut rotated_rx) = mpsc::channel::<String>(1);
+    let rotate_dir = config_dir.clone();
+    let rotator: TokenRotatorFn = std::sync::Arc::new(move || {
+        let mut config = CommonConfig::load_from_dir(&rotate_dir)?;
+        config.auth_token = CommonConfig::generate_auth_token();
+        config.save_to_dir(&rotate_dir).context("Failed to save the new auth token")?;
+        let _ = rotated_tx.try_send(config.auth_token.clone());
+        Ok(config.auth_token)
+    });
+    if !config.admin_token.is_empty() {
+        bridge = bridge.with_admin_token(config.admin_token.clone());
+    }
+    bridge = bridge
+        .with_transports(transports)
+        .with_log_file(config_dir.join(crate::log_file::LOG_FILENAME))
+        .with_token_rotator(rotator);
+    if config.admin_ui {
+        bridge = bridge.with_admin_ui();
+    }
+
     // Run the bridge, racing against the shutdown signal.
-    let result = tokio::select! {
-        r = bridge.start() => r,
+    let (result, close_reason) = tokio::select! {
+        r = bridge.start() => (r.map_err(Into::into), CloseReason::ShuttingDown),
         _ = &mut shutdown_rx => {
             info!("Bridge shutdown requested");
-            Ok(())
+            (Ok(()), CloseReason::ShuttingDown)
+        }
+        _ = inactivity => {
+            let after_days = config.auto_lock.as_ref().map_or(0, |a| a.after_days);
+            warn!("🔒 No connection for {} days: replacing every device token and restarting, pair again", after_days);
+            let locked = match activity.as_deref() {
+                Some(activity) => auto_lock::lock(&mut config, &config_dir, activity),
+                None => Ok(()),
+            };
+            if locked.is_ok() {
+                let _ = event_tx.send(AppEvent::Bridge(BridgeEvent::AutoLocked { restart: true })).await;
+            }
+            (locked, CloseReason::Locked)
+        }
+        Some(error) = spawn_failed_rx.recv() => {
+            (Err(anyhow::anyhow!("Agent failed to start: {}", error)), CloseReason::ShuttingDown)
+        }
+        Some(auth_token) = rotated_rx.recv() => {
+            // Devices with a credentials key roll over to the new token
+            // before the restart drops their connections.
+            let update = CredentialsUpdate { auth_token: Some(auth_token), ..CredentialsUpdate::default() };
+            let sent = device_keys.distribute(&*credentials_pool.read().await, &update);
+            if sent > 0 {
+                info!("🔑 Sent the new auth token to {} paired device(s)", sent);
+                tokio::time::sleep(device_keys::DELIVERY_GRACE).await;
+            }
+            let _ = event_tx.send(AppEvent::Bridge(BridgeEvent::AuthTokenRotated)).await;
+            (Ok(()), CloseReason::Restarting)
         }
     };
+    // Connections outlive the server; tell their clients why they end.
+    if bridge.close_connections(close_reason) {
+        tokio::time::sleep(CLOSE_GRACE).await;
+    }
+
+    if let Some(watcher) = power_watcher {
+        watcher.abort();
+    }
+    if let Some(watcher) = service_token_watcher {
+        watcher.abort();
+    }
+    if let Some(watcher) = tunnel_watcher {
+        watcher.abort();
+    }
+    if let Some(reminder) = insecure_reminder {
+        reminder.abort();
+    }
+    if let Some(scheduler) = scheduler {
+        scheduler.abort();
+    }
+    if let Some(flusher) = usage_flusher {
+        flusher.abort();
+    }
+    if let Some(ref usage) = usage {
+        usage.flush();
+    }
+    drop(_manifest_guard);
 
     // Release the lock BEFORE sending BridgeStopped so that when the TUI
     // starts a new bridge in response to that event, the lock is already free.
@@ -293,3 +831,191 @@ pub async fn run_bridge(
 
     result
 }
+
+/// The push client for `[push_relay]`: the relay, keeping its registrations
+/// in `config_dir`, or the configured ntfy or Gotify server. Without a
+/// complete section, desktop notifications on this machine when enabled and
+/// there is a desktop, else `None`.
+pub fn push_relay_client(config: &CommonConfig, config_dir: &std::path::Path) -> Result<Option<PushRelayClient>> {
+    let Some(push_cfg) = &config.push_relay else {
+        return Ok(desktop_notifier(config));
+    };
+    let client = match push_cfg.provider {
+        PushProvider::Relay => {
+            if push_cfg.url.is_empty() || push_cfg.token_url.is_empty() || push_cfg.client_id.is_empty() {
+                warn!("Push relay config incomplete — push notifications disabled");
+                return Ok(desktop_notifier(config));
+            }
+            info!("Push relay: JWT auth (client_id={}, relay={})", push_cfg.client_id, push_cfg.url);
+            PushRelayClient::new(push_cfg.url.clone(), String::new())
+                .with_jwt_credentials(
+                    push_cfg.token_url.clone(),
+                    push_cfg.client_id.clone(),
+                    push_cfg.client_secret.clone(),
+                )
+                .with_registrations(std::sync::Arc::new(PushRegistrations::open(config_dir)))
+        }
+        PushProvider::Ntfy => {
+            push_cfg.validate()?;
+            info!("Push notifications: ntfy topic {} on {}", push_cfg.topic, push_cfg.url);
+            let token = Some(push_cfg.token.clone());
+            PushRelayClient::for_sender(std::sync::Arc::new(NtfySender::new(&push_cfg.url, &push_cfg.topic, token)))
+        }
+        PushProvider::Gotify => {
+            push_cfg.validate()?;
+            info!("Push notifications: Gotify server {}", push_cfg.url);
+            PushRelayClient::for_sender(std::sync::Arc::new(GotifySender::new(&push_cfg.url, &push_cfg.token)))
+        }
+    };
+    let mut client = client.with_cooldown(std::time::Duration::from_secs(push_cfg.cooldown_secs));
+    for (category, secs) in &push_cfg.cooldowns {
+        client = client.with_category_cooldown(*category, std::time::Duration::from_secs(*secs));
+    }
+    if let Some(ref quiet) = push_cfg.quiet_hours {
+        client = client.with_quiet_hours(QuietHours::parse(&quiet.start, &quiet.end)?);
+    }
+    Ok(Some(client))
+}
+
+/// Desktop notifications in place of a push relay, unless
+/// `desktop_notifications = false` or nothing could show them.
+fn desktop_notifier(config: &CommonConfig) -> Option<PushRelayClient> {
+    if !config.desktop_notifications {
+        return None;
+    }
+    if !DesktopSender::available() {
+        debug!("No desktop session — desktop notifications disabled");
+        return None;
+    }
+    info!("🖥️  No push relay: permission requests and finished turns show as desktop notifications");
+    Some(PushRelayClient::for_sender(std::sync::Arc::new(DesktopSender)))
+}
+
+/// The `[geoip]` databases, when configured.
+fn open_geoip(config: &CommonConfig) -> Result<Option<std::sync::Arc<crate::geoip::GeoIp>>> {
+    let Some(ref geoip_config) = config.geoip else {
+        return Ok(None);
+    };
+    let geoip = crate::geoip::GeoIp::open(geoip_config)?;
+    info!("🌍 Naming the country and network of connecting addresses");
+    Ok(Some(std::sync::Arc::new(geoip)))
+}
+
+/// Serve only pairing on `transport_name` (`bridge pair --serve`), showing a
+/// QR code for every code issued, until `max_pairings` devices have paired or
+/// Ctrl-C. Devices need `[pairing_approval]` `auto_approve` when approval is
+/// configured, since nobody is asked. `open_image` opens the QR image in the
+/// platform image viewer. On a terminal, a countdown line under the code is
+/// redrawn every second; `watch` clears the screen for each new code so only
+/// the current one shows.
+pub async fn run_pair_server(
+    config: CommonConfig,
+    transport_name: &str,
+    max_pairings: Option<usize>,
+    open_image: bool,
+    watch: bool,
+) -> Result<PairSummary> {
+    config.limits.validate()?;
+    if let Some(ref approval) = config.pairing_approval {
+        approval.validate()?;
+    }
+    if let Some(ref auth_failures) = config.auth_failures {
+        auth_failures.validate()?;
+    }
+    let _bridge_lock = lock_config_dir()?;
+
+    let transport_cfg = config.transports.get(transport_name)
+        .ok_or_else(|| anyhow::anyhow!("Transport '{}' not found in config", transport_name))?;
+    let config_dir = CommonConfig::config_dir();
+    let cwd = std::env::current_dir()
+        .unwrap_or_else(|_| std::path::PathBuf::from("."))
+        .to_string_lossy()
+        .to_string();
+    let bind_address = if transport_name == "tailscale-serve" {
+        "127.0.0.1".to_string()
+    } else {
+        config.bind_address.clone().unwrap_or_else(|| "0.0.0.0".to_string())
+    };
+    let default_port: u16 = if transport_name == "tailscale-serve" { 8766 } else { 8765 };
+    let port = transport_cfg.port.unwrap_or(default_port);
+
+    let (hostname, pm, tls_config, _ts_guard, _cf_runner) = build_transport(
+        transport_name,
+        transport_cfg,
+        &config,
+        &config_dir,
+        config.advertise_addr.as_deref(),
+        &cwd,
+    )
+    .await?;
+    let pm = match config.push_relay {
+        Some(ref push_cfg) if !push_cfg.url.is_empty() && !push_cfg.client_id.is_empty() => pm.with_relay_url(push_cfg.url.clone()),
+        _ => pm,
+    };
+    let pm = if config.short_pairing_url { pm.with_short_url() } else { pm };
+    let pm = match config.pairing_approval.clone() {
+        Some(approval) => pm.with_approver(std::sync::Arc::new(move |device: PairingDevice| {
+            let approved = approval.auto_approves(&device.ip);
+            if !approved {
+                warn!("🚫 {} is not in [pairing_approval] auto_approve — declined", device);
+            }
+            Box::pin(async move { approved })
+        })),
+        None => pm,
+    };
+
+    if let Some(ref geoip) = config.geoip {
+        geoip.validate()?;
+    }
+    let geoip = open_geoip(&config)?;
+    let listener = tokio::net::TcpListener::bind((bind_address.as_str(), port))
+        .await
+        .with_context(|| format!("Failed to listen on {}:{}", bind_address, port))?;
+    let server = PairServer::new(listener, tls_config)
+        .with_timeouts(HandshakeTimeouts {
+            tls: std::time::Duration::from_secs(config.limits.handshake_timeout_secs),
+            request: std::time::Duration::from_secs(config.limits.request_timeout_secs),
+            upgrade: std::time::Duration::from_secs(config.limits.upgrade_timeout_secs),
+        })
+        .with_auth_failures(config.auth_failures.clone().map(|c| std::sync::Arc::new(AuthFailures::new(c).with_geoip(geoip.clone()))))
+        .with_max_pairings(max_pairings);
+    info!("🔗 Serving only pairing on {} transport: {}", transport_name, hostname);
+
+    let mut image = crate::qr::QrImage::new(&config_dir, open_image);
+    let live = std::io::IsTerminal::is_terminal(&std::io::stdout());
+    let report = |event: PairEvent<'_>| {
+        use std::io::Write;
+        // Finish the countdown line before printing anything else
+        let clear_countdown = || if live { print!("\r\x1b[2K") };
+        match event {
+            PairEvent::Code(pm, paired) => {
+                clear_countdown();
+                if watch && live {
+                    print!("\x1b[2J\x1b[H");
+                }
+                if let Err(e) = crate::qr::display_qr_code_with_pairing(&hostname, pm, &mut image) {
+                    warn!("Failed to show the QR code: {:#}", e);
+                }
+                match max_pairings {
+                    Some(max) => println!("  Paired {} of {} — a new code follows each pairing. Ctrl-C to stop.\n", paired, max),
+                    None => println!("  Paired {} — a new code follows each pairing. Ctrl-C to stop.\n", paired),
+                }
+            }
+            PairEvent::FailedAttempt(pm, ip) => {
+                clear_countdown();
+                println!("  ❌ Wrong code from {} at {} ({})", ip, chrono::Local::now().format("%H:%M:%S"), crate::pair_server::countdown_line(pm));
+            }
+            PairEvent::Tick(pm) if live => print!("\r\x1b[2K  {}", crate::pair_server::countdown_line(pm)),
+            PairEvent::Tick(_) => {}
+        }
+        let _ = std::io::stdout().flush();
+    };
+    let shutdown = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    let summary = server.run(pm, report, shutdown).await;
+    if live {
+        println!();
+    }
+    summary
+}
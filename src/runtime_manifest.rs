@@ -0,0 +1,163 @@
+//! `runtime.json`: what the running bridge is serving, for external tooling.
+//!
+//! `run_bridge` writes the manifest to the config directory once its
+//! transport is up, rewrites it whenever the bridge restarts (e.g. on another
+//! transport), and removes it on shutdown. Readers should also check
+//! [`is_live`], since a crashed bridge can't remove its manifest.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::power::PowerStatus;
+
+pub const RUNTIME_FILENAME: &str = "runtime.json";
+
+/// The live instance's transport and endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeManifest {
+    /// Bridge version
+    pub version: String,
+    pub pid: u32,
+    /// RFC 3339 start time of this transport
+    pub started_at: String,
+    /// Selected transport (`local`, `cloudflare`, `tailscale-serve`, ...)
+    pub transport: String,
+    /// Address and port the WebSocket listener is bound to
+    pub bind_address: String,
+    pub port: u16,
+    /// URL devices connect to, as in the pairing QR code
+    pub url: String,
+    /// Host part of `url`
+    pub public_hostname: String,
+    /// SHA256 fingerprint of the pinned certificate (`None` without self-managed TLS)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_fingerprint: Option<String>,
+    /// Power source and whether the pool is power saving (with `[power]`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power: Option<PowerStatus>,
+}
+
+impl RuntimeManifest {
+    /// Describe this process serving `transport` on `bind_address:port` at `url`.
+    pub fn new(transport: &str, bind_address: &str, port: u16, url: &str, tls_fingerprint: Option<String>) -> Self {
+        Self {
+            version: crate::VERSION.to_string(),
+            pid: std::process::id(),
+            started_at: chrono::Local::now().to_rfc3339(),
+            transport: transport.to_string(),
+            bind_address: bind_address.to_string(),
+            port,
+            url: url.to_string(),
+            public_hostname: public_hostname(url),
+            tls_fingerprint,
+            power: None,
+        }
+    }
+
+    /// Write to `dir`, replacing any previous manifest atomically. The file is
+    /// removed when the returned guard is dropped.
+    pub fn write(&self, dir: &Path) -> Result<ManifestGuard> {
+        self.save(dir)?;
+        Ok(ManifestGuard { path: dir.join(RUNTIME_FILENAME) })
+    }
+
+    /// Replace the manifest in `dir` atomically, e.g. to update it while the
+    /// bridge runs.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(RUNTIME_FILENAME);
+        let tmp = dir.join(format!("{}.tmp", RUNTIME_FILENAME));
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(&tmp, json).with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The manifest in `dir`, if a bridge has written one.
+    pub fn read(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(RUNTIME_FILENAME);
+        match std::fs::read_to_string(&path) {
+            Ok(json) => Ok(Some(serde_json::from_str(&json).with_context(|| format!("Invalid {}", path.display()))?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+}
+
+/// Removes `runtime.json` when the bridge stops.
+pub struct ManifestGuard {
+    path: PathBuf,
+}
+
+impl Drop for ManifestGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Whether a bridge is running from `dir`: it holds `bridge.lock` for as
+/// long as it runs, so a manifest without the lock is stale.
+pub fn is_live(dir: &Path) -> bool {
+    let Ok(lock) = std::fs::File::open(dir.join("bridge.lock")) else {
+        return false;
+    };
+    if lock.try_lock_shared().is_ok() {
+        let _ = lock.unlock();
+        return false;
+    }
+    true
+}
+
+fn public_hostname(url: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split('/').next().unwrap_or(rest);
+    match authority.strip_prefix('[') {
+        Some(v6) => v6.split(']').next().unwrap_or(v6).to_string(),
+        None => authority.rsplit_once(':').map_or(authority, |(host, _)| host).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hostname_is_taken_from_the_url() {
+        assert_eq!(public_hostname("wss://192.168.1.20:8765"), "192.168.1.20");
+        assert_eq!(public_hostname("https://agent.example.com"), "agent.example.com");
+        assert_eq!(public_hostname("wss://[fd7a:115c::1]:8765/"), "fd7a:115c::1");
+    }
+
+    #[test]
+    fn manifest_round_trips_and_is_removed_on_drop() {
+        let dir = std::env::temp_dir().join(format!("bridge-runtime-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(RuntimeManifest::read(&dir).unwrap(), None);
+
+        let manifest = RuntimeManifest::new("local", "0.0.0.0", 8765, "wss://10.0.0.5:8765", Some("AB:CD".into()));
+        let guard = manifest.write(&dir).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(dir.join(RUNTIME_FILENAME)).unwrap()).unwrap();
+        assert_eq!(json["publicHostname"], "10.0.0.5");
+        assert_eq!(json["tlsFingerprint"], "AB:CD");
+        assert_eq!(json["pid"], std::process::id());
+        assert_eq!(RuntimeManifest::read(&dir).unwrap(), Some(manifest));
+
+        drop(guard);
+        assert_eq!(RuntimeManifest::read(&dir).unwrap(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lock_holder_is_live() {
+        use fs2::FileExt;
+        let dir = std::env::temp_dir().join(format!("bridge-runtime-lock-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(!is_live(&dir));
+        let lock = std::fs::File::create(dir.join("bridge.lock")).unwrap();
+        assert!(!is_live(&dir));
+        lock.lock_exclusive().unwrap();
+        assert!(is_live(&dir));
+        lock.unlock().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,247 @@
+//! Sandboxed agent processes on Linux (`[[sandbox]]`).
+//!
+//! A sandboxed agent is started through bubblewrap (`bwrap`) in new user,
+//! PID, IPC and UTS namespaces (and a network namespace without `network`).
+//! The host's root is mounted read-only; only the agent's working directory,
+//! a private `/tmp` and the configured `writable` paths can be written. With
+//! `seccomp`, bubblewrap also installs a filter that fails syscalls agents
+//! have no use for (mounting, tracing, loading modules or BPF programs,
+//! entering namespaces, ...) with `EPERM`.
+//!
+//! The same command is built for the pooled and the legacy spawn paths. The
+//! runner refuses to start when sandboxes are configured but can't be applied,
+//! rather than running agents unconfined.
+
+use anyhow::{Context, Result};
+use std::path::Path;
+use tokio::process::Command;
+
+use crate::common_config::SandboxConfig;
+
+/// The sandbox for `agent_command`: the first entry whose `agent` occurs in
+/// it, or that has no `agent`.
+pub fn for_agent<'a>(sandboxes: &'a [SandboxConfig], agent_command: &str) -> Option<&'a SandboxConfig> {
+    sandboxes
+        .iter()
+        .find(|s| s.agent.as_deref().is_none_or(|agent| agent_command.contains(agent)))
+}
+
+/// Fail unless sandboxes can be applied here: Linux, with `bwrap` on `PATH`,
+/// and a seccomp filter for this architecture when one is asked for.
+pub fn check_supported(sandboxes: &[SandboxConfig]) -> Result<()> {
+    if sandboxes.is_empty() {
+        return Ok(());
+    }
+    if !cfg!(target_os = "linux") {
+        anyhow::bail!("[[sandbox]] is only supported on Linux");
+    }
+    which::which("bwrap").context("[[sandbox]] needs bubblewrap (`bwrap`) on PATH")?;
+    if sandboxes.iter().any(|s| s.seccomp) && seccomp::filter().is_none() {
+        anyhow::bail!("[[sandbox]] seccomp has no filter for this architecture; set seccomp = false");
+    }
+    Ok(())
+}
+
+/// Command that runs `program` with `args` in `working_dir`, inside
+/// `sandbox` when there is one. The caller sets up stdio.
+pub fn command(sandbox: Option<&SandboxConfig>, program: &str, args: &[&str], working_dir: &Path) -> Result<Command> {
+    let Some(sandbox) = sandbox else {
+        let mut command = Command::new(program);
+        command.args(args).current_dir(working_dir);
+        return Ok(command);
+    };
+    let mut command = Command::new("bwrap");
+    command.args(bwrap_args(sandbox, working_dir)).current_dir(working_dir);
+    if sandbox.seccomp {
+        let fd = seccomp_fd(&mut command)?;
+        command.arg("--seccomp").arg(fd.to_string());
+    }
+    command.arg("--").arg(program).args(args);
+    Ok(command)
+}
+
+/// bubblewrap options up to (not including) `--seccomp` and the command.
+fn bwrap_args(sandbox: &SandboxConfig, working_dir: &Path) -> Vec<String> {
+    let mut args: Vec<String> = [
+        "--die-with-parent",
+        "--unshare-user",
+        "--unshare-pid",
+        "--unshare-ipc",
+        "--unshare-uts",
+        "--unshare-cgroup-try",
+    ]
+    .map(String::from)
+    .to_vec();
+    if !sandbox.network {
+        args.push("--unshare-net".into());
+    }
+    let dir = working_dir.display().to_string();
+    args.extend(["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc", "--tmpfs", "/tmp"].map(String::from));
+    args.extend(["--bind".to_string(), dir.clone(), dir.clone()]);
+    for path in &sandbox.writable {
+        let path = path.display().to_string();
+        args.extend(["--bind-try".to_string(), path.clone(), path]);
+    }
+    args.extend(["--chdir".to_string(), dir]);
+    args
+}
+
+/// Hand the seccomp filter to the child on an inherited pipe and return the
+/// descriptor number it will have there.
+#[cfg(target_os = "linux")]
+fn seccomp_fd(command: &mut Command) -> Result<i32> {
+    use std::io::Write;
+    use std::os::fd::{AsRawFd, OwnedFd};
+
+    let program = seccomp::filter().context("No seccomp filter for this architecture")?;
+    let (reader, mut writer) = std::io::pipe().context("Failed to create a pipe for the seccomp filter")?;
+    // A few hundred bytes: fits the pipe buffer, so this doesn't block.
+    writer.write_all(&program).context("Failed to write the seccomp filter")?;
+    drop(writer);
+    let reader = OwnedFd::from(reader);
+    let fd = reader.as_raw_fd();
+    // SAFETY: only fcntl(2), which is async-signal-safe, runs between fork
+    // and exec. It clears close-on-exec on the child's copy of the pipe, so
+    // bubblewrap inherits it; the parent's copy closes with `command`.
+    unsafe {
+        command.pre_exec(move || {
+            if libc::fcntl(reader.as_raw_fd(), libc::F_SETFD, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(fd)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn seccomp_fd(_command: &mut Command) -> Result<i32> {
+    anyhow::bail!("[[sandbox]] is only supported on Linux")
+}
+
+/// A classic BPF seccomp program, as bubblewrap's `--seccomp` expects.
+mod seccomp {
+    const LD_W_ABS: u16 = 0x20;
+    const JEQ_K: u16 = 0x15;
+    const JGE_K: u16 = 0x35;
+    const RET_K: u16 = 0x06;
+
+    const RET_KILL_PROCESS: u32 = 0x8000_0000;
+    const RET_ALLOW: u32 = 0x7fff_0000;
+    const RET_ERRNO_EPERM: u32 = 0x0005_0000 | 1;
+
+    /// Offsets in `struct seccomp_data`.
+    const OFFSET_NR: u32 = 0;
+    const OFFSET_ARCH: u32 = 4;
+
+    /// `AUDIT_ARCH_*` and the numbers of the refused syscalls: `umount2`,
+    /// `mount`, `pivot_root`, `chroot`, `unshare`, `setns`, `kexec_load`,
+    /// `init_module`, `finit_module`, `delete_module`, `ptrace`,
+    /// `process_vm_readv`, `process_vm_writev`, `reboot`, `swapon`,
+    /// `swapoff`, `add_key`, `request_key`, `keyctl`, `perf_event_open`,
+    /// `bpf`, `userfaultfd`, `open_by_handle_at`.
+    #[cfg(target_arch = "x86_64")]
+    const ARCH: Option<(u32, &[u32])> = Some((
+        0xc000_003e,
+        &[166, 165, 155, 161, 272, 308, 246, 175, 313, 176, 101, 310, 311, 169, 167, 168, 248, 249, 250, 298, 321, 323, 304],
+    ));
+    #[cfg(target_arch = "aarch64")]
+    const ARCH: Option<(u32, &[u32])> = Some((
+        0xc000_00b7,
+        &[39, 40, 41, 51, 97, 268, 104, 105, 273, 106, 117, 270, 271, 142, 224, 225, 217, 218, 219, 241, 280, 282, 265],
+    ));
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    const ARCH: Option<(u32, &[u32])> = None;
+
+    /// First x32 syscall number on x86_64; the x32 ABI is refused outright.
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    fn insn(code: u16, jt: u8, jf: u8, k: u32) -> [u8; 8] {
+        let mut bytes = [0u8; 8];
+        bytes[..2].copy_from_slice(&code.to_ne_bytes());
+        bytes[2] = jt;
+        bytes[3] = jf;
+        bytes[4..].copy_from_slice(&k.to_ne_bytes());
+        bytes
+    }
+
+    /// The filter for this architecture as `struct sock_filter` bytes.
+    pub fn filter() -> Option<Vec<u8>> {
+        let (arch, denied) = ARCH?;
+        Some(build(arch, denied, cfg!(target_arch = "x86_64")))
+    }
+
+    pub(super) fn build(arch: u32, denied: &[u32], refuse_x32: bool) -> Vec<u8> {
+        let mut program = vec![
+            insn(LD_W_ABS, 0, 0, OFFSET_ARCH),
+            // Another architecture's syscall numbers mean something else.
+            insn(JEQ_K, 1, 0, arch),
+            insn(RET_K, 0, 0, RET_KILL_PROCESS),
+            insn(LD_W_ABS, 0, 0, OFFSET_NR),
+        ];
+        let checks = denied.len() + usize::from(refuse_x32);
+        if refuse_x32 {
+            program.push(insn(JGE_K, checks as u8, 0, X32_SYSCALL_BIT));
+        }
+        for (i, nr) in denied.iter().enumerate() {
+            // Jump over the remaining checks and the allow to the deny.
+            let remaining = denied.len() - i - 1;
+            program.push(insn(JEQ_K, (remaining + 1) as u8, 0, *nr));
+        }
+        program.push(insn(RET_K, 0, 0, RET_ALLOW));
+        program.push(insn(RET_K, 0, 0, RET_ERRNO_EPERM));
+        program.concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn sandbox(agent: Option<&str>, network: bool) -> SandboxConfig {
+        SandboxConfig {
+            agent: agent.map(String::from),
+            network,
+            seccomp: true,
+            writable: vec![PathBuf::from("/home/me/.claude")],
+        }
+    }
+
+    #[test]
+    fn first_matching_sandbox_applies() {
+        let sandboxes = [sandbox(Some("claude"), false), sandbox(None, true)];
+        assert!(!for_agent(&sandboxes, "claude-code-acp").unwrap().network);
+        assert!(for_agent(&sandboxes, "gemini --experimental-acp").unwrap().network);
+        assert_eq!(for_agent(&sandboxes[..1], "gemini"), None);
+    }
+
+    #[test]
+    fn root_is_read_only_except_the_project() {
+        let args = bwrap_args(&sandbox(None, false), Path::new("/src/api"));
+        let joined = args.join(" ");
+        assert!(joined.contains("--unshare-user"));
+        assert!(joined.contains("--unshare-net"));
+        assert!(joined.contains("--ro-bind / /"));
+        assert!(joined.contains("--bind /src/api /src/api"));
+        assert!(joined.contains("--bind-try /home/me/.claude /home/me/.claude"));
+        assert!(joined.ends_with("--chdir /src/api"));
+        assert!(!bwrap_args(&sandbox(None, true), Path::new("/src/api")).contains(&"--unshare-net".to_string()));
+    }
+
+    #[test]
+    fn seccomp_program_checks_arch_then_denies_listed_syscalls() {
+        let program = seccomp::build(0xc000_003e, &[101, 165], true);
+        let insns: Vec<&[u8]> = program.chunks(8).collect();
+        // arch load + check + kill, nr load, x32 check, 2 denies, allow, deny
+        assert_eq!(insns.len(), 9);
+        let k = |i: usize| u32::from_ne_bytes(insns[i][4..].try_into().unwrap());
+        assert_eq!(k(1), 0xc000_003e);
+        // Every jump lands on the final EPERM return.
+        for (i, insn) in insns.iter().enumerate().take(7).skip(4) {
+            assert_eq!(i + 1 + insn[2] as usize, 8, "instruction {i}");
+        }
+        assert_eq!(k(7), 0x7fff_0000);
+        assert_eq!(k(8), 0x0005_0001);
+    }
+}
@@ -0,0 +1,482 @@
+//! Scheduled prompts (`[[schedules]]` in `common.toml`).
+//!
+//! Each schedule has a cron expression, evaluated in the bridge host's local
+//! time. When it fires, the prompt is sent to the ACP session of the agent
+//! pooled for the schedule's token, like a prompt from the phone: the agent
+//! is spawned and initialized first if it isn't running, and a session is
+//! created if it has none yet, which the phone then resumes. When the agent
+//! answers, the start of its reply is pushed through the push relay.
+//!
+//! A connected client sees the run's `session/update` notifications as they
+//! stream. Permission requests go to the client like any others, so a run
+//! that needs one waits for the phone or times out.
+//!
+//! [`AgentRun`] does the prompting, and is shared with `POST /trigger`
+//! ([`crate::trigger`]).
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, Local, NaiveDate, TimeZone, Timelike};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, RwLock};
+use tracing::{info, warn};
+
+use crate::agent_pool::{AgentPool, AgentSlot};
+use crate::common_config::{CommonConfig, ScheduleConfig};
+use crate::push::PushRelayClient;
+
+/// Longest reply excerpt pushed to the phone, in characters.
+pub const SUMMARY_CHARS: usize = 200;
+
+/// Prefix of the ids of requests the scheduler sends.
+const REQUEST_ID_PREFIX: &str = "bridge-schedule-";
+
+/// How far ahead [`Cron::next_after`] looks for a matching time.
+const MAX_LOOKAHEAD_DAYS: i64 = 366 * 5;
+
+/// A parsed cron expression: `minute hour day-of-month month day-of-week`.
+///
+/// Fields take `*`, numbers, ranges (`1-5`), lists (`1,15`) and steps
+/// (`*/15`, `9-17/2`). Months and weekdays may be named (`jan`, `mon`);
+/// Sunday is `0` or `7`. `@hourly`, `@daily` and `@weekly` are accepted too.
+/// As in cron, when both day fields are restricted a day matching either
+/// one counts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Self> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            other => other,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            anyhow::bail!("cron expression {:?} must have 5 fields: minute hour day-of-month month day-of-week", expression);
+        };
+        let mut weekdays = parse_field(weekday, 0, 7, &WEEKDAYS, 0).with_context(|| format!("day-of-week {:?}", weekday))?;
+        // 7 is Sunday too.
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59, &[], 0).with_context(|| format!("minute {:?}", minute))?,
+            hours: parse_field(hour, 0, 23, &[], 0).with_context(|| format!("hour {:?}", hour))?,
+            days: parse_field(day, 1, 31, &[], 0).with_context(|| format!("day-of-month {:?}", day))?,
+            months: parse_field(month, 1, 12, &MONTHS, 1).with_context(|| format!("month {:?}", month))?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+        day_matches && self.months & (1 << date.month()) != 0
+    }
+
+    /// The first matching minute after `after`. Local times skipped by a
+    /// daylight saving change don't match.
+    pub fn next_after<Tz: TimeZone>(&self, after: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let tz = after.timezone();
+        let start = after.naive_local().with_second(0)?.with_nanosecond(0)? + ChronoDuration::minutes(1);
+        let mut date = start.date();
+        for _ in 0..MAX_LOOKAHEAD_DAYS {
+            if self.matches_date(date) {
+                for hour in (0..24).filter(|h| self.hours & (1 << h) != 0) {
+                    for minute in (0..60).filter(|m| self.minutes & (1 << m) != 0) {
+                        let candidate = date.and_hms_opt(hour, minute, 0)?;
+                        if candidate < start {
+                            continue;
+                        }
+                        if let Some(at) = tz.from_local_datetime(&candidate).earliest() {
+                            return Some(at);
+                        }
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+}
+
+/// Bitmask of the values `field` selects within `min..=max`. `names[i]`
+/// stands for `i + name_base`.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str], name_base: u32) -> Result<u64> {
+    let value = |s: &str| -> Result<u32> {
+        let lower = s.to_ascii_lowercase();
+        let n = match names.iter().position(|name| *name == lower) {
+            Some(i) => i as u32 + name_base,
+            None => s.parse().with_context(|| format!("{:?} is not a number", s))?,
+        };
+        if !(min..=max).contains(&n) {
+            anyhow::bail!("{} is outside {}-{}", n, min, max);
+        }
+        Ok(n)
+    };
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0).with_context(|| format!("bad step {:?}", step))?),
+            None => (part, 1),
+        };
+        let (from, to) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((from, to)) => (value(from)?, value(to)?),
+                // `5/15` means from 5 to the end, every 15.
+                None if part.contains('/') => (value(range)?, max),
+                None => {
+                    let n = value(range)?;
+                    (n, n)
+                }
+            },
+        };
+        if from > to {
+            anyhow::bail!("range {:?} is backwards", range);
+        }
+        for n in (from..=to).step_by(step as usize) {
+            mask |= 1 << n;
+        }
+    }
+    Ok(mask)
+}
+
+/// A schedule ready to run: its config, parsed cron expression and the token
+/// of the agent it prompts.
+#[derive(Clone)]
+pub struct Job {
+    pub schedule: ScheduleConfig,
+    pub cron: Cron,
+    token: String,
+}
+
+/// The jobs for `config`'s `[[schedules]]`. Fails on an invalid schedule or
+/// one naming an unknown device.
+pub fn jobs(config: &CommonConfig) -> Result<Vec<Job>> {
+    let mut jobs: Vec<Job> = Vec::new();
+    for schedule in &config.schedules {
+        schedule.validate()?;
+        if jobs.iter().any(|j| j.schedule.name == schedule.name) {
+            anyhow::bail!("[[schedules]] {:?}: name is used twice", schedule.name);
+        }
+        let token = match schedule.device {
+            Some(ref name) => config
+                .devices
+                .iter()
+                .find(|d| d.name == *name)
+                .map(|d| d.token.clone())
+                .with_context(|| format!("[[schedules]] {:?}: no [[devices]] entry is named {:?}", schedule.name, name))?,
+            None => config.auth_token.clone(),
+        };
+        jobs.push(Job { schedule: schedule.clone(), cron: Cron::parse(&schedule.cron)?, token });
+    }
+    Ok(jobs)
+}
+
+/// The agent's reply to a scheduled prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    /// `stopReason` from the `session/prompt` response
+    pub stop_reason: String,
+    /// The agent's message text, from its `agent_message_chunk` updates
+    pub text: String,
+}
+
+/// The first [`SUMMARY_CHARS`] characters of `text`, on one line.
+pub fn summary(text: &str) -> String {
+    let line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match line.char_indices().nth(SUMMARY_CHARS) {
+        Some((cut, _)) => format!("{}…", &line[..cut]),
+        None => line,
+    }
+}
+
+/// The text `message` adds to session `session_id`'s agent message, if it's
+/// an `agent_message_chunk` update for that session.
+fn chunk_text<'a>(message: &'a serde_json::Value, session_id: &str) -> Option<&'a str> {
+    if message.get("method")?.as_str()? != "session/update" {
+        return None;
+    }
+    let params = message.get("params")?;
+    if params.get("sessionId")?.as_str()? != session_id {
+        return None;
+    }
+    let update = params.get("update")?;
+    if update.get("sessionUpdate")?.as_str()? != "agent_message_chunk" {
+        return None;
+    }
+    update.pointer("/content/text")?.as_str()
+}
+
+/// Send the request `method` to the agent and wait for its answer, passing
+/// every other message to `on_message`. Returns the response's `result`.
+async fn request(
+    tx: &tokio::sync::mpsc::Sender<String>,
+    rx: &mut broadcast::Receiver<String>,
+    method: &str,
+    params: serde_json::Value,
+    mut on_message: impl FnMut(&serde_json::Value),
+) -> Result<serde_json::Value> {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    let id = format!("{}{}", REQUEST_ID_PREFIX, NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let request = serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+    tx.send(request.to_string()).await.context("Agent stdin closed")?;
+    loop {
+        let line = match rx.recv().await {
+            Ok(line) => line,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("⏰ Scheduled run missed {} agent message(s)", n);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => anyhow::bail!("Agent exited during {}", method),
+        };
+        let Ok(message) = serde_json::from_str::<serde_json::Value>(&line) else {
+            continue;
+        };
+        // The agent's own requests carry a method; answers don't.
+        if message.get("id").and_then(|v| v.as_str()) == Some(id.as_str()) && message.get("method").is_none() {
+            if let Some(error) = message.get("error") {
+                anyhow::bail!("Agent answered {} with an error: {}", method, error);
+            }
+            return Ok(message.get("result").cloned().unwrap_or_default());
+        }
+        on_message(&message);
+    }
+}
+
+/// The `sessionId` of a cached `session/new` response.
+fn session_id_of(response: &str) -> Option<String> {
+    let response: serde_json::Value = serde_json::from_str(response).ok()?;
+    response.pointer("/result/sessionId")?.as_str().map(str::to_string)
+}
+
+/// A prompt the bridge sends itself, holding on to the pooled agent of a
+/// token: connected, initialized and with a session. Dropping it leaves the
+/// agent idle again, unless a client connected in the meantime.
+pub struct AgentRun {
+    tx: mpsc::Sender<String>,
+    rx: broadcast::Receiver<String>,
+    slot: Arc<AgentSlot>,
+    session_id: String,
+    was_connected: bool,
+    connections: u64,
+}
+
+impl AgentRun {
+    /// Connect to `token`'s agent, spawning and initializing it if it isn't
+    /// running, and create a session if it has none yet.
+    pub async fn open(pool: &Arc<RwLock<AgentPool>>, token: &str, agent_command: &str) -> Result<Self> {
+        let was_connected = pool.read().await.slot(token).is_some_and(|slot| slot.state().connected);
+        let ((tx, rx, _, _, cached_init, cached_session, _), _, slot) = AgentPool::connect(pool, token, agent_command).await?;
+        let connections = slot.state().connections;
+        let mut run = Self { tx, rx, slot, session_id: String::new(), was_connected, connections };
+
+        if cached_init.is_none() {
+            let params = serde_json::json!({
+                "protocolVersion": 1,
+                "clientCapabilities": { "fs": { "readTextFile": false, "writeTextFile": false }, "terminal": false }
+            });
+            let result = request(&run.tx, &mut run.rx, "initialize", params, |_| {}).await?;
+            run.slot.cache_init_response(serde_json::json!({ "jsonrpc": "2.0", "id": 0, "result": result }).to_string());
+        }
+        run.session_id = match cached_session.as_deref().and_then(session_id_of) {
+            Some(id) => id,
+            None => {
+                let cwd = {
+                    let pool = pool.read().await;
+                    pool.session_dir(token).unwrap_or_else(|| pool.working_dir_for(token).to_path_buf())
+                };
+                let result = request(&run.tx, &mut run.rx, "session/new", serde_json::json!({ "cwd": cwd, "mcpServers": [] }), |_| {}).await?;
+                let id = result.get("sessionId").and_then(|s| s.as_str()).context("session/new answer has no sessionId")?.to_string();
+                run.slot.cache_session_response(serde_json::json!({ "jsonrpc": "2.0", "id": 0, "result": result }).to_string());
+                id
+            }
+        };
+        Ok(run)
+    }
+
+    /// The session the prompt goes to.
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Send `text` as a prompt once earlier bridge prompts to this agent are
+    /// done, and wait up to `timeout` for the reply. A prompt still running
+    /// then is cancelled.
+    pub async fn prompt(mut self, text: &str, timeout: Duration) -> Result<Outcome> {
+        let slot = Arc::clone(&self.slot);
+        let _turn = slot.bridge_prompts.lock().await;
+        let session_id = self.session_id.clone();
+        let mut reply = String::new();
+        let params = serde_json::json!({ "sessionId": session_id, "prompt": [{ "type": "text", "text": text }] });
+        let prompt = request(&self.tx, &mut self.rx, "session/prompt", params, |message| {
+            if let Some(chunk) = chunk_text(message, &session_id) {
+                reply.push_str(chunk);
+            }
+        });
+        match tokio::time::timeout(timeout, prompt).await {
+            Ok(result) => {
+                let result = result?;
+                let stop_reason = result.get("stopReason").and_then(|s| s.as_str()).unwrap_or("end_turn").to_string();
+                Ok(Outcome { stop_reason, text: reply })
+            }
+            Err(_) => {
+                let cancel = serde_json::json!({ "jsonrpc": "2.0", "method": "session/cancel", "params": { "sessionId": session_id } });
+                let _ = self.tx.send(cancel.to_string()).await;
+                Err(anyhow::anyhow!("No answer within {}s; the prompt was cancelled", timeout.as_secs()))
+            }
+        }
+    }
+}
+
+impl Drop for AgentRun {
+    fn drop(&mut self) {
+        if !self.was_connected && self.slot.state().connections == self.connections {
+            self.slot.mark_disconnected();
+        }
+    }
+}
+
+/// Send `job`'s prompt to its agent and wait for the reply.
+pub async fn run(pool: &Arc<RwLock<AgentPool>>, job: &Job, agent_command: &str) -> Result<Outcome> {
+    let timeout = Duration::from_secs(job.schedule.timeout_secs);
+    let run = tokio::time::timeout(timeout, AgentRun::open(pool, &job.token, agent_command))
+        .await
+        .map_err(|_| anyhow::anyhow!("The agent didn't start within {}s", timeout.as_secs()))??;
+    run.prompt(&job.schedule.prompt, timeout).await
+}
+
+/// Run `jobs` at their times until aborted. A run still going at its next
+/// time makes that one skipped.
+pub fn start(
+    pool: Arc<RwLock<AgentPool>>,
+    jobs: Vec<Job>,
+    agent_command: String,
+    push_relay: Option<Arc<PushRelayClient>>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let tasks: Vec<_> = jobs
+            .into_iter()
+            .map(|job| tokio::spawn(run_on_schedule(Arc::clone(&pool), job, agent_command.clone(), push_relay.clone())))
+            .collect();
+        // Aborting this task drops the guard, which stops the schedules.
+        struct AbortOnDrop(Vec<tokio::task::JoinHandle<()>>);
+        impl Drop for AbortOnDrop {
+            fn drop(&mut self) {
+                self.0.iter().for_each(|t| t.abort());
+            }
+        }
+        let _tasks = AbortOnDrop(tasks);
+        std::future::pending::<()>().await
+
+    })
+}
+
+async fn run_on_schedule(pool: Arc<RwLock<AgentPool>>, job: Job, agent_command: String, push_relay: Option<Arc<PushRelayClient>>) {
+    let name = job.schedule.name.clone();
+    loop {
+        let Some(next) = job.cron.next_after(&Local::now()) else {
+            warn!("⏰ Schedule '{}' never fires again", name);
+            return;
+        };
+        info!("⏰ Schedule '{}' runs next at {}", name, next.format("%Y-%m-%d %H:%M"));
+        let wait = (next - Local::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+
+        info!("⏰ Running schedule '{}'", name);
+        let outcome = run(&pool, &job, &agent_command).await;
+        let body = match outcome {
+            Ok(ref outcome) => {
+                info!("⏰ Schedule '{}' finished ({}): {}", name, outcome.stop_reason, summary(&outcome.text));
+                if job.schedule.push && !outcome.text.trim().is_empty() {
+                    summary(&outcome.text)
+                } else {
+                    "Finished".to_string()
+                }
+            }
+            Err(ref e) => {
+                warn!("⏰ Schedule '{}' failed: {:#}", name, e);
+                format!("Failed: {}", e)
+            }
+        };
+        if let Some(ref relay) = push_relay {
+            if let Err(e) = relay.notify_scheduled_task(&name, &body, outcome.is_ok()).await {
+                warn!("⏰ Push notification for schedule '{}' failed: {}", name, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<chrono::Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    #[test]
+    fn cron_finds_the_next_matching_minute() {
+        let weekday_mornings = Cron::parse("0 8 * * mon-fri").unwrap();
+        // Friday 2026-10-16 07:59 → 08:00 the same day
+        assert_eq!(weekday_mornings.next_after(&at("2026-10-16T07:59:30Z")), Some(at("2026-10-16T08:00:00Z")));
+        // Friday 08:00 itself → Monday
+        assert_eq!(weekday_mornings.next_after(&at("2026-10-16T08:00:00Z")), Some(at("2026-10-19T08:00:00Z")));
+
+        let quarter_hours = Cron::parse("*/15 9-17 * * *").unwrap();
+        assert_eq!(quarter_hours.next_after(&at("2026-10-16T09:01:00Z")), Some(at("2026-10-16T09:15:00Z")));
+        assert_eq!(quarter_hours.next_after(&at("2026-10-16T17:45:00Z")), Some(at("2026-10-17T09:00:00Z")));
+
+        // Either day field matches when both are restricted: the 1st, or Sundays (7).
+        let either = Cron::parse("30 6 1 * 7").unwrap();
+        assert_eq!(either.next_after(&at("2026-10-16T00:00:00Z")), Some(at("2026-10-18T06:30:00Z")));
+        assert_eq!(either.next_after(&at("2026-10-25T07:00:00Z")), Some(at("2026-11-01T06:30:00Z")));
+
+        assert_eq!(Cron::parse("@daily").unwrap(), Cron::parse("0 0 * * *").unwrap());
+        assert_eq!(Cron::parse("0 0 29 feb *").unwrap().next_after(&at("2026-10-16T00:00:00Z")), Some(at("2028-02-29T00:00:00Z")));
+        assert!(Cron::parse("0 0 31 2 *").unwrap().next_after(&at("2026-10-16T00:00:00Z")).is_none());
+        for bad in ["0 8 * *", "60 * * * *", "0 8 * * 1-", "0 8 * * fri-mon", "*/0 * * * *", "0 8 * * funday"] {
+            assert!(Cron::parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn replies_are_collected_from_message_chunks() {
+        let chunk = |session: &str, kind: &str, text: &str| {
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "session/update",
+                "params": { "sessionId": session, "update": { "sessionUpdate": kind, "content": { "type": "text", "text": text } } }
+            })
+        };
+        assert_eq!(chunk_text(&chunk("s1", "agent_message_chunk", "Two"), "s1"), Some("Two"));
+        assert_eq!(chunk_text(&chunk("s2", "agent_message_chunk", "other"), "s1"), None);
+        assert_eq!(chunk_text(&chunk("s1", "agent_thought_chunk", "hmm"), "s1"), None);
+
+        assert_eq!(summary("  Two builds\nfailed:\n\n- lint "), "Two builds failed: - lint");
+        let long = summary(&"é".repeat(SUMMARY_CHARS + 5));
+        assert_eq!(long.chars().count(), SUMMARY_CHARS + 1);
+        assert!(long.ends_with('…'));
+    }
+}
@@ -0,0 +1,189 @@
+//! Cloudflare service token rotation in the running bridge.
+//!
+//! Cloudflare Access service tokens are issued for a year. When the
+//! cloudflare transport in `common.toml` keeps the `api_token` it was set up
+//! with, `run_bridge` starts [`watch`], which reloads the config every
+//! [`CHECK_INTERVAL`] and, once fewer than 30 days are left, issues a new
+//! token through [`CloudflareClient`] and saves it to the transport. Pairing
+//! hands out the new token from then on.
+//!
+//! The new token is sent to every device holding a credentials key in a
+//! `bridge/credentialsUpdate` (see [`crate::device_keys`]). The old token is
+//! not revoked: other devices keep connecting with it until it expires, and
+//! are told to pair again by push (`event: "credentialsRotated"`) and a
+//! `bridge/credentialsRotated` notification on every pooled agent's
+//! connection.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::agent_pool::AgentPool;
+use crate::cloudflare::{CloudflareClient, ServiceToken};
+use crate::common_config::{CommonConfig, TransportConfig};
+use crate::device_keys::{CredentialsUpdate, DeviceKeys};
+use crate::pairing::PairingManager;
+use crate::push::PushRelayClient;
+
+/// The transport whose service token is rotated.
+pub const TRANSPORT: &str = "cloudflare";
+
+/// How often a running bridge looks at the service token's age.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(6 * 3600);
+
+/// Whether `config` has a cloudflare transport the bridge can rotate the
+/// service token of.
+pub fn enabled(config: &CommonConfig) -> bool {
+    config
+        .transports
+        .get(TRANSPORT)
+        .is_some_and(|t| t.enabled && t.api_token.as_deref().is_some_and(|k| !k.is_empty()))
+}
+
+/// The `bridge/credentialsRotated` notification for `transport`.
+pub fn credentials_rotated_notification(transport: &str) -> String {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "bridge/credentialsRotated",
+        "params": { "transport": transport },
+    })
+    .to_string()
+}
+
+/// Issue a new service token for the cloudflare transport in `dir`'s
+/// `common.toml` when the current one is due, and save it. Returns `None`
+/// when nothing was due.
+pub async fn rotate_if_due(dir: &Path) -> Result<Option<ServiceToken>> {
+    let mut config = CommonConfig::load_from_dir(dir)?;
+    let Some(transport) = config.transports.get_mut(TRANSPORT) else {
+        return Ok(None);
+    };
+    if !transport.enabled || !transport.service_token_needs_rotation() {
+        return Ok(None);
+    }
+    let (Some(api_token), Some(account_id), Some(hostname)) =
+        (transport.api_token.clone(), transport.account_id.clone(), transport.hostname.clone())
+    else {
+        anyhow::bail!("The cloudflare transport needs api_token, account_id and hostname to rotate its service token");
+    };
+    let client = CloudflareClient::new(api_token, account_id);
+    let token = client
+        .create_service_token(hostname.trim_start_matches("https://"))
+        .await?;
+    record(transport, &token);
+    config
+        .save_to_dir(dir)
+        .context("Failed to save the new service token")?;
+    Ok(Some(token))
+}
+
+/// Store `token` as the transport's service token, issued now.
+fn record(transport: &mut TransportConfig, token: &ServiceToken) {
+    transport.client_id = Some(token.client_id.clone());
+    transport.client_secret = Some(token.client_secret.clone());
+    transport.stamp_service_token_issued();
+}
+
+/// Rotate the service token in `dir` whenever it is due, checking every
+/// [`CHECK_INTERVAL`]. `pairing` is the manager serving the cloudflare
+/// transport, if this bridge serves it. Never resolves.
+pub async fn watch(
+    dir: PathBuf,
+    pairing: Option<Arc<PairingManager>>,
+    pool: Arc<RwLock<AgentPool>>,
+    device_keys: Arc<DeviceKeys>,
+    push_relay: Option<Arc<PushRelayClient>>,
+) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let token = match rotate_if_due(&dir).await {
+            Ok(Some(token)) => token,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("Cloudflare service token rotation failed, retrying later: {:#}", e);
+                continue;
+            }
+        };
+        if let Some(ref pairing) = pairing {
+            pairing.set_service_token(token.client_id.clone(), token.client_secret.clone());
+        }
+        let update = CredentialsUpdate {
+            client_id: Some(token.client_id.clone()),
+            client_secret: Some(token.client_secret.clone()),
+            ..CredentialsUpdate::default()
+        };
+        let pool = pool.read().await;
+        let updated = device_keys.distribute(&pool, &update);
+        let connected = pool.notify_all(&credentials_rotated_notification(TRANSPORT));
+        drop(pool);
+        info!(
+            "🔑 Cloudflare service token rotated; sent it to {} paired device(s), told {} connected client(s)",
+            updated, connected
+        );
+        if let Some(ref relay) = push_relay {
+            if let Err(e) = relay.notify_credentials_rotated(TRANSPORT).await {
+                warn!("Credentials rotation push notification failed: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cloudflare(issued_at: Option<i64>) -> TransportConfig {
+        TransportConfig {
+            enabled: true,
+            hostname: Some("https://agent.example.com".into()),
+            account_id: Some("account".into()),
+            client_id: Some("old-id".into()),
+            client_secret: Some("old-secret".into()),
+            service_token_issued_at: issued_at,
+            ..TransportConfig::default()
+        }
+    }
+
+    #[test]
+    fn tokens_are_due_a_month_before_they_expire() {
+        let now = chrono::Utc::now().timestamp();
+        let day = 24 * 3600;
+        assert!(!cloudflare(Some(now - 300 * day)).service_token_needs_rotation());
+        assert!(cloudflare(Some(now - 340 * day)).service_token_needs_rotation());
+        assert!(cloudflare(None).service_token_needs_rotation(), "an unknown age is rotated");
+        let no_token = TransportConfig { client_id: None, ..cloudflare(None) };
+        assert!(!no_token.service_token_needs_rotation());
+
+        let mut transport = cloudflare(None);
+        record(&mut transport, &ServiceToken { client_id: "new-id".into(), client_secret: "new-secret".into() });
+        assert_eq!(transport.client_id.as_deref(), Some("new-id"));
+        assert_eq!(transport.client_secret.as_deref(), Some("new-secret"));
+        assert!(!transport.service_token_needs_rotation());
+    }
+
+    #[tokio::test]
+    async fn rotation_needs_a_due_token_and_an_api_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = CommonConfig::default();
+        config.transports.insert(TRANSPORT.into(), cloudflare(Some(chrono::Utc::now().timestamp())));
+        config.save_to_dir(dir.path()).unwrap();
+        assert!(!enabled(&config));
+        assert!(rotate_if_due(dir.path()).await.unwrap().is_none());
+
+        config.transports.insert(TRANSPORT.into(), cloudflare(None));
+        config.save_to_dir(dir.path()).unwrap();
+        let error = rotate_if_due(dir.path()).await.unwrap_err();
+        assert!(error.to_string().contains("api_token"), "{error:#}");
+    }
+
+    #[test]
+    fn notification_names_the_transport() {
+        let v: serde_json::Value = serde_json::from_str(&credentials_rotated_notification(TRANSPORT)).unwrap();
+        assert_eq!(v["method"], "bridge/credentialsRotated");
+        assert_eq!(v["params"]["transport"], "cloudflare");
+    }
+}
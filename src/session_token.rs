@@ -0,0 +1,201 @@
+//! Short-lived session tokens derived from the bridge's auth token.
+//!
+//! A device exchanges its long-lived auth token at `POST /session-token` for
+//! a token of the form `st2.<origin>.<expiry>.<nonce>.<signature>`, where
+//! `origin` is when the auth token was exchanged and the signature is
+//! HMAC-SHA256 keyed by the auth token over everything before it. The bridge
+//! accepts a session token wherever it accepts the auth token until it
+//! expires, so one leaked from a proxy log is useful for minutes, not
+//! forever. Presenting a still-valid session token to the same endpoint
+//! refreshes it, keeping its origin: a chain of refreshes ends
+//! [`MAX_LIFETIME`] after the exchange, and the device has to present the
+//! auth token again. Verification is stateless, and rotating the auth token
+//! revokes every session token issued under it.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const PREFIX: &str = "st2";
+
+/// How long refreshing can keep a session token alive after the auth token
+/// was exchanged for it.
+pub const MAX_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A freshly issued session token.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct IssuedToken {
+    #[serde(rename = "sessionToken")]
+    pub token: String,
+    /// Unix time (seconds) after which the token is rejected.
+    #[serde(rename = "expiresAt")]
+    pub expires_at: u64,
+    /// Lifetime in seconds, for clients that don't trust their clock.
+    #[serde(rename = "expiresIn")]
+    pub expires_in: u64,
+}
+
+/// Issues and verifies session tokens for one auth token.
+pub struct SessionTokens {
+    auth_token: String,
+    ttl: Duration,
+}
+
+impl SessionTokens {
+    pub fn new(auth_token: String, ttl: Duration) -> Self {
+        Self { auth_token, ttl }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Issue a token valid for the configured lifetime.
+    pub fn issue(&self) -> IssuedToken {
+        let now = unix_now();
+        self.issue_at(now, now)
+    }
+
+    /// A new token for `presented`: a fresh chain for the auth token, or a
+    /// refresh of a still-valid session token that keeps its origin. `None`
+    /// for anything else, and once the chain has reached [`MAX_LIFETIME`].
+    pub fn exchange(&self, presented: &str) -> Option<IssuedToken> {
+        self.exchange_at(presented, unix_now())
+    }
+
+    fn exchange_at(&self, presented: &str, now: u64) -> Option<IssuedToken> {
+        if self.is_auth_token(presented) {
+            return Some(self.issue_at(now, now));
+        }
+        let (origin, _) = self.verify_at(presented, now)?;
+        (now < origin + MAX_LIFETIME.as_secs()).then(|| self.issue_at(origin, now))
+    }
+
+    fn issue_at(&self, origin: u64, now: u64) -> IssuedToken {
+        let expires_at = (now + self.ttl.as_secs()).min(origin + MAX_LIFETIME.as_secs().max(self.ttl.as_secs()));
+        let nonce = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(rand::random::<[u8; 12]>());
+        let payload = format!("{}.{}.{}.{}", PREFIX, origin, expires_at, nonce);
+        let signature = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(self.sign(&payload));
+        IssuedToken { token: format!("{}.{}", payload, signature), expires_at, expires_in: expires_at - now }
+    }
+
+    /// Whether `token` is an unexpired session token issued under this auth token.
+    pub fn verify(&self, token: &str) -> bool {
+        self.verify_at(token, unix_now()).is_some()
+    }
+
+    /// The origin and expiry of `token` when it is an unexpired session token
+    /// issued under this auth token.
+    fn verify_at(&self, token: &str, now: u64) -> Option<(u64, u64)> {
+        let (payload, signature) = token.rsplit_once('.')?;
+        let mut parts = payload.split('.');
+        let (Some(PREFIX), Some(origin), Some(expiry), Some(_nonce), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return None;
+        };
+        let origin = origin.parse::<u64>().ok()?;
+        let expires_at = expiry.parse::<u64>().ok()?;
+        let signature = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(signature).ok()?;
+        let mut mac = HmacSha256::new_from_slice(self.auth_token.as_bytes()).expect("HMAC accepts any key length");
+        mac.update(payload.as_bytes());
+        (mac.verify_slice(&signature).is_ok() && now < expires_at).then_some((origin, expires_at))
+    }
+
+    /// Whether `presented` is the auth token itself, the only token that may
+    /// mint session tokens.
+    pub fn is_auth_token(&self, presented: &str) -> bool {
+        bool::from(presented.as_bytes().ct_eq(self.auth_token.as_bytes()))
+    }
+
+    /// Whether `presented` is the auth token itself or a valid session token.
+    pub fn accepts(&self, presented: &str) -> bool {
+        self.is_auth_token(presented) || self.verify(presented)
+    }
+
+    fn sign(&self, payload: &str) -> Vec<u8> {
+        let mut mac = HmacSha256::new_from_slice(self.auth_token.as_bytes()).expect("HMAC accepts any key length");
+        mac.update(payload.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens() -> SessionTokens {
+        SessionTokens::new("long-lived".to_string(), Duration::from_secs(900))
+    }
+
+    #[test]
+    fn issued_token_verifies_until_expiry() {
+        let tokens = tokens();
+        let issued = tokens.issue_at(1_000, 1_000);
+        assert_eq!(issued.expires_at, 1_900);
+        assert!(issued.token.starts_with("st2.1000.1900."));
+        assert!(tokens.verify_at(&issued.token, 1_000).is_some());
+        assert!(tokens.verify_at(&issued.token, 1_899).is_some());
+        assert!(tokens.verify_at(&issued.token, 1_900).is_none());
+        assert_ne!(issued.token, tokens.issue_at(1_000, 1_000).token);
+    }
+
+    #[test]
+    fn tampered_or_foreign_tokens_are_rejected() {
+        let tokens = tokens();
+        let issued = tokens.issue_at(1_000, 1_000);
+
+        let extended = issued.token.replacen(".1900.", ".9999.", 1);
+        assert!(tokens.verify_at(&extended, 1_000).is_none());
+        let younger = issued.token.replacen("st2.1000.", "st2.5000.", 1);
+        assert!(tokens.verify_at(&younger, 1_000).is_none());
+
+        let other = SessionTokens::new("rotated".to_string(), Duration::from_secs(900));
+        assert!(other.verify_at(&issued.token, 1_000).is_none());
+
+        for junk in ["", "st2", "st2.1000.1900.abc", "long-lived", "st2.x.y.z.w", "st1.1900.a.b"] {
+            assert!(tokens.verify_at(junk, 1_000).is_none(), "{junk:?}");
+        }
+    }
+
+    #[test]
+    fn accepts_auth_token_and_live_session_tokens() {
+        let tokens = tokens();
+        assert!(tokens.accepts("long-lived"));
+        assert!(tokens.accepts(&tokens.issue().token));
+        assert!(!tokens.accepts(&tokens.issue_at(0, 0).token));
+        assert!(!tokens.accepts("wrong"));
+
+        assert!(tokens.is_auth_token("long-lived"));
+        assert!(!tokens.is_auth_token(&tokens.issue().token));
+    }
+
+    #[test]
+    fn refreshing_ends_at_the_maximum_lifetime() {
+        let tokens = tokens();
+        let max = MAX_LIFETIME.as_secs();
+        let first = tokens.exchange_at("long-lived", 1_000).unwrap();
+        let refreshed = tokens.exchange_at(&first.token, 1_800).unwrap();
+        assert_eq!(refreshed.expires_at, 2_700);
+        assert!(refreshed.token.starts_with("st2.1000."));
+
+        // Near the end of the chain, a refresh expires with it
+        let late = tokens.issue_at(1_000, 1_000 + max - 500);
+        assert_eq!((late.expires_at, late.expires_in), (1_000 + max, 500));
+        let last = tokens.exchange_at(&late.token, 1_000 + max - 100).unwrap();
+        assert_eq!(last.expires_at, 1_000 + max);
+        assert!(tokens.exchange_at(&last.token, 1_000 + max).is_none());
+        // The auth token starts a new chain
+        assert!(tokens.exchange_at("long-lived", 1_000 + max).unwrap().token.starts_with(&format!("st2.{}.", 1_000 + max)));
+        assert!(tokens.exchange_at("wrong", 1_000).is_none());
+    }
+}
@@ -0,0 +1,401 @@
+//! `bridge status`: the configuration, plus a live probe of every enabled
+//! transport.
+//!
+//! | Transport                | Probe                                                                      |
+//! |--------------------------|----------------------------------------------------------------------------|
+//! | `local`, `tailscale-ip`  | connect to the advertised URL and, with TLS, handshake pinned to the served certificate |
+//! | `cloudflare`             | resolve the hostname, then an HTTPS `HEAD` through the tunnel              |
+//! | `tailscale-serve`        | check `tailscale serve` proxies to the bridge, then an HTTPS `HEAD`        |
+//!
+//! A cloudflare transport with an `api_token` is also checked through the
+//! Cloudflare API (see [`crate::tunnel_health`]): a tunnel without edge
+//! connections is unreachable.
+//!
+//! The system clock is compared with `[clock_check] url` (see
+//! [`crate::clock_skew`]) unless the check is disabled.
+//!
+//! Last-connection times come from `bridge.log`: every connection logged
+//! after a `Bridge started on <transport> transport` line arrived on that
+//! transport.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::clock_skew::{self, ClockSkew};
+use crate::common_config::{ClockCheckConfig, CommonConfig, TransportConfig};
+use crate::log_file;
+use crate::output::{paint, Table, Tone};
+use crate::runtime_manifest::{self, RuntimeManifest};
+use crate::tunnel_health::{self, TunnelHealth};
+
+/// How long each network step of a probe may take.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Logged by `run_bridge` when a transport comes up.
+const STARTED_MARKER: &str = "Bridge started on ";
+/// Logged by the bridge for every accepted WebSocket connection.
+const CONNECTED_MARKER: &str = "WebSocket connection established";
+
+/// Outcome of probing one transport.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", content = "detail", rename_all = "lowercase")]
+pub enum Probe {
+    /// What was checked and how it answered.
+    Reachable(String),
+    /// The step that failed and why.
+    Unreachable(String),
+}
+
+impl Probe {
+    pub fn is_reachable(&self) -> bool {
+        matches!(self, Probe::Reachable(_))
+    }
+
+    fn detail(&self) -> &str {
+        match self {
+            Probe::Reachable(detail) | Probe::Unreachable(detail) => detail,
+        }
+    }
+
+    /// Add what the Cloudflare API says about the tunnel; a tunnel without
+    /// edge connections is unreachable whatever the `HEAD` answered.
+    fn with_tunnel(self, health: &TunnelHealth) -> Probe {
+        let detail = format!("{}; {}", self.detail(), health.summary());
+        if health.is_down() || !self.is_reachable() {
+            Probe::Unreachable(detail)
+        } else {
+            Probe::Reachable(detail)
+        }
+    }
+}
+
+/// The system clock against `[clock_check] url`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum ClockStatus {
+    /// Within `max_skew_secs`.
+    Ok(ClockSkew),
+    /// Off by more than `max_skew_secs`.
+    Skewed(ClockSkew),
+    /// The server couldn't be asked.
+    Unknown { error: String },
+}
+
+impl ClockStatus {
+    async fn check(config: &ClockCheckConfig) -> Self {
+        match clock_skew::measure(config).await {
+            Ok(skew) if skew.is_excessive() => ClockStatus::Skewed(skew),
+            Ok(skew) => ClockStatus::Ok(skew),
+            Err(e) => ClockStatus::Unknown { error: format!("{:#}", e) },
+        }
+    }
+}
+
+/// One enabled transport as seen from this machine.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransportStatus {
+    pub name: String,
+    /// URL devices connect to (`None` when it couldn't be resolved).
+    pub url: Option<String>,
+    #[serde(flatten)]
+    pub probe: Probe,
+    /// Log timestamp of the last connection accepted on this transport.
+    pub last_connection: Option<String>,
+    /// The Cloudflare API's view of the tunnel, for `cloudflare` with an `api_token`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel: Option<TunnelHealth>,
+}
+
+/// Everything `bridge status` prints.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusReport {
+    pub config_path: PathBuf,
+    /// `BRIDGE_*` variables overriding `common.toml`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub env_overrides: Vec<String>,
+    pub agent_id: String,
+    /// Manifest of the bridge running from this config directory.
+    pub running: Option<RuntimeManifest>,
+    /// A `runtime.json` left behind by a bridge that is no longer running.
+    pub stale: Option<RuntimeManifest>,
+    pub tailscale_available: bool,
+    /// `None` when `[clock_check]` is disabled.
+    pub clock: Option<ClockStatus>,
+    pub transports: Vec<TransportStatus>,
+}
+
+/// Gather the configuration and probe every enabled transport in turn.
+pub async fn collect(config: &CommonConfig, config_dir: &Path) -> StatusReport {
+    let manifest = RuntimeManifest::read(config_dir).ok().flatten();
+    let (running, stale) = match manifest {
+        Some(m) if runtime_manifest::is_live(config_dir) => (Some(m), None),
+        other => (None, other),
+    };
+    let last = log_file::read_recent(&config_dir.join(log_file::LOG_FILENAME), 5, usize::MAX)
+        .map(|lines| last_connections(&lines))
+        .unwrap_or_default();
+
+    let clock = if config.clock_check.enabled { Some(ClockStatus::check(&config.clock_check).await) } else { None };
+
+    let mut transports = Vec::new();
+    for (name, transport_cfg) in config.enabled_transports() {
+        let (url, mut probe) = match crate::runner::resolve_endpoint(name, transport_cfg, config, config_dir) {
+            Ok((url, fingerprint)) => {
+                let probe = probe_transport(name, transport_cfg, &url, fingerprint.as_deref()).await;
+                (Some(url), probe)
+            }
+            Err(e) => (None, Probe::Unreachable(format!("{:#}", e))),
+        };
+        let mut tunnel = None;
+        if name == "cloudflare" {
+            if let Some((client, tunnel_id)) = tunnel_health::client_for(transport_cfg) {
+                let health = tunnel_health::check(&client, &tunnel_id).await;
+                probe = probe.with_tunnel(&health);
+                tunnel = Some(health);
+            }
+        }
+        transports.push(TransportStatus {
+            name: name.to_string(),
+            url,
+            probe,
+            last_connection: last.get(name).cloned(),
+            tunnel,
+        });
+    }
+
+    StatusReport {
+        config_path: CommonConfig::config_path(),
+        env_overrides: config.env_overrides.iter().map(|o| o.var.clone()).collect(),
+        agent_id: config.agent_id.clone(),
+        running,
+        stale,
+        tailscale_available: crate::tailscale::is_tailscale_available(),
+        clock,
+        transports,
+    }
+}
+
+async fn probe_transport(name: &str, transport_cfg: &TransportConfig, url: &str, fingerprint: Option<&str>) -> Probe {
+    match name {
+        "cloudflare" => probe_https(url).await,
+        "tailscale-serve" => {
+            let port = transport_cfg.port.unwrap_or(8766);
+            match crate::tailscale::serve_status() {
+                Ok(status) if crate::tailscale::serve_proxies_to(&status, port) => probe_https(url).await,
+                Ok(_) => Probe::Unreachable(format!("tailscale serve isn't proxying to localhost:{}", port)),
+                Err(e) => Probe::Unreachable(format!("{:#}", e)),
+            }
+        }
+        _ => probe_listener(url, fingerprint).await,
+    }
+}
+
+/// Connect to the bridge's own listener at `url`; with `wss://`, complete a
+/// TLS handshake that only accepts the certificate with `fingerprint`.
+pub async fn probe_listener(url: &str, fingerprint: Option<&str>) -> Probe {
+    let (secure, host, port) = match split_url(url) {
+        Ok(parts) => parts,
+        Err(e) => return Probe::Unreachable(format!("{:#}", e)),
+    };
+    let connect = crate::connect::connect_stream(&host, port, secure, fingerprint);
+    match tokio::time::timeout(PROBE_TIMEOUT, connect).await {
+        Err(_) => Probe::Unreachable(format!("no answer from {}:{} within {}s", host, port, PROBE_TIMEOUT.as_secs())),
+        Ok(Err(e)) => Probe::Unreachable(format!("{:#}", e)),
+        Ok(Ok(_)) if !secure => Probe::Reachable(format!("accepting connections on {}:{} (no TLS)", host, port)),
+        Ok(Ok(_)) => Probe::Reachable(match fingerprint {
+            Some(fp) => format!("TLS handshake OK, certificate matches {}", fp.chars().take(23).collect::<String>()),
+            None => "TLS handshake OK, certificate trusted by the OS".to_string(),
+        }),
+    }
+}
+
+/// Resolve the host of `url` and send it an HTTPS `HEAD`; any HTTP answer
+/// means the path to the bridge is up.
+pub async fn probe_https(url: &str) -> Probe {
+    let (_, host, _) = match split_url(url) {
+        Ok(parts) => parts,
+        Err(e) => return Probe::Unreachable(format!("{:#}", e)),
+    };
+    let addrs = match tokio::time::timeout(PROBE_TIMEOUT, tokio::net::lookup_host((host.as_str(), 443))).await {
+        Ok(Ok(addrs)) => addrs.map(|a| a.ip().to_string()).collect::<Vec<_>>(),
+        Ok(Err(e)) => return Probe::Unreachable(format!("{} doesn't resolve: {}", host, e)),
+        Err(_) => return Probe::Unreachable(format!("DNS lookup of {} timed out", host)),
+    };
+    let client = match crate::proxy::client_builder()
+        .user_agent(format!("aptove-bridge/{}", crate::VERSION))
+        .timeout(PROBE_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => return Probe::Unreachable(format!("Failed to build HTTP client: {}", e)),
+    };
+    match client.head(format!("https://{}/", host)).send().await {
+        Ok(response) => Probe::Reachable(format!(
+            "{} resolves to {}, HTTPS HEAD → {}",
+            host,
+            addrs.first().map_or("?", String::as_str),
+            response.status()
+        )),
+        Err(e) => Probe::Unreachable(format!("HTTPS HEAD to {} failed: {}", host, e)),
+    }
+}
+
+/// `(secure, host, port)` of a `ws(s)://` or `http(s)://` URL.
+fn split_url(url: &str) -> Result<(bool, String, u16)> {
+    let uri: tokio_tungstenite::tungstenite::http::Uri = url.parse().with_context(|| format!("Invalid URL '{}'", url))?;
+    let secure = matches!(uri.scheme_str(), Some("wss" | "https"));
+    let host = uri.host().context("URL has no host")?.trim_matches(['[', ']']).to_string();
+    let port = uri.port_u16().unwrap_or(if secure { 443 } else { 80 });
+    Ok((secure, host, port))
+}
+
+/// Timestamp of the last connection per transport in `bridge.log` lines,
+/// oldest first.
+pub fn last_connections(lines: &[String]) -> HashMap<String, String> {
+    let mut transport: Option<&str> = None;
+    let mut last = HashMap::new();
+    for line in lines {
+        if let Some((_, rest)) = line.split_once(STARTED_MARKER) {
+            transport = rest.split_once(" transport").map(|(name, _)| name);
+        } else if line.contains(CONNECTED_MARKER) {
+            let mut fields = line.split_whitespace();
+            if let (Some(name), Some(date), Some(time)) = (transport, fields.next(), fields.next()) {
+                last.insert(name.to_string(), format!("{} {}", date, time));
+            }
+        }
+    }
+    last
+}
+
+impl fmt::Display for StatusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Config:    {}", self.config_path.display())?;
+        if !self.env_overrides.is_empty() {
+            writeln!(f, "Env:       {}", self.env_overrides.join(", "))?;
+        }
+        writeln!(f, "Agent ID:  {}", if self.agent_id.is_empty() { "(not generated yet)" } else { &self.agent_id })?;
+        let bridge = match (&self.running, &self.stale) {
+            (Some(m), _) => paint(&format!("running on {} since {} (pid {})", m.transport, m.started_at, m.pid), Tone::Good),
+            (None, Some(m)) => paint(&format!("not running (stale runtime.json from pid {})", m.pid), Tone::Muted),
+            (None, None) => paint("not running", Tone::Muted),
+        };
+        writeln!(f, "Bridge:    {}", bridge)?;
+        if let Some(power) = self.running.as_ref().and_then(|m| m.power.as_ref()) {
+            let source = match (power.on_battery, power.battery_percent) {
+                (true, Some(percent)) => format!("on battery ({}%)", percent),
+                (true, None) => "on battery".to_string(),
+                (false, _) => "plugged in".to_string(),
+            };
+            let line = if power.saving {
+                paint(&format!("{}, power saving since {}", source, power.since), Tone::Bad)
+            } else {
+                source
+            };
+            writeln!(f, "Power:     {}", line)?;
+        }
+        writeln!(f, "Tailscale: {}", if self.tailscale_available { "available" } else { "not available" })?;
+        if let Some(clock) = &self.clock {
+            let line = match clock {
+                ClockStatus::Ok(skew) => paint(&skew.to_string(), Tone::Good),
+                ClockStatus::Skewed(skew) => paint(
+                    &format!("{} (over {}s: token expiry is unreliable, sync the clock)", skew, skew.max_skew_secs),
+                    Tone::Bad,
+                ),
+                ClockStatus::Unknown { error } => paint(&format!("not checked: {}", error), Tone::Muted),
+            };
+            writeln!(f, "Clock:     {}", line)?;
+        }
+        writeln!(f)?;
+        if self.transports.is_empty() {
+            return write!(f, "No transport enabled — run `bridge` once to configure one");
+        }
+        let mut table = Table::new(&["TRANSPORT", "STATE", "URL", "LAST CONNECTION", "DETAIL"]);
+        for t in &self.transports {
+            let state = match t.probe {
+                Probe::Reachable(_) => ("reachable", Tone::Good),
+                Probe::Unreachable(_) => ("unreachable", Tone::Bad),
+            };
+            table.toned_row([
+                (t.name.as_str(), Tone::Plain),
+                state,
+                (t.url.as_deref().unwrap_or("-"), Tone::Plain),
+                (t.last_connection.as_deref().unwrap_or("-"), Tone::Plain),
+                (t.probe.detail(), Tone::Muted),
+            ]);
+        }
+        write!(f, "{}", table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connections_are_attributed_to_the_running_transport() {
+        let lines: Vec<String> = [
+            "2026-10-14 08:00:00.000 INFO  Bridge started on local transport: wss://10.0.0.5:8765",
+            "2026-10-14 08:01:00.000 INFO  ✅ WebSocket connection established",
+            "2026-10-14 08:05:00.000 INFO  ✅ WebSocket connection established",
+            "2026-10-14 09:00:00.000 INFO  Bridge started on cloudflare transport: https://agent.example.com",
+            "2026-10-14 09:00:01.000 DEBUG unrelated",
+            "2026-10-14 09:30:00.000 INFO  ✅ WebSocket connection established",
+        ]
+        .map(String::from)
+        .to_vec();
+        let last = last_connections(&lines);
+        assert_eq!(last.get("local").map(String::as_str), Some("2026-10-14 08:05:00.000"));
+        assert_eq!(last.get("cloudflare").map(String::as_str), Some("2026-10-14 09:30:00.000"));
+        assert_eq!(last.get("tailscale-serve"), None);
+    }
+
+    #[test]
+    fn probes_serialize_with_their_state() {
+        let status = TransportStatus {
+            name: "local".into(),
+            url: Some("wss://10.0.0.5:8765".into()),
+            probe: Probe::Unreachable("Connection refused".into()),
+            last_connection: None,
+            tunnel: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&status).unwrap(),
+            serde_json::json!({
+                "name": "local", "url": "wss://10.0.0.5:8765", "state": "unreachable",
+                "detail": "Connection refused", "lastConnection": null
+            })
+        );
+    }
+
+    #[test]
+    fn tunnel_without_edge_connections_is_unreachable() {
+        let head = Probe::Reachable("HEAD answered 200".into());
+        let down = TunnelHealth::from_clients("tunnel", &[]);
+        assert_eq!(
+            head.clone().with_tunnel(&down),
+            Probe::Unreachable("HEAD answered 200; Cloudflare reports no edge connections".into())
+        );
+        let failed = TunnelHealth::failed("tunnel", &anyhow::anyhow!("403 Forbidden"));
+        assert!(head.with_tunnel(&failed).is_reachable(), "an API error doesn't override the probe");
+    }
+
+    #[tokio::test]
+    async fn listener_probe_reports_refused_and_plain_connections() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let probe = probe_listener(&format!("ws://127.0.0.1:{}", port), None).await;
+        assert!(probe.is_reachable(), "{probe:?}");
+
+        drop(listener);
+        let probe = probe_listener(&format!("ws://127.0.0.1:{}", port), None).await;
+        assert!(!probe.is_reachable(), "{probe:?}");
+    }
+}
@@ -0,0 +1,338 @@
+//! Streamable HTTP transport: the pooled agent over `POST` + Server-Sent Events.
+//!
+//! For clients and corporate proxies that can't keep a WebSocket open:
+//!
+//! - `GET /acp` (`Accept: text/event-stream`) streams every agent message as an
+//!   SSE event whose `id` is the message's sequence number. Reconnecting with
+//!   `Last-Event-ID` replays whatever was missed.
+//! - `POST /acp` with one JSON-RPC message forwards it to the agent and returns
+//!   `202 Accepted`; the reply arrives on the event stream.
+//!
+//! Both authenticate like the WebSocket endpoint and share the token's pooled
+//! agent, so a client can move between transports without losing its session.
+
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{broadcast, RwLock};
+use tracing::{debug, info, warn};
+
+use crate::agent_pool::AgentPool;
+use crate::auth_failures::AuthFailures;
+use crate::bridge::{authenticate, create_http_response};
+use crate::device_tokens::{denied_response, DeviceTokens, Grant, Scopes};
+use crate::session_token::SessionTokens;
+
+/// Path of the streamable HTTP endpoint.
+pub const PATH: &str = "/acp";
+
+/// Largest accepted `POST` body.
+const MAX_BODY: usize = 1024 * 1024;
+
+/// Interval between SSE keep-alive comments, so idle proxies don't drop the stream.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Whether the request line targets the streamable HTTP endpoint.
+pub fn matches(first_line: &str) -> bool {
+    let mut parts = first_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("").split('?').next().unwrap_or("");
+    matches!(method, "GET" | "POST") && path == PATH
+}
+
+/// Value of header `name` (case-insensitive) in a raw HTTP request.
+pub(crate) fn header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+    request
+        .lines()
+        .skip(1)
+        .take_while(|l| !l.is_empty())
+        .filter_map(|l| l.split_once(':'))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.trim())
+}
+
+/// Client token from `X-Bridge-Token`, `Authorization: Bearer`, or `?token=`.
+pub(crate) fn client_token(request: &str) -> Option<String> {
+    if let Some(t) = header(request, "X-Bridge-Token") {
+        return Some(t.to_string());
+    }
+    if let Some(t) = header(request, "Authorization").and_then(|v| v.strip_prefix("Bearer ")) {
+        return Some(t.trim().to_string());
+    }
+    let target = request.lines().next()?.split_whitespace().nth(1)?;
+    let query = target.split_once('?')?.1;
+    query.split('&').find_map(|p| p.strip_prefix("token=")).map(|t| t.to_string())
+}
+
+/// The body of the request whose first read was `raw`: what followed the
+/// headers, then up to `content_length` bytes in all. `None` when the client
+/// sends nothing for `read_timeout`.
+pub(crate) async fn read_body<S>(stream: &mut S, raw: &[u8], content_length: usize, read_timeout: Duration) -> Result<Option<Vec<u8>>>
+where
+    S: AsyncRead + Unpin,
+{
+    let header_end = raw.windows(4).position(|w| w == b"\r\n\r\n").map(|p| p + 4).unwrap_or(raw.len());
+    let mut body = raw[header_end..].to_vec();
+    while body.len() < content_length {
+        let mut chunk = vec![0u8; (content_length - body.len()).min(8192)];
+        let n = match tokio::time::timeout(read_timeout, stream.read(&mut chunk)).await {
+            Ok(result) => result?,
+            Err(_) => return Ok(None),
+        };
+        if n == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    Ok(Some(body))
+}
+
+/// Serve one request to the streamable HTTP endpoint.
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_request<S>(
+    stream: &mut S,
+    raw: &[u8],
+    request: &str,
+    auth_token: &Option<String>,
+    session_tokens: Option<&SessionTokens>,
+    devices: &DeviceTokens,
+    agent_command: Option<&str>,
+    pool: Option<Arc<RwLock<AgentPool>>>,
+    auth_failures: Option<&AuthFailures>,
+    client_ip: &str,
+    read_timeout: Duration,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let token = client_token(request).unwrap_or_default();
+    // Route session tokens to the agent of the token they derive from.
+    let grant = match auth_token {
+        Some(expected) => authenticate(&token, expected, session_tokens, devices),
+        None => (!token.is_empty()).then(|| Grant::full(&token)),
+    };
+    let Some(Grant { pool_key: token, scopes, .. }) = grant else {
+        warn!("🚫 Streamable HTTP request rejected: invalid or missing auth token");
+        if let Some(failures) = auth_failures {
+            failures.record(client_ip, PATH);
+        }
+        let resp = create_http_response(401, "Unauthorized", r#"{"error":"unauthorized"}"#);
+        stream.write_all(resp.as_bytes()).await?;
+        return Ok(());
+    };
+
+    let (Some(pool), Some(agent_command)) = (pool, agent_command) else {
+        let resp = create_http_response(
+            503,
+            "Service Unavailable",
+            r#"{"error":"pool_disabled","message":"The HTTP endpoint requires keep-alive agent pooling"}"#,
+        );
+        stream.write_all(resp.as_bytes()).await?;
+        return Ok(());
+    };
+
+    if request.starts_with("GET") {
+        serve_event_stream(stream, request, &token, scopes, &pool, agent_command).await
+    } else {
+        accept_message(stream, raw, request, &token, scopes, &pool, read_timeout).await
+    }
+}
+
+/// `GET /acp`: attach to the token's agent and stream its output as SSE.
+async fn serve_event_stream<S>(
+    stream: &mut S,
+    request: &str,
+    token: &str,
+    scopes: Scopes,
+    pool: &Arc<RwLock<AgentPool>>,
+    agent_command: &str,
+) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let ((agent_tx, mut rx, buffered, _, _, _, _), subscribed_through, slot) =
+        AgentPool::connect(pool, token, agent_command).await?;
+    info!("📡 SSE event stream opened");
+
+    let head = "HTTP/1.1 200 OK\r\n\
+                Content-Type: text/event-stream\r\n\
+                Cache-Control: no-cache\r\n\
+                X-Accel-Buffering: no\r\n\
+                Connection: close\r\n\
+                \r\n";
+    let result = async {
+        stream.write_all(head.as_bytes()).await?;
+
+        // With Last-Event-ID, replay from the sequence log; otherwise deliver the
+        // messages buffered while no client was attached.
+        let mut replayed_through = 0;
+        let last_event_id = header(request, "Last-Event-ID").and_then(|v| v.parse::<u64>().ok());
+        let replay = match last_event_id {
+            Some(after) => pool.read().await.replay_since(token, after).map(|r| (after, r)),
+            None => None,
+        };
+        match replay {
+            Some((after, replay)) => {
+                info!("📡 Replaying {} message(s) after event {}", replay.messages.len(), after);
+                for (seq, msg) in replay.messages {
+                    write_event(stream, Some(seq), &msg).await?;
+                }
+                replayed_through = replay.last_seq;
+            }
+            None => {
+                for msg in buffered {
+                    write_event(stream, None, &msg).await?;
+                }
+            }
+        }
+        stream.flush().await?;
+
+        let mut next_seq = subscribed_through + 1;
+        let mut keep_alive = tokio::time::interval(KEEP_ALIVE);
+        keep_alive.tick().await;
+        loop {
+            tokio::select! {
+                result = rx.recv() => match result {
+                    Ok(line) => {
+                        let seq = next_seq;
+                        next_seq += 1;
+                        if seq <= replayed_through {
+                            continue;
+                        }
+                        slot.capture_handshake(&line);
+                        // Refuse agent requests outside the token's scopes.
+                        if let Some((scope, id)) = scopes.denied_request(&line) {
+                            warn!("🚫 Agent request denied: token lacks the '{}' scope", scope.as_str());
+                            let _ = agent_tx.send(denied_response(&id, scope)).await;
+                            continue;
+                        }
+                        write_event(stream, Some(seq), &line).await?;
+                        stream.flush().await?;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("SSE receiver lagged, skipped {} messages", n);
+                        next_seq += n;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = keep_alive.tick() => {
+                    stream.write_all(b": keep-alive\n\n").await?;
+                    stream.flush().await?;
+                }
+            }
+        }
+        Ok::<(), std::io::Error>(())
+    }
+    .await;
+
+    if let Err(e) = result {
+        debug!("SSE stream ended: {}", e);
+    }
+    info!("📡 SSE event stream closed, agent stays alive in pool");
+    slot.mark_disconnected();
+    Ok(())
+}
+
+async fn write_event<S: AsyncWrite + Unpin>(stream: &mut S, id: Option<u64>, data: &str) -> std::io::Result<()> {
+    let event = match id {
+        Some(id) => format!("id: {}\ndata: {}\n\n", id, data),
+        None => format!("data: {}\n\n", data),
+    };
+    stream.write_all(event.as_bytes()).await
+}
+
+/// `POST /acp`: forward one JSON-RPC message to the token's agent.
+async fn accept_message<S>(
+    stream: &mut S,
+    raw: &[u8],
+    request: &str,
+    token: &str,
+    scopes: Scopes,
+    pool: &Arc<RwLock<AgentPool>>,
+    read_timeout: Duration,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let content_length: usize = header(request, "Content-Length").and_then(|v| v.parse().ok()).unwrap_or(0);
+    if content_length > MAX_BODY {
+        let resp = create_http_response(413, "Payload Too Large", r#"{"error":"payload_too_large"}"#);
+        stream.write_all(resp.as_bytes()).await?;
+        return Ok(());
+    }
+
+    let Some(body) = read_body(stream, raw, content_length, read_timeout).await? else {
+        warn!("⏱️  Streamable HTTP body stalled for {:?}, closing connection", read_timeout);
+        return Ok(());
+    };
+
+    let Ok(mut message) = serde_json::from_slice::<serde_json::Value>(&body) else {
+        let resp = create_http_response(400, "Bad Request", r#"{"error":"invalid_json"}"#);
+        stream.write_all(resp.as_bytes()).await?;
+        return Ok(());
+    };
+
+    let Some(slot) = pool.read().await.slot(token) else {
+        let resp = create_http_response(
+            409,
+            "Conflict",
+            r#"{"error":"no_event_stream","message":"Open the event stream (GET /acp) before posting"}"#,
+        );
+        stream.write_all(resp.as_bytes()).await?;
+        return Ok(());
+    };
+
+    // Answer requests outside the token's scopes on the event stream, and
+    // hide client capabilities it may not use.
+    if let Some(scope) = scopes.denies(&message) {
+        warn!("🚫 Streamable HTTP request denied: token lacks the '{}' scope", scope.as_str());
+        if let Some(id) = message.get("id") {
+            let _ = slot.output.publish(denied_response(id, scope));
+        }
+        let resp = create_http_response(403, "Forbidden", &serde_json::json!({ "error": "scope_denied", "scope": scope.as_str() }).to_string());
+        stream.write_all(resp.as_bytes()).await?;
+        return Ok(());
+    }
+    scopes.restrict_initialize(&mut message);
+
+    // A reconnecting client must not re-initialize a live agent: answer from
+    // the cached response, delivered on the event stream.
+    if let Some(reply) = slot.cached_initialize_reply(&message) {
+        info!("🔄 Intercepting initialize over HTTP");
+        let _ = slot.output.publish(reply);
+    } else if message.get("method").and_then(|m| m.as_str()) == Some("bridge/ack") {
+        if let Some(seq) = message.pointer("/params/seq").and_then(|s| s.as_u64()) {
+            slot.output.ack(seq);
+        }
+    } else if slot.ws_to_agent_tx.send(message.to_string()).await.is_err() {
+        let resp = create_http_response(502, "Bad Gateway", r#"{"error":"agent_unavailable"}"#);
+        stream.write_all(resp.as_bytes()).await?;
+        return Ok(());
+    }
+
+    let resp = create_http_response(202, "Accepted", "");
+    stream.write_all(resp.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_and_tokens() {
+        assert!(matches("POST /acp HTTP/1.1"));
+        assert!(matches("GET /acp?token=abc HTTP/1.1"));
+        assert!(!matches("GET /acpx HTTP/1.1"));
+        assert!(!matches("PUT /acp HTTP/1.1"));
+
+        let req = "GET /acp HTTP/1.1\r\nauthorization: Bearer secret\r\n\r\n";
+        assert_eq!(client_token(req).as_deref(), Some("secret"));
+        let req = "GET /acp?x=1&token=q HTTP/1.1\r\nHost: h\r\n\r\n";
+        assert_eq!(client_token(req).as_deref(), Some("q"));
+        let req = "POST /acp HTTP/1.1\r\nX-Bridge-Token: t\r\nLast-Event-ID: 42\r\n\r\n{}";
+        assert_eq!(client_token(req).as_deref(), Some("t"));
+        assert_eq!(header(req, "last-event-id"), Some("42"));
+    }
+}
@@ -1,4 +1,5 @@
 use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use tracing::{debug, info};
 
@@ -113,6 +114,49 @@ pub fn get_tailscale_hostname() -> Result<Option<String>> {
     }
 }
 
+/// Fetch the tailnet-issued HTTPS certificate for `hostname` with `tailscale cert`,
+/// writing the PEM pair into `dir`. Returns `(cert_path, key_path)`.
+/// Tailscale caches the certificate and renews it when close to expiry, so this
+/// is cheap to call on every start. Requires HTTPS enabled on the tailnet.
+pub fn fetch_tailscale_cert(hostname: &str, dir: &Path) -> Result<(PathBuf, PathBuf)> {
+    match tailscale_state() {
+        TailscaleState::NotInstalled => anyhow::bail!("{}", INSTALL_HINT),
+        TailscaleState::NotRunning => anyhow::bail!("{}", NOT_RUNNING_HINT),
+        TailscaleState::Available => {}
+    }
+    let cert_path = dir.join("tailscale-cert.pem");
+    let key_path = dir.join("tailscale-key.pem");
+    info!("🔐 Requesting Tailscale certificate for {}", hostname);
+    let output = Command::new("tailscale")
+        .arg("cert")
+        .arg("--cert-file")
+        .arg(&cert_path)
+        .arg("--key-file")
+        .arg(&key_path)
+        .arg(hostname)
+        .output()
+        .context("Failed to run 'tailscale cert'")?;
+    if !output.status.success() {
+        return Err(cert_error(output.status, &String::from_utf8_lossy(&output.stderr)));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = std::fs::set_permissions(&key_path, std::fs::Permissions::from_mode(0o600));
+    }
+    Ok((cert_path, key_path))
+}
+
+/// The error for a failed `tailscale cert`, pointing at the tailnet's HTTPS setting.
+fn cert_error(status: impl std::fmt::Display, stderr: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "tailscale cert failed ({}): {}\n\
+         Ensure HTTPS is enabled on your tailnet: https://tailscale.com/kb/1153/enabling-https",
+        status,
+        stderr.trim()
+    )
+}
+
 /// Parse `(major, minor)` from `tailscale version` output.
 fn parse_tailscale_version(output: &str) -> Option<(u32, u32)> {
     let first_line = output.lines().next()?;
@@ -218,6 +262,42 @@ pub fn tailscale_serve_start(port: u16) -> Result<TailscaleServeGuard> {
     Ok(TailscaleServeGuard::new(HTTPS_PORT))
 }
 
+/// The current `tailscale serve` configuration (`tailscale serve status --json`).
+pub fn serve_status() -> Result<serde_json::Value> {
+    match tailscale_state() {
+        TailscaleState::NotInstalled => anyhow::bail!("{}", INSTALL_HINT),
+        TailscaleState::NotRunning => anyhow::bail!("{}", NOT_RUNNING_HINT),
+        TailscaleState::Available => {}
+    }
+    let output = Command::new("tailscale")
+        .args(["serve", "status", "--json"])
+        .output()
+        .context("Failed to run 'tailscale serve status --json'")?;
+    if !output.status.success() {
+        anyhow::bail!("'tailscale serve status' failed (exit {})", output.status);
+    }
+    // An empty config prints nothing rather than `{}`
+    if output.stdout.iter().all(u8::is_ascii_whitespace) {
+        return Ok(serde_json::json!({}));
+    }
+    serde_json::from_slice(&output.stdout).context("Unexpected 'tailscale serve status' output")
+}
+
+/// Whether a [`serve_status`] config has a handler proxying to the bridge on
+/// localhost `port`, as set up by [`tailscale_serve_start`].
+pub fn serve_proxies_to(status: &serde_json::Value, port: u16) -> bool {
+    let backends = [format!("http://localhost:{}", port), format!("http://127.0.0.1:{}", port)];
+    status
+        .get("Web")
+        .and_then(|w| w.as_object())
+        .into_iter()
+        .flat_map(|web| web.values())
+        .filter_map(|site| site.get("Handlers").and_then(|h| h.as_object()))
+        .flat_map(|handlers| handlers.values())
+        .filter_map(|handler| handler.get("Proxy").and_then(|p| p.as_str()))
+        .any(|proxy| backends.iter().any(|b| proxy.trim_end_matches('/') == b))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -235,6 +315,16 @@ mod tests {
         assert_eq!(parse_tailscale_version("not-a-version"), None);
     }
 
+    #[test]
+    fn test_cert_error_names_the_status_and_the_https_setting() {
+        let err = cert_error("exit status: 1", "  500 Internal Server Error: your tailnet does not support HTTPS\n");
+        assert_eq!(
+            err.to_string(),
+            "tailscale cert failed (exit status: 1): 500 Internal Server Error: your tailnet does not support HTTPS\n\
+             Ensure HTTPS is enabled on your tailnet: https://tailscale.com/kb/1153/enabling-https"
+        );
+    }
+
     #[test]
     fn test_is_tailscale_available_smoke() {
         // This just tests the function runs without panicking.
@@ -285,4 +375,15 @@ mod tests {
             .map(|s| s.trim_end_matches('.').to_string());
         assert_eq!(dns_name, None);
     }
+
+    #[test]
+    fn test_serve_proxies_to_finds_bridge_backend() {
+        let status = serde_json::json!({
+            "TCP": { "443": { "HTTPS": true } },
+            "Web": { "my-laptop.tail1234.ts.net:443": { "Handlers": { "/": { "Proxy": "http://localhost:8766" } } } }
+        });
+        assert!(serve_proxies_to(&status, 8766));
+        assert!(!serve_proxies_to(&status, 8765));
+        assert!(!serve_proxies_to(&serde_json::json!({}), 8766));
+    }
 }
@@ -0,0 +1,371 @@
+//! `bridge test-connection`: the client side of a connection, stage by
+//! stage, so "the app can't connect" turns into the stage that fails.
+//!
+//! | Stage | Passes when |
+//! |-------|-------------|
+//! | `tcp` | the bridge's host and port accept a TCP connection |
+//! | `tls` | the TLS handshake succeeds and the certificate matches the pinned fingerprint (or the OS trust store without one) |
+//! | `pairing` | `GET /pair/…?code=…` returns connection details (pairing URLs only) |
+//! | `upgrade` | the WebSocket upgrade is accepted with the token |
+//! | `initialize` | the agent answers an ACP `initialize` request without an error |
+//!
+//! The first failing stage ends the run; the stages after it are skipped.
+//! Testing a pairing URL uses up its one-time code and pairs a device, like
+//! scanning the QR code would.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_tungstenite::tungstenite::{self, Message};
+
+use crate::connect::{self, ConnectTarget, Io};
+use crate::output::{Table, Tone};
+use crate::pairing::PairingResponse;
+
+/// What to test.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TestTarget {
+    /// Pair with a pairing URL, then connect with the details it returns.
+    Pairing {
+        /// `http(s)://host:port/pair/<transport>?code=…`.
+        url: String,
+        /// The `fp` of the URL, or an override.
+        fingerprint: Option<String>,
+    },
+    /// Connect with a known URL and token.
+    Token(ConnectTarget),
+}
+
+impl TestTarget {
+    /// A pairing URL, or the `scheme://pair?url=…` deep link carrying one.
+    pub fn pairing(url: &str) -> Result<Self> {
+        let url = match url.split_once("://pair?") {
+            Some((_, query)) => {
+                let encoded = query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix("url="))
+                    .context("Deep link carries no pairing URL (static `data=` payloads have no code; use --url and --token)")?;
+                urlencoding::decode(encoded).context("Invalid deep link")?.into_owned()
+            }
+            None => url.to_string(),
+        };
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            anyhow::bail!("Pairing URL must start with http:// or https://");
+        }
+        if crate::pairing::code_of(&url).is_none() {
+            anyhow::bail!("Pairing URL has no code");
+        }
+        let fingerprint = url
+            .split_once('?')
+            .and_then(|(_, query)| query.split('&').find_map(|pair| pair.strip_prefix("fp=")))
+            .map(|fp| urlencoding::decode(fp).map(|fp| fp.into_owned()))
+            .transpose()
+            .context("Invalid fingerprint in pairing URL")?;
+        Ok(Self::Pairing { url, fingerprint })
+    }
+
+    fn url(&self) -> &str {
+        match self {
+            Self::Pairing { url, .. } => url,
+            Self::Token(target) => &target.url,
+        }
+    }
+}
+
+/// Outcome of one stage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StageState {
+    Pass,
+    Fail,
+    /// Not run: an earlier stage failed, or the target doesn't need it.
+    Skip,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Stage {
+    pub name: &'static str,
+    pub state: StageState,
+    /// Milliseconds the stage took; absent when skipped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elapsed_ms: Option<u64>,
+    pub detail: String,
+}
+
+/// Everything `bridge test-connection` prints.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestConnectionReport {
+    pub url: String,
+    pub stages: Vec<Stage>,
+}
+
+impl TestConnectionReport {
+    /// Name of the stage that failed, if any.
+    pub fn failed_stage(&self) -> Option<&'static str> {
+        self.stages.iter().find(|s| s.state == StageState::Fail).map(|s| s.name)
+    }
+}
+
+/// Stages run so far, in order; a failure skips the rest.
+struct Stages {
+    stages: Vec<Stage>,
+    timeout: Duration,
+    failed: bool,
+}
+
+impl Stages {
+    /// Run `stage` with the timeout, recording its outcome as `detail(&value)`.
+    async fn run<T>(
+        &mut self,
+        name: &'static str,
+        stage: impl std::future::Future<Output = Result<T>>,
+        detail: impl FnOnce(&T) -> String,
+    ) -> Option<T> {
+        if self.failed {
+            self.skip(name, "earlier stage failed");
+            return None;
+        }
+        let started = Instant::now();
+        let result = match tokio::time::timeout(self.timeout, stage).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow::anyhow!("timed out after {}s", self.timeout.as_secs_f32())),
+        };
+        let elapsed_ms = Some(started.elapsed().as_millis() as u64);
+        match result {
+            Ok(value) => {
+                self.stages.push(Stage { name, state: StageState::Pass, elapsed_ms, detail: detail(&value) });
+                Some(value)
+            }
+            Err(e) => {
+                self.failed = true;
+                self.stages.push(Stage { name, state: StageState::Fail, elapsed_ms, detail: format!("{:#}", e) });
+                None
+            }
+        }
+    }
+
+    fn skip(&mut self, name: &'static str, detail: &str) {
+        self.stages.push(Stage { name, state: StageState::Skip, elapsed_ms: None, detail: detail.to_string() });
+    }
+}
+
+/// Test `target`, giving each stage up to `timeout`.
+pub async fn run(target: &TestTarget, timeout: Duration) -> TestConnectionReport {
+    let mut stages = Stages { stages: Vec::new(), timeout, failed: false };
+    let connect_target = match target {
+        TestTarget::Pairing { url, fingerprint } => {
+            let stream = open(&mut stages, url, fingerprint.as_deref()).await;
+            let pairing = match stream {
+                Some(stream) => stages.run("pairing", pair(stream, url), |p| format!("paired as {}; the code is now used", p.agent_id)).await,
+                None => {
+                    stages.skip("pairing", "earlier stage failed");
+                    None
+                }
+            };
+            pairing.map(|p| ConnectTarget {
+                url: p.url,
+                token: p.auth_token,
+                fingerprint: p.cert_fingerprint.or_else(|| fingerprint.clone()),
+            })
+        }
+        TestTarget::Token(target) => Some(target.clone()),
+    };
+
+    let ws = match connect_target {
+        // A pairing URL was tested on its own connection: open one to the
+        // WebSocket URL it returned, checked by the upgrade stage.
+        Some(ref t) if matches!(target, TestTarget::Pairing { .. }) => {
+            stages.run("upgrade", upgrade_fresh(t), |_| format!("{} accepted the token", t.url)).await
+        }
+        Some(ref t) => match open(&mut stages, &t.url, t.fingerprint.as_deref()).await {
+            Some(stream) => stages.run("upgrade", upgrade(t, stream), |_| "accepted the token".to_string()).await,
+            None => {
+                stages.skip("upgrade", "earlier stage failed");
+                None
+            }
+        },
+        None => {
+            stages.skip("upgrade", "earlier stage failed");
+            None
+        }
+    };
+
+    match ws {
+        Some(ws) => {
+            stages.run("initialize", initialize(ws), Clone::clone).await;
+        }
+        None => stages.skip("initialize", "earlier stage failed"),
+    }
+
+    TestConnectionReport { url: target.url().to_string(), stages: stages.stages }
+}
+
+/// The `tcp` and `tls` stages for `url`.
+async fn open(stages: &mut Stages, url: &str, fingerprint: Option<&str>) -> Option<Box<dyn Io>> {
+    let endpoint = url.parse().context("Invalid URL").and_then(|uri| connect::endpoint(&uri));
+    let (host, port, secure) = match endpoint {
+        Ok(endpoint) => endpoint,
+        Err(e) => {
+            stages.run::<()>("tcp", async { Err(e) }, |_| String::new()).await;
+            stages.skip("tls", "earlier stage failed");
+            return None;
+        }
+    };
+    let tcp = stages
+        .run("tcp", connect::tcp_connect(&host, port), |tcp| match tcp.peer_addr() {
+            Ok(addr) => format!("connected to {}", addr),
+            Err(_) => format!("connected to {}:{}", host, port),
+        })
+        .await;
+    let Some(tcp) = tcp else {
+        stages.skip("tls", "earlier stage failed");
+        return None;
+    };
+    if !secure {
+        stages.skip("tls", "plain ws:// or http://");
+        return Some(Box::new(tcp));
+    }
+    let pinned = match fingerprint {
+        Some(_) => "certificate matches the pinned fingerprint",
+        None => "certificate trusted by the OS store",
+    };
+    stages.run("tls", connect::tls_connect(&host, tcp, fingerprint), |_| pinned.to_string()).await
+}
+
+/// `GET` the pairing URL over `stream`.
+async fn pair(mut stream: Box<dyn Io>, url: &str) -> Result<PairingResponse> {
+    let uri: tungstenite::http::Uri = url.parse().context("Invalid pairing URL")?;
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let host = uri.authority().map(|a| a.as_str()).unwrap_or_default();
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host);
+    stream.write_all(request.as_bytes()).await.context("Failed to send pairing request")?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.context("Failed to read pairing response")?;
+
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n").context("Malformed HTTP response")?;
+    let status = head.lines().next().unwrap_or_default();
+    if !status.contains(" 200 ") {
+        anyhow::bail!("{}: {}", status.trim_start_matches("HTTP/1.1 "), body.trim());
+    }
+    serde_json::from_str(body).context("Invalid pairing response")
+}
+
+type Ws = tokio_tungstenite::WebSocketStream<Box<dyn Io>>;
+
+async fn upgrade(target: &ConnectTarget, stream: Box<dyn Io>) -> Result<Ws> {
+    let request = connect::client_request(target, &[])?;
+    match tokio_tungstenite::client_async(request, stream).await {
+        Ok((ws, _)) => Ok(ws),
+        Err(tungstenite::Error::Http(response)) => {
+            let body = response.body().as_deref().map(String::from_utf8_lossy).unwrap_or_default();
+            anyhow::bail!("HTTP {}: {}", response.status(), body.trim())
+        }
+        Err(e) => Err(anyhow::Error::new(e).context("WebSocket handshake failed")),
+    }
+}
+
+async fn upgrade_fresh(target: &ConnectTarget) -> Result<Ws> {
+    let uri = connect::client_request(target, &[])?.uri().clone();
+    let (host, port, secure) = connect::endpoint(&uri)?;
+    let stream = connect::connect_stream(&host, port, secure, target.fingerprint.as_deref()).await?;
+    upgrade(target, stream).await
+}
+
+/// Send ACP `initialize` and wait for its response, ignoring the bridge's
+/// own notifications.
+async fn initialize(mut ws: Ws) -> Result<String> {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "initialize",
+        "params": { "protocolVersion": 1, "clientCapabilities": {} },
+    });
+    ws.send(Message::Text(request.to_string().into())).await.context("Failed to send initialize")?;
+    loop {
+        let text = match ws.next().await {
+            Some(Ok(Message::Text(text))) => text,
+            Some(Ok(Message::Close(Some(frame)))) => {
+                anyhow::bail!("bridge closed the connection: {} {}", u16::from(frame.code), frame.reason)
+            }
+            Some(Ok(Message::Close(None))) => anyhow::bail!("bridge closed the connection"),
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => return Err(anyhow::Error::new(e).context("WebSocket error")),
+            None => anyhow::bail!("bridge closed the connection"),
+        };
+        let Ok(message) = serde_json::from_str::<Value>(&text) else { continue };
+        if message.get("id") != Some(&json!(1)) {
+            continue;
+        }
+        let _ = ws.close(None).await;
+        if let Some(error) = message.get("error") {
+            anyhow::bail!("agent returned an error: {}", error);
+        }
+        let info = &message["result"]["agentInfo"];
+        return Ok(match (info["name"].as_str(), info["version"].as_str()) {
+            (Some(name), Some(version)) => format!("agent {} {} answered", name, version),
+            (Some(name), None) => format!("agent {} answered", name),
+            _ => "agent answered".to_string(),
+        });
+    }
+}
+
+impl fmt::Display for TestConnectionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Target: {}", self.url)?;
+        writeln!(f)?;
+        let mut table = Table::new(&["STAGE", "STATE", "TIME", "DETAIL"]);
+        for stage in &self.stages {
+            let state = match stage.state {
+                StageState::Pass => ("ok", Tone::Good),
+                StageState::Fail => ("fail", Tone::Bad),
+                StageState::Skip => ("skip", Tone::Muted),
+            };
+            let elapsed = stage.elapsed_ms.map(|ms| format!("{}ms", ms)).unwrap_or_default();
+            table.toned_row([(stage.name, Tone::Plain), state, (elapsed.as_str(), Tone::Muted), (stage.detail.as_str(), Tone::Muted)]);
+        }
+        write!(f, "{}", table)?;
+        match self.failed_stage() {
+            None => write!(f, "\nConnection works."),
+            Some(stage) => write!(f, "\nFailed at the {} stage.", stage),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairing_urls_and_deep_links_parse() {
+        let url = "https://10.0.0.2:8765/pair/local?code=123456&fp=SHA256%3AAB%3ACD";
+        let expected = TestTarget::Pairing { url: url.to_string(), fingerprint: Some("SHA256:AB:CD".to_string()) };
+        assert_eq!(TestTarget::pairing(url).unwrap(), expected);
+        let link = crate::pairing::deep_link("bridge", url);
+        assert_eq!(TestTarget::pairing(&link).unwrap(), expected);
+
+        assert!(TestTarget::pairing("https://10.0.0.2:8765/pair/local").is_err());
+        assert!(TestTarget::pairing("wss://10.0.0.2:8765").is_err());
+        assert!(TestTarget::pairing(&crate::pairing::deep_link("bridge", r#"{"url":"wss://x"}"#)).is_err());
+    }
+
+    #[tokio::test]
+    async fn refused_connections_fail_at_tcp() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let target = ConnectTarget { url: format!("ws://127.0.0.1:{}", port), token: "t".to_string(), fingerprint: None };
+        let report = run(&TestTarget::Token(target), Duration::from_secs(5)).await;
+        assert_eq!(report.failed_stage(), Some("tcp"));
+        let states: Vec<_> = report.stages.iter().map(|s| (s.name, s.state)).collect();
+        assert_eq!(
+            states,
+            [("tcp", StageState::Fail), ("tls", StageState::Skip), ("upgrade", StageState::Skip), ("initialize", StageState::Skip)]
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//! End-to-end test helpers (feature `testkit`).
+//!
+//! [`TestBridge`] runs a real [`StdioBridge`] in-process on an ephemeral
+//! loopback port with an auth token and a pairing code; [`TestClient`] is a
+//! WebSocket client that speaks JSON-RPC to it.
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use bridge::testkit::TestBridge;
+//!
+//! let bridge = TestBridge::echo().await?;
+//! let mut client = bridge.pair_and_connect().await?;
+//! client.handshake().await?;
+//! let response = client.request("demo/echo", serde_json::json!({ "hi": 1 })).await?;
+//! assert_eq!(response["result"]["hi"], 1);
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, RwLock};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use crate::agent_pool::{AgentPool, PoolConfig};
+use crate::bridge::{AgentHandle, StdioBridge};
+use crate::connect::{self, ConnectTarget, Io};
+use crate::pairing::{PairingManager, PairingResponse};
+
+/// How long [`TestClient`] waits for a message before failing.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A bridge serving plain `ws://` on `127.0.0.1:<ephemeral>`; stopped on drop.
+pub struct TestBridge {
+    addr: SocketAddr,
+    auth_token: String,
+    pairing_code: String,
+    server: JoinHandle<()>,
+}
+
+impl TestBridge {
+    /// Start a bridge in front of `agent_handle`. Agent commands are pooled
+    /// per token, as in the standalone binary.
+    pub async fn start(agent_handle: AgentHandle) -> Result<Self> {
+        Self::start_with(agent_handle, |bridge| bridge).await
+    }
+
+    /// Like [`start`](Self::start), with `configure` applied to the
+    /// [`StdioBridge`] builder before it serves (e.g. `with_session_tokens`).
+    pub async fn start_with(
+        agent_handle: AgentHandle,
+        configure: impl FnOnce(StdioBridge) -> StdioBridge,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.context("Failed to bind ephemeral port")?;
+        let addr = listener.local_addr()?;
+        let auth_token = uuid::Uuid::new_v4().to_string();
+        let cwd = std::env::current_dir().unwrap_or_default().display().to_string();
+        let pairing = PairingManager::new_with_cf(
+            uuid::Uuid::new_v4().to_string(),
+            format!("ws://{}", addr),
+            auth_token.clone(),
+            None,
+            None,
+            None,
+            cwd,
+        );
+        let pairing_code = pairing.get_code();
+
+        let mut bridge = StdioBridge::new(String::new(), addr.port())
+            .with_agent_handle(agent_handle.clone())
+            .with_auth_token(Some(auth_token.clone()))
+            .with_pairing(pairing);
+        if let AgentHandle::Command(_) = agent_handle {
+            bridge = bridge.with_agent_pool(Arc::new(RwLock::new(AgentPool::new(PoolConfig::default()))));
+        }
+        let bridge = configure(bridge);
+        let server = tokio::spawn(async move {
+            if let Err(e) = bridge.serve(listener).await {
+                tracing::error!("Test bridge stopped: {:#}", e);
+            }
+        });
+
+        Ok(Self { addr, auth_token, pairing_code, server })
+    }
+
+    /// Start a bridge in front of a pooled agent subprocess (e.g. `"cat"`).
+    pub async fn command(agent_command: &str) -> Result<Self> {
+        Self::start(AgentHandle::Command(agent_command.to_string())).await
+    }
+
+    /// Start a bridge in front of the bundled echo agent, run in-process.
+    pub async fn echo() -> Result<Self> {
+        Self::start(Self::echo_handle()).await
+    }
+
+    /// An [`AgentHandle`] running the bundled echo agent in-process, for
+    /// [`start_with`](Self::start_with).
+    pub fn echo_handle() -> AgentHandle {
+        let (stdin_tx, mut stdin_rx) = mpsc::channel::<Vec<u8>>(64);
+        let (stdout_tx, stdout_rx) = mpsc::channel::<Vec<u8>>(64);
+        tokio::spawn(async move {
+            while let Some(bytes) = stdin_rx.recv().await {
+                for line in String::from_utf8_lossy(&bytes).lines() {
+                    if let Some(reply) = crate::bench::echo_reply(line) {
+                        if stdout_tx.send(format!("{}\n", reply).into_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+        AgentHandle::InProcess {
+            stdin_tx,
+            stdout_rx: Arc::new(tokio::sync::Mutex::new(stdout_rx)),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// `ws://` URL of the bridge.
+    pub fn url(&self) -> String {
+        format!("ws://{}", self.addr)
+    }
+
+    pub fn auth_token(&self) -> &str {
+        &self.auth_token
+    }
+
+    /// The one-time pairing code; [`pair`](Self::pair) consumes it.
+    pub fn pairing_code(&self) -> &str {
+        &self.pairing_code
+    }
+
+    /// Connection details for [`TestClient::connect`] or [`crate::connect::run`].
+    pub fn target(&self) -> ConnectTarget {
+        ConnectTarget { url: self.url(), token: self.auth_token.clone(), fingerprint: None }
+    }
+
+    /// Pair like the mobile app: `GET /pair/local?code=…`.
+    pub async fn pair(&self) -> Result<PairingResponse> {
+        self.pair_with_code(&self.pairing_code).await
+    }
+
+    /// Pair with an arbitrary code, e.g. to test rejection.
+    pub async fn pair_with_code(&self, code: &str) -> Result<PairingResponse> {
+        let path = format!("/pair/local?code={}", urlencoding::encode(code));
+        let body = self.http("GET", &path, None).await.map_err(|e| anyhow::anyhow!("Pairing failed: {}", e))?;
+        serde_json::from_str(&body).context("Invalid pairing response body")
+    }
+
+    /// Exchange `token` (the auth token or a session token) at
+    /// `POST /session-token`; returns the response JSON.
+    pub async fn session_token(&self, token: &str) -> Result<Value> {
+        let body = self.http("POST", "/session-token", Some(token))
+            .await
+            .map_err(|e| anyhow::anyhow!("Session token request failed: {}", e))?;
+        serde_json::from_str(&body).context("Invalid session token response body")
+    }
+
+    /// Send a bodiless HTTP/1.1 request; returns the body of a 200 response.
+    async fn http(&self, method: &str, path: &str, token: Option<&str>) -> Result<String> {
+        let mut stream = TcpStream::connect(self.addr).await?;
+        let mut request = format!("{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n", method, path, self.addr);
+        if let Some(token) = token {
+            request.push_str(&format!("X-Bridge-Token: {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        tokio::time::timeout(DEFAULT_TIMEOUT, stream.read_to_end(&mut response))
+            .await
+            .context("Timed out waiting for HTTP response")??;
+
+        let response = String::from_utf8_lossy(&response);
+        let (head, body) = response.split_once("\r\n\r\n").context("Malformed HTTP response")?;
+        let status = head.lines().next().unwrap_or_default();
+        if !status.contains(" 200 ") {
+            anyhow::bail!("{} {}", status, body);
+        }
+        Ok(body.to_string())
+    }
+
+    /// Pair, then connect with the returned URL and token.
+    pub async fn pair_and_connect(&self) -> Result<TestClient> {
+        let pairing = self.pair().await?;
+        TestClient::connect(&ConnectTarget { url: pairing.url, token: pairing.auth_token, fingerprint: None }).await
+    }
+
+    /// Connect with the bridge's auth token, skipping pairing.
+    pub async fn connect(&self) -> Result<TestClient> {
+        TestClient::connect(&self.target()).await
+    }
+}
+
+impl Drop for TestBridge {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// A JSON-RPC WebSocket client with per-message timeouts.
+pub struct TestClient {
+    ws: WebSocketStream<Box<dyn Io>>,
+    /// Messages read past while waiting for a response, in arrival order.
+    buffered: VecDeque<Value>,
+    next_id: u64,
+    timeout: Duration,
+    protocol: Option<String>,
+}
+
+impl TestClient {
+    /// Open a WebSocket to `target` (`X-Bridge-Token` auth, optional pinning).
+    pub async fn connect(target: &ConnectTarget) -> Result<Self> {
+        Self::connect_with_protocols(target, &[]).await
+    }
+
+    /// Like [`connect`](Self::connect), offering wire protocol generations
+    /// (e.g. `aptove-bridge.v2`) as `Sec-WebSocket-Protocol`.
+    pub async fn connect_with_protocols(target: &ConnectTarget, protocols: &[&str]) -> Result<Self> {
+        let (ws, protocol) = connect::open_with_protocols(target, protocols).await?;
+        Ok(Self { ws, buffered: VecDeque::new(), next_id: 1, timeout: DEFAULT_TIMEOUT, protocol })
+    }
+
+    /// The subprotocol the bridge selected, if any.
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// Change how long to wait for each message (default 5s).
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Send a raw JSON-RPC message.
+    pub async fn send(&mut self, message: &Value) -> Result<()> {
+        self.ws.send(Message::Text(message.to_string().into())).await.context("Failed to send")
+    }
+
+    /// The next JSON message from the bridge, including notifications.
+    pub async fn recv(&mut self) -> Result<Value> {
+        if let Some(message) = self.buffered.pop_front() {
+            return Ok(message);
+        }
+        self.recv_from_socket().await
+    }
+
+    async fn recv_from_socket(&mut self) -> Result<Value> {
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        loop {
+            let message = tokio::time::timeout_at(deadline, self.ws.next())
+                .await
+                .context("Timed out waiting for a message")?;
+            match message {
+                Some(Ok(Message::Text(text))) => return serde_json::from_str(&text).context("Bridge sent invalid JSON"),
+                Some(Ok(Message::Close(_))) | None => anyhow::bail!("Bridge closed the connection"),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e).context("WebSocket error"),
+            }
+        }
+    }
+
+    /// Send a request and wait for its response; other messages are kept for [`recv`](Self::recv).
+    pub async fn request(&mut self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })).await?;
+        loop {
+            let message = self.recv_from_socket().await?;
+            if message.get("id").and_then(|i| i.as_u64()) == Some(id) && message.get("method").is_none() {
+                return Ok(message);
+            }
+            self.buffered.push_back(message);
+        }
+    }
+
+    /// Wait for the next notification named `method`, skipping (and dropping) others.
+    pub async fn notification(&mut self, method: &str) -> Result<Value> {
+        loop {
+            let message = self.recv().await?;
+            if message.get("id").is_none() && message.get("method").and_then(|m| m.as_str()) == Some(method) {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// ACP `initialize` + `session/new`; returns the session ID.
+    pub async fn handshake(&mut self) -> Result<String> {
+        let init = self.request("initialize", json!({ "protocolVersion": 1, "clientCapabilities": {} })).await?;
+        if let Some(error) = init.get("error") {
+            anyhow::bail!("initialize failed: {}", error);
+        }
+        let session = self.request("session/new", json!({ "cwd": ".", "mcpServers": [] })).await?;
+        session["result"]["sessionId"]
+            .as_str()
+            .map(String::from)
+            .with_context(|| format!("session/new returned no sessionId: {}", session))
+    }
+
+    /// Close the WebSocket cleanly.
+    pub async fn close(mut self) -> Result<()> {
+        self.ws.close(None).await.context("Failed to close WebSocket")
+    }
+}
@@ -1,16 +1,36 @@
-use anyhow::{Context, Result};
-use rcgen::{CertificateParams, DnType, KeyPair, SanType};
+use rcgen::{
+    BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, Issuer, KeyPair, KeyUsagePurpose, SanType,
+};
 use sha2::{Sha256, Digest};
 use std::fs;
 use std::net::IpAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio_rustls::rustls;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
 use tracing::{info, warn};
 
-const CERT_FILENAME: &str = "cert.pem";
+use crate::common_config::{KeyStorage, TlsPolicyConfig};
+use crate::error::{BridgeError, Result, ResultExt};
+use crate::keystore;
+
+/// File name of the generated certificate inside the config directory.
+pub const CERT_FILENAME: &str = "cert.pem";
 const KEY_FILENAME: &str = "key.pem";
 const EXTRA_SANS_FILENAME: &str = "cert-extra-sans.json";
+/// File name of the local CA certificate (`[tls_policy] ca = true`).
+pub const CA_CERT_FILENAME: &str = "ca.pem";
+const CA_KEY_FILENAME: &str = "ca-key.pem";
+/// Validity of the local CA.
+const CA_VALIDITY_DAYS: i64 = 3650;
+
+/// A user-provided certificate to serve instead of the generated self-signed one.
+pub enum CertImport<'a> {
+    /// PEM certificate chain and PEM private key.
+    Pem { cert: &'a Path, key: &'a Path },
+    /// PKCS#12 bundle holding the certificate chain and private key.
+    Pkcs12 { path: &'a Path, password: &'a str },
+}
 
 /// TLS configuration for the bridge
 pub struct TlsConfig {
@@ -24,38 +44,79 @@ pub struct TlsConfig {
     pub fingerprint: String,
     /// TLS acceptor for incoming connections
     pub acceptor: tokio_rustls::TlsAcceptor,
+    /// Protocol version / cipher suite / ALPN policy the acceptor was built with
+    pub policy: TlsPolicyConfig,
 }
 
 impl TlsConfig {
-    /// Load or generate TLS configuration.
+    /// Load or generate TLS configuration with the default (TLS 1.3-only) policy.
     /// `extra_sans` is a list of additional IP addresses or DNS names to include in the certificate SANs.
-    pub fn load_or_generate(config_dir: &PathBuf, extra_sans: &[String]) -> Result<Self> {
+    pub fn load_or_generate(config_dir: &Path, extra_sans: &[String]) -> Result<Self> {
+        Self::load_or_generate_with_policy(config_dir, extra_sans, &TlsPolicyConfig::default())
+    }
+
+    /// Load or generate TLS configuration, building the acceptor with `policy`.
+    /// Returns an error if the policy names an unknown version, cipher suite or ALPN value,
+    /// or combines them in a way no handshake could satisfy.
+    pub fn load_or_generate_with_policy(config_dir: &Path, extra_sans: &[String], policy: &TlsPolicyConfig) -> Result<Self> {
         let cert_path = config_dir.join(CERT_FILENAME);
         let key_path = config_dir.join(KEY_FILENAME);
         let extra_sans_path = config_dir.join(EXTRA_SANS_FILENAME);
 
+        // Resolve where the private key lives.
+        let keystore_account = if policy.key_storage == KeyStorage::System {
+            if keystore::is_available() {
+                Some(keystore_account(config_dir))
+            } else {
+                warn!("⚠️  System keystore unavailable — storing TLS private key in {}", key_path.display());
+                None
+            }
+        } else {
+            None
+        };
+        let lookup = keystore_account.as_deref().map(keystore::load);
+        let mut stored_key = keystore_key(policy.key_storage, lookup, cert_path.exists(), key_path.exists())?;
+        let has_key = |stored: &Option<String>| stored.is_some() || key_path.exists();
+
         // If cert exists, check whether extra_sans have changed
-        if cert_path.exists() && key_path.exists() {
-            if !extra_sans.is_empty() {
-                let mut sorted = extra_sans.to_vec();
-                sorted.sort();
-                let current_json = serde_json::to_string(&sorted).unwrap_or_default();
+        if cert_path.exists() && has_key(&stored_key) && !extra_sans.is_empty() {
+            let mut sorted = extra_sans.to_vec();
+            sorted.sort();
+            let current_json = serde_json::to_string(&sorted).unwrap_or_default();
 
-                let stored_json = fs::read_to_string(&extra_sans_path).unwrap_or_default();
-                if stored_json.trim() != current_json.trim() {
-                    warn!("⚠️  Tailscale address changed since last certificate generation. Regenerating TLS certificate (mobile app will need to re-pair).");
-                    let _ = fs::remove_file(&cert_path);
-                    let _ = fs::remove_file(&key_path);
-                }
+            let stored_json = fs::read_to_string(&extra_sans_path).unwrap_or_default();
+            if stored_json.trim() != current_json.trim() {
+                warn!("⚠️  Tailscale address changed since last certificate generation. Regenerating TLS certificate (mobile app will need to re-pair).");
+                let _ = fs::remove_file(&cert_path);
+                let _ = fs::remove_file(&key_path);
+                stored_key = None;
             }
         }
 
-        if cert_path.exists() && key_path.exists() {
+        if cert_path.exists() && has_key(&stored_key) {
             info!("🔐 Loading existing TLS certificate");
-            Self::load_existing(&cert_path, &key_path)
+            let key_pem = match stored_key {
+                Some(pem) => pem,
+                None => {
+                    let pem = fs::read_to_string(&key_path)
+                        .io_err("Failed to read private key file")?;
+                    // Move an existing on-disk key into the keystore once it's enabled.
+                    if let Some(account) = keystore_account.as_deref() {
+                        match keystore::store(account, &pem) {
+                            Ok(()) => {
+                                let _ = fs::remove_file(&key_path);
+                                info!("🔐 Moved TLS private key into the system keystore");
+                            }
+                            Err(e) => warn!("Failed to move TLS key into system keystore: {}", e),
+                        }
+                    }
+                    pem
+                }
+            };
+            Self::load_existing(&cert_path, &key_path, &key_pem, policy)
         } else {
             info!("🔐 Generating new self-signed TLS certificate");
-            let result = Self::generate_new(&cert_path, &key_path, extra_sans)?;
+            let result = Self::generate_new(&cert_path, &key_path, extra_sans, policy, keystore_account.as_deref())?;
             // Persist extra_sans for future change detection
             if !extra_sans.is_empty() {
                 let mut sorted = extra_sans.to_vec();
@@ -67,64 +128,142 @@ impl TlsConfig {
         }
     }
 
+    /// Load or generate the certificate `transport` serves: the self-signed one
+    /// of [`Self::load_or_generate_with_policy`], or with `policy.ca` a leaf
+    /// signed by the local CA. The leaf is renewed when its addresses change
+    /// or a third of its validity is left; `fingerprint` is the CA's, so
+    /// neither makes devices pair again.
+    pub fn load_or_generate_for_transport(
+        config_dir: &Path,
+        transport: &str,
+        extra_sans: &[String],
+        policy: &TlsPolicyConfig,
+    ) -> Result<Self> {
+        if !policy.ca {
+            return Self::load_or_generate_with_policy(config_dir, extra_sans, policy);
+        }
+        if policy.leaf_validity_days == 0 {
+            return Err(BridgeError::config("TLS policy: leaf_validity_days must be at least 1"));
+        }
+        let ca = LocalCa::load_or_generate(config_dir)?;
+        let cert_path = config_dir.join(format!("leaf-{}.pem", transport));
+        let key_path = config_dir.join(format!("leaf-{}-key.pem", transport));
+        let names = san_names(extra_sans);
+
+        let existing = match (fs::read_to_string(&cert_path), fs::read_to_string(&key_path)) {
+            (Ok(cert_pem), Ok(key_pem)) if leaf_is_current(&cert_pem, &ca.key_id(), &names, policy.leaf_validity_days) => {
+                Some((cert_pem, key_pem))
+            }
+            _ => None,
+        };
+        let (cert_pem, key_pem) = match existing {
+            Some(pair) => {
+                info!("🔐 Loading TLS certificate for {} signed by the local CA", transport);
+                pair
+            }
+            None => {
+                info!("🔐 Issuing TLS certificate for {} from the local CA", transport);
+                let pair = ca.issue_leaf(&names, policy.leaf_validity_days)?;
+                crate::private_file::write(&cert_path, &pair.0).io_err("Failed to write certificate file")?;
+                crate::private_file::write(&key_path, &pair.1).io_err("Failed to write private key file")?;
+                pair
+            }
+        };
+
+        let (mut certs, key) = parse_pem(&cert_pem, &key_pem)?;
+        certs.push(ca.der.clone());
+        let acceptor = Self::build_acceptor(certs, key, policy)?;
+
+        Ok(Self {
+            cert_path,
+            key_path,
+            fingerprint: ca.fingerprint,
+            acceptor,
+            policy: policy.clone(),
+        })
+    }
+
+    /// Load a user-provided certificate and key instead of the self-signed pair.
+    ///
+    /// The leaf certificate's SANs must cover `advertised_host` (an IP address or DNS
+    /// name, wildcards allowed), otherwise clients would reject the handshake anyway.
+    pub fn load_imported(import: &CertImport, advertised_host: &str, policy: &TlsPolicyConfig) -> Result<Self> {
+        let (cert_path, key_path, certs, key) = match import {
+            CertImport::Pem { cert, key } => {
+                let cert_pem = fs::read_to_string(cert)
+                    .io_err(format!("Failed to read certificate file {}", cert.display()))?;
+                let key_pem = fs::read_to_string(key)
+                    .io_err(format!("Failed to read private key file {}", key.display()))?;
+                let (certs, key_der) = parse_pem(&cert_pem, &key_pem)?;
+                (cert.to_path_buf(), key.to_path_buf(), certs, key_der)
+            }
+            CertImport::Pkcs12 { path, password } => {
+                let data = fs::read(path)
+                    .io_err(format!("Failed to read PKCS#12 bundle {}", path.display()))?;
+                let (certs, key_der) = parse_pkcs12(&data, password)
+                    .config_err(format!("Failed to load PKCS#12 bundle {}", path.display()))?;
+                (path.to_path_buf(), path.to_path_buf(), certs, key_der)
+            }
+        };
+
+        let leaf = certs.first().config_err("No certificate found in imported certificate")?;
+        verify_san(leaf.as_ref(), advertised_host)?;
+        let fingerprint = fingerprint_der(leaf.as_ref());
+        let acceptor = Self::build_acceptor(certs, key, policy)?;
+
+        info!("🔐 Using imported TLS certificate from {}", cert_path.display());
+
+        Ok(Self {
+            cert_path,
+            key_path,
+            fingerprint,
+            acceptor,
+            policy: policy.clone(),
+        })
+    }
+
     /// Load existing certificate and key
-    fn load_existing(cert_path: &PathBuf, key_path: &PathBuf) -> Result<Self> {
+    fn load_existing(cert_path: &Path, key_path: &Path, key_pem: &str, policy: &TlsPolicyConfig) -> Result<Self> {
         let cert_pem = fs::read_to_string(cert_path)
-            .context("Failed to read certificate file")?;
-        let key_pem = fs::read_to_string(key_path)
-            .context("Failed to read private key file")?;
+            .io_err("Failed to read certificate file")?;
 
         let fingerprint = Self::calculate_fingerprint(&cert_pem)?;
-        let acceptor = Self::create_acceptor(&cert_pem, &key_pem)?;
+        let acceptor = Self::create_acceptor(&cert_pem, key_pem, policy)?;
 
         Ok(Self {
-            cert_path: cert_path.clone(),
-            key_path: key_path.clone(),
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
             fingerprint,
             acceptor,
+            policy: policy.clone(),
         })
     }
 
-    /// Generate new self-signed certificate
-    fn generate_new(cert_path: &PathBuf, key_path: &PathBuf, extra_sans: &[String]) -> Result<Self> {
+    /// Generate new self-signed certificate.
+    /// When `keystore_account` is set the private key goes to the system keystore instead of `key_path`.
+    fn generate_new(
+        cert_path: &Path,
+        key_path: &Path,
+        extra_sans: &[String],
+        policy: &TlsPolicyConfig,
+        keystore_account: Option<&str>,
+    ) -> Result<Self> {
         // Set up certificate parameters
         let mut params = CertificateParams::default();
         params.distinguished_name.push(DnType::CommonName, "ACP Bridge");
         params.distinguished_name.push(DnType::OrganizationName, "Local Development");
 
-        // Add base SANs for local connections
-        params.subject_alt_names = vec![
-            SanType::DnsName("localhost".try_into().unwrap()),
-            SanType::IpAddress(std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1))),
-        ];
-
-        // Add local network IP
-        if let Ok(local_ip) = local_ip_address::local_ip() {
-            params.subject_alt_names.push(SanType::IpAddress(local_ip));
-        }
+        params.subject_alt_names = san_types(&san_names(extra_sans));
 
-        // Add extra SANs (Tailscale IP/hostname, etc.)
-        for san in extra_sans {
-            if let Ok(ip) = san.parse::<IpAddr>() {
-                params.subject_alt_names.push(SanType::IpAddress(ip));
-            } else {
-                let dns = san.clone().try_into();
-                match dns {
-                    Ok(d) => params.subject_alt_names.push(SanType::DnsName(d)),
-                    Err(_) => warn!("Skipping invalid SAN '{}': not a valid IP or DNS name", san),
-                }
-            }
-        }
-        
         // Valid for 1 year
         params.not_before = time::OffsetDateTime::now_utc();
         params.not_after = time::OffsetDateTime::now_utc() + time::Duration::days(365);
 
         // Generate self-signed certificate
         let key_pair = KeyPair::generate()
-            .context("Failed to generate key pair")?;
+            .config_err("Failed to generate key pair")?;
         let cert = params.self_signed(&key_pair)
-            .context("Failed to generate self-signed certificate")?;
+            .config_err("Failed to generate self-signed certificate")?;
 
         let cert_pem = cert.pem();
         let key_pem = key_pair.serialize_pem();
@@ -132,34 +271,53 @@ impl TlsConfig {
         // Ensure the directory exists before writing
         if let Some(parent) = cert_path.parent() {
             fs::create_dir_all(parent)
-                .context("Failed to create certificate directory")?;
+                .io_err("Failed to create certificate directory")?;
         }
 
-        // Save to files
+        // Save to files (the key only if the keystore didn't take it)
         fs::write(cert_path, &cert_pem)
-            .context("Failed to write certificate file")?;
-        fs::write(key_path, &key_pem)
-            .context("Failed to write private key file")?;
+            .io_err("Failed to write certificate file")?;
+        let key_in_keystore = match keystore_account {
+            Some(account) => match keystore::store(account, &key_pem) {
+                Ok(()) => {
+                    let _ = fs::remove_file(key_path);
+                    info!("🔐 TLS private key stored in the system keystore");
+                    true
+                }
+                Err(e) => {
+                    warn!("Failed to store TLS key in system keystore, writing {}: {}", key_path.display(), e);
+                    false
+                }
+            },
+            None => false,
+        };
+        if !key_in_keystore {
+            fs::write(key_path, &key_pem)
+                .io_err("Failed to write private key file")?;
+        }
 
         // Set restrictive permissions on Unix
         #[cfg(unix)]
         {
             use std::os::unix::fs::PermissionsExt;
             let perms = fs::Permissions::from_mode(0o600);
-            fs::set_permissions(cert_path, perms.clone())?;
-            fs::set_permissions(key_path, perms)?;
+            fs::set_permissions(cert_path, perms.clone()).io_err("Failed to restrict certificate file permissions")?;
+            if !key_in_keystore {
+                fs::set_permissions(key_path, perms).io_err("Failed to restrict private key file permissions")?;
+            }
         }
 
         info!("✅ TLS certificate generated and saved");
 
         let fingerprint = Self::calculate_fingerprint(&cert_pem)?;
-        let acceptor = Self::create_acceptor(&cert_pem, &key_pem)?;
+        let acceptor = Self::create_acceptor(&cert_pem, &key_pem, policy)?;
 
         Ok(Self {
-            cert_path: cert_path.clone(),
-            key_path: key_path.clone(),
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
             fingerprint,
             acceptor,
+            policy: policy.clone(),
         })
     }
 
@@ -168,45 +326,36 @@ impl TlsConfig {
         // Parse PEM to get DER bytes
         let mut reader = std::io::BufReader::new(cert_pem.as_bytes());
         let certs = rustls_pemfile::certs(&mut reader)
-            .collect::<Result<Vec<_>, _>>()
-            .context("Failed to parse certificate PEM")?;
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .config_err("Failed to parse certificate PEM")?;
 
         let cert_der = certs.first()
-            .context("No certificate found in PEM")?;
+            .config_err("No certificate found in PEM")?;
 
-        // Calculate SHA256 hash
-        let mut hasher = Sha256::new();
-        hasher.update(cert_der.as_ref());
-        let hash = hasher.finalize();
-
-        // Format as hex with colons (e.g., "AB:CD:EF:...")
-        let fingerprint = hash.iter()
-            .map(|b| format!("{:02X}", b))
-            .collect::<Vec<_>>()
-            .join(":");
-
-        Ok(fingerprint)
+        Ok(fingerprint_der(cert_der.as_ref()))
     }
 
     /// Create TLS acceptor from PEM strings
-    fn create_acceptor(cert_pem: &str, key_pem: &str) -> Result<tokio_rustls::TlsAcceptor> {
-        // Parse certificate
-        let mut cert_reader = std::io::BufReader::new(cert_pem.as_bytes());
-        let certs = rustls_pemfile::certs(&mut cert_reader)
-            .collect::<Result<Vec<_>, _>>()
-            .context("Failed to parse certificate")?;
-
-        // Parse private key
-        let mut key_reader = std::io::BufReader::new(key_pem.as_bytes());
-        let key = rustls_pemfile::private_key(&mut key_reader)
-            .context("Failed to read private key")?
-            .context("No private key found")?;
+    fn create_acceptor(cert_pem: &str, key_pem: &str, policy: &TlsPolicyConfig) -> Result<tokio_rustls::TlsAcceptor> {
+        let (certs, key) = parse_pem(cert_pem, key_pem)?;
+        Self::build_acceptor(certs, key, policy)
+    }
 
+    /// Create TLS acceptor from a parsed certificate chain and private key
+    fn build_acceptor(
+        certs: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+        policy: &TlsPolicyConfig,
+    ) -> Result<tokio_rustls::TlsAcceptor> {
         // Build TLS config
-        let config = rustls::ServerConfig::builder()
+        let (provider, versions) = build_provider(policy)?;
+        let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(provider))
+            .with_protocol_versions(versions)
+            .config_err("TLS policy: no cipher suite is usable with the enabled protocol versions")?
             .with_no_client_auth()
             .with_single_cert(certs, key)
-            .context("Failed to build TLS config")?;
+            .config_err("Failed to build TLS config")?;
+        config.alpn_protocols = policy.alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
 
         Ok(tokio_rustls::TlsAcceptor::from(Arc::new(config)))
     }
@@ -217,3 +366,581 @@ impl TlsConfig {
         self.fingerprint.chars().take(23).collect()
     }
 }
+
+/// The long-lived CA of `[tls_policy] ca = true`, kept as `ca.pem` and
+/// `ca-key.pem` in the config directory.
+struct LocalCa {
+    key: KeyPair,
+    der: CertificateDer<'static>,
+    fingerprint: String,
+}
+
+impl LocalCa {
+    fn load_or_generate(config_dir: &Path) -> Result<Self> {
+        let cert_path = config_dir.join(CA_CERT_FILENAME);
+        let key_path = config_dir.join(CA_KEY_FILENAME);
+        let (cert_pem, key_pem) = if cert_path.exists() && key_path.exists() {
+            let cert_pem = fs::read_to_string(&cert_path).io_err("Failed to read CA certificate file")?;
+            let key_pem = fs::read_to_string(&key_path).io_err("Failed to read CA private key file")?;
+            (cert_pem, key_pem)
+        } else {
+            info!("🔐 Generating local TLS certificate authority");
+            let key = KeyPair::generate().config_err("Failed to generate key pair")?;
+            let mut params = ca_params();
+            params.not_before = time::OffsetDateTime::now_utc();
+            params.not_after = time::OffsetDateTime::now_utc() + time::Duration::days(CA_VALIDITY_DAYS);
+            let cert = params.self_signed(&key).config_err("Failed to generate CA certificate")?;
+            fs::create_dir_all(config_dir).io_err("Failed to create certificate directory")?;
+            crate::private_file::write(&cert_path, cert.pem()).io_err("Failed to write CA certificate file")?;
+            crate::private_file::write(&key_path, key.serialize_pem()).io_err("Failed to write CA private key file")?;
+            (cert.pem(), key.serialize_pem())
+        };
+
+        let key = KeyPair::from_pem(&key_pem).config_err("Failed to read CA private key")?;
+        let mut reader = std::io::BufReader::new(cert_pem.as_bytes());
+        let der = rustls_pemfile::certs(&mut reader)
+            .next()
+            .config_err("No certificate found in CA PEM")?
+            .config_err("Failed to parse CA certificate PEM")?;
+        let fingerprint = fingerprint_der(der.as_ref());
+        Ok(Self { key, der, fingerprint })
+    }
+
+    /// Key identifier leaves name as their authority key identifier.
+    fn key_id(&self) -> Vec<u8> {
+        ca_params().key_identifier(&self.key)
+    }
+
+    /// A new leaf certificate and key PEM for `names`, valid for `days`.
+    fn issue_leaf(&self, names: &[String], days: u32) -> Result<(String, String)> {
+        let mut params = CertificateParams::default();
+        params.distinguished_name.push(DnType::CommonName, "ACP Bridge");
+        params.distinguished_name.push(DnType::OrganizationName, "Local Development");
+        params.subject_alt_names = san_types(names);
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        params.use_authority_key_identifier_extension = true;
+        params.not_before = time::OffsetDateTime::now_utc();
+        params.not_after = time::OffsetDateTime::now_utc() + time::Duration::days(days.into());
+
+        let key = KeyPair::generate().config_err("Failed to generate key pair")?;
+        let issuer = Issuer::new(ca_params(), &self.key);
+        let cert = params.signed_by(&key, &issuer).config_err("Failed to sign leaf certificate")?;
+        Ok((cert.pem(), key.serialize_pem()))
+    }
+}
+
+/// Subject and constraints of the local CA. Leaves name it as their issuer,
+/// so these must stay the same as when `ca.pem` was generated.
+fn ca_params() -> CertificateParams {
+    let mut params = CertificateParams::default();
+    params.distinguished_name.push(DnType::CommonName, "ACP Bridge Local CA");
+    params.distinguished_name.push(DnType::OrganizationName, "Local Development");
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    params
+}
+
+/// Whether the leaf in `cert_pem` was issued by the CA with `ca_key_id`,
+/// covers exactly `names` and has more than a third of `validity_days` left.
+fn leaf_is_current(cert_pem: &str, ca_key_id: &[u8], names: &[String], validity_days: u32) -> bool {
+    use x509_parser::extensions::{GeneralName, ParsedExtension};
+
+    let mut reader = std::io::BufReader::new(cert_pem.as_bytes());
+    let Some(Ok(der)) = rustls_pemfile::certs(&mut reader).next() else { return false };
+    let Ok((_, cert)) = x509_parser::parse_x509_certificate(der.as_ref()) else { return false };
+
+    let issued_by_ca = cert.extensions().iter().any(|ext| {
+        matches!(ext.parsed_extension(), ParsedExtension::AuthorityKeyIdentifier(aki)
+            if aki.key_identifier.as_ref().is_some_and(|id| id.0 == ca_key_id))
+    });
+    if !issued_by_ca {
+        return false;
+    }
+    let renew_at = cert.validity().not_after.timestamp() - i64::from(validity_days) * 86_400 / 3;
+    if time::OffsetDateTime::now_utc().unix_timestamp() >= renew_at {
+        return false;
+    }
+    let Ok(Some(san)) = cert.subject_alternative_name() else { return false };
+    let mut present: Vec<String> = san
+        .value
+        .general_names
+        .iter()
+        .filter_map(|name| match name {
+            GeneralName::DNSName(dns) => Some(dns.to_string()),
+            GeneralName::IPAddress(bytes) => match bytes.len() {
+                4 => <[u8; 4]>::try_from(*bytes).ok().map(|b| IpAddr::from(b).to_string()),
+                16 => <[u8; 16]>::try_from(*bytes).ok().map(|b| IpAddr::from(b).to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    present.sort();
+    present == names
+}
+
+/// SANs of a generated certificate: localhost, the local network IP and
+/// `extra_sans` (Tailscale IP/hostname, etc.), sorted and deduplicated.
+fn san_names(extra_sans: &[String]) -> Vec<String> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+    if let Ok(local_ip) = local_ip_address::local_ip() {
+        names.push(local_ip.to_string());
+    }
+    names.extend(extra_sans.iter().cloned());
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn san_types(names: &[String]) -> Vec<SanType> {
+    let mut sans = Vec::with_capacity(names.len());
+    for name in names {
+        if let Ok(ip) = name.parse::<IpAddr>() {
+            sans.push(SanType::IpAddress(ip));
+        } else {
+            match name.clone().try_into() {
+                Ok(dns) => sans.push(SanType::DnsName(dns)),
+                Err(_) => warn!("Skipping invalid SAN '{}': not a valid IP or DNS name", name),
+            }
+        }
+    }
+    sans
+}
+
+/// The TLS key kept in the system keystore, from `lookup` (`None` when the
+/// keystore is unavailable). Only a confirmed missing entry counts as no key:
+/// while `cert.pem` exists, a failed lookup, or an unavailable keystore with
+/// no `key.pem` to fall back on, is an error rather than a reason to generate
+/// a new certificate that every paired device would have to pair with again.
+fn keystore_key(
+    storage: KeyStorage,
+    lookup: Option<anyhow::Result<Option<String>>>,
+    cert_exists: bool,
+    key_on_disk: bool,
+) -> Result<Option<String>> {
+    if storage != KeyStorage::System {
+        return Ok(None);
+    }
+    match lookup {
+        Some(Ok(key)) => Ok(key),
+        Some(Err(e)) if cert_exists => Err(BridgeError::config(format!(
+            "Failed to read the TLS private key from the system keystore: {:#}. \
+             Unlock the keystore and start again; a new certificate would make every device pair again",
+            e
+        ))),
+        Some(Err(e)) => {
+            warn!("Failed to read TLS key from system keystore: {:#}", e);
+            Ok(None)
+        }
+        None if cert_exists && !key_on_disk => Err(BridgeError::config(
+            "The TLS private key is kept in the system keystore (key_storage = \"system\"), which is unavailable. \
+             Start the bridge where the keystore can be reached; a new certificate would make every device pair again",
+        )),
+        None => Ok(None),
+    }
+}
+
+/// SHA256 fingerprint of a DER certificate, hex encoded with colons (e.g. "AB:CD:EF:...")
+fn fingerprint_der(cert_der: &[u8]) -> String {
+    let hash = Sha256::digest(cert_der);
+    hash.iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Keystore account of the TLS key of `config_dir`, keyed by config dir so
+/// several projects on one machine don't collide.
+pub fn keystore_account(config_dir: &Path) -> String {
+    format!("tls-key:{}", config_dir.display())
+}
+
+/// Certificate devices pin for the generated certificates of `config_dir`:
+/// the local CA with `policy.ca`, else the self-signed `cert.pem`.
+pub fn pinned_cert_path(config_dir: &Path, policy: &TlsPolicyConfig) -> PathBuf {
+    config_dir.join(if policy.ca { CA_CERT_FILENAME } else { CERT_FILENAME })
+}
+
+/// SHA256 fingerprint of the first certificate in a PEM file, in the same
+/// format as [`TlsConfig::fingerprint`].
+pub fn pem_file_fingerprint(path: &Path) -> Result<String> {
+    let cert_pem = fs::read_to_string(path)
+        .io_err(format!("Failed to read certificate {}", path.display()))?;
+    TlsConfig::calculate_fingerprint(&cert_pem)
+}
+
+/// Client TLS config that trusts exactly the certificate with `fingerprint`,
+/// or certificates it signed (the pinning a paired device does), or the OS
+/// trust store when `None`.
+pub fn client_config(fingerprint: Option<&str>) -> Result<rustls::ClientConfig> {
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let builder = rustls::ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .config_err("Failed to build TLS client config")?;
+    let config = match fingerprint {
+        Some(fingerprint) => builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier {
+                fingerprint: fingerprint.replace(':', "").to_ascii_uppercase(),
+                provider,
+            }))
+            .with_no_client_auth(),
+        None => builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(
+                rustls_platform_verifier::Verifier::new(provider).config_err("Failed to load the OS trust store")?,
+            ))
+            .with_no_client_auth(),
+    };
+    Ok(config)
+}
+
+/// Accepts only the server certificate whose SHA256 fingerprint (compared
+/// without colons, as compact pairing URLs carry it) matches, or
+/// one signed by the CA in the presented chain whose fingerprint matches (the
+/// local CA of `[tls_policy] ca = true`). Handshake signatures are still
+/// verified against the server certificate.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    /// Uppercase hex, no colons.
+    fingerprint: String,
+    provider: Arc<rustls::crypto::CryptoProvider>,
+}
+
+impl rustls::client::danger::ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &rustls::pki_types::ServerName<'_>,
+        ocsp_response: &[u8],
+        now: rustls::pki_types::UnixTime,
+    ) -> std::result::Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        let matches = |cert: &CertificateDer<'_>| fingerprint_der(cert.as_ref()).replace(':', "") == self.fingerprint;
+        if matches(end_entity) {
+            return Ok(rustls::client::danger::ServerCertVerified::assertion());
+        }
+        let Some(ca) = intermediates.iter().find(|c| matches(c)) else {
+            return Err(rustls::Error::General("certificate fingerprint does not match the pinned one".into()));
+        };
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(ca.clone().into_owned())?;
+        rustls::client::WebPkiServerVerifier::builder_with_provider(Arc::new(roots), Arc::clone(&self.provider))
+            .build()
+            .map_err(|e| rustls::Error::General(e.to_string()))?
+            .verify_server_cert(end_entity, &[], server_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Parse a PEM certificate chain and private key
+fn parse_pem(cert_pem: &str, key_pem: &str) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let mut cert_reader = std::io::BufReader::new(cert_pem.as_bytes());
+    let certs = rustls_pemfile::certs(&mut cert_reader)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .config_err("Failed to parse certificate")?;
+
+    let mut key_reader = std::io::BufReader::new(key_pem.as_bytes());
+    let key = rustls_pemfile::private_key(&mut key_reader)
+        .config_err("Failed to read private key")?
+        .config_err("No private key found")?;
+
+    Ok((certs, key))
+}
+
+/// Extract the certificate chain and private key from a PKCS#12 bundle
+fn parse_pkcs12(data: &[u8], password: &str) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let store = p12_keystore::KeyStore::from_pkcs12(data, password, p12_keystore::Pkcs12ImportPolicy::Relaxed)
+        .map_err(|e| BridgeError::config(format!("{} (wrong password?)", e)))?;
+    let (_, chain) = store
+        .private_key_chain()
+        .config_err("PKCS#12 bundle contains no private key")?;
+
+    let certs: Vec<CertificateDer<'static>> = chain
+        .certs()
+        .iter()
+        .map(|c| CertificateDer::from(c.as_der().to_vec()))
+        .collect();
+    if certs.is_empty() {
+        return Err(BridgeError::config("PKCS#12 bundle contains no certificate for its private key"));
+    }
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(chain.key().as_der().to_vec()));
+
+    Ok((certs, key))
+}
+
+/// Check that the certificate's subjectAltName covers `host`.
+fn verify_san(cert_der: &[u8], host: &str) -> Result<()> {
+    use x509_parser::extensions::GeneralName;
+
+    let (_, cert) = x509_parser::parse_x509_certificate(cert_der)
+        .map_err(|e| BridgeError::config(format!("Failed to parse certificate: {}", e)))?;
+    let names = cert
+        .subject_alternative_name()
+        .map_err(|e| BridgeError::config(format!("Invalid subjectAltName extension: {}", e)))?
+        .map(|ext| ext.value.general_names.clone())
+        .unwrap_or_default();
+
+    let host_ip = host.parse::<IpAddr>().ok();
+    let mut present = Vec::new();
+    for name in &names {
+        match name {
+            GeneralName::DNSName(dns) => {
+                if host_ip.is_none() && san_dns_matches(dns, host) {
+                    return Ok(());
+                }
+                present.push(dns.to_string());
+            }
+            GeneralName::IPAddress(bytes) => {
+                let ip = match bytes.len() {
+                    4 => <[u8; 4]>::try_from(*bytes).ok().map(IpAddr::from),
+                    16 => <[u8; 16]>::try_from(*bytes).ok().map(IpAddr::from),
+                    _ => None,
+                };
+                if let Some(ip) = ip {
+                    if host_ip == Some(ip) {
+                        return Ok(());
+                    }
+                    present.push(ip.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Err(BridgeError::config(format!(
+        "Imported certificate is not valid for '{}'. Certificate SANs: [{}]",
+        host,
+        present.join(", ")
+    )))
+}
+
+/// Match a SAN DNS entry against a hostname, allowing a single leading `*.` label wildcard.
+fn san_dns_matches(pattern: &str, host: &str) -> bool {
+    let host = host.trim_end_matches('.');
+    let pattern = pattern.trim_end_matches('.');
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => host
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest.eq_ignore_ascii_case(suffix)),
+        None => pattern.eq_ignore_ascii_case(host),
+    }
+}
+
+/// Resolve a `TlsPolicyConfig` into a crypto provider restricted to the requested
+/// cipher suites, plus the list of protocol versions to enable.
+fn build_provider(
+    policy: &TlsPolicyConfig,
+) -> Result<(rustls::crypto::CryptoProvider, &'static [&'static rustls::SupportedProtocolVersion])> {
+    static TLS13_ONLY: &[&rustls::SupportedProtocolVersion] = &[&rustls::version::TLS13];
+    static TLS12_AND_13: &[&rustls::SupportedProtocolVersion] =
+        &[&rustls::version::TLS13, &rustls::version::TLS12];
+
+    let versions = match policy.min_version.trim() {
+        "1.3" => TLS13_ONLY,
+        "1.2" => TLS12_AND_13,
+        other => {
+            return Err(BridgeError::config(format!(
+                "Unsupported TLS min_version '{}': expected \"1.2\" or \"1.3\"",
+                other
+            )))
+        }
+    };
+
+    let mut provider = rustls::crypto::aws_lc_rs::default_provider();
+
+    if !policy.cipher_suites.is_empty() {
+        let mut selected = Vec::with_capacity(policy.cipher_suites.len());
+        for name in &policy.cipher_suites {
+            let suite = rustls::crypto::aws_lc_rs::ALL_CIPHER_SUITES
+                .iter()
+                .find(|s| s.suite().as_str().is_some_and(|n| n.eq_ignore_ascii_case(name.trim())))
+                .ok_or_else(|| {
+                    let known: Vec<&str> = rustls::crypto::aws_lc_rs::ALL_CIPHER_SUITES
+                        .iter()
+                        .filter_map(|s| s.suite().as_str())
+                        .collect();
+                    BridgeError::config(format!("Unknown TLS cipher suite '{}'. Supported: {}", name, known.join(", ")))
+                })?;
+            if !versions.iter().any(|v| v.version == suite.version().version) {
+                return Err(BridgeError::config(format!(
+                    "TLS cipher suite '{}' requires {:?}, which is disabled by min_version = \"{}\"",
+                    name,
+                    suite.version().version,
+                    policy.min_version
+                )));
+            }
+            selected.push(*suite);
+        }
+        provider.cipher_suites = selected;
+    }
+
+    for proto in &policy.alpn {
+        match proto.as_str() {
+            "" => return Err(BridgeError::config("TLS policy: empty ALPN protocol identifier")),
+            "h2" | "h3" => {
+                return Err(BridgeError::config(format!(
+                    "TLS policy: ALPN '{}' is not supported — WebSocket upgrades require http/1.1",
+                    proto
+                )))
+            }
+            _ => {}
+        }
+    }
+
+    Ok((provider, versions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keystore_failures_never_stand_for_a_missing_key() {
+        let failed = || Some(Err(anyhow::anyhow!("User interaction is not allowed.")));
+        // A failed read or an unavailable keystore keeps the certificate
+        let err = keystore_key(KeyStorage::System, failed(), true, false).unwrap_err().to_string();
+        assert!(err.contains("User interaction is not allowed"), "{}", err);
+        assert!(keystore_key(KeyStorage::System, failed(), true, true).is_err());
+        assert!(keystore_key(KeyStorage::System, None, true, false).is_err());
+
+        // A confirmed missing entry, or no certificate yet, may generate one
+        assert_eq!(keystore_key(KeyStorage::System, Some(Ok(None)), true, false).unwrap(), None);
+        assert_eq!(keystore_key(KeyStorage::System, failed(), false, false).unwrap(), None);
+        assert_eq!(keystore_key(KeyStorage::System, None, true, true).unwrap(), None);
+        let stored = Some(Ok(Some("PEM".to_string())));
+        assert_eq!(keystore_key(KeyStorage::System, stored, true, false).unwrap().as_deref(), Some("PEM"));
+        assert_eq!(keystore_key(KeyStorage::File, failed(), true, false).unwrap(), None);
+    }
+
+    fn policy_error(policy: TlsPolicyConfig) -> String {
+        match build_provider(&policy) {
+            Ok(_) => panic!("policy should be rejected"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn default_policy_is_tls13_only() {
+        let (provider, versions) = build_provider(&TlsPolicyConfig::default()).unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].version, rustls::ProtocolVersion::TLSv1_3);
+        assert!(!provider.cipher_suites.is_empty());
+
+        let tls12 = TlsPolicyConfig { min_version: "1.2".into(), ..TlsPolicyConfig::default() };
+        assert_eq!(build_provider(&tls12).unwrap().1.len(), 2);
+    }
+
+    #[test]
+    fn invalid_policies_name_the_bad_value() {
+        let err = policy_error(TlsPolicyConfig { min_version: "1.1".into(), ..TlsPolicyConfig::default() });
+        assert!(err.contains("'1.1'"), "{}", err);
+
+        let err = policy_error(TlsPolicyConfig { cipher_suites: vec!["TLS_NULL_WITH_NULL".into()], ..TlsPolicyConfig::default() });
+        assert!(err.contains("'TLS_NULL_WITH_NULL'") && err.contains("TLS13_AES_128_GCM_SHA256"), "{}", err);
+
+        let tls12_suite = TlsPolicyConfig {
+            cipher_suites: vec!["TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256".into()],
+            ..TlsPolicyConfig::default()
+        };
+        let err = policy_error(tls12_suite.clone());
+        assert!(err.contains("'TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256'") && err.contains("\"1.3\""), "{}", err);
+        assert!(build_provider(&TlsPolicyConfig { min_version: "1.2".into(), ..tls12_suite }).is_ok());
+
+        for alpn in ["h2", "h3"] {
+            let err = policy_error(TlsPolicyConfig { alpn: vec!["http/1.1".into(), alpn.into()], ..TlsPolicyConfig::default() });
+            assert!(err.contains(&format!("'{}'", alpn)), "{}", err);
+        }
+        assert!(policy_error(TlsPolicyConfig { alpn: vec![String::new()], ..TlsPolicyConfig::default() }).contains("empty ALPN"));
+    }
+
+    #[test]
+    fn san_dns_exact_and_wildcard() {
+        assert!(san_dns_matches("bridge.example.com", "Bridge.Example.com"));
+        assert!(san_dns_matches("*.example.com", "bridge.example.com"));
+        assert!(!san_dns_matches("*.example.com", "example.com"));
+        assert!(!san_dns_matches("*.example.com", "a.b.example.com"));
+        assert!(!san_dns_matches("other.example.com", "bridge.example.com"));
+    }
+
+    #[test]
+    fn verify_san_checks_ip_and_dns() {
+        let mut params = CertificateParams::default();
+        params.subject_alt_names = vec![
+            SanType::DnsName("bridge.example.com".try_into().unwrap()),
+            SanType::IpAddress("100.64.0.7".parse().unwrap()),
+        ];
+        let key = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+
+        assert!(verify_san(cert.der(), "bridge.example.com").is_ok());
+        assert!(verify_san(cert.der(), "100.64.0.7").is_ok());
+        let err = verify_san(cert.der(), "192.168.1.10").unwrap_err().to_string();
+        assert!(err.contains("100.64.0.7"), "error should list SANs: {}", err);
+    }
+
+    #[test]
+    fn load_imported_pem_rejects_san_mismatch() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut params = CertificateParams::default();
+        params.subject_alt_names = vec![SanType::DnsName("bridge.example.com".try_into().unwrap())];
+        let key = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        let cert_path = dir.path().join("imported.pem");
+        let key_path = dir.path().join("imported.key");
+        fs::write(&cert_path, cert.pem()).unwrap();
+        fs::write(&key_path, key.serialize_pem()).unwrap();
+
+        let import = CertImport::Pem { cert: &cert_path, key: &key_path };
+        let policy = TlsPolicyConfig::default();
+        let tls = TlsConfig::load_imported(&import, "bridge.example.com", &policy).unwrap();
+        assert_eq!(tls.fingerprint, fingerprint_der(cert.der()));
+        assert!(TlsConfig::load_imported(&import, "10.0.0.1", &policy).is_err());
+    }
+
+    #[tokio::test]
+    async fn ca_mode_pins_the_ca_across_leaf_renewals() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let policy = TlsPolicyConfig { ca: true, ..TlsPolicyConfig::default() };
+        let first = TlsConfig::load_or_generate_for_transport(dir.path(), "local", &[], &policy).unwrap();
+        let leaf = fs::read_to_string(&first.cert_path).unwrap();
+        assert_eq!(first.fingerprint, pem_file_fingerprint(&dir.path().join(CA_CERT_FILENAME)).unwrap());
+
+        TlsConfig::load_or_generate_for_transport(dir.path(), "local", &[], &policy).unwrap();
+        assert_eq!(fs::read_to_string(&first.cert_path).unwrap(), leaf, "a current leaf is reused");
+
+        let moved = TlsConfig::load_or_generate_for_transport(dir.path(), "local", &["10.1.2.3".to_string()], &policy).unwrap();
+        assert_ne!(fs::read_to_string(&moved.cert_path).unwrap(), leaf, "a new address issues a new leaf");
+        assert_eq!(moved.fingerprint, first.fingerprint);
+
+        // A client pinning the CA accepts the new leaf for the new address.
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        let acceptor = moved.acceptor.clone();
+        let server = tokio::spawn(async move { acceptor.accept(server).await.map(|_| ()) });
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config(Some(&moved.fingerprint)).unwrap()));
+        let name = rustls::pki_types::ServerName::try_from("10.1.2.3").unwrap();
+        let _tls = connector.connect(name, client).await.unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
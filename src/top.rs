@@ -0,0 +1,636 @@
+//! `bridge top`: a live dashboard of the bridge running from this config
+//! directory — client connections, pooled agents with their message rates
+//! and buffers, the pairing code, the tunnel and recent log lines — with
+//! keys to kill an agent or replace the pairing code.
+//!
+//! Everything comes from the bridge's admin endpoints (see [`crate::admin`]),
+//! reached over loopback with the admin token, and from `bridge.log`.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, TableState};
+use ratatui::{Frame, Terminal};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use crate::admin::{AdminStatus, PairingStatus};
+use crate::agent_pool::AgentSummary;
+use crate::log_file;
+use crate::runtime_manifest::RuntimeManifest;
+use crate::status::Probe;
+use crate::tui::events::LogRecord;
+use crate::tui::widgets::log_panel::render_log_panel;
+
+/// How long one admin request may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often a tunnel (`cloudflare`, `tailscale-serve`) is probed.
+const TUNNEL_PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Log lines kept for the log panel.
+const LOG_LINES: usize = 500;
+
+/// Least severe level shown in the log panel (INFO).
+const LOG_LEVEL: u8 = 3;
+
+/// The admin endpoints of a running bridge.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdminClient {
+    host: String,
+    port: u16,
+    secure: bool,
+    /// Pinned certificate fingerprint (with TLS)
+    fingerprint: Option<String>,
+    token: String,
+}
+
+impl AdminClient {
+    /// The bridge described by `manifest`, over loopback when it listens on
+    /// every address. TLS is pinned to the manifest's certificate.
+    pub fn local(manifest: &RuntimeManifest, token: String) -> Self {
+        let host = match manifest.bind_address.as_str() {
+            "0.0.0.0" | "" => "127.0.0.1".to_string(),
+            "::" | "[::]" => "::1".to_string(),
+            addr => addr.trim_matches(['[', ']']).to_string(),
+        };
+        Self {
+            host,
+            port: manifest.port,
+            secure: manifest.tls_fingerprint.is_some(),
+            fingerprint: manifest.tls_fingerprint.clone(),
+            token,
+        }
+    }
+
+    pub async fn status(&self) -> Result<AdminStatus> {
+        let body = self.request("GET", "/admin/status").await?;
+        serde_json::from_str(&body).context("Invalid /admin/status response")
+    }
+
+    pub async fn kill_agent(&self, id: &str) -> Result<()> {
+        self.request("POST", &format!("/admin/agents/{}/kill", id)).await.map(|_| ())
+    }
+
+    pub async fn rotate_pairing_code(&self) -> Result<PairingStatus> {
+        let body = self.request("POST", "/admin/pairing/rotate").await?;
+        serde_json::from_str(&body).context("Invalid /admin/pairing/rotate response")
+    }
+
+    /// Send one request and return the body of a `2xx` answer.
+    async fn request(&self, method: &str, path: &str) -> Result<String> {
+        let exchange = async {
+            let mut stream = crate::connect::connect_stream(&self.host, self.port, self.secure, self.fingerprint.as_deref()).await?;
+            let request = format!(
+                "{} {} HTTP/1.1\r\nHost: {}:{}\r\nX-Bridge-Token: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                method, path, self.host, self.port, self.token
+            );
+            stream.write_all(request.as_bytes()).await?;
+            read_response(&mut stream).await
+        };
+        let (status, body) = tokio::time::timeout(REQUEST_TIMEOUT, exchange)
+            .await
+            .map_err(|_| anyhow::anyhow!("No answer from {}:{} within {}s", self.host, self.port, REQUEST_TIMEOUT.as_secs()))??;
+        match status {
+            200..=299 => Ok(body),
+            401 => anyhow::bail!("The bridge rejected the admin token in common.toml"),
+            _ => {
+                let error = serde_json::from_str::<serde_json::Value>(&body)
+                    .ok()
+                    .and_then(|v| v["error"].as_str().map(String::from))
+                    .unwrap_or(body);
+                anyhow::bail!("{} {} → {} {}", method, path, status, error)
+            }
+        }
+    }
+}
+
+/// Status code and body of an HTTP/1.1 response with `Content-Length`.
+async fn read_response<S: AsyncRead + Unpin>(stream: &mut S) -> Result<(u16, String)> {
+    let mut data = Vec::new();
+    let mut chunk = [0u8; 8192];
+    loop {
+        let n = stream.read(&mut chunk).await.context("Failed to read response")?;
+        data.extend_from_slice(&chunk[..n]);
+        if let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            let head = String::from_utf8_lossy(&data[..end]).into_owned();
+            let length: usize = crate::streamable_http::header(&head, "Content-Length")
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(0);
+            if data.len() >= end + 4 + length {
+                let status = head
+                    .split_whitespace()
+                    .nth(1)
+                    .and_then(|s| s.parse().ok())
+                    .context("Invalid HTTP status line")?;
+                return Ok((status, String::from_utf8_lossy(&data[end + 4..end + 4 + length]).into_owned()));
+            }
+        }
+        if n == 0 {
+            anyhow::bail!("Connection closed before the response was complete");
+        }
+    }
+}
+
+/// Messages per second into and out of each agent, by id, between two
+/// snapshots `elapsed` apart. Agents missing from `previous` are left out.
+pub fn message_rates(previous: &[AgentSummary], current: &[AgentSummary], elapsed: Duration) -> HashMap<String, (f64, f64)> {
+    let secs = elapsed.as_secs_f64();
+    if secs <= 0.0 {
+        return HashMap::new();
+    }
+    current
+        .iter()
+        .filter_map(|agent| {
+            let before = previous.iter().find(|p| p.id == agent.id)?;
+            let rate = |now: u64, then: u64| now.saturating_sub(then) as f64 / secs;
+            Some((agent.id.clone(), (rate(agent.messages_in, before.messages_in), rate(agent.messages_out, before.messages_out))))
+        })
+        .collect()
+}
+
+/// `1h02m`, `3m05s` or `42s`.
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+fn format_bytes(bytes: usize) -> String {
+    match bytes {
+        0..=1023 => format!("{}B", bytes),
+        1024..=1_048_575 => format!("{:.1}K", bytes as f64 / 1024.0),
+        _ => format!("{:.1}M", bytes as f64 / 1_048_576.0),
+    }
+}
+
+/// What a key press asks for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    None,
+    Quit,
+    Kill(String),
+    RotatePairingCode,
+}
+
+/// Dashboard state between refreshes.
+pub struct Dashboard {
+    manifest: RuntimeManifest,
+    status: Option<AdminStatus>,
+    fetched_at: Option<Instant>,
+    rates: HashMap<String, (f64, f64)>,
+    /// Last failed refresh, shown until one succeeds
+    error: Option<String>,
+    tunnel: Option<Probe>,
+    logs: Vec<LogRecord>,
+    table: TableState,
+    /// Agent waiting for `y` to be killed
+    confirm_kill: Option<String>,
+    /// Outcome of the last action
+    notice: Option<String>,
+}
+
+impl Dashboard {
+    pub fn new(manifest: RuntimeManifest) -> Self {
+        Self {
+            manifest,
+            status: None,
+            fetched_at: None,
+            rates: HashMap::new(),
+            error: None,
+            tunnel: None,
+            logs: Vec::new(),
+            table: TableState::default().with_selected(Some(0)),
+            confirm_kill: None,
+            notice: None,
+        }
+    }
+
+    /// Take a refresh result, updating message rates from the previous one.
+    pub fn update(&mut self, status: Result<AdminStatus>) {
+        match status {
+            Ok(status) => {
+                let now = Instant::now();
+                if let (Some(previous), Some(at)) = (&self.status, self.fetched_at) {
+                    self.rates = message_rates(&previous.agents, &status.agents, now - at);
+                }
+                let last = status.agents.len().saturating_sub(1);
+                self.table.select(Some(self.table.selected().unwrap_or(0).min(last)));
+                self.status = Some(status);
+                self.fetched_at = Some(now);
+                self.error = None;
+            }
+            Err(e) => self.error = Some(format!("{:#}", e)),
+        }
+    }
+
+    pub fn push_log(&mut self, line: &str) {
+        let mut fields = line.splitn(4, ' ').filter(|f| !f.is_empty());
+        let (Some(_date), Some(time), Some(level)) = (fields.next(), fields.next(), fields.next()) else {
+            return;
+        };
+        let message = line.split_once(level).map_or("", |(_, rest)| rest.trim_start());
+        self.logs.push(LogRecord {
+            timestamp: time.chars().take(8).collect(),
+            level: format!("{:<5}", level),
+            message: message.to_string(),
+        });
+        if self.logs.len() > LOG_LINES {
+            self.logs.drain(..self.logs.len() - LOG_LINES);
+        }
+    }
+
+    pub fn set_tunnel(&mut self, probe: Probe) {
+        self.tunnel = Some(probe);
+    }
+
+    pub fn set_notice(&mut self, notice: String) {
+        self.notice = Some(notice);
+    }
+
+    fn agents(&self) -> &[AgentSummary] {
+        self.status.as_ref().map_or(&[], |s| s.agents.as_slice())
+    }
+
+    fn selected_agent(&self) -> Option<&AgentSummary> {
+        self.agents().get(self.table.selected()?)
+    }
+
+    pub fn on_key(&mut self, key: KeyEvent) -> Action {
+        if key.kind != KeyEventKind::Press {
+            return Action::None;
+        }
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Action::Quit;
+        }
+        if let Some(id) = self.confirm_kill.take() {
+            return match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => Action::Kill(id),
+                _ => Action::None,
+            };
+        }
+        self.notice = None;
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => Action::Quit,
+            KeyCode::Up => {
+                self.table.select_previous();
+                Action::None
+            }
+            KeyCode::Down => {
+                let last = self.agents().len().saturating_sub(1);
+                self.table.select(Some(self.table.selected().map_or(0, |i| (i + 1).min(last))));
+                Action::None
+            }
+            KeyCode::Char('k') => {
+                self.confirm_kill = self.selected_agent().map(|a| a.id.clone());
+                Action::None
+            }
+            KeyCode::Char('r') => Action::RotatePairingCode,
+            _ => Action::None,
+        }
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(1),      // header
+                Constraint::Length(4),      // tunnel, pool, clients, pairing
+                Constraint::Min(5),         // agents
+                Constraint::Percentage(35), // log
+                Constraint::Length(1),      // keys / notices
+            ])
+            .split(frame.area());
+
+        let uptime = self.status.as_ref().map_or("-".to_string(), |s| format_duration(s.uptime_secs));
+        let header = format!(
+            " Aptove Bridge v{}  ·  {} {}  ·  up {}  ·  pid {}",
+            self.manifest.version, self.manifest.transport, self.manifest.url, uptime, self.manifest.pid
+        );
+        frame.render_widget(Paragraph::new(header).style(Style::default().bg(Color::DarkGray).fg(Color::White)), chunks[0]);
+
+        frame.render_widget(Paragraph::new(self.summary_lines()), chunks[1]);
+
+        let rows: Vec<Row> = self
+            .agents()
+            .iter()
+            .map(|agent| {
+                let (rate_in, rate_out) = self.rates.get(&agent.id).copied().unwrap_or_default();
+                let (state, tone) = match (agent.connected, agent.idle_secs) {
+                    (true, _) => ("connected".to_string(), Color::Green),
+                    (false, Some(idle)) => (format!("idle {}", format_duration(idle)), Color::Yellow),
+                    (false, None) => ("idle".to_string(), Color::Yellow),
+                };
+                Row::new(vec![
+                    Span::raw(agent.id.clone()),
+                    Span::raw(agent.user.clone().unwrap_or_else(|| "-".to_string())),
+                    Span::raw(agent.name.clone()),
+                    Span::styled(state, Style::default().fg(tone)),
+                    Span::raw(agent.connections.to_string()),
+                    Span::raw(format!("{:.1}", rate_in)),
+                    Span::raw(format!("{:.1}", rate_out)),
+                    Span::raw(format!("{}/{}", agent.messages_in, agent.messages_out)),
+                    Span::raw(format!("{} ({})", agent.buffered, format_bytes(agent.buffered_bytes))),
+                    Span::styled(agent.command.clone(), Style::default().fg(Color::DarkGray)),
+                ])
+            })
+            .collect();
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(12),
+                Constraint::Length(10),
+                Constraint::Length(16),
+                Constraint::Length(12),
+                Constraint::Length(5),
+                Constraint::Length(6),
+                Constraint::Length(6),
+                Constraint::Length(13),
+                Constraint::Length(12),
+                Constraint::Min(10),
+            ],
+        )
+        .header(
+            Row::new(["ID", "USER", "NAME", "STATE", "CONNS", "IN/s", "OUT/s", "IN/OUT", "BUFFERED", "COMMAND"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .block(Block::default().borders(Borders::TOP).title(" Agents "));
+        frame.render_stateful_widget(table, chunks[2], &mut self.table);
+
+        let log_block = Block::default().borders(Borders::TOP).title(" Log ");
+        let log_area = log_block.inner(chunks[3]);
+        frame.render_widget(log_block, chunks[3]);
+        render_log_panel(frame, log_area, &self.logs, 0, None);
+
+        let footer = if let Some(ref id) = self.confirm_kill {
+            Span::styled(format!(" Kill agent {}? y/N", id), Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+        } else if let Some(ref error) = self.error {
+            Span::styled(format!(" ⚠ {}", error), Style::default().fg(Color::Red))
+        } else if let Some(ref notice) = self.notice {
+            Span::styled(format!(" {}", notice), Style::default().fg(Color::Green))
+        } else {
+            Span::styled(" ↑/↓ select  k kill agent  r new pairing code  q quit", Style::default().fg(Color::DarkGray))
+        };
+        frame.render_widget(Paragraph::new(Line::from(footer)), chunks[4]);
+    }
+
+    fn summary_lines(&self) -> Vec<Line<'static>> {
+        let label = |text: &str| Span::styled(format!(" {:<10}", text), Style::default().fg(Color::DarkGray));
+        let good = Style::default().fg(Color::Green);
+        let bad = Style::default().fg(Color::Red);
+
+        let tunnel = match (&self.tunnel, &self.status) {
+            (Some(Probe::Reachable(detail)), _) => Span::styled(format!("◉ {}", detail), good),
+            (Some(Probe::Unreachable(detail)), _) => Span::styled(format!("○ {}", detail), bad),
+            (None, Some(_)) => Span::styled("◉ listener answering".to_string(), good),
+            (None, None) if self.error.is_some() => Span::styled("○ listener not answering".to_string(), bad),
+            (None, None) => Span::raw("…"),
+        };
+
+        let Some(ref status) = self.status else {
+            return vec![Line::from(vec![label("Tunnel"), tunnel])];
+        };
+        let mut pool = status.pool.as_ref().map_or("no agent pool".to_string(), |p| {
+            format!(
+                "{}/{} agents ({} connected, {} idle, {} warm){}",
+                p.total,
+                p.max,
+                p.connected,
+                p.idle,
+                p.warm,
+                if p.power_saving { ", power saving" } else { "" }
+            )
+        });
+        if status.shedding {
+            pool.push_str(" — shedding new connections");
+        }
+        let total: usize = status.connections.iter().map(|c| c.count).sum();
+        let clients = status
+            .connections
+            .iter()
+            .map(|c| format!("{} ×{}", c.ip, c.count))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let pairing = match status.pairing {
+            None => Span::raw("disabled"),
+            Some(ref p) if p.used => Span::raw(format!("{} used — press r for a new code", p.code)),
+            Some(ref p) if p.seconds_remaining == 0 => Span::raw(format!("{} expired — press r for a new code", p.code)),
+            Some(ref p) => Span::styled(format!("{}  {}s left  {}", p.code, p.seconds_remaining, p.url), good),
+        };
+        vec![
+            Line::from(vec![label("Tunnel"), tunnel]),
+            Line::from(vec![label("Pool"), Span::raw(pool)]),
+            Line::from(vec![label("Clients"), Span::raw(format!("{} connection(s): {}", total, clients))]),
+            Line::from(vec![label("Pairing"), pairing]),
+        ]
+    }
+}
+
+enum Event {
+    Key(KeyEvent),
+    Log(String),
+    Tunnel(Probe),
+    Redraw,
+}
+
+/// Show the dashboard for the bridge at `client` until `q` is pressed.
+pub async fn run(client: AdminClient, manifest: RuntimeManifest, log_path: PathBuf, interval: Duration) -> Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<Event>();
+
+    // crossterm::event::read() blocks, so keys are read on their own thread.
+    let key_tx = tx.clone();
+    std::thread::spawn(move || loop {
+        let event = match crossterm::event::read() {
+            Ok(crossterm::event::Event::Key(key)) => Event::Key(key),
+            Ok(crossterm::event::Event::Resize(..)) => Event::Redraw,
+            Ok(_) => continue,
+            Err(_) => break,
+        };
+        if key_tx.send(event).is_err() {
+            break;
+        }
+    });
+
+    let log_tx = tx.clone();
+    tokio::spawn(async move {
+        for line in log_file::read_recent(&log_path, LOG_LEVEL, LOG_LINES).unwrap_or_default() {
+            let _ = log_tx.send(Event::Log(line));
+        }
+        let _ = log_file::follow(&log_path, LOG_LEVEL, |line| {
+            let _ = log_tx.send(Event::Log(line.to_string()));
+        })
+        .await;
+    });
+
+    // The listener itself is checked by every refresh; tunnels end elsewhere.
+    if matches!(manifest.transport.as_str(), "cloudflare" | "tailscale-serve") {
+        let tunnel_tx = tx.clone();
+        let url = manifest.url.clone();
+        tokio::spawn(async move {
+            let mut probe = tokio::time::interval(TUNNEL_PROBE_INTERVAL);
+            loop {
+                probe.tick().await;
+                if tunnel_tx.send(Event::Tunnel(crate::status::probe_https(&url).await)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+    terminal.clear()?;
+
+    let mut dashboard = Dashboard::new(manifest);
+    let result = async {
+        let mut refresh = tokio::time::interval(interval);
+        loop {
+            terminal.draw(|frame| dashboard.render(frame))?;
+            tokio::select! {
+                _ = refresh.tick() => dashboard.update(client.status().await),
+                Some(event) = rx.recv() => match event {
+                    Event::Key(key) => match dashboard.on_key(key) {
+                        Action::Quit => break,
+                        Action::Kill(id) => {
+                            match client.kill_agent(&id).await {
+                                Ok(()) => dashboard.set_notice(format!("Killed agent {}", id)),
+                                Err(e) => dashboard.update(Err(e)),
+                            }
+                            refresh.reset_immediately();
+                        }
+                        Action::RotatePairingCode => match client.rotate_pairing_code().await {
+                            Ok(pairing) => {
+                                dashboard.set_notice(format!("New pairing code {}", pairing.code));
+                                refresh.reset_immediately();
+                            }
+                            Err(e) => dashboard.update(Err(e)),
+                        },
+                        Action::None => {}
+                    },
+                    Event::Log(line) => dashboard.push_log(&line),
+                    Event::Tunnel(probe) => dashboard.set_tunnel(probe),
+                    Event::Redraw => {}
+                },
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    }
+    .await;
+
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent(id: &str, messages_in: u64, messages_out: u64) -> AgentSummary {
+        AgentSummary {
+            id: id.into(),
+            user: None,
+            name: "Agent".into(),
+            command: "cat".into(),
+            connected: true,
+            connections: 1,
+            idle_secs: None,
+            buffered: 0,
+            buffered_bytes: 0,
+            messages_in,
+            messages_out,
+        }
+    }
+
+    #[test]
+    fn rates_come_from_the_change_between_snapshots() {
+        let before = [agent("a", 10, 100), agent("gone", 1, 1)];
+        let after = [agent("a", 14, 120), agent("new", 5, 5)];
+        let rates = message_rates(&before, &after, Duration::from_secs(2));
+        assert_eq!(rates.get("a"), Some(&(2.0, 10.0)));
+        assert_eq!(rates.get("new"), None);
+        assert_eq!(rates.len(), 1);
+    }
+
+    #[test]
+    fn log_lines_become_records() {
+        let manifest = RuntimeManifest::new("local", "0.0.0.0", 8765, "wss://10.0.0.5:8765", None);
+        let mut dashboard = Dashboard::new(manifest);
+        dashboard.push_log("2026-10-14 08:01:00.123 WARN  ⚠️  TLS disabled");
+        dashboard.push_log("garbage");
+        assert_eq!(dashboard.logs.len(), 1);
+        assert_eq!(dashboard.logs[0].timestamp, "08:01:00");
+        assert_eq!(dashboard.logs[0].level, "WARN ");
+        assert_eq!(dashboard.logs[0].message, "⚠️  TLS disabled");
+    }
+
+    #[test]
+    fn killing_needs_confirmation() {
+        let manifest = RuntimeManifest::new("local", "0.0.0.0", 8765, "ws://10.0.0.5:8765", None);
+        let client = AdminClient::local(&manifest, "t".into());
+        assert_eq!((client.host.as_str(), client.secure), ("127.0.0.1", false));
+
+        let mut dashboard = Dashboard::new(manifest);
+        let press = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        assert_eq!(dashboard.on_key(press(KeyCode::Char('k'))), Action::None);
+        dashboard.update(Ok(AdminStatus {
+            version: "0".into(),
+            uptime_secs: 5,
+            connections: Vec::new(),
+            shedding: false,
+            pool: None,
+            agents: vec![agent("a", 0, 0), agent("b", 0, 0)],
+            pairing: None,
+        }));
+        dashboard.on_key(press(KeyCode::Down));
+        dashboard.on_key(press(KeyCode::Char('k')));
+        assert_eq!(dashboard.on_key(press(KeyCode::Char('n'))), Action::None);
+        dashboard.on_key(press(KeyCode::Char('k')));
+        assert_eq!(dashboard.on_key(press(KeyCode::Char('y'))), Action::Kill("b".into()));
+        assert_eq!(dashboard.on_key(press(KeyCode::Char('r'))), Action::RotatePairingCode);
+    }
+
+    #[tokio::test]
+    async fn reads_the_admin_status_from_a_bridge() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let admin = std::sync::Arc::new(crate::admin::Admin::new(
+            Some("secret".into()),
+            std::sync::Arc::new(crate::rate_limiter::RateLimiter::new(10, 30)),
+        ));
+        let server = tokio::spawn(async move {
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buffer = vec![0u8; 4096];
+                let n = stream.read(&mut buffer).await.unwrap();
+                let request = String::from_utf8_lossy(&buffer[..n]).into_owned();
+                crate::admin::handle_request(&mut stream, &request, &admin, None, "127.0.0.1").await.unwrap();
+            }
+        });
+
+        let manifest = RuntimeManifest::new("local", "127.0.0.1", port, "ws://127.0.0.1", None);
+        let status = AdminClient::local(&manifest, "secret".into()).status().await.unwrap();
+        assert_eq!(status.version, crate::VERSION);
+        assert!(status.pairing.is_none());
+
+        let error = AdminClient::local(&manifest, "wrong".into()).status().await.unwrap_err();
+        assert!(error.to_string().contains("rejected the admin token"), "{error:#}");
+        server.await.unwrap();
+    }
+}
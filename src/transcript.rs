@@ -0,0 +1,430 @@
+//! Session transcript mirroring to S3-compatible object storage.
+//!
+//! Lines exchanged with pooled agents are queued to a background task that
+//! gzips them per session and uploads one object per chunk:
+//!
+//! ```text
+//! <prefix><YYYY-MM-DD>/<agent_id>/<session>-<unix_ms>.jsonl.gz
+//! ```
+//!
+//! Sessions of a `[[users]]` token are filed under that user's `agent_id`.
+//!
+//! Each line of the object is `{"ts": "...", "dir": "client"|"agent", "msg": "..."}`.
+//! Recording never blocks agent I/O: when the queue is full the line is dropped
+//! with a warning. Finished chunks are uploaded by a separate task, so a slow
+//! endpoint doesn't stop lines from being recorded; when its queue is full
+//! the chunk is dropped with a warning.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::common_config::{TranscriptConfig, UserConfig};
+
+/// Queue depth between agent I/O tasks and the uploader.
+const QUEUE_CAPACITY: usize = 4096;
+
+/// Finished chunks waiting for the upload task.
+const UPLOAD_QUEUE_CAPACITY: usize = 16;
+
+/// Which side produced a transcript line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Client → agent (agent stdin).
+    Client,
+    /// Agent → client (agent stdout).
+    Agent,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::Client => "client",
+            Direction::Agent => "agent",
+        }
+    }
+}
+
+struct Entry {
+    session: String,
+    direction: Direction,
+    at: DateTime<Utc>,
+    line: String,
+}
+
+/// Handle for recording transcript lines. Cheap to clone.
+#[derive(Clone)]
+pub struct TranscriptSink {
+    tx: mpsc::Sender<Entry>,
+}
+
+impl TranscriptSink {
+    /// Start the uploader task. `agent_id` becomes part of every object key,
+    /// except for sessions of a token in `users`, which get the user's.
+    pub fn start(config: TranscriptConfig, agent_id: String, users: &[UserConfig]) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let uploader = S3Uploader::new(config.clone());
+        tokio::spawn(run_uploader(rx, uploader, config, AgentIds::new(agent_id, users)));
+        Self { tx }
+    }
+
+    /// Stable, non-secret session label derived from an auth token.
+    pub fn session_label(token: &str) -> String {
+        hex::encode(&Sha256::digest(token.as_bytes())[..6])
+    }
+
+    /// Queue a line for `session`. Never blocks.
+    pub fn record(&self, session: &str, direction: Direction, line: &str) {
+        let entry = Entry {
+            session: session.to_string(),
+            direction,
+            at: Utc::now(),
+            line: line.to_string(),
+        };
+        if let Err(mpsc::error::TrySendError::Full(_)) = self.tx.try_send(entry) {
+            warn!("Transcript queue full — dropping line");
+        }
+    }
+}
+
+/// The agent ID each session's objects are filed under.
+struct AgentIds {
+    owner: String,
+    by_session: HashMap<String, String>,
+}
+
+impl AgentIds {
+    fn new(owner: String, users: &[UserConfig]) -> Self {
+        let by_session = users
+            .iter()
+            .flat_map(|user| {
+                std::iter::once(&user.auth_token)
+                    .chain(user.devices.iter().map(|d| &d.token))
+                    .map(|token| (TranscriptSink::session_label(token), user.agent_id.clone()))
+            })
+            .collect();
+        Self { owner, by_session }
+    }
+
+    fn of(&self, session: &str) -> &str {
+        self.by_session.get(session).unwrap_or(&self.owner)
+    }
+}
+
+/// A finished chunk and the object key it is uploaded to.
+struct Upload {
+    key: String,
+    body: Vec<u8>,
+}
+
+/// Pending, not yet uploaded lines of one session.
+struct Chunk {
+    started: DateTime<Utc>,
+    encoder: GzEncoder<Vec<u8>>,
+    raw_bytes: usize,
+}
+
+impl Chunk {
+    fn new(started: DateTime<Utc>) -> Self {
+        Self {
+            started,
+            encoder: GzEncoder::new(Vec::new(), Compression::default()),
+            raw_bytes: 0,
+        }
+    }
+
+    fn append(&mut self, entry: &Entry) -> std::io::Result<()> {
+        let record = serde_json::json!({
+            "ts": entry.at.to_rfc3339(),
+            "dir": entry.direction.as_str(),
+            "msg": entry.line,
+        });
+        let mut line = record.to_string();
+        line.push('\n');
+        self.raw_bytes += line.len();
+        self.encoder.write_all(line.as_bytes())
+    }
+}
+
+async fn run_uploader(
+    mut rx: mpsc::Receiver<Entry>,
+    uploader: S3Uploader,
+    config: TranscriptConfig,
+    agent_ids: AgentIds,
+) {
+    let mut chunks: HashMap<String, Chunk> = HashMap::new();
+    let mut interval = tokio::time::interval(Duration::from_secs(config.flush_interval_secs));
+    interval.tick().await;
+    let (uploads, upload_rx) = mpsc::channel(UPLOAD_QUEUE_CAPACITY);
+    let upload_task = tokio::spawn(run_uploads(upload_rx, uploader));
+
+    info!("📝 Mirroring transcripts to {}/{}", config.endpoint.trim_end_matches('/'), config.bucket);
+
+    loop {
+        tokio::select! {
+            entry = rx.recv() => {
+                let Some(entry) = entry else { break };
+                let chunk = chunks
+                    .entry(entry.session.clone())
+                    .or_insert_with(|| Chunk::new(entry.at));
+                if let Err(e) = chunk.append(&entry) {
+                    warn!("Failed to compress transcript line: {}", e);
+                    continue;
+                }
+                if chunk.raw_bytes >= config.max_chunk_bytes {
+                    if let Some(chunk) = chunks.remove(&entry.session) {
+                        queue_upload(&uploads, finish_chunk(&config.prefix, agent_ids.of(&entry.session), &entry.session, chunk));
+                    }
+                }
+            }
+            _ = interval.tick() => {
+                for (session, chunk) in chunks.drain() {
+                    queue_upload(&uploads, finish_chunk(&config.prefix, agent_ids.of(&session), &session, chunk));
+                }
+            }
+        }
+    }
+
+    // All senders dropped (bridge shutting down) — flush what's left and wait
+    // for the uploads to finish.
+    for (session, chunk) in chunks.drain() {
+        if let Some(upload) = finish_chunk(&config.prefix, agent_ids.of(&session), &session, chunk) {
+            let _ = uploads.send(upload).await;
+        }
+    }
+    drop(uploads);
+    let _ = upload_task.await;
+}
+
+/// Upload queued chunks one at a time until the queue is closed.
+async fn run_uploads(mut rx: mpsc::Receiver<Upload>, uploader: S3Uploader) {
+    while let Some(Upload { key, body }) = rx.recv().await {
+        match uploader.put(&key, body).await {
+            Ok(()) => debug!("Uploaded transcript chunk {}", key),
+            Err(e) => warn!("Failed to upload transcript chunk {}: {:#}", key, e),
+        }
+    }
+}
+
+/// Hand a chunk to the upload task without waiting for it.
+fn queue_upload(uploads: &mpsc::Sender<Upload>, upload: Option<Upload>) {
+    if let Some(upload) = upload {
+        if let Err(mpsc::error::TrySendError::Full(upload)) = uploads.try_send(upload) {
+            warn!("Transcript upload queue full — dropping chunk {}", upload.key);
+        }
+    }
+}
+
+fn finish_chunk(prefix: &str, agent_id: &str, session: &str, chunk: Chunk) -> Option<Upload> {
+    let key = object_key(prefix, agent_id, session, chunk.started);
+    match chunk.encoder.finish() {
+        Ok(body) => Some(Upload { key, body }),
+        Err(e) => {
+            warn!("Failed to finish transcript chunk {}: {}", key, e);
+            None
+        }
+    }
+}
+
+/// Object key for a chunk, grouped by the UTC day the chunk started.
+fn object_key(prefix: &str, agent_id: &str, session: &str, started: DateTime<Utc>) -> String {
+    format!(
+        "{}{}/{}/{}-{}.jsonl.gz",
+        prefix,
+        started.format("%Y-%m-%d"),
+        agent_id,
+        session,
+        started.timestamp_millis()
+    )
+}
+
+/// Minimal S3 `PutObject` client using AWS Signature Version 4 and path-style URLs.
+struct S3Uploader {
+    config: TranscriptConfig,
+    http_client: reqwest::Client,
+}
+
+impl S3Uploader {
+    fn new(config: TranscriptConfig) -> Self {
+        Self {
+            config,
+            http_client: crate::proxy::client_builder().build().unwrap_or_default(),
+        }
+    }
+
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<()> {
+        let path = format!(
+            "/{}/{}",
+            uri_encode(&self.config.bucket),
+            key.split('/').map(uri_encode).collect::<Vec<_>>().join("/")
+        );
+        let url = reqwest::Url::parse(&format!("{}{}", self.config.endpoint.trim_end_matches('/'), path))
+            .context("Invalid transcript endpoint URL")?;
+        let host = match (url.host_str(), url.port()) {
+            (Some(h), Some(p)) => format!("{}:{}", h, p),
+            (Some(h), None) => h.to_string(),
+            (None, _) => anyhow::bail!("Transcript endpoint has no host"),
+        };
+
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex::encode(Sha256::digest(&body));
+
+        let canonical_request = format!(
+            "PUT\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            url.path(),
+            host,
+            payload_hash,
+            amz_date,
+            SIGNED_HEADERS,
+            payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = signing_key(&self.config.secret_access_key, &date, &self.config.region, "s3");
+        let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.config.access_key_id, scope, SIGNED_HEADERS, signature
+        );
+
+        let response = self
+            .http_client
+            .put(url)
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("authorization", authorization)
+            .header("content-type", "application/gzip")
+            .body(body)
+            .send()
+            .await
+            .context("Transcript upload request failed")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            anyhow::bail!("S3 returned {}: {}", status, text.chars().take(300).collect::<String>());
+        }
+        Ok(())
+    }
+}
+
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// SigV4 signing key: HMAC chain over date, region, service and "aws4_request".
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let k_date = hmac_sha256(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+    let k_region = hmac_sha256(&k_date, region.as_bytes());
+    let k_service = hmac_sha256(&k_region, service.as_bytes());
+    hmac_sha256(&k_service, b"aws4_request")
+}
+
+/// URI-encode one path segment the way SigV4 expects (RFC 3986 unreserved set kept).
+fn uri_encode(segment: &str) -> String {
+    urlencoding::encode(segment).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn signing_key_matches_aws_example() {
+        // Example from the AWS SigV4 documentation.
+        let key = signing_key("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY", "20150830", "us-east-1", "iam");
+        assert_eq!(hex::encode(key), "c4afb1cc5771d871763a393e44b703571b55cc28424d1a5e86da6ed3c154a4b9");
+    }
+
+    #[test]
+    fn object_key_uses_daily_prefix() {
+        let started = Utc.with_ymd_and_hms(2026, 3, 9, 23, 59, 0).unwrap();
+        let key = object_key("bridge/", "agent-1", "abc123", started);
+        assert_eq!(key, format!("bridge/2026-03-09/agent-1/abc123-{}.jsonl.gz", started.timestamp_millis()));
+    }
+
+    #[test]
+    fn user_sessions_are_filed_under_the_users_agent_id() {
+        let user = UserConfig {
+            name: "alice".into(),
+            agent_id: "alice-id".into(),
+            auth_token: "alice-token-0123456789".into(),
+            max_agents: None,
+            devices: vec![crate::common_config::DeviceConfig { name: "tablet".into(), token: "alice-tablet-0123456789".into(), scopes: vec![] }],
+        };
+        let ids = AgentIds::new("owner-id".into(), &[user]);
+        assert_eq!(ids.of(&TranscriptSink::session_label("alice-token-0123456789")), "alice-id");
+        assert_eq!(ids.of(&TranscriptSink::session_label("alice-tablet-0123456789")), "alice-id");
+        assert_eq!(ids.of(&TranscriptSink::session_label("owner-token")), "owner-id");
+    }
+
+    #[tokio::test]
+    async fn a_stalled_upload_does_not_hold_up_recording() {
+        // An endpoint that accepts connections and never answers.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                held.push(socket);
+            }
+        });
+        let config = TranscriptConfig {
+            endpoint,
+            bucket: "transcripts".into(),
+            region: "us-east-1".into(),
+            access_key_id: "key".into(),
+            secret_access_key: "secret".into(),
+            prefix: String::new(),
+            flush_interval_secs: 3600,
+            max_chunk_bytes: 1,
+        };
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(run_uploader(rx, S3Uploader::new(config.clone()), config, AgentIds::new("agent".into(), &[])));
+
+        // Every line fills a chunk; with uploads awaited inline the second
+        // line would wait for the stalled first upload.
+        let recorded = tokio::time::timeout(Duration::from_secs(5), async {
+            for i in 0..(UPLOAD_QUEUE_CAPACITY * 2) {
+                let entry = Entry { session: "s".into(), direction: Direction::Agent, at: Utc::now(), line: i.to_string() };
+                tx.send(entry).await.unwrap();
+            }
+        })
+        .await;
+        assert!(recorded.is_ok(), "recording waited for an upload");
+    }
+
+    #[test]
+    fn chunk_round_trips_through_gzip() {
+        use std::io::Read;
+        let mut chunk = Chunk::new(Utc::now());
+        let entry = Entry { session: "s".into(), direction: Direction::Agent, at: Utc::now(), line: "{\"id\":1}".into() };
+        chunk.append(&entry).unwrap();
+        let gz = chunk.encoder.finish().unwrap();
+        let mut out = String::new();
+        flate2::read::GzDecoder::new(&gz[..]).read_to_string(&mut out).unwrap();
+        let v: serde_json::Value = serde_json::from_str(out.trim()).unwrap();
+        assert_eq!(v["dir"], "agent");
+        assert_eq!(v["msg"], "{\"id\":1}");
+    }
+}
@@ -0,0 +1,261 @@
+//! `POST /trigger`: start agent work from another system.
+//!
+//! A webhook (GitHub, Zapier, a CI job) posts a prompt; the bridge sends it to
+//! a pooled agent the way [`crate::scheduler`] sends scheduled prompts, and
+//! answers `202 Accepted` with the session it went to as soon as the agent
+//! has one. The phone resumes that session to review the work, and is sent
+//! the start of the reply through the push relay when the agent is done.
+//!
+//! ```json
+//! {"prompt": "Triage the new issue", "agent": "laptop", "timeoutSecs": 900}
+//! ```
+//!
+//! `agent` names a `[[devices]]` entry whose agent gets the prompt; without
+//! it, the caller's own agent does. The request authenticates like
+//! `POST /acp`, and needs the `chat` scope, plus `admin` to prompt another
+//! device's agent.
+
+use anyhow::Result;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::agent_pool::AgentPool;
+use crate::auth_failures::AuthFailures;
+use crate::bridge::{authenticate, create_http_response};
+use crate::device_tokens::{DeviceTokens, Grant, Scope};
+use crate::push::PushRelayClient;
+use crate::scheduler::{summary, AgentRun};
+use crate::session_token::SessionTokens;
+use crate::streamable_http::{client_token, header, read_body};
+
+/// Path of the trigger endpoint.
+pub const PATH: &str = "/trigger";
+
+/// Largest accepted request body.
+const MAX_BODY: usize = 256 * 1024;
+
+/// How long the agent may take to start and create a session before the
+/// request is answered with `504`.
+const OPEN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time a prompt may run when the request doesn't say.
+pub const DEFAULT_TIMEOUT_SECS: u64 = 600;
+
+/// Longest `timeoutSecs` accepted.
+const MAX_TIMEOUT_SECS: u64 = 24 * 60 * 60;
+
+/// Whether the request line targets the trigger endpoint.
+pub fn matches(first_line: &str) -> bool {
+    let mut parts = first_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("").split('?').next().unwrap_or("");
+    method == "POST" && path == PATH
+}
+
+/// The body of a `POST /trigger` request.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct TriggerRequest {
+    prompt: String,
+    #[serde(default)]
+    agent: Option<String>,
+    #[serde(default = "timeout_default")]
+    timeout_secs: u64,
+}
+
+fn timeout_default() -> u64 {
+    DEFAULT_TIMEOUT_SECS
+}
+
+/// An HTTP error answer: status, reason phrase and JSON body.
+type Refusal = (u16, &'static str, String);
+
+fn refusal(status: u16, reason: &'static str, error: &str, message: &str) -> Refusal {
+    (status, reason, serde_json::json!({ "error": error, "message": message }).to_string())
+}
+
+fn parse(body: &[u8]) -> Result<TriggerRequest, Refusal> {
+    let request: TriggerRequest =
+        serde_json::from_slice(body).map_err(|e| refusal(400, "Bad Request", "invalid_request", &e.to_string()))?;
+    if request.prompt.trim().is_empty() {
+        return Err(refusal(400, "Bad Request", "invalid_request", "prompt is empty"));
+    }
+    if !(1..=MAX_TIMEOUT_SECS).contains(&request.timeout_secs) {
+        return Err(refusal(400, "Bad Request", "invalid_request", &format!("timeoutSecs must be 1-{}", MAX_TIMEOUT_SECS)));
+    }
+    Ok(request)
+}
+
+/// The pool key of the agent `grant` may prompt for `agent`.
+fn target(grant: &Grant, agent: Option<&str>, devices: &DeviceTokens) -> Result<String, Refusal> {
+    if !grant.scopes.contains(Scope::Chat) {
+        return Err(refusal(403, "Forbidden", "scope_denied", "This token lacks the 'chat' scope"));
+    }
+    let Some(name) = agent.filter(|name| grant.device.as_deref() != Some(*name)) else {
+        return Ok(grant.pool_key.clone());
+    };
+    if !grant.scopes.contains(Scope::Admin) {
+        return Err(refusal(403, "Forbidden", "scope_denied", "Prompting another device's agent needs the 'admin' scope"));
+    }
+    devices
+        .token_of(name, grant.user.as_deref())
+        .map(str::to_string)
+        .ok_or_else(|| refusal(404, "Not Found", "unknown_agent", &format!("No device is named {:?}", name)))
+}
+
+/// Serve one `POST /trigger` request.
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_request<S>(
+    stream: &mut S,
+    raw: &[u8],
+    request: &str,
+    auth_token: &Option<String>,
+    session_tokens: Option<&SessionTokens>,
+    devices: &DeviceTokens,
+    agent_command: Option<&str>,
+    pool: Option<Arc<RwLock<AgentPool>>>,
+    push_relay: Option<Arc<PushRelayClient>>,
+    auth_failures: Option<&AuthFailures>,
+    client_ip: &str,
+    read_timeout: Duration,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let token = client_token(request).unwrap_or_default();
+    let grant = match auth_token {
+        Some(expected) => authenticate(&token, expected, session_tokens, devices),
+        None => (!token.is_empty()).then(|| Grant::full(&token)),
+    };
+    let Some(grant) = grant else {
+        warn!("🚫 Trigger request rejected: invalid or missing auth token");
+        if let Some(failures) = auth_failures {
+            failures.record(client_ip, PATH);
+        }
+        let resp = create_http_response(401, "Unauthorized", r#"{"error":"unauthorized"}"#);
+        stream.write_all(resp.as_bytes()).await?;
+        return Ok(());
+    };
+
+    let (Some(pool), Some(agent_command)) = (pool, agent_command) else {
+        let resp = create_http_response(
+            503,
+            "Service Unavailable",
+            r#"{"error":"pool_disabled","message":"Triggers require keep-alive agent pooling"}"#,
+        );
+        stream.write_all(resp.as_bytes()).await?;
+        return Ok(());
+    };
+
+    let content_length: usize = header(request, "Content-Length").and_then(|v| v.parse().ok()).unwrap_or(0);
+    if content_length > MAX_BODY {
+        let resp = create_http_response(413, "Payload Too Large", r#"{"error":"payload_too_large"}"#);
+        stream.write_all(resp.as_bytes()).await?;
+        return Ok(());
+    }
+    let Some(body) = read_body(stream, raw, content_length, read_timeout).await? else {
+        warn!("⏱️  Trigger body stalled for {:?}, closing connection", read_timeout);
+        return Ok(());
+    };
+
+    let accepted = parse(&body).and_then(|trigger| Ok((target(&grant, trigger.agent.as_deref(), devices)?, trigger)));
+    let (pool_key, trigger) = match accepted {
+        Ok(accepted) => accepted,
+        Err((status, reason, body)) => {
+            warn!("🚫 Trigger request refused ({}): {}", status, body);
+            stream.write_all(create_http_response(status, reason, &body).as_bytes()).await?;
+            return Ok(());
+        }
+    };
+
+    let run = match tokio::time::timeout(OPEN_TIMEOUT, AgentRun::open(&pool, &pool_key, agent_command)).await {
+        Ok(Ok(run)) => run,
+        Ok(Err(e)) => {
+            warn!("🚫 Trigger failed to reach the agent: {:#}", e);
+            let (status, reason, body) = refusal(502, "Bad Gateway", "agent_unavailable", &format!("{:#}", e));
+            stream.write_all(create_http_response(status, reason, &body).as_bytes()).await?;
+            return Ok(());
+        }
+        Err(_) => {
+            let (status, reason, body) = refusal(504, "Gateway Timeout", "agent_timeout", "The agent didn't start in time");
+            stream.write_all(create_http_response(status, reason, &body).as_bytes()).await?;
+            return Ok(());
+        }
+    };
+    let session_id = run.session_id().to_string();
+    let agent = trigger.agent.clone().or(grant.device.clone());
+    info!("🎯 Trigger accepted for session {}", session_id);
+    let body = serde_json::json!({ "sessionId": session_id, "agent": agent }).to_string();
+    stream.write_all(create_http_response(202, "Accepted", &body).as_bytes()).await?;
+
+    tokio::spawn(async move {
+        let outcome = run.prompt(&trigger.prompt, Duration::from_secs(trigger.timeout_secs)).await;
+        let body = match outcome {
+            Ok(ref outcome) => {
+                info!("🎯 Triggered prompt finished ({}): {}", outcome.stop_reason, summary(&outcome.text));
+                if outcome.text.trim().is_empty() {
+                    "Finished".to_string()
+                } else {
+                    summary(&outcome.text)
+                }
+            }
+            Err(ref e) => {
+                warn!("🎯 Triggered prompt failed: {:#}", e);
+                format!("Failed: {}", e)
+            }
+        };
+        if let Some(relay) = push_relay {
+            if let Err(e) = relay.notify_triggered_prompt(&session_id, &body, outcome.is_ok()).await {
+                warn!("🎯 Push notification for a triggered prompt failed: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common_config::DeviceConfig;
+
+    #[test]
+    fn requests_are_checked_before_reaching_an_agent() {
+        assert!(matches("POST /trigger HTTP/1.1"));
+        assert!(matches("POST /trigger?token=abc HTTP/1.1"));
+        assert!(!matches("GET /trigger HTTP/1.1"));
+        assert!(!matches("POST /triggers HTTP/1.1"));
+
+        let trigger = parse(br#"{"prompt":"Triage issue 12","agent":"laptop","source":"github"}"#).unwrap();
+        assert_eq!(
+            trigger,
+            TriggerRequest { prompt: "Triage issue 12".into(), agent: Some("laptop".into()), timeout_secs: DEFAULT_TIMEOUT_SECS }
+        );
+        for bad in [&br#"{"agent":"laptop"}"#[..], br#"{"prompt":"  "}"#, br#"{"prompt":"x","timeoutSecs":0}"#, b"prompt"] {
+            assert_eq!(parse(bad).unwrap_err().0, 400);
+        }
+    }
+
+    #[test]
+    fn other_devices_agents_need_the_admin_scope() {
+        let devices = DeviceTokens::new(
+            &[
+                DeviceConfig { name: "laptop".into(), token: "laptop-token".into(), scopes: vec![Scope::Chat] },
+                DeviceConfig { name: "ci".into(), token: "ci-token".into(), scopes: vec![Scope::Terminal] },
+            ],
+            None,
+        );
+        let owner = Grant::full("auth-token");
+        assert_eq!(target(&owner, None, &devices).unwrap(), "auth-token");
+        assert_eq!(target(&owner, Some("laptop"), &devices).unwrap(), "laptop-token");
+        assert_eq!(target(&owner, Some("tablet"), &devices).unwrap_err().0, 404);
+
+        let laptop = devices.grant("laptop-token").unwrap();
+        assert_eq!(target(&laptop, Some("laptop"), &devices).unwrap(), "laptop-token");
+        assert_eq!(target(&laptop, Some("ci"), &devices).unwrap_err().0, 403);
+        assert_eq!(target(&devices.grant("ci-token").unwrap(), None, &devices).unwrap_err().0, 403);
+    }
+}
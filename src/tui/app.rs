@@ -18,6 +18,8 @@ use tokio::sync::mpsc;
 use tracing::info;
 
 use crate::common_config::{CommonConfig, PushRelayConfig, TransportConfig};
+use crate::push_providers::PushProvider;
+use crate::pairing::PairingDevice;
 use crate::tui::{
     events::{AppEvent, BridgeEvent},
     screens::{
@@ -87,6 +89,9 @@ pub struct App {
     // Transport selected for this session (set by wizard or auto-detected).
     selected_transport: Option<String>,
 
+    // Transport given with `bridge start --transport`; never asked for.
+    fixed_transport: Option<String>,
+
     // Bridge shutdown signal.
     bridge_shutdown: Option<tokio::sync::oneshot::Sender<()>>,
 
@@ -120,6 +125,9 @@ pub struct App {
     // When true, open the QR popup as soon as the pairing URL is ready.
     // Set after any wizard completion so the user can pair immediately.
     show_qr_on_ready: bool,
+
+    // Pairing requests awaiting y/N; the front one is shown in a popup.
+    pending_approvals: std::collections::VecDeque<(PairingDevice, tokio::sync::oneshot::Sender<bool>)>,
 }
 
 impl App {
@@ -173,6 +181,7 @@ impl App {
             ac_matches: Vec::new(),
             ac_idx: 0,
             selected_transport,
+            fixed_transport: None,
             bridge_shutdown: None,
             event_tx,
             quit: false,
@@ -183,7 +192,20 @@ impl App {
             copy_hint_ticks: 0,
             restart_pending: false,
             show_qr_on_ready: false,
+            pending_approvals: std::collections::VecDeque::new(),
+        }
+    }
+
+    /// Serve `name` (`bridge start --transport`) instead of asking which
+    /// transport to use.
+    pub fn with_transport(mut self, name: &str) -> Self {
+        self.selected_transport = Some(name.to_string());
+        self.fixed_transport = Some(name.to_string());
+        if matches!(self.wizard.as_ref().map(|w| &w.step), Some(WizardStep::TransportPick { .. })) {
+            self.wizard = None;
+            self.screen = Screen::Running;
         }
+        self
     }
 
     /// Start the ratatui terminal, keyboard thread, and run the event loop.
@@ -300,6 +322,12 @@ impl App {
                 if self.copy_hint_ticks > 0 {
                     self.copy_hint_ticks -= 1;
                 }
+                // Drop requests the bridge stopped waiting for (timed out).
+                let before = self.pending_approvals.len();
+                self.pending_approvals.retain(|(_, reply)| !reply.is_closed());
+                if self.pending_approvals.len() != before {
+                    self.show_next_approval();
+                }
             }
             AppEvent::Resize(w, h) => {
                 self.term_area = Rect { x: 0, y: 0, width: w, height: h };
@@ -307,6 +335,12 @@ impl App {
             AppEvent::CloudflareSetupResult(result) => {
                 self.handle_cloudflare_result(result).await;
             }
+            AppEvent::PairingApproval { device, reply } => {
+                self.pending_approvals.push_back((device, reply));
+                if self.pending_approvals.len() == 1 {
+                    self.show_next_approval();
+                }
+            }
             AppEvent::TestPushResult(result) => {
                 match result {
                     Ok(true)  => self.log_push("Push notification sent successfully.".to_string()),
@@ -402,13 +436,11 @@ impl App {
                 }
             }
 
-            Some(WizardStep::AgentCustomInput { ref input }) => {
-                if !input.is_empty() {
-                    let cmd = input.clone();
-                    self.config.agent_command = Some(cmd);
-                    let _ = self.config.save();
-                    self.advance_wizard_after_agent().await;
-                }
+            Some(WizardStep::AgentCustomInput { ref input }) if !input.is_empty() => {
+                let cmd = input.clone();
+                self.config.agent_command = Some(cmd);
+                let _ = self.config.save();
+                self.advance_wizard_after_agent().await;
             }
 
             Some(WizardStep::TransportPick { selected, ts_available, .. }) => {
@@ -448,6 +480,7 @@ impl App {
                     let event_tx = self.event_tx.clone();
                     tokio::spawn(async move {
                         let result = run_cloudflare_setup(api_token, account_id, domain, subdomain).await
+                            .map(Box::new)
                             .map_err(|e| e.to_string());
                         let _ = event_tx.send(AppEvent::CloudflareSetupResult(result)).await;
                     });
@@ -478,10 +511,12 @@ impl App {
                     }
 
                     self.config.push_relay = Some(PushRelayConfig {
+                        provider: PushProvider::Relay,
                         url: push_url,
                         token_url,
                         client_id,
                         client_secret,
+                        ..self.config.push_relay.take().unwrap_or_default()
                     });
                     let _ = self.config.save();
                     self.advance_past_push();
@@ -494,7 +529,11 @@ impl App {
 
     async fn advance_wizard_after_agent(&mut self) {
         let enabled_count = self.config.enabled_transports().len();
-        if enabled_count == 1 {
+        if let Some(name) = self.fixed_transport.clone() {
+            // Chosen on the command line.
+            self.selected_transport = Some(name);
+            self.advance_wizard_after_transport().await;
+        } else if enabled_count == 1 {
             // Exactly one transport configured — use it automatically.
             let name = self.config.enabled_transports()[0].0.to_string();
             self.selected_transport = Some(name);
@@ -593,6 +632,27 @@ impl App {
         self.needs_clear = true;
     }
 
+    /// Show the oldest pending pairing request, or close the approval popup
+    /// when none are left.
+    fn show_next_approval(&mut self) {
+        if let Some((device, _)) = self.pending_approvals.front() {
+            self.popup = Some(PopupKind::PairingApproval { device: device.to_string() });
+            self.needs_clear = true;
+        } else if matches!(self.popup, Some(PopupKind::PairingApproval { .. })) {
+            self.close_popup();
+        }
+    }
+
+    /// Answer the pairing request currently shown.
+    fn answer_approval(&mut self, approved: bool) {
+        if let Some((device, reply)) = self.pending_approvals.pop_front() {
+            let verdict = if approved { "approved" } else { "declined" };
+            self.log_push(format!("Pairing {}: {}", verdict, device));
+            let _ = reply.send(approved);
+        }
+        self.show_next_approval();
+    }
+
     fn finish_wizard(&mut self) {
         self.wizard = None;
         self.screen = Screen::Running;
@@ -620,10 +680,10 @@ impl App {
         }
     }
 
-    async fn handle_cloudflare_result(&mut self, result: Result<TransportConfig, String>) {
+    async fn handle_cloudflare_result(&mut self, result: Result<Box<TransportConfig>, String>) {
         match result {
             Ok(tc) => {
-                self.config.transports.insert("cloudflare".to_string(), tc);
+                self.config.transports.insert("cloudflare".to_string(), *tc);
                 let _ = self.config.save();
                 self.selected_transport = Some("cloudflare".to_string());
                 self.advance_after_transport_pick().await;
@@ -937,7 +997,7 @@ impl App {
             use crate::push::PushRelayClient;
             let client = PushRelayClient::new(push_cfg.url.clone(), String::new())
                 .with_jwt_credentials(push_cfg.token_url.clone(), push_cfg.client_id.clone(), push_cfg.client_secret.clone());
-            let result = client.notify("test").await.map_err(|e| e.to_string());
+            let result = client.notify("test", None).await.map_err(|e| e.to_string());
             let _ = event_tx.send(AppEvent::TestPushResult(result)).await;
         });
         self.log_push("Sending test push notification...".to_string());
@@ -945,6 +1005,11 @@ impl App {
 
     async fn handle_popup_key(&mut self, key: crossterm::event::KeyEvent) {
         match self.popup.clone() {
+            Some(PopupKind::PairingApproval { .. }) => match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => self.answer_approval(true),
+                KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc | KeyCode::Enter => self.answer_approval(false),
+                _ => {}
+            },
             Some(PopupKind::LogLevel { selected }) => {
                 match key.code {
                     KeyCode::Up => {
@@ -1061,10 +1126,12 @@ impl App {
                             });
                         } else {
                             self.config.push_relay = Some(PushRelayConfig {
+                                provider: PushProvider::Relay,
                                 url: "https://push.aptove.com".to_string(),
                                 token_url: "https://token.aptove.com".to_string(),
                                 client_id,
                                 client_secret,
+                                ..self.config.push_relay.take().unwrap_or_default()
                             });
                             let _ = self.config.save();
                             self.log_push("Aptove push service configured.".to_string());
@@ -1118,10 +1185,12 @@ impl App {
                             });
                         } else {
                             self.config.push_relay = Some(PushRelayConfig {
+                                provider: PushProvider::Relay,
                                 url: push_url,
                                 token_url,
                                 client_id,
                                 client_secret,
+                                ..self.config.push_relay.take().unwrap_or_default()
                             });
                             let _ = self.config.save();
                             self.log_push("Self-managed push service configured.".to_string());
@@ -1178,12 +1247,20 @@ impl App {
             BridgeEvent::PairingCompleted => {
                 self.log_push("Pairing completed.".to_string());
             }
-            BridgeEvent::PairingUrlReady { url, transport } => {
+            BridgeEvent::PairingUrlReady { url, deep_link, transport } => {
                 info!("Pairing URL ready for transport: {}", transport);
                 self.pairing_url = Some(url.clone());
-                // Pre-render QR string.
-                if let Ok(qr) = crate::qr::render_qr_code(&url) {
-                    self.qr_string = Some(qr);
+                // Pre-render QR string, with the code for NFC tags (see
+                // `bridge pair --nfc-export`) and the deep link below it for
+                // sending to the phone as a tappable link.
+                let qr = if self.config.deep_link.qr {
+                    crate::qr::render_qr_code(&deep_link)
+                } else {
+                    crate::qr::render_pairing_qr(&url).map(|(qr, _)| qr)
+                };
+                if let Ok(qr) = qr {
+                    let code = crate::pairing::code_of(&url).map(|c| format!("  🔢 Code {}\n", c)).unwrap_or_default();
+                    self.qr_string = Some(format!("{}\n{}  📲 {}\n", qr, code, deep_link));
                 }
                 // Auto-open QR popup after wizard completion so the user can
                 // pair their mobile client immediately.
@@ -1206,6 +1283,27 @@ impl App {
                 self.push_up = true;
                 self.log_push("Push token registered.".to_string());
             }
+            BridgeEvent::AutoLocked { restart } => {
+                self.log_push("Locked after inactivity: device tokens replaced, pair again.".to_string());
+                // Pick up the new tokens so later saves don't restore the old ones.
+                match CommonConfig::load() {
+                    Ok(config) => self.config = config,
+                    Err(e) => self.log_push(format!("Failed to reload config: {}", e)),
+                }
+                self.show_qr_on_ready = true;
+                if restart {
+                    self.restart_pending = true;
+                }
+            }
+            BridgeEvent::AuthTokenRotated => {
+                self.log_push("Auth token replaced from the admin endpoint, pair again.".to_string());
+                match CommonConfig::load() {
+                    Ok(config) => self.config = config,
+                    Err(e) => self.log_push(format!("Failed to reload config: {}", e)),
+                }
+                self.show_qr_on_ready = true;
+                self.restart_pending = true;
+            }
             BridgeEvent::BridgeStopped => {
                 self.transport_up = false;
                 self.log_push("Bridge stopped.".to_string());
@@ -1246,9 +1344,9 @@ async fn run_cloudflare_setup(
     domain: String,
     subdomain: String,
 ) -> anyhow::Result<TransportConfig> {
-    use crate::cloudflare::{write_credentials_file, write_cloudflared_config_at};
+    use crate::cloudflare::{write_credentials_file, write_cloudflared_ingress_at};
 
-    let client = CloudflareClient::new(api_token, account_id.clone());
+    let client = CloudflareClient::new(api_token.clone(), account_id.clone());
     let hostname = format!("{}.{}", subdomain, domain);
     let tunnel_name = format!("{}-tunnel", domain.split('.').next().unwrap_or("bridge"));
 
@@ -1265,16 +1363,16 @@ async fn run_cloudflare_setup(
     let service_token = client.create_service_token(&hostname).await?;
 
     info!("Configuring tunnel ingress...");
-    client.configure_tunnel_ingress(&tunnel.id, &hostname, 8080).await?;
+    let ingress = client.configure_tunnel_ingress(&tunnel.id, &hostname, 8080).await?;
 
     let credentials_path = write_credentials_file(&account_id, &tunnel.id, &tunnel.secret)?;
     let config_dir = crate::common_config::CommonConfig::config_dir();
     let per_project_config = config_dir.join("cloudflared.yml");
-    write_cloudflared_config_at(&tunnel.id, &credentials_path, &hostname, 8080, &per_project_config)?;
+    write_cloudflared_ingress_at(&tunnel.id, &credentials_path, &ingress, &per_project_config)?;
 
     info!("Cloudflare setup complete for {}", hostname);
 
-    Ok(TransportConfig {
+    let mut transport = TransportConfig {
         enabled: true,
         port: Some(8080),
         tls: None,
@@ -1286,7 +1384,11 @@ async fn run_cloudflare_setup(
         client_secret: Some(service_token.client_secret),
         domain: Some(domain),
         subdomain: Some(subdomain),
-    })
+        api_token: Some(api_token),
+        ..Default::default()
+    };
+    transport.stamp_service_token_issued();
+    Ok(transport)
 }
 
 /// Returns 0 = No Push, 1 = Aptove, 2 = Self Managed.
@@ -1,5 +1,6 @@
 use crossterm::event::{KeyEvent, MouseEvent};
 use crate::common_config::TransportConfig;
+use crate::pairing::PairingDevice;
 
 /// A single log record captured from the tracing subscriber.
 #[derive(Debug, Clone)]
@@ -17,11 +18,17 @@ pub enum BridgeEvent {
     ClientConnected { session_id: String },
     ClientDisconnected { session_id: String },
     PairingCompleted,
-    PairingUrlReady { url: String, transport: String },
+    PairingUrlReady { url: String, deep_link: String, transport: String },
     AgentSpawned { command: String },
     AgentExited,
     TlsFingerprint { fingerprint: String },
     PushRegistered,
+    /// `[auto_lock]` replaced the device tokens in `common.toml`; with
+    /// `restart` the bridge is stopping to serve the new ones.
+    AutoLocked { restart: bool },
+    /// `POST /admin/token/rotate` replaced the auth token in `common.toml`;
+    /// the bridge is stopping to serve the new one.
+    AuthTokenRotated,
     BridgeStopped,
     BridgeError { message: String },
 }
@@ -36,9 +43,11 @@ pub enum AppEvent {
     Tick,
     Resize(u16, u16),
     /// Result of an async Cloudflare setup triggered from the wizard.
-    CloudflareSetupResult(Result<TransportConfig, String>),
+    CloudflareSetupResult(Result<Box<TransportConfig>, String>),
     /// Result of an async test-push triggered from the running screen.
     TestPushResult(Result<bool, String>),
+    /// A device with a valid pairing code is waiting for approval.
+    PairingApproval { device: PairingDevice, reply: tokio::sync::oneshot::Sender<bool> },
 }
 
 /// Commands sent from the TUI to the bridge runner.
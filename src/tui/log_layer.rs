@@ -75,9 +75,10 @@ impl<S: Subscriber> Layer<S> for TuiLogLayer {
     }
 }
 
+/// Collects an event's `message` field.
 #[derive(Default)]
-struct MessageVisitor {
-    message: String,
+pub(crate) struct MessageVisitor {
+    pub(crate) message: String,
 }
 
 impl tracing::field::Visit for MessageVisitor {
@@ -37,6 +37,8 @@ pub enum PopupKind {
     LogLevel { selected: usize },
     /// Push notifications configuration (multi-step).
     PushConfig { step: PushPopupStep },
+    /// Approve or decline the device described by `device`.
+    PairingApproval { device: String },
 }
 
 const HELP_TEXT: &str = "\
@@ -72,6 +74,13 @@ pub fn render_popup(
         PopupKind::PushConfig { step } => {
             render_push_popup(frame, step);
         }
+        PopupKind::PairingApproval { device } => {
+            let text = format!(
+                "\n  {} wants to pair.\n\n  Approving sends it this bridge's auth token.\n\n  Approve? [y/N]",
+                device
+            );
+            render_text_popup(frame, frame.area(), "Pairing request", &text);
+        }
     }
 }
 
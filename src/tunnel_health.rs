@@ -0,0 +1,239 @@
+//! Cloudflare tunnel health, as the Cloudflare API sees it.
+//!
+//! cloudflared can stay alive while holding no connection to Cloudflare's
+//! edge, e.g. after a network change it didn't recover from. The bridge then
+//! looks fine locally but can't be reached through the tunnel. When the
+//! cloudflare transport has an `api_token`, `run_bridge` starts [`watch`],
+//! which asks the API for the tunnel's connections every [`CHECK_INTERVAL`].
+//! The latest answer is served at `GET /healthz` (see [`healthz`]), and
+//! after [`RESTART_AFTER`] checks in a row without an active connection,
+//! cloudflared is restarted. `bridge status` runs the same [`check`].
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::cloudflare::{CloudflareClient, TunnelClient};
+use crate::cloudflared_runner::CloudflaredRunner;
+use crate::common_config::TransportConfig;
+
+/// How often a running bridge asks the API about its tunnel.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Checks in a row without an edge connection before cloudflared is restarted.
+pub const RESTART_AFTER: u32 = 3;
+
+/// How long a restarted cloudflared may take to connect.
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The outcome of one check.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TunnelHealth {
+    pub tunnel_id: String,
+    /// Active edge connections, pending reconnects excluded
+    pub connections: usize,
+    /// Data centers of the active connections
+    pub colos: Vec<String>,
+    /// Why the API couldn't be asked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub checked_at: DateTime<Utc>,
+}
+
+impl TunnelHealth {
+    /// Summarize the API's list of cloudflared instances on the tunnel.
+    pub fn from_clients(tunnel_id: &str, clients: &[TunnelClient]) -> Self {
+        let mut colos: Vec<String> = clients
+            .iter()
+            .flat_map(|c| &c.conns)
+            .filter(|conn| !conn.is_pending_reconnect)
+            .map(|conn| conn.colo_name.clone())
+            .collect();
+        let connections = colos.len();
+        colos.sort();
+        colos.dedup();
+        Self { tunnel_id: tunnel_id.to_string(), connections, colos, error: None, checked_at: Utc::now() }
+    }
+
+    /// A check that couldn't reach the API.
+    pub fn failed(tunnel_id: &str, error: &anyhow::Error) -> Self {
+        Self {
+            tunnel_id: tunnel_id.to_string(),
+            connections: 0,
+            colos: Vec::new(),
+            error: Some(format!("{:#}", error)),
+            checked_at: Utc::now(),
+        }
+    }
+
+    /// The API answered and reported no active connection.
+    pub fn is_down(&self) -> bool {
+        self.error.is_none() && self.connections == 0
+    }
+
+    /// One line for logs and `bridge status`.
+    pub fn summary(&self) -> String {
+        match (&self.error, self.connections) {
+            (Some(error), _) => format!("Cloudflare API check failed: {}", error),
+            (None, 0) => "Cloudflare reports no edge connections".to_string(),
+            (None, n) => format!("{} edge connection(s) ({})", n, self.colos.join(", ")),
+        }
+    }
+}
+
+/// The API client and tunnel ID for `transport`, when it has the
+/// credentials to ask.
+pub fn client_for(transport: &TransportConfig) -> Option<(CloudflareClient, String)> {
+    let non_empty = |v: &Option<String>| v.clone().filter(|s| !s.is_empty());
+    let api_token = non_empty(&transport.api_token)?;
+    let account_id = non_empty(&transport.account_id)?;
+    let tunnel_id = non_empty(&transport.tunnel_id)?;
+    Some((CloudflareClient::new(api_token, account_id), tunnel_id))
+}
+
+/// Ask the API about `tunnel_id` once.
+pub async fn check(client: &CloudflareClient, tunnel_id: &str) -> TunnelHealth {
+    match client.tunnel_connections(tunnel_id).await {
+        Ok(clients) => TunnelHealth::from_clients(tunnel_id, &clients),
+        Err(e) => TunnelHealth::failed(tunnel_id, &e.into()),
+    }
+}
+
+/// The latest check, shared with the `/healthz` handler.
+#[derive(Debug, Default)]
+pub struct TunnelMonitor {
+    latest: Mutex<Option<TunnelHealth>>,
+}
+
+impl TunnelMonitor {
+    pub fn latest(&self) -> Option<TunnelHealth> {
+        self.latest.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn set(&self, health: TunnelHealth) {
+        *self.latest.lock().unwrap_or_else(|e| e.into_inner()) = Some(health);
+    }
+}
+
+/// Status code and JSON body of `GET /healthz`: `200` with
+/// `{"status": "ok"}`, or `503` with `"degraded"` while the tunnel has no
+/// edge connection. `tunnel` is the latest check, when there is one.
+pub fn healthz(monitor: Option<&TunnelMonitor>) -> (u16, String) {
+    let tunnel = monitor.and_then(TunnelMonitor::latest);
+    let down = tunnel.as_ref().is_some_and(TunnelHealth::is_down);
+    let mut body = serde_json::json!({ "status": if down { "degraded" } else { "ok" } });
+    if let Some(tunnel) = tunnel {
+        body["tunnel"] = serde_json::to_value(tunnel).unwrap_or_default();
+    }
+    (if down { 503 } else { 200 }, body.to_string())
+}
+
+/// Check the tunnel every [`CHECK_INTERVAL`], recording each answer in
+/// `monitor` and restarting `runner` after [`RESTART_AFTER`] checks in a row
+/// without an edge connection. Never resolves.
+pub async fn watch(
+    client: CloudflareClient,
+    tunnel_id: String,
+    monitor: Arc<TunnelMonitor>,
+    runner: Option<Arc<Mutex<CloudflaredRunner>>>,
+) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    let mut down_checks = 0;
+    loop {
+        interval.tick().await;
+        let health = check(&client, &tunnel_id).await;
+        if let Some(ref error) = health.error {
+            warn!("Cloudflare tunnel health check failed: {}", error);
+        }
+        down_checks = if health.is_down() { down_checks + 1 } else { 0 };
+        monitor.set(health);
+        if down_checks < RESTART_AFTER {
+            continue;
+        }
+        down_checks = 0;
+        let Some(ref runner) = runner else {
+            warn!("☁️  Cloudflare reports no edge connections for tunnel {}", tunnel_id);
+            continue;
+        };
+        let runner = Arc::clone(runner);
+        let restarted = tokio::task::spawn_blocking(move || {
+            let mut runner = runner.lock().unwrap_or_else(|e| e.into_inner());
+            if runner.is_running() {
+                warn!("☁️  Cloudflare reports no edge connections although cloudflared is running — restarting it");
+            } else {
+                warn!("☁️  cloudflared exited — restarting it");
+            }
+            runner.restart(READY_TIMEOUT)
+        })
+        .await;
+        match restarted {
+            Ok(Ok(())) => info!("☁️  cloudflared restarted and connected"),
+            Ok(Err(e)) => warn!("Failed to restart cloudflared: {:#}", e),
+            Err(e) => warn!("cloudflared restart task failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cloudflare::TunnelConnection;
+
+    fn conn(colo: &str, pending: bool) -> TunnelConnection {
+        TunnelConnection { id: format!("{colo}-conn"), colo_name: colo.into(), is_pending_reconnect: pending }
+    }
+
+    #[test]
+    fn pending_reconnects_are_not_counted() {
+        let clients = vec![TunnelClient {
+            id: "cloudflared".into(),
+            version: "2026.9.0".into(),
+            conns: vec![conn("FRA", false), conn("AMS", false), conn("FRA", false), conn("LHR", true)],
+        }];
+        let health = TunnelHealth::from_clients("tunnel", &clients);
+        assert_eq!(health.connections, 3);
+        assert_eq!(health.colos, vec!["AMS", "FRA"]);
+        assert!(!health.is_down());
+        assert_eq!(health.summary(), "3 edge connection(s) (AMS, FRA)");
+
+        let pending = vec![TunnelClient { conns: vec![conn("LHR", true)], ..clients[0].clone() }];
+        assert!(TunnelHealth::from_clients("tunnel", &pending).is_down());
+        assert!(TunnelHealth::from_clients("tunnel", &[]).is_down());
+
+        let failed = TunnelHealth::failed("tunnel", &anyhow::anyhow!("timed out"));
+        assert!(!failed.is_down(), "an unreachable API says nothing about the tunnel");
+        assert!(failed.summary().contains("timed out"));
+    }
+
+    #[test]
+    fn healthz_degrades_while_the_tunnel_is_down() {
+        assert_eq!(healthz(None), (200, r#"{"status":"ok"}"#.to_string()));
+
+        let monitor = TunnelMonitor::default();
+        assert_eq!(healthz(Some(&monitor)).0, 200, "no check yet");
+
+        monitor.set(TunnelHealth::from_clients("tunnel", &[]));
+        let (status, body) = healthz(Some(&monitor));
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(status, 503);
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["tunnel"]["tunnelId"], "tunnel");
+        assert_eq!(body["tunnel"]["connections"], 0);
+    }
+
+    #[test]
+    fn checks_need_an_api_token_and_a_tunnel() {
+        let transport = TransportConfig {
+            enabled: true,
+            account_id: Some("account".into()),
+            tunnel_id: Some("tunnel".into()),
+            ..TransportConfig::default()
+        };
+        assert!(client_for(&transport).is_none());
+        let transport = TransportConfig { api_token: Some("api".into()), ..transport };
+        assert_eq!(client_for(&transport).map(|(_, id)| id).as_deref(), Some("tunnel"));
+    }
+}
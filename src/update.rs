@@ -0,0 +1,305 @@
+//! Release checks and `bridge self-update`.
+//!
+//! Releases are published to GitHub by `cargo-dist`: one archive per target
+//! (`bridge-<target>.tar.xz`, `.zip` on Windows) plus a `<archive>.sha256`
+//! checksum file. The update downloads both, refuses to install unless the
+//! checksum matches, and replaces the running executable with a rename.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// GitHub API endpoint for the latest published release.
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/aptove/bridge/releases/latest";
+
+/// A published release as returned by the GitHub releases API.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release {
+    /// Git tag, e.g. `v0.2.5`.
+    pub tag_name: String,
+    /// Release notes (markdown).
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub assets: Vec<ReleaseAsset>,
+}
+
+/// A downloadable file attached to a release.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReleaseAsset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+impl Release {
+    /// Version without the leading `v`.
+    pub fn version(&self) -> &str {
+        self.tag_name.trim_start_matches('v')
+    }
+
+    /// Whether the release notes mention a security fix.
+    pub fn mentions_security_fix(&self) -> bool {
+        let notes = self.body.to_ascii_lowercase();
+        notes.contains("security") || notes.contains("cve-")
+    }
+
+    fn asset(&self, name: &str) -> Option<&ReleaseAsset> {
+        self.assets.iter().find(|a| a.name == name)
+    }
+}
+
+/// Result of `bridge self-update`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum UpdateOutcome {
+    /// The running binary is already the latest release.
+    UpToDate { version: String },
+    /// A newer release exists but `check_only` was set.
+    Available { version: String },
+    /// The binary at `path` was replaced with `version`.
+    Installed { version: String, path: PathBuf },
+}
+
+fn http_client() -> Result<reqwest::Client> {
+    crate::proxy::client_builder()
+        .user_agent(format!("aptove-bridge/{}", crate::VERSION))
+        .timeout(std::time::Duration::from_secs(60))
+        .build()
+        .context("Failed to build HTTP client")
+}
+
+/// Fetch metadata for the latest published release.
+pub async fn fetch_latest_release() -> Result<Release> {
+    let response = http_client()?
+        .get(LATEST_RELEASE_URL)
+        .header("Accept", "application/vnd.github+json")
+        .send()
+        .await
+        .context("Failed to reach the GitHub releases API")?;
+    if !response.status().is_success() {
+        anyhow::bail!("GitHub releases API returned {}", response.status());
+    }
+    response.json().await.context("Failed to parse release metadata")
+}
+
+/// Compare dotted numeric versions (`0.2.10` > `0.2.9`). Pre-release suffixes
+/// are ignored, so `0.3.0-rc.1` compares equal to `0.3.0`.
+pub fn is_newer(candidate: &str, current: &str) -> bool {
+    fn parts(v: &str) -> Vec<u64> {
+        v.trim_start_matches('v')
+            .split(['-', '+'])
+            .next()
+            .unwrap_or("")
+            .split('.')
+            .map(|p| p.parse().unwrap_or(0))
+            .collect()
+    }
+    let (a, b) = (parts(candidate), parts(current));
+    for i in 0..a.len().max(b.len()) {
+        let (x, y) = (a.get(i).copied().unwrap_or(0), b.get(i).copied().unwrap_or(0));
+        if x != y {
+            return x > y;
+        }
+    }
+    false
+}
+
+/// Rust target triple of this build, matching the `cargo-dist` archive names.
+pub fn target_triple() -> Option<&'static str> {
+    if cfg!(all(target_os = "macos", target_arch = "aarch64")) {
+        Some("aarch64-apple-darwin")
+    } else if cfg!(all(target_os = "macos", target_arch = "x86_64")) {
+        Some("x86_64-apple-darwin")
+    } else if cfg!(all(target_os = "linux", target_arch = "aarch64")) {
+        Some("aarch64-unknown-linux-gnu")
+    } else if cfg!(all(target_os = "linux", target_arch = "x86_64")) {
+        Some("x86_64-unknown-linux-gnu")
+    } else if cfg!(all(target_os = "windows", target_arch = "x86_64")) {
+        Some("x86_64-pc-windows-msvc")
+    } else {
+        None
+    }
+}
+
+fn archive_name(target: &str) -> String {
+    if target.contains("windows") {
+        format!("bridge-{}.zip", target)
+    } else {
+        format!("bridge-{}.tar.xz", target)
+    }
+}
+
+/// Extract the hex digest from a `sha256sum`-style line (`<hex>  <file>`).
+fn parse_checksum(contents: &str) -> Option<String> {
+    let digest = contents.split_whitespace().next()?.to_ascii_lowercase();
+    (digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit())).then_some(digest)
+}
+
+async fn download(client: &reqwest::Client, url: &str) -> Result<Vec<u8>> {
+    let response = client.get(url).send().await.with_context(|| format!("Failed to download {}", url))?;
+    if !response.status().is_success() {
+        anyhow::bail!("Download of {} returned {}", url, response.status());
+    }
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// Check for a newer release and, unless `check_only`, install it over the
+/// running executable.
+pub async fn self_update(check_only: bool) -> Result<UpdateOutcome> {
+    let release = fetch_latest_release().await?;
+    let version = release.version().to_string();
+    if !is_newer(&version, crate::VERSION) {
+        return Ok(UpdateOutcome::UpToDate { version: crate::VERSION.to_string() });
+    }
+    if check_only {
+        return Ok(UpdateOutcome::Available { version });
+    }
+
+    let target = target_triple().context("No prebuilt release for this platform")?;
+    let archive = archive_name(target);
+    let archive_asset = release
+        .asset(&archive)
+        .with_context(|| format!("Release {} has no {}", release.tag_name, archive))?;
+    let checksum_asset = release
+        .asset(&format!("{}.sha256", archive))
+        .with_context(|| format!("Release {} has no checksum for {}", release.tag_name, archive))?;
+
+    let client = http_client()?;
+    let expected = parse_checksum(&String::from_utf8_lossy(&download(&client, &checksum_asset.browser_download_url).await?))
+        .context("Malformed checksum file")?;
+    let bytes = download(&client, &archive_asset.browser_download_url).await?;
+    let actual = hex::encode(Sha256::digest(&bytes));
+    if actual != expected {
+        anyhow::bail!("Checksum mismatch for {}: expected {}, got {}", archive, expected, actual);
+    }
+    info!("✅ Verified {} (sha256 {})", archive, actual);
+
+    let current_exe = std::env::current_exe()
+        .context("Cannot locate the running executable")?
+        .canonicalize()
+        .context("Cannot resolve the running executable")?;
+    let install_dir = current_exe.parent().context("Executable has no parent directory")?;
+
+    // Unpack next to the executable so the final rename stays on one filesystem.
+    let staging = install_dir.join(format!(".bridge-update-{}", std::process::id()));
+    std::fs::create_dir_all(&staging).context("Failed to create staging directory")?;
+    let result = install_from_archive(&bytes, &archive, &staging, &current_exe);
+    let _ = std::fs::remove_dir_all(&staging);
+    result?;
+
+    Ok(UpdateOutcome::Installed { version, path: current_exe })
+}
+
+fn install_from_archive(bytes: &[u8], archive: &str, staging: &Path, current_exe: &Path) -> Result<()> {
+    let archive_path = staging.join(archive);
+    std::fs::write(&archive_path, bytes).context("Failed to write downloaded archive")?;
+
+    // `tar` ships with macOS, Linux and Windows 10+ (bsdtar, which also reads zip).
+    let status = std::process::Command::new("tar")
+        .arg("-xf")
+        .arg(&archive_path)
+        .arg("-C")
+        .arg(staging)
+        .status()
+        .context("Failed to run tar")?;
+    if !status.success() {
+        anyhow::bail!("tar failed to extract {}", archive);
+    }
+
+    let binary_name = if cfg!(windows) { "bridge.exe" } else { "bridge" };
+    let new_binary = find_file(staging, binary_name)?
+        .with_context(|| format!("{} not found in {}", binary_name, archive))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&new_binary, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    // Windows can't overwrite a running executable, but it can rename it.
+    #[cfg(windows)]
+    {
+        let old = current_exe.with_extension("old.exe");
+        let _ = std::fs::remove_file(&old);
+        std::fs::rename(current_exe, &old).context("Failed to move the running executable aside")?;
+    }
+
+    std::fs::rename(&new_binary, current_exe)
+        .with_context(|| format!("Failed to replace {}", current_exe.display()))?;
+    Ok(())
+}
+
+fn find_file(dir: &Path, name: &str) -> Result<Option<PathBuf>> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if let Some(found) = find_file(&path, name)? {
+                return Ok(Some(found));
+            }
+        } else if path.file_name().is_some_and(|n| n == name) {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// Startup check (opt-in via `check_for_updates`): log when a newer release
+/// exists. Failures are logged at debug level only.
+pub async fn log_if_outdated() {
+    let release = match fetch_latest_release().await {
+        Ok(r) => r,
+        Err(e) => {
+            tracing::debug!("Update check failed: {:#}", e);
+            return;
+        }
+    };
+    if !is_newer(release.version(), crate::VERSION) {
+        return;
+    }
+    if release.mentions_security_fix() {
+        warn!(
+            "🛡️  Bridge {} is available with security fixes (running {}). Run `bridge self-update`.",
+            release.version(),
+            crate::VERSION
+        );
+    } else {
+        info!(
+            "⬆️  Bridge {} is available (running {}). Run `bridge self-update`.",
+            release.version(),
+            crate::VERSION
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_comparison() {
+        assert!(is_newer("0.2.10", "0.2.9"));
+        assert!(is_newer("v1.0.0", "0.9.9"));
+        assert!(is_newer("0.3", "0.2.4"));
+        assert!(!is_newer("0.2.4", "0.2.4"));
+        assert!(!is_newer("0.2.3", "0.2.4"));
+        assert!(!is_newer("0.2.4-rc.1", "0.2.4"));
+    }
+
+    #[test]
+    fn checksum_file_parsing() {
+        let hex = "a".repeat(64);
+        assert_eq!(parse_checksum(&format!("{}  bridge-x86_64-unknown-linux-gnu.tar.xz\n", hex)), Some(hex.clone()));
+        assert_eq!(parse_checksum(&hex.to_uppercase()), Some(hex));
+        assert_eq!(parse_checksum("not-a-digest  file"), None);
+    }
+
+    #[test]
+    fn security_notes_detection() {
+        let release = |body: &str| Release { tag_name: "v1.0.0".into(), body: body.into(), assets: vec![] };
+        assert!(release("Fixes CVE-2026-1234").mentions_security_fix());
+        assert!(release("## Security\n- tighten pairing").mentions_security_fix());
+        assert!(!release("New QR layout").mentions_security_fix());
+        assert_eq!(archive_name("x86_64-pc-windows-msvc"), "bridge-x86_64-pc-windows-msvc.zip");
+    }
+}
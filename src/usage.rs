@@ -0,0 +1,447 @@
+//! Daily usage per device (`[usage]`), for an agent shared with family or
+//! teammates.
+//!
+//! Pooled WebSocket connections count, under their device's name (see
+//! [`device_name`]), the requests the client sends the agent, the agent's
+//! responses, and the bytes of messages in both directions. Counters are kept
+//! per local date in `usage.json` in the config directory, written every
+//! [`FLUSH_INTERVAL`] and when the bridge stops, and dropped after
+//! `retention_days`. `bridge usage` reports them.
+//!
+//! The bridge never sees the agent's token counts; the report estimates them
+//! from the bytes at [`BYTES_PER_TOKEN`].
+//!
+//! Once a device reaches its daily budget a warning is logged, once per day.
+//! With `over_budget = "deny"` its `session/prompt` requests are answered
+//! with [`OVER_BUDGET`] until the next day; everything else, such as
+//! `session/cancel`, still reaches the agent.
+
+use anyhow::{Context, Result};
+use chrono::{Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::common_config::{OverBudget, UsageBudget, UsageConfig};
+use crate::output::{Table, Tone};
+
+pub const USAGE_FILENAME: &str = "usage.json";
+
+/// How often a running bridge writes `usage.json`.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// JSON-RPC error code for prompts refused over budget.
+pub const OVER_BUDGET: i64 = -32004;
+
+/// Rough bytes of JSON-RPC per model token, for the report's estimate.
+pub const BYTES_PER_TOKEN: u64 = 4;
+
+/// Name counters are kept under: the `[[devices]]` token's or the paired
+/// device's name, behind the user's for `[[users]]` (`alice/phone`). The
+/// auth token without a paired device counts as `owner`, a user's as the
+/// user.
+pub fn device_name(user: Option<&str>, device: Option<&str>, paired: Option<&str>) -> String {
+    match (user, device.or(paired)) {
+        (Some(user), Some(device)) => format!("{}/{}", user, device),
+        (Some(user), None) => user.to_string(),
+        (None, Some(device)) => device.to_string(),
+        (None, None) => "owner".to_string(),
+    }
+}
+
+/// One device's counts for one day.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Counters {
+    /// JSON-RPC requests from the client that reached the agent
+    pub requests: u64,
+    /// JSON-RPC responses from the agent
+    pub responses: u64,
+    /// Bytes of messages from the client
+    pub bytes_in: u64,
+    /// Bytes of messages from the agent
+    pub bytes_out: u64,
+}
+
+impl Counters {
+    pub fn bytes(&self) -> u64 {
+        self.bytes_in + self.bytes_out
+    }
+
+    /// Tokens estimated from the bytes.
+    pub fn approx_tokens(&self) -> u64 {
+        self.bytes() / BYTES_PER_TOKEN
+    }
+
+    /// Whether a limit of `budget` has been reached.
+    pub fn reached(&self, budget: &UsageBudget) -> bool {
+        budget.daily_requests.is_some_and(|n| self.requests >= n) || budget.daily_bytes.is_some_and(|n| self.bytes() >= n)
+    }
+
+    fn add(&mut self, other: &Counters) {
+        self.requests += other.requests;
+        self.responses += other.responses;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+    }
+}
+
+/// Contents of `usage.json`: counters per local date and device.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub days: BTreeMap<NaiveDate, BTreeMap<String, Counters>>,
+}
+
+impl UsageRecord {
+    /// Read `usage.json` in `dir`; empty when there is none.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(USAGE_FILENAME);
+        match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).with_context(|| format!("Failed to parse {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Drop days more than `retention_days` before `today`.
+    fn prune(&mut self, today: NaiveDate, retention_days: u32) {
+        let first = today - chrono::Duration::days(retention_days as i64 - 1);
+        self.days.retain(|day, _| *day >= first);
+    }
+
+    /// Each device's totals from `first` on.
+    pub fn totals(&self, first: NaiveDate) -> BTreeMap<String, Counters> {
+        let mut totals: BTreeMap<String, Counters> = BTreeMap::new();
+        for devices in self.days.range(first..).map(|(_, devices)| devices) {
+            for (device, counters) in devices {
+                totals.entry(device.clone()).or_default().add(counters);
+            }
+        }
+        totals
+    }
+}
+
+/// What to do with a counted message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Allow,
+    /// The device has just reached its budget; the message says so.
+    Reached(String),
+    /// Refuse the request with this JSON-RPC error response.
+    Deny(String),
+}
+
+/// The shape of a JSON-RPC message, without its params.
+#[derive(Deserialize)]
+struct Envelope {
+    id: Option<serde_json::Value>,
+    method: Option<String>,
+}
+
+/// The usage counters of a config directory, shared by every connection and
+/// the periodic flush.
+#[derive(Debug)]
+pub struct Usage {
+    path: PathBuf,
+    config: UsageConfig,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    record: UsageRecord,
+    /// Written since the last flush.
+    dirty: bool,
+    /// Devices already warned about, per day.
+    reached: HashSet<(NaiveDate, String)>,
+}
+
+impl Usage {
+    /// Open `usage.json` in `dir`, starting empty if it can't be read.
+    pub fn open(dir: &Path, config: UsageConfig) -> Self {
+        let record = UsageRecord::load(dir).unwrap_or_else(|e| {
+            warn!("{:#}; starting usage counters afresh", e);
+            UsageRecord::default()
+        });
+        Self { path: dir.join(USAGE_FILENAME), config, state: Mutex::new(State { record, ..State::default() }) }
+    }
+
+    /// Count `text` from `device`'s client, before it goes to the agent.
+    pub fn record_client(&self, device: &str, text: &str) -> Verdict {
+        self.record_client_on(today(), device, text)
+    }
+
+    /// Count `line` from the agent to `device`'s client.
+    pub fn record_agent(&self, device: &str, line: &str) -> Verdict {
+        self.record_agent_on(today(), device, line)
+    }
+
+    fn record_client_on(&self, day: NaiveDate, device: &str, text: &str) -> Verdict {
+        let envelope = serde_json::from_str::<Envelope>(text).ok();
+        let request = envelope.as_ref().filter(|e| e.id.is_some() && e.method.is_some());
+        let budget = self.config.budget(device);
+        let mut state = self.lock();
+        let counters = state.counters(day, device);
+        if let Some(request) = request.filter(|r| r.method.as_deref() == Some("session/prompt")) {
+            if self.config.over_budget == OverBudget::Deny && counters.reached(&budget) {
+                return Verdict::Deny(over_budget_response(request.id.as_ref().unwrap_or(&serde_json::Value::Null), &budget));
+            }
+        }
+        counters.bytes_in += text.len() as u64;
+        if request.is_some() {
+            counters.requests += 1;
+        }
+        state.dirty = true;
+        state.check(day, device, &budget)
+    }
+
+    fn record_agent_on(&self, day: NaiveDate, device: &str, line: &str) -> Verdict {
+        let response = serde_json::from_str::<Envelope>(line).is_ok_and(|e| e.id.is_some() && e.method.is_none());
+        let budget = self.config.budget(device);
+        let mut state = self.lock();
+        let counters = state.counters(day, device);
+        counters.bytes_out += line.len() as u64;
+        if response {
+            counters.responses += 1;
+        }
+        state.dirty = true;
+        state.check(day, device, &budget)
+    }
+
+    /// Write `usage.json` if anything was counted since the last call,
+    /// dropping days past `retention_days`.
+    pub fn flush(&self) {
+        let mut state = self.lock();
+        if !state.dirty {
+            return;
+        }
+        let today = today();
+        state.record.prune(today, self.config.retention_days);
+        state.reached.retain(|(day, _)| *day == today);
+        let written = serde_json::to_string_pretty(&state.record)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| std::fs::write(&self.path, json).map_err(Into::into));
+        match written {
+            Ok(()) => state.dirty = false,
+            Err(e) => warn!("Failed to write {}: {:#}", self.path.display(), e),
+        }
+    }
+
+    /// Flush every [`FLUSH_INTERVAL`]; never resolves.
+    pub async fn watch(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            self.flush();
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl State {
+    fn counters(&mut self, day: NaiveDate, device: &str) -> &mut Counters {
+        self.record.days.entry(day).or_default().entry(device.to_string()).or_default()
+    }
+
+    /// [`Verdict::Reached`] the first time `device` is at `budget` on `day`.
+    fn check(&mut self, day: NaiveDate, device: &str, budget: &UsageBudget) -> Verdict {
+        let counters = self.counters(day, device);
+        if !counters.reached(budget) {
+            return Verdict::Allow;
+        }
+        let message = format!(
+            "{} reached its daily budget ({}): {} requests, {} bytes today",
+            device,
+            describe(budget),
+            counters.requests,
+            counters.bytes()
+        );
+        if self.reached.insert((day, device.to_string())) { Verdict::Reached(message) } else { Verdict::Allow }
+    }
+}
+
+fn today() -> NaiveDate {
+    Local::now().date_naive()
+}
+
+/// `budget`'s limits, e.g. `200 requests, 50000000 bytes`.
+fn describe(budget: &UsageBudget) -> String {
+    let limits: Vec<String> = [(budget.daily_requests, "requests"), (budget.daily_bytes, "bytes")]
+        .into_iter()
+        .filter_map(|(limit, unit)| limit.map(|n| format!("{} {}", n, unit)))
+        .collect();
+    limits.join(", ")
+}
+
+/// JSON-RPC error answering prompt `id` of a device over `budget`.
+fn over_budget_response(id: &serde_json::Value, budget: &UsageBudget) -> String {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": OVER_BUDGET,
+            "message": format!("This device has used its daily budget ({}); try again tomorrow", describe(budget)),
+        }
+    })
+    .to_string()
+}
+
+/// `bridge usage`: each device's totals over the last days, and today's.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageReport {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub devices: Vec<DeviceUsage>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceUsage {
+    pub device: String,
+    #[serde(flatten)]
+    pub total: Counters,
+    pub approx_tokens: u64,
+    pub today: Counters,
+    /// The device's daily limits from `[usage]`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_requests: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_bytes: Option<u64>,
+}
+
+impl DeviceUsage {
+    pub fn over_budget(&self) -> bool {
+        self.today.reached(&UsageBudget { daily_requests: self.daily_requests, daily_bytes: self.daily_bytes })
+    }
+}
+
+impl UsageReport {
+    /// The `days` days of `record` up to `today`, with budgets from `config`.
+    pub fn new(record: &UsageRecord, config: Option<&UsageConfig>, days: u32, today: NaiveDate) -> Self {
+        let from = today - chrono::Duration::days(days.max(1) as i64 - 1);
+        let todays = record.days.get(&today);
+        let devices = record
+            .totals(from)
+            .into_iter()
+            .map(|(device, total)| {
+                let budget = config.map(|c| c.budget(&device)).unwrap_or_default();
+                DeviceUsage {
+                    today: todays.and_then(|d| d.get(&device)).copied().unwrap_or_default(),
+                    daily_requests: budget.daily_requests,
+                    daily_bytes: budget.daily_bytes,
+                    approx_tokens: total.approx_tokens(),
+                    device,
+                    total,
+                }
+            })
+            .collect();
+        Self { from, to: today, devices }
+    }
+}
+
+impl fmt::Display for UsageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Usage from {} to {}\n", self.from, self.to)?;
+        if self.devices.is_empty() {
+            return write!(f, "Nothing counted yet.");
+        }
+        let mut table = Table::new(&["DEVICE", "REQUESTS", "RESPONSES", "IN", "OUT", "~TOKENS", "TODAY"]);
+        for device in &self.devices {
+            let today = match device.daily_requests {
+                Some(limit) => format!("{}/{} requests", device.today.requests, limit),
+                None => format!("{} requests", device.today.requests),
+            };
+            let tone = if device.over_budget() { Tone::Bad } else { Tone::Plain };
+            table.toned_row([
+                (device.device.clone(), Tone::Plain),
+                (device.total.requests.to_string(), Tone::Plain),
+                (device.total.responses.to_string(), Tone::Plain),
+                (format_bytes(device.total.bytes_in), Tone::Plain),
+                (format_bytes(device.total.bytes_out), Tone::Plain),
+                (device.approx_tokens.to_string(), Tone::Plain),
+                (today, tone),
+            ]);
+        }
+        write!(f, "{}", table)
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{}B", bytes),
+        1024..=1_048_575 => format!("{:.1}K", bytes as f64 / 1024.0),
+        _ => format!("{:.1}M", bytes as f64 / 1_048_576.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROMPT: &str = r#"{"jsonrpc":"2.0","id":7,"method":"session/prompt","params":{}}"#;
+    const CANCEL: &str = r#"{"jsonrpc":"2.0","method":"session/cancel","params":{}}"#;
+    const RESULT: &str = r#"{"jsonrpc":"2.0","id":7,"result":{"stopReason":"end_turn"}}"#;
+    const UPDATE: &str = r#"{"jsonrpc":"2.0","method":"session/update","params":{}}"#;
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 10, d).unwrap()
+    }
+
+    #[test]
+    fn devices_are_counted_per_day_and_budgets_deny_prompts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = UsageConfig { daily_requests: Some(2), over_budget: OverBudget::Deny, ..UsageConfig::default() };
+        config.devices.insert("alice/phone".to_string(), UsageBudget { daily_requests: Some(1), daily_bytes: None });
+        let usage = Usage::open(dir.path(), config.clone());
+
+        assert_eq!(usage.record_client_on(day(15), "owner", PROMPT), Verdict::Allow);
+        assert_eq!(usage.record_client_on(day(15), "owner", CANCEL), Verdict::Allow);
+        assert_eq!(usage.record_agent_on(day(15), "owner", UPDATE), Verdict::Allow);
+        assert_eq!(usage.record_agent_on(day(15), "owner", RESULT), Verdict::Allow);
+
+        assert!(matches!(usage.record_client_on(day(16), "alice/phone", PROMPT), Verdict::Reached(m) if m.starts_with("alice/phone reached")));
+        let Verdict::Deny(response) = usage.record_client_on(day(16), "alice/phone", PROMPT) else { panic!("prompt over budget allowed") };
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!((response["id"].as_i64(), response["error"]["code"].as_i64()), (Some(7), Some(OVER_BUDGET)));
+        assert_eq!(usage.record_client_on(day(16), "alice/phone", CANCEL), Verdict::Allow);
+        assert_eq!(usage.record_client_on(day(17), "alice/phone", PROMPT), Verdict::Reached(
+            "alice/phone reached its daily budget (1 requests): 1 requests, 62 bytes today".to_string()
+        ));
+
+        let record = usage.lock().record.clone();
+        let owner = record.days[&day(15)]["owner"];
+        assert_eq!((owner.requests, owner.responses), (1, 1));
+        assert_eq!(owner.bytes_out, (UPDATE.len() + RESULT.len()) as u64);
+        assert_eq!(record.days[&day(16)]["alice/phone"].requests, 1);
+
+        let report = UsageReport::new(&record, Some(&config), 2, day(17));
+        let devices: Vec<_> = report.devices.iter().map(|d| (d.device.as_str(), d.total.requests, d.over_budget())).collect();
+        assert_eq!(devices, [("alice/phone", 2, true)]);
+    }
+
+    #[test]
+    fn counters_survive_a_restart_and_old_days_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = UsageConfig { retention_days: 2, ..UsageConfig::default() };
+        let usage = Usage::open(dir.path(), config.clone());
+        usage.record_client_on(today() - chrono::Duration::days(5), "owner", PROMPT);
+        usage.record_client(&device_name(Some("alice"), None, Some("Pixel 8")), PROMPT);
+        usage.flush();
+
+        let record = UsageRecord::load(dir.path()).unwrap();
+        assert_eq!(record.days.len(), 1);
+        assert_eq!(record.days[&today()]["alice/Pixel 8"].requests, 1);
+        assert_eq!(Usage::open(dir.path(), config).lock().record, record);
+        assert_eq!(device_name(None, Some("tablet"), Some("Pixel 8")), "tablet");
+        assert_eq!(device_name(None, None, None), "owner");
+    }
+}
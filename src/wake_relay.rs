@@ -0,0 +1,211 @@
+//! `bridge wake-relay`: reach a sleeping workstation's bridge through an
+//! always-on machine (`[wake_relay]`).
+//!
+//! The relay accepts TCP connections and forwards them byte for byte to the
+//! workstation's bridge, so TLS and certificate pinning stay end to end. When
+//! the bridge doesn't answer, the relay broadcasts a Wake-on-LAN magic packet
+//! for its MAC address, polls until the bridge accepts connections (at most
+//! `wait_secs`), and then forwards the connection that was waiting. The
+//! workstation advertises the relay's address (`advertise_addr`) when pairing,
+//! so the phone always connects through it.
+
+use anyhow::{Context, Result};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tracing::{debug, info, warn};
+
+use crate::common_config::WakeRelayConfig;
+
+/// How long a connection attempt to the target may take before the host is
+/// considered asleep.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Pause between connection attempts while the host wakes up.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Magic packets are repeated this often while the host stays down, since
+/// the first one may be lost. Concurrent connections share them.
+const RESEND_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Parse a MAC address written as six hex octets separated by `:` or `-`.
+pub fn parse_mac(mac: &str) -> Result<[u8; 6]> {
+    let octets: Vec<&str> = mac.trim().split([':', '-']).collect();
+    if octets.len() != 6 {
+        anyhow::bail!("{:?} is not a MAC address (expected six octets like 3c:7c:3f:12:34:56)", mac);
+    }
+    let mut bytes = [0u8; 6];
+    for (byte, octet) in bytes.iter_mut().zip(octets) {
+        if octet.len() != 2 {
+            anyhow::bail!("{:?} is not a MAC address (octet {:?})", mac, octet);
+        }
+        *byte = u8::from_str_radix(octet, 16).with_context(|| format!("{:?} is not a MAC address (octet {:?})", mac, octet))?;
+    }
+    Ok(bytes)
+}
+
+/// The Wake-on-LAN magic packet for `mac`: six `0xff` bytes followed by the
+/// MAC address sixteen times.
+pub fn magic_packet(mac: [u8; 6]) -> [u8; 102] {
+    let mut packet = [0xff; 102];
+    for chunk in packet[6..].chunks_exact_mut(6) {
+        chunk.copy_from_slice(&mac);
+    }
+    packet
+}
+
+/// Broadcast the magic packet for `mac` to `broadcast` (`IP:port`).
+pub async fn send_magic_packet(mac: [u8; 6], broadcast: &str) -> Result<()> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.context("Failed to open a UDP socket")?;
+    socket.set_broadcast(true).context("Failed to enable broadcast")?;
+    socket
+        .send_to(&magic_packet(mac), broadcast)
+        .await
+        .with_context(|| format!("Failed to send the magic packet to {}", broadcast))?;
+    Ok(())
+}
+
+/// Forwarding state shared by every connection.
+struct WakeRelay {
+    config: WakeRelayConfig,
+    mac: [u8; 6],
+    last_wake: Mutex<Option<Instant>>,
+}
+
+impl WakeRelay {
+    /// Connect to the target, waking it first when it doesn't answer.
+    async fn connect(&self) -> Result<TcpStream> {
+        if let Some(stream) = self.probe().await {
+            return Ok(stream);
+        }
+        info!("💤 {} is not answering — waking {}", self.config.target, self.config.mac);
+        let deadline = Instant::now() + Duration::from_secs(self.config.wait_secs);
+        loop {
+            self.wake().await;
+            tokio::time::sleep(POLL_INTERVAL).await;
+            if let Some(stream) = self.probe().await {
+                info!("⏰ {} is up", self.config.target);
+                return Ok(stream);
+            }
+            if Instant::now() >= deadline {
+                anyhow::bail!("{} did not come up within {}s", self.config.target, self.config.wait_secs);
+            }
+        }
+    }
+
+    async fn probe(&self) -> Option<TcpStream> {
+        match tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(&self.config.target)).await {
+            Ok(Ok(stream)) => Some(stream),
+            Ok(Err(e)) => {
+                debug!("Connecting to {} failed: {}", self.config.target, e);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Send the magic packet unless one went out within [`RESEND_INTERVAL`].
+    async fn wake(&self) {
+        {
+            let mut last_wake = self.last_wake.lock().unwrap_or_else(|e| e.into_inner());
+            if last_wake.is_some_and(|at| at.elapsed() < RESEND_INTERVAL) {
+                return;
+            }
+            *last_wake = Some(Instant::now());
+        }
+        match send_magic_packet(self.mac, &self.config.broadcast).await {
+            Ok(()) => info!("📣 Sent Wake-on-LAN packet for {} to {}", self.config.mac, self.config.broadcast),
+            Err(e) => warn!("{:#}", e),
+        }
+    }
+
+    async fn forward(&self, mut client: TcpStream, peer: std::net::SocketAddr) {
+        let mut upstream = match self.connect().await {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("🚫 Dropping connection from {}: {:#}", peer, e);
+                return;
+            }
+        };
+        debug!("🔀 Forwarding {} to {}", peer, self.config.target);
+        match tokio::io::copy_bidirectional(&mut client, &mut upstream).await {
+            Ok((sent, received)) => debug!("Connection from {} closed ({} bytes up, {} down)", peer, sent, received),
+            Err(e) => debug!("Connection from {} ended: {}", peer, e),
+        }
+    }
+}
+
+/// Listen on `config.listen` and forward every connection until the process
+/// is stopped.
+pub async fn run(config: WakeRelayConfig) -> Result<()> {
+    let listener = TcpListener::bind(&config.listen)
+        .await
+        .with_context(|| format!("Failed to listen on {}", config.listen))?;
+    serve(listener, config).await
+}
+
+/// Like [`run`], on an already-bound listener.
+pub async fn serve(listener: TcpListener, config: WakeRelayConfig) -> Result<()> {
+    let mac = parse_mac(&config.mac)?;
+    let relay = Arc::new(WakeRelay { config, mac, last_wake: Mutex::new(None) });
+    loop {
+        let (stream, peer) = listener.accept().await.context("Failed to accept a connection")?;
+        let relay = Arc::clone(&relay);
+        tokio::spawn(async move { relay.forward(stream, peer).await });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn magic_packet_repeats_the_mac() {
+        let mac = parse_mac("3C-7C-3F-12-34-56").unwrap();
+        assert_eq!(mac, [0x3c, 0x7c, 0x3f, 0x12, 0x34, 0x56]);
+        let packet = magic_packet(mac);
+        assert_eq!(packet[..6], [0xff; 6]);
+        assert!(packet[6..].chunks(6).all(|chunk| chunk == mac));
+        assert!(parse_mac("3c:7c:3f:12:34").is_err());
+        assert!(parse_mac("3c:7c:3f:12:34:zz").is_err());
+    }
+
+    #[tokio::test]
+    async fn sleeping_target_is_woken_before_forwarding() {
+        let wol = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        // A free port for the "workstation" bridge, which isn't up yet.
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let relay = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay_addr = relay.local_addr().unwrap();
+        tokio::spawn(serve(
+            relay,
+            WakeRelayConfig {
+                listen: relay_addr.to_string(),
+                target: target.to_string(),
+                mac: "3c:7c:3f:12:34:56".into(),
+                broadcast: wol.local_addr().unwrap().to_string(),
+                wait_secs: 10,
+            },
+        ));
+
+        let workstation = tokio::spawn(async move {
+            let mut packet = [0u8; 128];
+            let (len, _) = wol.recv_from(&mut packet).await.unwrap();
+            assert_eq!(packet[..len], magic_packet([0x3c, 0x7c, 0x3f, 0x12, 0x34, 0x56]));
+            let bridge = TcpListener::bind(target).await.unwrap();
+            let (mut stream, _) = bridge.accept().await.unwrap();
+            let mut ping = [0u8; 4];
+            stream.read_exact(&mut ping).await.unwrap();
+            assert_eq!(&ping, b"ping");
+            stream.write_all(b"pong").await.unwrap();
+        });
+
+        let mut client = TcpStream::connect(relay_addr).await.unwrap();
+        client.write_all(b"ping").await.unwrap();
+        let mut pong = [0u8; 4];
+        tokio::time::timeout(Duration::from_secs(10), client.read_exact(&mut pong)).await.unwrap().unwrap();
+        assert_eq!(&pong, b"pong");
+        workstation.await.unwrap();
+    }
+}
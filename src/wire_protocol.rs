@@ -0,0 +1,151 @@
+//! Wire format generations, negotiated with `Sec-WebSocket-Protocol`.
+//!
+//! | Subprotocol        | Frames                                                                      |
+//! |--------------------|-----------------------------------------------------------------------------|
+//! | `aptove-bridge.v1` | plain JSON-RPC; sequence envelopes are opt-in via `bridge/negotiate`        |
+//! | `aptove-bridge.v2` | every agent message in a sequence envelope `{"seq": N, "msg": …}` from the start |
+//!
+//! A client lists the generations it speaks; the bridge picks the newest one
+//! it also speaks and echoes it back. Clients that offer no `aptove-bridge.*`
+//! subprotocol predate negotiation and get v1. A client that offers only
+//! generations this bridge doesn't speak is refused with `426 Upgrade
+//! Required` and the supported list, instead of connecting and misreading
+//! frames.
+
+/// Subprotocol name prefix; the generation follows as `v<N>`.
+pub const PREFIX: &str = "aptove-bridge.";
+
+/// A wire format generation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum WireVersion {
+    /// Plain JSON-RPC frames.
+    #[default]
+    V1 = 1,
+    /// Agent messages always wrapped in sequence envelopes. Needs keep-alive
+    /// pooling, which assigns the sequence numbers.
+    V2 = 2,
+}
+
+impl WireVersion {
+    /// Newest first.
+    pub const ALL: [WireVersion; 2] = [WireVersion::V2, WireVersion::V1];
+
+    pub fn subprotocol(self) -> &'static str {
+        match self {
+            WireVersion::V1 => "aptove-bridge.v1",
+            WireVersion::V2 => "aptove-bridge.v2",
+        }
+    }
+
+    /// Whether agent messages must be sent in sequence envelopes.
+    pub fn seq_envelope(self) -> bool {
+        self == WireVersion::V2
+    }
+
+    /// The generations a connection can be served with: v2 only with
+    /// keep-alive pooling.
+    pub fn supported(keep_alive: bool) -> &'static [WireVersion] {
+        if keep_alive {
+            &Self::ALL
+        } else {
+            &[WireVersion::V1]
+        }
+    }
+
+    /// Subprotocol names of `versions`, for advertising and error bodies.
+    pub fn names(versions: &[WireVersion]) -> Vec<&'static str> {
+        versions.iter().map(|v| v.subprotocol()).collect()
+    }
+}
+
+/// Outcome of [`negotiate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Negotiation {
+    /// The client offered no `aptove-bridge.*` subprotocol: v1, and no
+    /// `Sec-WebSocket-Protocol` in the response.
+    Legacy,
+    /// Serve this generation and echo its subprotocol.
+    Selected(WireVersion),
+    /// Every generation the client offered is unknown or unavailable here.
+    Unsupported { offered: Vec<String> },
+}
+
+impl Negotiation {
+    /// The generation to serve, if the connection is accepted.
+    pub fn version(&self) -> Option<WireVersion> {
+        match self {
+            Negotiation::Legacy => Some(WireVersion::V1),
+            Negotiation::Selected(version) => Some(*version),
+            Negotiation::Unsupported { .. } => None,
+        }
+    }
+}
+
+/// Pick the newest generation in `supported` that the client offered.
+/// `headers` are the raw `Sec-WebSocket-Protocol` values, each possibly a
+/// comma-separated list.
+pub fn negotiate<'a>(headers: impl IntoIterator<Item = &'a str>, supported: &[WireVersion]) -> Negotiation {
+    let offered: Vec<String> = headers
+        .into_iter()
+        .flat_map(|h| h.split(','))
+        .map(str::trim)
+        .filter(|p| p.starts_with(PREFIX))
+        .map(String::from)
+        .collect();
+    if offered.is_empty() {
+        return Negotiation::Legacy;
+    }
+    supported
+        .iter()
+        .copied()
+        .filter(|v| offered.iter().any(|p| p == v.subprotocol()))
+        .max()
+        .map_or(Negotiation::Unsupported { offered }, Negotiation::Selected)
+}
+
+/// JSON body of the `426 Upgrade Required` refusal.
+pub fn unsupported_body(offered: &[String], supported: &[WireVersion]) -> String {
+    serde_json::json!({
+        "error": "unsupported_protocol",
+        "message": format!(
+            "This bridge speaks {}; the app offered {}. Update the app or the bridge.",
+            WireVersion::names(supported).join(", "),
+            offered.join(", "),
+        ),
+        "offered": offered,
+        "supported": WireVersion::names(supported),
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_newest_common_generation() {
+        let all = WireVersion::supported(true);
+        assert_eq!(negotiate(["aptove-bridge.v1, aptove-bridge.v2"], all), Negotiation::Selected(WireVersion::V2));
+        assert_eq!(negotiate(["aptove-bridge.v2", "aptove-bridge.v1"], WireVersion::supported(false)), Negotiation::Selected(WireVersion::V1));
+        assert_eq!(negotiate(["aptove-bridge.v1"], all).version(), Some(WireVersion::V1));
+    }
+
+    #[test]
+    fn clients_without_a_bridge_subprotocol_are_legacy() {
+        assert_eq!(negotiate([], WireVersion::supported(true)), Negotiation::Legacy);
+        assert_eq!(negotiate(["graphql-ws"], WireVersion::supported(true)), Negotiation::Legacy);
+        assert_eq!(Negotiation::Legacy.version(), Some(WireVersion::V1));
+    }
+
+    #[test]
+    fn unknown_generations_are_refused() {
+        let negotiation = negotiate(["aptove-bridge.v9"], WireVersion::supported(true));
+        assert_eq!(negotiation, Negotiation::Unsupported { offered: vec!["aptove-bridge.v9".into()] });
+        assert_eq!(negotiation.version(), None);
+        assert!(negotiate(["aptove-bridge.v2"], WireVersion::supported(false)).version().is_none());
+
+        let body: serde_json::Value = serde_json::from_str(&unsupported_body(&["aptove-bridge.v9".into()], &WireVersion::ALL)).unwrap();
+        assert_eq!(body["supported"], serde_json::json!(["aptove-bridge.v2", "aptove-bridge.v1"]));
+        assert!(body["message"].as_str().unwrap().contains("aptove-bridge.v9"));
+    }
+}
@@ -0,0 +1,201 @@
+//! Per-device git worktrees, so agents of simultaneous sessions don't edit
+//! the same checkout.
+//!
+//! With `[worktrees]` configured, the pool gives each token's agent a
+//! worktree of the repository it would otherwise run in, on a branch of its
+//! own. The worktree outlives agent restarts and evictions, so work is never
+//! lost with the process; `bridge/session/finish` either discards it or keeps
+//! the branch (committing and optionally pushing what's there) and removes the
+//! checkout.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tracing::info;
+
+use crate::common_config::WorktreeConfig;
+
+/// A worktree created for one token's agent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Worktree {
+    /// Directory the agent would have run in without worktrees.
+    pub source: PathBuf,
+    /// Top level of the repository `source` belongs to.
+    pub repo: PathBuf,
+    /// Checkout of the worktree.
+    pub path: PathBuf,
+    /// Branch checked out in the worktree.
+    pub branch: String,
+    /// Where `source` is within the repository.
+    pub subdir: PathBuf,
+}
+
+/// What `bridge/session/finish` does with the worktree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FinishAction {
+    /// Delete the checkout and its branch.
+    Discard,
+    /// Commit any changes to the branch, push it to `push_remote` when one
+    /// is configured, and delete the checkout only.
+    Branch,
+}
+
+/// Result of [`Worktree::finish`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FinishOutcome {
+    /// The kept branch (`None` when discarded).
+    pub branch: Option<String>,
+    /// Tip of the kept branch.
+    pub commit: Option<String>,
+    /// Remote the branch was pushed to.
+    pub pushed_to: Option<String>,
+}
+
+impl Worktree {
+    /// Add a worktree of the repository containing `source` under
+    /// `config.root`, on a new branch from its current `HEAD`.
+    pub async fn create(source: &Path, config: &WorktreeConfig) -> Result<Self> {
+        let repo = PathBuf::from(
+            git(source, &["rev-parse", "--show-toplevel"])
+                .await
+                .with_context(|| format!("{} is not in a git repository", source.display()))?,
+        );
+        let subdir = PathBuf::from(git(source, &["rev-parse", "--show-prefix"]).await?);
+        let id = uuid::Uuid::new_v4().simple().to_string()[..8].to_string();
+        let name = repo.file_name().map_or_else(|| "repo".into(), |n| n.to_string_lossy().into_owned());
+        let path = config.root.join(format!("{}-{}", name, id));
+        let branch = format!("{}{}", config.branch_prefix, id);
+
+        std::fs::create_dir_all(&config.root)
+            .with_context(|| format!("Failed to create worktree root {}", config.root.display()))?;
+        let path_arg = path.to_string_lossy();
+        git(&repo, &["worktree", "add", "-b", &branch, &path_arg, "HEAD"]).await?;
+        info!("🌿 Created worktree {} on branch {}", path.display(), branch);
+        Ok(Self { source: source.to_path_buf(), repo, path, branch, subdir })
+    }
+
+    /// Directory to run the agent in: where `source` is within the worktree.
+    pub fn agent_dir(&self) -> PathBuf {
+        self.path.join(&self.subdir)
+    }
+
+    /// Discard the worktree or keep its branch, then remove the checkout.
+    /// `message` is used for the commit of uncommitted changes.
+    pub async fn finish(&self, action: FinishAction, message: &str, push_remote: Option<&str>) -> Result<FinishOutcome> {
+        let path_arg = self.path.to_string_lossy();
+        match action {
+            FinishAction::Discard => {
+                git(&self.repo, &["worktree", "remove", "--force", &path_arg]).await?;
+                git(&self.repo, &["branch", "-D", &self.branch]).await?;
+                info!("🌿 Discarded worktree {} and branch {}", self.path.display(), self.branch);
+                Ok(FinishOutcome { branch: None, commit: None, pushed_to: None })
+            }
+            FinishAction::Branch => {
+                if !git(&self.path, &["status", "--porcelain"]).await?.is_empty() {
+                    git(&self.path, &["add", "-A"]).await?;
+                    git(&self.path, &["commit", "-m", message]).await?;
+                }
+                let commit = git(&self.path, &["rev-parse", "HEAD"]).await?;
+                if let Some(remote) = push_remote {
+                    git(&self.path, &["push", "-u", remote, &self.branch]).await?;
+                }
+                git(&self.repo, &["worktree", "remove", &path_arg]).await?;
+                info!("🌿 Kept branch {} at {} and removed worktree {}", self.branch, commit, self.path.display());
+                Ok(FinishOutcome {
+                    branch: Some(self.branch.clone()),
+                    commit: Some(commit),
+                    pushed_to: push_remote.map(str::to_string),
+                })
+            }
+        }
+    }
+}
+
+/// Run `git` in `dir` and return its trimmed stdout.
+async fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .await
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn repo_with_commit() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for args in [
+            &["init", "-q", "-b", "main"][..],
+            &["config", "user.email", "dev@example.com"],
+            &["config", "user.name", "Dev"],
+        ] {
+            git(dir.path(), args).await.unwrap();
+        }
+        std::fs::create_dir(dir.path().join("app")).unwrap();
+        std::fs::write(dir.path().join("app/README"), "hello\n").unwrap();
+        git(dir.path(), &["add", "-A"]).await.unwrap();
+        git(dir.path(), &["commit", "-q", "-m", "initial"]).await.unwrap();
+        dir
+    }
+
+    fn config(root: &Path) -> WorktreeConfig {
+        WorktreeConfig { root: root.to_path_buf(), branch_prefix: "bridge/".into(), push_remote: None }
+    }
+
+    #[tokio::test]
+    async fn kept_branch_has_the_sessions_changes() {
+        let repo = repo_with_commit().await;
+        let root = tempfile::tempdir().unwrap();
+        let source = repo.path().join("app");
+
+        let worktree = Worktree::create(&source, &config(root.path())).await.unwrap();
+        assert!(worktree.branch.starts_with("bridge/"));
+        assert_eq!(worktree.agent_dir(), worktree.path.join("app"));
+        assert!(worktree.agent_dir().join("README").is_file());
+
+        std::fs::write(worktree.agent_dir().join("NOTES"), "from the agent\n").unwrap();
+        let outcome = worktree.finish(FinishAction::Branch, "Session work", None).await.unwrap();
+        assert!(!worktree.path.exists());
+        assert_eq!(outcome.branch.as_deref(), Some(worktree.branch.as_str()));
+        let spec = format!("{}:app/NOTES", worktree.branch);
+        assert_eq!(git(repo.path(), &["show", &spec]).await.unwrap(), "from the agent");
+        // The main checkout is untouched.
+        assert!(!source.join("NOTES").exists());
+    }
+
+    #[tokio::test]
+    async fn discarded_worktree_leaves_no_branch() {
+        let repo = repo_with_commit().await;
+        let root = tempfile::tempdir().unwrap();
+
+        let worktree = Worktree::create(repo.path(), &config(root.path())).await.unwrap();
+        assert_eq!(worktree.agent_dir(), worktree.path);
+        std::fs::write(worktree.path.join("scratch"), "").unwrap();
+        let outcome = worktree.finish(FinishAction::Discard, "unused", None).await.unwrap();
+        assert_eq!(outcome.branch, None);
+        assert!(!worktree.path.exists());
+        assert!(git(repo.path(), &["branch", "--list", &worktree.branch]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn directories_outside_a_repository_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = Worktree::create(dir.path(), &config(dir.path())).await.unwrap_err();
+        assert!(err.to_string().contains("not in a git repository"), "{err}");
+    }
+}
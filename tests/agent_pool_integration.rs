@@ -9,7 +9,8 @@ use std::time::Duration;
 use tokio::sync::RwLock;
 
 // The crate is the `bridge` library — its public API surfaces everything we need.
-use bridge::agent_pool::{AgentPool, PoolConfig};
+use bridge::agent_pool::{start_warm_pool, AgentPool, PoolConfig, UserNamespaces};
+use bridge::common_config::EvictionConfig;
 
 // ── Helper ───────────────────────────────────────────────────────────────
 
@@ -19,6 +20,14 @@ fn fast_pool(max_agents: usize) -> AgentPool {
         max_agents,
         buffer_messages: true,
         max_buffer_size: 50,
+        max_buffer_bytes: 0,
+        buffer_ttl: None,
+        warm_agents: 0,
+        eviction: EvictionConfig::default(),
+        overrides: Vec::new(),
+        users: UserNamespaces::default(),
+        max_line_bytes: bridge::line_reader::DEFAULT_MAX_LINE_BYTES,
+        agent_version: None,
     })
 }
 
@@ -330,3 +339,79 @@ async fn cached_session_survives_multiple_reconnects() {
 
     pool.shutdown_all().await;
 }
+
+#[tokio::test]
+async fn warm_agent_is_assigned_with_cached_init_and_backfilled() {
+    let pool = Arc::new(RwLock::new(AgentPool::new(PoolConfig {
+        warm_agents: 1,
+        ..PoolConfig::default()
+    })));
+    let warm_task = start_warm_pool(Arc::clone(&pool), "cat".to_string());
+
+    // `cat` echoes the warm-up initialize request, which stands in for the response
+    let wait_for_warm = || async {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while pool.read().await.stats().warm == 0 {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("warm agent was not pre-spawned");
+    };
+    wait_for_warm().await;
+
+    let (_tx, _rx, _buf, reused, cached_init, cached_session, _) =
+        pool.write().await.get_or_spawn("tok1", "cat").await.unwrap();
+    assert!(!reused, "a warm agent is new to this token");
+    assert!(cached_init.unwrap().contains("bridge-warm-init"));
+    assert!(cached_session.is_none());
+    assert_eq!(pool.read().await.stats().total, 1);
+
+    // The taken agent is replaced in the background
+    wait_for_warm().await;
+
+    warm_task.abort();
+    pool.write().await.shutdown_all().await;
+}
+
+#[tokio::test]
+async fn concurrent_connects_with_same_token_share_one_agent() {
+    let pool = Arc::new(RwLock::new(fast_pool(5)));
+
+    let connects = (0..4).map(|_| {
+        let pool = Arc::clone(&pool);
+        tokio::spawn(async move { AgentPool::connect(&pool, "tok1", "cat").await.unwrap() })
+    });
+    let mut reused = 0;
+    for handle in connects {
+        let ((_, _, _, was_reused, _, _, _), _, _) = handle.await.unwrap();
+        if was_reused {
+            reused += 1;
+        }
+    }
+
+    assert_eq!(pool.read().await.stats().total, 1, "only one agent should be spawned");
+    assert_eq!(reused, 3, "later connections reuse the first agent");
+
+    pool.write().await.shutdown_all().await;
+}
+
+#[tokio::test]
+async fn slot_state_updates_do_not_need_the_pool_lock() {
+    let pool = Arc::new(RwLock::new(fast_pool(5)));
+    let (_, _, slot) = AgentPool::connect(&pool, "tok1", "cat").await.unwrap();
+
+    // Another session holding the pool write lock must not block this one
+    let guard = pool.write().await;
+    slot.buffer_message("queued".to_string());
+    slot.cache_session_response(r#"{"jsonrpc":"2.0","id":2,"result":{"sessionId":"s1"}}"#.to_string());
+    slot.mark_disconnected();
+    drop(guard);
+
+    let ((_, _, buffered, reused, _, cached_session, _), _, _) = AgentPool::connect(&pool, "tok1", "cat").await.unwrap();
+    assert!(reused);
+    assert_eq!(buffered, vec!["queued".to_string()]);
+    assert!(cached_session.unwrap().contains("s1"));
+
+    pool.write().await.shutdown_all().await;
+}
@@ -0,0 +1,447 @@
+//! End-to-end tests through `bridge::testkit`: a real bridge on an ephemeral
+//! port in front of the in-process echo agent (or `cat`, where pooling matters).
+
+use bridge::agent_pool::{AgentPool, PoolConfig};
+use bridge::bridge::AgentHandle;
+use bridge::common_config::{AuthFailureConfig, ClientAuthConfig, DeviceConfig, GeoFilterConfig, WorkspaceConfig, WorktreeConfig};
+use bridge::connect::ConnectTarget;
+use bridge::device_tokens::Scope;
+use bridge::testkit::{TestBridge, TestClient};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn pair_connect_and_echo() {
+    let bridge = TestBridge::echo().await.unwrap();
+    let mut client = bridge.pair_and_connect().await.unwrap();
+    let capabilities = client.notification("bridge/capabilities").await.unwrap();
+    assert!(capabilities["params"].is_object());
+
+    let session_id = client.handshake().await.unwrap();
+    assert!(!session_id.is_empty());
+
+    let response = client.request("demo/echo", json!({ "text": "hello" })).await.unwrap();
+    assert_eq!(response["result"]["text"], "hello");
+    client.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn pairing_returns_connection_details_once() {
+    let bridge = TestBridge::echo().await.unwrap();
+    let pairing = bridge.pair().await.unwrap();
+    assert_eq!(pairing.url, bridge.url());
+    assert_eq!(pairing.auth_token, bridge.auth_token());
+    assert!(bridge.pair().await.is_err(), "pairing code is single-use");
+}
+
+#[tokio::test]
+async fn test_connection_reports_each_stage() {
+    use bridge::test_connection::{self, StageState, TestTarget};
+
+    let bridge = TestBridge::echo().await.unwrap();
+    let pairing_url = format!("http://{}/pair/local?code={}", bridge.addr(), bridge.pairing_code());
+    let target = TestTarget::pairing(&pairing_url).unwrap();
+    let report = test_connection::run(&target, Duration::from_secs(5)).await;
+    assert_eq!(report.failed_stage(), None, "{}", report);
+    let names: Vec<_> = report.stages.iter().map(|s| s.name).collect();
+    assert_eq!(names, ["tcp", "tls", "pairing", "upgrade", "initialize"]);
+    assert_eq!(report.stages[1].state, StageState::Skip, "no TLS on ws://");
+
+    // The code is used up now.
+    let report = test_connection::run(&target, Duration::from_secs(5)).await;
+    assert_eq!(report.failed_stage(), Some("pairing"));
+
+    let wrong_token = ConnectTarget { token: "wrong".to_string(), ..bridge.target() };
+    let report = test_connection::run(&TestTarget::Token(wrong_token), Duration::from_secs(5)).await;
+    assert_eq!(report.failed_stage(), Some("upgrade"));
+    assert!(report.stages[2].detail.contains("401"), "{}", report.stages[2].detail);
+
+    let report = test_connection::run(&TestTarget::Token(bridge.target()), Duration::from_secs(5)).await;
+    assert_eq!(report.failed_stage(), None, "{}", report);
+}
+
+#[tokio::test]
+async fn wrong_pairing_code_is_rejected() {
+    let bridge = TestBridge::echo().await.unwrap();
+    let wrong = if bridge.pairing_code() == "000000" { "111111" } else { "000000" };
+    let err = bridge.pair_with_code(wrong).await.unwrap_err();
+    assert!(err.to_string().contains("401"), "{:#}", err);
+}
+
+#[tokio::test]
+async fn wrong_token_is_rejected() {
+    let bridge = TestBridge::echo().await.unwrap();
+    let target = ConnectTarget { url: bridge.url(), token: "wrong".into(), fingerprint: None };
+    assert!(TestClient::connect(&target).await.is_err());
+}
+
+#[tokio::test]
+async fn session_tokens_authenticate_and_refresh() {
+    let bridge = TestBridge::start_with(TestBridge::echo_handle(), |b| b.with_session_tokens(Duration::from_secs(300)))
+        .await
+        .unwrap();
+
+    let issued = bridge.session_token(bridge.auth_token()).await.unwrap();
+    assert_eq!(issued["expiresIn"], 300);
+    let session_token = issued["sessionToken"].as_str().unwrap().to_string();
+    assert_ne!(session_token, bridge.auth_token());
+
+    let refreshed = bridge.session_token(&session_token).await.unwrap();
+    let refreshed_token = refreshed["sessionToken"].as_str().unwrap().to_string();
+    assert_ne!(refreshed_token, session_token);
+
+    let target = ConnectTarget { url: bridge.url(), token: refreshed_token, fingerprint: None };
+    let mut client = TestClient::connect(&target).await.unwrap();
+    let capabilities = client.notification("bridge/capabilities").await.unwrap();
+    assert!(capabilities["params"]["extensions"].as_array().unwrap().contains(&json!("sessionTokens")));
+    client.handshake().await.unwrap();
+
+    let err = bridge.session_token("st2.1.9999999999.nonce.forged").await.unwrap_err();
+    assert!(err.to_string().contains("401"), "{:#}", err);
+}
+
+#[tokio::test]
+async fn session_tokens_are_disabled_by_default() {
+    let bridge = TestBridge::echo().await.unwrap();
+    let err = bridge.session_token(bridge.auth_token()).await.unwrap_err();
+    assert!(err.to_string().contains("404"), "{:#}", err);
+}
+
+#[tokio::test]
+async fn device_tokens_are_limited_to_their_scopes() {
+    let device = DeviceConfig { name: "team-tablet".into(), token: "team-tablet-token-0001".into(), scopes: vec![Scope::Chat] };
+    let bridge = TestBridge::start_with(AgentHandle::Command("cat".into()), |b| b.with_devices(vec![device]))
+        .await
+        .unwrap();
+    let target = ConnectTarget { url: bridge.url(), token: "team-tablet-token-0001".into(), fingerprint: None };
+    let mut client = TestClient::connect(&target).await.unwrap();
+    let capabilities = client.notification("bridge/capabilities").await.unwrap();
+    assert_eq!(capabilities["params"]["scopes"], json!(["chat"]));
+
+    // `cat` echoes what reaches the agent: initialize arrives without the
+    // file and terminal capabilities this token may not use.
+    let init = json!({
+        "jsonrpc": "2.0", "id": "init", "method": "initialize",
+        "params": { "clientCapabilities": { "fs": { "readTextFile": true, "writeTextFile": true }, "terminal": true } }
+    });
+    client.send(&init).await.unwrap();
+    let echoed = client.recv().await.unwrap();
+    assert_eq!(echoed["params"]["clientCapabilities"], json!({ "fs": { "readTextFile": false, "writeTextFile": false }, "terminal": false }));
+
+    let denied = client.request("bridge/appendMemory", json!({ "text": "x" })).await.unwrap();
+    assert_eq!(denied["error"]["code"], -32003);
+    assert!(denied["error"]["message"].as_str().unwrap().contains("admin"));
+
+    client.send(&json!({ "jsonrpc": "2.0", "id": "new", "method": "session/new", "params": {} })).await.unwrap();
+    assert_eq!(client.recv().await.unwrap()["method"], "session/new");
+}
+
+#[tokio::test]
+async fn rate_limited_connections_are_told_when_to_retry() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let bridge = TestBridge::start_with(TestBridge::echo_handle(), |b| b.with_rate_limits(10, 1)).await.unwrap();
+    let mut client = bridge.connect().await.unwrap();
+    client.notification("bridge/capabilities").await.unwrap();
+
+    // Over the per-minute attempt limit: a WebSocket is closed with 1013 …
+    let mut throttled = bridge.connect().await.unwrap();
+    assert!(throttled.recv().await.unwrap_err().to_string().contains("closed"));
+
+    // … and plain HTTP gets 429 with a jittered Retry-After.
+    let mut stream = tokio::net::TcpStream::connect(bridge.addr()).await.unwrap();
+    stream.write_all(b"GET /version HTTP/1.1\r\nHost: bridge\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 429 "), "{response}");
+    let retry_after: u64 = response
+        .lines()
+        .find_map(|l| l.strip_prefix("Retry-After: "))
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=90).contains(&retry_after), "{retry_after}");
+    assert!(response.contains(r#""error":"rate_limited""#));
+}
+
+#[tokio::test]
+async fn stalled_connections_are_dropped() {
+    use bridge::bridge::HandshakeTimeouts;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let timeouts = HandshakeTimeouts {
+        tls: Duration::from_millis(300),
+        request: Duration::from_millis(300),
+        upgrade: Duration::from_millis(300),
+    };
+    let bridge = TestBridge::start_with(TestBridge::echo_handle(), |b| b.with_handshake_timeouts(timeouts)).await.unwrap();
+    let closed_within = |mut stream: tokio::net::TcpStream| async move {
+        let mut rest = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut rest)).await.is_ok()
+    };
+
+    // Never sends a request.
+    let silent = tokio::net::TcpStream::connect(bridge.addr()).await.unwrap();
+    assert!(closed_within(silent).await, "silent client kept open");
+
+    // Starts a WebSocket upgrade and never finishes the headers.
+    let mut stalled = tokio::net::TcpStream::connect(bridge.addr()).await.unwrap();
+    stalled.write_all(b"GET / HTTP/1.1\r\nHost: bridge\r\nUpgrade: websocket\r\n").await.unwrap();
+    assert!(closed_within(stalled).await, "stalled upgrade kept open");
+
+    // The bridge still serves clients that keep to the deadlines.
+    let mut client = bridge.connect().await.unwrap();
+    client.notification("bridge/capabilities").await.unwrap();
+}
+
+#[tokio::test]
+async fn first_message_auth_gates_tokenless_connections() {
+    let client_auth = ClientAuthConfig { first_message: true, first_message_timeout_secs: 1, ..ClientAuthConfig::default() };
+    let bridge = TestBridge::start_with(TestBridge::echo_handle(), |b| b.with_client_auth(client_auth)).await.unwrap();
+    let tokenless = ConnectTarget { url: bridge.url(), token: String::new(), fingerprint: None };
+
+    let mut client = TestClient::connect(&tokenless).await.unwrap();
+    let reply = client.request("bridge/auth", json!({ "token": bridge.auth_token() })).await.unwrap();
+    assert_eq!(reply["result"]["authenticated"], true);
+    client.notification("bridge/capabilities").await.unwrap();
+    let response = client.request("demo/echo", json!({ "text": "hi" })).await.unwrap();
+    assert_eq!(response["result"]["text"], "hi");
+
+    let mut client = TestClient::connect(&tokenless).await.unwrap();
+    let reply = client.request("bridge/auth", json!({ "token": "wrong" })).await.unwrap();
+    assert_eq!(reply["error"]["code"], -32001);
+    assert!(client.recv().await.is_err(), "closed after a wrong token");
+
+    let mut client = TestClient::connect(&tokenless).await.unwrap();
+    assert!(client.recv().await.is_err(), "closed after the timeout");
+    assert!(TestClient::connect(&ConnectTarget { token: "wrong".into(), ..tokenless }).await.is_err());
+}
+
+#[tokio::test]
+async fn query_tokens_can_be_refused() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let client_auth = ClientAuthConfig { query_token: false, ..ClientAuthConfig::default() };
+    let bridge = TestBridge::start_with(TestBridge::echo_handle(), |b| b.with_client_auth(client_auth)).await.unwrap();
+    let mut stream = tokio::net::TcpStream::connect(bridge.addr()).await.unwrap();
+    let request = format!("POST /acp?token={} HTTP/1.1\r\nHost: bridge\r\nContent-Length: 0\r\n\r\n", bridge.auth_token());
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 401 "), "{response}");
+    assert!(response.contains("query_token_disabled"), "{response}");
+    bridge.connect().await.unwrap();
+}
+
+#[tokio::test]
+async fn healthz_answers_without_authentication() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let bridge = TestBridge::echo().await.unwrap();
+    let mut stream = tokio::net::TcpStream::connect(bridge.addr()).await.unwrap();
+    stream.write_all(b"GET /healthz HTTP/1.1\r\nHost: bridge\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+    assert!(response.ends_with(r#"{"status":"ok"}"#), "{response}");
+}
+
+#[tokio::test]
+async fn version_endpoint_advertises_the_connection_capabilities() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    for (handle, pool_mode, resumable) in [
+        (TestBridge::echo_handle(), "inProcess", false),
+        (AgentHandle::Command("cat".into()), "keepAlive", true),
+    ] {
+        let bridge = TestBridge::start(handle).await.unwrap();
+        let mut stream = tokio::net::TcpStream::connect(bridge.addr()).await.unwrap();
+        stream.write_all(b"GET /version HTTP/1.1\r\nHost: bridge\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+        let body: serde_json::Value = serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body["version"], bridge::VERSION);
+        assert_eq!(body["poolMode"], pool_mode);
+        assert_eq!(body["extensions"].as_array().unwrap().contains(&json!("resume")), resumable);
+
+        // Every WebSocket client is told the same before any agent traffic.
+        let mut client = TestClient::connect(&bridge.target()).await.unwrap();
+        let capabilities = client.notification("bridge/capabilities").await.unwrap();
+        assert_eq!(capabilities["params"]["version"], body["version"]);
+        assert_eq!(capabilities["params"]["poolMode"], body["poolMode"]);
+    }
+}
+
+#[tokio::test]
+async fn requests_from_unexpected_countries_are_refused() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let geo_filter = GeoFilterConfig { allow_countries: vec!["DE".into()], ..GeoFilterConfig::default() };
+    let bridge = TestBridge::start_with(TestBridge::echo_handle(), |b| b.with_geo_filter(&geo_filter)).await.unwrap();
+    let addr = bridge.addr();
+    let version = |country: &'static str| async move {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET /version HTTP/1.1\r\nHost: bridge\r\nCF-IPCountry: {}\r\n\r\n", country);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    };
+    assert!(version("DE").await.starts_with("HTTP/1.1 200 "));
+    let refused = version("FR").await;
+    assert!(refused.starts_with("HTTP/1.1 403 "), "{refused}");
+    assert!(refused.contains(r#""error":"forbidden""#));
+}
+
+#[tokio::test]
+async fn repeated_auth_failures_are_tarpitted() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let auth_failures = AuthFailureConfig { threshold: 2, tarpit_secs: 1, ..AuthFailureConfig::default() };
+    let bridge =
+        TestBridge::start_with(TestBridge::echo_handle(), |b| b.with_auth_failures(auth_failures.clone())).await.unwrap();
+    let addr = bridge.addr();
+    let send = |request: &'static str| async move {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    };
+    let wrong_token = "POST /acp HTTP/1.1\r\nHost: bridge\r\nAuthorization: Bearer wrong\r\nContent-Length: 0\r\n\r\n";
+    for _ in 0..2 {
+        let response = send(wrong_token).await;
+        assert!(response.starts_with("HTTP/1.1 401 "), "{response}");
+    }
+
+    // Over the threshold, even valid requests from the address are held.
+    let started = std::time::Instant::now();
+    assert!(send("GET /version HTTP/1.1\r\nHost: bridge\r\n\r\n").await.starts_with("HTTP/1.1 200 "));
+    assert!(started.elapsed() >= Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn wire_protocol_is_negotiated_per_connection() {
+    let bridge = TestBridge::start(AgentHandle::Command("cat".into())).await.unwrap();
+    let mut client = TestClient::connect_with_protocols(&bridge.target(), &["aptove-bridge.v1", "aptove-bridge.v2"])
+        .await
+        .unwrap();
+    assert_eq!(client.protocol(), Some("aptove-bridge.v2"));
+    let capabilities = client.notification("bridge/capabilities").await.unwrap();
+    assert_eq!(capabilities["params"]["protocol"], "aptove-bridge.v2");
+
+    // v2 wraps every agent message in a sequence envelope from the start.
+    client.send(&json!({ "jsonrpc": "2.0", "id": 1, "method": "session/new", "params": {} })).await.unwrap();
+    let envelope = client.recv().await.unwrap();
+    assert_eq!(envelope["seq"], 1);
+    assert_eq!(envelope["msg"]["method"], "session/new");
+
+    // Apps from before negotiation keep plain frames.
+    let legacy = TestClient::connect(&bridge.target()).await.unwrap();
+    assert_eq!(legacy.protocol(), None);
+
+    // A generation the bridge doesn't speak is refused up front.
+    let err = TestClient::connect_with_protocols(&bridge.target(), &["aptove-bridge.v9"]).await.err().unwrap();
+    assert!(format!("{err:#}").contains("426"), "{err:#}");
+}
+
+#[tokio::test]
+async fn envelope_generation_needs_keep_alive_pooling() {
+    let bridge = TestBridge::echo().await.unwrap();
+    let client = TestClient::connect_with_protocols(&bridge.target(), &["aptove-bridge.v2", "aptove-bridge.v1"])
+        .await
+        .unwrap();
+    assert_eq!(client.protocol(), Some("aptove-bridge.v1"));
+    assert!(TestClient::connect_with_protocols(&bridge.target(), &["aptove-bridge.v2"]).await.is_err());
+}
+
+#[tokio::test]
+async fn workspaces_are_listed_and_selected() {
+    let root = std::env::temp_dir().join(format!("bridge-e2e-workspaces-{}", uuid::Uuid::new_v4()));
+    let api = root.join("api");
+    std::fs::create_dir_all(&api).unwrap();
+    let pool = AgentPool::new(PoolConfig::default())
+        .with_working_dir(root.clone())
+        .with_workspaces(vec![WorkspaceConfig { name: "api".into(), path: api.clone() }]);
+    let bridge = TestBridge::start_with(AgentHandle::Command("cat".into()), |b| {
+        b.with_agent_pool(Arc::new(tokio::sync::RwLock::new(pool)))
+    })
+    .await
+    .unwrap();
+
+    let mut client = TestClient::connect(&bridge.target()).await.unwrap();
+    let capabilities = client.notification("bridge/capabilities").await.unwrap();
+    assert!(capabilities["params"]["extensions"].as_array().unwrap().contains(&json!("workspaces")));
+    let listed = client.request("bridge/listWorkspaces", json!({})).await.unwrap();
+    assert_eq!(listed["result"]["workspaces"], json!([{ "name": "api", "path": api, "exists": true }]));
+    assert_eq!(listed["result"]["selected"], json!(null));
+
+    let unknown = client.request("bridge/selectWorkspace", json!({ "name": "web" })).await.unwrap();
+    assert!(unknown["error"]["message"].as_str().unwrap().contains("Unknown workspace"));
+
+    // The agent was spawned in the default directory, so it is replaced and
+    // the client is sent to reconnect.
+    let selected = client.request("bridge/selectWorkspace", json!({ "name": "api" })).await.unwrap();
+    assert_eq!(selected["result"]["restart"], true);
+    assert!(client.recv().await.is_err(), "connection closes after a restart");
+
+    // Sessions on the new agent are pointed at the workspace (`cat` echoes the request).
+    let mut client = TestClient::connect(&bridge.target()).await.unwrap();
+    client.notification("bridge/capabilities").await.unwrap();
+    client.send(&json!({ "jsonrpc": "2.0", "id": 1, "method": "session/new", "params": { "cwd": "/elsewhere" } })).await.unwrap();
+    let echoed = client.recv().await.unwrap();
+    assert_eq!(echoed["params"]["cwd"], json!(api));
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn agents_run_in_a_worktree_until_the_session_is_finished() {
+    let root = std::env::temp_dir().join(format!("bridge-e2e-worktrees-{}", uuid::Uuid::new_v4()));
+    let repo = root.join("repo");
+    std::fs::create_dir_all(&repo).unwrap();
+    std::fs::write(repo.join("README"), "hello\n").unwrap();
+    for args in [
+        &["init", "-q"][..],
+        &["add", "-A"],
+        &["-c", "user.name=Dev", "-c", "user.email=dev@example.com", "commit", "-q", "-m", "initial"],
+    ] {
+        assert!(std::process::Command::new("git").arg("-C").arg(&repo).args(args).status().unwrap().success());
+    }
+    let pool = AgentPool::new(PoolConfig::default())
+        .with_working_dir(repo.clone())
+        .with_worktrees(WorktreeConfig { root: root.join("worktrees"), branch_prefix: "bridge/".into(), push_remote: None });
+    let bridge = TestBridge::start_with(AgentHandle::Command("cat".into()), |b| {
+        b.with_agent_pool(Arc::new(tokio::sync::RwLock::new(pool)))
+    })
+    .await
+    .unwrap();
+
+    let mut client = TestClient::connect(&bridge.target()).await.unwrap();
+    let capabilities = client.notification("bridge/capabilities").await.unwrap();
+    assert!(capabilities["params"]["extensions"].as_array().unwrap().contains(&json!("worktrees")));
+
+    // Sessions are pointed at the worktree (`cat` echoes the request).
+    client.send(&json!({ "jsonrpc": "2.0", "id": 1, "method": "session/new", "params": { "cwd": repo } })).await.unwrap();
+    let echoed = client.recv().await.unwrap();
+    let worktree = std::path::PathBuf::from(echoed["params"]["cwd"].as_str().unwrap());
+    assert!(worktree.starts_with(root.join("worktrees")));
+    assert!(worktree.join("README").is_file());
+
+    let refused = client.request("bridge/session/finish", json!({ "action": "merge" })).await.unwrap();
+    assert!(refused["error"]["message"].as_str().unwrap().contains("action"));
+    let finished = client.request("bridge/session/finish", json!({ "action": "discard" })).await.unwrap();
+    assert_eq!(finished["result"]["branch"], json!(null));
+    assert!(client.recv().await.is_err(), "connection closes after finishing");
+    assert!(!worktree.exists());
+
+    // The next connection gets a fresh worktree.
+    let mut client = TestClient::connect(&bridge.target()).await.unwrap();
+    client.notification("bridge/capabilities").await.unwrap();
+    let listed = client.request("bridge/listWorkspaces", json!({})).await.unwrap();
+    let next = listed["result"]["worktree"]["path"].as_str().unwrap();
+    assert_ne!(std::path::Path::new(next), worktree);
+    assert!(listed["result"]["worktree"]["branch"].as_str().unwrap().starts_with("bridge/"));
+    std::fs::remove_dir_all(&root).unwrap();
+}